@@ -0,0 +1,95 @@
+//! Append throughput, random reads, proof generation and record checks for each backend, at growing log sizes
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use num::{FromPrimitive, ToPrimitive};
+use transparentlog_bench::*;
+use transparentlog_core::test_helpers::LogRecord;
+use transparentlog_core::*;
+use transparentlog_rocksdb::{RocksDBLog, RocksDBStore};
+
+/// Measure one backend at all sizes, growing the same log from one size to the next
+fn bench_log<'a, TL>(c: &mut Criterion, backend: &str, log: &mut TL)
+where
+    TL: TransparentLog<'a, LogRecord>,
+    TL::LogSize: FromPrimitive + ToPrimitive,
+{
+    let mut group = c.benchmark_group(backend);
+    group.throughput(Throughput::Elements(1));
+    let mut generator = Generator::new(42);
+    for size in sizes() {
+        fill(log, size).expect("fill log");
+
+        group.bench_with_input(BenchmarkId::new("get", size), &size, |b, size| {
+            b.iter(|| log.get(log_size(generator.below(*size))).expect("get"))
+        });
+
+        let tree = log.latest().expect("latest");
+        group.bench_with_input(BenchmarkId::new("proofs", size), &size, |b, size| {
+            b.iter(|| {
+                let id = log_size(generator.below(*size));
+                log.proofs(proof_positions_iter(id, tree.size))
+                    .expect("proofs")
+            })
+        });
+
+        let records: Vec<_> = (0..100)
+            .map(|_| {
+                let id = log_size(generator.below(size));
+                Record {
+                    id,
+                    hash: log.get_hash(0, id).expect("leaf hash").into_owned(),
+                    meta: None,
+                }
+            })
+            .collect();
+        group.bench_with_input(
+            BenchmarkId::new("check_record", size),
+            &records,
+            |b, records| {
+                let mut i = 0;
+                b.iter(|| {
+                    let mut client = InMemoryLogClientBuilder::open(tree.clone())
+                        .no_cache()
+                        .build();
+                    i = (i + 1) % records.len();
+                    assert!(check_record(&mut client, log, &records[i]).expect("check record"));
+                })
+            },
+        );
+
+        // appending last, so the next size only fills what is missing
+        group.bench_function(BenchmarkId::new("append", size), |b| {
+            let mut i = 0;
+            b.iter(|| {
+                i += 1;
+                log.append(LogRecord::new(&format!("bench{}", i)))
+                    .expect("append")
+            })
+        });
+    }
+    group.finish();
+}
+
+fn in_memory(c: &mut Criterion) {
+    let mut log: InMemoryLog<LogRecord> = InMemoryLog::default();
+    bench_log(c, "memory", &mut log);
+}
+
+fn file(c: &mut Criterion) {
+    let path = data_dir("file").expect("file directory");
+    let mut log: FileLog<LogRecord> = FileLog::open(&path).expect("file log");
+    bench_log(c, "file", &mut log);
+}
+
+fn rocksdb(c: &mut Criterion) {
+    let path = data_dir("rocksdb").expect("rocksdb directory");
+    let store = RocksDBStore::open(&path).expect("rocksdb store");
+    let mut log: RocksDBLog<LogRecord> = KvLog::open(store).expect("rocksdb log");
+    bench_log(c, "rocksdb", &mut log);
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().sample_size(20);
+    targets = in_memory, file, rocksdb
+}
+criterion_main!(benches);
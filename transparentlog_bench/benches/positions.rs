@@ -0,0 +1,31 @@
+//! Proof positions collected into hash sets, and iterated without allocating, for random records at growing log sizes
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use transparentlog_bench::{sizes, Generator};
+use transparentlog_core::*;
+
+fn positions(c: &mut Criterion) {
+    let mut group = c.benchmark_group("positions");
+    group.throughput(Throughput::Elements(1));
+    let mut generator = Generator::new(42);
+    for size in sizes() {
+        group.bench_with_input(BenchmarkId::new("set", size), &size, |b, size| {
+            b.iter(|| proof_positions(generator.below(*size), *size))
+        });
+
+        group.bench_with_input(BenchmarkId::new("iter", size), &size, |b, size| {
+            b.iter(|| {
+                for position in proof_positions_iter(generator.below(*size), *size) {
+                    black_box(position);
+                }
+            })
+        });
+
+        group.bench_with_input(BenchmarkId::new("prefix", size), &size, |b, size| {
+            b.iter(|| prefix_proof_positions(generator.below(*size - 1) + 1, *size))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, positions);
+criterion_main!(benches);
@@ -0,0 +1,79 @@
+//! # Transparent Log benchmarks
+//!
+//! Shared setup for the criterion benchmarks in `benches`: filling logs of each backend up to the measured sizes,
+//! and picking the records to read and prove.
+//!
+//! Run with `cargo bench`. The sizes go from 10^4 to 10^7 entries, set `TRANSPARENTLOG_BENCH_MAX` to stop earlier.
+use num::{FromPrimitive, ToPrimitive};
+use std::env;
+use std::fs::{create_dir_all, remove_dir_all};
+use std::path::PathBuf;
+use transparentlog_core::test_helpers::LogRecord;
+use transparentlog_core::TransparentLog;
+
+/// The number of records appended in one bulk append while filling a log
+const FILL_BATCH: usize = 10_000;
+
+/// The log sizes to measure, from 10^4 to 10^7, capped by the `TRANSPARENTLOG_BENCH_MAX` environment variable
+pub fn sizes() -> Vec<usize> {
+    let max = env::var("TRANSPARENTLOG_BENCH_MAX")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(10_000_000);
+    [10_000, 100_000, 1_000_000, 10_000_000]
+        .into_iter()
+        .filter(|s| *s <= max)
+        .collect()
+}
+
+/// An empty directory for the given backend
+pub fn data_dir(backend: &str) -> anyhow::Result<PathBuf> {
+    let path = env::temp_dir().join("transparentlog_bench").join(backend);
+    if path.exists() {
+        remove_dir_all(&path)?;
+    }
+    create_dir_all(&path)?;
+    Ok(path)
+}
+
+/// Append records to the log until it reaches the given size
+pub fn fill<'a, TL>(log: &mut TL, size: usize) -> anyhow::Result<()>
+where
+    TL: TransparentLog<'a, LogRecord>,
+    TL::LogSize: ToPrimitive,
+{
+    let mut current = log.size()?.to_usize().unwrap_or_default();
+    while current < size {
+        let end = size.min(current + FILL_BATCH);
+        log.bulk_append(
+            (current..end)
+                .map(|i| LogRecord::new(&format!("rec{}", i)))
+                .collect(),
+        )?;
+        current = end;
+    }
+    Ok(())
+}
+
+/// Convert an index to the log size type
+pub fn log_size<LogSize: FromPrimitive>(index: usize) -> LogSize {
+    LogSize::from_usize(index).expect("index fits in the log size")
+}
+
+/// A small deterministic pseudo-random generator (xorshift64*), so every run reads and proves the same records
+pub struct Generator(u64);
+
+impl Generator {
+    /// Create a generator from a seed
+    pub fn new(seed: u64) -> Self {
+        Generator(seed.max(1))
+    }
+
+    /// The next random number, below the given bound
+    pub fn below(&mut self, bound: usize) -> usize {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        (self.0.wrapping_mul(0x2545_f491_4f6c_dd1d) % (bound.max(1) as u64)) as usize
+    }
+}
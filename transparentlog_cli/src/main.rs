@@ -0,0 +1,403 @@
+//! # Transparent Log command line tool
+//!
+//! Append, read, prove and audit a log stored in a local directory,
+//! or read, prove and verify the records of a log published over HTTP.
+use clap::{ArgEnum, Parser, Subcommand};
+use num::{Integer, Zero};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::fmt::{Debug, Display};
+use std::fs::File;
+use std::hash::Hash;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::PathBuf;
+use std::process::exit;
+use std::str::FromStr;
+use transparentlog_core::*;
+
+#[cfg(feature = "remote")]
+mod remote;
+
+/// The command line arguments
+#[derive(Parser)]
+#[clap(name = "transparentlog-cli", version, about)]
+struct Cli {
+    /// The directory of the log, or the URL it is published at with the remote backend
+    #[clap(short, long)]
+    path: PathBuf,
+    /// The storage backend of the log
+    #[clap(short, long, arg_enum, default_value = "file")]
+    backend: Backend,
+    /// The origin of a remote log
+    #[cfg(feature = "remote")]
+    #[clap(long, required_if_eq("backend", "remote"))]
+    origin: Option<String>,
+    /// The verifier keys of the checkpoints of a remote log, one of them must have signed the checkpoint
+    #[cfg(feature = "remote")]
+    #[clap(long, required_if_eq("backend", "remote"))]
+    vkey: Vec<String>,
+    #[clap(subcommand)]
+    command: Command,
+}
+
+/// The storage backends
+#[derive(Clone, Copy, ArgEnum)]
+enum Backend {
+    File,
+    #[cfg(feature = "rocksdb")]
+    Rocksdb,
+    #[cfg(feature = "remote")]
+    Remote,
+}
+
+/// The commands
+#[derive(Subcommand)]
+enum Command {
+    /// Append records, one per line, from a file or stdin
+    Append { file: Option<PathBuf> },
+    /// Print a record
+    Get { id: String },
+    /// Print the latest tree head as JSON
+    Latest,
+    /// Print the JSON inclusion proof of a record in the latest tree
+    Prove { id: String },
+    /// Print the JSON inclusion proof of the record with the given leaf hash in the latest tree
+    ProveHash { hash: String },
+    /// Verify a JSON inclusion proof, and that its tree is a prefix of the latest tree
+    Verify { proof: PathBuf },
+    /// Audit the log from a checkpoint, replaying the entries appended since, or the whole log without checkpoint,
+    /// and print the audit report, signed if a key is given
+    Audit {
+        /// The JSON tree head to start from
+        #[clap(long)]
+        from: Option<PathBuf>,
+        /// The size of the tree to audit, the latest tree by default
+        #[clap(long)]
+        to: Option<String>,
+        /// Replay the whole log, checking the records produce the root
+        #[clap(long)]
+        full: bool,
+        /// A file holding the 32 bytes Ed25519 seed signing the report
+        #[clap(long)]
+        key: Option<PathBuf>,
+        /// The name of the signing key
+        #[clap(long, default_value = "auditor")]
+        name: String,
+    },
+    /// Verify the tree of the old size is a prefix of the tree of the new size
+    Consistency { old: String, new: String },
+    /// Check the stored interior hashes match their children, and print the report
+    Check {
+        /// Only check about that many hashes per level
+        #[clap(long)]
+        sample: Option<u64>,
+    },
+    /// Reclaim the space of the redacted and expired records of a file log
+    Compact,
+}
+
+/// A tree head, as JSON
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+struct TreeJson<LogSize> {
+    size: LogSize,
+    hash: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    origin: Option<String>,
+}
+
+impl<LogSize> From<LogTree<LogSize>> for TreeJson<LogSize> {
+    fn from(t: LogTree<LogSize>) -> Self {
+        Self {
+            size: t.size,
+            hash: t.hash,
+            origin: t.origin,
+        }
+    }
+}
+
+impl<LogSize> From<TreeJson<LogSize>> for LogTree<LogSize> {
+    fn from(t: TreeJson<LogSize>) -> Self {
+        Self {
+            size: t.size,
+            hash: t.hash,
+            origin: t.origin,
+        }
+    }
+}
+
+/// A hash at a position in the tree, as JSON
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+struct HashJson<LogSize> {
+    level: LogHeight,
+    index: LogSize,
+    hash: String,
+}
+
+/// The proof that a leaf hash is included in a tree, as JSON
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+struct ProofJson<LogSize> {
+    tree: TreeJson<LogSize>,
+    index: LogSize,
+    leaf_hash: String,
+    hashes: Vec<HashJson<LogSize>>,
+}
+
+fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+    let ok = match cli.backend {
+        Backend::File => match cli.command {
+            Command::Compact => {
+                let reclaimed = FileLog::<String>::open(&cli.path)?.compact()?;
+                println!("{} bytes reclaimed", reclaimed);
+                true
+            }
+            command => run(FileLog::open(&cli.path)?, command)?,
+        },
+        #[cfg(feature = "rocksdb")]
+        Backend::Rocksdb => run(
+            transparentlog_rocksdb::RocksDBLog::<String>::open(
+                transparentlog_rocksdb::RocksDBStore::open(&cli.path)?,
+            )?,
+            cli.command,
+        )?,
+        #[cfg(feature = "remote")]
+        Backend::Remote => remote::run(
+            remote::open(
+                &cli.path.to_string_lossy(),
+                cli.origin.as_deref().unwrap_or_default(),
+                &cli.vkey,
+            )?,
+            cli.command,
+        )?,
+    };
+    if !ok {
+        exit(1);
+    }
+    Ok(())
+}
+
+/// Run a command on the log, returning false if a verification failed
+fn run<'a, TL>(mut log: TL, command: Command) -> anyhow::Result<bool>
+where
+    TL: TransparentLog<'a, String>,
+    TL::LogSize: FromStr + Display + Debug + Send + Sync + From<u8> + Serialize + DeserializeOwned,
+    <TL::LogSize as FromStr>::Err: std::error::Error + Send + Sync + 'static,
+{
+    match command {
+        Command::Append { file } => {
+            let reader: Box<dyn BufRead> = match file {
+                Some(f) => Box::new(BufReader::new(File::open(f)?)),
+                None => Box::new(BufReader::new(io::stdin())),
+            };
+            for line in reader.lines() {
+                let record = log.append(line?)?;
+                println!("{}\t{}", record.id, record.hash);
+            }
+            print_json(&TreeJson::from(log.latest()?))?;
+            Ok(true)
+        }
+        Command::Get { id } => match log.get(id.parse()?)? {
+            Some(record) => {
+                println!("{}", record.as_str());
+                Ok(true)
+            }
+            None => {
+                eprintln!("No record {}", id);
+                Ok(false)
+            }
+        },
+        Command::Latest => {
+            print_json(&TreeJson::from(log.latest()?))?;
+            Ok(true)
+        }
+        Command::Prove { id } => {
+            let tree = log.latest()?;
+            let proof = prove_inclusion(&log, id.parse()?, tree.size)?;
+            print_proof(tree, proof)?;
+            Ok(true)
+        }
+        Command::ProveHash { hash } => {
+            let tree = log.latest()?;
+            match prove_by_hash(&log, &hash, tree.size)? {
+                Some(proof) => {
+                    print_proof(tree, proof)?;
+                    Ok(true)
+                }
+                None => {
+                    eprintln!("No record with leaf hash {}", hash);
+                    Ok(false)
+                }
+            }
+        }
+        Command::Verify { proof } => {
+            let (inclusion, tree) = read_proof(File::open(proof)?)?;
+            if let Err(e) = verify_inclusion(&inclusion, &inclusion.record.hash, &tree) {
+                eprintln!("{}", e);
+                return Ok(false);
+            }
+            let latest = log.latest()?;
+            let ok = consistent(&log, &tree, &latest)?;
+            if ok {
+                println!(
+                    "Record {} is included in the tree of size {}",
+                    inclusion.record.id, tree.size
+                );
+            } else {
+                eprintln!("The proof tree is not a prefix of the latest tree");
+            }
+            Ok(ok)
+        }
+        Command::Audit {
+            from,
+            to,
+            full,
+            key,
+            name,
+        } => {
+            let from: LogTree<TL::LogSize> = match &from {
+                Some(path) => serde_json::from_reader::<_, TreeJson<TL::LogSize>>(BufReader::new(
+                    File::open(path)?,
+                ))?
+                .into(),
+                None => LogTree {
+                    size: TL::LogSize::zero(),
+                    hash: String::new(),
+                    origin: log.origin(),
+                },
+            };
+            let size = match to {
+                Some(to) => to.parse()?,
+                None => log.size()?,
+            };
+            let report = audit(&log, &from, size, full || from.size.is_zero())?;
+            match key {
+                Some(key) => {
+                    let seed: [u8; 32] = std::fs::read(key)?.as_slice().try_into()?;
+                    print_json(&report.sign(&[&Ed25519Signer::new(&name, &seed)])?)?;
+                }
+                None => print_json(&report)?,
+            }
+            if !report.consistent {
+                eprintln!(
+                    "The tree of size {} is not a prefix of the tree audited",
+                    from.size
+                );
+            }
+            if report.root_matches == Some(false) {
+                eprintln!("The records do not produce the root of the tree audited");
+            }
+            for id in report.mismatches.iter() {
+                eprintln!("Record {} does not match its leaf hash", id);
+            }
+            Ok(report.passed())
+        }
+        Command::Consistency { old, new } => {
+            let old = log.head_at(old.parse()?)?;
+            let new = log.head_at(new.parse()?)?;
+            let ok = consistent(&log, &old, &new)?;
+            if ok {
+                println!(
+                    "The tree of size {} is a prefix of the tree of size {}",
+                    old.size, new.size
+                );
+            } else {
+                eprintln!(
+                    "The tree of size {} is not a prefix of the tree of size {}",
+                    old.size, new.size
+                );
+            }
+            Ok(ok)
+        }
+        Command::Check { sample } => {
+            let depth = sample.map_or(CheckDepth::Full, CheckDepth::Sampled);
+            let report = log.self_check(depth)?;
+            print_json(&report)?;
+            if let Some(position) = &report.first_bad {
+                eprintln!(
+                    "The hash at level {} and index {} does not match its children",
+                    position.level, position.index
+                );
+            }
+            Ok(report.passed())
+        }
+        Command::Compact => {
+            eprintln!("Only file logs can be compacted");
+            Ok(false)
+        }
+    }
+}
+
+/// Print a value as pretty JSON
+fn print_json<V: Serialize>(value: &V) -> anyhow::Result<()> {
+    println!("{}", serde_json::to_string_pretty(value)?);
+    Ok(())
+}
+
+/// Print an inclusion proof in the given tree as JSON, its hashes sorted by level then index
+fn print_proof<LogSize>(
+    tree: LogTree<LogSize>,
+    proof: InclusionProof<LogSize>,
+) -> anyhow::Result<()>
+where
+    LogSize: Ord + Copy + Hash + Serialize,
+{
+    let mut hashes: Vec<_> = proof
+        .proofs
+        .into_iter()
+        .map(|(p, hash)| HashJson {
+            level: p.level,
+            index: p.index,
+            hash,
+        })
+        .collect();
+    hashes.sort_by_key(|h| (h.level, h.index));
+    print_json(&ProofJson {
+        tree: tree.into(),
+        index: proof.record.id,
+        leaf_hash: proof.record.hash,
+        hashes,
+    })
+}
+
+/// Read a JSON inclusion proof, and the tree it proves inclusion in
+fn read_proof<R, LogSize>(reader: R) -> anyhow::Result<(InclusionProof<LogSize>, LogTree<LogSize>)>
+where
+    R: Read,
+    LogSize: Integer + Copy + Hash + DeserializeOwned,
+{
+    let proof: ProofJson<LogSize> = serde_json::from_reader(BufReader::new(reader))?;
+    let inclusion = InclusionProof {
+        record: Record {
+            id: proof.index,
+            hash: proof.leaf_hash,
+            meta: None,
+        },
+        proofs: proof
+            .hashes
+            .into_iter()
+            .map(|h| (LogTreePosition::from((h.level, h.index)), h.hash))
+            .collect(),
+    };
+    Ok((inclusion, proof.tree.into()))
+}
+
+/// Check the first tree is a prefix of the second, with proofs from the log, local or remote
+fn consistent<'a, PS, K>(
+    log: &PS,
+    tree1: &LogTree<PS::LogSize>,
+    tree2: &LogTree<PS::LogSize>,
+) -> anyhow::Result<bool>
+where
+    PS: ProofSource<'a, String, K>,
+{
+    if tree1.size > tree2.size {
+        return Ok(false);
+    }
+    if tree1.size.is_zero() {
+        return Ok(true);
+    }
+    if tree1.size == tree2.size {
+        return Ok(hash_eq(&tree1.hash, &tree2.hash));
+    }
+    let proofs = log.fetch_proofs(prefix_proof_positions(tree1.size, tree2.size).into_iter())?;
+    Ok(verify_tree(tree1, &proofs) && verify_tree(tree2, &proofs))
+}
@@ -0,0 +1,109 @@
+//! Read, prove and verify the records of a log published over HTTP by `transparentlog_object_store::Publisher`
+use crate::{consistent, print_json, print_proof, Command, TreeJson};
+use object_store::http::HttpBuilder;
+use serde::Serialize;
+use std::fmt::Display;
+use std::fs::File;
+use std::str::FromStr;
+use std::sync::Arc;
+use transparentlog_core::*;
+use transparentlog_object_store::{TileReader, TileSource};
+
+/// The log published at the URL, whose checkpoints must have the origin and be signed by one of the keys
+pub fn open(url: &str, origin: &str, vkeys: &[String]) -> anyhow::Result<TileSource> {
+    let verifiers = vkeys
+        .iter()
+        .map(|vkey| {
+            Ok(Box::new(Ed25519Verifier::from_vkey(vkey)?) as Box<dyn LogVerifier + Send + Sync>)
+        })
+        .collect::<anyhow::Result<_>>()?;
+    let store = HttpBuilder::new().with_url(url).build()?;
+    TileSource::new(TileReader::new(Arc::new(store), ""), origin, verifiers)
+}
+
+/// Run a read command on a remote log, checking what it reads against its latest checkpoint,
+/// returning false if a verification failed
+pub fn run<'a, PS>(source: PS, command: Command) -> anyhow::Result<bool>
+where
+    PS: ProofSource<'a, String, Remote>,
+    PS::LogSize: FromStr + Display + Serialize + serde::de::DeserializeOwned,
+    <PS::LogSize as FromStr>::Err: std::error::Error + Send + Sync + 'static,
+{
+    let latest = source.fetch_latest()?;
+    match command {
+        Command::Get { id } => match proven(&source, &latest, id.parse()?)? {
+            Some((record, _)) => {
+                println!("{}", record);
+                Ok(true)
+            }
+            None => {
+                eprintln!("No record {} in the tree of size {}", id, latest.size);
+                Ok(false)
+            }
+        },
+        Command::Latest => {
+            print_json(&TreeJson::from(latest))?;
+            Ok(true)
+        }
+        Command::Prove { id } => match proven(&source, &latest, id.parse()?)? {
+            Some((_, proof)) => {
+                print_proof(latest, proof)?;
+                Ok(true)
+            }
+            None => {
+                eprintln!("No record {} in the tree of size {}", id, latest.size);
+                Ok(false)
+            }
+        },
+        Command::Verify { proof } => {
+            let (inclusion, tree) = crate::read_proof(File::open(proof)?)?;
+            if let Err(e) = verify_inclusion(&inclusion, &inclusion.record.hash, &tree) {
+                eprintln!("{}", e);
+                return Ok(false);
+            }
+            let ok = consistent(&source, &tree, &latest)?;
+            if ok {
+                println!(
+                    "Record {} is included in the tree of size {}",
+                    inclusion.record.id, tree.size
+                );
+            } else {
+                eprintln!("The proof tree is not a prefix of the latest tree");
+            }
+            Ok(ok)
+        }
+        _ => {
+            eprintln!("Remote logs can only be read with get, latest, prove and verify");
+            Ok(false)
+        }
+    }
+}
+
+/// The record at the index and its inclusion proof in the tree, none if there is no such record
+/// Fails if the record is not included in the tree
+fn proven<'a, PS>(
+    source: &PS,
+    tree: &LogTree<PS::LogSize>,
+    id: PS::LogSize,
+) -> anyhow::Result<Option<(String, InclusionProof<PS::LogSize>)>>
+where
+    PS: ProofSource<'a, String, Remote>,
+{
+    if id >= tree.size {
+        return Ok(None);
+    }
+    let record = match source.fetch_entry(id)? {
+        Some(record) => record.into_owned(),
+        None => return Ok(None),
+    };
+    let proof = InclusionProof {
+        record: Record {
+            id,
+            hash: (source.entry_hasher())(&record)?,
+            meta: None,
+        },
+        proofs: source.fetch_proofs(proof_positions(id, tree.size).into_iter())?,
+    };
+    verify_inclusion(&proof, &proof.record.hash, tree)?;
+    Ok(Some((record, proof)))
+}
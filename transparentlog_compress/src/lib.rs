@@ -0,0 +1,142 @@
+//! # Record compression for Transparent Log
+//!
+//! zstd and lz4 implementations of the `Compressor` trait, to pass when opening a `FileLog` or a `RocksDBLog`.
+//! zstd gives the smallest records, lz4 the fastest reads.
+//!
+
+use transparentlog_core::Compressor;
+
+/// The default zstd compression level
+pub const DEFAULT_ZSTD_LEVEL: i32 = 3;
+
+/// zstd compressor, named "zstd" in the log header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ZstdCompressor {
+    level: i32,
+}
+
+impl ZstdCompressor {
+    /// A compressor with the given level, from 1 to 22, 0 meaning the zstd default
+    /// The level does not need to be the same to read a log back
+    pub fn new(level: i32) -> Self {
+        Self { level }
+    }
+}
+
+impl Default for ZstdCompressor {
+    fn default() -> Self {
+        Self::new(DEFAULT_ZSTD_LEVEL)
+    }
+}
+
+impl Compressor for ZstdCompressor {
+    fn name(&self) -> &str {
+        "zstd"
+    }
+
+    fn compress(&self, data: &[u8]) -> anyhow::Result<Vec<u8>> {
+        Ok(zstd::encode_all(data, self.level)?)
+    }
+
+    fn decompress(&self, data: &[u8]) -> anyhow::Result<Vec<u8>> {
+        Ok(zstd::decode_all(data)?)
+    }
+}
+
+/// lz4 compressor, named "lz4" in the log header
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Lz4Compressor;
+
+impl Compressor for Lz4Compressor {
+    fn name(&self) -> &str {
+        "lz4"
+    }
+
+    fn compress(&self, data: &[u8]) -> anyhow::Result<Vec<u8>> {
+        Ok(lz4_flex::compress_prepend_size(data))
+    }
+
+    fn decompress(&self, data: &[u8]) -> anyhow::Result<Vec<u8>> {
+        Ok(lz4_flex::decompress_size_prepended(data)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::{Lz4Compressor, ZstdCompressor};
+    use std::fs::{create_dir, remove_dir_all};
+    use std::path::{Path, PathBuf};
+    use transparentlog_core::test_helpers::*;
+    use transparentlog_core::{CompressionError, Compressor, FileLog};
+
+    use serial_test::serial;
+
+    fn setup() -> anyhow::Result<PathBuf> {
+        let path = Path::new("./test_data_compress");
+        if path.exists() {
+            remove_dir_all(path)?;
+        }
+        create_dir(path)?;
+        Ok(PathBuf::from(path))
+    }
+
+    fn round_trip(compressor: &dyn Compressor) -> anyhow::Result<()> {
+        let data = "{\"text\":\"hello\"}".repeat(100).into_bytes();
+        let compressed = compressor.compress(&data)?;
+        assert!(compressed.len() < data.len());
+        assert_eq!(data, compressor.decompress(&compressed)?);
+        assert_eq!(
+            Vec::<u8>::new(),
+            compressor.decompress(&compressor.compress(&[])?)?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn zstd_round_trip() -> anyhow::Result<()> {
+        round_trip(&ZstdCompressor::default())
+    }
+
+    #[test]
+    fn lz4_round_trip() -> anyhow::Result<()> {
+        round_trip(&Lz4Compressor)
+    }
+
+    #[test]
+    #[serial]
+    fn zstd_file_13() -> anyhow::Result<()> {
+        let path = setup()?;
+        let mut ml: FileLog<LogRecord> =
+            FileLog::open_with_compressor(&path, Box::new(ZstdCompressor::new(19)))?;
+        test_13(&mut ml)?;
+        drop(ml);
+        // the level is not recorded
+        let ml: FileLog<LogRecord> =
+            FileLog::open_with_compressor(&path, Box::new(ZstdCompressor::default()))?;
+        check_13(&ml)
+    }
+
+    #[test]
+    #[serial]
+    fn lz4_file_13() -> anyhow::Result<()> {
+        let path = setup()?;
+        let mut ml: FileLog<LogRecord> =
+            FileLog::open_with_compressor(&path, Box::new(Lz4Compressor))?;
+        test_13(&mut ml)?;
+        drop(ml);
+        let err =
+            FileLog::<LogRecord>::open_with_compressor(&path, Box::new(ZstdCompressor::default()))
+                .err()
+                .unwrap();
+        assert_eq!(
+            Some(&CompressionError::Mismatch {
+                expected: Some("zstd".into()),
+                found: Some("lz4".into())
+            }),
+            err.downcast_ref::<CompressionError>()
+        );
+        let ml: FileLog<LogRecord> = FileLog::open_with_compressor(&path, Box::new(Lz4Compressor))?;
+        check_13(&ml)
+    }
+}
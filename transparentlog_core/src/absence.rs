@@ -0,0 +1,143 @@
+//! Absence proofs for logs whose records are appended in sorted order
+use crate::base::*;
+use num::{Integer, One, Zero};
+use serde::{Deserialize, Serialize};
+use std::fmt::{Debug, Display};
+use std::hash::Hash;
+
+/// A neighbour of an absent record: the record itself and the proof it is in the tree
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct Neighbour<T, LogSize: Eq + Hash> {
+    pub value: T,
+    pub proof: InclusionProof<LogSize>,
+}
+
+/// The proof that a record is absent from a sorted tree: the two adjacent records around it
+/// `before` is None if the record would be first, `after` is None if it would be last
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct AbsenceProof<T, LogSize: Eq + Hash> {
+    pub before: Option<Neighbour<T, LogSize>>,
+    pub after: Option<Neighbour<T, LogSize>>,
+}
+
+/// Prove that the key is absent from the tree of the given size, in a log whose records are sorted
+/// Returns None if the key is in the tree
+pub fn prove_absent<'a, T, TL>(
+    log: &TL,
+    key: &T,
+    tree: &LogTree<TL::LogSize>,
+) -> anyhow::Result<Option<AbsenceProof<T, TL::LogSize>>>
+where
+    T: Serialize + Deserialize<'a> + Ord + Clone,
+    TL: TransparentLog<'a, T>,
+    TL::LogSize: Display + Debug + Send + Sync,
+{
+    let one = TL::LogSize::one();
+    let two = one + one;
+    let mut low = TL::LogSize::zero();
+    let mut high = tree.size;
+    while low < high {
+        let mid = low + (high - low) / two;
+        let value = log
+            .get(mid)?
+            .ok_or(LogError::InvalidIndex(0, mid))?
+            .into_owned();
+        match value.cmp(key) {
+            std::cmp::Ordering::Less => low = mid + one,
+            std::cmp::Ordering::Greater => high = mid,
+            std::cmp::Ordering::Equal => return Ok(None),
+        }
+    }
+    let neighbour = |id: TL::LogSize| -> anyhow::Result<Neighbour<T, TL::LogSize>> {
+        Ok(Neighbour {
+            value: log
+                .get(id)?
+                .ok_or(LogError::InvalidIndex(0, id))?
+                .into_owned(),
+            proof: prove_inclusion(log, id, tree.size)?,
+        })
+    };
+    let before = if low.is_zero() {
+        None
+    } else {
+        Some(neighbour(low - one)?)
+    };
+    let after = if low < tree.size {
+        Some(neighbour(low)?)
+    } else {
+        None
+    };
+    Ok(Some(AbsenceProof { before, after }))
+}
+
+/// Verify that the key is absent from the given sorted tree
+pub fn verify_absent<T, LogSize>(
+    tree: &LogTree<LogSize>,
+    key: &T,
+    proof: &AbsenceProof<T, LogSize>,
+) -> anyhow::Result<bool>
+where
+    T: Serialize + Ord,
+    LogSize: Integer + Copy + Hash,
+{
+    let valid = |n: &Neighbour<T, LogSize>| -> anyhow::Result<bool> {
+        Ok(hash(&n.value)? == n.proof.record.hash && n.proof.verify(tree))
+    };
+    let one = LogSize::one();
+    Ok(match (&proof.before, &proof.after) {
+        (None, None) => tree.size.is_zero(),
+        (Some(b), None) => valid(b)? && b.value < *key && b.proof.record.id + one == tree.size,
+        (None, Some(a)) => valid(a)? && *key < a.value && a.proof.record.id.is_zero(),
+        (Some(b), Some(a)) => {
+            valid(b)?
+                && valid(a)?
+                && b.value < *key
+                && *key < a.value
+                && b.proof.record.id + one == a.proof.record.id
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::*;
+
+    #[test]
+    fn absence_sorted() -> anyhow::Result<()> {
+        let mut ml: InMemoryLog<u32> = InMemoryLog::default();
+        let empty = ml.latest()?;
+        let proof = prove_absent(&ml, &5, &empty)?.expect("absent from empty log");
+        assert!(verify_absent(&empty, &5, &proof)?);
+
+        for i in 0..20 {
+            ml.append(i * 10)?;
+        }
+        let tree = ml.latest()?;
+        assert_eq!(None, prove_absent(&ml, &50, &tree)?);
+
+        for key in [55, 1, 195, 1000] {
+            let proof = prove_absent(&ml, &key, &tree)?.expect("absent key");
+            assert!(verify_absent(&tree, &key, &proof)?);
+            let present = proof
+                .before
+                .as_ref()
+                .or(proof.after.as_ref())
+                .unwrap()
+                .value;
+            assert!(!verify_absent(&tree, &present, &proof)?);
+        }
+        let proof = prove_absent(&ml, &55, &tree)?.unwrap();
+        assert_eq!(Some(50), proof.before.as_ref().map(|n| n.value));
+        assert_eq!(Some(60), proof.after.as_ref().map(|n| n.value));
+
+        let mut first = prove_absent(&ml, &1, &tree)?.unwrap();
+        first.before = None;
+        assert!(!verify_absent(&tree, &1, &first)?);
+        let mut last = prove_absent(&ml, &1000, &tree)?.unwrap();
+        assert!(last.after.is_none());
+        last.before.as_mut().unwrap().value = 999;
+        assert!(!verify_absent(&tree, &1000, &last)?);
+        Ok(())
+    }
+}
@@ -0,0 +1,197 @@
+//! # Audits
+//!
+//! An auditor starts from a checkpoint it trusts, checks the tree it audits is consistent with it, replays the entries
+//! appended since, recomputing their leaf hashes from their records, and signs an `AuditReport` other parties can check.
+//! A full audit replays the whole log, and also checks the records produce the root of the tree audited.
+use crate::base::*;
+use crate::checkpoint::{LogSigner, LogVerifier};
+use crate::dsse::{DsseError, SignedEnvelope};
+use crate::json::{from_json, to_json};
+use crate::monitor::{is_tombstone, MonitorState};
+use num::{One, Zero};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+/// The payload type of signed audit reports
+pub const AUDIT_REPORT_TYPE: &str = "application/vnd.transparentlog.audit-report+json";
+
+/// The outcome of the audit of a log from a checkpoint
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct AuditReport<LogSize> {
+    /// The checkpoint the audit started from
+    pub from: LogTree<LogSize>,
+    /// The tree audited
+    pub to: LogTree<LogSize>,
+    /// Whether the checkpoint is a prefix of the tree audited
+    pub consistent: bool,
+    /// The first entry replayed, zero for a full audit
+    pub replayed_from: LogSize,
+    /// Whether the records produce the root of the tree audited, only known after a full audit
+    pub root_matches: Option<bool>,
+    /// The entries whose records do not hash to their stored leaf hash
+    pub mismatches: Vec<LogSize>,
+    /// The number of entries replayed without a record, whose leaf hash could not be recomputed
+    pub tombstones: LogSize,
+    /// Audit time, in milliseconds since the UNIX epoch
+    pub timestamp: u64,
+}
+
+impl<LogSize> AuditReport<LogSize> {
+    /// Whether the audit found nothing wrong
+    pub fn passed(&self) -> bool {
+        self.consistent && self.root_matches != Some(false) && self.mismatches.is_empty()
+    }
+}
+
+impl<LogSize: Serialize + DeserializeOwned> AuditReport<LogSize> {
+    /// Sign the report, as a versioned JSON document, in an envelope
+    pub fn sign(&self, signers: &[&dyn LogSigner]) -> anyhow::Result<SignedEnvelope> {
+        SignedEnvelope::sign(AUDIT_REPORT_TYPE, to_json(self)?.into_bytes(), signers)
+    }
+
+    /// Get a report back from its envelope, verifying its signatures
+    /// Returns the report and the names of the keys that signed it
+    pub fn open(
+        envelope: &SignedEnvelope,
+        verifiers: &[&dyn LogVerifier],
+    ) -> anyhow::Result<(Self, Vec<String>)> {
+        let names = envelope.verify(verifiers)?;
+        if envelope.payload_type != AUDIT_REPORT_TYPE {
+            return Err(DsseError::PayloadType(envelope.payload_type.clone()).into());
+        }
+        Ok((from_json(std::str::from_utf8(&envelope.payload)?)?, names))
+    }
+}
+
+/// Audit the tree of the given size of a log from a checkpoint, replaying the entries appended since,
+/// or all the entries for a full audit
+/// A checkpoint of another origin, or beyond the tree audited, is not consistent with it
+pub fn audit<'a, T, TL>(
+    log: &TL,
+    from: &LogTree<TL::LogSize>,
+    size: TL::LogSize,
+    full: bool,
+) -> anyhow::Result<AuditReport<TL::LogSize>>
+where
+    T: Serialize + Deserialize<'a>,
+    TL: TransparentLog<'a, T>,
+{
+    let to = log.head_at(size)?;
+    let consistent = (from.origin.is_none() || from.origin == to.origin)
+        && from.size <= to.size
+        && log
+            .consistency_between(from.size, to.size)?
+            .verify(from, &to);
+
+    let start = if full {
+        TL::LogSize::zero()
+    } else {
+        from.size.min(to.size)
+    };
+    let hasher = log.leaf_hasher();
+    let mut state = MonitorState::<TL::LogSize>::default();
+    let mut mismatches = vec![];
+    let mut tombstones = TL::LogSize::zero();
+    let mut id = start;
+    while id < to.size {
+        let stored = log.get_hash(0, id)?.into_owned();
+        let leaf = match log.get(id) {
+            Ok(Some(record)) => hasher(record.as_ref())?,
+            Ok(None) => break,
+            Err(e) if is_tombstone(&e) => {
+                tombstones = tombstones + TL::LogSize::one();
+                stored.clone()
+            }
+            Err(e) => return Err(e),
+        };
+        if leaf != stored {
+            mismatches.push(id);
+        }
+        state.push(leaf);
+        id = id + TL::LogSize::one();
+    }
+
+    Ok(AuditReport {
+        root_matches: full.then(|| id == to.size && state.root() == to.hash),
+        from: from.clone(),
+        to,
+        consistent,
+        replayed_from: start,
+        mismatches,
+        tombstones,
+        timestamp: RecordMeta::now().timestamp,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::test_helpers::*;
+    use crate::*;
+
+    #[test]
+    fn audit_from_checkpoint() -> anyhow::Result<()> {
+        let mut ml = InMemoryLog::with_origin("log");
+        append_multiple(&mut ml, 5)?;
+        let from = ml.latest()?;
+        append_multiple_offset(&mut ml, 5, 8)?;
+        ml.redact(7)?;
+
+        let report = audit(&ml, &from, 13, false)?;
+        assert!(report.passed());
+        assert!(report.consistent);
+        assert_eq!(ml.latest()?, report.to);
+        assert_eq!(5, report.replayed_from);
+        assert_eq!(1, report.tombstones);
+        assert_eq!(None, report.root_matches);
+
+        let report = audit(&ml, &from, 13, true)?;
+        assert!(report.passed());
+        assert_eq!(0, report.replayed_from);
+        assert_eq!(Some(true), report.root_matches);
+
+        let report = audit(&ml, &ml.head_at(9)?, 13, false)?;
+        assert!(report.passed());
+        assert_eq!(9, report.replayed_from);
+
+        // a checkpoint the log cannot be a continuation of
+        let forged = LogTree {
+            hash: ml.head_at(6)?.hash,
+            ..from.clone()
+        };
+        assert!(!audit(&ml, &forged, 13, false)?.passed());
+        let other = LogTree {
+            origin: Some("other".into()),
+            ..from.clone()
+        };
+        assert!(!audit(&ml, &other, 13, false)?.consistent);
+        assert!(!audit(&ml, &ml.latest()?, 5, false)?.consistent);
+        Ok(())
+    }
+
+    #[test]
+    fn audit_signed_report() -> anyhow::Result<()> {
+        let mut ml = InMemoryLog::default();
+        append_multiple(&mut ml, 13)?;
+        let empty = LogTree {
+            size: 0,
+            hash: String::new(),
+            origin: None,
+        };
+        let report = audit(&ml, &empty, 13, true)?;
+        assert!(report.passed());
+
+        let signer = Ed25519Signer::new("auditor", &[7; 32]);
+        let envelope = report.sign(&[&signer])?;
+        assert_eq!(AUDIT_REPORT_TYPE, envelope.payload_type);
+        let (opened, names) = AuditReport::open(&envelope, &[&signer.verifier()])?;
+        assert_eq!(report, opened);
+        assert_eq!(vec!["auditor".to_owned()], names);
+
+        let other = Ed25519Signer::new("other", &[8; 32]);
+        assert!(AuditReport::<usize>::open(&envelope, &[&other.verifier()]).is_err());
+        let mut tampered = envelope.clone();
+        tampered.payload_type = IN_TOTO_PAYLOAD_TYPE.to_owned();
+        assert!(AuditReport::<usize>::open(&tampered, &[&signer.verifier()]).is_err());
+        Ok(())
+    }
+}
@@ -1,43 +1,94 @@
-use crypto::digest::Digest;
-use crypto::sha2::Sha256;
 use maybe_owned::MaybeOwned;
-use num::{Integer, One, Zero};
-use serde::{Deserialize, Serialize};
+use num::{Integer, NumCast, One, ToPrimitive, Zero};
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use std::hash::Hash;
 use std::{
     collections::{HashMap, HashSet},
-    fmt::Display,
+    fmt::{Debug, Display},
+    io::{Read, Write},
     ops::Add,
+    time::{SystemTime, UNIX_EPOCH},
 };
 use thiserror::Error;
 
-/// The height of the tree
-pub type LogHeight = usize;
+use crate::codec::{hash_with, MessagePack};
+use crate::instrument::{
+    append_span, check_record_span, proofs_span, push_hash_span, record_verification_failure,
+};
+use crate::monitor::MonitorState;
+use crate::raw::hash_bytes;
+use crate::tile::{tile_width, Tile, TILE_HEIGHT};
+use crate::verify::VerifyError;
+
+pub use transparentlog_verify::{
+    convert_size, decode_digest, hash_eq, prefix_proof_positions_iter, proof_positions_iter,
+    tree_sizes, LogHeight, LogTreePosition, ProofPositions, SizeError, DIGEST_SIZE,
+};
+pub(crate) use transparentlog_verify::{first_leaf, hash_pair};
+
+/// Iterator over log entries and their index
+pub type LogIterator<'b, LogSize, T> =
+    Box<dyn Iterator<Item = anyhow::Result<(LogSize, MaybeOwned<'b, T>)>> + 'b>;
+
+/// Function computing the leaf hash of a record
+pub type LeafHasher<T> = fn(&T) -> anyhow::Result<String>;
 
-/// Reference to a Record, with its ID and its hash
-#[derive(Hash, PartialEq, Eq, PartialOrd, Ord, Clone, Debug)]
+/// Reference to a Record, with its ID, its hash and its metadata if known
+#[derive(Hash, PartialEq, Eq, PartialOrd, Ord, Clone, Debug, Serialize, Deserialize)]
 pub struct Record<LogSize> {
     pub id: LogSize,
     pub hash: String,
+    pub meta: Option<RecordMeta>,
 }
 
-/// Position in the tree
-#[derive(Hash, PartialEq, Eq, PartialOrd, Ord, Clone, Debug)]
-pub struct LogTreePosition<LogSize> {
-    pub level: LogHeight,
-    pub index: LogSize,
+impl<LogSize: ToPrimitive> Record<LogSize> {
+    /// Convert the record reference to another size type
+    pub fn convert<S: NumCast>(&self) -> Result<Record<S>, SizeError> {
+        Ok(Record {
+            id: convert_size(&self.id)?,
+            hash: self.hash.clone(),
+            meta: self.meta,
+        })
+    }
 }
 
-impl<LogSize> From<(LogHeight, LogSize)> for LogTreePosition<LogSize> {
-    fn from((level, index): (LogHeight, LogSize)) -> Self {
-        Self { level, index }
+/// Metadata stored alongside a record, not covered by the record hash
+#[derive(Hash, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct RecordMeta {
+    /// Append time, in milliseconds since the UNIX epoch
+    pub timestamp: u64,
+}
+
+impl RecordMeta {
+    /// Metadata with the current time
+    pub fn now() -> Self {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or_default();
+        Self { timestamp }
     }
 }
 
-/// Reference to a full log: its size and root hash
+/// Reference to a full log: its size and root hash, and the origin of the log if it has one
+#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
 pub struct LogTree<LogSize> {
     pub size: LogSize,
     pub hash: String,
+    pub origin: Option<String>,
+}
+
+impl<LogSize: ToPrimitive> LogTree<LogSize> {
+    /// Convert the tree to another size type, to check it against a log of another backend
+    pub fn convert<S: NumCast>(&self) -> Result<LogTree<S>, SizeError> {
+        Ok(LogTree {
+            size: convert_size(&self.size)?,
+            hash: self.hash.clone(),
+            origin: self.origin.clone(),
+        })
+    }
 }
 
 /// Specific Errors the library can return
@@ -47,16 +98,192 @@ pub enum LogError<LogSize: Display> {
     InvalidHeight(LogHeight),
     #[error("Invalid log tree index for height {0}: {1}")]
     InvalidIndex(LogHeight, LogSize),
+    #[error("Missing or incomplete tile at level {0}: {1}")]
+    MissingTile(LogHeight, LogSize),
+}
+
+/// The version of the export format
+pub const EXPORT_VERSION: u8 = 1;
+
+/// Specific Errors when importing a log
+#[derive(Debug, Clone, Error)]
+pub enum ExportError {
+    #[error("Unsupported export version: {0}")]
+    UnsupportedVersion(u8),
+    #[error("Cannot import into a non empty log")]
+    NotEmpty,
+    #[error("Imported log does not match the exported root hash")]
+    RootMismatch,
+}
+
+/// Specific Errors about entries without a record
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum TombstoneError {
+    #[error("This log does not support entries without a record")]
+    Unsupported,
+    #[error("The entry has no record: it was appended as a hash only, or redacted")]
+    NoRecord,
+    #[error("The record expired and was deleted, only its leaf hash {0} is kept")]
+    Expired(String),
+    #[error("Leaf hash {0} is not a hex encoded SHA-256 digest")]
+    InvalidHash(String),
+}
+
+/// A leaf hash appended without its record, lowercased like the hashes of records
+/// Fails if it is not a hex encoded digest, that would not fit the fixed size hash slots of the logs
+pub(crate) fn checked_leaf_hash(hash: &str) -> anyhow::Result<String> {
+    match decode_digest(hash) {
+        Some(_) => Ok(hash.to_ascii_lowercase()),
+        None => Err(TombstoneError::InvalidHash(hash.to_string()).into()),
+    }
+}
+
+/// Specific Errors about the serialized bytes of records
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum RawBytesError {
+    #[error("This log does not keep the serialized bytes of its records")]
+    Unsupported,
+}
+
+/// Specific Errors about record annotations
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum AnnotationError {
+    #[error("This log does not store annotations")]
+    Unsupported,
+}
+
+/// Specific Errors about sealed logs
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum SealError {
+    #[error("This log cannot be sealed")]
+    Unsupported,
+    #[error("The log is sealed and read only")]
+    Sealed,
+}
+
+/// Fail with a `SealError::Sealed` error if the log has a final tree head
+pub(crate) fn check_unsealed<LogSize>(sealed: &Option<LogTree<LogSize>>) -> Result<(), SealError> {
+    match sealed {
+        Some(_) => Err(SealError::Sealed),
+        None => Ok(()),
+    }
+}
+
+/// Specific Errors when a client checks records from another log
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum OriginError {
+    #[error("Log origin {found:?} does not match the expected origin {expected:?}")]
+    Mismatch {
+        expected: Option<String>,
+        found: Option<String>,
+    },
+}
+
+/// Specific Errors about the trees of past sizes
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum HistoryError {
+    #[error("Tree size beyond the size of the log")]
+    BeyondLog,
+    #[error("Tree position without any record")]
+    EmptyPosition,
+    #[error("Tree sizes in decreasing order")]
+    Decreasing,
 }
 
 /// Transparent log Trait
 pub trait TransparentLog<'a, T: Serialize + Deserialize<'a>> {
     /// The type used to represent the log size
-    type LogSize: Integer + Copy + Hash;
+    type LogSize: Integer + Copy + Hash + NumCast + 'static;
 
     /// Add a record, return the record ID
     fn add(&mut self, record: T) -> anyhow::Result<Self::LogSize>;
 
+    /// Add an entry without a record, a tombstone, return its ID
+    /// Getting a tombstone returns a `TombstoneError::NoRecord` error
+    fn add_tombstone(&mut self) -> anyhow::Result<Self::LogSize> {
+        Err(TombstoneError::Unsupported.into())
+    }
+
+    /// Replace the record with the given ID by a tombstone, keeping its hashes and metadata so proofs still hold
+    fn redact(&mut self, _id: Self::LogSize) -> anyhow::Result<()> {
+        Err(TombstoneError::Unsupported.into())
+    }
+
+    /// Expire the record with the given ID: redact it, and remember the tombstone comes from an expiry
+    /// Getting an expired entry returns a `TombstoneError::Expired` error with its leaf hash, instead of a
+    /// `TombstoneError::NoRecord` error; ranges and iterators report it as any other tombstone
+    fn expire(&mut self, _id: Self::LogSize) -> anyhow::Result<()> {
+        Err(TombstoneError::Unsupported.into())
+    }
+
+    /// Whether the record with the given ID expired
+    fn expired(&self, _id: Self::LogSize) -> anyhow::Result<bool> {
+        Ok(false)
+    }
+
+    /// Seal the log: record its final tree head and make its storage read only, any later write failing
+    /// with a `SealError::Sealed` error. Sealing a sealed log returns the same final tree head
+    fn seal(&mut self) -> anyhow::Result<LogTree<Self::LogSize>> {
+        Err(SealError::Unsupported.into())
+    }
+
+    /// The final tree head of the log, if it was sealed
+    fn sealed(&self) -> anyhow::Result<Option<LogTree<Self::LogSize>>> {
+        Ok(None)
+    }
+
+    /// Flush the entries appended so far to durable storage, so they survive a crash once this returns
+    /// Logs in memory, or writing each append durably, have nothing to do
+    fn sync(&self) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Store the metadata of the record with the given ID
+    fn add_meta(&mut self, id: Self::LogSize, meta: RecordMeta) -> anyhow::Result<()>;
+
+    /// Get the metadata of the record with the given ID, if any
+    fn get_meta(&self, id: Self::LogSize) -> anyhow::Result<Option<RecordMeta>>;
+
+    /// Store the annotation of the record with the given ID, replacing any previous one
+    /// Annotations are operational data kept outside the tree: they are not hashed nor proven,
+    /// so they can change without affecting the verification of the record
+    fn set_annotation(&mut self, _id: Self::LogSize, _annotation: Vec<u8>) -> anyhow::Result<()> {
+        Err(AnnotationError::Unsupported.into())
+    }
+
+    /// Get the annotation of the record with the given ID, if any
+    fn get_annotation(&self, _id: Self::LogSize) -> anyhow::Result<Option<Vec<u8>>> {
+        Ok(None)
+    }
+
+    /// The metadata for the next appended record: the current time, but never before the previous record
+    fn next_meta(&self) -> anyhow::Result<RecordMeta> {
+        let mut meta = RecordMeta::now();
+        let size = self.size()?;
+        if size > Self::LogSize::zero() {
+            if let Some(previous) = self.get_meta(size - Self::LogSize::one())? {
+                meta.timestamp = meta.timestamp.max(previous.timestamp);
+            }
+        }
+        Ok(meta)
+    }
+
+    /// The ID of the first record appended at or after the given timestamp, or the log size if there is none
+    /// Records without metadata are considered older than any timestamp
+    fn find_since(&self, timestamp: u64) -> anyhow::Result<Self::LogSize> {
+        let two = Self::LogSize::one().add(Self::LogSize::one());
+        let mut low = Self::LogSize::zero();
+        let mut high = self.size()?;
+        while low < high {
+            let mid = low + (high - low) / two;
+            match self.get_meta(mid)? {
+                Some(meta) if meta.timestamp >= timestamp => high = mid,
+                _ => low = mid + Self::LogSize::one(),
+            }
+        }
+        Ok(low)
+    }
+
     /// Add a hash and index, returns the index of the added hash
     fn add_hash(&mut self, level: LogHeight, hash: String) -> anyhow::Result<Self::LogSize>;
 
@@ -65,25 +292,121 @@ pub trait TransparentLog<'a, T: Serialize + Deserialize<'a>> {
         &self,
         level: LogHeight,
         index: Self::LogSize,
-    ) -> anyhow::Result<MaybeOwned<String>>;
+    ) -> anyhow::Result<MaybeOwned<'_, String>>;
+
+    /// Get the tile at the given tile level and index: the hashes stored at tree level `level * TILE_HEIGHT`,
+    /// starting at index `index * 2^TILE_HEIGHT`, at most 2^TILE_HEIGHT of them
+    fn tile(&self, level: LogHeight, index: Self::LogSize) -> anyhow::Result<Tile<Self::LogSize>> {
+        let tree_level = level * TILE_HEIGHT;
+        let mut hashes = vec![];
+        if let Some(sz) = tree_sizes(self.size()?).get(tree_level) {
+            let mut ix = index * tile_width();
+            let end = *sz.min(&(ix + tile_width()));
+            while ix < end {
+                hashes.push(self.get_hash(tree_level, ix)?.into_owned());
+                ix = ix + Self::LogSize::one();
+            }
+        }
+        Ok(Tile {
+            level,
+            index,
+            hashes,
+        })
+    }
+
+    /// The function computing the leaf hash of a record, from its serialization by the codec of the log
+    fn leaf_hasher(&self) -> LeafHasher<T> {
+        hash
+    }
 
     /// Append a new record to the log and return its index
     fn append(&mut self, record: T) -> anyhow::Result<Record<Self::LogSize>> {
-        let hash = hash(&record)?;
+        let span = append_span();
+        let hash = (self.leaf_hasher())(&record)?;
+        let meta = self.next_meta()?;
         let id = self.add(record)?;
+        span.record_index(&id);
+        self.add_meta(id, meta)?;
         self.push_hash(0, hash.clone())?;
-        Ok(Record { id, hash })
+        Ok(Record {
+            id,
+            hash,
+            meta: Some(meta),
+        })
+    }
+
+    /// Append the leaf hash of a record held elsewhere, storing a tombstone instead of the record
+    /// The record can then be proven as if it had been appended, but the log cannot be exported
+    fn append_hash_only(&mut self, hash: String) -> anyhow::Result<Record<Self::LogSize>> {
+        let hash = checked_leaf_hash(&hash)?;
+        let meta = self.next_meta()?;
+        let id = self.add_tombstone()?;
+        self.add_meta(id, meta)?;
+        self.push_hash(0, hash.clone())?;
+        Ok(Record {
+            id,
+            hash,
+            meta: Some(meta),
+        })
+    }
+
+    /// Append many records at once: hash them, in parallel with the `parallel` feature, add them, then build the new tree nodes level by level
+    fn bulk_append(&mut self, records: Vec<T>) -> anyhow::Result<Vec<Record<Self::LogSize>>>
+    where
+        T: Sync,
+    {
+        #[cfg(feature = "parallel")]
+        let hashes = records
+            .par_iter()
+            .map(self.leaf_hasher())
+            .collect::<anyhow::Result<Vec<String>>>()?;
+        #[cfg(not(feature = "parallel"))]
+        let hashes = records
+            .iter()
+            .map(self.leaf_hasher())
+            .collect::<anyhow::Result<Vec<String>>>()?;
+        let start = self.size()?;
+        let meta = self.next_meta()?;
+        let mut ids = Vec::with_capacity(records.len());
+        for record in records {
+            let id = self.add(record)?;
+            self.add_meta(id, meta)?;
+            ids.push(id);
+        }
+        add_leaf_hashes(self, start, hashes.clone())?;
+
+        Ok(ids
+            .into_iter()
+            .zip(hashes)
+            .map(|(id, hash)| Record {
+                id,
+                hash,
+                meta: Some(meta),
+            })
+            .collect())
+    }
+
+    /// The hashes waiting for their right sibling that the backend keeps, so `push_hash` does not read them back
+    /// from storage; backends without any read the left siblings from storage
+    fn pending_hashes(&mut self) -> Option<&mut PendingHashes<Self::LogSize>> {
+        None
     }
 
     /// Recursively push a hash to the tree at given level
+    /// A right node is hashed with its left sibling, taken from the pending hashes of the backend or else read from storage,
+    /// and a left node becomes the pending hash of its level
     fn push_hash(&mut self, level: LogHeight, hash: String) -> anyhow::Result<Self::LogSize> {
         let hid = self.add_hash(level, hash.clone())?;
-        let two = Self::LogSize::one().add(Self::LogSize::one());
-        if hid.mod_floor(&two) == Self::LogSize::one() {
-            let mut hasher = Sha256::new();
-            let hash1 = self.get_hash(level, hid - Self::LogSize::one())?;
-            hasher.input_str(&format!("{}{}", hash1, hash));
-            self.push_hash(level + 1, hasher.result_str())?;
+        let _span = push_hash_span(level, &hid);
+        if hid.is_odd() {
+            let left_id = hid - Self::LogSize::one();
+            let left = match self.pending_hashes().and_then(|p| p.take(level, left_id)) {
+                Some(h) => h,
+                None => self.get_hash(level, left_id)?.into_owned(),
+            };
+            self.push_hash(level + 1, hash_pair(&left, &hash))?;
+        } else if let Some(pending) = self.pending_hashes() {
+            pending.set(level, hid, hash);
         }
         Ok(hid)
     }
@@ -91,34 +414,190 @@ pub trait TransparentLog<'a, T: Serialize + Deserialize<'a>> {
     /// Get the log size
     fn size(&self) -> anyhow::Result<Self::LogSize>;
 
+    /// Get the origin identifying the log, reported in its tree heads, if it has one
+    fn origin(&self) -> Option<String> {
+        None
+    }
+
     /// Get the latest log size and root hash
+    /// Tree heads are requested far more often than records are appended, so backends should keep their latest tree,
+    /// in a `RootCache`, and serve it reading no more than their size; this default folds the stored hashes on each call
     fn latest(&self) -> anyhow::Result<LogTree<Self::LogSize>> {
         let sz = self.size()?;
-        let two = Self::LogSize::one().add(Self::LogSize::one());
-        let mut r = vec![];
+        Ok(LogTree {
+            size: sz,
+            hash: root_hash(self, sz)?,
+            origin: self.origin(),
+        })
+    }
 
-        for (level, size) in tree_sizes(sz).into_iter().enumerate().rev() {
-            if size.mod_floor(&two) == Self::LogSize::one() {
-                r.push(self.get_hash(level, size - Self::LogSize::one())?)
-            }
+    /// Get the hash of the node at the given position in the current tree
+    /// Complete subtrees have their hash stored, pending ones on the right edge of the tree are calculated
+    /// from the stored hashes below them, and never stored since they change as the log grows
+    fn ephemeral_hash(&self, level: LogHeight, index: Self::LogSize) -> anyhow::Result<String> {
+        node_hash(self, LogTreePosition { level, index }, self.size()?)
+    }
+
+    /// Get the audit path of the record at the given index in the tree of the given size, a past or current size of the log:
+    /// the hashes of the siblings on the path from the record up to the root, in that order, as given by `audit_positions`
+    /// A pending sibling on the right edge of the tree is calculated from the stored hashes below it
+    fn audit_path(&self, index: Self::LogSize, size: Self::LogSize) -> anyhow::Result<Vec<String>> {
+        if size > self.size()? {
+            return Err(HistoryError::BeyondLog.into());
         }
+        if index >= size {
+            return Err(HistoryError::EmptyPosition.into());
+        }
+        audit_positions(index, size)
+            .map(|p| node_hash(self, p, size))
+            .collect()
+    }
 
-        while r.len() > 1 {
-            let s1 = r.pop().unwrap();
-            let s2 = r.pop().unwrap();
-            let mut hasher = Sha256::new();
-            hasher.input_str(&format!("{}{}", s2, s1));
-            r.push(MaybeOwned::Owned(hasher.result_str()));
+    /// Calculate the root hash of the tree when the log had the given size, from the stored hashes of complete subtrees
+    fn root_at(&self, size: Self::LogSize) -> anyhow::Result<String> {
+        if size > self.size()? {
+            return Err(HistoryError::BeyondLog.into());
         }
+        root_hash(self, size)
+    }
 
+    /// Get the tree head when the log had the given size, to check proofs against past sizes
+    fn head_at(&self, size: Self::LogSize) -> anyhow::Result<LogTree<Self::LogSize>> {
         Ok(LogTree {
-            size: sz,
-            hash: r.pop().unwrap_or_default().into_owned(),
+            size,
+            hash: self.root_at(size)?,
+            origin: self.origin(),
+        })
+    }
+
+    /// Get the proof that the tree of size_a is a prefix of the tree of size_b, both past or current sizes of the log
+    fn consistency_between(
+        &self,
+        size_a: Self::LogSize,
+        size_b: Self::LogSize,
+    ) -> anyhow::Result<ConsistencyProof<Self::LogSize>> {
+        if size_a > size_b {
+            return Err(HistoryError::Decreasing.into());
+        }
+        if size_b > self.size()? {
+            return Err(HistoryError::BeyondLog.into());
+        }
+        let proofs = if size_a.is_zero() || size_a == size_b {
+            HashMap::new()
+        } else {
+            self.proofs(prefix_proof_positions(size_a, size_b).into_iter())?
+        };
+        Ok(ConsistencyProof {
+            size_a,
+            size_b,
+            proofs,
         })
     }
 
+    /// Check the stored interior hashes match the hashes of their children, level by level from the leaves,
+    /// stopping at the first mismatch, to detect a corrupted storage without reading any record
+    /// Checking the leaf hashes against the records is the job of an audit
+    fn self_check(&self, depth: CheckDepth) -> anyhow::Result<SelfCheckReport<Self::LogSize>> {
+        let size = self.size()?;
+        let mut report = SelfCheckReport {
+            size,
+            checked: 0,
+            first_bad: None,
+        };
+        let two = Self::LogSize::one() + Self::LogSize::one();
+        for (level, count) in tree_sizes(size).into_iter().enumerate().skip(1) {
+            for index in depth.indices(count) {
+                let left = self.get_hash(level - 1, index * two)?;
+                let right = self.get_hash(level - 1, index * two + Self::LogSize::one())?;
+                report.checked += 1;
+                if self.get_hash(level, index)?.as_str() != hash_pair(&left, &right) {
+                    report.first_bad = Some(LogTreePosition { level, index });
+                    return Ok(report);
+                }
+            }
+        }
+        Ok(report)
+    }
+
     /// Retrieve a log entry by its index
-    fn get(&self, index: Self::LogSize) -> anyhow::Result<Option<MaybeOwned<T>>>;
+    fn get(&self, index: Self::LogSize) -> anyhow::Result<Option<MaybeOwned<'_, T>>>;
+
+    /// Retrieve the record of a log entry as the bytes its codec serialized, without deserializing it
+    /// The backends hash these bytes into the leaf hash, so they can be checked without serializing the record again
+    fn get_raw_bytes(&self, _index: Self::LogSize) -> anyhow::Result<Option<Vec<u8>>> {
+        Err(RawBytesError::Unsupported.into())
+    }
+
+    /// Retrieve the exact bytes the leaf hash of a log entry is the hash of, so verifiers can recompute it
+    /// without deserializing the record and serializing it again, which is slow and breaks on a non canonical serialization
+    /// The backends hash the bytes their codec serialized, logs hashing their records otherwise override it
+    fn get_leaf_bytes(&self, index: Self::LogSize) -> anyhow::Result<Option<Vec<u8>>> {
+        self.get_raw_bytes(index)
+    }
+
+    /// Retrieve the log entries from start (inclusive) to end (exclusive), stopping at the end of the log
+    fn get_range(
+        &self,
+        start: Self::LogSize,
+        end: Self::LogSize,
+    ) -> anyhow::Result<Vec<MaybeOwned<'_, T>>> {
+        let mut v = vec![];
+        let mut index = start;
+        while index < end {
+            match self.get(index)? {
+                Some(r) => v.push(r),
+                None => break,
+            }
+            index = index + Self::LogSize::one();
+        }
+        Ok(v)
+    }
+
+    /// Iterate over all the log entries and their index, in order
+    fn iter(&self) -> LogIterator<'_, Self::LogSize, T> {
+        let mut index = Self::LogSize::zero();
+        let mut done = false;
+        Box::new(std::iter::from_fn(move || {
+            if done {
+                return None;
+            }
+            match self.get(index) {
+                Ok(Some(r)) => {
+                    let id = index;
+                    index = index + Self::LogSize::one();
+                    Some(Ok((id, r)))
+                }
+                Ok(None) => {
+                    done = true;
+                    None
+                }
+                Err(e) => {
+                    done = true;
+                    Some(Err(e))
+                }
+            }
+        }))
+    }
+
+    /// Find the index of the first record with the given hash
+    fn index_of(&self, hash: &str) -> anyhow::Result<Option<Self::LogSize>>;
+
+    /// Find the indices of all the records with the given hash, in order, the same payload appended several times
+    /// having several entries. By default the leaf hashes are scanned from the first record with that hash
+    fn indices_of(&self, hash: &str) -> anyhow::Result<Vec<Self::LogSize>> {
+        let mut v = vec![];
+        if let Some(first) = self.index_of(hash)? {
+            let size = self.size()?;
+            let mut index = first;
+            while index < size {
+                if self.get_hash(0, index)?.as_str() == hash {
+                    v.push(index);
+                }
+                index = index + Self::LogSize::one();
+            }
+        }
+        Ok(v)
+    }
 
     /// Return the requested proofs from the log
     fn proofs<I>(
@@ -128,17 +607,243 @@ pub trait TransparentLog<'a, T: Serialize + Deserialize<'a>> {
     where
         I: Iterator<Item = LogTreePosition<Self::LogSize>>,
     {
+        let positions: Vec<_> = positions.collect();
+        let _span = proofs_span("default", positions.len());
         positions
+            .into_iter()
             .map(|p| {
                 let hash = self.get_hash(p.level, p.index)?.into_owned();
                 Ok((p, hash))
             })
             .collect()
     }
+
+    /// Export the log truncated at the given size: the version and size, each record with its metadata, then the root hash
+    /// Returns the exported tree
+    fn export<W: Write>(
+        &self,
+        size: Self::LogSize,
+        mut writer: W,
+    ) -> anyhow::Result<LogTree<Self::LogSize>>
+    where
+        Self::LogSize: Serialize,
+    {
+        let size = size.min(self.size()?);
+        rmp_serde::encode::write(&mut writer, &(EXPORT_VERSION, size))?;
+        let mut state = MonitorState::<Self::LogSize>::default();
+        for r in self.iter() {
+            let (id, record) = r?;
+            if id >= size {
+                break;
+            }
+            state.push((self.leaf_hasher())(record.as_ref())?);
+            rmp_serde::encode::write(&mut writer, &(record.as_ref(), self.get_meta(id)?))?;
+        }
+        let tree = LogTree {
+            size,
+            hash: state.root(),
+            origin: self.origin(),
+        };
+        rmp_serde::encode::write(&mut writer, &tree.hash)?;
+        writer.flush()?;
+        Ok(tree)
+    }
+}
+
+/// The hashes of the complete subtrees still waiting for their right sibling, at most one per level,
+/// the peaks of the Merkle mountain range of the log: a level has one when it holds an odd number of hashes
+/// Each hash is kept with its index, so a hash stored by other means than `push_hash` is never mistaken for it
+#[derive(Debug, Clone)]
+pub struct PendingHashes<LogSize> {
+    levels: Vec<Option<(LogSize, String)>>,
+}
+
+impl<LogSize> Default for PendingHashes<LogSize> {
+    fn default() -> Self {
+        Self { levels: vec![] }
+    }
+}
+
+impl<LogSize: PartialEq + Copy> PendingHashes<LogSize> {
+    /// Remove and return the pending hash of the given level, if it is the one at the given index
+    pub fn take(&mut self, level: LogHeight, index: LogSize) -> Option<String> {
+        match self.levels.get(level) {
+            Some(Some((ix, _))) if *ix == index => self.levels[level].take().map(|(_, h)| h),
+            _ => None,
+        }
+    }
+
+    /// Keep the hash at the given level and index until its right sibling is pushed
+    pub fn set(&mut self, level: LogHeight, index: LogSize, hash: String) {
+        if self.levels.len() <= level {
+            self.levels.resize(level + 1, None);
+        }
+        self.levels[level] = Some((index, hash));
+    }
+
+    /// Forget all the pending hashes, when the stored levels were rolled back
+    pub fn clear(&mut self) {
+        self.levels.clear();
+    }
+}
+
+/// The indices of the records by leaf hash, for the logs keeping their lookup in memory
+/// A hash appended several times keeps all its indices, in order, so duplicates can be found and counted
+#[derive(Debug, Clone)]
+pub struct HashIndex<LogSize> {
+    indices: HashMap<String, Vec<LogSize>>,
+}
+
+impl<LogSize> Default for HashIndex<LogSize> {
+    fn default() -> Self {
+        Self {
+            indices: HashMap::new(),
+        }
+    }
+}
+
+impl<LogSize: Copy> HashIndex<LogSize> {
+    /// Add the index of a record with the given hash, indices being added in increasing order
+    pub fn insert(&mut self, hash: String, index: LogSize) {
+        self.indices.entry(hash).or_default().push(index);
+    }
+
+    /// The index of the first record with the given hash
+    pub fn first(&self, hash: &str) -> Option<LogSize> {
+        self.all(hash).first().copied()
+    }
+
+    /// The indices of all the records with the given hash
+    pub fn all(&self, hash: &str) -> &[LogSize] {
+        self.indices.get(hash).map_or(&[][..], Vec::as_slice)
+    }
+
+    /// The hashes of several records, with their indices
+    pub fn duplicates(&self) -> impl Iterator<Item = (&str, &[LogSize])> {
+        self.indices
+            .iter()
+            .filter(|(_, v)| v.len() > 1)
+            .map(|(h, v)| (h.as_str(), v.as_slice()))
+    }
+}
+
+/// How many of the stored interior hashes a self-check reads
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckDepth {
+    /// All of them
+    Full,
+    /// About the given number per level, evenly spread, always including the last one written
+    Sampled(u64),
+}
+
+impl CheckDepth {
+    /// The indices to check in a level of the given size
+    fn indices<LogSize: Integer + Copy + NumCast + 'static>(
+        self,
+        count: LogSize,
+    ) -> impl Iterator<Item = LogSize> {
+        let step = match self {
+            CheckDepth::Full => LogSize::one(),
+            CheckDepth::Sampled(n) => {
+                let n: LogSize = NumCast::from(n).unwrap_or(count);
+                (count / n.max(LogSize::one())).max(LogSize::one())
+            }
+        };
+        let last = (count > LogSize::zero() && !((count - LogSize::one()) % step).is_zero())
+            .then(|| count - LogSize::one());
+        std::iter::successors(Some(LogSize::zero()), move |i| Some(*i + step))
+            .take_while(move |i| *i < count)
+            .chain(last)
+    }
+}
+
+/// The outcome of the self-check of the hashes stored by a log
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SelfCheckReport<LogSize> {
+    /// The size of the log checked
+    pub size: LogSize,
+    /// The number of interior hashes checked
+    pub checked: u64,
+    /// The first interior hash not matching its children, by level then index
+    pub first_bad: Option<LogTreePosition<LogSize>>,
+}
+
+impl<LogSize> SelfCheckReport<LogSize> {
+    /// Whether all the hashes checked match their children
+    pub fn passed(&self) -> bool {
+        self.first_bad.is_none()
+    }
+}
+
+/// Marks the proof sources that are logs, every `TransparentLog` being a `ProofSource<'a, T, Local>`
+#[derive(Debug)]
+pub enum Local {}
+
+/// Marks the proof sources reading a log from elsewhere, implementing `ProofSource<'a, T, Remote>`
+/// The marker keeps their implementations apart from the one of all the logs
+#[derive(Debug)]
+pub enum Remote {}
+
+/// The read side of a log that clients check records against: its latest tree, proofs and entries
+/// Every `TransparentLog` is a proof source, and so can be remote logs that cannot be written to
+/// The methods are named apart from the `TransparentLog` ones so both traits can be in scope
+pub trait ProofSource<'a, T, K = Local> {
+    /// The type used to represent the log size
+    type LogSize: Integer + Copy + Hash + NumCast + 'static;
+
+    /// The origin of the log, if it has one
+    fn log_origin(&self) -> Option<String>;
+
+    /// Fetch the latest tree of the log
+    fn fetch_latest(&self) -> anyhow::Result<LogTree<Self::LogSize>>;
+
+    /// Fetch the hashes at the given positions
+    fn fetch_proofs<I>(
+        &self,
+        positions: I,
+    ) -> anyhow::Result<HashMap<LogTreePosition<Self::LogSize>, String>>
+    where
+        I: Iterator<Item = LogTreePosition<Self::LogSize>>;
+
+    /// Fetch the entry at the given index, if there is a record
+    fn fetch_entry(&self, index: Self::LogSize) -> anyhow::Result<Option<MaybeOwned<'_, T>>>;
+
+    /// The function hashing the entries into leaf hashes
+    fn entry_hasher(&self) -> LeafHasher<T>;
+}
+
+impl<'a, T: Serialize + Deserialize<'a>, TL: TransparentLog<'a, T>> ProofSource<'a, T> for TL {
+    type LogSize = TL::LogSize;
+
+    fn log_origin(&self) -> Option<String> {
+        self.origin()
+    }
+
+    fn fetch_latest(&self) -> anyhow::Result<LogTree<Self::LogSize>> {
+        self.latest()
+    }
+
+    fn fetch_proofs<I>(
+        &self,
+        positions: I,
+    ) -> anyhow::Result<HashMap<LogTreePosition<Self::LogSize>, String>>
+    where
+        I: Iterator<Item = LogTreePosition<Self::LogSize>>,
+    {
+        self.proofs(positions)
+    }
+
+    fn fetch_entry(&self, index: Self::LogSize) -> anyhow::Result<Option<MaybeOwned<'_, T>>> {
+        self.get(index)
+    }
+
+    fn entry_hasher(&self) -> LeafHasher<T> {
+        self.leaf_hasher()
+    }
 }
 
 /// A simple log client, optionally keeping a cache of tree entries
-pub trait LogClient<'a, T: Serialize + Deserialize<'a>, TL: TransparentLog<'a, T>> {
+pub trait LogClient<'a, T: Serialize + Deserialize<'a>, TL: ProofSource<'a, T, K>, K = Local> {
     /// Get the latest tree information stored
     fn latest(&self) -> &LogTree<TL::LogSize>;
 
@@ -156,48 +861,220 @@ pub trait LogClient<'a, T: Serialize + Deserialize<'a>, TL: TransparentLog<'a, T
 pub fn check_record<
     'a,
     T: Serialize + Deserialize<'a>,
-    TL: TransparentLog<'a, T>,
-    LC: LogClient<'a, T, TL>,
+    K,
+    TL: ProofSource<'a, T, K>,
+    LC: LogClient<'a, T, TL, K>,
 >(
     client: &mut LC,
     log: &TL,
     record: &Record<TL::LogSize>,
 ) -> anyhow::Result<bool> {
-    if record.id >= client.latest().size {
-        let l2 = log.latest()?;
-
-        if client.latest().size > TL::LogSize::zero() {
-            let v = prefix_proof_positions(client.latest().size, l2.size);
-            let proofs = get_proofs(client, log, v)?;
-            if !verify_tree(client.latest(), &proofs) {
-                return Ok(false);
-            }
-            if !verify_tree(&l2, &proofs) {
-                return Ok(false);
-            }
+    Ok(verified_proofs(client, log, record)?.is_some())
+}
+
+/// Check several records are contained in the given log, against a single latest tree
+/// The proofs of all records are fetched at once, and the latest tree of the client updated at most once
+/// Returns whether each record is included
+pub fn check_records<
+    'a,
+    T: Serialize + Deserialize<'a>,
+    K,
+    TL: ProofSource<'a, T, K>,
+    LC: LogClient<'a, T, TL, K>,
+>(
+    client: &mut LC,
+    log: &TL,
+    records: &[Record<TL::LogSize>],
+) -> anyhow::Result<Vec<bool>> {
+    check_origin(client, log)?;
+    let last = records.iter().map(|r| r.id).max();
+    if let Some(last) = last {
+        if last >= client.latest().size && !update_latest(client, log)? {
+            records.iter().for_each(|_| record_verification_failure());
+            return Ok(vec![false; records.len()]);
         }
-        client.set_latest(l2);
     }
-    let v = proof_positions(record.id, client.latest().size);
+    let size = client.latest().size;
+    let mut v = HashSet::new();
+    for record in records.iter().filter(|r| r.id < size) {
+        v.extend(proof_positions_iter(record.id, size));
+    }
     let proofs = get_proofs(client, log, v)?;
-    Ok(verify(client.latest(), record, &proofs))
+    Ok(records
+        .iter()
+        .map(|r| {
+            let ok = verify(client.latest(), r, &proofs);
+            if !ok {
+                record_verification_failure();
+            }
+            ok
+        })
+        .collect())
 }
 
-/// Get all the proofs from a given log to a given client, for all the positions
-fn get_proofs<
+/// Get the entry at the given index from the given log, hashing it and checking it is included like `check_record`
+/// Returns the record only if its proof of inclusion checks out, and none if there is no entry at the index or if it does not
+pub fn get_verified<
+    'l,
+    'a,
+    T: Serialize + Deserialize<'a>,
+    K,
+    TL: ProofSource<'a, T, K>,
+    LC: LogClient<'a, T, TL, K>,
+>(
+    client: &mut LC,
+    log: &'l TL,
+    id: TL::LogSize,
+) -> anyhow::Result<Option<MaybeOwned<'l, T>>> {
+    let entry = match log.fetch_entry(id)? {
+        Some(entry) => entry,
+        None => return Ok(None),
+    };
+    let record = Record {
+        id,
+        hash: (log.entry_hasher())(entry.as_ref())?,
+        meta: None,
+    };
+    Ok(verified_proofs(client, log, &record)?.map(|_| entry))
+}
+
+/// Get the leaf bytes of the entry at the given index from the given log, checking they are included like `get_verified`
+/// The bytes are hashed as they are read, so large records are neither deserialized nor serialized again
+/// Returns the bytes only if their proof of inclusion checks out, and none if there is no entry at the index or if it does not
+pub fn get_verified_bytes<
     'a,
     T: Serialize + Deserialize<'a>,
     TL: TransparentLog<'a, T>,
     LC: LogClient<'a, T, TL>,
+>(
+    client: &mut LC,
+    log: &TL,
+    id: TL::LogSize,
+) -> anyhow::Result<Option<Vec<u8>>> {
+    let bytes = match log.get_leaf_bytes(id)? {
+        Some(bytes) => bytes,
+        None => return Ok(None),
+    };
+    let record = Record {
+        id,
+        hash: hash_bytes(&bytes),
+        meta: None,
+    };
+    Ok(verified_proofs(client, log, &record)?.map(|_| bytes))
+}
+
+/// Check a record is contained in the given log, updating the latest tree of the client if needed
+/// Returns the proofs of inclusion in the latest tree, if the record is included
+pub(crate) fn verified_proofs<
+    'a,
+    T: Serialize + Deserialize<'a>,
+    K,
+    TL: ProofSource<'a, T, K>,
+    LC: LogClient<'a, T, TL, K>,
+>(
+    client: &mut LC,
+    log: &TL,
+    record: &Record<TL::LogSize>,
+) -> anyhow::Result<Option<HashMap<LogTreePosition<TL::LogSize>, String>>> {
+    let _span = check_record_span(&record.id, &client.latest().size);
+    check_origin(client, log)?;
+    if record.id >= client.latest().size
+        && (!update_latest(client, log)? || record.id >= client.latest().size)
+    {
+        record_verification_failure();
+        return Ok(None);
+    }
+    let v = proof_positions(record.id, client.latest().size);
+    let proofs = get_proofs(client, log, v)?;
+    if !verify(client.latest(), record, &proofs) {
+        record_verification_failure();
+        return Ok(None);
+    }
+    Ok(Some(proofs))
+}
+
+/// Never mix the proofs of different logs, a client without an origin adopting the one of the log
+fn check_origin<
+    'a,
+    T: Serialize + Deserialize<'a>,
+    K,
+    TL: ProofSource<'a, T, K>,
+    LC: LogClient<'a, T, TL, K>,
+>(
+    client: &LC,
+    log: &TL,
+) -> Result<(), OriginError> {
+    let origin = log.log_origin();
+    if client.latest().origin.is_some() && client.latest().origin != origin {
+        return Err(OriginError::Mismatch {
+            expected: client.latest().origin.clone(),
+            found: origin,
+        });
+    }
+    Ok(())
+}
+
+/// Move the client to the latest tree of the log, if it is consistent with the latest tree of the client
+/// Returns false if the trees are inconsistent
+fn update_latest<
+    'a,
+    T: Serialize + Deserialize<'a>,
+    K,
+    TL: ProofSource<'a, T, K>,
+    LC: LogClient<'a, T, TL, K>,
+>(
+    client: &mut LC,
+    log: &TL,
+) -> anyhow::Result<bool> {
+    let l2 = log.fetch_latest()?;
+    move_latest(client, log, l2)
+}
+
+/// Move the client to the given tree, if it is consistent with the latest tree of the client, using the proofs of the log
+/// Returns false if the trees are inconsistent
+pub(crate) fn move_latest<
+    'a,
+    T: Serialize + Deserialize<'a>,
+    K,
+    TL: ProofSource<'a, T, K>,
+    LC: LogClient<'a, T, TL, K>,
+>(
+    client: &mut LC,
+    log: &TL,
+    l2: LogTree<TL::LogSize>,
+) -> anyhow::Result<bool> {
+    let size = client.latest().size;
+    if l2.size <= size {
+        // the log did not grow, it must still be the same tree
+        return Ok(l2.size == size && hash_eq(&l2.hash, &client.latest().hash));
+    }
+    if size > TL::LogSize::zero() {
+        let v = prefix_proof_positions(size, l2.size);
+        let proofs = get_proofs(client, log, v)?;
+        if !verify_tree(client.latest(), &proofs) || !verify_tree(&l2, &proofs) {
+            return Ok(false);
+        }
+    }
+    client.set_latest(l2);
+    Ok(true)
+}
+
+/// Get all the proofs from a given log to a given client, for all the positions
+fn get_proofs<
+    'a,
+    T: Serialize + Deserialize<'a>,
+    K,
+    TL: ProofSource<'a, T, K>,
+    LC: LogClient<'a, T, TL, K>,
 >(
     client: &mut LC,
     log: &TL,
     positions: HashSet<LogTreePosition<TL::LogSize>>,
 ) -> anyhow::Result<HashMap<LogTreePosition<TL::LogSize>, String>> {
     let mut cached: HashMap<LogTreePosition<TL::LogSize>, String> = HashMap::new();
-    let read = log.proofs(positions.into_iter().filter(|p| {
+    let read = log.fetch_proofs(positions.into_iter().filter(|p| {
         if let Some(h) = client.cached(p) {
-            cached.insert(p.clone(), h);
+            cached.insert(*p, h);
             return false;
         }
         true
@@ -206,93 +1083,171 @@ fn get_proofs<
     if cached.is_empty() {
         Ok(read)
     } else {
-        cached.extend(read.into_iter());
+        cached.extend(read);
         Ok(cached)
     }
 }
 
-/// Hash a given record via its Serialize instance
-pub fn hash<T: Serialize>(record: &T) -> anyhow::Result<String> {
-    let mut hasher = Sha256::new();
-    hasher.input(&rmp_serde::to_vec(record)?);
-    Ok(hasher.result_str())
+/// Import a log exported with `TransparentLog::export` into the given empty log, checking the result matches the exported root hash
+/// Returns the imported tree
+pub fn import<'a, T, TL, R>(log: &mut TL, mut reader: R) -> anyhow::Result<LogTree<TL::LogSize>>
+where
+    T: Serialize + DeserializeOwned,
+    TL: TransparentLog<'a, T>,
+    TL::LogSize: DeserializeOwned,
+    R: Read,
+{
+    if !log.size()?.is_zero() {
+        return Err(ExportError::NotEmpty.into());
+    }
+    let (version, size): (u8, TL::LogSize) = rmp_serde::decode::from_read(&mut reader)?;
+    if version != EXPORT_VERSION {
+        return Err(ExportError::UnsupportedVersion(version).into());
+    }
+    let mut id = TL::LogSize::zero();
+    while id < size {
+        let (record, meta): (T, Option<RecordMeta>) = rmp_serde::decode::from_read(&mut reader)?;
+        let r = log.append(record)?;
+        if let Some(meta) = meta {
+            log.add_meta(r.id, meta)?;
+        }
+        id = id + TL::LogSize::one();
+    }
+    let hash: String = rmp_serde::decode::from_read(&mut reader)?;
+    let tree = log.latest()?;
+    if tree.size != size || !hash_eq(&tree.hash, &hash) {
+        return Err(ExportError::RootMismatch.into());
+    }
+    Ok(tree)
 }
 
-/// Return the level sizes for each level of the tree
-pub fn tree_sizes<LogSize: Integer + Copy>(size: LogSize) -> Vec<LogSize> {
-    let mut v = vec![];
-    let mut sz = size;
-    let mut height = LogSize::one();
-    let two = LogSize::one().add(LogSize::one());
-    if sz > LogSize::zero() {
-        v.push(sz);
-        while height < size && sz > LogSize::zero() {
-            if sz.is_one() {
-                sz = LogSize::zero();
-            } else {
-                sz = sz.div(two);
-            }
-            v.push(sz);
-            height = height * two;
+/// Add the leaf hashes of records added at the end of a log of the given size,
+/// building the new tree nodes level by level
+pub(crate) fn add_leaf_hashes<'a, T, TL>(
+    log: &mut TL,
+    size: TL::LogSize,
+    hashes: Vec<String>,
+) -> anyhow::Result<()>
+where
+    T: Serialize + Deserialize<'a>,
+    TL: TransparentLog<'a, T> + ?Sized,
+{
+    let two = TL::LogSize::one().add(TL::LogSize::one());
+    let mut level_start = size;
+    let mut level = 0;
+    let mut level_hashes = hashes;
+    while !level_hashes.is_empty() {
+        for h in level_hashes.iter() {
+            log.add_hash(level, h.clone())?;
         }
+        if level_start.mod_floor(&two).is_one() {
+            level_start = level_start - TL::LogSize::one();
+            level_hashes.insert(0, log.get_hash(level, level_start)?.into_owned());
+        }
+        #[cfg(feature = "parallel")]
+        let chunks = level_hashes.par_chunks_exact(2);
+        #[cfg(not(feature = "parallel"))]
+        let chunks = level_hashes.chunks_exact(2);
+        level_hashes = chunks.map(|c| hash_pair(&c[0], &c[1])).collect();
+        level_start = level_start / two;
+        level += 1;
     }
+    Ok(())
+}
 
-    v
+/// Deserialize a stored record, an empty one being a tombstone
+pub(crate) fn decode_record<T: DeserializeOwned>(bs: &[u8]) -> anyhow::Result<T> {
+    if bs.is_empty() {
+        return Err(TombstoneError::NoRecord.into());
+    }
+    Ok(rmp_serde::from_slice(bs)?)
 }
 
-/// Calculate the proof position needed to assert the record at the given index is present in a log of the given size
-pub fn proof_positions<LogSize: Integer + Copy + Hash>(
-    index: LogSize,
-    size: LogSize,
-) -> HashSet<LogTreePosition<LogSize>> {
+/// The error of getting the tombstone at the given index: a `TombstoneError::Expired` error with its leaf hash
+/// if the record expired, else a `TombstoneError::NoRecord` error
+pub fn tombstone_error<'a, T, TL>(log: &TL, index: TL::LogSize) -> anyhow::Error
+where
+    T: Serialize + Deserialize<'a>,
+    TL: TransparentLog<'a, T>,
+{
+    let expired = || -> anyhow::Result<anyhow::Error> {
+        Ok(if log.expired(index)? {
+            TombstoneError::Expired(log.get_hash(0, index)?.into_owned()).into()
+        } else {
+            TombstoneError::NoRecord.into()
+        })
+    };
+    expired().unwrap_or_else(|e| e)
+}
+
+/// Calculate the root hash of the tree of the given size, empty for an empty tree
+fn root_hash<'a, T, TL>(log: &TL, size: TL::LogSize) -> anyhow::Result<String>
+where
+    T: Serialize + Deserialize<'a>,
+    TL: TransparentLog<'a, T> + ?Sized,
+{
     let sizes = tree_sizes(size);
-    let mut proof = HashSet::new();
     if sizes.is_empty() {
-        return proof;
+        return Ok(String::new());
     }
-    proof_step(0, index, size, &sizes, &mut proof);
-    proof
+    node_hash(
+        log,
+        LogTreePosition {
+            level: sizes.len() - 1,
+            index: TL::LogSize::zero(),
+        },
+        size,
+    )
 }
 
-/// Calculate one level of proof
-fn proof_step<LogSize: Integer + Copy + Hash>(
-    level: LogHeight,
-    index: LogSize,
-    size: LogSize,
-    sizes: &[LogSize],
-    proof: &mut HashSet<LogTreePosition<LogSize>>,
-) {
-    let two = LogSize::one().add(LogSize::one());
-    if index.mod_floor(&two).is_zero() {
-        if index + LogSize::one() < size {
-            proof.insert(LogTreePosition {
-                level,
-                index: index + LogSize::one(),
-            });
-        } else {
-            let mut new_level = level;
-            let mut new_index = index + LogSize::one();
-            while new_level > 0 {
-                new_level -= 1;
-                new_index = new_index * two;
-                if (new_index as LogSize) < sizes[new_level] {
-                    proof.insert(LogTreePosition {
-                        level: new_level,
-                        index: new_index as LogSize,
-                    });
-                    break;
-                }
-            }
-        }
-    } else {
-        proof.insert(LogTreePosition {
-            level,
-            index: index - LogSize::one(),
-        });
+/// Calculate the hash of a node in the tree of the given size, recursively going down to the stored hashes of complete subtrees
+fn node_hash<'a, T, TL>(
+    log: &TL,
+    position: LogTreePosition<TL::LogSize>,
+    size: TL::LogSize,
+) -> anyhow::Result<String>
+where
+    T: Serialize + Deserialize<'a>,
+    TL: TransparentLog<'a, T> + ?Sized,
+{
+    if first_leaf(&position) >= size {
+        return Err(HistoryError::EmptyPosition.into());
+    }
+    let one = TL::LogSize::one();
+    let next = LogTreePosition {
+        level: position.level,
+        index: position.index + one,
+    };
+    if first_leaf(&next) <= size {
+        return Ok(log.get_hash(position.level, position.index)?.into_owned());
     }
-    if level < sizes.len() - 1 {
-        proof_step(level + 1, index / two, size / two, sizes, proof);
+    // a pending node is never at level 0, its right child is pending too or has no leaf in the tree
+    let left = LogTreePosition {
+        level: position.level - 1,
+        index: position.index * (one + one),
+    };
+    let right = LogTreePosition {
+        level: left.level,
+        index: left.index + one,
+    };
+    let h1 = node_hash(log, left, size)?;
+    if first_leaf(&right) >= size {
+        return Ok(h1);
     }
+    Ok(hash_pair(&h1, &node_hash(log, right, size)?))
+}
+
+/// Hash a given record via its Serialize instance, serialized as MessagePack
+pub fn hash<T: Serialize>(record: &T) -> anyhow::Result<String> {
+    hash_with::<MessagePack, T>(record)
+}
+
+/// Calculate the proof position needed to assert the record at the given index is present in a log of the given size
+pub fn proof_positions<LogSize: Integer + Copy + Hash>(
+    index: LogSize,
+    size: LogSize,
+) -> HashSet<LogTreePosition<LogSize>> {
+    proof_positions_iter(index, size).collect()
 }
 
 /// Calculate the proof positions needed to assert a tree of size1 is a prefix of a tree of size 2
@@ -302,35 +1257,94 @@ pub fn prefix_proof_positions<LogSize: Integer + Copy + Hash>(
 ) -> HashSet<LogTreePosition<LogSize>> {
     assert!(size1 > LogSize::zero());
     assert!(size1 < size2);
+    prefix_proof_positions_iter(size1, size2).collect()
+}
 
-    let mut proof = proof_positions(size1, size2);
-    proof.extend(proof_positions(size1 - LogSize::one(), size2));
-    let two = LogSize::one().add(LogSize::one());
-    let sizes2 = tree_sizes(size2);
-    let m = sizes2.len() - 1;
-    for (ix, sz) in sizes2.into_iter().enumerate() {
-        if ix < m && sz.mod_floor(&two).is_one() {
-            proof.insert(LogTreePosition {
-                level: ix,
-                index: sz - LogSize::one(),
-            });
-            break;
-        }
+/// Calculate the proof positions needed to assert the records from start (inclusive) to end (exclusive) belong to a tree of the given size
+/// These are the roots of the largest stored subtrees around the range, so no position covers a record of the range
+pub fn range_proof_positions<LogSize: Integer + Copy + Hash>(
+    start: LogSize,
+    end: LogSize,
+    size: LogSize,
+) -> HashSet<LogTreePosition<LogSize>> {
+    let sizes = tree_sizes(size);
+    let mut proof = HashSet::new();
+    if start < end && end <= size {
+        range_step(
+            LogTreePosition {
+                level: sizes.len() - 1,
+                index: LogSize::zero(),
+            },
+            start,
+            end,
+            &sizes,
+            &mut proof,
+        );
     }
     proof
 }
 
+/// Calculate the proof positions for a range under a given position
+fn range_step<LogSize: Integer + Copy + Hash>(
+    position: LogTreePosition<LogSize>,
+    start: LogSize,
+    end: LogSize,
+    sizes: &[LogSize],
+    proof: &mut HashSet<LogTreePosition<LogSize>>,
+) {
+    let first = first_leaf(&position);
+    let last = first_leaf(&LogTreePosition {
+        level: position.level,
+        index: position.index + LogSize::one(),
+    })
+    .min(sizes[0]);
+    // absent subtrees and subtrees inside the range need no proof
+    if first >= sizes[0] || (start <= first && last <= end) {
+        return;
+    }
+    if (last <= start || end <= first) && position.index < sizes[position.level] {
+        proof.insert(position);
+        return;
+    }
+    // a single leaf is either in the range or stored, so this is never reached at level 0
+    let two = LogSize::one().add(LogSize::one());
+    let index = position.index * two;
+    for index in [index, index + LogSize::one()] {
+        range_step(
+            LogTreePosition {
+                level: position.level - 1,
+                index,
+            },
+            start,
+            end,
+            sizes,
+            proof,
+        );
+    }
+}
+
 /// Verify that a given record belongs to the given tree, using the proofs provided
+/// Only the proofs at the positions needed for the record are used, so other hashes cannot stand for the record
 pub fn verify<LogSize: Integer + Copy + Hash>(
     tree: &LogTree<LogSize>,
     record: &Record<LogSize>,
     proofs: &HashMap<LogTreePosition<LogSize>, String>,
 ) -> bool {
-    let sizes = tree_sizes(tree.size);
-    if sizes.is_empty() {
-        return false;
+    matches!(try_verify(tree, record, proofs), Ok(true))
+}
+
+/// Verify that a given record belongs to the given tree, using the proofs provided, telling why it cannot be checked
+/// Returns false if the proofs lead to another root, and fails with a `VerifyError::OutOfTree` error for a record
+/// outside the tree, or a `VerifyError::MissingProof` error if a position needed is missing or has an empty hash
+pub fn try_verify<LogSize: Integer + Copy + Hash>(
+    tree: &LogTree<LogSize>,
+    record: &Record<LogSize>,
+    proofs: &HashMap<LogTreePosition<LogSize>, String>,
+) -> Result<bool, VerifyError> {
+    if record.id >= tree.size {
+        return Err(VerifyError::OutOfTree);
     }
-    let mut proofs2 = proofs.clone();
+    let mut proofs2 = needed_proofs(proof_positions_iter(record.id, tree.size), proofs)?;
     proofs2.insert(
         LogTreePosition {
             level: 0,
@@ -338,15 +1352,316 @@ pub fn verify<LogSize: Integer + Copy + Hash>(
         },
         record.hash.clone(),
     );
-    tree.hash
-        == calc_hash(
-            LogTreePosition {
-                level: sizes.len() - 1,
-                index: LogSize::zero(),
-            },
-            &proofs2,
-            &sizes,
-        )
+    Ok(hash_eq(
+        &tree.hash,
+        &calc_root(&proofs2, &tree_sizes(tree.size))?,
+    ))
+}
+
+/// Iterate over the positions of the siblings on the path from the record at the given index up to the root
+/// of the tree of the given size, skipping the levels where the node has no sibling with a record in the tree
+pub fn audit_positions<LogSize: Integer + Copy>(
+    index: LogSize,
+    size: LogSize,
+) -> impl Iterator<Item = LogTreePosition<LogSize>> {
+    let two = LogSize::one() + LogSize::one();
+    let mut level = 0;
+    let mut index = index;
+    let mut width = LogSize::one();
+    std::iter::from_fn(move || {
+        while width < size {
+            let sibling = if index.is_odd() {
+                index - LogSize::one()
+            } else {
+                index + LogSize::one()
+            };
+            let present = sibling * width < size;
+            let position = LogTreePosition {
+                level,
+                index: sibling,
+            };
+            level += 1;
+            index = index / two;
+            width = width * two;
+            if present {
+                return Some(position);
+            }
+        }
+        None
+    })
+}
+
+/// Verify that a given record belongs to the given tree, folding the hashes of its audit path from the leaf up
+/// The path must have exactly one hash per position given by `audit_positions`
+pub fn verify_audit_path<LogSize: Integer + Copy>(
+    tree: &LogTree<LogSize>,
+    record: &Record<LogSize>,
+    path: &[String],
+) -> bool {
+    if record.id >= tree.size {
+        return false;
+    }
+    let mut hashes = path.iter();
+    let mut hash = record.hash.clone();
+    for position in audit_positions(record.id, tree.size) {
+        // empty hashes are never valid, as in `calc_hash`
+        let sibling = match hashes.next() {
+            Some(h) if !h.is_empty() => h,
+            _ => return false,
+        };
+        hash = if position.index.is_odd() {
+            hash_pair(&hash, sibling)
+        } else {
+            hash_pair(sibling, &hash)
+        };
+    }
+    hashes.next().is_none() && hash_eq(&hash, &tree.hash)
+}
+
+/// A record with the proofs of its inclusion in a tree
+#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
+#[serde(bound(
+    serialize = "LogSize: Serialize + Ord + Clone",
+    deserialize = "LogSize: Deserialize<'de> + Eq + Hash"
+))]
+pub struct InclusionProof<LogSize: Eq + Hash> {
+    pub record: Record<LogSize>,
+    #[serde(with = "crate::json::proof_hashes")]
+    pub proofs: HashMap<LogTreePosition<LogSize>, String>,
+}
+
+impl<LogSize: Integer + Copy + Hash> InclusionProof<LogSize> {
+    /// Verify that the record belongs to the given tree
+    pub fn verify(&self, tree: &LogTree<LogSize>) -> bool {
+        verify(tree, &self.record, &self.proofs)
+    }
+
+    /// Verify that the record belongs to the given tree, like `try_verify`
+    pub fn try_verify(&self, tree: &LogTree<LogSize>) -> Result<bool, VerifyError> {
+        try_verify(tree, &self.record, &self.proofs)
+    }
+}
+
+/// Get the proof that the record with the given ID belongs to the tree of the given size
+pub fn prove_inclusion<'a, T, TL>(
+    log: &TL,
+    id: TL::LogSize,
+    size: TL::LogSize,
+) -> anyhow::Result<InclusionProof<TL::LogSize>>
+where
+    T: Serialize + Deserialize<'a>,
+    TL: TransparentLog<'a, T>,
+    TL::LogSize: Display + Debug + Send + Sync,
+{
+    if id >= size || size > log.size()? {
+        return Err(LogError::InvalidIndex(0, id).into());
+    }
+    Ok(InclusionProof {
+        record: Record {
+            id,
+            hash: log.get_hash(0, id)?.into_owned(),
+            meta: log.get_meta(id)?,
+        },
+        proofs: log.proofs(proof_positions_iter(id, size))?,
+    })
+}
+
+/// Prove the first record with the given leaf hash is in the tree of the given size, for verifiers knowing the hash
+/// of what they hold but not its index, found with `index_of`; `prove_all_by_hash` proves all the duplicates
+/// Returns none if no record of the tree has that leaf hash
+pub fn prove_by_hash<'a, T, TL>(
+    log: &TL,
+    leaf_hash: &str,
+    size: TL::LogSize,
+) -> anyhow::Result<Option<InclusionProof<TL::LogSize>>>
+where
+    T: Serialize + Deserialize<'a>,
+    TL: TransparentLog<'a, T>,
+    TL::LogSize: Display + Debug + Send + Sync,
+{
+    if size > log.size()? {
+        return Err(HistoryError::BeyondLog.into());
+    }
+    match log.index_of(leaf_hash)? {
+        Some(id) if id < size => Ok(Some(prove_inclusion(log, id, size)?)),
+        _ => Ok(None),
+    }
+}
+
+/// Prove all the records with the given leaf hash are in the tree of the given size, with `indices_of`
+/// Returns no proof if no record of the tree has that leaf hash
+pub fn prove_all_by_hash<'a, T, TL>(
+    log: &TL,
+    leaf_hash: &str,
+    size: TL::LogSize,
+) -> anyhow::Result<Vec<InclusionProof<TL::LogSize>>>
+where
+    T: Serialize + Deserialize<'a>,
+    TL: TransparentLog<'a, T>,
+    TL::LogSize: Display + Debug + Send + Sync,
+{
+    if size > log.size()? {
+        return Err(HistoryError::BeyondLog.into());
+    }
+    log.indices_of(leaf_hash)?
+        .into_iter()
+        .take_while(|id| *id < size)
+        .map(|id| prove_inclusion(log, id, size))
+        .collect()
+}
+
+/// The proofs that a range of records belongs to a tree, without the hashes of the records themselves
+#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
+#[serde(bound(
+    serialize = "LogSize: Serialize + Ord + Clone",
+    deserialize = "LogSize: Deserialize<'de> + Eq + Hash"
+))]
+pub struct RangeProof<LogSize: Eq + Hash> {
+    pub start: LogSize,
+    pub end: LogSize,
+    #[serde(with = "crate::json::proof_hashes")]
+    pub proofs: HashMap<LogTreePosition<LogSize>, String>,
+}
+
+impl<LogSize: Integer + Copy + Hash> RangeProof<LogSize> {
+    /// Verify that the records with the given leaf hashes are the range of the given tree
+    pub fn verify(&self, tree: &LogTree<LogSize>, hashes: &[String]) -> bool {
+        matches!(self.try_verify(tree, hashes), Ok(true))
+    }
+
+    /// Verify that the records with the given leaf hashes are the range of the given tree, like `try_verify_range`
+    /// Returns false if there are not as many hashes as records in the range
+    pub fn try_verify(
+        &self,
+        tree: &LogTree<LogSize>,
+        hashes: &[String],
+    ) -> Result<bool, VerifyError> {
+        let end = hashes
+            .iter()
+            .fold(self.start, |end, _| end + LogSize::one());
+        Ok(end == self.end && try_verify_range(tree, self.start, hashes, &self.proofs)?)
+    }
+}
+
+/// Get the proof that the records from start (inclusive) to end (exclusive) belong to the tree of the given size
+/// The proof size grows with the size of the tree, not with the length of the range
+pub fn range_proof<'a, T, TL>(
+    log: &TL,
+    start: TL::LogSize,
+    end: TL::LogSize,
+    size: TL::LogSize,
+) -> anyhow::Result<RangeProof<TL::LogSize>>
+where
+    T: Serialize + Deserialize<'a>,
+    TL: TransparentLog<'a, T>,
+    TL::LogSize: Display + Debug + Send + Sync,
+{
+    if start >= end || end > size || size > log.size()? {
+        return Err(LogError::InvalidIndex(0, end).into());
+    }
+    Ok(RangeProof {
+        start,
+        end,
+        proofs: log.proofs(range_proof_positions(start, end, size).into_iter())?,
+    })
+}
+
+/// The proofs that the tree of a size is a prefix of the tree of a larger size
+#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
+#[serde(bound(
+    serialize = "LogSize: Serialize + Ord + Clone",
+    deserialize = "LogSize: Deserialize<'de> + Eq + Hash"
+))]
+pub struct ConsistencyProof<LogSize: Eq + Hash> {
+    pub size_a: LogSize,
+    pub size_b: LogSize,
+    #[serde(with = "crate::json::proof_hashes")]
+    pub proofs: HashMap<LogTreePosition<LogSize>, String>,
+}
+
+impl<LogSize: Integer + Copy + Hash> ConsistencyProof<LogSize> {
+    /// Verify that the first tree is a prefix of the second one, both of the sizes of the proof
+    pub fn verify(&self, tree_a: &LogTree<LogSize>, tree_b: &LogTree<LogSize>) -> bool {
+        matches!(self.try_verify(tree_a, tree_b), Ok(true))
+    }
+
+    /// Verify that the first tree is a prefix of the second one, like `try_verify_consistency_between`
+    pub fn try_verify(
+        &self,
+        tree_a: &LogTree<LogSize>,
+        tree_b: &LogTree<LogSize>,
+    ) -> Result<bool, VerifyError> {
+        Ok(tree_a.size == self.size_a
+            && tree_b.size == self.size_b
+            && try_verify_consistency_between(tree_a, tree_b, &self.proofs)?)
+    }
+}
+
+/// Verify that the first tree is a prefix of the second one, using the proofs provided
+/// An empty tree is a prefix of any tree, and a tree of the same size must be the same tree
+pub fn verify_consistency_between<LogSize: Integer + Copy + Hash>(
+    tree_a: &LogTree<LogSize>,
+    tree_b: &LogTree<LogSize>,
+    proofs: &HashMap<LogTreePosition<LogSize>, String>,
+) -> bool {
+    matches!(
+        try_verify_consistency_between(tree_a, tree_b, proofs),
+        Ok(true)
+    )
+}
+
+/// Verify that the first tree is a prefix of the second one, using the proofs provided, like `try_verify_tree`
+/// Returns false if the first tree is larger than the second one
+pub fn try_verify_consistency_between<LogSize: Integer + Copy + Hash>(
+    tree_a: &LogTree<LogSize>,
+    tree_b: &LogTree<LogSize>,
+    proofs: &HashMap<LogTreePosition<LogSize>, String>,
+) -> Result<bool, VerifyError> {
+    if tree_a.size > tree_b.size {
+        return Ok(false);
+    }
+    if tree_a.size.is_zero() {
+        return Ok(tree_a.hash.is_empty());
+    }
+    if tree_a.size == tree_b.size {
+        return Ok(hash_eq(&tree_a.hash, &tree_b.hash));
+    }
+    Ok(try_verify_tree(tree_a, proofs)? && try_verify_tree(tree_b, proofs)?)
+}
+
+/// Verify that the given leaf hashes are the records of the given tree from the start index, using the proofs provided
+/// Only the proofs at the positions needed for the range are used, so other hashes cannot stand for its records
+pub fn verify_range<LogSize: Integer + Copy + Hash>(
+    tree: &LogTree<LogSize>,
+    start: LogSize,
+    hashes: &[String],
+    proofs: &HashMap<LogTreePosition<LogSize>, String>,
+) -> bool {
+    matches!(try_verify_range(tree, start, hashes, proofs), Ok(true))
+}
+
+/// Verify that the given leaf hashes are the records of the given tree from the start index, like `try_verify`
+/// Fails with a `VerifyError::OutOfTree` error for an empty range or a range going past the end of the tree
+pub fn try_verify_range<LogSize: Integer + Copy + Hash>(
+    tree: &LogTree<LogSize>,
+    start: LogSize,
+    hashes: &[String],
+    proofs: &HashMap<LogTreePosition<LogSize>, String>,
+) -> Result<bool, VerifyError> {
+    let end = hashes.iter().fold(start, |end, _| end + LogSize::one());
+    if start >= end || end > tree.size {
+        return Err(VerifyError::OutOfTree);
+    }
+    let mut proofs2 = needed_proofs(range_proof_positions(start, end, tree.size), proofs)?;
+    let mut index = start;
+    for h in hashes {
+        proofs2.insert(LogTreePosition::from((0, index)), h.clone());
+        index = index + LogSize::one();
+    }
+    Ok(hash_eq(
+        &tree.hash,
+        &calc_root(&proofs2, &tree_sizes(tree.size))?,
+    ))
 }
 
 /// Verify that the tree is correct with the proofs provided
@@ -354,64 +1669,62 @@ pub fn verify_tree<LogSize: Integer + Copy + Hash>(
     tree: &LogTree<LogSize>,
     proofs: &HashMap<LogTreePosition<LogSize>, String>,
 ) -> bool {
+    matches!(try_verify_tree(tree, proofs), Ok(true))
+}
+
+/// Verify that the tree is correct with the proofs provided, telling why it cannot be checked
+/// Returns false if the proofs lead to another root or the tree is empty,
+/// and fails with a `VerifyError::MissingProof` error if the proofs do not cover the tree
+pub fn try_verify_tree<LogSize: Integer + Copy + Hash>(
+    tree: &LogTree<LogSize>,
+    proofs: &HashMap<LogTreePosition<LogSize>, String>,
+) -> Result<bool, VerifyError> {
     let sizes = tree_sizes(tree.size);
     if sizes.is_empty() {
-        return false;
+        return Ok(false);
     }
-    tree.hash
-        == calc_hash(
-            LogTreePosition {
-                level: sizes.len() - 1,
-                index: LogSize::zero(),
-            },
-            proofs,
-            &sizes,
-        )
+    Ok(hash_eq(&tree.hash, &calc_root(proofs, &sizes)?))
+}
+
+/// Keep the proofs at the given positions, failing if one is missing or empty
+fn needed_proofs<LogSize: Integer + Copy + Hash>(
+    positions: impl IntoIterator<Item = LogTreePosition<LogSize>>,
+    proofs: &HashMap<LogTreePosition<LogSize>, String>,
+) -> Result<HashMap<LogTreePosition<LogSize>, String>, VerifyError> {
+    positions
+        .into_iter()
+        .map(|p| match proofs.get(&p) {
+            Some(h) if !h.is_empty() => Ok((p, h.clone())),
+            _ => Err(VerifyError::MissingProof(p.level)),
+        })
+        .collect()
+}
+
+/// Calculate the root hash of a tree with the given sizes from the proofs
+fn calc_root<LogSize: Integer + Copy + Hash>(
+    proofs: &HashMap<LogTreePosition<LogSize>, String>,
+    sizes: &[LogSize],
+) -> Result<String, VerifyError> {
+    transparentlog_verify::calc_root(sizes, &|p| proofs.get(p).map(String::as_str))
 }
 
-/// Calculate the hash of a given level or index, recursively going down the tree
-fn calc_hash<LogSize: Integer + Copy + Hash>(
+/// Calculate the hash of a given level or index from the proofs, like `calc_root`
+#[cfg(feature = "checkpoint")]
+pub(crate) fn calc_hash<LogSize: Integer + Copy + Hash>(
     position: LogTreePosition<LogSize>,
     proofs: &HashMap<LogTreePosition<LogSize>, String>,
     sizes: &[LogSize],
-) -> String {
-    if position.index < sizes[position.level] {
-        if let Some(h) = proofs.get(&position) {
-            return h.clone();
-        }
-    }
-    if position.level > 0 {
-        let two = LogSize::one().add(LogSize::one());
-        let new_index = position.index * two;
-        let h1 = calc_hash(
-            LogTreePosition {
-                level: position.level - 1,
-                index: new_index,
-            },
-            proofs,
-            sizes,
-        );
-        let h2 = calc_hash(
-            LogTreePosition {
-                level: position.level - 1,
-                index: new_index + LogSize::one(),
-            },
-            proofs,
-            sizes,
-        );
-        if h2.is_empty() {
-            return h1;
-        }
-        let mut hasher = Sha256::new();
-        hasher.input_str(&format!("{}{}", h1, h2));
-        return hasher.result_str();
-    }
-    String::new()
+) -> Result<String, VerifyError> {
+    transparentlog_verify::calc_hash(position, &|p| proofs.get(p).map(String::as_str), sizes)
 }
 
 #[cfg(test)]
 pub mod tests {
-    use crate::{prefix_proof_positions, proof_positions, tree_sizes};
+    use crate::{
+        decode_digest, hash_eq, prefix_proof_positions, proof_positions, proof_positions_iter,
+        range_proof_positions, tree_sizes, CheckDepth, HashIndex, PendingHashes,
+    };
+    use std::collections::HashSet;
 
     #[test]
     fn test_tree_sizes() {
@@ -453,6 +1766,13 @@ pub mod tests {
         assert!(v.contains(&(3, 0).into()));
         assert!(v.contains(&(2, 2).into()));
 
+        let v = proof_positions(0, 7);
+        assert_eq!(4, v.len());
+        assert!(v.contains(&(0, 1).into()));
+        assert!(v.contains(&(1, 1).into()));
+        assert!(v.contains(&(1, 2).into()));
+        assert!(v.contains(&(0, 6).into()));
+
         let v = proof_positions(9, 16);
         assert_eq!(4, v.len());
         assert!(v.contains(&(3, 0).into()));
@@ -461,6 +1781,42 @@ pub mod tests {
         assert!(v.contains(&(0, 8).into()));
     }
 
+    #[test]
+    fn test_range_proof_positions() {
+        assert!(range_proof_positions(3_u64, 3, 8).is_empty());
+        assert!(range_proof_positions(3_u64, 9, 8).is_empty());
+        assert!(range_proof_positions(0_u64, 13, 13).is_empty());
+        let v = range_proof_positions(2_u64, 6, 8);
+        assert_eq!(2, v.len());
+        assert!(v.contains(&(1, 0).into()));
+        assert!(v.contains(&(1, 3).into()));
+        let v = range_proof_positions(9_u64, 13, 13);
+        assert_eq!(2, v.len());
+        assert!(v.contains(&(3, 0).into()));
+        assert!(v.contains(&(0, 8).into()));
+        assert_eq!(proof_positions(9_u64, 13), range_proof_positions(9, 10, 13));
+    }
+
+    #[test]
+    fn test_proof_positions_iter() {
+        assert_eq!(0, proof_positions_iter(0_u64, 0).count());
+        assert_eq!(
+            vec![(0, 8), (1, 5), (0, 12), (3, 0)],
+            proof_positions_iter(9_u64, 13)
+                .map(|p| (p.level, p.index))
+                .collect::<Vec<_>>()
+        );
+        for size in 1..200_usize {
+            let sizes = tree_sizes(size);
+            for index in 0..size {
+                let v: Vec<_> = proof_positions_iter(index, size).collect();
+                let set: HashSet<_> = v.iter().cloned().collect();
+                assert_eq!(v.len(), set.len());
+                assert!(v.iter().all(|p| p.index < sizes[p.level]));
+            }
+        }
+    }
+
     #[test]
     fn test_prefix_proof_positions() {
         let v = prefix_proof_positions(7_u64, 13);
@@ -482,4 +1838,77 @@ pub mod tests {
         assert!(v.contains(&(0, 7).into()));
         assert!(v.contains(&(3, 1).into()));
     }
+
+    #[test]
+    fn test_pending_hashes() {
+        let mut pending = PendingHashes::default();
+        assert_eq!(None, pending.take(0, 0_u64));
+        pending.set(0, 4, String::from("h4"));
+        pending.set(2, 0, String::from("h0"));
+        assert_eq!(None, pending.take(1, 2));
+        // a hash at another index is not the pending one
+        assert_eq!(None, pending.take(0, 8));
+        assert_eq!(Some(String::from("h4")), pending.take(0, 4));
+        assert_eq!(None, pending.take(0, 4));
+        pending.clear();
+        assert_eq!(None, pending.take(2, 0));
+    }
+
+    #[test]
+    fn test_hash_eq() {
+        let h = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+        assert_eq!(Some(0xe3), decode_digest(h).map(|d| d[0]));
+        assert_eq!(None, decode_digest(&h[2..]));
+        assert_eq!(None, decode_digest("not a digest"));
+        assert!(hash_eq(h, h));
+        assert!(hash_eq(h, &h.to_uppercase()));
+        assert!(!hash_eq(h, &format!("{}00", &h[..62])));
+        assert!(!hash_eq(h, &h[2..]));
+        assert!(!hash_eq(h, ""));
+        assert!(hash_eq("", ""));
+        assert!(hash_eq("h4", "h4"));
+        assert!(!hash_eq("h4", "H4"));
+    }
+
+    #[test]
+    fn test_check_depth() {
+        assert_eq!(
+            vec![0, 1, 2, 3, 4, 5],
+            CheckDepth::Full.indices(6_u64).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec![0, 3, 5],
+            CheckDepth::Sampled(2).indices(6_u64).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec![0, 2, 4, 6],
+            CheckDepth::Sampled(3).indices(7_u64).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec![0, 1, 2],
+            CheckDepth::Sampled(10).indices(3_u64).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec![0, 2],
+            CheckDepth::Sampled(0).indices(3_u64).collect::<Vec<_>>()
+        );
+        assert!(CheckDepth::Sampled(2).indices(0_u64).next().is_none());
+    }
+
+    #[test]
+    fn test_hash_index() {
+        let mut index = HashIndex::default();
+        assert_eq!(None, index.first("h1"));
+        index.insert(String::from("h0"), 0_u64);
+        index.insert(String::from("h1"), 1);
+        index.insert(String::from("h1"), 3);
+        assert_eq!(Some(1), index.first("h1"));
+        assert_eq!(&[1, 3], index.all("h1"));
+        assert_eq!(&[0], index.all("h0"));
+        assert!(index.all("h2").is_empty());
+        assert_eq!(
+            vec![("h1", &[1_u64, 3][..])],
+            index.duplicates().collect::<Vec<_>>()
+        );
+    }
 }
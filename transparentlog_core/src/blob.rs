@@ -0,0 +1,498 @@
+//! # Blob storage
+//!
+//! A log storing its records in a content-addressable blob store, keyed by their hash,
+//! while the inner log only stores a reference to each blob, with the hashes and metadata.
+//! Large records stay out of the inner log, identical records are only stored once,
+//! and proofs and lookups never touch the blob store.
+use crate::base::*;
+use crate::tile::Tile;
+use maybe_owned::MaybeOwned;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{create_dir_all, read, rename, write};
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+use transparentlog_verify::sha256_hex;
+
+/// Storage for serialized records, by their hash
+pub trait BlobStore {
+    /// Store a blob under its hash
+    fn put(&mut self, hash: &str, data: Vec<u8>) -> anyhow::Result<()>;
+
+    /// Get a blob, if stored
+    fn get(&self, hash: &str) -> anyhow::Result<Option<Vec<u8>>>;
+
+    /// Whether a blob is stored
+    fn contains(&self, hash: &str) -> anyhow::Result<bool> {
+        Ok(self.get(hash)?.is_some())
+    }
+}
+
+/// A blob store in memory
+#[derive(Debug, Default)]
+pub struct MemoryBlobStore {
+    blobs: HashMap<String, Vec<u8>>,
+}
+
+impl MemoryBlobStore {
+    /// The number of blobs stored
+    pub fn len(&self) -> usize {
+        self.blobs.len()
+    }
+
+    /// Whether no blob is stored
+    pub fn is_empty(&self) -> bool {
+        self.blobs.is_empty()
+    }
+}
+
+impl BlobStore for MemoryBlobStore {
+    fn put(&mut self, hash: &str, data: Vec<u8>) -> anyhow::Result<()> {
+        self.blobs.insert(hash.to_owned(), data);
+        Ok(())
+    }
+
+    fn get(&self, hash: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        Ok(self.blobs.get(hash).cloned())
+    }
+
+    fn contains(&self, hash: &str) -> anyhow::Result<bool> {
+        Ok(self.blobs.contains_key(hash))
+    }
+}
+
+/// A blob store keeping each blob in its own file, under a directory named by the first two characters of its hash
+#[derive(Debug)]
+pub struct DirBlobStore {
+    dir: PathBuf,
+}
+
+impl DirBlobStore {
+    /// Open a store in the given directory, creating it if needed
+    pub fn open<P: AsRef<Path>>(dir: P) -> anyhow::Result<Self> {
+        create_dir_all(&dir)?;
+        Ok(Self {
+            dir: dir.as_ref().to_path_buf(),
+        })
+    }
+
+    /// The path of the file of a blob
+    fn path(&self, hash: &str) -> anyhow::Result<PathBuf> {
+        if hash.len() < 3 || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(BlobError::InvalidHash(hash.to_owned()).into());
+        }
+        Ok(self.dir.join(&hash[..2]).join(hash))
+    }
+}
+
+impl BlobStore for DirBlobStore {
+    /// Writes a temporary file first, so a blob file is always complete
+    fn put(&mut self, hash: &str, data: Vec<u8>) -> anyhow::Result<()> {
+        let path = self.path(hash)?;
+        create_dir_all(self.dir.join(&hash[..2]))?;
+        let tmp = path.with_extension("tmp");
+        write(&tmp, data)?;
+        rename(tmp, path)?;
+        Ok(())
+    }
+
+    fn get(&self, hash: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        let p = self.path(hash)?;
+        if p.exists() {
+            Ok(Some(read(p)?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn contains(&self, hash: &str) -> anyhow::Result<bool> {
+        Ok(self.path(hash)?.exists())
+    }
+}
+
+/// The reference to a blob stored by the inner log of a blob log
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct BlobRef {
+    /// The hash of the serialized record, which is also its leaf hash
+    pub hash: String,
+    /// The size of the serialized record, in bytes
+    pub size: u64,
+}
+
+/// Specific Errors for blob logs
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum BlobError {
+    #[error("Blob {0} is missing from the blob store")]
+    Missing(String),
+    #[error("Blob {0} does not match its hash")]
+    Corrupted(String),
+    #[error("Invalid blob hash: {0}")]
+    InvalidHash(String),
+}
+
+/// A log with its records in a blob store, keyed by their hash
+/// Blobs are written before their reference, so the inner log never refers to a missing blob,
+/// and each blob read is checked against its hash
+#[derive(Debug)]
+pub struct BlobLog<T, L, B> {
+    log: L,
+    blobs: B,
+    _marker: PhantomData<T>,
+}
+
+impl<'a, T, L, B> BlobLog<T, L, B>
+where
+    L: TransparentLog<'a, BlobRef>,
+    B: BlobStore,
+{
+    /// Create a blob log from the inner log of references and the blob store
+    pub fn new(log: L, blobs: B) -> Self {
+        Self {
+            log,
+            blobs,
+            _marker: PhantomData,
+        }
+    }
+
+    /// The reference to the blob of the record with the given ID, without reading the blob
+    pub fn blob_ref(&self, id: L::LogSize) -> anyhow::Result<Option<BlobRef>> {
+        Ok(self.log.get(id)?.map(MaybeOwned::into_owned))
+    }
+
+    /// The blob store
+    pub fn blobs(&self) -> &B {
+        &self.blobs
+    }
+
+    /// Get back the inner log and the blob store
+    pub fn into_inner(self) -> (L, B) {
+        (self.log, self.blobs)
+    }
+
+    /// Read and check the blob of a reference
+    fn read_blob(&self, blob: &BlobRef) -> anyhow::Result<T>
+    where
+        T: DeserializeOwned,
+    {
+        Ok(rmp_serde::from_slice(&self.read_blob_bytes(blob)?)?)
+    }
+
+    /// Read the bytes of the blob of a reference, checked against its hash
+    fn read_blob_bytes(&self, blob: &BlobRef) -> anyhow::Result<Vec<u8>> {
+        let data = self
+            .blobs
+            .get(&blob.hash)?
+            .ok_or_else(|| BlobError::Missing(blob.hash.clone()))?;
+        if sha256_hex(&data) != blob.hash {
+            return Err(BlobError::Corrupted(blob.hash.clone()).into());
+        }
+        Ok(data)
+    }
+}
+
+impl<'a, T, L, B> TransparentLog<'a, T> for BlobLog<T, L, B>
+where
+    T: Serialize + DeserializeOwned,
+    L: TransparentLog<'a, BlobRef>,
+    B: BlobStore,
+{
+    type LogSize = L::LogSize;
+
+    fn size(&self) -> anyhow::Result<Self::LogSize> {
+        self.log.size()
+    }
+
+    fn origin(&self) -> Option<String> {
+        self.log.origin()
+    }
+
+    /// Records are serialized as MessagePack in the blob store, so their leaf hash is the hash of their blob
+    fn leaf_hasher(&self) -> LeafHasher<T> {
+        hash
+    }
+
+    fn get(&self, index: Self::LogSize) -> anyhow::Result<Option<MaybeOwned<'_, T>>> {
+        self.log
+            .get(index)?
+            .map(|blob| Ok(MaybeOwned::Owned(self.read_blob(&blob)?)))
+            .transpose()
+    }
+
+    /// The bytes of the blob, checked against its hash
+    fn get_raw_bytes(&self, index: Self::LogSize) -> anyhow::Result<Option<Vec<u8>>> {
+        self.log
+            .get(index)?
+            .map(|blob| self.read_blob_bytes(&blob))
+            .transpose()
+    }
+
+    fn get_range(
+        &self,
+        start: Self::LogSize,
+        end: Self::LogSize,
+    ) -> anyhow::Result<Vec<MaybeOwned<'_, T>>> {
+        self.log
+            .get_range(start, end)?
+            .iter()
+            .map(|blob| Ok(MaybeOwned::Owned(self.read_blob(blob)?)))
+            .collect()
+    }
+
+    fn iter(&self) -> LogIterator<'_, Self::LogSize, T> {
+        Box::new(self.log.iter().map(move |r| {
+            let (id, blob) = r?;
+            Ok((id, MaybeOwned::Owned(self.read_blob(&blob)?)))
+        }))
+    }
+
+    /// Stores the blob unless an identical record already did, and the log is not sealed
+    fn add(&mut self, record: T) -> anyhow::Result<Self::LogSize> {
+        check_unsealed(&self.log.sealed()?)?;
+        let data = rmp_serde::to_vec(&record)?;
+        let hash = sha256_hex(&data);
+        let size = data.len() as u64;
+        if !self.blobs.contains(&hash)? {
+            self.blobs.put(&hash, data)?;
+        }
+        self.log.add(BlobRef { hash, size })
+    }
+
+    fn add_tombstone(&mut self) -> anyhow::Result<Self::LogSize> {
+        self.log.add_tombstone()
+    }
+
+    /// Only redacts the reference, since other records may share the blob
+    fn redact(&mut self, id: Self::LogSize) -> anyhow::Result<()> {
+        self.log.redact(id)
+    }
+
+    fn expire(&mut self, id: Self::LogSize) -> anyhow::Result<()> {
+        self.log.expire(id)
+    }
+
+    fn expired(&self, id: Self::LogSize) -> anyhow::Result<bool> {
+        self.log.expired(id)
+    }
+
+    fn seal(&mut self) -> anyhow::Result<LogTree<Self::LogSize>> {
+        self.log.seal()
+    }
+
+    fn sealed(&self) -> anyhow::Result<Option<LogTree<Self::LogSize>>> {
+        self.log.sealed()
+    }
+
+    /// Only syncs the inner log, not the blob store
+    fn sync(&self) -> anyhow::Result<()> {
+        self.log.sync()
+    }
+
+    fn add_meta(&mut self, id: Self::LogSize, meta: RecordMeta) -> anyhow::Result<()> {
+        self.log.add_meta(id, meta)
+    }
+
+    fn get_meta(&self, id: Self::LogSize) -> anyhow::Result<Option<RecordMeta>> {
+        self.log.get_meta(id)
+    }
+
+    fn set_annotation(&mut self, id: Self::LogSize, annotation: Vec<u8>) -> anyhow::Result<()> {
+        self.log.set_annotation(id, annotation)
+    }
+
+    fn get_annotation(&self, id: Self::LogSize) -> anyhow::Result<Option<Vec<u8>>> {
+        self.log.get_annotation(id)
+    }
+
+    fn find_since(&self, timestamp: u64) -> anyhow::Result<Self::LogSize> {
+        self.log.find_since(timestamp)
+    }
+
+    fn add_hash(&mut self, level: LogHeight, hash: String) -> anyhow::Result<Self::LogSize> {
+        self.log.add_hash(level, hash)
+    }
+
+    fn get_hash(
+        &self,
+        level: LogHeight,
+        index: Self::LogSize,
+    ) -> anyhow::Result<MaybeOwned<'_, String>> {
+        self.log.get_hash(level, index)
+    }
+
+    fn index_of(&self, hash: &str) -> anyhow::Result<Option<Self::LogSize>> {
+        self.log.index_of(hash)
+    }
+
+    fn indices_of(&self, hash: &str) -> anyhow::Result<Vec<Self::LogSize>> {
+        self.log.indices_of(hash)
+    }
+
+    fn tile(&self, level: LogHeight, index: Self::LogSize) -> anyhow::Result<Tile<Self::LogSize>> {
+        self.log.tile(level, index)
+    }
+
+    fn latest(&self) -> anyhow::Result<LogTree<Self::LogSize>> {
+        self.log.latest()
+    }
+
+    fn ephemeral_hash(&self, level: LogHeight, index: Self::LogSize) -> anyhow::Result<String> {
+        self.log.ephemeral_hash(level, index)
+    }
+
+    fn root_at(&self, size: Self::LogSize) -> anyhow::Result<String> {
+        self.log.root_at(size)
+    }
+
+    fn head_at(&self, size: Self::LogSize) -> anyhow::Result<LogTree<Self::LogSize>> {
+        self.log.head_at(size)
+    }
+
+    fn proofs<I>(
+        &self,
+        positions: I,
+    ) -> anyhow::Result<HashMap<LogTreePosition<Self::LogSize>, String>>
+    where
+        I: Iterator<Item = LogTreePosition<Self::LogSize>>,
+    {
+        self.log.proofs(positions)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::test_helpers::*;
+    use crate::*;
+    use std::fs::{create_dir, remove_dir_all};
+    use std::path::Path;
+
+    use serial_test::serial;
+
+    fn memory_blob_log() -> BlobLog<LogRecord, InMemoryLog<BlobRef>, MemoryBlobStore> {
+        BlobLog::new(InMemoryLog::default(), MemoryBlobStore::default())
+    }
+
+    #[test]
+    fn blob_memory_13() -> anyhow::Result<()> {
+        let mut ml = memory_blob_log();
+        test_13(&mut ml)?;
+        let blob = ml.blob_ref(9)?.unwrap();
+        assert_eq!(hash(&LogRecord::new("rec9"))?, blob.hash);
+        assert_eq!(
+            rmp_serde::to_vec(&LogRecord::new("rec9"))?.len() as u64,
+            blob.size
+        );
+        assert_eq!(13, ml.blobs().len());
+        Ok(())
+    }
+
+    #[test]
+    fn blob_memory_raw_bytes() -> anyhow::Result<()> {
+        let mut ml = memory_blob_log();
+        let mut client = InMemoryLogClientBuilder::new(&ml)?.build();
+        raw_bytes(&mut ml, &mut client)
+    }
+
+    #[test]
+    fn blob_memory_duplicates() -> anyhow::Result<()> {
+        duplicates(&mut memory_blob_log())
+    }
+
+    #[test]
+    fn blob_memory_range() -> anyhow::Result<()> {
+        range(&mut memory_blob_log())
+    }
+
+    #[test]
+    fn blob_memory_iter() -> anyhow::Result<()> {
+        iter(&mut memory_blob_log())
+    }
+
+    #[test]
+    fn blob_memory_bulk() -> anyhow::Result<()> {
+        bulk(&mut memory_blob_log())
+    }
+
+    #[test]
+    fn blob_memory_properties() -> anyhow::Result<()> {
+        properties(|| Ok(memory_blob_log()))
+    }
+
+    #[test]
+    fn blob_memory_hash_only() -> anyhow::Result<()> {
+        hash_only(&mut memory_blob_log())
+    }
+
+    #[test]
+    fn blob_dedup() -> anyhow::Result<()> {
+        let mut ml = memory_blob_log();
+        let r1 = ml.append(LogRecord::new("same"))?;
+        let r2 = ml.append(LogRecord::new("same"))?;
+        assert_eq!(r1.hash, r2.hash);
+        assert_eq!(1, ml.blobs().len());
+        ml.redact(r1.id)?;
+        assert_eq!(Some(&LogRecord::new("same")), ml.get(r2.id)?.as_deref());
+        Ok(())
+    }
+
+    #[test]
+    fn blob_missing_corrupted() -> anyhow::Result<()> {
+        let mut ml = memory_blob_log();
+        append_multiple(&mut ml, 2)?;
+        let (log, _) = ml.into_inner();
+        let mut blobs = MemoryBlobStore::default();
+        let h0 = hash(&LogRecord::new("rec0"))?;
+        blobs.put(&h0, rmp_serde::to_vec(&LogRecord::new("forged"))?)?;
+        let ml: BlobLog<LogRecord, _, _> = BlobLog::new(log, blobs);
+        let err = ml.get(0).err().unwrap();
+        assert_eq!(
+            Some(&BlobError::Corrupted(h0)),
+            err.downcast_ref::<BlobError>()
+        );
+        let err = ml.get(1).err().unwrap();
+        assert_eq!(
+            Some(&BlobError::Missing(hash(&LogRecord::new("rec1"))?)),
+            err.downcast_ref::<BlobError>()
+        );
+        // proofs do not need the blobs
+        assert!(check_record(
+            &mut InMemoryLogClientBuilder::new(&ml)?.build(),
+            &ml,
+            &Record {
+                id: 1,
+                hash: hash(&LogRecord::new("rec1"))?,
+                meta: None,
+            },
+        )?);
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    fn blob_file() -> anyhow::Result<()> {
+        let path = Path::new("./test_data");
+        if path.exists() {
+            remove_dir_all(path)?;
+        }
+        create_dir(path)?;
+        let refs = path.join("refs");
+        create_dir(&refs)?;
+        let mut ml = BlobLog::new(
+            FileLog::<BlobRef>::open(&refs)?,
+            DirBlobStore::open(path.join("blobs"))?,
+        );
+        test_13(&mut ml)?;
+        let h = hash(&LogRecord::new("rec9"))?;
+        assert!(path.join("blobs").join(&h[..2]).join(&h).exists());
+        let (log, blobs) = ml.into_inner();
+        drop(log);
+
+        // reopen
+        let ml = BlobLog::new(FileLog::<BlobRef>::open(&refs)?, blobs);
+        check_13(&ml)?;
+        assert!(ml.blobs().get("../x").is_err());
+        Ok(())
+    }
+}
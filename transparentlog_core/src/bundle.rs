@@ -0,0 +1,272 @@
+//! Proof bundles: a record reference, its inclusion proof and the tree head, sealed in one blob for offline verifiers
+use crate::base::*;
+use crate::checkpoint::*;
+use num::Integer;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::fmt::{Debug, Display};
+use std::hash::Hash;
+use std::str::FromStr;
+use thiserror::Error;
+
+/// The version of the bundle format, the first byte of a sealed bundle
+pub const BUNDLE_VERSION: u8 = 1;
+
+/// Specific Errors for bundles
+#[derive(Debug, Clone, Error)]
+pub enum BundleError {
+    #[error("Unsupported bundle version: {0}")]
+    UnsupportedVersion(u8),
+    #[error("Empty bundle")]
+    Empty,
+    #[error("Bundle has no signed checkpoint")]
+    Unsigned,
+    #[error("Bundle tree does not match its signed checkpoint")]
+    TreeMismatch,
+    #[error("Bundle inclusion proof is invalid")]
+    InvalidProof,
+}
+
+/// A record inclusion proof with the tree it was computed for, and optionally the signed checkpoint of that tree
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Bundle<LogSize: Eq + Hash> {
+    pub proof: InclusionProof<LogSize>,
+    pub tree: LogTree<LogSize>,
+    pub checkpoint: Option<String>,
+}
+
+/// The serialized form of a bundle
+#[derive(Serialize, Deserialize)]
+struct SealedBundle<LogSize> {
+    id: LogSize,
+    hash: String,
+    meta: Option<RecordMeta>,
+    proofs: Vec<(LogHeight, LogSize, String)>,
+    size: LogSize,
+    root: String,
+    checkpoint: Option<String>,
+}
+
+impl<LogSize> Bundle<LogSize>
+where
+    LogSize: Integer + Copy + Hash + Display + FromStr + Serialize + DeserializeOwned,
+{
+    /// Build the bundle of a record in the latest tree of the log, signing its checkpoint for the given origin if signers are given
+    pub fn prove<'a, T, TL>(
+        log: &TL,
+        id: LogSize,
+        origin: &str,
+        signers: &[&dyn LogSigner],
+    ) -> anyhow::Result<Self>
+    where
+        T: Serialize + Deserialize<'a>,
+        TL: TransparentLog<'a, T, LogSize = LogSize>,
+        LogSize: Debug + Send + Sync,
+    {
+        let tree = log.latest()?;
+        let proof = prove_inclusion(log, id, tree.size)?;
+        let checkpoint = if signers.is_empty() {
+            None
+        } else {
+            Some(Checkpoint::new(origin, tree.clone()).sign(signers)?)
+        };
+        Ok(Self {
+            proof,
+            tree,
+            checkpoint,
+        })
+    }
+
+    /// Serialize the bundle: the version byte then the MessagePack encoded bundle
+    pub fn seal(&self) -> anyhow::Result<Vec<u8>> {
+        let mut proofs: Vec<_> = self
+            .proof
+            .proofs
+            .iter()
+            .map(|(p, h)| (p.level, p.index, h.clone()))
+            .collect();
+        proofs.sort();
+        let sealed = SealedBundle {
+            id: self.proof.record.id,
+            hash: self.proof.record.hash.clone(),
+            meta: self.proof.record.meta,
+            proofs,
+            size: self.tree.size,
+            root: self.tree.hash.clone(),
+            checkpoint: self.checkpoint.clone(),
+        };
+        let mut b = vec![BUNDLE_VERSION];
+        rmp_serde::encode::write(&mut b, &sealed)?;
+        Ok(b)
+    }
+
+    /// Deserialize a sealed bundle, without verifying it
+    pub fn unseal(b: &[u8]) -> anyhow::Result<Self> {
+        match b.first() {
+            None => return Err(BundleError::Empty.into()),
+            Some(&BUNDLE_VERSION) => (),
+            Some(v) => return Err(BundleError::UnsupportedVersion(*v).into()),
+        }
+        let sealed: SealedBundle<LogSize> = rmp_serde::from_slice(&b[1..])?;
+        Ok(Self {
+            proof: InclusionProof {
+                record: Record {
+                    id: sealed.id,
+                    hash: sealed.hash,
+                    meta: sealed.meta,
+                },
+                proofs: sealed
+                    .proofs
+                    .into_iter()
+                    .map(|(level, index, h)| (LogTreePosition { level, index }, h))
+                    .collect(),
+            },
+            tree: LogTree {
+                size: sealed.size,
+                hash: sealed.root,
+                origin: None,
+            },
+            checkpoint: sealed.checkpoint,
+        })
+    }
+
+    /// Verify the record is included in the bundle tree
+    /// If verifiers are given, the bundle tree must also be the one of a checkpoint signed by one of them
+    pub fn verify(&self, verifiers: &[&dyn LogVerifier]) -> anyhow::Result<()> {
+        if !verifiers.is_empty() {
+            let note = self.checkpoint.as_ref().ok_or(BundleError::Unsigned)?;
+            let checkpoint = Checkpoint::<LogSize>::open(note, verifiers)?;
+            // a bundle tree without origin stands for the tree of the checkpoint origin
+            let origin = self.tree.origin.as_ref().unwrap_or(&checkpoint.origin);
+            if checkpoint.tree.size != self.tree.size
+                || !hash_eq(&checkpoint.tree.hash, &self.tree.hash)
+                || *origin != checkpoint.origin
+            {
+                return Err(BundleError::TreeMismatch.into());
+            }
+        }
+        if !self.proof.verify(&self.tree) {
+            return Err(BundleError::InvalidProof.into());
+        }
+        Ok(())
+    }
+}
+
+/// Check a record is contained in the given log like `check_record`
+/// Returns the unsigned bundle of the record inclusion proof and the verified tree, if the record is included
+pub fn check_record_with_proof<'a, T, K, TL, LC>(
+    client: &mut LC,
+    log: &TL,
+    record: &Record<TL::LogSize>,
+) -> anyhow::Result<Option<Bundle<TL::LogSize>>>
+where
+    T: Serialize + Deserialize<'a>,
+    TL: ProofSource<'a, T, K>,
+    LC: LogClient<'a, T, TL, K>,
+{
+    Ok(verified_proofs(client, log, record)?.map(|proofs| Bundle {
+        proof: InclusionProof {
+            record: record.clone(),
+            proofs,
+        },
+        tree: client.latest().clone(),
+        checkpoint: None,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::test_helpers::*;
+    use crate::*;
+
+    #[test]
+    fn bundle_seal() -> anyhow::Result<()> {
+        let signer = Ed25519Signer::new("example.com/log", &[5_u8; 32]);
+        let verifier = signer.verifier();
+        let other = Ed25519Signer::new("example.com/log", &[6_u8; 32]).verifier();
+        let mut ml: InMemoryLog<LogRecord> = InMemoryLog::default();
+        append_multiple(&mut ml, 13)?;
+
+        let bundle = Bundle::prove(&ml, 6, "example.com/log", &[&signer])?;
+        let b = bundle.seal()?;
+        assert_eq!(BUNDLE_VERSION, b[0]);
+        let unsealed = Bundle::unseal(&b)?;
+        assert_eq!(bundle, unsealed);
+        unsealed.verify(&[])?;
+        unsealed.verify(&[&verifier])?;
+        assert!(unsealed.verify(&[&other]).is_err());
+        assert_eq!(hash(&LogRecord::new("rec6"))?, unsealed.proof.record.hash);
+
+        let mut tampered = unsealed.clone();
+        tampered.proof.record.id = 5;
+        assert!(matches!(
+            tampered.verify(&[]).unwrap_err().downcast(),
+            Ok(BundleError::InvalidProof)
+        ));
+        tampered.tree = ml.latest()?;
+        tampered.tree.hash = hash_two(0)?;
+        assert!(matches!(
+            tampered.verify(&[&verifier]).unwrap_err().downcast(),
+            Ok(BundleError::TreeMismatch)
+        ));
+
+        let unsigned = Bundle::prove(&ml, 12, "example.com/log", &[])?;
+        unsigned.verify(&[])?;
+        assert!(matches!(
+            unsigned.verify(&[&verifier]).unwrap_err().downcast(),
+            Ok(BundleError::Unsigned)
+        ));
+
+        let mut b = b;
+        b[0] = 2;
+        assert!(matches!(
+            Bundle::<usize>::unseal(&b).unwrap_err().downcast(),
+            Ok(BundleError::UnsupportedVersion(2))
+        ));
+        assert!(Bundle::<usize>::unseal(&[]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn bundle_check_record() -> anyhow::Result<()> {
+        let mut ml: InMemoryLog<LogRecord> = InMemoryLog::default();
+        let mut client = InMemoryLogClientBuilder::new(&ml)?.build();
+        append_multiple(&mut ml, 13)?;
+        let record = Record {
+            id: 9,
+            hash: hash(&LogRecord::new("rec9"))?,
+            meta: None,
+        };
+        let bundle = check_record_with_proof(&mut client, &ml, &record)?.unwrap();
+        assert_eq!(ml.latest()?, bundle.tree);
+        assert_eq!(record, bundle.proof.record);
+        assert_eq!(proof_positions(9, 13).len(), bundle.proof.proofs.len());
+        let unsealed = Bundle::<usize>::unseal(&bundle.seal()?)?;
+        unsealed.verify(&[])?;
+
+        let forged = Record {
+            hash: hash(&LogRecord::new("rec8"))?,
+            ..record
+        };
+        assert_eq!(None, check_record_with_proof(&mut client, &ml, &forged)?);
+        Ok(())
+    }
+
+    #[test]
+    fn bundle_origin() -> anyhow::Result<()> {
+        let signer = Ed25519Signer::new("example.com/log", &[5_u8; 32]);
+        let verifier = signer.verifier();
+        let mut ml: InMemoryLog<LogRecord> = InMemoryLog::with_origin("example.com/log");
+        append_multiple(&mut ml, 13)?;
+
+        let bundle = Bundle::prove(&ml, 6, "example.com/log", &[&signer])?;
+        bundle.verify(&[&verifier])?;
+        Bundle::<usize>::unseal(&bundle.seal()?)?.verify(&[&verifier])?;
+        let other = Bundle::prove(&ml, 6, "example.com/other", &[&signer])?;
+        assert!(matches!(
+            other.verify(&[&verifier]).unwrap_err().downcast(),
+            Ok(BundleError::TreeMismatch)
+        ));
+        Ok(())
+    }
+}
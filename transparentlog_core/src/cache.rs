@@ -0,0 +1,446 @@
+//! # Proof cache
+//!
+//! A log keeping the tree hashes it reads in memory, so the proofs of popular records are served without storage reads.
+//! Every stored hash is the root of a perfect subtree, which never changes once written, so cached hashes never need invalidating.
+//!
+//! The backends also keep the root of their latest tree in a `RootCache`, so `latest` does not fold the partial levels on each call.
+use crate::base::*;
+use maybe_owned::MaybeOwned;
+use num::{Integer, NumCast};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hash;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::RwLock;
+use thiserror::Error;
+
+/// The default number of hashes a cache keeps
+pub const DEFAULT_CACHE_CAPACITY: usize = 100_000;
+
+/// Specific Errors for cached logs
+#[derive(Debug, Clone, Error)]
+pub enum CacheError {
+    #[error("Proof cache lock poisoned")]
+    Poisoned,
+}
+
+/// The cached hashes, with their insertion order to evict the oldest first
+#[derive(Debug)]
+struct HashCache<LogSize> {
+    hashes: HashMap<LogTreePosition<LogSize>, String>,
+    order: VecDeque<LogTreePosition<LogSize>>,
+}
+
+/// A log caching the tree hashes read from the wrapped log, up to a capacity
+#[derive(Debug)]
+pub struct CachedLog<L, S> {
+    log: L,
+    capacity: usize,
+    cache: RwLock<HashCache<S>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl<L, S: Hash + Eq + Clone> CachedLog<L, S> {
+    /// Wrap a log, caching up to `DEFAULT_CACHE_CAPACITY` hashes
+    pub fn new(log: L) -> Self {
+        Self::with_capacity(log, DEFAULT_CACHE_CAPACITY)
+    }
+
+    /// Wrap a log, caching up to the given number of hashes
+    pub fn with_capacity(log: L, capacity: usize) -> Self {
+        Self {
+            log,
+            capacity,
+            cache: RwLock::new(HashCache {
+                hashes: HashMap::new(),
+                order: VecDeque::new(),
+            }),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// The wrapped log
+    pub fn inner(&self) -> &L {
+        &self.log
+    }
+
+    /// Get back the wrapped log
+    pub fn into_inner(self) -> L {
+        self.log
+    }
+
+    /// The number of hashes cached
+    pub fn len(&self) -> usize {
+        self.cache
+            .read()
+            .map(|c| c.hashes.len())
+            .unwrap_or_default()
+    }
+
+    /// Whether no hash is cached
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The number of hashes served from the cache
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    /// The number of hashes read from the wrapped log
+    pub fn misses(&self) -> u64 {
+        self.misses.load(Ordering::Relaxed)
+    }
+
+    /// Get a cached hash
+    fn cached(&self, position: &LogTreePosition<S>) -> Result<Option<String>, CacheError> {
+        let cache = self.cache.read().map_err(|_| CacheError::Poisoned)?;
+        Ok(cache.hashes.get(position).cloned())
+    }
+
+    /// Cache hashes read from the wrapped log, evicting the oldest ones beyond the capacity
+    fn insert<I: IntoIterator<Item = (LogTreePosition<S>, String)>>(
+        &self,
+        hashes: I,
+    ) -> Result<(), CacheError> {
+        if self.capacity == 0 {
+            return Ok(());
+        }
+        let mut cache = self.cache.write().map_err(|_| CacheError::Poisoned)?;
+        for (position, hash) in hashes {
+            if cache.hashes.insert(position.clone(), hash).is_none() {
+                cache.order.push_back(position);
+            }
+            while cache.order.len() > self.capacity {
+                if let Some(oldest) = cache.order.pop_front() {
+                    cache.hashes.remove(&oldest);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Only the tree hashes go through the cache: records and metadata are always read from the wrapped log
+impl<'a, T, L, S> TransparentLog<'a, T> for CachedLog<L, S>
+where
+    T: Serialize + Deserialize<'a>,
+    L: TransparentLog<'a, T, LogSize = S>,
+    S: Integer + Copy + Hash + NumCast + 'static,
+{
+    type LogSize = S;
+
+    fn add(&mut self, record: T) -> anyhow::Result<Self::LogSize> {
+        self.log.add(record)
+    }
+
+    fn add_tombstone(&mut self) -> anyhow::Result<Self::LogSize> {
+        self.log.add_tombstone()
+    }
+
+    fn redact(&mut self, id: Self::LogSize) -> anyhow::Result<()> {
+        self.log.redact(id)
+    }
+
+    fn expire(&mut self, id: Self::LogSize) -> anyhow::Result<()> {
+        self.log.expire(id)
+    }
+
+    fn expired(&self, id: Self::LogSize) -> anyhow::Result<bool> {
+        self.log.expired(id)
+    }
+
+    fn seal(&mut self) -> anyhow::Result<LogTree<Self::LogSize>> {
+        self.log.seal()
+    }
+
+    fn sealed(&self) -> anyhow::Result<Option<LogTree<Self::LogSize>>> {
+        self.log.sealed()
+    }
+
+    fn sync(&self) -> anyhow::Result<()> {
+        self.log.sync()
+    }
+
+    fn add_meta(&mut self, id: Self::LogSize, meta: RecordMeta) -> anyhow::Result<()> {
+        self.log.add_meta(id, meta)
+    }
+
+    fn get_meta(&self, id: Self::LogSize) -> anyhow::Result<Option<RecordMeta>> {
+        self.log.get_meta(id)
+    }
+
+    fn set_annotation(&mut self, id: Self::LogSize, annotation: Vec<u8>) -> anyhow::Result<()> {
+        self.log.set_annotation(id, annotation)
+    }
+
+    fn get_annotation(&self, id: Self::LogSize) -> anyhow::Result<Option<Vec<u8>>> {
+        self.log.get_annotation(id)
+    }
+
+    fn find_since(&self, timestamp: u64) -> anyhow::Result<Self::LogSize> {
+        self.log.find_since(timestamp)
+    }
+
+    fn add_hash(&mut self, level: LogHeight, hash: String) -> anyhow::Result<Self::LogSize> {
+        self.log.add_hash(level, hash)
+    }
+
+    /// Checks the hashes stored by the wrapped log, not the cached ones
+    fn self_check(&self, depth: CheckDepth) -> anyhow::Result<SelfCheckReport<Self::LogSize>> {
+        self.log.self_check(depth)
+    }
+
+    fn get_hash(
+        &self,
+        level: LogHeight,
+        index: Self::LogSize,
+    ) -> anyhow::Result<MaybeOwned<'_, String>> {
+        let position = LogTreePosition { level, index };
+        if let Some(hash) = self.cached(&position)? {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            return Ok(hash.into());
+        }
+        let hash = self.log.get_hash(level, index)?.into_owned();
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        self.insert([(position, hash.clone())])?;
+        Ok(hash.into())
+    }
+
+    fn append(&mut self, record: T) -> anyhow::Result<Record<Self::LogSize>> {
+        self.log.append(record)
+    }
+
+    fn append_hash_only(&mut self, hash: String) -> anyhow::Result<Record<Self::LogSize>> {
+        self.log.append_hash_only(hash)
+    }
+
+    fn bulk_append(&mut self, records: Vec<T>) -> anyhow::Result<Vec<Record<Self::LogSize>>>
+    where
+        T: Sync,
+    {
+        self.log.bulk_append(records)
+    }
+
+    fn size(&self) -> anyhow::Result<Self::LogSize> {
+        self.log.size()
+    }
+
+    fn origin(&self) -> Option<String> {
+        self.log.origin()
+    }
+
+    fn latest(&self) -> anyhow::Result<LogTree<Self::LogSize>> {
+        self.log.latest()
+    }
+
+    fn leaf_hasher(&self) -> LeafHasher<T> {
+        self.log.leaf_hasher()
+    }
+
+    fn get(&self, index: Self::LogSize) -> anyhow::Result<Option<MaybeOwned<'_, T>>> {
+        self.log.get(index)
+    }
+
+    fn get_raw_bytes(&self, index: Self::LogSize) -> anyhow::Result<Option<Vec<u8>>> {
+        self.log.get_raw_bytes(index)
+    }
+
+    fn get_leaf_bytes(&self, index: Self::LogSize) -> anyhow::Result<Option<Vec<u8>>> {
+        self.log.get_leaf_bytes(index)
+    }
+
+    fn get_range(
+        &self,
+        start: Self::LogSize,
+        end: Self::LogSize,
+    ) -> anyhow::Result<Vec<MaybeOwned<'_, T>>> {
+        self.log.get_range(start, end)
+    }
+
+    fn iter(&self) -> LogIterator<'_, Self::LogSize, T> {
+        self.log.iter()
+    }
+
+    fn index_of(&self, hash: &str) -> anyhow::Result<Option<Self::LogSize>> {
+        self.log.index_of(hash)
+    }
+
+    fn indices_of(&self, hash: &str) -> anyhow::Result<Vec<Self::LogSize>> {
+        self.log.indices_of(hash)
+    }
+
+    /// Only the positions missing from the cache are read from the wrapped log, all at once
+    fn proofs<I>(
+        &self,
+        positions: I,
+    ) -> anyhow::Result<HashMap<LogTreePosition<Self::LogSize>, String>>
+    where
+        I: Iterator<Item = LogTreePosition<Self::LogSize>>,
+    {
+        let mut proofs = HashMap::new();
+        let mut missing = vec![];
+        {
+            let cache = self.cache.read().map_err(|_| CacheError::Poisoned)?;
+            for position in positions {
+                match cache.hashes.get(&position) {
+                    Some(hash) => {
+                        proofs.insert(position, hash.clone());
+                    }
+                    None => missing.push(position),
+                }
+            }
+        }
+        self.hits.fetch_add(proofs.len() as u64, Ordering::Relaxed);
+        if !missing.is_empty() {
+            let read = self.log.proofs(missing.into_iter())?;
+            self.misses.fetch_add(read.len() as u64, Ordering::Relaxed);
+            self.insert(read.iter().map(|(p, h)| (*p, h.clone())))?;
+            proofs.extend(read);
+        }
+        Ok(proofs)
+    }
+}
+
+/// The latest tree of a log, kept by the backends between calls to `latest`
+/// The root of a given size never changes, so the cached tree is only used while the log has its size;
+/// backends also clear it whenever they store a hash, so a tree computed while hashes are being added is never kept
+#[derive(Debug)]
+pub struct RootCache<LogSize> {
+    tree: RwLock<Option<LogTree<LogSize>>>,
+}
+
+impl<LogSize> Default for RootCache<LogSize> {
+    fn default() -> Self {
+        Self {
+            tree: RwLock::new(None),
+        }
+    }
+}
+
+impl<LogSize: PartialEq + Clone> RootCache<LogSize> {
+    /// The cached tree if it has the given size, otherwise the computed tree, cached for later calls
+    pub fn get_or_compute<F>(&self, size: LogSize, compute: F) -> anyhow::Result<LogTree<LogSize>>
+    where
+        F: FnOnce() -> anyhow::Result<LogTree<LogSize>>,
+    {
+        if let Some(tree) = self
+            .tree
+            .read()
+            .map_err(|_| CacheError::Poisoned)?
+            .as_ref()
+            .filter(|t| t.size == size)
+        {
+            return Ok(tree.clone());
+        }
+        let tree = compute()?;
+        *self.tree.write().map_err(|_| CacheError::Poisoned)? = Some(tree.clone());
+        Ok(tree)
+    }
+
+    /// Forget the cached tree
+    pub fn clear(&self) {
+        if let Ok(mut tree) = self.tree.write() {
+            *tree = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::test_helpers::*;
+    use crate::*;
+
+    #[test]
+    fn cache_memory_13() -> anyhow::Result<()> {
+        let mut ml = CachedLog::new(InMemoryLog::default());
+        test_13(&mut ml)?;
+        check_13(&ml)
+    }
+
+    #[test]
+    fn cache_memory_client() -> anyhow::Result<()> {
+        let mut ml = CachedLog::new(InMemoryLog::default());
+        let mut client = InMemoryLogClientBuilder::new(&ml)?.no_cache().build();
+        client_13_nocache(&mut ml, &mut client)
+    }
+
+    #[test]
+    fn cache_memory_properties() -> anyhow::Result<()> {
+        properties(|| Ok(CachedLog::new(InMemoryLog::<LogRecord>::default())))
+    }
+
+    #[test]
+    fn cache_memory_proofs() -> anyhow::Result<()> {
+        let mut ml = CachedLog::new(InMemoryLog::<LogRecord>::default());
+        range_proofs(&mut ml)?;
+        batch_proofs(&mut CachedLog::new(InMemoryLog::default()))?;
+        hash_only(&mut CachedLog::new(InMemoryLog::default()))
+    }
+
+    #[test]
+    fn cache_hits() -> anyhow::Result<()> {
+        let mut ml = CachedLog::new(InMemoryLog::default());
+        append_multiple(&mut ml, 13)?;
+        let misses = ml.misses();
+        let proofs = ml.proofs(proof_positions(9, 13).into_iter())?;
+        assert_eq!(misses + 4, ml.misses());
+        let hits = ml.hits();
+        assert_eq!(proofs, ml.proofs(proof_positions(9, 13).into_iter())?);
+        assert_eq!(misses + 4, ml.misses());
+        assert_eq!(hits + 4, ml.hits());
+        assert_eq!(ml.inner().get_hash(1, 5)?, ml.get_hash(1, 5)?);
+        assert_eq!(hits + 5, ml.hits());
+
+        // new hashes are read, the cached ones are still valid
+        append_multiple_offset(&mut ml, 13, 3)?;
+        let proofs = ml.proofs(proof_positions(9, 16).into_iter())?;
+        assert_eq!(
+            ml.inner().proofs(proof_positions(9, 16).into_iter())?,
+            proofs
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn cache_capacity() -> anyhow::Result<()> {
+        let mut ml = CachedLog::with_capacity(InMemoryLog::default(), 3);
+        append_multiple(&mut ml, 13)?;
+        let proofs = ml.proofs(proof_positions(9, 13).into_iter())?;
+        assert_eq!(4, proofs.len());
+        assert_eq!(3, ml.len());
+        check_13(&ml)?;
+        assert!(ml.len() <= 3);
+
+        let mut ml = CachedLog::with_capacity(InMemoryLog::default(), 0);
+        append_multiple(&mut ml, 13)?;
+        check_13(&ml)?;
+        assert!(ml.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn root_cache() -> anyhow::Result<()> {
+        let cache = RootCache::default();
+        let tree = |size: u64, hash: &str| LogTree {
+            size,
+            hash: hash.to_owned(),
+            origin: None,
+        };
+        assert_eq!(tree(1, "a"), cache.get_or_compute(1, || Ok(tree(1, "a")))?);
+        // the same size is served from the cache
+        assert_eq!(tree(1, "a"), cache.get_or_compute(1, || Ok(tree(1, "b")))?);
+        // another size is computed again
+        assert_eq!(tree(2, "c"), cache.get_or_compute(2, || Ok(tree(2, "c")))?);
+        assert!(cache
+            .get_or_compute(3, || anyhow::bail!("missing"))
+            .is_err());
+        assert_eq!(tree(2, "c"), cache.get_or_compute(2, || Ok(tree(2, "d")))?);
+        cache.clear();
+        assert_eq!(tree(2, "d"), cache.get_or_compute(2, || Ok(tree(2, "d")))?);
+        Ok(())
+    }
+}
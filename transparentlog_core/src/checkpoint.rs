@@ -0,0 +1,508 @@
+//! Signed notes and checkpoints, compatible with the Go `golang.org/x/mod/sumdb/note` format
+use crate::base::*;
+use crypto::ed25519;
+use num::Zero;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fmt::Display;
+use std::future::Future;
+use std::pin::Pin;
+use std::str::FromStr;
+use thiserror::Error;
+
+/// The algorithm byte identifying Ed25519 keys
+const ALG_ED25519: u8 = 1;
+
+/// The prefix of a signature line
+const SIGNATURE_PREFIX: &str = "\u{2014} ";
+
+/// The SHA-256 of the empty string, standing for the empty root hash of an empty tree in checkpoints
+const EMPTY_ROOT: &str = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+
+/// Specific Errors for notes, checkpoints and promises
+#[derive(Debug, Clone, Error)]
+pub enum CheckpointError {
+    #[error("Malformed note: {0}")]
+    MalformedNote(&'static str),
+    #[error("Malformed checkpoint: {0}")]
+    MalformedCheckpoint(&'static str),
+    #[error("Malformed promise: {0}")]
+    MalformedPromise(&'static str),
+    #[error("Malformed verifier key")]
+    MalformedKey,
+    #[error("Invalid signature from {0}")]
+    InvalidSignature(String),
+    #[error("No signature from a known key")]
+    UnverifiedNote,
+}
+
+/// A signature being computed, by a signer calling an external service
+pub type SignatureFuture<'s> = Pin<Box<dyn Future<Output = anyhow::Result<Vec<u8>>> + Send + 's>>;
+
+/// Something that can sign notes
+pub trait LogSigner {
+    /// The name of the key
+    fn name(&self) -> &str;
+
+    /// The hash identifying the key
+    fn key_hash(&self) -> u32;
+
+    /// Sign the given message
+    fn sign(&self, msg: &[u8]) -> anyhow::Result<Vec<u8>>;
+
+    /// Sign the given message without blocking, for signers calling a KMS or HSM
+    /// By default the signature is computed at once with `sign`
+    fn sign_async<'s>(&'s self, msg: &'s [u8]) -> SignatureFuture<'s> {
+        Box::pin(std::future::ready(self.sign(msg)))
+    }
+}
+
+/// Something that can verify note signatures
+pub trait LogVerifier {
+    /// The name of the key
+    fn name(&self) -> &str;
+
+    /// The hash identifying the key
+    fn key_hash(&self) -> u32;
+
+    /// Verify the signature of the given message
+    fn verify(&self, msg: &[u8], signature: &[u8]) -> bool;
+}
+
+/// Calculate the key hash for a given name and encoded key
+fn key_hash(name: &str, key: &[u8]) -> u32 {
+    let mut hasher = Sha256::new();
+    hasher.update(name.as_bytes());
+    hasher.update(b"\n");
+    hasher.update(key);
+    let b = hasher.finalize();
+    u32::from_be_bytes([b[0], b[1], b[2], b[3]])
+}
+
+/// The id of a key in signatures that do not carry its hash, as in DSSE envelopes: its name and hash,
+/// the start of its verifier key, telling apart the keys of the same name of a log rotating them
+pub fn key_id(name: &str, key_hash: u32) -> String {
+    format!("{}+{:08x}", name, key_hash)
+}
+
+/// Ed25519 note signer
+pub struct Ed25519Signer {
+    name: String,
+    secret: [u8; 64],
+    public: [u8; 32],
+}
+
+impl Ed25519Signer {
+    /// Create a new signer from a name and a 32 bytes seed
+    pub fn new(name: &str, seed: &[u8; 32]) -> Self {
+        let (secret, public) = ed25519::keypair(seed);
+        Self {
+            name: name.to_owned(),
+            secret,
+            public,
+        }
+    }
+
+    /// Get the verifier for this signer
+    pub fn verifier(&self) -> Ed25519Verifier {
+        Ed25519Verifier::new(&self.name, &self.public)
+    }
+}
+
+impl LogSigner for Ed25519Signer {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn key_hash(&self) -> u32 {
+        self.verifier().key_hash
+    }
+
+    fn sign(&self, msg: &[u8]) -> anyhow::Result<Vec<u8>> {
+        Ok(ed25519::signature(msg, &self.secret).to_vec())
+    }
+}
+
+/// Ed25519 note verifier
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ed25519Verifier {
+    name: String,
+    key_hash: u32,
+    public: [u8; 32],
+}
+
+impl Ed25519Verifier {
+    /// Create a new verifier from a name and a public key
+    pub fn new(name: &str, public: &[u8; 32]) -> Self {
+        let mut key = vec![ALG_ED25519];
+        key.extend_from_slice(public);
+        Self {
+            name: name.to_owned(),
+            key_hash: key_hash(name, &key),
+            public: *public,
+        }
+    }
+
+    /// Parse a verifier key in the `<name>+<hash>+<base64 key>` format
+    pub fn from_vkey(vkey: &str) -> Result<Self, CheckpointError> {
+        let mut parts = vkey.splitn(3, '+');
+        let (name, hash, key) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(name), Some(hash), Some(key)) => (name, hash, key),
+            _ => return Err(CheckpointError::MalformedKey),
+        };
+        let key = base64::decode(key).map_err(|_| CheckpointError::MalformedKey)?;
+        if key.len() != 33 || key[0] != ALG_ED25519 {
+            return Err(CheckpointError::MalformedKey);
+        }
+        let mut public = [0_u8; 32];
+        public.copy_from_slice(&key[1..]);
+        let verifier = Self::new(name, &public);
+        if u32::from_str_radix(hash, 16).ok() != Some(verifier.key_hash) {
+            return Err(CheckpointError::MalformedKey);
+        }
+        Ok(verifier)
+    }
+
+    /// The public key
+    pub fn public_key(&self) -> &[u8; 32] {
+        &self.public
+    }
+
+    /// Encode the verifier key in the `<name>+<hash>+<base64 key>` format
+    pub fn vkey(&self) -> String {
+        let mut key = vec![ALG_ED25519];
+        key.extend_from_slice(&self.public);
+        format!(
+            "{}+{:08x}+{}",
+            self.name,
+            self.key_hash,
+            base64::encode(key)
+        )
+    }
+}
+
+impl LogVerifier for Ed25519Verifier {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn key_hash(&self) -> u32 {
+        self.key_hash
+    }
+
+    fn verify(&self, msg: &[u8], signature: &[u8]) -> bool {
+        signature.len() == 64 && ed25519::verify(msg, &self.public, signature)
+    }
+}
+
+/// A signature line of a note
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NoteSignature {
+    pub name: String,
+    pub key_hash: u32,
+    pub signature: Vec<u8>,
+}
+
+impl NoteSignature {
+    /// Parse a signature line, without the trailing new line
+    fn parse(line: &str) -> Result<Self, CheckpointError> {
+        let malformed = CheckpointError::MalformedNote("invalid signature line");
+        let line = line
+            .strip_prefix(SIGNATURE_PREFIX)
+            .ok_or(malformed.clone())?;
+        let (name, sig) = line.split_once(' ').ok_or(malformed.clone())?;
+        let b = base64::decode(sig).map_err(|_| malformed.clone())?;
+        if name.is_empty() || b.len() < 5 {
+            return Err(malformed);
+        }
+        Ok(Self {
+            name: name.to_owned(),
+            key_hash: u32::from_be_bytes([b[0], b[1], b[2], b[3]]),
+            signature: b[4..].to_vec(),
+        })
+    }
+}
+
+impl Display for NoteSignature {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut b = self.key_hash.to_be_bytes().to_vec();
+        b.extend_from_slice(&self.signature);
+        write!(f, "{}{} {}", SIGNATURE_PREFIX, self.name, base64::encode(b))
+    }
+}
+
+/// Sign the text of a note with all the given signers, returning the full note
+pub fn sign_note(text: &str, signers: &[&dyn LogSigner]) -> anyhow::Result<String> {
+    let mut note = note_start(text)?;
+    for signer in signers {
+        let signature = signer.sign(text.as_bytes())?;
+        note.push_str(&signature_line(*signer, signature));
+    }
+    Ok(note)
+}
+
+/// Sign the text of a note with all the given signers without blocking, like `sign_note`
+pub async fn sign_note_async(text: &str, signers: &[&dyn LogSigner]) -> anyhow::Result<String> {
+    let mut note = note_start(text)?;
+    for signer in signers {
+        let signature = signer.sign_async(text.as_bytes()).await?;
+        note.push_str(&signature_line(*signer, signature));
+    }
+    Ok(note)
+}
+
+/// Add the signatures of the given signers to a signed note, as witnesses cosigning it
+/// The signatures already in the note are kept without being verified, and signers that already signed it skipped
+pub fn cosign_note(note: &str, signers: &[&dyn LogSigner]) -> anyhow::Result<String> {
+    let (text, signatures) = parse_note(note)?;
+    let mut cosigned = note.to_owned();
+    for signer in signers {
+        if !signatures
+            .iter()
+            .any(|s| s.name == signer.name() && s.key_hash == signer.key_hash())
+        {
+            let signature = signer.sign(text.as_bytes())?;
+            cosigned.push_str(&signature_line(*signer, signature));
+        }
+    }
+    Ok(cosigned)
+}
+
+/// The text of a note followed by the blank line before its signatures, failing if the text is not a note text
+fn note_start(text: &str) -> Result<String, CheckpointError> {
+    if !text.ends_with('\n') || text.contains("\n\n") {
+        return Err(CheckpointError::MalformedNote(
+            "text must end with a single new line",
+        ));
+    }
+    Ok(format!("{}\n", text))
+}
+
+/// The signature line of a signer
+fn signature_line(signer: &dyn LogSigner, signature: Vec<u8>) -> String {
+    let sig = NoteSignature {
+        name: signer.name().to_owned(),
+        key_hash: signer.key_hash(),
+        signature,
+    };
+    format!("{}\n", sig)
+}
+
+/// Parse a note into its text and signatures, without verifying them
+pub fn parse_note(note: &str) -> Result<(&str, Vec<NoteSignature>), CheckpointError> {
+    let split = note
+        .rfind("\n\n")
+        .ok_or(CheckpointError::MalformedNote("missing signatures"))?;
+    let (text, sigs) = note.split_at(split + 1);
+    let sigs = sigs[1..]
+        .strip_suffix('\n')
+        .ok_or(CheckpointError::MalformedNote("missing final new line"))?;
+    let signatures = sigs
+        .split('\n')
+        .map(NoteSignature::parse)
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok((text, signatures))
+}
+
+/// Parse a note and verify its signatures, returning its text and the verified signatures
+/// Fails if a signature from a known key is invalid, or if no signature is from a known key
+pub fn open_note<'n>(
+    note: &'n str,
+    verifiers: &[&dyn LogVerifier],
+) -> Result<(&'n str, Vec<NoteSignature>), CheckpointError> {
+    let (text, signatures) = parse_note(note)?;
+    let mut verified = vec![];
+    for sig in signatures {
+        if let Some(v) = verifiers
+            .iter()
+            .find(|v| v.name() == sig.name && v.key_hash() == sig.key_hash)
+        {
+            if !v.verify(text.as_bytes(), &sig.signature) {
+                return Err(CheckpointError::InvalidSignature(sig.name));
+            }
+            verified.push(sig);
+        }
+    }
+    if verified.is_empty() {
+        return Err(CheckpointError::UnverifiedNote);
+    }
+    Ok((text, verified))
+}
+
+/// A checkpoint: a tree head tied to the origin of the log
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Checkpoint<LogSize> {
+    pub origin: String,
+    pub tree: LogTree<LogSize>,
+}
+
+impl<LogSize: Display + FromStr + Zero> Checkpoint<LogSize> {
+    /// Create a new checkpoint
+    pub fn new(origin: &str, tree: LogTree<LogSize>) -> Self {
+        Self {
+            origin: origin.to_owned(),
+            tree,
+        }
+    }
+
+    /// The text of the checkpoint: origin, size and base64 encoded root hash lines
+    pub fn text(&self) -> anyhow::Result<String> {
+        let hash = if self.tree.size.is_zero() {
+            EMPTY_ROOT
+        } else {
+            &self.tree.hash
+        };
+        Ok(format!(
+            "{}\n{}\n{}\n",
+            self.origin,
+            self.tree.size,
+            base64::encode(hex::decode(hash)?)
+        ))
+    }
+
+    /// Parse the text of a checkpoint, ignoring extension lines
+    pub fn parse(text: &str) -> Result<Self, CheckpointError> {
+        let mut lines = text.split('\n');
+        let (origin, size, hash) = match (lines.next(), lines.next(), lines.next()) {
+            (Some(origin), Some(size), Some(hash)) if !origin.is_empty() => (origin, size, hash),
+            _ => return Err(CheckpointError::MalformedCheckpoint("missing lines")),
+        };
+        let size: LogSize = size
+            .parse()
+            .map_err(|_| CheckpointError::MalformedCheckpoint("invalid size"))?;
+        let hash = hex::encode(
+            base64::decode(hash)
+                .map_err(|_| CheckpointError::MalformedCheckpoint("invalid root hash"))?,
+        );
+        let hash = if size.is_zero() {
+            if hash != EMPTY_ROOT {
+                return Err(CheckpointError::MalformedCheckpoint(
+                    "invalid empty root hash",
+                ));
+            }
+            String::new()
+        } else {
+            hash
+        };
+        Ok(Self {
+            origin: origin.to_owned(),
+            tree: LogTree {
+                size,
+                hash,
+                origin: None,
+            },
+        })
+    }
+
+    /// Sign the checkpoint, returning the signed note
+    pub fn sign(&self, signers: &[&dyn LogSigner]) -> anyhow::Result<String> {
+        sign_note(&self.text()?, signers)
+    }
+
+    /// Sign the checkpoint without blocking, returning the signed note
+    pub async fn sign_async(&self, signers: &[&dyn LogSigner]) -> anyhow::Result<String> {
+        sign_note_async(&self.text()?, signers).await
+    }
+
+    /// Open a signed checkpoint, verifying its signatures
+    pub fn open(note: &str, verifiers: &[&dyn LogVerifier]) -> Result<Self, CheckpointError> {
+        let (text, _) = open_note(note, verifiers)?;
+        Self::parse(text)
+    }
+}
+
+/// Seal the log, returning the checkpoint of its final tree signed by the given signers
+pub fn seal_checkpoint<'a, T, TL>(
+    log: &mut TL,
+    origin: &str,
+    signers: &[&dyn LogSigner],
+) -> anyhow::Result<String>
+where
+    T: Serialize + Deserialize<'a>,
+    TL: TransparentLog<'a, T>,
+    TL::LogSize: Display + FromStr,
+{
+    Checkpoint::new(origin, log.seal()?).sign(signers)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::test_helpers::*;
+    use crate::*;
+
+    const PETER_VKEY: &str = "PeterNeumann+c74f20a3+ARpc2QcUPDhMQegwxbzhKqiBfsVkmqq/LDE4izWy10TW";
+    const PETER_NOTE: &str = "If you think cryptography is the answer to your problem,\nthen you don't know what your problem is.\n\n\u{2014} PeterNeumann x08go/ZJkuBS9UG/SffcvIAQxVBtiFupLLr8pAcElZInNIuGUgYN1FFYC2pZSNXgKvqfqdngotpRZb6KE6RyyBwJnAM=\n";
+
+    #[test]
+    fn go_note() -> anyhow::Result<()> {
+        let verifier = Ed25519Verifier::from_vkey(PETER_VKEY)?;
+        assert_eq!(PETER_VKEY, verifier.vkey());
+        let (text, sigs) = open_note(PETER_NOTE, &[&verifier])?;
+        assert!(text.starts_with("If you think"));
+        assert_eq!(1, sigs.len());
+        assert_eq!(0xc74f20a3, sigs[0].key_hash);
+
+        let tampered = PETER_NOTE.replace("answer", "solution");
+        assert!(matches!(
+            open_note(&tampered, &[&verifier]),
+            Err(CheckpointError::InvalidSignature(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn checkpoint_sign_open() -> anyhow::Result<()> {
+        let mut ml: InMemoryLog<LogRecord> = InMemoryLog::default();
+        append_multiple(&mut ml, 13)?;
+        let signer = Ed25519Signer::new("example.com/log", &[7_u8; 32]);
+        let witness = Ed25519Signer::new("witness", &[8_u8; 32]);
+        let checkpoint = Checkpoint::new("example.com/log", ml.latest()?);
+        let note = checkpoint.sign(&[&signer, &witness])?;
+        assert!(note.starts_with("example.com/log\n13\n"));
+
+        let opened: Checkpoint<usize> = Checkpoint::open(&note, &[&signer.verifier()])?;
+        assert_eq!(checkpoint, opened);
+        let (_, sigs) = open_note(&note, &[&signer.verifier(), &witness.verifier()])?;
+        assert_eq!(2, sigs.len());
+
+        let other = Ed25519Signer::new("other", &[9_u8; 32]);
+        assert!(matches!(
+            Checkpoint::<usize>::open(&note, &[&other.verifier()]),
+            Err(CheckpointError::UnverifiedNote)
+        ));
+        let tampered = note.replacen("13", "14", 1);
+        assert!(matches!(
+            Checkpoint::<usize>::open(&tampered, &[&signer.verifier()]),
+            Err(CheckpointError::InvalidSignature(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn checkpoint_empty() -> anyhow::Result<()> {
+        let ml: InMemoryLog<LogRecord> = InMemoryLog::default();
+        let signer = Ed25519Signer::new("log", &[1_u8; 32]);
+        let note = Checkpoint::new("log", ml.latest()?).sign(&[&signer])?;
+        let opened: Checkpoint<usize> = Checkpoint::open(&note, &[&signer.verifier()])?;
+        assert_eq!(0, opened.tree.size);
+        assert_eq!("", opened.tree.hash);
+        Ok(())
+    }
+
+    #[test]
+    fn checkpoint_sealed() -> anyhow::Result<()> {
+        let mut ml: InMemoryLog<LogRecord> = InMemoryLog::default();
+        append_multiple(&mut ml, 13)?;
+        let signer = Ed25519Signer::new("example.com/log", &[7_u8; 32]);
+        let note = seal_checkpoint(&mut ml, "example.com/log", &[&signer])?;
+        let opened: Checkpoint<usize> = Checkpoint::open(&note, &[&signer.verifier()])?;
+        assert_eq!(ml.sealed()?.map(|t| t.hash), Some(opened.tree.hash));
+        assert_sealed(ml.append(LogRecord::new("rec13")));
+        assert_eq!(
+            note,
+            seal_checkpoint(&mut ml, "example.com/log", &[&signer])?
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,263 @@
+//! # Record codecs
+//!
+//! Codecs serializing the records of a log. The serialized bytes of a record are the preimage of its leaf hash,
+//! so logs that must agree on hashes with other implementations choose the codec these use.
+//! MessagePack is the default, JSON is always available, CBOR and bincode are behind the `cbor` and `bincode` features.
+//! A log records the name of a codec other than MessagePack when it is created and refuses to open with another one.
+//! A record whose serialization is not deterministic, like a `HashMap` field, gets another leaf hash when it is
+//! serialized again, so `encode_canonical` checks records round-trip to the same bytes, and `sorted_map`
+//! serializes maps with their keys in order.
+use serde::de::DeserializeOwned;
+use serde::{Serialize, Serializer};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Debug;
+use std::hash::BuildHasher;
+use thiserror::Error;
+use transparentlog_verify::sha256_hex;
+
+/// A serialization format for records, selected by type
+pub trait Codec: Debug + Send + Sync + 'static {
+    /// The name recorded in the log header
+    const NAME: &'static str;
+
+    /// Serialize a value
+    fn encode<T: Serialize + ?Sized>(value: &T) -> anyhow::Result<Vec<u8>>;
+
+    /// Deserialize a value serialized by `encode`
+    fn decode<T: DeserializeOwned>(data: &[u8]) -> anyhow::Result<T>;
+}
+
+/// MessagePack, via rmp-serde, the default codec
+#[derive(Debug, Default, Clone, Copy)]
+pub struct MessagePack;
+
+impl Codec for MessagePack {
+    const NAME: &'static str = "msgpack";
+
+    fn encode<T: Serialize + ?Sized>(value: &T) -> anyhow::Result<Vec<u8>> {
+        Ok(rmp_serde::to_vec(value)?)
+    }
+
+    fn decode<T: DeserializeOwned>(data: &[u8]) -> anyhow::Result<T> {
+        Ok(rmp_serde::from_slice(data)?)
+    }
+}
+
+/// JSON, via serde_json, without whitespace
+#[cfg(feature = "json")]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Json;
+
+#[cfg(feature = "json")]
+impl Codec for Json {
+    const NAME: &'static str = "json";
+
+    fn encode<T: Serialize + ?Sized>(value: &T) -> anyhow::Result<Vec<u8>> {
+        Ok(serde_json::to_vec(value)?)
+    }
+
+    fn decode<T: DeserializeOwned>(data: &[u8]) -> anyhow::Result<T> {
+        Ok(serde_json::from_slice(data)?)
+    }
+}
+
+/// CBOR, via ciborium
+#[cfg(feature = "cbor")]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Cbor;
+
+#[cfg(feature = "cbor")]
+impl Codec for Cbor {
+    const NAME: &'static str = "cbor";
+
+    fn encode<T: Serialize + ?Sized>(value: &T) -> anyhow::Result<Vec<u8>> {
+        let mut data = vec![];
+        ciborium::ser::into_writer(value, &mut data)?;
+        Ok(data)
+    }
+
+    fn decode<T: DeserializeOwned>(data: &[u8]) -> anyhow::Result<T> {
+        Ok(ciborium::de::from_reader(data)?)
+    }
+}
+
+/// Bincode, with its default options
+#[cfg(feature = "bincode")]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Bincode;
+
+#[cfg(feature = "bincode")]
+impl Codec for Bincode {
+    const NAME: &'static str = "bincode";
+
+    fn encode<T: Serialize + ?Sized>(value: &T) -> anyhow::Result<Vec<u8>> {
+        Ok(bincode::serialize(value)?)
+    }
+
+    fn decode<T: DeserializeOwned>(data: &[u8]) -> anyhow::Result<T> {
+        Ok(bincode::deserialize(data)?)
+    }
+}
+
+/// Hash a given record serialized with the given codec
+pub fn hash_with<C: Codec, T: Serialize + ?Sized>(record: &T) -> anyhow::Result<String> {
+    Ok(sha256_hex(&C::encode(record)?))
+}
+
+/// Serialize a record with the given codec, checking that deserializing and serializing it again gives the same bytes
+/// Fails with a `CodecError::NotCanonical` error otherwise, as the leaf hash of the record could not be recomputed
+pub fn encode_canonical<C: Codec, T: Serialize + DeserializeOwned>(
+    record: &T,
+) -> anyhow::Result<Vec<u8>> {
+    let bytes = C::encode(record)?;
+    if C::encode(&C::decode::<T>(&bytes)?)? != bytes {
+        return Err(CodecError::NotCanonical(C::NAME.into()).into());
+    }
+    Ok(bytes)
+}
+
+/// Serialize a map with its keys in order, whatever the order of its iterator
+/// Use with `#[serde(serialize_with = "sorted_map")]` on `HashMap` fields
+pub fn sorted_map<K, V, H, S>(map: &HashMap<K, V, H>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    K: Ord + Serialize,
+    V: Serialize,
+    H: BuildHasher,
+    S: Serializer,
+{
+    serializer.collect_map(map.iter().collect::<BTreeMap<_, _>>())
+}
+
+/// Errors about codecs: opening a log with another codec, or serializations that do not round-trip
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum CodecError {
+    #[error("log written with codec {found}, opened with {expected}")]
+    Mismatch { expected: String, found: String },
+    #[error("serializing with codec {0} again does not give the same bytes")]
+    NotCanonical(String),
+}
+
+/// Check the codec a log is opened with matches the one recorded in its header, no record meaning MessagePack
+/// Returns true if the header must be written, for a new log opened with another codec than MessagePack
+pub fn check_codec<C: Codec>(recorded: Option<&str>, empty: bool) -> Result<bool, CodecError> {
+    let found = recorded.unwrap_or(MessagePack::NAME);
+    if found == C::NAME {
+        Ok(false)
+    } else if recorded.is_none() && empty {
+        Ok(true)
+    } else {
+        Err(CodecError::Mismatch {
+            expected: C::NAME.into(),
+            found: found.into(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::test_helpers::*;
+    use crate::*;
+    use serde::{Deserialize, Deserializer, Serialize};
+    use std::collections::{BTreeMap, HashMap};
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn codec_header() {
+        assert_eq!(Ok(false), check_codec::<MessagePack>(None, false));
+        assert_eq!(Ok(false), check_codec::<MessagePack>(None, true));
+        assert_eq!(Ok(true), check_codec::<Json>(None, true));
+        assert_eq!(Ok(false), check_codec::<Json>(Some("json"), false));
+        assert_eq!(
+            Err(CodecError::Mismatch {
+                expected: "json".into(),
+                found: "msgpack".into()
+            }),
+            check_codec::<Json>(None, false)
+        );
+        assert_eq!(
+            Err(CodecError::Mismatch {
+                expected: "msgpack".into(),
+                found: "json".into()
+            }),
+            check_codec::<MessagePack>(Some("json"), true)
+        );
+    }
+
+    #[test]
+    fn codec_round_trip() -> anyhow::Result<()> {
+        let record = LogRecord::new("rec0");
+        assert_eq!(
+            record,
+            MessagePack::decode::<LogRecord>(&MessagePack::encode(&record)?)?
+        );
+        #[cfg(feature = "json")]
+        assert_eq!(b"{\"text\":\"rec0\"}".to_vec(), Json::encode(&record)?);
+        #[cfg(feature = "json")]
+        assert_eq!(record, Json::decode::<LogRecord>(&Json::encode(&record)?)?);
+        assert_eq!(hash(&record)?, hash_with::<MessagePack, _>(&record)?);
+        #[cfg(feature = "json")]
+        assert_ne!(hash(&record)?, hash_with::<Json, _>(&record)?);
+        Ok(())
+    }
+
+    /// A record whose text is trimmed when deserialized
+    #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+    struct Trimmed {
+        #[serde(deserialize_with = "trim")]
+        text: String,
+    }
+
+    fn trim<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+        Ok(String::deserialize(deserializer)?.trim().to_owned())
+    }
+
+    /// A record with a map, serialized in order
+    #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+    struct Labels {
+        #[serde(serialize_with = "sorted_map")]
+        labels: HashMap<String, u32>,
+    }
+
+    #[test]
+    fn codec_canonical() -> anyhow::Result<()> {
+        let record = LogRecord::new("rec0");
+        assert_eq!(
+            MessagePack::encode(&record)?,
+            encode_canonical::<MessagePack, _>(&record)?
+        );
+        #[cfg(feature = "json")]
+        assert_eq!(
+            Json::encode(&record)?,
+            encode_canonical::<Json, _>(&record)?
+        );
+
+        let trimmed = Trimmed {
+            text: "rec0".into(),
+        };
+        encode_canonical::<MessagePack, _>(&trimmed)?;
+        let padded = Trimmed {
+            text: " rec0 ".into(),
+        };
+        let err = encode_canonical::<MessagePack, _>(&padded).unwrap_err();
+        assert_eq!(
+            Some(&CodecError::NotCanonical("msgpack".into())),
+            err.downcast_ref::<CodecError>()
+        );
+
+        let labels = Labels {
+            labels: (0..32).map(|i| (format!("label{}", i), i)).collect(),
+        };
+        let sorted: BTreeMap<_, _> = labels.labels.iter().collect();
+        assert_eq!(
+            MessagePack::encode(&(sorted,))?,
+            encode_canonical::<MessagePack, _>(&labels)?
+        );
+        #[cfg(feature = "json")]
+        assert_eq!(
+            br#"{"labels":{"label0":0,"label1":1,"label10":10"#.to_vec(),
+            encode_canonical::<Json, _>(&labels)?[..45].to_vec()
+        );
+        Ok(())
+    }
+}
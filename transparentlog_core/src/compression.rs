@@ -0,0 +1,94 @@
+//! # Record compression
+//!
+//! Compressors of the serialized records stored by a log. A log records the name of its compressor in a header
+//! when it is created and refuses to open with another one, so it always knows how to decode its records.
+//! Hashes are always computed over the uncompressed serialized records, so compression does not change proofs.
+//! The `transparentlog_compress` crate provides zstd and lz4 compressors.
+use std::fmt::Debug;
+use thiserror::Error;
+
+/// A compressor of serialized records
+pub trait Compressor: Debug + Send + Sync {
+    /// The name recorded in the log header
+    fn name(&self) -> &str;
+
+    /// Compress serialized bytes
+    fn compress(&self, data: &[u8]) -> anyhow::Result<Vec<u8>>;
+
+    /// Decompress bytes compressed by `compress`
+    fn decompress(&self, data: &[u8]) -> anyhow::Result<Vec<u8>>;
+}
+
+/// Errors opening a log with compression
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum CompressionError {
+    #[error("log written with compression {found:?}, opened with {expected:?}")]
+    Mismatch {
+        expected: Option<String>,
+        found: Option<String>,
+    },
+}
+
+/// Check the compressor a log is opened with matches the one recorded in its header
+/// Returns true if the header must be written, for a new log opened with a compressor
+pub fn check_compressor(
+    recorded: Option<&str>,
+    compressor: Option<&dyn Compressor>,
+    empty: bool,
+) -> Result<bool, CompressionError> {
+    let expected = compressor.map(|c| c.name());
+    match (recorded, expected) {
+        (None, None) => Ok(false),
+        (None, Some(_)) if empty => Ok(true),
+        (r, e) if r == e => Ok(false),
+        (r, e) => Err(CompressionError::Mismatch {
+            expected: e.map(String::from),
+            found: r.map(String::from),
+        }),
+    }
+}
+
+/// Compress serialized bytes if there is a compressor
+pub fn compress(compressor: Option<&dyn Compressor>, data: Vec<u8>) -> anyhow::Result<Vec<u8>> {
+    match compressor {
+        Some(c) => c.compress(&data),
+        None => Ok(data),
+    }
+}
+
+/// Decompress bytes if there is a compressor
+pub fn decompress(compressor: Option<&dyn Compressor>, data: Vec<u8>) -> anyhow::Result<Vec<u8>> {
+    match compressor {
+        Some(c) => c.decompress(&data),
+        None => Ok(data),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::test_helpers::RleCompressor;
+    use crate::*;
+
+    #[test]
+    fn compression_header() {
+        let rle = RleCompressor;
+        assert_eq!(Ok(false), check_compressor(None, None, false));
+        assert_eq!(Ok(true), check_compressor(None, Some(&rle), true));
+        assert_eq!(Ok(false), check_compressor(Some("rle"), Some(&rle), false));
+        assert_eq!(
+            Err(CompressionError::Mismatch {
+                expected: Some("rle".into()),
+                found: None
+            }),
+            check_compressor(None, Some(&rle), false)
+        );
+        assert_eq!(
+            Err(CompressionError::Mismatch {
+                expected: None,
+                found: Some("rle".into())
+            }),
+            check_compressor(Some("rle"), None, true)
+        );
+    }
+}
@@ -0,0 +1,254 @@
+//! # Deduplication
+//!
+//! A log in submit-if-absent mode: appending a record already logged returns the existing record instead of a duplicate.
+//! Duplicates are found through the hash index every log keeps, with `index_of`.
+use crate::base::*;
+use maybe_owned::MaybeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// A log appending each record only once, identical records getting the first record appended
+#[derive(Debug)]
+pub struct DedupLog<L> {
+    log: L,
+}
+
+impl<L> DedupLog<L> {
+    /// Wrap a log, deduplicating the records appended from now on
+    pub fn new(log: L) -> Self {
+        Self { log }
+    }
+
+    /// The wrapped log
+    pub fn inner(&self) -> &L {
+        &self.log
+    }
+
+    /// Get back the wrapped log
+    pub fn into_inner(self) -> L {
+        self.log
+    }
+
+    /// Find the record already logged with the given hash
+    fn existing<'a, T>(&self, hash: &str) -> anyhow::Result<Option<Record<L::LogSize>>>
+    where
+        T: Serialize + Deserialize<'a>,
+        L: TransparentLog<'a, T>,
+    {
+        Ok(match self.log.index_of(hash)? {
+            Some(id) => Some(Record {
+                id,
+                hash: hash.to_owned(),
+                meta: self.log.get_meta(id)?,
+            }),
+            None => None,
+        })
+    }
+}
+
+/// Only appends are deduplicated: records added with `add` and hashes pushed with `add_hash` go straight to the wrapped log
+impl<'a, T, L> TransparentLog<'a, T> for DedupLog<L>
+where
+    T: Serialize + Deserialize<'a>,
+    L: TransparentLog<'a, T>,
+{
+    type LogSize = L::LogSize;
+
+    fn add(&mut self, record: T) -> anyhow::Result<Self::LogSize> {
+        self.log.add(record)
+    }
+
+    fn add_tombstone(&mut self) -> anyhow::Result<Self::LogSize> {
+        self.log.add_tombstone()
+    }
+
+    fn redact(&mut self, id: Self::LogSize) -> anyhow::Result<()> {
+        self.log.redact(id)
+    }
+
+    fn expire(&mut self, id: Self::LogSize) -> anyhow::Result<()> {
+        self.log.expire(id)
+    }
+
+    fn expired(&self, id: Self::LogSize) -> anyhow::Result<bool> {
+        self.log.expired(id)
+    }
+
+    fn seal(&mut self) -> anyhow::Result<LogTree<Self::LogSize>> {
+        self.log.seal()
+    }
+
+    fn sealed(&self) -> anyhow::Result<Option<LogTree<Self::LogSize>>> {
+        self.log.sealed()
+    }
+
+    fn sync(&self) -> anyhow::Result<()> {
+        self.log.sync()
+    }
+
+    fn add_meta(&mut self, id: Self::LogSize, meta: RecordMeta) -> anyhow::Result<()> {
+        self.log.add_meta(id, meta)
+    }
+
+    fn get_meta(&self, id: Self::LogSize) -> anyhow::Result<Option<RecordMeta>> {
+        self.log.get_meta(id)
+    }
+
+    fn set_annotation(&mut self, id: Self::LogSize, annotation: Vec<u8>) -> anyhow::Result<()> {
+        self.log.set_annotation(id, annotation)
+    }
+
+    fn get_annotation(&self, id: Self::LogSize) -> anyhow::Result<Option<Vec<u8>>> {
+        self.log.get_annotation(id)
+    }
+
+    fn find_since(&self, timestamp: u64) -> anyhow::Result<Self::LogSize> {
+        self.log.find_since(timestamp)
+    }
+
+    fn add_hash(&mut self, level: LogHeight, hash: String) -> anyhow::Result<Self::LogSize> {
+        self.log.add_hash(level, hash)
+    }
+
+    fn get_hash(
+        &self,
+        level: LogHeight,
+        index: Self::LogSize,
+    ) -> anyhow::Result<MaybeOwned<'_, String>> {
+        self.log.get_hash(level, index)
+    }
+
+    /// Return the record already logged with the same hash, if any
+    fn append(&mut self, record: T) -> anyhow::Result<Record<Self::LogSize>> {
+        match self.existing(&(self.log.leaf_hasher())(&record)?)? {
+            Some(existing) => Ok(existing),
+            None => self.log.append(record),
+        }
+    }
+
+    /// Return the record already logged with the same hash, if any
+    fn append_hash_only(&mut self, hash: String) -> anyhow::Result<Record<Self::LogSize>> {
+        match self.existing(&hash)? {
+            Some(existing) => Ok(existing),
+            None => self.log.append_hash_only(hash),
+        }
+    }
+
+    /// Records are appended one by one, so duplicates within the batch are only logged once
+    fn bulk_append(&mut self, records: Vec<T>) -> anyhow::Result<Vec<Record<Self::LogSize>>>
+    where
+        T: Sync,
+    {
+        records.into_iter().map(|r| self.append(r)).collect()
+    }
+
+    fn size(&self) -> anyhow::Result<Self::LogSize> {
+        self.log.size()
+    }
+
+    fn origin(&self) -> Option<String> {
+        self.log.origin()
+    }
+
+    fn latest(&self) -> anyhow::Result<LogTree<Self::LogSize>> {
+        self.log.latest()
+    }
+
+    fn leaf_hasher(&self) -> LeafHasher<T> {
+        self.log.leaf_hasher()
+    }
+
+    fn get(&self, index: Self::LogSize) -> anyhow::Result<Option<MaybeOwned<'_, T>>> {
+        self.log.get(index)
+    }
+
+    fn get_raw_bytes(&self, index: Self::LogSize) -> anyhow::Result<Option<Vec<u8>>> {
+        self.log.get_raw_bytes(index)
+    }
+
+    fn get_leaf_bytes(&self, index: Self::LogSize) -> anyhow::Result<Option<Vec<u8>>> {
+        self.log.get_leaf_bytes(index)
+    }
+
+    fn get_range(
+        &self,
+        start: Self::LogSize,
+        end: Self::LogSize,
+    ) -> anyhow::Result<Vec<MaybeOwned<'_, T>>> {
+        self.log.get_range(start, end)
+    }
+
+    fn iter(&self) -> LogIterator<'_, Self::LogSize, T> {
+        self.log.iter()
+    }
+
+    fn index_of(&self, hash: &str) -> anyhow::Result<Option<Self::LogSize>> {
+        self.log.index_of(hash)
+    }
+
+    fn indices_of(&self, hash: &str) -> anyhow::Result<Vec<Self::LogSize>> {
+        self.log.indices_of(hash)
+    }
+
+    fn proofs<I>(
+        &self,
+        positions: I,
+    ) -> anyhow::Result<HashMap<LogTreePosition<Self::LogSize>, String>>
+    where
+        I: Iterator<Item = LogTreePosition<Self::LogSize>>,
+    {
+        self.log.proofs(positions)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::test_helpers::*;
+    use crate::*;
+
+    #[test]
+    fn dedup_memory_13() -> anyhow::Result<()> {
+        let mut ml = DedupLog::new(InMemoryLog::default());
+        test_13(&mut ml)?;
+        check_13(&ml)
+    }
+
+    #[test]
+    fn dedup_memory_properties() -> anyhow::Result<()> {
+        properties(|| Ok(DedupLog::new(InMemoryLog::<LogRecord>::default())))
+    }
+
+    #[test]
+    fn dedup_append() -> anyhow::Result<()> {
+        let mut ml = DedupLog::new(InMemoryLog::default());
+        append_multiple(&mut ml, 5)?;
+        let tree = ml.latest()?;
+        let first = ml.get_hash(0, 2)?.into_owned();
+        let record = ml.append(LogRecord::new("rec2"))?;
+        assert_eq!(2, record.id);
+        assert_eq!(first, record.hash);
+        assert_eq!(ml.get_meta(2)?, record.meta);
+        assert_eq!(tree, ml.latest()?);
+
+        let record = ml.append_hash_only(first)?;
+        assert_eq!(2, record.id);
+        assert_eq!(5, ml.size()?);
+
+        let records = ml.bulk_append(vec![
+            LogRecord::new("rec1"),
+            LogRecord::new("new"),
+            LogRecord::new("new"),
+        ])?;
+        assert_eq!(
+            vec![1, 5, 5],
+            records.iter().map(|r| r.id).collect::<Vec<_>>()
+        );
+        assert_eq!(6, ml.size()?);
+
+        // without deduplication, the same record is appended again
+        let mut ml = ml.into_inner();
+        assert_eq!(6, ml.append(LogRecord::new("new"))?.id);
+        Ok(())
+    }
+}
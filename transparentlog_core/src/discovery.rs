@@ -0,0 +1,387 @@
+//! # Checkpoint discovery
+//!
+//! Clients get proofs from the server of a log, but can fetch its latest signed checkpoint elsewhere: from a
+//! well-known HTTPS URL or a DNS TXT record of the domain of its origin, configured per log in a `CheckpointDiscovery`.
+//! A server showing a forked tree then also needs to control those places, and a client checks the checkpoints found
+//! in all of them are consistent with each other before following the latest one.
+//! The network calls are left to a `DiscoveryTransport`, so clients use the HTTP client and resolver they already have.
+use crate::base::*;
+use crate::checkpoint::*;
+use crate::witness::*;
+use num::Zero;
+use serde::{Deserialize, Serialize};
+use std::fmt::Display;
+use std::str::FromStr;
+use thiserror::Error;
+
+/// The prefix of the DNS name holding the checkpoints of the logs of a domain
+pub const DNS_PREFIX: &str = "_transparentlog-checkpoint";
+
+/// The maximum length of a character string of a TXT record
+pub const TXT_STRING_SIZE: usize = 255;
+
+/// Specific Errors for checkpoint discovery
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum DiscoveryError {
+    #[error("No checkpoint found for log {0}")]
+    NotFound(String),
+    #[error("Malformed TXT record: {0}")]
+    MalformedRecord(&'static str),
+}
+
+/// Fetches what discovery needs from the network
+pub trait DiscoveryTransport {
+    /// Get the body of the document at an HTTPS URL, none if there is no document there
+    fn get(&self, url: &str) -> anyhow::Result<Option<String>>;
+
+    /// Get the TXT records of a DNS name, each as the list of its character strings
+    fn txt(&self, name: &str) -> anyhow::Result<Vec<Vec<String>>>;
+}
+
+/// A place a signed checkpoint is published to
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckpointLocation {
+    /// A document holding the signed note, at an HTTPS URL
+    Https(String),
+    /// The TXT records of a DNS name, each holding the signed note of a log, base64 encoded
+    DnsTxt(String),
+}
+
+impl CheckpointLocation {
+    /// The well-known URL of the checkpoint of a log, on the host of its origin:
+    /// `https://<host>/.well-known/transparentlog/<origin>/checkpoint`
+    pub fn well_known_url(origin: &str) -> Self {
+        Self::Https(format!(
+            "https://{}/.well-known/transparentlog/{}/checkpoint",
+            origin_host(origin),
+            origin
+        ))
+    }
+
+    /// The DNS name of the checkpoints of the logs of the domain of an origin: `_transparentlog-checkpoint.<host>`
+    pub fn well_known_txt(origin: &str) -> Self {
+        Self::DnsTxt(format!("{}.{}", DNS_PREFIX, origin_host(origin)))
+    }
+
+    /// Fetch the signed notes published there, none if nothing is published
+    fn fetch<Tr: DiscoveryTransport>(&self, transport: &Tr) -> anyhow::Result<Vec<String>> {
+        match self {
+            Self::Https(url) => Ok(transport.get(url)?.into_iter().collect()),
+            Self::DnsTxt(name) => Ok(transport
+                .txt(name)?
+                .iter()
+                .map(|strings| note_from_txt(strings))
+                .collect::<Result<_, _>>()?),
+        }
+    }
+}
+
+/// The places the checkpoints of a log are published to
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CheckpointDiscovery {
+    /// The origin of the log
+    pub origin: String,
+    /// The places to fetch the checkpoints from
+    pub locations: Vec<CheckpointLocation>,
+}
+
+/// A signed checkpoint found by discovery
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiscoveredCheckpoint<LogSize> {
+    /// Where the checkpoint was found
+    pub location: CheckpointLocation,
+    /// The signed note
+    pub note: String,
+    /// The checkpoint
+    pub checkpoint: Checkpoint<LogSize>,
+}
+
+impl CheckpointDiscovery {
+    /// Discovery of the checkpoints of a log at the given places
+    pub fn new(origin: &str, locations: Vec<CheckpointLocation>) -> Self {
+        Self {
+            origin: origin.to_owned(),
+            locations,
+        }
+    }
+
+    /// Discovery of the checkpoints of a log at its well-known URL and DNS name
+    pub fn well_known(origin: &str) -> Self {
+        Self::new(
+            origin,
+            vec![
+                CheckpointLocation::well_known_url(origin),
+                CheckpointLocation::well_known_txt(origin),
+            ],
+        )
+    }
+
+    /// Fetch the checkpoints of the log from all the places, verifying their signatures, sorted by size
+    /// Places that cannot be reached are skipped, and notes of other logs ignored, but a note of the log
+    /// without a valid signature fails the discovery, as does finding no checkpoint at all
+    pub fn discover<LogSize, Tr>(
+        &self,
+        transport: &Tr,
+        verifiers: &[&dyn LogVerifier],
+    ) -> anyhow::Result<Vec<DiscoveredCheckpoint<LogSize>>>
+    where
+        LogSize: Display + FromStr + Zero + Ord + Copy,
+        Tr: DiscoveryTransport,
+    {
+        let mut found = vec![];
+        for location in self.locations.iter() {
+            let notes = match location.fetch(transport) {
+                Ok(notes) => notes,
+                Err(_) => continue,
+            };
+            for note in notes {
+                let (text, _) = parse_note(&note)?;
+                if Checkpoint::<LogSize>::parse(text)?.origin != self.origin {
+                    continue;
+                }
+                let checkpoint = Checkpoint::open(&note, verifiers)?;
+                found.push(DiscoveredCheckpoint {
+                    location: location.clone(),
+                    note,
+                    checkpoint,
+                });
+            }
+        }
+        if found.is_empty() {
+            return Err(DiscoveryError::NotFound(self.origin.clone()).into());
+        }
+        found.sort_by_key(|d| d.checkpoint.tree.size);
+        Ok(found)
+    }
+
+    /// Discover the checkpoints of the log and move the client through them, from the smallest to the latest,
+    /// checking each is consistent with the tree the client trusts using the proofs of the log
+    /// Returns false, leaving the client at the last consistent checkpoint, if a checkpoint is inconsistent,
+    /// and fails with an `OriginError::Mismatch` error if the client follows another log
+    pub fn follow<'a, T, K, TL, LC, Tr>(
+        &self,
+        client: &mut LC,
+        log: &TL,
+        transport: &Tr,
+        verifiers: &[&dyn LogVerifier],
+    ) -> anyhow::Result<bool>
+    where
+        T: Serialize + Deserialize<'a>,
+        TL: ProofSource<'a, T, K>,
+        TL::LogSize: Display + FromStr,
+        LC: LogClient<'a, T, TL, K>,
+        Tr: DiscoveryTransport,
+    {
+        let origin = client.latest().origin.clone().or_else(|| log.log_origin());
+        if origin.iter().any(|o| *o != self.origin) {
+            return Err(OriginError::Mismatch {
+                expected: origin,
+                found: Some(self.origin.clone()),
+            }
+            .into());
+        }
+        for discovered in self.discover::<TL::LogSize, _>(transport, verifiers)? {
+            let tree = LogTree {
+                origin: Some(discovered.checkpoint.origin),
+                ..discovered.checkpoint.tree
+            };
+            if !move_latest(client, log, tree)? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Discover the checkpoints of the log, keeping the ones whose signatures satisfy the witness policy
+    pub fn discover_witnessed<LogSize, Tr>(
+        &self,
+        transport: &Tr,
+        policy: &WitnessPolicy,
+    ) -> anyhow::Result<Vec<DiscoveredCheckpoint<LogSize>>>
+    where
+        LogSize: Display + FromStr + Zero + Ord + Copy,
+        Tr: DiscoveryTransport,
+    {
+        let verifiers = policy.verifiers();
+        let verifiers: Vec<&dyn LogVerifier> =
+            verifiers.iter().map(|v| *v as &dyn LogVerifier).collect();
+        let found: Vec<DiscoveredCheckpoint<LogSize>> = self
+            .discover(transport, &verifiers)?
+            .into_iter()
+            .filter(|d| policy.open::<LogSize>(&d.note).is_ok())
+            .collect();
+        if found.is_empty() {
+            return Err(DiscoveryError::NotFound(self.origin.clone()).into());
+        }
+        Ok(found)
+    }
+}
+
+/// The host of an origin: the part before the first slash
+pub fn origin_host(origin: &str) -> &str {
+    origin.split('/').next().unwrap_or(origin)
+}
+
+/// Encode a signed note as the character strings of a TXT record
+pub fn txt_record(note: &str) -> Vec<String> {
+    base64::encode(note)
+        .as_bytes()
+        .chunks(TXT_STRING_SIZE)
+        .map(|c| String::from_utf8_lossy(c).into_owned())
+        .collect()
+}
+
+/// Decode the signed note held by the character strings of a TXT record
+pub fn note_from_txt(strings: &[String]) -> Result<String, DiscoveryError> {
+    let encoded: String = strings.concat();
+    let bytes = base64::decode(encoded.trim())
+        .map_err(|_| DiscoveryError::MalformedRecord("invalid base64"))?;
+    String::from_utf8(bytes).map_err(|_| DiscoveryError::MalformedRecord("invalid note"))
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::test_helpers::*;
+    use crate::*;
+    use std::collections::HashMap;
+
+    /// Documents and TXT records served from memory, unknown names being unreachable
+    #[derive(Default)]
+    struct MemoryTransport {
+        documents: HashMap<String, String>,
+        records: HashMap<String, Vec<Vec<String>>>,
+    }
+
+    impl DiscoveryTransport for MemoryTransport {
+        fn get(&self, url: &str) -> anyhow::Result<Option<String>> {
+            Ok(self.documents.get(url).cloned())
+        }
+
+        fn txt(&self, name: &str) -> anyhow::Result<Vec<Vec<String>>> {
+            self.records
+                .get(name)
+                .cloned()
+                .ok_or_else(|| anyhow::anyhow!("NXDOMAIN {}", name))
+        }
+    }
+
+    #[test]
+    fn discovery_well_known() -> anyhow::Result<()> {
+        let signer = Ed25519Signer::new("example.com/log", &[1_u8; 32]);
+        let verifier = signer.verifier();
+        let mut ml = InMemoryLog::with_origin("example.com/log");
+        append_multiple(&mut ml, 5)?;
+        let mut client = InMemoryLogClientBuilder::new(&ml)?.build();
+        let old = Checkpoint::new("example.com/log", ml.latest()?).sign(&[&signer])?;
+        append_multiple_offset(&mut ml, 5, 8)?;
+        let new = Checkpoint::new("example.com/log", ml.latest()?).sign(&[&signer])?;
+
+        let discovery = CheckpointDiscovery::well_known("example.com/log");
+        assert_eq!(
+            vec![
+                CheckpointLocation::Https(
+                    "https://example.com/.well-known/transparentlog/example.com/log/checkpoint"
+                        .into()
+                ),
+                CheckpointLocation::DnsTxt("_transparentlog-checkpoint.example.com".into()),
+            ],
+            discovery.locations
+        );
+        let mut transport = MemoryTransport::default();
+        let err = discovery
+            .discover::<usize, _>(&transport, &[&verifier])
+            .unwrap_err();
+        assert_eq!(
+            Some(&DiscoveryError::NotFound("example.com/log".into())),
+            err.downcast_ref::<DiscoveryError>()
+        );
+
+        // the web server is behind the DNS record, which also holds the checkpoint of another log
+        let other = Checkpoint::new("example.com/other", ml.latest()?).sign(&[&signer])?;
+        transport.documents.insert(
+            "https://example.com/.well-known/transparentlog/example.com/log/checkpoint".into(),
+            old.clone(),
+        );
+        transport.records.insert(
+            "_transparentlog-checkpoint.example.com".into(),
+            vec![txt_record(&other), txt_record(&new)],
+        );
+        assert!(txt_record(&new).iter().all(|s| s.len() <= TXT_STRING_SIZE));
+        let found = discovery.discover::<usize, _>(&transport, &[&verifier])?;
+        assert_eq!(
+            vec![5, 13],
+            found
+                .iter()
+                .map(|d| d.checkpoint.tree.size)
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(new, found[1].note);
+        assert!(discovery.follow(&mut client, &ml, &transport, &[&verifier])?);
+        assert_eq!(&ml.latest()?, client.latest());
+
+        // a fork served by the web server
+        let mut fork = InMemoryLog::with_origin("example.com/log");
+        append_multiple_offset(&mut fork, 1, 13)?;
+        let forked = Checkpoint::new("example.com/log", fork.latest()?).sign(&[&signer])?;
+        transport.documents.insert(
+            "https://example.com/.well-known/transparentlog/example.com/log/checkpoint".into(),
+            forked,
+        );
+        assert!(!discovery.follow(&mut client, &ml, &transport, &[&verifier])?);
+
+        // only the checkpoint cosigned by a witness satisfies the policy
+        let witness = Ed25519Signer::new("witness", &[3_u8; 32]);
+        transport.records.insert(
+            "_transparentlog-checkpoint.example.com".into(),
+            vec![txt_record(&cosign_note(&new, &[&witness])?)],
+        );
+        let policy = WitnessPolicy::all(vec![
+            WitnessPolicy::Key(verifier.clone()),
+            WitnessPolicy::Key(witness.verifier()),
+        ]);
+        let found = discovery.discover_witnessed::<usize, _>(&transport, &policy)?;
+        assert_eq!(1, found.len());
+        assert_eq!(
+            CheckpointLocation::DnsTxt("_transparentlog-checkpoint.example.com".into()),
+            found[0].location
+        );
+
+        // a checkpoint signed by another key of the log
+        let intruder = Ed25519Signer::new("example.com/log", &[2_u8; 32]);
+        let forged = Checkpoint::new("example.com/log", fork.latest()?).sign(&[&intruder])?;
+        transport.records.insert(
+            "_transparentlog-checkpoint.example.com".into(),
+            vec![txt_record(&forged)],
+        );
+        assert!(discovery
+            .discover::<usize, _>(&transport, &[&verifier])
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn discovery_txt_record() -> anyhow::Result<()> {
+        let signer = Ed25519Signer::new("example.com/log", &[1_u8; 32]);
+        let rotated = Ed25519Signer::new("example.com/log", &[2_u8; 32]);
+        let mut ml = InMemoryLog::with_origin("example.com/log");
+        append_multiple(&mut ml, 13)?;
+        let note = Checkpoint::new("example.com/log", ml.latest()?).sign(&[&signer, &rotated])?;
+        let record = txt_record(&note);
+        assert!(record.len() > 1);
+        assert_eq!(note, note_from_txt(&record)?);
+        assert_eq!(
+            Err(DiscoveryError::MalformedRecord("invalid base64")),
+            note_from_txt(&["not base64!".to_owned()])
+        );
+        assert_eq!("example.com", origin_host("example.com/log"));
+        assert_eq!("example.com", origin_host("example.com"));
+
+        let discovery = CheckpointDiscovery::well_known("example.com/log");
+        let json = serde_json::to_string(&discovery)?;
+        assert!(json.contains("{\"dns_txt\":\"_transparentlog-checkpoint.example.com\"}"));
+        assert_eq!(discovery, serde_json::from_str(&json)?);
+        Ok(())
+    }
+}
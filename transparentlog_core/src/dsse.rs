@@ -0,0 +1,610 @@
+//! # DSSE envelopes
+//!
+//! Logging of statements signed in Dead Simple Signing Envelopes, as used by in-toto and sigstore:
+//! `SignedEnvelope` records, an `EnvelopeLog` checking their signatures on append,
+//! and in-toto attestations of the inclusion of a record, carrying its proof bundle.
+//! Envelopes are signed over the pre-authentication encoding (PAE) of their payload type and payload,
+//! with the same signers and verifiers as notes, the key id of a signature being the name and hash of its key.
+//!
+//! See <https://github.com/secure-systems-lab/dsse>
+use crate::base::*;
+use crate::bundle::Bundle;
+use crate::checkpoint::{key_id, LogSigner, LogVerifier};
+use crate::json::ProofHash;
+use crate::leaf::Leaf;
+use maybe_owned::MaybeOwned;
+use num::Integer;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Display;
+use std::hash::Hash;
+use std::str::FromStr;
+use thiserror::Error;
+
+/// The payload type of in-toto statements
+pub const IN_TOTO_PAYLOAD_TYPE: &str = "application/vnd.in-toto+json";
+
+/// The type of in-toto statements
+pub const IN_TOTO_STATEMENT_TYPE: &str = "https://in-toto.io/Statement/v1";
+
+/// The predicate type of inclusion attestations
+pub const INCLUSION_PREDICATE_TYPE: &str =
+    "https://github.com/JPMoresmau/transparentlog/inclusion/v1";
+
+/// Specific Errors for envelopes
+#[derive(Debug, Clone, Error)]
+pub enum DsseError {
+    #[error("No valid envelope signature from a known key")]
+    Unverified,
+    #[error("Unexpected payload type: {0}")]
+    PayloadType(String),
+    #[error("Unexpected predicate type: {0}")]
+    PredicateType(String),
+    #[error("Attestation subject does not match its record")]
+    SubjectMismatch,
+}
+
+/// A signature of an envelope
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct EnvelopeSignature {
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub keyid: String,
+    #[serde(with = "base64_bytes")]
+    pub sig: Vec<u8>,
+}
+
+/// A DSSE envelope: a typed payload and its signatures, serialized with the field names of the JSON format
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[serde(rename_all = "camelCase")]
+pub struct SignedEnvelope {
+    pub payload_type: String,
+    #[serde(with = "base64_bytes")]
+    pub payload: Vec<u8>,
+    pub signatures: Vec<EnvelopeSignature>,
+}
+
+/// Payloads and signatures as base64 strings, like in the JSON format
+pub(crate) mod base64_bytes {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&base64::encode(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        base64::decode(String::deserialize(deserializer)?).map_err(D::Error::custom)
+    }
+}
+
+/// The pre-authentication encoding of a payload, the message signed
+pub fn pae(payload_type: &str, payload: &[u8]) -> Vec<u8> {
+    let mut b = format!(
+        "DSSEv1 {} {} {} ",
+        payload_type.len(),
+        payload_type,
+        payload.len()
+    )
+    .into_bytes();
+    b.extend_from_slice(payload);
+    b
+}
+
+impl SignedEnvelope {
+    /// Sign a payload of the given type with all the given signers
+    pub fn sign(
+        payload_type: &str,
+        payload: Vec<u8>,
+        signers: &[&dyn LogSigner],
+    ) -> anyhow::Result<Self> {
+        let msg = pae(payload_type, &payload);
+        let signatures = signers
+            .iter()
+            .map(|s| {
+                Ok(EnvelopeSignature {
+                    keyid: key_id(s.name(), s.key_hash()),
+                    sig: s.sign(&msg)?,
+                })
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        Ok(Self {
+            payload_type: payload_type.to_owned(),
+            payload,
+            signatures,
+        })
+    }
+
+    /// Verify the signatures, returning the names of the keys with a valid signature, at least one
+    /// A signature without key id is checked against all the verifiers, and one with the name of a key only against
+    /// all the verifiers of that name, as envelopes were signed before their key ids had the hash of their key
+    pub fn verify(&self, verifiers: &[&dyn LogVerifier]) -> Result<Vec<String>, DsseError> {
+        let msg = pae(&self.payload_type, &self.payload);
+        let mut names = vec![];
+        for s in self.signatures.iter() {
+            for v in verifiers.iter() {
+                if (s.keyid.is_empty()
+                    || s.keyid == v.name()
+                    || s.keyid == key_id(v.name(), v.key_hash()))
+                    && v.verify(&msg, &s.sig)
+                    && !names.iter().any(|n| n == v.name())
+                {
+                    names.push(v.name().to_owned());
+                }
+            }
+        }
+        if names.is_empty() {
+            return Err(DsseError::Unverified);
+        }
+        Ok(names)
+    }
+}
+
+/// The canonical bytes of an envelope are its JSON encoding
+impl Leaf for SignedEnvelope {
+    fn leaf_bytes(&self) -> Cow<'_, [u8]> {
+        // only strings: the encoding cannot fail
+        Cow::Owned(serde_json::to_vec(self).unwrap_or_default())
+    }
+}
+
+/// A log of envelopes, refusing the envelopes without a valid signature from one of its verifiers
+/// Without verifiers, envelopes are logged unchecked
+pub struct EnvelopeLog<L> {
+    log: L,
+    verifiers: Vec<Box<dyn LogVerifier + Send + Sync>>,
+}
+
+impl<L> EnvelopeLog<L> {
+    /// Wrap a log, logging envelopes unchecked until verifiers are added
+    pub fn new(log: L) -> Self {
+        Self {
+            log,
+            verifiers: vec![],
+        }
+    }
+
+    /// Add a verifier of the envelopes appended
+    pub fn verifier(&mut self, verifier: Box<dyn LogVerifier + Send + Sync>) -> &mut Self {
+        self.verifiers.push(verifier);
+        self
+    }
+
+    /// The wrapped log
+    pub fn inner(&self) -> &L {
+        &self.log
+    }
+
+    /// Get back the wrapped log
+    pub fn into_inner(self) -> L {
+        self.log
+    }
+
+    /// Check an envelope is signed by one of the verifiers, if any
+    fn check(&self, envelope: &SignedEnvelope) -> Result<(), DsseError> {
+        if self.verifiers.is_empty() {
+            return Ok(());
+        }
+        let verifiers: Vec<&dyn LogVerifier> = self
+            .verifiers
+            .iter()
+            .map(|v| v.as_ref() as &dyn LogVerifier)
+            .collect();
+        envelope.verify(&verifiers).map(|_| ())
+    }
+}
+
+/// Envelopes are checked when added or appended, hashes appended without the envelope cannot be
+impl<'a, L> TransparentLog<'a, SignedEnvelope> for EnvelopeLog<L>
+where
+    L: TransparentLog<'a, SignedEnvelope>,
+{
+    type LogSize = L::LogSize;
+
+    fn add(&mut self, record: SignedEnvelope) -> anyhow::Result<Self::LogSize> {
+        self.check(&record)?;
+        self.log.add(record)
+    }
+
+    fn add_tombstone(&mut self) -> anyhow::Result<Self::LogSize> {
+        self.log.add_tombstone()
+    }
+
+    fn redact(&mut self, id: Self::LogSize) -> anyhow::Result<()> {
+        self.log.redact(id)
+    }
+
+    fn expire(&mut self, id: Self::LogSize) -> anyhow::Result<()> {
+        self.log.expire(id)
+    }
+
+    fn expired(&self, id: Self::LogSize) -> anyhow::Result<bool> {
+        self.log.expired(id)
+    }
+
+    fn seal(&mut self) -> anyhow::Result<LogTree<Self::LogSize>> {
+        self.log.seal()
+    }
+
+    fn sealed(&self) -> anyhow::Result<Option<LogTree<Self::LogSize>>> {
+        self.log.sealed()
+    }
+
+    fn sync(&self) -> anyhow::Result<()> {
+        self.log.sync()
+    }
+
+    fn add_meta(&mut self, id: Self::LogSize, meta: RecordMeta) -> anyhow::Result<()> {
+        self.log.add_meta(id, meta)
+    }
+
+    fn get_meta(&self, id: Self::LogSize) -> anyhow::Result<Option<RecordMeta>> {
+        self.log.get_meta(id)
+    }
+
+    fn set_annotation(&mut self, id: Self::LogSize, annotation: Vec<u8>) -> anyhow::Result<()> {
+        self.log.set_annotation(id, annotation)
+    }
+
+    fn get_annotation(&self, id: Self::LogSize) -> anyhow::Result<Option<Vec<u8>>> {
+        self.log.get_annotation(id)
+    }
+
+    fn find_since(&self, timestamp: u64) -> anyhow::Result<Self::LogSize> {
+        self.log.find_since(timestamp)
+    }
+
+    fn add_hash(&mut self, level: LogHeight, hash: String) -> anyhow::Result<Self::LogSize> {
+        self.log.add_hash(level, hash)
+    }
+
+    fn get_hash(
+        &self,
+        level: LogHeight,
+        index: Self::LogSize,
+    ) -> anyhow::Result<MaybeOwned<'_, String>> {
+        self.log.get_hash(level, index)
+    }
+
+    fn append(&mut self, record: SignedEnvelope) -> anyhow::Result<Record<Self::LogSize>> {
+        self.check(&record)?;
+        self.log.append(record)
+    }
+
+    /// No envelope is appended if one of them is not signed
+    fn bulk_append(
+        &mut self,
+        records: Vec<SignedEnvelope>,
+    ) -> anyhow::Result<Vec<Record<Self::LogSize>>> {
+        for record in records.iter() {
+            self.check(record)?;
+        }
+        self.log.bulk_append(records)
+    }
+
+    fn size(&self) -> anyhow::Result<Self::LogSize> {
+        self.log.size()
+    }
+
+    fn origin(&self) -> Option<String> {
+        self.log.origin()
+    }
+
+    fn latest(&self) -> anyhow::Result<LogTree<Self::LogSize>> {
+        self.log.latest()
+    }
+
+    fn leaf_hasher(&self) -> LeafHasher<SignedEnvelope> {
+        self.log.leaf_hasher()
+    }
+
+    fn get(&self, index: Self::LogSize) -> anyhow::Result<Option<MaybeOwned<'_, SignedEnvelope>>> {
+        self.log.get(index)
+    }
+
+    fn get_raw_bytes(&self, index: Self::LogSize) -> anyhow::Result<Option<Vec<u8>>> {
+        self.log.get_raw_bytes(index)
+    }
+
+    fn get_leaf_bytes(&self, index: Self::LogSize) -> anyhow::Result<Option<Vec<u8>>> {
+        self.log.get_leaf_bytes(index)
+    }
+
+    fn get_range(
+        &self,
+        start: Self::LogSize,
+        end: Self::LogSize,
+    ) -> anyhow::Result<Vec<MaybeOwned<'_, SignedEnvelope>>> {
+        self.log.get_range(start, end)
+    }
+
+    fn iter(&self) -> LogIterator<'_, Self::LogSize, SignedEnvelope> {
+        self.log.iter()
+    }
+
+    fn index_of(&self, hash: &str) -> anyhow::Result<Option<Self::LogSize>> {
+        self.log.index_of(hash)
+    }
+
+    fn indices_of(&self, hash: &str) -> anyhow::Result<Vec<Self::LogSize>> {
+        self.log.indices_of(hash)
+    }
+
+    fn proofs<I>(
+        &self,
+        positions: I,
+    ) -> anyhow::Result<HashMap<LogTreePosition<Self::LogSize>, String>>
+    where
+        I: Iterator<Item = LogTreePosition<Self::LogSize>>,
+    {
+        self.log.proofs(positions)
+    }
+}
+
+/// The subject of an in-toto statement
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Subject {
+    pub name: String,
+    pub digest: BTreeMap<String, String>,
+}
+
+/// An in-toto statement
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct Statement<P> {
+    #[serde(rename = "_type")]
+    pub statement_type: String,
+    pub subject: Vec<Subject>,
+    pub predicate_type: String,
+    pub predicate: P,
+}
+
+/// The predicate of an inclusion attestation: the content of the proof bundle of a record
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct InclusionPredicate<LogSize> {
+    pub log_index: LogSize,
+    pub leaf_hash: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<u64>,
+    pub tree_size: LogSize,
+    pub root_hash: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub origin: Option<String>,
+    pub hashes: Vec<ProofHash<LogSize>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub checkpoint: Option<String>,
+}
+
+impl<LogSize> Bundle<LogSize>
+where
+    LogSize: Integer + Copy + Hash + Display + FromStr + Serialize + DeserializeOwned,
+{
+    /// The in-toto statement of the inclusion of the bundle record, its subject being the leaf hash of the record
+    pub fn statement(&self) -> Statement<InclusionPredicate<LogSize>> {
+        let mut hashes: Vec<_> = self
+            .proof
+            .proofs
+            .iter()
+            .map(|(p, h)| ProofHash {
+                level: p.level,
+                index: p.index,
+                hash: h.clone(),
+            })
+            .collect();
+        hashes.sort();
+        let record = &self.proof.record;
+        Statement {
+            statement_type: IN_TOTO_STATEMENT_TYPE.to_owned(),
+            subject: vec![Subject {
+                name: record.id.to_string(),
+                digest: BTreeMap::from([("sha256".to_owned(), record.hash.clone())]),
+            }],
+            predicate_type: INCLUSION_PREDICATE_TYPE.to_owned(),
+            predicate: InclusionPredicate {
+                log_index: record.id,
+                leaf_hash: record.hash.clone(),
+                timestamp: record.meta.map(|m| m.timestamp),
+                tree_size: self.tree.size,
+                root_hash: self.tree.hash.clone(),
+                origin: self.tree.origin.clone(),
+                hashes,
+                checkpoint: self.checkpoint.clone(),
+            },
+        }
+    }
+
+    /// Sign the in-toto statement of the bundle in an envelope
+    pub fn attest(&self, signers: &[&dyn LogSigner]) -> anyhow::Result<SignedEnvelope> {
+        SignedEnvelope::sign(
+            IN_TOTO_PAYLOAD_TYPE,
+            serde_json::to_vec(&self.statement())?,
+            signers,
+        )
+    }
+
+    /// Get the bundle back from an attestation, verifying the envelope signature if verifiers are given
+    /// The bundle is not verified: `verify` checks its proof and its checkpoint
+    pub fn from_attestation(
+        envelope: &SignedEnvelope,
+        verifiers: &[&dyn LogVerifier],
+    ) -> anyhow::Result<Self> {
+        if !verifiers.is_empty() {
+            envelope.verify(verifiers)?;
+        }
+        if envelope.payload_type != IN_TOTO_PAYLOAD_TYPE {
+            return Err(DsseError::PayloadType(envelope.payload_type.clone()).into());
+        }
+        let statement: Statement<InclusionPredicate<LogSize>> =
+            serde_json::from_slice(&envelope.payload)?;
+        if statement.predicate_type != INCLUSION_PREDICATE_TYPE {
+            return Err(DsseError::PredicateType(statement.predicate_type).into());
+        }
+        let predicate = statement.predicate;
+        if !statement.subject.iter().any(|s| {
+            s.digest.get("sha256") == Some(&predicate.leaf_hash)
+                && s.name == predicate.log_index.to_string()
+        }) {
+            return Err(DsseError::SubjectMismatch.into());
+        }
+        Ok(Self {
+            proof: InclusionProof {
+                record: Record {
+                    id: predicate.log_index,
+                    hash: predicate.leaf_hash,
+                    meta: predicate
+                        .timestamp
+                        .map(|timestamp| RecordMeta { timestamp }),
+                },
+                proofs: predicate
+                    .hashes
+                    .into_iter()
+                    .map(|p| {
+                        (
+                            LogTreePosition {
+                                level: p.level,
+                                index: p.index,
+                            },
+                            p.hash,
+                        )
+                    })
+                    .collect(),
+            },
+            tree: LogTree {
+                size: predicate.tree_size,
+                hash: predicate.root_hash,
+                origin: predicate.origin,
+            },
+            checkpoint: predicate.checkpoint,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::*;
+
+    fn statement(i: u8) -> anyhow::Result<SignedEnvelope> {
+        let signer = Ed25519Signer::new("builder", &[1_u8; 32]);
+        SignedEnvelope::sign(
+            IN_TOTO_PAYLOAD_TYPE,
+            format!("{{\"artifact\":{}}}", i).into_bytes(),
+            &[&signer],
+        )
+    }
+
+    #[test]
+    fn dsse_pae() {
+        // the example of the DSSE protocol
+        assert_eq!(
+            b"DSSEv1 29 http://example.com/HelloWorld 11 hello world".to_vec(),
+            pae("http://example.com/HelloWorld", b"hello world")
+        );
+    }
+
+    #[test]
+    fn dsse_sign_verify() -> anyhow::Result<()> {
+        let signer = Ed25519Signer::new("builder", &[1_u8; 32]);
+        let other = Ed25519Signer::new("other", &[2_u8; 32]);
+        let envelope = statement(1)?;
+        assert_eq!(
+            vec!["builder".to_owned()],
+            envelope.verify(&[&other.verifier(), &signer.verifier()])?
+        );
+        assert!(matches!(
+            envelope.verify(&[&other.verifier()]),
+            Err(DsseError::Unverified)
+        ));
+
+        // the JSON format
+        let json = serde_json::to_string(&envelope)?;
+        assert!(json.starts_with(&format!(
+            "{{\"payloadType\":\"{}\",\"payload\":\"{}\",\"signatures\":[{{\"keyid\":\"builder+{:08x}\",\"sig\":\"",
+            IN_TOTO_PAYLOAD_TYPE,
+            base64::encode(&envelope.payload),
+            signer.key_hash()
+        )));
+        assert_eq!(envelope, serde_json::from_str(&json)?);
+        assert_eq!(json.as_bytes(), &*envelope.leaf_bytes());
+
+        let mut tampered = envelope.clone();
+        tampered.payload_type = "text/plain".into();
+        assert!(tampered.verify(&[&signer.verifier()]).is_err());
+        let mut named = envelope.clone();
+        named.signatures[0].keyid = "builder".into();
+        assert!(named.verify(&[&signer.verifier()]).is_ok());
+        let mut rotated = envelope.clone();
+        rotated.signatures[0].keyid = key_id("builder", other.key_hash());
+        assert!(rotated.verify(&[&signer.verifier()]).is_err());
+        let mut anonymous = envelope;
+        anonymous.signatures[0].keyid = String::new();
+        assert!(anonymous.verify(&[&signer.verifier()]).is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn dsse_envelope_log() -> anyhow::Result<()> {
+        let signer = Ed25519Signer::new("builder", &[1_u8; 32]);
+        let mut ml = EnvelopeLog::new(InMemoryLog::default());
+        ml.verifier(Box::new(signer.verifier()));
+        let record = ml.append(statement(0)?)?;
+        assert_eq!(hash(&statement(0)?)?, record.hash);
+        ml.bulk_append(vec![statement(1)?, statement(2)?])?;
+
+        let unsigned = SignedEnvelope::sign(IN_TOTO_PAYLOAD_TYPE, b"{}".to_vec(), &[])?;
+        let err = ml.append(unsigned.clone()).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<DsseError>(),
+            Some(DsseError::Unverified)
+        ));
+        assert!(ml
+            .bulk_append(vec![statement(3)?, unsigned.clone()])
+            .is_err());
+        assert_eq!(3, ml.size()?);
+
+        // hashed via their JSON encoding
+        let mut ml = LeafLog::new(EnvelopeLog::new(InMemoryLog::default()));
+        let record = ml.append(unsigned)?;
+        assert_eq!(hash_leaf(&ml.get(0)?.unwrap().into_owned()), record.hash);
+        Ok(())
+    }
+
+    #[test]
+    fn dsse_attestation() -> anyhow::Result<()> {
+        let log_signer = Ed25519Signer::new("example.com/log", &[3_u8; 32]);
+        let mut ml = EnvelopeLog::new(InMemoryLog::default());
+        for i in 0..13 {
+            ml.append(statement(i)?)?;
+        }
+        let bundle = Bundle::prove(&ml, 9, "example.com/log", &[&log_signer])?;
+        let attestation = bundle.attest(&[&log_signer])?;
+        assert_eq!(IN_TOTO_PAYLOAD_TYPE, attestation.payload_type);
+
+        let opened = Bundle::from_attestation(&attestation, &[&log_signer.verifier()])?;
+        assert_eq!(bundle, opened);
+        opened.verify(&[&log_signer.verifier()])?;
+        let statement = opened.statement();
+        assert_eq!(
+            Some(&hash(&ml.get(9)?.unwrap().into_owned())?),
+            statement.subject[0].digest.get("sha256")
+        );
+
+        let other = Ed25519Signer::new("other", &[4_u8; 32]).verifier();
+        assert!(Bundle::<usize>::from_attestation(&attestation, &[&other]).is_err());
+
+        // a forged statement, signed by the log but for another record
+        let mut statement = bundle.statement();
+        statement.predicate.log_index = 8;
+        let forged = SignedEnvelope::sign(
+            IN_TOTO_PAYLOAD_TYPE,
+            serde_json::to_vec(&statement)?,
+            &[&log_signer],
+        )?;
+        let err = Bundle::<usize>::from_attestation(&forged, &[]).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<DsseError>(),
+            Some(DsseError::SubjectMismatch)
+        ));
+        Ok(())
+    }
+}
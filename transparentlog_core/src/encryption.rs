@@ -0,0 +1,666 @@
+//! # Encryption at rest
+//!
+//! A log wrapper encrypting each serialized record with an AEAD cipher before handing it to the inner log.
+//! The tree can hash either the plaintext records, so verification is the same as for an unencrypted log,
+//! or the sealed records, so the log can be audited by parties without the key.
+use crate::base::*;
+use crate::tile::Tile;
+use crypto::aead::{AeadDecryptor, AeadEncryptor};
+use crypto::aes::KeySize;
+use crypto::aes_gcm::AesGcm;
+use maybe_owned::MaybeOwned;
+use num::One;
+use rand::{OsRng, Rng};
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+use serde::de::{DeserializeOwned, SeqAccess, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::HashMap;
+use std::fmt::{self, Debug, Formatter};
+use std::marker::PhantomData;
+use thiserror::Error;
+
+/// The size of an AES-GCM nonce, in bytes
+const NONCE_SIZE: usize = 12;
+
+/// The size of an AES-GCM authentication tag, in bytes
+const TAG_SIZE: usize = 16;
+
+/// An authenticated cipher of serialized records
+/// The associated data binds a sealed record to its position in the log
+pub trait RecordCipher: Debug + Send + Sync {
+    /// Encrypt and authenticate the plaintext with the associated data
+    fn seal(&self, aad: &[u8], plaintext: &[u8]) -> anyhow::Result<Vec<u8>>;
+
+    /// Check and decrypt bytes sealed by `seal` with the same associated data
+    fn open(&self, aad: &[u8], sealed: &[u8]) -> anyhow::Result<Vec<u8>>;
+}
+
+/// Errors decrypting records
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum EncryptionError {
+    #[error("sealed record too short: {0} bytes")]
+    Truncated(usize),
+    #[error("sealed record does not authenticate: wrong key, or tampered with")]
+    Authentication,
+    #[error("records are hashed sealed: their leaf hashes cannot be computed from the plaintext")]
+    HashedCiphertext,
+}
+
+/// AES-256-GCM cipher, with a random nonce stored before each sealed record
+pub struct AesGcmCipher {
+    key: [u8; 32],
+}
+
+impl AesGcmCipher {
+    /// A cipher with the given 256 bits key
+    pub fn new(key: [u8; 32]) -> Self {
+        Self { key }
+    }
+}
+
+/// Never shows the key
+impl Debug for AesGcmCipher {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AesGcmCipher").finish_non_exhaustive()
+    }
+}
+
+impl RecordCipher for AesGcmCipher {
+    /// The sealed bytes are the nonce, the ciphertext and the tag
+    fn seal(&self, aad: &[u8], plaintext: &[u8]) -> anyhow::Result<Vec<u8>> {
+        let mut sealed = vec![0; NONCE_SIZE + plaintext.len() + TAG_SIZE];
+        let (nonce, rest) = sealed.split_at_mut(NONCE_SIZE);
+        OsRng::new()?.fill_bytes(nonce);
+        let (ciphertext, tag) = rest.split_at_mut(plaintext.len());
+        AesGcm::new(KeySize::KeySize256, &self.key, nonce, aad).encrypt(plaintext, ciphertext, tag);
+        Ok(sealed)
+    }
+
+    fn open(&self, aad: &[u8], sealed: &[u8]) -> anyhow::Result<Vec<u8>> {
+        if sealed.len() < NONCE_SIZE + TAG_SIZE {
+            return Err(EncryptionError::Truncated(sealed.len()).into());
+        }
+        let (nonce, rest) = sealed.split_at(NONCE_SIZE);
+        let (ciphertext, tag) = rest.split_at(rest.len() - TAG_SIZE);
+        let mut plaintext = vec![0; ciphertext.len()];
+        if AesGcm::new(KeySize::KeySize256, &self.key, nonce, aad).decrypt(
+            ciphertext,
+            &mut plaintext,
+            tag,
+        ) {
+            Ok(plaintext)
+        } else {
+            Err(EncryptionError::Authentication.into())
+        }
+    }
+}
+
+/// A sealed record, as stored by the inner log of an encrypted log
+/// Serialized as a byte string, not as a sequence of numbers
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sealed(pub Vec<u8>);
+
+impl Serialize for Sealed {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(&self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for Sealed {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_byte_buf(SealedVisitor)
+    }
+}
+
+/// Accepts byte strings, and sequences of bytes from formats without them
+struct SealedVisitor;
+
+impl<'de> Visitor<'de> for SealedVisitor {
+    type Value = Sealed;
+
+    fn expecting(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str("sealed record bytes")
+    }
+
+    fn visit_bytes<E>(self, v: &[u8]) -> Result<Sealed, E> {
+        Ok(Sealed(v.to_vec()))
+    }
+
+    fn visit_byte_buf<E>(self, v: Vec<u8>) -> Result<Sealed, E> {
+        Ok(Sealed(v))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Sealed, A::Error> {
+        let mut v = Vec::with_capacity(seq.size_hint().unwrap_or_default());
+        while let Some(b) = seq.next_element()? {
+            v.push(b);
+        }
+        Ok(Sealed(v))
+    }
+}
+
+/// What the tree of an encrypted log hashes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashedBytes {
+    /// The serialized plaintext record, as an unencrypted log would
+    Plaintext,
+    /// The sealed record stored by the inner log
+    Ciphertext,
+}
+
+/// A log encrypting its records, storing them sealed in an inner log along with the hashes and metadata
+/// Sizes, proofs and lookups by hash go straight to the inner log, only reads need the key
+#[derive(Debug)]
+pub struct EncryptedLog<T, L> {
+    log: L,
+    cipher: Box<dyn RecordCipher>,
+    hashed: HashedBytes,
+    _marker: PhantomData<T>,
+}
+
+impl<'a, T, L> EncryptedLog<T, L>
+where
+    T: Serialize,
+    L: TransparentLog<'a, Sealed>,
+    L::LogSize: Serialize,
+{
+    /// Create an encrypted log over the inner log, hashing the plaintext records
+    pub fn new(log: L, cipher: Box<dyn RecordCipher>) -> Self {
+        Self {
+            log,
+            cipher,
+            hashed: HashedBytes::Plaintext,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Hash the sealed records instead of the plaintext ones
+    /// This must always be set for a given log, and its export can then not be imported into another log
+    pub fn hash_ciphertext(&mut self) -> &mut Self {
+        self.hashed = HashedBytes::Ciphertext;
+        self
+    }
+
+    /// What the tree hashes
+    pub fn hashed(&self) -> HashedBytes {
+        self.hashed
+    }
+
+    /// The inner log, holding the sealed records
+    pub fn inner(&self) -> &L {
+        &self.log
+    }
+
+    /// Get back the inner log
+    pub fn into_inner(self) -> L {
+        self.log
+    }
+
+    /// Seal a record to be stored at the given index
+    fn seal_record(&self, id: L::LogSize, record: &T) -> anyhow::Result<Sealed> {
+        let aad = rmp_serde::to_vec(&id)?;
+        Ok(Sealed(self.cipher.seal(&aad, &rmp_serde::to_vec(record)?)?))
+    }
+}
+
+/// The leaf hash of a record and its sealed version
+fn leaf_hash<T: Serialize>(
+    hashed: HashedBytes,
+    record: &T,
+    sealed: &Sealed,
+) -> anyhow::Result<String> {
+    match hashed {
+        HashedBytes::Plaintext => hash(record),
+        HashedBytes::Ciphertext => hash(sealed),
+    }
+}
+
+/// Open a sealed record stored at the given index
+fn open<T, LogSize>(cipher: &dyn RecordCipher, id: LogSize, sealed: &Sealed) -> anyhow::Result<T>
+where
+    T: DeserializeOwned,
+    LogSize: Serialize,
+{
+    Ok(rmp_serde::from_slice(&open_bytes(cipher, id, sealed)?)?)
+}
+
+/// Open a sealed record stored at the given index, as its MessagePack serialization
+fn open_bytes<LogSize: Serialize>(
+    cipher: &dyn RecordCipher,
+    id: LogSize,
+    sealed: &Sealed,
+) -> anyhow::Result<Vec<u8>> {
+    let aad = rmp_serde::to_vec(&id)?;
+    cipher.open(&aad, &sealed.0)
+}
+
+impl<'a, T, L> TransparentLog<'a, T> for EncryptedLog<T, L>
+where
+    T: Serialize + DeserializeOwned,
+    L: TransparentLog<'a, Sealed>,
+    L::LogSize: Serialize,
+{
+    type LogSize = L::LogSize;
+
+    fn size(&self) -> anyhow::Result<Self::LogSize> {
+        self.log.size()
+    }
+
+    fn origin(&self) -> Option<String> {
+        self.log.origin()
+    }
+
+    fn get(&self, index: Self::LogSize) -> anyhow::Result<Option<MaybeOwned<'_, T>>> {
+        self.log
+            .get(index)?
+            .map(|sealed| {
+                Ok(MaybeOwned::Owned(open(
+                    self.cipher.as_ref(),
+                    index,
+                    &sealed,
+                )?))
+            })
+            .transpose()
+    }
+
+    /// The plaintext record, serialized as MessagePack
+    fn get_raw_bytes(&self, index: Self::LogSize) -> anyhow::Result<Option<Vec<u8>>> {
+        self.log
+            .get(index)?
+            .map(|sealed| open_bytes(self.cipher.as_ref(), index, &sealed))
+            .transpose()
+    }
+
+    /// The plaintext record, or the sealed record if it is hashed instead
+    fn get_leaf_bytes(&self, index: Self::LogSize) -> anyhow::Result<Option<Vec<u8>>> {
+        match self.hashed {
+            HashedBytes::Plaintext => self.get_raw_bytes(index),
+            HashedBytes::Ciphertext => self
+                .log
+                .get(index)?
+                .map(|sealed| Ok(rmp_serde::to_vec(sealed.as_ref())?))
+                .transpose(),
+        }
+    }
+
+    fn get_range(
+        &self,
+        start: Self::LogSize,
+        end: Self::LogSize,
+    ) -> anyhow::Result<Vec<MaybeOwned<'_, T>>> {
+        let mut id = start;
+        let mut v = vec![];
+        for sealed in self.log.get_range(start, end)? {
+            v.push(MaybeOwned::Owned(open(self.cipher.as_ref(), id, &sealed)?));
+            id = id + Self::LogSize::one();
+        }
+        Ok(v)
+    }
+
+    fn iter(&self) -> LogIterator<'_, Self::LogSize, T> {
+        let cipher = self.cipher.as_ref();
+        Box::new(self.log.iter().map(move |r| {
+            let (id, sealed) = r?;
+            Ok((id, MaybeOwned::Owned(open(cipher, id, &sealed)?)))
+        }))
+    }
+
+    /// The plaintext records are hashed as MessagePack, sealed records cannot be hashed without their nonce,
+    /// so the hasher fails with an `EncryptionError::HashedCiphertext` error
+    fn leaf_hasher(&self) -> LeafHasher<T> {
+        match self.hashed {
+            HashedBytes::Plaintext => hash,
+            HashedBytes::Ciphertext => |_| Err(EncryptionError::HashedCiphertext.into()),
+        }
+    }
+
+    /// Seals the record with its index
+    fn add(&mut self, record: T) -> anyhow::Result<Self::LogSize> {
+        let sealed = self.seal_record(self.log.size()?, &record)?;
+        self.log.add(sealed)
+    }
+
+    /// Hashes the plaintext or the sealed record, as configured
+    fn append(&mut self, record: T) -> anyhow::Result<Record<Self::LogSize>> {
+        let sealed = self.seal_record(self.log.size()?, &record)?;
+        let hash = leaf_hash(self.hashed, &record, &sealed)?;
+        let meta = self.next_meta()?;
+        let id = self.log.add(sealed)?;
+        self.log.add_meta(id, meta)?;
+        self.push_hash(0, hash.clone())?;
+        Ok(Record {
+            id,
+            hash,
+            meta: Some(meta),
+        })
+    }
+
+    /// Hashes the plaintext or the sealed records, as configured
+    fn bulk_append(&mut self, records: Vec<T>) -> anyhow::Result<Vec<Record<Self::LogSize>>>
+    where
+        T: Sync,
+    {
+        let start = self.log.size()?;
+        let mut id = start;
+        let mut sealed = Vec::with_capacity(records.len());
+        for record in records.iter() {
+            sealed.push(self.seal_record(id, record)?);
+            id = id + Self::LogSize::one();
+        }
+        let hashed = self.hashed;
+        #[cfg(feature = "parallel")]
+        let hashes = records
+            .par_iter()
+            .zip(sealed.par_iter())
+            .map(|(r, s)| leaf_hash(hashed, r, s))
+            .collect::<anyhow::Result<Vec<String>>>()?;
+        #[cfg(not(feature = "parallel"))]
+        let hashes = records
+            .iter()
+            .zip(sealed.iter())
+            .map(|(r, s)| leaf_hash(hashed, r, s))
+            .collect::<anyhow::Result<Vec<String>>>()?;
+        let meta = self.next_meta()?;
+        let mut ids = Vec::with_capacity(records.len());
+        for s in sealed {
+            let id = self.log.add(s)?;
+            self.log.add_meta(id, meta)?;
+            ids.push(id);
+        }
+        add_leaf_hashes(self, start, hashes.clone())?;
+
+        Ok(ids
+            .into_iter()
+            .zip(hashes)
+            .map(|(id, hash)| Record {
+                id,
+                hash,
+                meta: Some(meta),
+            })
+            .collect())
+    }
+
+    fn add_tombstone(&mut self) -> anyhow::Result<Self::LogSize> {
+        self.log.add_tombstone()
+    }
+
+    fn redact(&mut self, id: Self::LogSize) -> anyhow::Result<()> {
+        self.log.redact(id)
+    }
+
+    fn expire(&mut self, id: Self::LogSize) -> anyhow::Result<()> {
+        self.log.expire(id)
+    }
+
+    fn expired(&self, id: Self::LogSize) -> anyhow::Result<bool> {
+        self.log.expired(id)
+    }
+
+    fn seal(&mut self) -> anyhow::Result<LogTree<Self::LogSize>> {
+        self.log.seal()
+    }
+
+    fn sealed(&self) -> anyhow::Result<Option<LogTree<Self::LogSize>>> {
+        self.log.sealed()
+    }
+
+    fn sync(&self) -> anyhow::Result<()> {
+        self.log.sync()
+    }
+
+    fn add_meta(&mut self, id: Self::LogSize, meta: RecordMeta) -> anyhow::Result<()> {
+        self.log.add_meta(id, meta)
+    }
+
+    fn get_meta(&self, id: Self::LogSize) -> anyhow::Result<Option<RecordMeta>> {
+        self.log.get_meta(id)
+    }
+
+    fn set_annotation(&mut self, id: Self::LogSize, annotation: Vec<u8>) -> anyhow::Result<()> {
+        self.log.set_annotation(id, annotation)
+    }
+
+    fn get_annotation(&self, id: Self::LogSize) -> anyhow::Result<Option<Vec<u8>>> {
+        self.log.get_annotation(id)
+    }
+
+    fn find_since(&self, timestamp: u64) -> anyhow::Result<Self::LogSize> {
+        self.log.find_since(timestamp)
+    }
+
+    fn add_hash(&mut self, level: LogHeight, hash: String) -> anyhow::Result<Self::LogSize> {
+        self.log.add_hash(level, hash)
+    }
+
+    fn get_hash(
+        &self,
+        level: LogHeight,
+        index: Self::LogSize,
+    ) -> anyhow::Result<MaybeOwned<'_, String>> {
+        self.log.get_hash(level, index)
+    }
+
+    fn index_of(&self, hash: &str) -> anyhow::Result<Option<Self::LogSize>> {
+        self.log.index_of(hash)
+    }
+
+    fn indices_of(&self, hash: &str) -> anyhow::Result<Vec<Self::LogSize>> {
+        self.log.indices_of(hash)
+    }
+
+    fn tile(&self, level: LogHeight, index: Self::LogSize) -> anyhow::Result<Tile<Self::LogSize>> {
+        self.log.tile(level, index)
+    }
+
+    fn latest(&self) -> anyhow::Result<LogTree<Self::LogSize>> {
+        self.log.latest()
+    }
+
+    fn ephemeral_hash(&self, level: LogHeight, index: Self::LogSize) -> anyhow::Result<String> {
+        self.log.ephemeral_hash(level, index)
+    }
+
+    fn root_at(&self, size: Self::LogSize) -> anyhow::Result<String> {
+        self.log.root_at(size)
+    }
+
+    fn head_at(&self, size: Self::LogSize) -> anyhow::Result<LogTree<Self::LogSize>> {
+        self.log.head_at(size)
+    }
+
+    fn proofs<I>(
+        &self,
+        positions: I,
+    ) -> anyhow::Result<HashMap<LogTreePosition<Self::LogSize>, String>>
+    where
+        I: Iterator<Item = LogTreePosition<Self::LogSize>>,
+    {
+        self.log.proofs(positions)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::test_helpers::*;
+    use crate::*;
+    use std::fs::{create_dir, remove_dir_all};
+    use std::path::Path;
+
+    use serial_test::serial;
+
+    const KEY: [u8; 32] = [7; 32];
+
+    fn memory_encrypted_log() -> EncryptedLog<LogRecord, InMemoryLog<Sealed>> {
+        EncryptedLog::new(InMemoryLog::default(), Box::new(AesGcmCipher::new(KEY)))
+    }
+
+    #[test]
+    fn encryption_seal_open() -> anyhow::Result<()> {
+        let cipher = AesGcmCipher::new(KEY);
+        let sealed = cipher.seal(b"1", b"secret")?;
+        assert_eq!(b"secret".to_vec(), cipher.open(b"1", &sealed)?);
+        assert!(!sealed.windows(6).any(|w| w == b"secret"));
+        assert_ne!(sealed, cipher.seal(b"1", b"secret")?);
+
+        let err = cipher.open(b"2", &sealed).err().unwrap();
+        assert_eq!(
+            Some(&EncryptionError::Authentication),
+            err.downcast_ref::<EncryptionError>()
+        );
+        let err = AesGcmCipher::new([8; 32])
+            .open(b"1", &sealed)
+            .err()
+            .unwrap();
+        assert_eq!(
+            Some(&EncryptionError::Authentication),
+            err.downcast_ref::<EncryptionError>()
+        );
+        let err = cipher.open(b"1", &sealed[..10]).err().unwrap();
+        assert_eq!(
+            Some(&EncryptionError::Truncated(10)),
+            err.downcast_ref::<EncryptionError>()
+        );
+        assert!(!format!("{:?}", cipher).contains('7'));
+        Ok(())
+    }
+
+    #[test]
+    fn encryption_memory_13() -> anyhow::Result<()> {
+        let mut ml = memory_encrypted_log();
+        test_13(&mut ml)?;
+        let sealed = ml.inner().get(9)?.unwrap();
+        assert!(!sealed.0.windows(4).any(|w| w == b"rec9"));
+        Ok(())
+    }
+
+    #[test]
+    fn encryption_memory_raw_bytes() -> anyhow::Result<()> {
+        let mut ml = memory_encrypted_log();
+        let mut client = InMemoryLogClientBuilder::new(&ml)?.build();
+        raw_bytes(&mut ml, &mut client)
+    }
+
+    #[test]
+    fn encryption_memory_duplicates() -> anyhow::Result<()> {
+        duplicates(&mut memory_encrypted_log())
+    }
+
+    #[test]
+    fn encryption_memory_range() -> anyhow::Result<()> {
+        range(&mut memory_encrypted_log())
+    }
+
+    #[test]
+    fn encryption_memory_iter() -> anyhow::Result<()> {
+        iter(&mut memory_encrypted_log())
+    }
+
+    #[test]
+    fn encryption_memory_bulk() -> anyhow::Result<()> {
+        bulk(&mut memory_encrypted_log())
+    }
+
+    #[test]
+    fn encryption_memory_properties() -> anyhow::Result<()> {
+        properties(|| Ok(memory_encrypted_log()))
+    }
+
+    #[test]
+    fn encryption_memory_hash_only() -> anyhow::Result<()> {
+        hash_only(&mut memory_encrypted_log())
+    }
+
+    #[test]
+    fn encryption_memory_redact() -> anyhow::Result<()> {
+        redact(&mut memory_encrypted_log())
+    }
+
+    #[test]
+    fn encryption_memory_client_13() -> anyhow::Result<()> {
+        let mut ml = memory_encrypted_log();
+        let mut client = InMemoryLogClientBuilder::new(&ml)?.build();
+        client_13(&mut ml, &mut client)
+    }
+
+    #[test]
+    fn encryption_ciphertext() -> anyhow::Result<()> {
+        let mut ml = memory_encrypted_log();
+        ml.hash_ciphertext();
+        let mut records = vec![];
+        for i in 0..5 {
+            records.push(ml.append(LogRecord::new(&format!("rec{}", i)))?);
+        }
+        records.extend(
+            ml.bulk_append(
+                (5..13)
+                    .map(|i| LogRecord::new(&format!("rec{}", i)))
+                    .collect(),
+            )?,
+        );
+        let leaves: Vec<String> = records.iter().map(|r| r.hash.clone()).collect();
+        assert_eq!(model_root(&leaves), ml.latest()?.hash);
+
+        // auditable without the key
+        let sealed = ml.inner().get(9)?.unwrap();
+        assert_eq!(hash(&*sealed)?, records[9].hash);
+        assert_ne!(hash(&LogRecord::new("rec9"))?, records[9].hash);
+        assert_eq!(Some(9), ml.index_of(&records[9].hash)?);
+        let bytes = ml.get_leaf_bytes(9)?.unwrap();
+        assert_eq!(hash_bytes(&bytes), records[9].hash);
+        assert_ne!(ml.get_raw_bytes(9)?, Some(bytes));
+        let err = (ml.leaf_hasher())(&LogRecord::new("rec9")).err().unwrap();
+        assert_eq!(
+            Some(&EncryptionError::HashedCiphertext),
+            err.downcast_ref::<EncryptionError>()
+        );
+
+        let mut client = InMemoryLogClientBuilder::new(&ml)?.build();
+        assert!(check_record(&mut client, &ml, &records[9])?);
+        assert_eq!(Some(LogRecord::new("rec9")), ml.get(9)?.as_deref().cloned());
+        Ok(())
+    }
+
+    #[test]
+    fn encryption_moved_record() -> anyhow::Result<()> {
+        let mut ml = memory_encrypted_log();
+        append_multiple(&mut ml, 2)?;
+        let mut inner = ml.into_inner();
+        let sealed = inner.get(0)?.unwrap().into_owned();
+        inner.add(sealed)?;
+        let ml = EncryptedLog::<LogRecord, _>::new(inner, Box::new(AesGcmCipher::new(KEY)));
+        let err = ml.get(2).err().unwrap();
+        assert_eq!(
+            Some(&EncryptionError::Authentication),
+            err.downcast_ref::<EncryptionError>()
+        );
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    fn encryption_file_13() -> anyhow::Result<()> {
+        let path = Path::new("./test_data");
+        if path.exists() {
+            remove_dir_all(path)?;
+        }
+        create_dir(path)?;
+        let mut ml = EncryptedLog::new(
+            FileLog::<Sealed>::open(&path)?,
+            Box::new(AesGcmCipher::new(KEY)),
+        );
+        test_13(&mut ml)?;
+        drop(ml);
+        let ml = EncryptedLog::new(
+            FileLog::<Sealed>::open(&path)?,
+            Box::new(AesGcmCipher::new(KEY)),
+        );
+        check_13(&ml)?;
+        assert_eq!(
+            Some(LogRecord::new("rec12")),
+            ml.get(12)?.as_deref().cloned()
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,215 @@
+//! # Audit events
+//!
+//! `AuditEvent` is a ready-made record type for application audit logs: an actor doing an action on a resource at
+//! some time, with attributes. Its canonical encoding, hashed as its leaf when logged through a `LeafLog`, is its JSON
+//! document with the version of its schema, the keys of all objects sorted, no white space, absent optional fields
+//! omitted and integers only, so implementations in any language get the same leaf hash for the same event.
+//! Fields added by later versions are optional: older readers keep them as extensions, encoded back as they were so
+//! leaf hashes still match, and only incompatible changes bump `AUDIT_EVENT_VERSION`.
+use crate::base::RecordMeta;
+use crate::json::Versioned;
+use crate::leaf::Leaf;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use thiserror::Error;
+
+/// The version of the schema of audit events
+pub const AUDIT_EVENT_VERSION: u32 = 1;
+
+/// Specific Errors for audit events
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum AuditEventError {
+    #[error("Audit event without a version")]
+    NoVersion,
+    #[error("Unsupported audit event version: {0}")]
+    UnsupportedVersion(u32),
+    #[error("Attribute {0} holds a number that is not an integer")]
+    NonIntegerAttribute(String),
+}
+
+/// An event of an application audit log
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct AuditEvent {
+    /// Who did it: a user, a service account
+    pub actor: String,
+    /// What was done, like `login` or `document.delete`
+    pub action: String,
+    /// What it was done to
+    pub resource: String,
+    /// Event time, in milliseconds since the UNIX epoch
+    pub timestamp: u64,
+    /// A unique identifier of the event, so identical events are different records
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    /// The other details of the event, sorted by name
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub attributes: BTreeMap<String, Value>,
+    /// The fields of later versions of the schema
+    #[serde(flatten)]
+    pub extensions: BTreeMap<String, Value>,
+}
+
+impl AuditEvent {
+    /// An event happening now, without attributes
+    pub fn new(actor: &str, action: &str, resource: &str) -> Self {
+        Self {
+            actor: actor.to_owned(),
+            action: action.to_owned(),
+            resource: resource.to_owned(),
+            timestamp: RecordMeta::now().timestamp,
+            id: None,
+            attributes: BTreeMap::new(),
+            extensions: BTreeMap::new(),
+        }
+    }
+
+    /// Set the unique identifier of the event
+    pub fn with_id(mut self, id: &str) -> Self {
+        self.id = Some(id.to_owned());
+        self
+    }
+
+    /// Add an attribute
+    pub fn with_attribute<V: Into<Value>>(mut self, name: &str, value: V) -> Self {
+        self.attributes.insert(name.to_owned(), value.into());
+        self
+    }
+
+    /// Check the event has a canonical encoding: numbers with a fractional part or an exponent are encoded
+    /// differently by different JSON libraries, so attributes only hold integers
+    pub fn validate(&self) -> Result<(), AuditEventError> {
+        for (name, value) in self.attributes.iter().chain(self.extensions.iter()) {
+            if !integers_only(value) {
+                return Err(AuditEventError::NonIntegerAttribute(name.clone()));
+            }
+        }
+        Ok(())
+    }
+
+    /// The canonical JSON document of the event, the one hashed as its leaf
+    pub fn to_canonical_json(&self) -> anyhow::Result<String> {
+        // the maps of JSON values keep their keys sorted
+        let document = serde_json::to_value(&Versioned {
+            version: AUDIT_EVENT_VERSION,
+            value: self,
+        })?;
+        Ok(serde_json::to_string(&document)?)
+    }
+
+    /// Read an event from a JSON document of the current version, checking it has a canonical encoding
+    pub fn from_json(json: &str) -> anyhow::Result<Self> {
+        let document: Value = serde_json::from_str(json)?;
+        let version = document
+            .get("version")
+            .and_then(Value::as_u64)
+            .ok_or(AuditEventError::NoVersion)?;
+        if version != AUDIT_EVENT_VERSION as u64 {
+            return Err(AuditEventError::UnsupportedVersion(version as u32).into());
+        }
+        let event: Versioned<Self> = serde_json::from_value(document)?;
+        event.value.validate()?;
+        Ok(event.value)
+    }
+}
+
+/// Audit events are hashed via their canonical JSON document
+impl Leaf for AuditEvent {
+    fn leaf_bytes(&self) -> Cow<'_, [u8]> {
+        // JSON values with string keys always serialize
+        Cow::Owned(self.to_canonical_json().unwrap_or_default().into_bytes())
+    }
+}
+
+/// Whether all the numbers of a JSON value are integers
+fn integers_only(value: &Value) -> bool {
+    match value {
+        Value::Number(n) => n.is_i64() || n.is_u64(),
+        Value::Array(values) => values.iter().all(integers_only),
+        Value::Object(map) => map.values().all(integers_only),
+        _ => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::*;
+
+    fn event() -> AuditEvent {
+        AuditEvent {
+            timestamp: 1_700_000_000_000,
+            ..AuditEvent::new("alice", "document.delete", "documents/42")
+        }
+        .with_attribute("reason", "duplicate")
+        .with_attribute("ip", "192.0.2.1")
+        .with_attribute("versions", vec![1, 2])
+    }
+
+    #[test]
+    fn audit_event_canonical() -> anyhow::Result<()> {
+        let event = event();
+        assert_eq!(
+            "{\"action\":\"document.delete\",\"actor\":\"alice\",\"attributes\":{\"ip\":\"192.0.2.1\",\
+            \"reason\":\"duplicate\",\"versions\":[1,2]},\"resource\":\"documents/42\",\
+            \"timestamp\":1700000000000,\"version\":1}",
+            event.to_canonical_json()?
+        );
+        assert_eq!(
+            hash_bytes(event.to_canonical_json()?.as_bytes()),
+            hash_leaf(&event)
+        );
+        // the same event built in another order, from another document
+        let other = AuditEvent::from_json(
+            "{\"version\":1, \"timestamp\":1700000000000, \"resource\":\"documents/42\",\
+            \"attributes\":{\"versions\":[1,2], \"reason\":\"duplicate\", \"ip\":\"192.0.2.1\"},\
+            \"actor\":\"alice\", \"action\":\"document.delete\"}",
+        )?;
+        assert_eq!(event, other);
+        assert_eq!(hash_leaf(&event), hash_leaf(&other));
+        assert_ne!(
+            hash_leaf(&event),
+            hash_leaf(&event.clone().with_id("event-1"))
+        );
+
+        let mut ml = LeafLog::new(InMemoryLog::default());
+        let record = ml.append(event.clone())?;
+        assert_eq!(hash_leaf(&event), record.hash);
+        assert_eq!(Some(0), ml.index_of(&hash_leaf(&other))?);
+        Ok(())
+    }
+
+    #[test]
+    fn audit_event_versions() -> anyhow::Result<()> {
+        // an event of a later version of the same schema, with a field this version does not know
+        let json = "{\"action\":\"login\",\"actor\":\"bob\",\"outcome\":{\"code\":403},\"resource\":\"app\",\
+            \"timestamp\":1700000000000,\"version\":1}";
+        let event = AuditEvent::from_json(json)?;
+        assert_eq!(
+            Some(&serde_json::json!({"code": 403})),
+            event.extensions.get("outcome")
+        );
+        assert_eq!(json, event.to_canonical_json()?);
+
+        let err = AuditEvent::from_json("{\"version\":2,\"actor\":\"bob\"}").unwrap_err();
+        assert_eq!(
+            Some(&AuditEventError::UnsupportedVersion(2)),
+            err.downcast_ref::<AuditEventError>()
+        );
+        let err = AuditEvent::from_json("{\"actor\":\"bob\"}").unwrap_err();
+        assert_eq!(
+            Some(&AuditEventError::NoVersion),
+            err.downcast_ref::<AuditEventError>()
+        );
+
+        let event = event.with_attribute("score", 0.5);
+        assert_eq!(
+            Err(AuditEventError::NonIntegerAttribute("score".into())),
+            event.validate()
+        );
+        let err = AuditEvent::from_json(&event.to_canonical_json()?).unwrap_err();
+        assert!(err.downcast_ref::<AuditEventError>().is_some());
+        Ok(())
+    }
+}
@@ -1,109 +1,740 @@
 use std::marker::PhantomData;
 
 pub use crate::base::*;
+use crate::cache::RootCache;
+use crate::codec::*;
+use crate::compression::*;
+use crate::instrument::{proofs_span, record_append, record_proofs, time_read};
 use maybe_owned::MaybeOwned;
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
-use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fmt::Debug;
-use std::fs::{File, OpenOptions};
+use std::fs::{read_to_string, remove_file, rename, write, File, OpenOptions};
 use std::io::prelude::*;
-use std::io::SeekFrom;
+use std::io::{BufReader, BufWriter, SeekFrom};
 use std::path::Path;
+#[cfg(feature = "mmap")]
+use std::sync::{Mutex, MutexGuard};
+use thiserror::Error;
+
+#[cfg(feature = "mmap")]
+use crate::mmap::Mmap;
 
 const HASH_SIZE_IN_BYTES: usize = 64;
 
-/// A file based transparent log
+/// The size of a lookup record: the hash and the record index
+const LOOKUP_SIZE_IN_BYTES: usize = HASH_SIZE_IN_BYTES + std::mem::size_of::<u64>();
+
+/// The size of a metadata record: the timestamp, 0 meaning no metadata
+const META_SIZE_IN_BYTES: u64 = std::mem::size_of::<u64>() as u64;
+
+/// The header file, holding the name of the compressor of the records, if any
+const HEADER_FILE: &str = "header.bin";
+
+/// The codec file, holding the name of the codec of the records, absent for MessagePack
+const CODEC_FILE: &str = "codec.bin";
+
+/// The seal file, holding the final tree head of a sealed log
+const SEAL_FILE: &str = "seal.bin";
+
+/// The annotation file, appending for each annotation the record index, the annotation length and the annotation
+const ANNOTATION_FILE: &str = "annotations.bin";
+
+/// The expiry file, appending the index of each expired record
+const EXPIRED_FILE: &str = "expired.bin";
+
+/// The compaction marker file, written once the compacted files are complete and removed once they replaced the old ones
+const COMPACT_FILE: &str = "compact.bin";
+
+/// The compacted data file, before it replaces the data file
+const COMPACT_DATA_FILE: &str = "data.compact";
+
+/// The compacted index file, before it replaces the index file
+const COMPACT_INDEX_FILE: &str = "index.compact";
+
+/// Specific Errors for file logs
+#[derive(Debug, Clone, Error)]
+pub enum FileLogError {
+    #[error("File log lock poisoned")]
+    Poisoned,
+}
+
+/// A file based transparent log, serializing its records with the codec C
+/// Reads are positional, never moving the cursor of a file, so the log is `Send + Sync` and can be read from several threads
 #[derive(Debug)]
-pub struct FileLog<'a, T: Serialize + Deserialize<'a>> {
+pub struct FileLog<'a, T: Serialize + Deserialize<'a>, C: Codec = MessagePack> {
     dir: &'a Path,
-    data: RefCell<File>,
-    index: RefCell<File>,
-    hashes: RefCell<Vec<File>>,
-    _marker: PhantomData<T>,
+    data: File,
+    index: File,
+    hashes: Vec<File>,
+    lookup_file: File,
+    lookup: HashIndex<u64>,
+    timestamps: File,
+    annotations: HashMap<u64, Vec<u8>>,
+    expired: HashSet<u64>,
+    compressor: Option<Box<dyn Compressor>>,
+    sealed: Option<LogTree<u64>>,
+    root: RootCache<u64>,
+    pending_hashes: PendingHashes<u64>,
+    #[cfg(feature = "mmap")]
+    maps: Option<Mutex<Vec<Option<Mmap>>>>,
+    _marker: PhantomData<(T, C)>,
 }
 
 impl<'a, T: Serialize + Deserialize<'a>> FileLog<'a, T> {
     /// Open a new or existing log from the given directory
     pub fn open<P: AsRef<Path>>(dir: &'a P) -> anyhow::Result<Self> {
-        let dir = dir.as_ref();
-        let data = OpenOptions::new()
-            .read(true)
-            .append(true)
-            .create(true)
-            .open(dir.join("data.bin"))?;
-        let index = OpenOptions::new()
-            .read(true)
-            .append(true)
-            .create(true)
-            .open(dir.join("index.bin"))?;
+        Self::open_full(dir.as_ref(), None)
+    }
+
+    /// Open a new or existing log from the given directory, compressing the records with the given compressor
+    /// A new log records the compressor name in its header, an existing log must have been written with it
+    pub fn open_with_compressor<P: AsRef<Path>>(
+        dir: &'a P,
+        compressor: Box<dyn Compressor>,
+    ) -> anyhow::Result<Self> {
+        Self::open_full(dir.as_ref(), Some(compressor))
+    }
+
+    /// Open a new or existing log from the given directory, reading hashes from memory maps of the hash files
+    /// The hash files must not be truncated by another process while the log is open
+    #[cfg(feature = "mmap")]
+    pub fn open_with_mmap<P: AsRef<Path>>(dir: &'a P) -> anyhow::Result<Self> {
+        let mut log = Self::open(dir)?;
+        log.maps = Some(Mutex::new(vec![]));
+        Ok(log)
+    }
+}
+
+impl<'a, T: Serialize + Deserialize<'a>, C: Codec> FileLog<'a, T, C> {
+    /// Open a new or existing log from the given directory, serializing the records with the codec C
+    /// A new log records the codec name in its header, an existing log must have been written with it
+    pub fn open_with_codec<P: AsRef<Path>>(
+        dir: &'a P,
+        compressor: Option<Box<dyn Compressor>>,
+    ) -> anyhow::Result<Self> {
+        Self::open_full(dir.as_ref(), compressor)
+    }
+
+    /// Open the log, checking the codec and the compressor against the header
+    /// The files of a sealed log are opened read only
+    fn open_full(dir: &'a Path, compressor: Option<Box<dyn Compressor>>) -> anyhow::Result<Self> {
+        finish_compaction(dir)?;
+        let seal = dir.join(SEAL_FILE);
+        let sealed: Option<LogTree<u64>> = if seal.exists() {
+            Some(rmp_serde::from_slice(&std::fs::read(&seal)?)?)
+        } else {
+            None
+        };
+        let options = file_options(sealed.is_some());
+        let data = options.open(dir.join("data.bin"))?;
+        let header = dir.join(HEADER_FILE);
+        let recorded = if header.exists() {
+            Some(read_to_string(&header)?)
+        } else {
+            None
+        };
+        let empty = data.metadata()?.len() == 0;
+        if check_compressor(recorded.as_deref(), compressor.as_deref(), empty)? {
+            if let Some(c) = &compressor {
+                write(&header, c.name())?;
+            }
+        }
+        let codec = dir.join(CODEC_FILE);
+        let recorded = if codec.exists() {
+            Some(read_to_string(&codec)?)
+        } else {
+            None
+        };
+        if check_codec::<C>(recorded.as_deref(), empty)? {
+            write(&codec, C::NAME)?;
+        }
+        let index = options.open(dir.join("index.bin"))?;
 
         let mut ix = 0;
         let mut p = dir.join(format!("hash{}.bin", ix));
         let mut hashes = vec![];
         while p.exists() {
-            hashes.push(OpenOptions::new().read(true).append(true).open(p)?);
+            hashes.push(options.open(p)?);
             ix += 1;
             p = dir.join(format!("hash{}.bin", ix));
         }
 
+        let mut lookup_file = options.open(dir.join("lookup.bin"))?;
+        let mut b = vec![];
+        lookup_file.read_to_end(&mut b)?;
+        let mut lookup = HashIndex::default();
+        for chunk in b.chunks_exact(LOOKUP_SIZE_IN_BYTES) {
+            let (h, id) = chunk.split_at(HASH_SIZE_IN_BYTES);
+            let mut b1 = [0_u8; std::mem::size_of::<u64>()];
+            b1.copy_from_slice(id);
+            lookup.insert(
+                String::from_utf8_lossy(h).into_owned(),
+                u64::from_be_bytes(b1),
+            );
+        }
+
+        // metadata is written in place, not appended
+        let timestamps = OpenOptions::new()
+            .read(true)
+            .write(sealed.is_none())
+            .create(sealed.is_none())
+            .truncate(false)
+            .open(dir.join("timestamps.bin"))?;
+
+        let annotation = dir.join(ANNOTATION_FILE);
+        let annotations = if annotation.exists() {
+            read_annotations(&std::fs::read(&annotation)?)
+        } else {
+            HashMap::new()
+        };
+        let expiry = dir.join(EXPIRED_FILE);
+        let expired = if expiry.exists() {
+            std::fs::read(&expiry)?
+                .chunks_exact(std::mem::size_of::<u64>())
+                .map(|chunk| {
+                    let mut b1 = [0_u8; std::mem::size_of::<u64>()];
+                    b1.copy_from_slice(chunk);
+                    u64::from_be_bytes(b1)
+                })
+                .collect()
+        } else {
+            HashSet::new()
+        };
+
         Ok(Self {
             dir,
-            data: RefCell::new(data),
-            index: RefCell::new(index),
-            hashes: RefCell::new(hashes),
+            data,
+            index,
+            hashes,
+            lookup_file,
+            lookup,
+            timestamps,
+            annotations,
+            expired,
+            compressor,
+            sealed,
+            root: RootCache::default(),
+            pending_hashes: PendingHashes::default(),
+            #[cfg(feature = "mmap")]
+            maps: None,
             _marker: PhantomData,
         })
     }
+
+    /// Rewrite the data file without the bytes of redacted and expired records, and the index file with the new offsets,
+    /// returning the number of bytes reclaimed. The hashes are untouched, so the tree and its proofs are unchanged
+    /// The new files are written and synced aside, and a marker file is synced before they replace the old ones,
+    /// so a compaction interrupted by a crash is abandoned or finished when the log is opened again
+    pub fn compact(&mut self) -> anyhow::Result<u64> {
+        check_unsealed(&self.sealed)?;
+        let mut index = vec![];
+        File::open(self.dir.join("index.bin"))?.read_to_end(&mut index)?;
+        let mut data = BufWriter::new(File::create(self.dir.join(COMPACT_DATA_FILE))?);
+        let mut compacted = Vec::with_capacity(index.len());
+        let mut offset = 0_u64;
+        for chunk in index.chunks_exact(SZ as usize) {
+            let (o, l) = chunk.split_at(std::mem::size_of::<u64>());
+            let mut b1 = [0_u8; std::mem::size_of::<u64>()];
+            b1.copy_from_slice(o);
+            let mut b2 = [0_u8; std::mem::size_of::<usize>()];
+            b2.copy_from_slice(l);
+            let length = usize::from_be_bytes(b2);
+            if length > 0 {
+                let mut b3 = vec![0_u8; length];
+                read_exact_at(&self.data, &mut b3, u64::from_be_bytes(b1))?;
+                data.write_all(&b3)?;
+            }
+            // tombstones keep the offset of the next record, as when they are added
+            compacted.extend_from_slice(&offset.to_be_bytes());
+            compacted.extend_from_slice(&length.to_be_bytes());
+            offset += length as u64;
+        }
+        data.into_inner()?.sync_all()?;
+        let mut index_file = File::create(self.dir.join(COMPACT_INDEX_FILE))?;
+        index_file.write_all(&compacted)?;
+        index_file.sync_all()?;
+        let reclaimed = self.data.metadata()?.len() - offset;
+        File::create(self.dir.join(COMPACT_FILE))?.sync_all()?;
+        finish_compaction(self.dir)?;
+
+        let options = file_options(false);
+        self.data = options.open(self.dir.join("data.bin"))?;
+        self.index = options.open(self.dir.join("index.bin"))?;
+        Ok(reclaimed)
+    }
+
+    /// Read count consecutive hashes of the given level from the given index
+    fn read_hashes(&self, level: LogHeight, index: u64, count: usize) -> anyhow::Result<Vec<u8>> {
+        let last = index + count as u64 - 1;
+        let v = self
+            .hashes
+            .get(level)
+            .ok_or(LogError::InvalidHeight::<u64>(level))?;
+        #[cfg(feature = "mmap")]
+        if let Some(maps) = &self.maps {
+            let start = HASH_SIZE_IN_BYTES * index as usize;
+            let end = start + HASH_SIZE_IN_BYTES * count;
+            let mut maps = lock(maps)?;
+            if maps.len() <= level {
+                maps.resize_with(level + 1, || None);
+            }
+            // map again when the file grew past the current map
+            if maps[level].as_ref().map_or(true, |m| m.len() < end) {
+                let len = v.metadata()?.len() as usize;
+                if len < end {
+                    return Err(LogError::InvalidIndex(level, last).into());
+                }
+                maps[level] = Some(Mmap::map(v, len)?);
+            }
+            return Ok(maps[level]
+                .as_ref()
+                .map(|m| m[start..end].to_vec())
+                .unwrap_or_default());
+        }
+        if last >= level_size(v)? {
+            return Err(LogError::InvalidIndex(level, last).into());
+        }
+        let mut b = vec![0_u8; HASH_SIZE_IN_BYTES * count];
+        time_read("file", || {
+            read_exact_at(v, &mut b, (HASH_SIZE_IN_BYTES as u64) * index)
+        })?;
+        Ok(b)
+    }
+}
+
+/// Finish a compaction interrupted by a crash: the compacted files replace the old ones if the marker file
+/// says they are complete, else they are removed
+fn finish_compaction(dir: &Path) -> anyhow::Result<()> {
+    let marker = dir.join(COMPACT_FILE);
+    let complete = marker.exists();
+    for (compacted, file) in [
+        (COMPACT_DATA_FILE, "data.bin"),
+        (COMPACT_INDEX_FILE, "index.bin"),
+    ] {
+        let compacted = dir.join(compacted);
+        if !compacted.exists() {
+            continue;
+        }
+        if complete {
+            rename(compacted, dir.join(file))?;
+        } else {
+            remove_file(compacted)?;
+        }
+    }
+    if complete {
+        remove_file(marker)?;
+    }
+    Ok(())
+}
+
+/// Lock the memory maps of a log
+#[cfg(feature = "mmap")]
+fn lock<X>(mutex: &Mutex<X>) -> Result<MutexGuard<'_, X>, FileLogError> {
+    mutex.lock().map_err(|_| FileLogError::Poisoned)
+}
+
+/// Read exactly enough bytes to fill the buffer from the given offset in the file, without moving its cursor
+#[cfg(unix)]
+fn read_exact_at(file: &File, buf: &mut [u8], offset: u64) -> std::io::Result<()> {
+    std::os::unix::fs::FileExt::read_exact_at(file, buf, offset)
+}
+
+/// Read exactly enough bytes to fill the buffer from the given offset in the file, without relying on its cursor
+#[cfg(windows)]
+fn read_exact_at(file: &File, mut buf: &mut [u8], mut offset: u64) -> std::io::Result<()> {
+    use std::os::windows::fs::FileExt;
+    while !buf.is_empty() {
+        match file.seek_read(buf, offset) {
+            Ok(0) => return Err(std::io::ErrorKind::UnexpectedEof.into()),
+            Ok(n) => {
+                buf = &mut buf[n..];
+                offset += n as u64;
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+/// Write all the bytes at the given offset in the file, which must not be opened for appending
+#[cfg(unix)]
+fn write_all_at(file: &File, buf: &[u8], offset: u64) -> std::io::Result<()> {
+    std::os::unix::fs::FileExt::write_all_at(file, buf, offset)
+}
+
+/// Write all the bytes at the given offset in the file, which must not be opened for appending
+#[cfg(windows)]
+fn write_all_at(file: &File, mut buf: &[u8], mut offset: u64) -> std::io::Result<()> {
+    use std::os::windows::fs::FileExt;
+    while !buf.is_empty() {
+        match file.seek_write(buf, offset) {
+            Ok(0) => return Err(std::io::ErrorKind::WriteZero.into()),
+            Ok(n) => {
+                buf = &buf[n..];
+                offset += n as u64;
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+/// The options to open a log file: read only if the log is sealed, else appending and creating it if needed
+fn file_options(sealed: bool) -> OpenOptions {
+    let mut options = OpenOptions::new();
+    options.read(true).append(!sealed).create(!sealed);
+    options
+}
+
+/// Read the annotations from the content of the annotation file, the last annotation of a record replacing the previous ones
+/// A torn annotation at the end of the file is ignored
+fn read_annotations(mut b: &[u8]) -> HashMap<u64, Vec<u8>> {
+    const WORD: usize = std::mem::size_of::<u64>();
+    const HEADER: usize = 2 * WORD;
+    let mut annotations = HashMap::new();
+    while b.len() >= HEADER {
+        let mut b1 = [0_u8; WORD];
+        b1.copy_from_slice(&b[..WORD]);
+        let mut b2 = [0_u8; WORD];
+        b2.copy_from_slice(&b[WORD..HEADER]);
+        let length = u64::from_be_bytes(b2) as usize;
+        if b.len() - HEADER < length {
+            break;
+        }
+        annotations.insert(u64::from_be_bytes(b1), b[HEADER..HEADER + length].to_vec());
+        b = &b[HEADER + length..];
+    }
+    annotations
+}
+
+/// The number of hashes in a level file
+fn level_size(file: &File) -> anyhow::Result<u64> {
+    Ok(file.metadata()?.len() / HASH_SIZE_IN_BYTES as u64)
+}
+
+/// Sequential iterator over a FileLog, reading the index and data files through buffers
+struct FileLogIterator<'b, T, C> {
+    index: BufReader<File>,
+    data: BufReader<File>,
+    compressor: Option<&'b dyn Compressor>,
+    position: u64,
+    id: u64,
+    size: u64,
+    _marker: PhantomData<(T, C)>,
+}
+
+impl<'b, T: DeserializeOwned, C: Codec> FileLogIterator<'b, T, C> {
+    /// Read the next record
+    fn read_next(&mut self) -> anyhow::Result<T> {
+        let mut b1 = [0_u8; std::mem::size_of::<u64>()];
+        self.index.read_exact(&mut b1)?;
+        let mut b2 = [0_u8; std::mem::size_of::<usize>()];
+        self.index.read_exact(&mut b2)?;
+
+        let offset = u64::from_be_bytes(b1);
+        let length: usize = usize::from_be_bytes(b2);
+        if length == 0 {
+            return Err(TombstoneError::NoRecord.into());
+        }
+        if offset != self.position {
+            self.data.seek(SeekFrom::Start(offset))?;
+        }
+        let mut b3 = vec![0_u8; length];
+        self.data.read_exact(&mut b3)?;
+        self.position = offset + length as u64;
+        C::decode(&decompress(self.compressor, b3)?)
+    }
+}
+
+impl<'b, T: DeserializeOwned, C: Codec> Iterator for FileLogIterator<'b, T, C> {
+    type Item = anyhow::Result<(u64, T)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.id >= self.size {
+            return None;
+        }
+        let id = self.id;
+        self.id += 1;
+        match self.read_next() {
+            Ok(r) => Some(Ok((id, r))),
+            Err(e) => {
+                self.size = 0;
+                Some(Err(e))
+            }
+        }
+    }
 }
 
 /// The size of an index record: the offset in the file and the record length
 const SZ: u64 = std::mem::size_of::<usize>() as u64 + std::mem::size_of::<u64>() as u64;
 
-impl<'a, T: Serialize + DeserializeOwned> TransparentLog<'a, T> for FileLog<'a, T> {
+impl<'a, T: Serialize + DeserializeOwned, C: Codec> TransparentLog<'a, T> for FileLog<'a, T, C> {
     type LogSize = u64;
 
+    fn leaf_hasher(&self) -> LeafHasher<T> {
+        |record| hash_with::<C, T>(record)
+    }
+
     fn size(&self) -> anyhow::Result<Self::LogSize> {
-        Ok(self.index.borrow().metadata()?.len() / SZ)
+        Ok(self.index.metadata()?.len() / SZ)
+    }
+
+    fn latest(&self) -> anyhow::Result<LogTree<Self::LogSize>> {
+        let size = self.size()?;
+        self.root.get_or_compute(size, || self.head_at(size))
+    }
+
+    fn get(&self, index: Self::LogSize) -> anyhow::Result<Option<MaybeOwned<'_, T>>> {
+        self.get_raw_bytes(index)?
+            .map(|bs| Ok(MaybeOwned::Owned(C::decode(&bs)?)))
+            .transpose()
     }
 
-    fn get(&self, index: Self::LogSize) -> anyhow::Result<Option<MaybeOwned<T>>> {
-        let mut index_file = self.index.borrow_mut();
-        index_file.seek(SeekFrom::Start(index * SZ))?;
+    /// The stored bytes, decompressed
+    fn get_raw_bytes(&self, index: Self::LogSize) -> anyhow::Result<Option<Vec<u8>>> {
+        if index >= self.size()? {
+            return Ok(None);
+        }
         let mut b1 = [0_u8; std::mem::size_of::<u64>()];
-        index_file.read_exact(&mut b1)?;
+        read_exact_at(&self.index, &mut b1, index * SZ)?;
         let mut b2 = [0_u8; std::mem::size_of::<usize>()];
-        index_file.read_exact(&mut b2)?;
+        read_exact_at(&self.index, &mut b2, index * SZ + b1.len() as u64)?;
 
         let offset = u64::from_be_bytes(b1);
         let length: usize = usize::from_be_bytes(b2);
+        if length == 0 {
+            return Err(tombstone_error(self, index));
+        }
         let mut b3 = vec![0_u8; length];
-        let mut data_file = self.data.borrow_mut();
-        data_file.seek(SeekFrom::Start(offset))?;
-        data_file.read_exact(&mut b3)?;
-        let r = rmp_serde::from_read_ref(&b3)?;
-        Ok(Some(MaybeOwned::Owned(r)))
+        time_read("file", || read_exact_at(&self.data, &mut b3, offset))?;
+        Ok(Some(decompress(self.compressor.as_deref(), b3)?))
+    }
+
+    fn get_range(
+        &self,
+        start: Self::LogSize,
+        end: Self::LogSize,
+    ) -> anyhow::Result<Vec<MaybeOwned<'_, T>>> {
+        let end = end.min(self.size()?);
+        if start >= end {
+            return Ok(vec![]);
+        }
+        let mut b1 = vec![0_u8; ((end - start) * SZ) as usize];
+        read_exact_at(&self.index, &mut b1, start * SZ)?;
+
+        let entries: Vec<(u64, usize)> = b1
+            .chunks_exact(SZ as usize)
+            .map(|chunk| {
+                let (o, l) = chunk.split_at(std::mem::size_of::<u64>());
+                let mut b2 = [0_u8; std::mem::size_of::<u64>()];
+                b2.copy_from_slice(o);
+                let mut b3 = [0_u8; std::mem::size_of::<usize>()];
+                b3.copy_from_slice(l);
+                (u64::from_be_bytes(b2), usize::from_be_bytes(b3))
+            })
+            .collect();
+
+        let first = entries[0].0;
+        let (last, length) = entries[entries.len() - 1];
+        let mut b4 = vec![0_u8; (last - first) as usize + length];
+        read_exact_at(&self.data, &mut b4, first)?;
+        entries
+            .into_iter()
+            .map(|(offset, length)| {
+                if length == 0 {
+                    return Err(TombstoneError::NoRecord.into());
+                }
+                let o = (offset - first) as usize;
+                let bs = decompress(self.compressor.as_deref(), b4[o..o + length].to_vec())?;
+                let r = C::decode(&bs)?;
+                Ok(MaybeOwned::Owned(r))
+            })
+            .collect()
+    }
+
+    fn iter(&self) -> LogIterator<'_, Self::LogSize, T> {
+        let open = || -> anyhow::Result<FileLogIterator<T, C>> {
+            Ok(FileLogIterator {
+                index: BufReader::new(File::open(self.dir.join("index.bin"))?),
+                data: BufReader::new(File::open(self.dir.join("data.bin"))?),
+                compressor: self.compressor.as_deref(),
+                position: 0,
+                id: 0,
+                size: self.size()?,
+                _marker: PhantomData,
+            })
+        };
+        match open() {
+            Ok(it) => Box::new(it.map(|r| r.map(|(id, t)| (id, MaybeOwned::Owned(t))))),
+            Err(e) => Box::new(std::iter::once(Err(e))),
+        }
     }
 
     fn add(&mut self, record: T) -> anyhow::Result<Self::LogSize> {
-        let mut data_file = self.data.borrow_mut();
-        let offset = data_file.metadata()?.len();
-        let data = rmp_serde::to_vec(&record)?;
+        check_unsealed(&self.sealed)?;
+        let offset = self.data.metadata()?.len();
+        let data = compress(self.compressor.as_deref(), C::encode(&record)?)?;
         let length = data.len();
-        data_file.seek(SeekFrom::End(0))?;
-        data_file.write_all(&data)?;
-        let mut index_file = self.index.borrow_mut();
-        let id = index_file.metadata()?.len() / SZ;
-        index_file.seek(SeekFrom::End(0))?;
-        index_file.write_all(&offset.to_be_bytes())?;
-        index_file.write_all(&length.to_be_bytes())?;
+        self.data.seek(SeekFrom::End(0))?;
+        self.data.write_all(&data)?;
+        let id = self.index.metadata()?.len() / SZ;
+        self.index.seek(SeekFrom::End(0))?;
+        self.index.write_all(&offset.to_be_bytes())?;
+        self.index.write_all(&length.to_be_bytes())?;
+        record_append("file");
         Ok(id)
     }
 
-    fn add_hash(&mut self, level: LogHeight, hash: String) -> anyhow::Result<Self::LogSize> {
-        let mut hs = self.hashes.borrow_mut();
+    /// A tombstone is an index record with no length
+    fn add_tombstone(&mut self) -> anyhow::Result<Self::LogSize> {
+        check_unsealed(&self.sealed)?;
+        let offset = self.data.metadata()?.len();
+        let id = self.index.metadata()?.len() / SZ;
+        self.index.seek(SeekFrom::End(0))?;
+        self.index.write_all(&offset.to_be_bytes())?;
+        self.index.write_all(&0_usize.to_be_bytes())?;
+        record_append("file");
+        Ok(id)
+    }
+
+    /// Overwrites the record with zeros in the data file, then removes its length from the index
+    /// The files are opened again without appending, to write in place
+    fn redact(&mut self, id: Self::LogSize) -> anyhow::Result<()> {
+        check_unsealed(&self.sealed)?;
+        if id >= self.size()? {
+            return Err(LogError::InvalidIndex(0, id).into());
+        }
+        let index_file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(self.dir.join("index.bin"))?;
+        let mut b1 = [0_u8; std::mem::size_of::<u64>()];
+        read_exact_at(&index_file, &mut b1, id * SZ)?;
+        let mut b2 = [0_u8; std::mem::size_of::<usize>()];
+        read_exact_at(&index_file, &mut b2, id * SZ + b1.len() as u64)?;
+        let length: usize = usize::from_be_bytes(b2);
+        if length > 0 {
+            let data_file = OpenOptions::new()
+                .write(true)
+                .open(self.dir.join("data.bin"))?;
+            write_all_at(&data_file, &vec![0_u8; length], u64::from_be_bytes(b1))?;
+            data_file.sync_data()?;
+            write_all_at(
+                &index_file,
+                &0_usize.to_be_bytes(),
+                id * SZ + b1.len() as u64,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Redacts the record, then appends its index to the expiry file, created on the first expiry
+    fn expire(&mut self, id: Self::LogSize) -> anyhow::Result<()> {
+        self.redact(id)?;
+        OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(self.dir.join(EXPIRED_FILE))?
+            .write_all(&id.to_be_bytes())?;
+        self.expired.insert(id);
+        Ok(())
+    }
+
+    fn expired(&self, id: Self::LogSize) -> anyhow::Result<bool> {
+        Ok(self.expired.contains(&id))
+    }
+
+    fn add_meta(&mut self, id: Self::LogSize, meta: RecordMeta) -> anyhow::Result<()> {
+        check_unsealed(&self.sealed)?;
+        write_all_at(
+            &self.timestamps,
+            &meta.timestamp.to_be_bytes(),
+            id * META_SIZE_IN_BYTES,
+        )?;
+        Ok(())
+    }
+
+    fn get_meta(&self, id: Self::LogSize) -> anyhow::Result<Option<RecordMeta>> {
+        if self.timestamps.metadata()?.len() < (id + 1) * META_SIZE_IN_BYTES {
+            return Ok(None);
+        }
+        let mut b = [0_u8; META_SIZE_IN_BYTES as usize];
+        read_exact_at(&self.timestamps, &mut b, id * META_SIZE_IN_BYTES)?;
+        let timestamp = u64::from_be_bytes(b);
+        Ok((timestamp > 0).then(|| RecordMeta { timestamp }))
+    }
+
+    /// Appends the annotation to the annotation file, created on the first annotation and read back when the log is opened
+    fn set_annotation(&mut self, id: Self::LogSize, annotation: Vec<u8>) -> anyhow::Result<()> {
+        check_unsealed(&self.sealed)?;
+        if id >= self.size()? {
+            return Err(LogError::InvalidIndex(0, id).into());
+        }
+        let mut b = Vec::with_capacity(2 * std::mem::size_of::<u64>() + annotation.len());
+        b.extend_from_slice(&id.to_be_bytes());
+        b.extend_from_slice(&(annotation.len() as u64).to_be_bytes());
+        b.extend_from_slice(&annotation);
+        OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(self.dir.join(ANNOTATION_FILE))?
+            .write_all(&b)?;
+        self.annotations.insert(id, annotation);
+        Ok(())
+    }
+
+    fn get_annotation(&self, id: Self::LogSize) -> anyhow::Result<Option<Vec<u8>>> {
+        Ok(self.annotations.get(&id).cloned())
+    }
+
+    /// Writes the final tree head to the seal file, then opens the files again read only
+    fn seal(&mut self) -> anyhow::Result<LogTree<Self::LogSize>> {
+        if let Some(tree) = &self.sealed {
+            return Ok(tree.clone());
+        }
+        let tree = self.latest()?;
+        write(self.dir.join(SEAL_FILE), rmp_serde::to_vec(&tree)?)?;
+        let options = file_options(true);
+        self.data = options.open(self.dir.join("data.bin"))?;
+        self.index = options.open(self.dir.join("index.bin"))?;
+        for (level, file) in self.hashes.iter_mut().enumerate() {
+            *file = options.open(self.dir.join(format!("hash{}.bin", level)))?;
+        }
+        self.lookup_file = options.open(self.dir.join("lookup.bin"))?;
+        self.timestamps = options.open(self.dir.join("timestamps.bin"))?;
+        self.sealed = Some(tree.clone());
+        Ok(tree)
+    }
+
+    fn sealed(&self) -> anyhow::Result<Option<LogTree<Self::LogSize>>> {
+        Ok(self.sealed.clone())
+    }
+
+    /// Syncs the data, index, hash, lookup and timestamp files
+    fn sync(&self) -> anyhow::Result<()> {
+        self.data.sync_data()?;
+        self.index.sync_data()?;
+        for file in self.hashes.iter() {
+            file.sync_data()?;
+        }
+        self.lookup_file.sync_data()?;
+        self.timestamps.sync_data()?;
+        Ok(())
+    }
+
+    fn pending_hashes(&mut self) -> Option<&mut PendingHashes<Self::LogSize>> {
+        Some(&mut self.pending_hashes)
+    }
 
-        if hs.len() <= level {
+    fn add_hash(&mut self, level: LogHeight, hash: String) -> anyhow::Result<Self::LogSize> {
+        check_unsealed(&self.sealed)?;
+        self.root.clear();
+        let hs = &mut self.hashes;
+        if hs.len() == level {
             let p = self.dir.join(format!("hash{}.bin", level));
             hs.push(
                 OpenOptions::new()
@@ -117,34 +748,90 @@ impl<'a, T: Serialize + DeserializeOwned> TransparentLog<'a, T> for FileLog<'a,
             .get_mut(level)
             .ok_or(LogError::InvalidHeight::<Self::LogSize>(level))?;
         let b = hash.as_bytes();
-        let l = v.metadata()?.len() / HASH_SIZE_IN_BYTES as u64;
+        let l = level_size(v)?;
         v.write_all(b)?;
+        if level == 0 {
+            self.lookup_file.write_all(b)?;
+            self.lookup_file.write_all(&l.to_be_bytes())?;
+            self.lookup.insert(hash, l);
+        }
         Ok(l)
     }
 
+    fn index_of(&self, hash: &str) -> anyhow::Result<Option<Self::LogSize>> {
+        Ok(self.lookup.first(hash))
+    }
+
+    fn indices_of(&self, hash: &str) -> anyhow::Result<Vec<Self::LogSize>> {
+        Ok(self.lookup.all(hash).to_vec())
+    }
+
     fn get_hash(
         &self,
         level: LogHeight,
         index: Self::LogSize,
-    ) -> anyhow::Result<MaybeOwned<String>> {
-        let mut hs = self.hashes.borrow_mut();
-        let v = hs
-            .get_mut(level)
-            .ok_or(LogError::InvalidHeight::<Self::LogSize>(level))?;
-        v.seek(SeekFrom::Start((HASH_SIZE_IN_BYTES as u64) * index))?;
-        let mut b2 = [0_u8; HASH_SIZE_IN_BYTES];
-        v.read_exact(&mut b2)?;
-        Ok(String::from_utf8_lossy(&b2).into_owned().into())
+    ) -> anyhow::Result<MaybeOwned<'_, String>> {
+        let b = self.read_hashes(level, index, 1)?;
+        Ok(String::from_utf8_lossy(&b).into_owned().into())
+    }
+
+    /// Read the hashes level by level, in index order, reading consecutive hashes at once
+    fn proofs<I>(
+        &self,
+        positions: I,
+    ) -> anyhow::Result<HashMap<LogTreePosition<Self::LogSize>, String>>
+    where
+        I: Iterator<Item = LogTreePosition<Self::LogSize>>,
+    {
+        let positions: Vec<_> = positions.collect();
+        let _span = proofs_span("file", positions.len());
+        let mut by_level: BTreeMap<LogHeight, Vec<u64>> = BTreeMap::new();
+        for p in positions {
+            by_level.entry(p.level).or_default().push(p.index);
+        }
+        let mut proofs = HashMap::new();
+        for (level, mut indices) in by_level {
+            indices.sort_unstable();
+            indices.dedup();
+            let mut ix = 0;
+            while ix < indices.len() {
+                let mut end = ix + 1;
+                while end < indices.len() && indices[end] == indices[end - 1] + 1 {
+                    end += 1;
+                }
+                let b = self.read_hashes(level, indices[ix], end - ix)?;
+                for (index, chunk) in indices[ix..end]
+                    .iter()
+                    .zip(b.chunks_exact(HASH_SIZE_IN_BYTES))
+                {
+                    proofs.insert(
+                        LogTreePosition {
+                            level,
+                            index: *index,
+                        },
+                        String::from_utf8_lossy(chunk).into_owned(),
+                    );
+                }
+                ix = end;
+            }
+        }
+        record_proofs("file", proofs.len());
+        Ok(proofs)
     }
 }
 
 #[cfg(test)]
 mod tests {
 
+    use super::write_all_at;
     use crate::test_helpers::*;
-    use crate::{FileLog, InMemoryLogClientBuilder};
-    use std::fs::{create_dir, remove_dir_all};
+    use crate::{
+        hash, hash_bytes, proof_positions, prove_inclusion, verify, CheckDepth, CompressionError,
+        FileLog, InMemoryLogClientBuilder, LogTreePosition, Raw, TransparentLog,
+    };
+    use std::fs::{create_dir, remove_dir_all, OpenOptions};
     use std::path::{Path, PathBuf};
+    use std::thread;
 
     use serial_test::serial;
 
@@ -152,9 +839,9 @@ mod tests {
         let path = Path::new("./test_data");
         println!("{} {}", path.display(), path.exists());
         if path.exists() {
-            remove_dir_all(&path)?;
+            remove_dir_all(path)?;
         }
-        create_dir(&path)?;
+        create_dir(path)?;
         Ok(PathBuf::from(path))
     }
 
@@ -166,6 +853,14 @@ mod tests {
         empty(&mut ml)
     }
 
+    #[test]
+    #[serial]
+    fn file_out_of_range() -> anyhow::Result<()> {
+        let path = setup()?;
+        let mut ml: FileLog<LogRecord> = FileLog::open(&path)?;
+        out_of_range(&mut ml)
+    }
+
     #[test]
     #[serial]
     fn file_add() -> anyhow::Result<()> {
@@ -174,6 +869,358 @@ mod tests {
         add(&mut ml)
     }
 
+    #[test]
+    #[serial]
+    fn file_lookup() -> anyhow::Result<()> {
+        let path = setup()?;
+        let mut ml: FileLog<LogRecord> = FileLog::open(&path)?;
+        lookup(&mut ml)
+    }
+
+    #[test]
+    #[serial]
+    fn file_range() -> anyhow::Result<()> {
+        let path = setup()?;
+        let mut ml: FileLog<LogRecord> = FileLog::open(&path)?;
+        range(&mut ml)
+    }
+
+    #[test]
+    #[serial]
+    fn file_iter() -> anyhow::Result<()> {
+        let path = setup()?;
+        let mut ml: FileLog<LogRecord> = FileLog::open(&path)?;
+        iter(&mut ml)
+    }
+
+    #[test]
+    #[serial]
+    fn file_tiles() -> anyhow::Result<()> {
+        let path = setup()?;
+        let mut ml: FileLog<LogRecord> = FileLog::open(&path)?;
+        tiles(&mut ml)
+    }
+
+    #[test]
+    #[serial]
+    fn file_batch_proofs() -> anyhow::Result<()> {
+        let path = setup()?;
+        let mut ml: FileLog<LogRecord> = FileLog::open(&path)?;
+        batch_proofs(&mut ml)
+    }
+
+    #[test]
+    #[serial]
+    fn file_range_proofs() -> anyhow::Result<()> {
+        let path = setup()?;
+        let mut ml: FileLog<LogRecord> = FileLog::open(&path)?;
+        range_proofs(&mut ml)
+    }
+
+    #[test]
+    #[serial]
+    fn file_historic_roots() -> anyhow::Result<()> {
+        let path = setup()?;
+        let mut ml: FileLog<LogRecord> = FileLog::open(&path)?;
+        historic_roots(&mut ml)
+    }
+
+    #[test]
+    #[serial]
+    fn file_consistency_between() -> anyhow::Result<()> {
+        let path = setup()?;
+        let mut ml: FileLog<LogRecord> = FileLog::open(&path)?;
+        consistency_between_sizes(&mut ml)
+    }
+
+    #[test]
+    #[serial]
+    fn file_audit_paths() -> anyhow::Result<()> {
+        let path = setup()?;
+        let mut ml: FileLog<LogRecord> = FileLog::open(&path)?;
+        audit_paths(&mut ml)
+    }
+
+    #[test]
+    #[serial]
+    fn file_prove_hashes() -> anyhow::Result<()> {
+        let path = setup()?;
+        let mut ml: FileLog<LogRecord> = FileLog::open(&path)?;
+        prove_hashes(&mut ml)
+    }
+
+    #[test]
+    #[serial]
+    fn file_annotations() -> anyhow::Result<()> {
+        let path = setup()?;
+        let mut ml: FileLog<LogRecord> = FileLog::open(&path)?;
+        annotations(&mut ml)?;
+        let ml: FileLog<LogRecord> = FileLog::open(&path)?;
+        assert_eq!(Some(b"hidden".to_vec()), ml.get_annotation(1)?);
+        assert_eq!(Some(vec![]), ml.get_annotation(2)?);
+        assert_eq!(None, ml.get_annotation(0)?);
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    fn file_duplicates() -> anyhow::Result<()> {
+        let path = setup()?;
+        let mut ml: FileLog<LogRecord> = FileLog::open(&path)?;
+        duplicates(&mut ml)?;
+        let ml: FileLog<LogRecord> = FileLog::open(&path)?;
+        assert_eq!(
+            vec![1, 5, 6],
+            ml.indices_of(&hash(&LogRecord::new("rec1"))?)?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn file_send_sync() {
+        fn send_sync<X: Send + Sync>() {}
+        send_sync::<FileLog<LogRecord>>();
+        send_sync::<FileLog<Vec<u8>, Raw>>();
+    }
+
+    #[test]
+    #[serial]
+    fn file_concurrent_reads() -> anyhow::Result<()> {
+        let path = setup()?;
+        let mut ml: FileLog<LogRecord> = FileLog::open(&path)?;
+        append_multiple(&mut ml, 100)?;
+        let tree = &ml.latest()?;
+        let ml = &ml;
+        thread::scope(|s| {
+            let readers: Vec<_> = (0..4)
+                .map(|t| {
+                    s.spawn(move || -> anyhow::Result<()> {
+                        for i in (t..100).step_by(4) {
+                            let record = ml.get(i)?.expect("record");
+                            assert_eq!(LogRecord::new(&format!("rec{}", i)), *record);
+                            assert!(prove_inclusion(ml, i, tree.size)?.verify(tree));
+                        }
+                        Ok(())
+                    })
+                })
+                .collect();
+            readers
+                .into_iter()
+                .try_for_each(|r| r.join().expect("reader thread"))
+        })
+    }
+
+    #[test]
+    #[serial]
+    fn file_bulk() -> anyhow::Result<()> {
+        let path = setup()?;
+        let mut ml: FileLog<LogRecord> = FileLog::open(&path)?;
+        bulk(&mut ml)
+    }
+
+    #[test]
+    #[serial]
+    fn file_timestamps() -> anyhow::Result<()> {
+        let path = setup()?;
+        let mut ml: FileLog<LogRecord> = FileLog::open(&path)?;
+        timestamps(&mut ml)
+    }
+
+    #[test]
+    #[serial]
+    fn file_export_import() -> anyhow::Result<()> {
+        let path = setup()?;
+        let mut ml: FileLog<LogRecord> = FileLog::open(&path)?;
+        let path2 = path.join("import");
+        create_dir(&path2)?;
+        let mut ml2: FileLog<LogRecord> = FileLog::open(&path2)?;
+        export_import(&mut ml, &mut ml2)
+    }
+
+    #[test]
+    #[serial]
+    fn file_mirror() -> anyhow::Result<()> {
+        let path = setup()?;
+        let mut ml: FileLog<LogRecord> = FileLog::open(&path)?;
+        let path2 = path.join("mirror");
+        create_dir(&path2)?;
+        let mut ml2: FileLog<LogRecord> = FileLog::open(&path2)?;
+        mirror(&mut ml, &mut ml2)
+    }
+
+    #[test]
+    #[serial]
+    fn file_properties() -> anyhow::Result<()> {
+        let path = setup()?;
+        properties(|| {
+            setup()?;
+            FileLog::<LogRecord>::open(&path)
+        })
+    }
+
+    #[test]
+    #[serial]
+    fn file_hash_only() -> anyhow::Result<()> {
+        let path = setup()?;
+        let mut ml: FileLog<LogRecord> = FileLog::open(&path)?;
+        hash_only(&mut ml)?;
+        drop(ml);
+        let ml: FileLog<LogRecord> = FileLog::open(&path)?;
+        check_13(&ml)?;
+        assert_tombstone(ml.get(3));
+        assert_tombstone(ml.iter().nth(3).unwrap());
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    fn file_invalid_hash_only() -> anyhow::Result<()> {
+        let path = setup()?;
+        let mut ml: FileLog<LogRecord> = FileLog::open(&path)?;
+        invalid_hash_only(&mut ml)?;
+        drop(ml);
+        let ml: FileLog<LogRecord> = FileLog::open(&path)?;
+        check_13(&ml)
+    }
+
+    #[test]
+    #[serial]
+    fn file_compact() -> anyhow::Result<()> {
+        let path = setup()?;
+        let mut ml: FileLog<LogRecord> = FileLog::open(&path)?;
+        test_13(&mut ml)?;
+        ml.redact(9)?;
+        ml.expire(3)?;
+        let tree = ml.latest()?;
+        let length = std::fs::metadata(path.join("data.bin"))?.len();
+        let reclaimed = ml.compact()?;
+        assert!(reclaimed > 0);
+        assert_eq!(
+            length - reclaimed,
+            std::fs::metadata(path.join("data.bin"))?.len()
+        );
+        assert_eq!(0, ml.compact()?);
+        assert_eq!(tree, ml.latest()?);
+        check_13(&ml)?;
+        assert_tombstone(ml.get(9));
+        assert!(ml.expired(3)?);
+        assert_eq!(Some(&LogRecord::new("rec8")), ml.get(8)?.as_deref());
+        assert_eq!(
+            vec![LogRecord::new("rec10"), LogRecord::new("rec11")],
+            ml.get_range(10, 12)?
+                .into_iter()
+                .map(|r| r.into_owned())
+                .collect::<Vec<_>>()
+        );
+        ml.append(LogRecord::new("rec13"))?;
+
+        let ml: FileLog<LogRecord> = FileLog::open(&path)?;
+        assert_eq!(14, ml.size()?);
+        assert_eq!(Some(&LogRecord::new("rec12")), ml.get(12)?.as_deref());
+        assert_eq!(Some(&LogRecord::new("rec13")), ml.get(13)?.as_deref());
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    fn file_compact_interrupted() -> anyhow::Result<()> {
+        let path = setup()?;
+        let mut ml: FileLog<LogRecord> = FileLog::open(&path)?;
+        test_13(&mut ml)?;
+        drop(ml);
+
+        // compacted files without the marker are abandoned
+        std::fs::write(path.join("data.compact"), b"torn")?;
+        let ml: FileLog<LogRecord> = FileLog::open(&path)?;
+        assert!(!path.join("data.compact").exists());
+        assert_eq!(Some(&LogRecord::new("rec8")), ml.get(8)?.as_deref());
+        drop(ml);
+
+        // complete compacted files replace the old ones
+        std::fs::copy(path.join("data.bin"), path.join("data.compact"))?;
+        std::fs::copy(path.join("index.bin"), path.join("index.compact"))?;
+        std::fs::write(path.join("compact.bin"), b"")?;
+        let ml: FileLog<LogRecord> = FileLog::open(&path)?;
+        assert!(!path.join("compact.bin").exists());
+        assert!(!path.join("index.compact").exists());
+        assert_eq!(13, ml.size()?);
+        check_13(&ml)?;
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    fn file_self_check() -> anyhow::Result<()> {
+        let path = setup()?;
+        let mut ml: FileLog<LogRecord> = FileLog::open(&path)?;
+        self_check(&mut ml)?;
+        drop(ml);
+        let file = OpenOptions::new()
+            .write(true)
+            .open(path.join("hash2.bin"))?;
+        write_all_at(&file, hash(&LogRecord::new("rot"))?.as_bytes(), 64)?;
+        let ml: FileLog<LogRecord> = FileLog::open(&path)?;
+        let report = ml.self_check(CheckDepth::Full)?;
+        assert_eq!(
+            Some(LogTreePosition { level: 2, index: 1 }),
+            report.first_bad
+        );
+        assert_eq!(8, report.checked);
+        // the sampled check misses the hash, but not its parent
+        let report = ml.self_check(CheckDepth::Sampled(1))?;
+        assert_eq!(
+            Some(LogTreePosition { level: 3, index: 0 }),
+            report.first_bad
+        );
+        assert_eq!(5, report.checked);
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    fn file_expire() -> anyhow::Result<()> {
+        let path = setup()?;
+        let mut ml: FileLog<LogRecord> = FileLog::open(&path)?;
+        expire(&mut ml)?;
+        let ml: FileLog<LogRecord> = FileLog::open(&path)?;
+        assert!(ml.expired(9)?);
+        assert!(!ml.expired(10)?);
+        assert!(ml.get(9).is_err());
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    fn file_redact() -> anyhow::Result<()> {
+        let path = setup()?;
+        let mut ml: FileLog<LogRecord> = FileLog::open(&path)?;
+        redact(&mut ml)?;
+        drop(ml);
+        let data = std::fs::read(path.join("data.bin"))?;
+        assert!(data.windows(4).any(|w| w == b"rec8"));
+        assert!(!data.windows(4).any(|w| w == b"rec9"));
+        let ml: FileLog<LogRecord> = FileLog::open(&path)?;
+        assert_eq!(14, ml.size()?);
+        assert_tombstone(ml.get(9));
+        assert_eq!(Some(&LogRecord::new("rec13")), ml.get(13)?.as_deref());
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    fn file_seal() -> anyhow::Result<()> {
+        let path = setup()?;
+        let mut ml: FileLog<LogRecord> = FileLog::open(&path)?;
+        seal(&mut ml)?;
+        let tree = ml.sealed()?;
+        drop(ml);
+        let mut ml: FileLog<LogRecord> = FileLog::open(&path)?;
+        assert_eq!(tree, ml.sealed()?);
+        assert_sealed(ml.append(LogRecord::new("rec13")));
+        check_13(&ml)?;
+        Ok(())
+    }
+
     #[test]
     #[serial]
     fn file_13() -> anyhow::Result<()> {
@@ -185,6 +1232,133 @@ mod tests {
         check_13(&ml)
     }
 
+    #[test]
+    #[serial]
+    fn file_sync() -> anyhow::Result<()> {
+        let path = setup()?;
+        let mut ml: FileLog<LogRecord> = FileLog::open(&path)?;
+        append_multiple(&mut ml, 13)?;
+        ml.sync()?;
+        ml.redact(9)?;
+        ml.sync()?;
+
+        let ml: FileLog<LogRecord> = FileLog::open(&path)?;
+        assert_eq!(13, ml.size()?);
+        assert_tombstone(ml.get(9));
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    fn file_compressed_13() -> anyhow::Result<()> {
+        let path = setup()?;
+        let mut ml: FileLog<LogRecord> =
+            FileLog::open_with_compressor(&path, Box::new(RleCompressor))?;
+        test_13(&mut ml)?;
+
+        let ml: FileLog<LogRecord> = FileLog::open_with_compressor(&path, Box::new(RleCompressor))?;
+        check_13(&ml)?;
+        assert_eq!(13, ml.iter().count());
+        assert_eq!(3, ml.get_range(10, 13)?.len());
+        let err = FileLog::<LogRecord>::open(&path).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<CompressionError>(),
+            Some(CompressionError::Mismatch { expected: None, .. })
+        ));
+        Ok(())
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    #[serial]
+    fn file_codec_json() -> anyhow::Result<()> {
+        let path = setup()?;
+        let mut ml = FileLog::<LogRecord, crate::Json>::open_with_codec(&path, None)?;
+        codec_records::<crate::Json, _>(&mut ml)?;
+
+        let ml = FileLog::<LogRecord, crate::Json>::open_with_codec(&path, None)?;
+        assert_eq!(13, ml.iter().count());
+        let err = FileLog::<LogRecord>::open(&path).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<crate::CodecError>(),
+            Some(crate::CodecError::Mismatch { expected, found }) if expected == "msgpack" && found == "json"
+        ));
+
+        let path = setup()?;
+        let mut ml = FileLog::<LogRecord, crate::Json>::open_with_codec(
+            &path,
+            Some(Box::new(RleCompressor)),
+        )?;
+        codec_records::<crate::Json, _>(&mut ml)?;
+        Ok(())
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    #[serial]
+    fn file_codec_default() -> anyhow::Result<()> {
+        let path = setup()?;
+        let mut ml: FileLog<LogRecord> = FileLog::open(&path)?;
+        append_multiple(&mut ml, 3)?;
+        let err = FileLog::<LogRecord, crate::Json>::open_with_codec(&path, None).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<crate::CodecError>(),
+            Some(crate::CodecError::Mismatch { expected, found }) if expected == "json" && found == "msgpack"
+        ));
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    fn file_codec_raw() -> anyhow::Result<()> {
+        let path = setup()?;
+        let mut ml = FileLog::<Vec<u8>, Raw>::open_with_codec(&path, None)?;
+        let blobs: Vec<Vec<u8>> = (0..13_u8).map(|i| vec![i; i as usize]).collect();
+        let mut records = vec![ml.append(blobs[0].clone())?];
+        records.extend(ml.bulk_append(blobs[1..].to_vec())?);
+        for (record, blob) in records.iter().zip(&blobs) {
+            assert_eq!(hash_bytes(blob), record.hash);
+        }
+        let stored = std::fs::read(path.join("data.bin"))?;
+        assert_eq!(blobs.concat(), stored);
+
+        let ml = FileLog::<Vec<u8>, Raw>::open_with_codec(&path, None)?;
+        assert_eq!(Some(&blobs[9]), ml.get(9)?.as_deref());
+        assert_eq!(Some(9), ml.index_of(&hash_bytes(&blobs[9]))?);
+        let tree = ml.latest()?;
+        let proofs = ml.proofs(proof_positions(9, tree.size).into_iter())?;
+        assert!(verify(&tree, &records[9], &proofs));
+        Ok(())
+    }
+
+    #[cfg(feature = "cbor")]
+    #[test]
+    #[serial]
+    fn file_codec_cbor() -> anyhow::Result<()> {
+        let path = setup()?;
+        let mut ml = FileLog::<LogRecord, crate::Cbor>::open_with_codec(&path, None)?;
+        codec_records::<crate::Cbor, _>(&mut ml)
+    }
+
+    #[cfg(feature = "bincode")]
+    #[test]
+    #[serial]
+    fn file_codec_bincode() -> anyhow::Result<()> {
+        let path = setup()?;
+        let mut ml = FileLog::<LogRecord, crate::Bincode>::open_with_codec(&path, None)?;
+        codec_records::<crate::Bincode, _>(&mut ml)
+    }
+
+    #[test]
+    #[serial]
+    fn file_compressed_properties() -> anyhow::Result<()> {
+        let path = setup()?;
+        properties(|| {
+            setup()?;
+            FileLog::<LogRecord>::open_with_compressor(&path, Box::new(RleCompressor))
+        })
+    }
+
     #[test]
     #[serial]
     fn file_memory_13() -> anyhow::Result<()> {
@@ -194,6 +1368,15 @@ mod tests {
         client_13(&mut ml, &mut client)
     }
 
+    #[test]
+    #[serial]
+    fn file_raw_bytes() -> anyhow::Result<()> {
+        let path = setup()?;
+        let mut ml: FileLog<LogRecord> = FileLog::open(&path)?;
+        let mut client = InMemoryLogClientBuilder::new(&ml)?.build();
+        raw_bytes(&mut ml, &mut client)
+    }
+
     #[test]
     #[serial]
     fn file_memory_13_no_cache() -> anyhow::Result<()> {
@@ -203,6 +1386,15 @@ mod tests {
         client_13_nocache(&mut ml, &mut client)
     }
 
+    #[test]
+    #[serial]
+    fn file_memory_records() -> anyhow::Result<()> {
+        let path = setup()?;
+        let mut ml: FileLog<LogRecord> = FileLog::open(&path)?;
+        let mut client = InMemoryLogClientBuilder::new(&ml)?.no_cache().build();
+        client_records(&mut ml, &mut client)
+    }
+
     #[test]
     #[serial]
     fn file_verify_tree_prefix() -> anyhow::Result<()> {
@@ -210,4 +1402,33 @@ mod tests {
         let mut ml: FileLog<LogRecord> = FileLog::open(&path)?;
         test_verify_tree_prefix(&mut ml)
     }
+
+    #[test]
+    #[serial]
+    #[cfg(feature = "mmap")]
+    fn file_mmap_out_of_range() -> anyhow::Result<()> {
+        let path = setup()?;
+        let mut ml: FileLog<LogRecord> = FileLog::open_with_mmap(&path)?;
+        out_of_range(&mut ml)
+    }
+
+    #[test]
+    #[serial]
+    #[cfg(feature = "mmap")]
+    fn file_mmap_batch_proofs() -> anyhow::Result<()> {
+        let path = setup()?;
+        let mut ml: FileLog<LogRecord> = FileLog::open_with_mmap(&path)?;
+        batch_proofs(&mut ml)
+    }
+
+    #[test]
+    #[serial]
+    #[cfg(feature = "mmap")]
+    fn file_mmap_properties() -> anyhow::Result<()> {
+        let path = setup()?;
+        properties(|| {
+            setup()?;
+            FileLog::<LogRecord>::open_with_mmap(&path)
+        })
+    }
 }
@@ -0,0 +1,147 @@
+//! # Following a log
+//!
+//! A `Follower` iterates over the entries of a log from a given index, blocking at the end of the log
+//! until new entries are appended, the building block of monitors reading a log as it grows.
+//! It waits for the events of a `NotifyingLog` subscription if given one, and otherwise polls the size of the log,
+//! which works for remote logs too. An entry that cannot be read, like a tombstone, is returned as an error and skipped.
+use crate::base::*;
+use crate::notify::AppendEvent;
+use serde::{Deserialize, Serialize};
+use std::marker::PhantomData;
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::thread::sleep;
+use std::time::Duration;
+
+/// The default time between two checks of the log size
+const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// An endless iterator over the entries of a log and their index, waiting for new entries at the end of the log
+pub struct Follower<'l, TL, T, LogSize> {
+    log: &'l TL,
+    next: LogSize,
+    events: Option<Receiver<AppendEvent<LogSize>>>,
+    poll_interval: Duration,
+    record: PhantomData<T>,
+}
+
+impl<'l, TL, T, LogSize> Follower<'l, TL, T, LogSize> {
+    /// Follow the log from the entry with the given index, polling its size
+    pub fn new(log: &'l TL, from: LogSize) -> Self {
+        Self {
+            log,
+            next: from,
+            events: None,
+            poll_interval: DEFAULT_POLL_INTERVAL,
+            record: PhantomData,
+        }
+    }
+
+    /// Wake up on the events of a subscription to the log, polling only if none comes within the poll interval
+    pub fn subscription(&mut self, events: Receiver<AppendEvent<LogSize>>) -> &mut Self {
+        self.events = Some(events);
+        self
+    }
+
+    /// Set the time between two checks of the log size
+    pub fn poll_interval(&mut self, poll_interval: Duration) -> &mut Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    /// The index of the next entry returned
+    pub fn position(&self) -> &LogSize {
+        &self.next
+    }
+
+    /// Wait for an event, or for the poll interval to elapse
+    fn wait(&mut self) {
+        match &self.events {
+            Some(events) => match events.recv_timeout(self.poll_interval) {
+                // the log is read anew, the pending events are only wake ups
+                Ok(_) => while events.try_recv().is_ok() {},
+                Err(RecvTimeoutError::Timeout) => {}
+                // the notifying log is gone, and its subscription with it
+                Err(RecvTimeoutError::Disconnected) => self.events = None,
+            },
+            None => sleep(self.poll_interval),
+        }
+    }
+}
+
+impl<'l, 'a, TL, T, LogSize> Iterator for Follower<'l, TL, T, LogSize>
+where
+    T: Serialize + Deserialize<'a> + Clone,
+    TL: TransparentLog<'a, T, LogSize = LogSize>,
+    LogSize: num::Integer + Copy,
+{
+    type Item = anyhow::Result<(LogSize, T)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.log.size() {
+                Ok(size) if self.next < size => {
+                    let id = self.next;
+                    self.next = id + LogSize::one();
+                    return match self.log.get(id) {
+                        Ok(Some(r)) => Some(Ok((id, r.into_owned()))),
+                        Ok(None) => continue,
+                        Err(e) => Some(Err(e)),
+                    };
+                }
+                Ok(_) => self.wait(),
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::test_helpers::*;
+    use crate::*;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn follow_polling() -> anyhow::Result<()> {
+        let mut ml = InMemoryLog::default();
+        append_multiple(&mut ml, 3)?;
+        ml.append_hash_only(hash(&LogRecord::new("rec3"))?)?;
+        ml.append(LogRecord::new("rec4"))?;
+        let mut follower = Follower::new(&ml, 1);
+        follower.poll_interval(Duration::from_millis(1));
+        assert_eq!((1, LogRecord::new("rec1")), follower.next().unwrap()?);
+        assert_eq!((2, LogRecord::new("rec2")), follower.next().unwrap()?);
+        assert!(follower.next().unwrap().is_err());
+        assert_eq!((4, LogRecord::new("rec4")), follower.next().unwrap()?);
+        assert_eq!(&5, follower.position());
+        Ok(())
+    }
+
+    #[test]
+    fn follow_subscription() -> anyhow::Result<()> {
+        let shared = SharedLog::new(NotifyingLog::new(InMemoryLog::default()));
+        append_multiple(&mut &shared, 2)?;
+        let events = shared.write()?.subscribe();
+        let reader = &shared;
+        let mut follower = Follower::new(&reader, 0);
+        // only the subscription wakes the follower up in time
+        follower
+            .subscription(events)
+            .poll_interval(Duration::from_secs(60));
+        thread::scope(|s| {
+            s.spawn(|| {
+                for i in 2..6 {
+                    thread::sleep(Duration::from_millis(10));
+                    (&shared).append(LogRecord::new(&format!("rec{}", i)))?;
+                }
+                Ok::<_, anyhow::Error>(())
+            });
+            for (i, entry) in follower.take(6).enumerate() {
+                assert_eq!((i, LogRecord::new(&format!("rec{}", i))), entry?);
+            }
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,106 @@
+//! # Fuzzing entry points
+//!
+//! This module decodes arbitrary bytes into mutated proofs, tree heads and record hashes, and checks that verification
+//! never panics, and never accepts a record that is not in the tree. The `fuzz` directory wraps it in cargo-fuzz targets.
+//!
+use crate::test_helpers::{append_multiple, model_root, LogRecord};
+use crate::{proof_positions, verify, verify_tree, InMemoryLog, LogTree, Record, TransparentLog};
+
+/// The maximum number of records in the fuzzed log
+const MAX_RECORDS: u8 = 40;
+
+/// Verify a proof built from the given bytes, panicking if a forged proof is accepted
+/// The first three bytes choose the log size, the record and the tree size, then each group of three bytes is a mutation
+pub fn verify_bytes(data: &[u8]) {
+    if data.len() < 3 {
+        return;
+    }
+    let n = (data[0] % MAX_RECORDS) as usize + 1;
+    let mut log: InMemoryLog<LogRecord> = InMemoryLog::default();
+    append_multiple(&mut log, n).expect("in memory append");
+    let leaves: Vec<String> = (0..n)
+        .map(|i| log.get_hash(0, i).expect("leaf hash").into_owned())
+        .collect();
+
+    let size = (data[2] as usize) % n + 1;
+    let id = (data[1] as usize) % size;
+    let mut tree = LogTree {
+        size,
+        hash: model_root(&leaves[..size]),
+        origin: None,
+    };
+    let mut record = Record {
+        id,
+        hash: leaves[id].clone(),
+        meta: None,
+    };
+    let mut proofs = log
+        .proofs(proof_positions(id, size).into_iter())
+        .expect("in memory proofs");
+    assert!(verify(&tree, &record, &proofs), "genuine proof rejected");
+
+    let hash_of = |b: u8| match b as usize % (n + 1) {
+        0 => String::new(),
+        i => leaves[i - 1].clone(),
+    };
+    for m in data[3..].chunks_exact(3) {
+        match m[0] % 7 {
+            0 => {
+                if let Some(p) = proofs
+                    .keys()
+                    .nth(m[1] as usize % (proofs.len() + 1))
+                    .cloned()
+                {
+                    proofs.remove(&p);
+                }
+            }
+            1 => {
+                proofs.insert(
+                    (m[1] as usize % 8, m[2] as usize % 64).into(),
+                    hash_of(m[2]),
+                );
+            }
+            2 => {
+                let k = m[1] as usize % (proofs.len() + 1);
+                if let Some(h) = proofs.values_mut().nth(k) {
+                    *h = hash_of(m[2]);
+                }
+            }
+            3 => record.hash = hash_of(m[1]),
+            4 => record.id = m[1] as usize % (n + 2),
+            5 => tree.size = m[1] as usize % (n + 2),
+            _ => tree.hash = model_root(&leaves[..(m[1] as usize % (n + 1))]),
+        }
+    }
+
+    // verify_tree trusts the proof hashes, so it only has to return
+    verify_tree(&tree, &proofs);
+    if verify(&tree, &record, &proofs) {
+        assert!(
+            record.id < tree.size && tree.size <= n,
+            "record outside the tree"
+        );
+        assert_eq!(leaves[record.id], record.hash, "forged record accepted");
+        assert_eq!(
+            model_root(&leaves[..tree.size]),
+            tree.hash,
+            "forged tree accepted"
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::fuzz::*;
+    use proptest::prelude::*;
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(2000))]
+
+        #[test]
+        fn fuzz_verify(data in proptest::collection::vec(any::<u8>(), 0..30)) {
+            verify_bytes(&data);
+        }
+    }
+}
@@ -0,0 +1,180 @@
+//! Gossip: clients exchange the tree heads they verified and check them against each other through the log,
+//! so a log showing different views to different clients gets detected
+use crate::base::*;
+use serde::{Deserialize, Serialize};
+use std::fmt::Display;
+use thiserror::Error;
+
+/// Specific Errors for gossip
+#[derive(Debug, Clone, Error)]
+pub enum GossipError {
+    #[error("Tree heads of different logs: {0:?} and {1:?}")]
+    OriginMismatch(Option<String>, Option<String>),
+    #[error("Tree head of {0} records is ahead of the log")]
+    AheadOfLog(String),
+    #[error("Split view: tree heads of {0} and {1} records are inconsistent")]
+    SplitView(String, String),
+}
+
+/// Reconciliation of the tree heads seen by different clients
+pub struct Gossip;
+
+impl Gossip {
+    /// Check two tree heads of the same log are consistent, using the proofs of the log
+    /// Returns the most recent of the two heads, that both clients can adopt
+    pub fn reconcile<'a, T, TL>(
+        head_a: &LogTree<TL::LogSize>,
+        head_b: &LogTree<TL::LogSize>,
+        log: &TL,
+    ) -> anyhow::Result<LogTree<TL::LogSize>>
+    where
+        T: Serialize + Deserialize<'a>,
+        TL: TransparentLog<'a, T>,
+        TL::LogSize: Display,
+    {
+        let origin = match (&head_a.origin, &head_b.origin) {
+            (Some(a), Some(b)) if a != b => {
+                return Err(GossipError::OriginMismatch(Some(a.clone()), Some(b.clone())).into())
+            }
+            (a, b) => a.clone().or_else(|| b.clone()),
+        };
+        let (small, large) = if head_a.size <= head_b.size {
+            (head_a, head_b)
+        } else {
+            (head_b, head_a)
+        };
+        if large.size > log.size()? {
+            return Err(GossipError::AheadOfLog(large.size.to_string()).into());
+        }
+        if !log
+            .consistency_between(small.size, large.size)?
+            .verify(small, large)
+        {
+            return Err(
+                GossipError::SplitView(small.size.to_string(), large.size.to_string()).into(),
+            );
+        }
+        Ok(LogTree {
+            size: large.size,
+            hash: large.hash.clone(),
+            origin,
+        })
+    }
+
+    /// Reconcile the latest heads of two clients, both clients moving to the most recent one
+    pub fn exchange<'a, T, TL, LC1, LC2>(
+        client_a: &mut LC1,
+        client_b: &mut LC2,
+        log: &TL,
+    ) -> anyhow::Result<LogTree<TL::LogSize>>
+    where
+        T: Serialize + Deserialize<'a>,
+        TL: TransparentLog<'a, T>,
+        TL::LogSize: Display,
+        LC1: LogClient<'a, T, TL>,
+        LC2: LogClient<'a, T, TL>,
+    {
+        let head = Self::reconcile(client_a.latest(), client_b.latest(), log)?;
+        client_a.set_latest(head.clone());
+        client_b.set_latest(head.clone());
+        Ok(head)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::test_helpers::*;
+    use crate::*;
+
+    #[test]
+    fn gossip_reconcile() -> anyhow::Result<()> {
+        let mut ml: InMemoryLog<LogRecord> = InMemoryLog::with_origin("log");
+        let empty = ml.latest()?;
+        append_multiple(&mut ml, 5)?;
+        let head5 = ml.latest()?;
+        append_multiple_offset(&mut ml, 5, 8)?;
+        let head13 = ml.latest()?;
+        assert_eq!(head13, Gossip::reconcile(&head5, &head13, &ml)?);
+        assert_eq!(head13, Gossip::reconcile(&head13, &head5, &ml)?);
+        assert_eq!(head13, Gossip::reconcile(&head13, &head13, &ml)?);
+        assert_eq!(head5, Gossip::reconcile(&empty, &head5, &ml)?);
+
+        // a head without origin takes the other one
+        let anonymous = LogTree {
+            origin: None,
+            ..head5.clone()
+        };
+        assert_eq!(head13, Gossip::reconcile(&anonymous, &head13, &ml)?);
+        Ok(())
+    }
+
+    #[test]
+    fn gossip_split_view() -> anyhow::Result<()> {
+        let mut ml: InMemoryLog<LogRecord> = InMemoryLog::default();
+        append_multiple(&mut ml, 13)?;
+        let head13 = ml.latest()?;
+
+        // the view of another client, from a log with a different record
+        let mut forked: InMemoryLog<LogRecord> = InMemoryLog::default();
+        append_multiple(&mut forked, 4)?;
+        forked.append(LogRecord::new("forked"))?;
+        let head5 = forked.latest()?;
+        let err = Gossip::reconcile(&head5, &head13, &ml).err().unwrap();
+        assert!(matches!(
+            err.downcast_ref::<GossipError>(),
+            Some(GossipError::SplitView(s1, s2)) if s1 == "5" && s2 == "13"
+        ));
+        let forged = LogTree {
+            hash: head5.hash.clone(),
+            ..head13.clone()
+        };
+        assert!(matches!(
+            Gossip::reconcile(&forged, &head13, &ml)
+                .err()
+                .unwrap()
+                .downcast_ref::<GossipError>(),
+            Some(GossipError::SplitView(_, _))
+        ));
+
+        append_multiple_offset(&mut forked, 5, 10)?;
+        let err = Gossip::reconcile(&head13, &forked.latest()?, &ml)
+            .err()
+            .unwrap();
+        assert!(matches!(
+            err.downcast_ref::<GossipError>(),
+            Some(GossipError::AheadOfLog(s)) if s == "15"
+        ));
+
+        let first = LogTree {
+            origin: Some("first".into()),
+            ..head13.clone()
+        };
+        let second = LogTree {
+            origin: Some("second".into()),
+            ..head13
+        };
+        assert!(matches!(
+            Gossip::reconcile(&first, &second, &ml)
+                .err()
+                .unwrap()
+                .downcast_ref::<GossipError>(),
+            Some(GossipError::OriginMismatch(_, _))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn gossip_exchange() -> anyhow::Result<()> {
+        let mut ml: InMemoryLog<LogRecord> = InMemoryLog::default();
+        append_multiple(&mut ml, 5)?;
+        let mut client_a = InMemoryLogClientBuilder::new(&ml)?.build();
+        append_multiple_offset(&mut ml, 5, 8)?;
+        let mut client_b = InMemoryLogClientBuilder::new(&ml)?.build();
+        let head = Gossip::exchange(&mut client_a, &mut client_b, &ml)?;
+        assert_eq!(13, head.size);
+        assert_eq!(&head, client_a.latest());
+        assert_eq!(&head, client_b.latest());
+        Ok(())
+    }
+}
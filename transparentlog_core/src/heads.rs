@@ -0,0 +1,379 @@
+//! # Head history
+//!
+//! A `HeadHistoryLog` records each tree head it publishes in a meta-log, itself a transparent log,
+//! each `HeadEntry` holding the hash of the previous one, its leaf hash with the default leaf hash.
+//! Auditors reading the entries back with `head_history` verify with `verify_head_history` the exact sequence
+//! of heads published, and detect a rollback or a fork of the log with `check_head_history`, even without witnesses.
+use crate::base::*;
+use maybe_owned::MaybeOwned;
+use num::{One, ToPrimitive, Zero};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::ops::Range;
+use thiserror::Error;
+
+/// Specific Errors for head histories
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum HeadHistoryError {
+    #[error("Head {0} does not reference the head before it")]
+    BrokenChain(usize),
+    #[error("Head {0} is not larger than the head before it")]
+    Rollback(usize),
+    #[error("Head {0} is not consistent with the head before it")]
+    Inconsistent(usize),
+}
+
+/// A published tree head, with the hash of the head published before it, empty for the first one
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct HeadEntry<LogSize> {
+    pub tree: LogTree<LogSize>,
+    pub previous: String,
+    /// The time of publication, in milliseconds since the UNIX epoch
+    pub timestamp: u64,
+}
+
+/// Verify heads follow each other: each referencing the one before it, and larger than it
+/// The entries start after the given one, or at the first head published
+pub fn verify_head_history<LogSize: PartialOrd + Serialize>(
+    previous: Option<&HeadEntry<LogSize>>,
+    entries: &[HeadEntry<LogSize>],
+) -> anyhow::Result<()> {
+    let mut previous = previous;
+    for (i, entry) in entries.iter().enumerate() {
+        let expected = match previous {
+            Some(p) => {
+                if entry.tree.size <= p.tree.size {
+                    return Err(HeadHistoryError::Rollback(i).into());
+                }
+                hash(p)?
+            }
+            None => String::new(),
+        };
+        if entry.previous != expected {
+            return Err(HeadHistoryError::BrokenChain(i).into());
+        }
+        previous = Some(entry);
+    }
+    Ok(())
+}
+
+/// Check heads following each other are consistent trees of the given log
+pub fn check_head_history<'a, T, TL>(
+    log: &TL,
+    entries: &[HeadEntry<TL::LogSize>],
+) -> anyhow::Result<()>
+where
+    T: Serialize + Deserialize<'a>,
+    TL: TransparentLog<'a, T>,
+{
+    for (i, pair) in entries.windows(2).enumerate() {
+        let (a, b) = (&pair[0].tree, &pair[1].tree);
+        if a.size > b.size || !log.consistency_between(a.size, b.size)?.verify(a, b) {
+            return Err(HeadHistoryError::Inconsistent(i + 1).into());
+        }
+    }
+    Ok(())
+}
+
+/// A log recording the heads it publishes in a meta-log
+#[derive(Debug)]
+pub struct HeadHistoryLog<L, M> {
+    log: L,
+    heads: M,
+}
+
+impl<L, M> HeadHistoryLog<L, M> {
+    /// Wrap a log, recording its heads in the given meta-log
+    pub fn new(log: L, heads: M) -> Self {
+        Self { log, heads }
+    }
+
+    /// Publish the latest tree head, unless it was the last one published
+    /// Fails, without publishing it, if the tree is not larger than the last head published
+    pub fn publish<'a, T>(&mut self) -> anyhow::Result<HeadEntry<L::LogSize>>
+    where
+        T: Serialize + Deserialize<'a>,
+        L: TransparentLog<'a, T>,
+        M: TransparentLog<'a, HeadEntry<L::LogSize>>,
+        HeadEntry<L::LogSize>: Serialize + Deserialize<'a> + Clone,
+    {
+        let tree = self.log.latest()?;
+        let published = self.heads.size()?;
+        let previous = if published.is_zero() {
+            String::new()
+        } else {
+            let last = self.last(published)?;
+            if last.tree == tree {
+                return Ok(last);
+            }
+            let index = published.to_usize().unwrap_or(usize::MAX);
+            if tree.size <= last.tree.size {
+                return Err(HeadHistoryError::Rollback(index).into());
+            }
+            hash(&last)?
+        };
+        let entry = HeadEntry {
+            tree,
+            previous,
+            timestamp: RecordMeta::now().timestamp,
+        };
+        self.heads.append(entry.clone())?;
+        Ok(entry)
+    }
+
+    /// The heads published in the given range of the meta-log, oldest first
+    pub fn head_history<'a, T>(
+        &self,
+        range: Range<M::LogSize>,
+    ) -> anyhow::Result<Vec<HeadEntry<L::LogSize>>>
+    where
+        T: Serialize + Deserialize<'a>,
+        L: TransparentLog<'a, T>,
+        M: TransparentLog<'a, HeadEntry<L::LogSize>>,
+        HeadEntry<L::LogSize>: Serialize + Deserialize<'a> + Clone,
+    {
+        Ok(self
+            .heads
+            .get_range(range.start, range.end)?
+            .into_iter()
+            .map(MaybeOwned::into_owned)
+            .collect())
+    }
+
+    /// The meta-log of the heads
+    pub fn heads(&self) -> &M {
+        &self.heads
+    }
+
+    /// The wrapped log
+    pub fn inner(&self) -> &L {
+        &self.log
+    }
+
+    /// Get back the wrapped log and the meta-log
+    pub fn into_inner(self) -> (L, M) {
+        (self.log, self.heads)
+    }
+
+    /// The last head published, of the given number of heads
+    fn last<'a, T>(&self, published: M::LogSize) -> anyhow::Result<HeadEntry<L::LogSize>>
+    where
+        T: Serialize + Deserialize<'a>,
+        L: TransparentLog<'a, T>,
+        M: TransparentLog<'a, HeadEntry<L::LogSize>>,
+        HeadEntry<L::LogSize>: Serialize + Deserialize<'a> + Clone,
+    {
+        Ok(self
+            .heads
+            .get(published - M::LogSize::one())?
+            .ok_or(TombstoneError::NoRecord)?
+            .into_owned())
+    }
+}
+
+impl<'a, T, L, M> TransparentLog<'a, T> for HeadHistoryLog<L, M>
+where
+    T: Serialize + Deserialize<'a>,
+    L: TransparentLog<'a, T>,
+{
+    type LogSize = L::LogSize;
+
+    fn add(&mut self, record: T) -> anyhow::Result<Self::LogSize> {
+        self.log.add(record)
+    }
+
+    fn add_tombstone(&mut self) -> anyhow::Result<Self::LogSize> {
+        self.log.add_tombstone()
+    }
+
+    fn redact(&mut self, id: Self::LogSize) -> anyhow::Result<()> {
+        self.log.redact(id)
+    }
+
+    fn expire(&mut self, id: Self::LogSize) -> anyhow::Result<()> {
+        self.log.expire(id)
+    }
+
+    fn expired(&self, id: Self::LogSize) -> anyhow::Result<bool> {
+        self.log.expired(id)
+    }
+
+    fn seal(&mut self) -> anyhow::Result<LogTree<Self::LogSize>> {
+        self.log.seal()
+    }
+
+    fn sealed(&self) -> anyhow::Result<Option<LogTree<Self::LogSize>>> {
+        self.log.sealed()
+    }
+
+    fn sync(&self) -> anyhow::Result<()> {
+        self.log.sync()
+    }
+
+    fn add_meta(&mut self, id: Self::LogSize, meta: RecordMeta) -> anyhow::Result<()> {
+        self.log.add_meta(id, meta)
+    }
+
+    fn get_meta(&self, id: Self::LogSize) -> anyhow::Result<Option<RecordMeta>> {
+        self.log.get_meta(id)
+    }
+
+    fn set_annotation(&mut self, id: Self::LogSize, annotation: Vec<u8>) -> anyhow::Result<()> {
+        self.log.set_annotation(id, annotation)
+    }
+
+    fn get_annotation(&self, id: Self::LogSize) -> anyhow::Result<Option<Vec<u8>>> {
+        self.log.get_annotation(id)
+    }
+
+    fn find_since(&self, timestamp: u64) -> anyhow::Result<Self::LogSize> {
+        self.log.find_since(timestamp)
+    }
+
+    fn add_hash(&mut self, level: LogHeight, hash: String) -> anyhow::Result<Self::LogSize> {
+        self.log.add_hash(level, hash)
+    }
+
+    fn get_hash(
+        &self,
+        level: LogHeight,
+        index: Self::LogSize,
+    ) -> anyhow::Result<MaybeOwned<'_, String>> {
+        self.log.get_hash(level, index)
+    }
+
+    fn append(&mut self, record: T) -> anyhow::Result<Record<Self::LogSize>> {
+        self.log.append(record)
+    }
+
+    fn append_hash_only(&mut self, hash: String) -> anyhow::Result<Record<Self::LogSize>> {
+        self.log.append_hash_only(hash)
+    }
+
+    fn bulk_append(&mut self, records: Vec<T>) -> anyhow::Result<Vec<Record<Self::LogSize>>>
+    where
+        T: Sync,
+    {
+        self.log.bulk_append(records)
+    }
+
+    fn size(&self) -> anyhow::Result<Self::LogSize> {
+        self.log.size()
+    }
+
+    fn origin(&self) -> Option<String> {
+        self.log.origin()
+    }
+
+    fn latest(&self) -> anyhow::Result<LogTree<Self::LogSize>> {
+        self.log.latest()
+    }
+
+    fn leaf_hasher(&self) -> LeafHasher<T> {
+        self.log.leaf_hasher()
+    }
+
+    fn get(&self, index: Self::LogSize) -> anyhow::Result<Option<MaybeOwned<'_, T>>> {
+        self.log.get(index)
+    }
+
+    fn get_raw_bytes(&self, index: Self::LogSize) -> anyhow::Result<Option<Vec<u8>>> {
+        self.log.get_raw_bytes(index)
+    }
+
+    fn get_leaf_bytes(&self, index: Self::LogSize) -> anyhow::Result<Option<Vec<u8>>> {
+        self.log.get_leaf_bytes(index)
+    }
+
+    fn get_range(
+        &self,
+        start: Self::LogSize,
+        end: Self::LogSize,
+    ) -> anyhow::Result<Vec<MaybeOwned<'_, T>>> {
+        self.log.get_range(start, end)
+    }
+
+    fn iter(&self) -> LogIterator<'_, Self::LogSize, T> {
+        self.log.iter()
+    }
+
+    fn index_of(&self, hash: &str) -> anyhow::Result<Option<Self::LogSize>> {
+        self.log.index_of(hash)
+    }
+
+    fn indices_of(&self, hash: &str) -> anyhow::Result<Vec<Self::LogSize>> {
+        self.log.indices_of(hash)
+    }
+
+    fn proofs<I>(
+        &self,
+        positions: I,
+    ) -> anyhow::Result<HashMap<LogTreePosition<Self::LogSize>, String>>
+    where
+        I: Iterator<Item = LogTreePosition<Self::LogSize>>,
+    {
+        self.log.proofs(positions)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::test_helpers::*;
+    use crate::*;
+
+    fn history_error(r: anyhow::Result<()>) -> HeadHistoryError {
+        r.unwrap_err().downcast::<HeadHistoryError>().unwrap()
+    }
+
+    #[test]
+    fn heads_history() -> anyhow::Result<()> {
+        let mut ml = HeadHistoryLog::new(InMemoryLog::default(), InMemoryLog::default());
+        append_multiple(&mut ml, 5)?;
+        let first = ml.publish()?;
+        assert_eq!("", first.previous);
+        assert_eq!(first, ml.publish()?);
+        append_multiple_offset(&mut ml, 5, 3)?;
+        ml.publish()?;
+        append_multiple_offset(&mut ml, 8, 5)?;
+        ml.publish()?;
+        assert_eq!(3, ml.heads().size()?);
+
+        let heads = ml.head_history(0..3)?;
+        assert_eq!(
+            vec![5, 8, 13],
+            heads.iter().map(|h| h.tree.size).collect::<Vec<_>>()
+        );
+        assert_eq!(ml.latest()?, heads[2].tree);
+        verify_head_history(None, &heads)?;
+        verify_head_history(Some(&heads[0]), &heads[1..])?;
+        check_head_history(ml.inner(), &heads)?;
+        // the heads are leaves of the meta-log, with their own proofs
+        assert_eq!(heads[1].previous, ml.heads().get_hash(0, 0)?.as_str());
+
+        let rolled_back = HeadEntry {
+            tree: heads[0].tree.clone(),
+            previous: hash(&heads[2])?,
+            timestamp: heads[2].timestamp,
+        };
+        assert_eq!(
+            HeadHistoryError::Rollback(0),
+            history_error(verify_head_history(Some(&heads[2]), &[rolled_back]))
+        );
+        let mut dropped = heads.clone();
+        dropped.remove(1);
+        assert_eq!(
+            HeadHistoryError::BrokenChain(1),
+            history_error(verify_head_history(None, &dropped))
+        );
+
+        // a fork of the log has the same sizes but other hashes
+        let mut fork = InMemoryLog::default();
+        append_multiple_offset(&mut fork, 1, 13)?;
+        assert_eq!(
+            HeadHistoryError::Inconsistent(1),
+            history_error(check_head_history(&fork, &heads))
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,209 @@
+//! # Importing records
+//!
+//! Bootstrap a log from an existing data set: an `Importer` streams newline delimited JSON,
+//! or CSV with a header line when the `csv` feature is enabled, into any log, appending the records in batches.
+//! After each batch, a progress callback receives the number of records imported so far; an import interrupted
+//! by an error resumes by skipping that many records of the same input.
+use crate::base::*;
+use serde::{de::DeserializeOwned, Serialize};
+#[cfg(feature = "json")]
+use std::io::BufRead;
+#[cfg(feature = "csv")]
+use std::io::Read;
+use thiserror::Error;
+
+/// The default number of records appended at once
+const DEFAULT_BATCH_SIZE: usize = 1000;
+
+/// Specific Errors for imports
+#[derive(Debug, Clone, Error)]
+pub enum ImportError {
+    #[error("Invalid record {record} of the input: {message}")]
+    Parse { record: u64, message: String },
+}
+
+/// An importer of records into a log, in batches
+pub struct Importer<'p> {
+    batch_size: usize,
+    skip: u64,
+    progress: Option<Box<dyn FnMut(u64) + 'p>>,
+}
+
+impl Default for Importer<'_> {
+    fn default() -> Self {
+        Self {
+            batch_size: DEFAULT_BATCH_SIZE,
+            skip: 0,
+            progress: None,
+        }
+    }
+}
+
+impl<'p> Importer<'p> {
+    /// Set the number of records appended at once
+    pub fn batch_size(&mut self, batch_size: usize) -> &mut Self {
+        if batch_size > 0 {
+            self.batch_size = batch_size;
+        }
+        self
+    }
+
+    /// Resume an import, skipping the given number of records already imported from the input
+    pub fn resume_from(&mut self, imported: u64) -> &mut Self {
+        self.skip = imported;
+        self
+    }
+
+    /// Set the callback receiving the number of records imported so far, skipped ones included, after each batch
+    pub fn progress(&mut self, progress: Box<dyn FnMut(u64) + 'p>) -> &mut Self {
+        self.progress = Some(progress);
+        self
+    }
+
+    /// Import the records of a newline delimited JSON input, one per line, ignoring blank lines
+    /// Returns the number of records imported, skipped ones included
+    #[cfg(feature = "json")]
+    pub fn import_json_lines<'a, T, TL, R>(&mut self, log: &mut TL, input: R) -> anyhow::Result<u64>
+    where
+        T: Serialize + DeserializeOwned + Sync,
+        TL: TransparentLog<'a, T>,
+        R: BufRead,
+    {
+        let records = input
+            .lines()
+            .filter(|line| !matches!(line, Ok(l) if l.trim().is_empty()))
+            .map(|line| Ok(serde_json::from_str(&line?)?));
+        self.import(log, records)
+    }
+
+    /// Import the records of a CSV input, its first line holding the names of the record fields
+    /// Returns the number of records imported, skipped ones included
+    #[cfg(feature = "csv")]
+    pub fn import_csv<'a, T, TL, R>(&mut self, log: &mut TL, input: R) -> anyhow::Result<u64>
+    where
+        T: Serialize + DeserializeOwned + Sync,
+        TL: TransparentLog<'a, T>,
+        R: Read,
+    {
+        let mut reader = csv::Reader::from_reader(input);
+        let records = reader.deserialize().map(|r| Ok(r?));
+        self.import(log, records)
+    }
+
+    /// Append parsed records in batches, skipping the ones already imported
+    fn import<'a, T, TL, I>(&mut self, log: &mut TL, records: I) -> anyhow::Result<u64>
+    where
+        T: Serialize + DeserializeOwned + Sync,
+        TL: TransparentLog<'a, T>,
+        I: Iterator<Item = anyhow::Result<T>>,
+    {
+        let mut imported = self.skip;
+        let mut batch = Vec::with_capacity(self.batch_size);
+        for (record, parsed) in records.enumerate() {
+            let record = record as u64;
+            if record < self.skip {
+                continue;
+            }
+            batch.push(parsed.map_err(|e| ImportError::Parse {
+                record,
+                message: e.to_string(),
+            })?);
+            if batch.len() == self.batch_size {
+                self.flush(log, &mut batch, &mut imported)?;
+            }
+        }
+        if !batch.is_empty() {
+            self.flush(log, &mut batch, &mut imported)?;
+        }
+        Ok(imported)
+    }
+
+    /// Append a batch and report the progress
+    fn flush<'a, T, TL>(
+        &mut self,
+        log: &mut TL,
+        batch: &mut Vec<T>,
+        imported: &mut u64,
+    ) -> anyhow::Result<()>
+    where
+        T: Serialize + DeserializeOwned + Sync,
+        TL: TransparentLog<'a, T>,
+    {
+        *imported += log.bulk_append(std::mem::take(batch))?.len() as u64;
+        if let Some(progress) = self.progress.as_mut() {
+            progress(*imported);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::test_helpers::*;
+    use crate::*;
+    #[cfg(feature = "json")]
+    use std::cell::RefCell;
+
+    #[cfg(feature = "json")]
+    fn json_lines(count: usize) -> String {
+        (0..count)
+            .map(|i| format!("{{\"text\":\"rec{}\"}}\n", i))
+            .collect()
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn import_json() -> anyhow::Result<()> {
+        let mut ml = InMemoryLog::default();
+        let progress = RefCell::new(vec![]);
+        let mut importer = Importer::default();
+        importer
+            .batch_size(5)
+            .progress(Box::new(|imported| progress.borrow_mut().push(imported)));
+        let input = json_lines(13).replace("{\"text\":\"rec3\"}\n", "{\"text\":\"rec3\"}\n\n");
+        assert_eq!(13, importer.import_json_lines(&mut ml, input.as_bytes())?);
+        assert_eq!(vec![5, 10, 13], *progress.borrow());
+        check_13(&ml)?;
+        Ok(())
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn import_resume() -> anyhow::Result<()> {
+        let mut ml = InMemoryLog::default();
+        let mut importer = Importer::default();
+        importer.batch_size(4);
+        let input = json_lines(13).replace("{\"text\":\"rec9\"}", "{\"txt\":\"rec9\"}");
+        let err = importer
+            .import_json_lines::<LogRecord, _, _>(&mut ml, input.as_bytes())
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<ImportError>(),
+            Some(ImportError::Parse { record: 9, .. })
+        ));
+        assert_eq!(8, ml.size()?);
+
+        importer.resume_from(ml.size()? as u64);
+        assert_eq!(
+            13,
+            importer.import_json_lines(&mut ml, json_lines(13).as_bytes())?
+        );
+        check_13(&ml)?;
+        Ok(())
+    }
+
+    #[cfg(feature = "csv")]
+    #[test]
+    fn import_csv() -> anyhow::Result<()> {
+        let mut ml = InMemoryLog::default();
+        let input: String = std::iter::once("text\n".to_owned())
+            .chain((0..13).map(|i| format!("rec{}\n", i)))
+            .collect();
+        let mut importer = Importer::default();
+        importer.batch_size(6);
+        assert_eq!(13, importer.import_csv(&mut ml, input.as_bytes())?);
+        check_13(&ml)?;
+        Ok(())
+    }
+}
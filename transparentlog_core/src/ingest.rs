@@ -0,0 +1,322 @@
+//! # Message bus ingestion
+//!
+//! An `Ingestor` appends the records consumed from a message bus, like a Kafka topic or a NATS subject, to a log with
+//! at-least-once semantics: the messages of a batch are only committed back to their `MessageSource` once its records
+//! are appended and synced to storage, so a crash in between gets them delivered again.
+//! The leaf hash of a record is its idempotency key: a record already in the log, delivered again after a crash or sent
+//! twice upstream, is skipped, so records meant to be logged twice must differ, by an event id or a timestamp.
+//! The `transparentlog_ingest` crate provides the Kafka and NATS sources.
+use crate::base::*;
+#[cfg(feature = "json")]
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::ops::AddAssign;
+use std::sync::atomic::{AtomicBool, Ordering};
+use thiserror::Error;
+
+/// The default maximum number of messages appended at once
+const DEFAULT_BATCH_SIZE: usize = 1000;
+
+/// Specific Errors for ingestion
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum IngestError {
+    #[error("Invalid message: {0}")]
+    Invalid(String),
+}
+
+/// A message consumed from a bus, with what commits it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message<A> {
+    pub payload: Vec<u8>,
+    pub ack: A,
+}
+
+/// A message bus the records to append are consumed from
+pub trait MessageSource {
+    /// What commits a message: its partition and offset, or the message itself to acknowledge
+    type Ack;
+
+    /// Receive at most the given number of messages, waiting a short while for the first one
+    /// Returns no message if there is nothing new on the bus
+    fn receive(&mut self, max: usize) -> anyhow::Result<Vec<Message<Self::Ack>>>;
+
+    /// Commit the given messages, so they are not delivered again
+    fn commit(&mut self, acks: Vec<Self::Ack>) -> anyhow::Result<()>;
+}
+
+/// The outcome of ingesting messages
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct IngestReport {
+    /// The number of records appended
+    pub appended: u64,
+    /// The number of records skipped since already in the log
+    pub duplicates: u64,
+    /// The number of messages skipped since they could not be decoded
+    pub rejected: u64,
+}
+
+impl AddAssign for IngestReport {
+    fn add_assign(&mut self, other: Self) {
+        self.appended += other.appended;
+        self.duplicates += other.duplicates;
+        self.rejected += other.rejected;
+    }
+}
+
+/// The decoder of message payloads into records
+pub type Decoder<T> = Box<dyn Fn(&[u8]) -> anyhow::Result<T> + Send>;
+
+/// An ingestor of the records of a message bus into a log, in batches
+pub struct Ingestor<T> {
+    batch_size: usize,
+    skip_invalid: bool,
+    decode: Decoder<T>,
+}
+
+/// An ingestor of JSON messages
+#[cfg(feature = "json")]
+impl<T: DeserializeOwned> Default for Ingestor<T> {
+    fn default() -> Self {
+        Self::with_decoder(Box::new(|payload| Ok(serde_json::from_slice(payload)?)))
+    }
+}
+
+impl<T> Ingestor<T> {
+    /// An ingestor decoding the messages with the given function
+    pub fn with_decoder(decode: Decoder<T>) -> Self {
+        Self {
+            batch_size: DEFAULT_BATCH_SIZE,
+            skip_invalid: false,
+            decode,
+        }
+    }
+
+    /// Set the maximum number of messages appended at once
+    pub fn batch_size(&mut self, batch_size: usize) -> &mut Self {
+        if batch_size > 0 {
+            self.batch_size = batch_size;
+        }
+        self
+    }
+
+    /// Skip and commit the messages that cannot be decoded, instead of failing without committing their batch
+    pub fn skip_invalid(&mut self, skip_invalid: bool) -> &mut Self {
+        self.skip_invalid = skip_invalid;
+        self
+    }
+
+    /// Receive a batch of messages, append their records that are not in the log yet and sync the log, then commit the messages
+    pub fn ingest_batch<'a, TL, S>(
+        &self,
+        log: &mut TL,
+        source: &mut S,
+    ) -> anyhow::Result<IngestReport>
+    where
+        T: Serialize + Deserialize<'a> + Sync,
+        TL: TransparentLog<'a, T>,
+        S: MessageSource,
+    {
+        let messages = source.receive(self.batch_size)?;
+        let hasher = log.leaf_hasher();
+        let mut report = IngestReport::default();
+        let mut hashes = HashSet::new();
+        let mut records = Vec::with_capacity(messages.len());
+        let mut acks = Vec::with_capacity(messages.len());
+        for message in messages {
+            match (self.decode)(&message.payload) {
+                Ok(record) => {
+                    let hash = hasher(&record)?;
+                    if hashes.contains(&hash) || log.index_of(&hash)?.is_some() {
+                        report.duplicates += 1;
+                    } else {
+                        hashes.insert(hash);
+                        records.push(record);
+                    }
+                }
+                Err(_) if self.skip_invalid => report.rejected += 1,
+                Err(e) => return Err(IngestError::Invalid(format!("{:#}", e)).into()),
+            }
+            acks.push(message.ack);
+        }
+        if !records.is_empty() {
+            report.appended = log.bulk_append(records)?.len() as u64;
+            log.sync()?;
+        }
+        if !acks.is_empty() {
+            source.commit(acks)?;
+        }
+        Ok(report)
+    }
+
+    /// Ingest batches of messages until the running flag is cleared
+    pub fn run<'a, TL, S>(
+        &self,
+        log: &mut TL,
+        source: &mut S,
+        running: &AtomicBool,
+    ) -> anyhow::Result<IngestReport>
+    where
+        T: Serialize + Deserialize<'a> + Sync,
+        TL: TransparentLog<'a, T>,
+        S: MessageSource,
+    {
+        let mut report = IngestReport::default();
+        while running.load(Ordering::Acquire) {
+            report += self.ingest_batch(log, source)?;
+        }
+        Ok(report)
+    }
+}
+
+#[cfg(all(test, feature = "json"))]
+mod tests {
+
+    use crate::test_helpers::*;
+    use crate::*;
+
+    /// A partition of messages in memory, committing by offset, that can fail the next commit
+    #[derive(Default)]
+    struct MemoryPartition {
+        messages: Vec<Vec<u8>>,
+        committed: usize,
+        position: usize,
+        fail_commit: bool,
+    }
+
+    impl MemoryPartition {
+        fn send(&mut self, text: &str) {
+            self.messages
+                .push(serde_json::to_vec(&LogRecord::new(text)).unwrap());
+        }
+
+        /// A consumer restarting after a crash reads again from the committed offset
+        fn restart(&mut self) {
+            self.position = self.committed;
+        }
+    }
+
+    impl MessageSource for MemoryPartition {
+        type Ack = usize;
+
+        fn receive(&mut self, max: usize) -> anyhow::Result<Vec<Message<usize>>> {
+            let end = self.messages.len().min(self.position + max);
+            let messages = (self.position..end)
+                .map(|offset| Message {
+                    payload: self.messages[offset].clone(),
+                    ack: offset,
+                })
+                .collect();
+            self.position = end;
+            Ok(messages)
+        }
+
+        fn commit(&mut self, acks: Vec<usize>) -> anyhow::Result<()> {
+            if std::mem::take(&mut self.fail_commit) {
+                return Err(anyhow::anyhow!("Broker unavailable"));
+            }
+            if let Some(offset) = acks.iter().max() {
+                self.committed = self.committed.max(offset + 1);
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn ingest_at_least_once() -> anyhow::Result<()> {
+        let mut ml: InMemoryLog<LogRecord> = InMemoryLog::default();
+        let mut partition = MemoryPartition::default();
+        for i in 0..5 {
+            partition.send(&format!("rec{}", i));
+        }
+        // sent twice upstream
+        partition.send("rec4");
+        let mut ingestor = Ingestor::default();
+        ingestor.batch_size(4);
+        assert_eq!(
+            IngestReport {
+                appended: 4,
+                duplicates: 0,
+                rejected: 0
+            },
+            ingestor.ingest_batch(&mut ml, &mut partition)?
+        );
+        assert_eq!(4, partition.committed);
+
+        // the records are appended, but the consumer crashes before committing
+        partition.fail_commit = true;
+        assert!(ingestor.ingest_batch(&mut ml, &mut partition).is_err());
+        assert_eq!(5, ml.size()?);
+        assert_eq!(4, partition.committed);
+        partition.restart();
+        assert_eq!(
+            IngestReport {
+                appended: 0,
+                duplicates: 2,
+                rejected: 0
+            },
+            ingestor.ingest_batch(&mut ml, &mut partition)?
+        );
+        assert_eq!(6, partition.committed);
+        assert_eq!(
+            IngestReport::default(),
+            ingestor.ingest_batch(&mut ml, &mut partition)?
+        );
+
+        for i in 5..13 {
+            partition.send(&format!("rec{}", i));
+        }
+        let running = std::sync::atomic::AtomicBool::new(true);
+        let mut report = IngestReport::default();
+        while partition.committed < partition.messages.len() {
+            report += ingestor.ingest_batch(&mut ml, &mut partition)?;
+        }
+        running.store(false, std::sync::atomic::Ordering::Release);
+        assert_eq!(
+            IngestReport::default(),
+            ingestor.run(&mut ml, &mut partition, &running)?
+        );
+        assert_eq!(8, report.appended);
+        check_13(&ml)?;
+        Ok(())
+    }
+
+    #[test]
+    fn ingest_invalid() -> anyhow::Result<()> {
+        let mut ml: InMemoryLog<LogRecord> = InMemoryLog::default();
+        let mut partition = MemoryPartition::default();
+        partition.send("rec0");
+        partition.messages.push(b"not json".to_vec());
+        partition.send("rec1");
+        let mut ingestor: Ingestor<LogRecord> = Ingestor::default();
+        let err = ingestor.ingest_batch(&mut ml, &mut partition).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<IngestError>(),
+            Some(IngestError::Invalid(_))
+        ));
+        assert_eq!(0, ml.size()?);
+        assert_eq!(0, partition.committed);
+
+        partition.restart();
+        ingestor.skip_invalid(true);
+        assert_eq!(
+            IngestReport {
+                appended: 2,
+                duplicates: 0,
+                rejected: 1
+            },
+            ingestor.ingest_batch(&mut ml, &mut partition)?
+        );
+        assert_eq!(3, partition.committed);
+
+        // messages in another format
+        let mut partition = MemoryPartition::default();
+        partition.messages.push(b"rec2".to_vec());
+        let ingestor = Ingestor::with_decoder(Box::new(|payload| {
+            Ok(LogRecord::new(std::str::from_utf8(payload)?))
+        }));
+        assert_eq!(1, ingestor.ingest_batch(&mut ml, &mut partition)?.appended);
+        assert_eq!(Some(2), ml.index_of(&hash(&LogRecord::new("rec2"))?)?);
+        Ok(())
+    }
+}
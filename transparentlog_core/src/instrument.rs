@@ -0,0 +1,148 @@
+//! # Instrumentation
+//!
+//! Counters and histograms emitted through the `metrics` facade when the `metrics` feature is enabled,
+//! and spans emitted through `tracing` when the `tracing` feature is enabled, no-ops otherwise.
+//! Backends, including the ones in other crates, report their appends, proof requests and storage reads here,
+//! labelled with the name of the backend, so enabling the features on this crate instruments all of them.
+//!
+//! Metrics emitted:
+//! - `transparentlog_appends_total`: records and tombstones appended, by backend
+//! - `transparentlog_proof_requests_total`: calls to `proofs`, by backend
+//! - `transparentlog_proof_positions_total`: tree positions requested, by backend
+//! - `transparentlog_storage_read_seconds`: latency of reads from storage, by backend
+//! - `transparentlog_verification_failures_total`: records a client failed to verify
+//!
+//! Spans emitted:
+//! - `append` at debug level, with the `index` of the new record
+//! - `push_hash` at trace level, with the `level` and `index` of the hash, nested for each level the hash cascades to
+//! - `proofs` at debug level, with the `backend` and the number of `positions` requested
+//! - `check_record` at debug level, with the `index` of the record and the `size` of the latest tree of the client
+use crate::base::LogHeight;
+use num::ToPrimitive;
+#[cfg(feature = "metrics")]
+use std::time::Instant;
+
+/// Count an entry appended to a backend
+#[inline]
+pub fn record_append(backend: &'static str) {
+    #[cfg(feature = "metrics")]
+    metrics::counter!("transparentlog_appends_total", 1, "backend" => backend);
+    #[cfg(not(feature = "metrics"))]
+    let _ = backend;
+}
+
+/// Count a proof request served by a backend, with the number of positions requested
+#[inline]
+pub fn record_proofs(backend: &'static str, positions: usize) {
+    #[cfg(feature = "metrics")]
+    {
+        metrics::counter!("transparentlog_proof_requests_total", 1, "backend" => backend);
+        metrics::counter!("transparentlog_proof_positions_total", positions as u64, "backend" => backend);
+    }
+    #[cfg(not(feature = "metrics"))]
+    let _ = (backend, positions);
+}
+
+/// Time a read from the storage of a backend
+#[inline]
+pub fn time_read<R, F: FnOnce() -> R>(backend: &'static str, read: F) -> R {
+    #[cfg(feature = "metrics")]
+    {
+        let start = Instant::now();
+        let r = read();
+        metrics::histogram!("transparentlog_storage_read_seconds", start.elapsed().as_secs_f64(), "backend" => backend);
+        r
+    }
+    #[cfg(not(feature = "metrics"))]
+    {
+        let _ = backend;
+        read()
+    }
+}
+
+/// Count a record a client failed to verify against the log
+#[inline]
+pub fn record_verification_failure() {
+    #[cfg(feature = "metrics")]
+    metrics::counter!("transparentlog_verification_failures_total", 1);
+}
+
+/// A span entered until dropped
+pub struct SpanGuard {
+    #[cfg(feature = "tracing")]
+    span: tracing::span::EnteredSpan,
+}
+
+impl SpanGuard {
+    /// Record the index of the span, once known
+    #[inline]
+    pub fn record_index<S: ToPrimitive>(&self, index: &S) {
+        #[cfg(feature = "tracing")]
+        self.span.record("index", index.to_u64());
+        #[cfg(not(feature = "tracing"))]
+        let _ = index;
+    }
+}
+
+/// Enter the span of an append, its index being recorded once the record is added
+#[inline]
+pub fn append_span() -> SpanGuard {
+    SpanGuard {
+        #[cfg(feature = "tracing")]
+        span: tracing::debug_span!("append", index = tracing::field::Empty).entered(),
+    }
+}
+
+/// Enter the span of a hash pushed to the tree, covering the parent hashes it completes
+#[inline]
+pub fn push_hash_span<S: ToPrimitive>(level: LogHeight, index: &S) -> SpanGuard {
+    #[cfg(not(feature = "tracing"))]
+    let _ = (level, index);
+    SpanGuard {
+        #[cfg(feature = "tracing")]
+        span: tracing::trace_span!("push_hash", level, index = index.to_u64()).entered(),
+    }
+}
+
+/// Enter the span of a proof request served by a backend
+#[inline]
+pub fn proofs_span(backend: &'static str, positions: usize) -> SpanGuard {
+    #[cfg(not(feature = "tracing"))]
+    let _ = (backend, positions);
+    SpanGuard {
+        #[cfg(feature = "tracing")]
+        span: tracing::debug_span!("proofs", backend, positions).entered(),
+    }
+}
+
+/// Enter the span of a client checking a record
+#[inline]
+pub fn check_record_span<S: ToPrimitive>(index: &S, size: &S) -> SpanGuard {
+    #[cfg(not(feature = "tracing"))]
+    let _ = (index, size);
+    SpanGuard {
+        #[cfg(feature = "tracing")]
+        span: tracing::debug_span!("check_record", index = index.to_u64(), size = size.to_u64())
+            .entered(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn instrument_read() -> anyhow::Result<()> {
+        record_append("test");
+        record_proofs("test", 3);
+        record_verification_failure();
+        let span = append_span();
+        span.record_index(&3_u64);
+        let _span = push_hash_span(0, &3_u64);
+        let _span = proofs_span("test", 2);
+        let _span = check_record_span(&3_u64, &4_u64);
+        assert_eq!(42, time_read("test", || Ok::<_, anyhow::Error>(42))?);
+        assert!(time_read("test", || Err::<u8, _>(anyhow::anyhow!("failed read"))).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,196 @@
+//! # JSON format
+//!
+//! Tree heads, records, positions, tiles and proofs serialize to JSON with the field names of their structs,
+//! sizes and indices being JSON integers and hashes hex strings. The hashes of a proof are a list of
+//! `{"level", "index", "hash"}` objects, sorted by level then index, since JSON objects only have string keys.
+//! Documents crossing a process boundary are wrapped in a `Versioned` object adding the `version` of the format,
+//! and `JSON_SCHEMA` is the JSON Schema of the current version.
+use crate::base::LogHeight;
+#[cfg(feature = "json")]
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// The version of the JSON format
+pub const JSON_VERSION: u32 = 1;
+
+/// The JSON Schema of the current version of the JSON format
+pub const JSON_SCHEMA: &str = include_str!("../schema/transparentlog-v1.json");
+
+/// Specific Errors for JSON documents
+#[derive(Debug, Clone, Error)]
+pub enum JsonError {
+    #[error("Unsupported JSON format version: {0}")]
+    UnsupportedVersion(u32),
+}
+
+/// A value with the version of the JSON format, the fields of the value following the version
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Versioned<T> {
+    pub version: u32,
+    #[serde(flatten)]
+    pub value: T,
+}
+
+impl<T> Versioned<T> {
+    /// Wrap a value with the current version
+    pub fn new(value: T) -> Self {
+        Self {
+            version: JSON_VERSION,
+            value,
+        }
+    }
+
+    /// Get back the value, if its version is supported
+    pub fn into_value(self) -> Result<T, JsonError> {
+        if self.version == JSON_VERSION {
+            Ok(self.value)
+        } else {
+            Err(JsonError::UnsupportedVersion(self.version))
+        }
+    }
+}
+
+/// A hash of an inclusion proof
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ProofHash<LogSize> {
+    pub level: LogHeight,
+    pub index: LogSize,
+    pub hash: String,
+}
+
+/// Serialize a value as a versioned JSON document
+#[cfg(feature = "json")]
+pub fn to_json<T: Serialize>(value: &T) -> anyhow::Result<String> {
+    Ok(serde_json::to_string(&Versioned::new(value))?)
+}
+
+/// Deserialize a value from a versioned JSON document
+#[cfg(feature = "json")]
+pub fn from_json<T: DeserializeOwned>(json: &str) -> anyhow::Result<T> {
+    Ok(serde_json::from_str::<Versioned<T>>(json)?.into_value()?)
+}
+
+/// Proof hashes by position, as a sorted list of proof hashes
+pub(crate) mod proof_hashes {
+    use super::ProofHash;
+    use crate::base::LogTreePosition;
+    use serde::{de::Error, Deserialize, Deserializer, Serialize, Serializer};
+    use std::collections::HashMap;
+    use std::hash::Hash;
+
+    pub fn serialize<S, LogSize>(
+        proofs: &HashMap<LogTreePosition<LogSize>, String>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+        LogSize: Serialize + Ord + Clone,
+    {
+        let mut hashes: Vec<_> = proofs
+            .iter()
+            .map(|(p, h)| ProofHash {
+                level: p.level,
+                index: p.index.clone(),
+                hash: h.clone(),
+            })
+            .collect();
+        hashes.sort();
+        hashes.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D, LogSize>(
+        deserializer: D,
+    ) -> Result<HashMap<LogTreePosition<LogSize>, String>, D::Error>
+    where
+        D: Deserializer<'de>,
+        LogSize: Deserialize<'de> + Eq + Hash,
+    {
+        let hashes = Vec::<ProofHash<LogSize>>::deserialize(deserializer)?;
+        let count = hashes.len();
+        let proofs: HashMap<_, _> = hashes
+            .into_iter()
+            .map(|p| {
+                (
+                    LogTreePosition {
+                        level: p.level,
+                        index: p.index,
+                    },
+                    p.hash,
+                )
+            })
+            .collect();
+        if proofs.len() < count {
+            return Err(D::Error::custom("duplicate proof position"));
+        }
+        Ok(proofs)
+    }
+}
+
+#[cfg(all(test, feature = "json"))]
+mod tests {
+
+    use crate::test_helpers::*;
+    use crate::*;
+
+    #[test]
+    fn json_round_trip() -> anyhow::Result<()> {
+        let mut ml = InMemoryLog::default();
+        append_multiple(&mut ml, 13)?;
+        let tree = ml.latest()?;
+        let json = to_json(&tree)?;
+        assert_eq!(
+            format!(
+                "{{\"version\":1,\"size\":13,\"hash\":\"{}\",\"origin\":null}}",
+                tree.hash
+            ),
+            json
+        );
+        assert_eq!(tree, from_json(&json)?);
+
+        let proof = prove_inclusion(&ml, 9, 13)?;
+        let json = to_json(&proof)?;
+        let meta = proof.record.meta.unwrap().timestamp;
+        assert!(json.starts_with(&format!(
+            "{{\"version\":1,\"record\":{{\"id\":9,\"hash\":\"{}\",\"meta\":{{\"timestamp\":{}}}}},\"proofs\":[{{\"level\":0,\"index\":8,\"hash\":\"{}\"}},",
+            proof.record.hash,
+            meta,
+            ml.get_hash(0, 8)?
+        )));
+        let back: InclusionProof<usize> = from_json(&json)?;
+        assert_eq!(proof, back);
+        assert!(back.verify(&tree));
+
+        let range = range_proof(&ml, 3, 9, 13)?;
+        assert_eq!(range, from_json(&to_json(&range)?)?);
+        let consistency = ml.consistency_between(5, 13)?;
+        assert_eq!(consistency, from_json(&to_json(&consistency)?)?);
+        let tile = ml.tile(0, 0)?;
+        assert_eq!(tile, from_json(&to_json(&tile)?)?);
+        let position = LogTreePosition { level: 2, index: 1 };
+        assert_eq!(
+            "{\"version\":1,\"level\":2,\"index\":1}",
+            to_json(&position)?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn json_versions() -> anyhow::Result<()> {
+        let schema: serde_json::Value = serde_json::from_str(JSON_SCHEMA)?;
+        assert_eq!(
+            Some(JSON_VERSION as u64),
+            schema["properties"]["version"]["const"].as_u64()
+        );
+        let err =
+            from_json::<LogTree<usize>>("{\"version\":2,\"size\":0,\"hash\":\"\",\"origin\":null}")
+                .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<JsonError>(),
+            Some(JsonError::UnsupportedVersion(2))
+        ));
+        let duplicate = "{\"version\":1,\"size_a\":1,\"size_b\":2,\"proofs\":[{\"level\":0,\"index\":1,\"hash\":\"a\"},{\"level\":0,\"index\":1,\"hash\":\"b\"}]}";
+        assert!(from_json::<ConsistencyProof<usize>>(duplicate).is_err());
+        Ok(())
+    }
+}
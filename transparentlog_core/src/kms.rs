@@ -0,0 +1,185 @@
+//! # External signers
+//!
+//! Production logs keep their private key in a KMS or an HSM, and never in process memory: the signers here only
+//! know the public key, to identify the key in signatures, and hand the messages to sign to a callback calling
+//! the external service. A `CallbackSigner` calls a blocking API, like a PKCS#11 session, and an `AsyncCallbackSigner`
+//! returns the future of a network call, awaited by `sign_async`, or run to completion on the current thread by `sign`.
+use crate::checkpoint::*;
+use std::future::Future;
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread::Thread;
+
+/// A signer calling a blocking external service with the messages to sign, for an Ed25519 key
+pub struct CallbackSigner<F> {
+    verifier: Ed25519Verifier,
+    sign: F,
+}
+
+impl<F: Fn(&[u8]) -> anyhow::Result<Vec<u8>>> CallbackSigner<F> {
+    /// Create a new signer from the name and public key of the external key, and the callback signing with it
+    pub fn ed25519(name: &str, public: &[u8; 32], sign: F) -> Self {
+        Self {
+            verifier: Ed25519Verifier::new(name, public),
+            sign,
+        }
+    }
+
+    /// Get the verifier for this signer
+    pub fn verifier(&self) -> Ed25519Verifier {
+        self.verifier.clone()
+    }
+}
+
+impl<F: Fn(&[u8]) -> anyhow::Result<Vec<u8>>> LogSigner for CallbackSigner<F> {
+    fn name(&self) -> &str {
+        self.verifier.name()
+    }
+
+    fn key_hash(&self) -> u32 {
+        self.verifier.key_hash()
+    }
+
+    fn sign(&self, msg: &[u8]) -> anyhow::Result<Vec<u8>> {
+        (self.sign)(msg)
+    }
+}
+
+/// A signer calling an asynchronous external service with the messages to sign, for an Ed25519 key
+pub struct AsyncCallbackSigner<F> {
+    verifier: Ed25519Verifier,
+    sign: F,
+}
+
+impl<F, Fut> AsyncCallbackSigner<F>
+where
+    F: Fn(Vec<u8>) -> Fut,
+    Fut: Future<Output = anyhow::Result<Vec<u8>>> + Send + 'static,
+{
+    /// Create a new signer from the name and public key of the external key, and the callback signing with it
+    pub fn ed25519(name: &str, public: &[u8; 32], sign: F) -> Self {
+        Self {
+            verifier: Ed25519Verifier::new(name, public),
+            sign,
+        }
+    }
+
+    /// Get the verifier for this signer
+    pub fn verifier(&self) -> Ed25519Verifier {
+        self.verifier.clone()
+    }
+}
+
+impl<F, Fut> LogSigner for AsyncCallbackSigner<F>
+where
+    F: Fn(Vec<u8>) -> Fut,
+    Fut: Future<Output = anyhow::Result<Vec<u8>>> + Send + 'static,
+{
+    fn name(&self) -> &str {
+        self.verifier.name()
+    }
+
+    fn key_hash(&self) -> u32 {
+        self.verifier.key_hash()
+    }
+
+    /// Block the current thread until the external service returns the signature
+    fn sign(&self, msg: &[u8]) -> anyhow::Result<Vec<u8>> {
+        block_on((self.sign)(msg.to_vec()))
+    }
+
+    fn sign_async<'s>(&'s self, msg: &'s [u8]) -> SignatureFuture<'s> {
+        Box::pin((self.sign)(msg.to_vec()))
+    }
+}
+
+/// Wakes up the thread blocked on a future
+struct ThreadWaker(Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+/// Run a future to completion on the current thread, parking it while the future is pending
+pub(crate) fn block_on<F: Future>(future: F) -> F::Output {
+    let waker = Waker::from(Arc::new(ThreadWaker(std::thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    let mut future = Box::pin(future);
+    loop {
+        match future.as_mut().poll(&mut cx) {
+            Poll::Ready(output) => return output,
+            Poll::Pending => std::thread::park(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::kms::block_on;
+    use crate::test_helpers::*;
+    use crate::*;
+    use std::sync::mpsc;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn kms_callback() -> anyhow::Result<()> {
+        let mut ml: InMemoryLog<LogRecord> = InMemoryLog::default();
+        append_multiple(&mut ml, 13)?;
+        // the key held by the device, out of reach of the signer
+        let device = Ed25519Signer::new("example.com/log", &[7_u8; 32]);
+        let public = device.verifier();
+        let calls = Arc::new(Mutex::new(0));
+        let counted = calls.clone();
+        let signer = CallbackSigner::ed25519(
+            "example.com/log",
+            public.public_key(),
+            move |msg: &[u8]| {
+                *counted.lock().unwrap() += 1;
+                device.sign(msg)
+            },
+        );
+        assert_eq!(public, signer.verifier());
+        assert_eq!(public.key_hash(), signer.key_hash());
+
+        let checkpoint = Checkpoint::new("example.com/log", ml.latest()?);
+        let note = checkpoint.sign(&[&signer])?;
+        assert_eq!(checkpoint, Checkpoint::open(&note, &[&public])?);
+        assert_eq!(note, block_on(checkpoint.sign_async(&[&signer]))?);
+        assert_eq!(2, *calls.lock().unwrap());
+
+        let failing =
+            CallbackSigner::ed25519("example.com/log", public.public_key(), |_: &[u8]| {
+                Err(anyhow::anyhow!("device unavailable"))
+            });
+        assert!(checkpoint.sign(&[&failing]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn kms_async_callback() -> anyhow::Result<()> {
+        let mut ml: InMemoryLog<LogRecord> = InMemoryLog::default();
+        append_multiple(&mut ml, 13)?;
+        let device = Arc::new(Ed25519Signer::new("example.com/log", &[7_u8; 32]));
+        let public = device.verifier();
+        // a remote service answering from another thread
+        let signer = AsyncCallbackSigner::ed25519("example.com/log", public.public_key(), {
+            let device = device.clone();
+            move |msg: Vec<u8>| {
+                let (tx, rx) = mpsc::channel();
+                let device = device.clone();
+                std::thread::spawn(move || tx.send(device.sign(&msg)));
+                async move { rx.recv().unwrap_or_else(|e| Err(e.into())) }
+            }
+        });
+
+        let checkpoint = Checkpoint::new("example.com/log", ml.latest()?);
+        let note = block_on(checkpoint.sign_async(&[&signer]))?;
+        assert_eq!(checkpoint, Checkpoint::open(&note, &[&public])?);
+        assert_eq!(note, checkpoint.sign(&[&signer])?);
+        assert_eq!(note, checkpoint.sign(&[&*device])?);
+        Ok(())
+    }
+}
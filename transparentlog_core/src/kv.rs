@@ -0,0 +1,946 @@
+//! # Key-value storage
+//!
+//! A generic implementation of the log over any ordered key-value store with atomic batch writes,
+//! so adding a database backend only means implementing `KvStore`.
+//! Indices are stored as big endian `u64` keys, so the key order is the log order.
+//! The number of hashes of each level is stored with the hashes, so hash indices never depend on the number
+//! of records, and checked against it on open.
+use crate::base::*;
+use crate::cache::RootCache;
+use crate::codec::{check_codec, hash_with, Codec, MessagePack};
+use crate::compression::{check_compressor, compress, decompress, Compressor};
+use crate::instrument::{proofs_span, record_append, record_proofs, time_read};
+use maybe_owned::MaybeOwned;
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::marker::PhantomData;
+use thiserror::Error;
+
+/// The namespaces of a key-value store, that stores map to tables, column families or key prefixes
+#[derive(Hash, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug)]
+pub enum KvNamespace {
+    /// The serialized records, by index
+    Entries,
+    /// The hashes, by level and index
+    Hashes,
+    /// The indices of each leaf hash, keyed by hash and index, without values
+    Index,
+    /// The serialized record metadata, by index
+    Meta,
+    /// The record annotations, by index
+    Annotations,
+    /// The indices of the expired records, without values
+    Expired,
+    /// The values about the whole log, like its final tree head once sealed
+    Log,
+    /// The number of hashes of each level, by level
+    Levels,
+}
+
+impl KvNamespace {
+    /// All the namespaces
+    pub const ALL: [KvNamespace; 8] = [
+        KvNamespace::Entries,
+        KvNamespace::Hashes,
+        KvNamespace::Index,
+        KvNamespace::Meta,
+        KvNamespace::Annotations,
+        KvNamespace::Expired,
+        KvNamespace::Log,
+        KvNamespace::Levels,
+    ];
+
+    /// The name of the namespace
+    pub fn name(&self) -> &'static str {
+        match self {
+            KvNamespace::Entries => "entries",
+            KvNamespace::Hashes => "hashes",
+            KvNamespace::Index => "index",
+            KvNamespace::Meta => "meta",
+            KvNamespace::Annotations => "annotations",
+            KvNamespace::Expired => "expired",
+            KvNamespace::Log => "log",
+            KvNamespace::Levels => "levels",
+        }
+    }
+}
+
+/// A write to a key-value store: the namespace, the key and the value
+pub type KvWrite = (KvNamespace, Vec<u8>, Vec<u8>);
+
+/// A function called on each key and value of a scan
+pub type KvVisitor<'v> = dyn FnMut(&[u8], &[u8]) -> anyhow::Result<()> + 'v;
+
+/// The writes of a transaction not committed yet, by namespace and key
+type KvPending = BTreeMap<(KvNamespace, Vec<u8>), Vec<u8>>;
+
+/// An ordered key-value store
+pub trait KvStore {
+    /// Get the value of a key
+    fn get(&self, namespace: KvNamespace, key: &[u8]) -> anyhow::Result<Option<Vec<u8>>>;
+
+    /// Get the values of several keys, in the same order, that stores can read in one go
+    fn get_many(
+        &self,
+        namespace: KvNamespace,
+        keys: &[Vec<u8>],
+    ) -> anyhow::Result<Vec<Option<Vec<u8>>>> {
+        keys.iter().map(|k| self.get(namespace, k)).collect()
+    }
+
+    /// Write all the given values in one atomic transaction
+    fn put(&mut self, writes: Vec<KvWrite>) -> anyhow::Result<()>;
+
+    /// Call the given function on each key and value from start (inclusive) to end (exclusive), in key order
+    fn scan(
+        &self,
+        namespace: KvNamespace,
+        start: &[u8],
+        end: &[u8],
+        f: &mut KvVisitor,
+    ) -> anyhow::Result<()>;
+
+    /// The last key of a namespace
+    fn last_key(&self, namespace: KvNamespace) -> anyhow::Result<Option<Vec<u8>>>;
+
+    /// Drop the overwritten values of a key from storage, for stores keeping them until a compaction
+    fn compact(&self, _namespace: KvNamespace, _key: &[u8]) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+/// Specific Errors for key-value logs
+#[derive(Debug, Clone, Error)]
+pub enum KvLogError {
+    #[error("Level {0} holds {1} hashes, inconsistent with a log of {2} records")]
+    InconsistentLevel(LogHeight, u64, u64),
+}
+
+/// A key-value store in memory
+#[derive(Debug, Default)]
+pub struct MemoryKvStore {
+    namespaces: BTreeMap<KvNamespace, BTreeMap<Vec<u8>, Vec<u8>>>,
+}
+
+impl KvStore for MemoryKvStore {
+    fn get(&self, namespace: KvNamespace, key: &[u8]) -> anyhow::Result<Option<Vec<u8>>> {
+        Ok(self
+            .namespaces
+            .get(&namespace)
+            .and_then(|m| m.get(key))
+            .cloned())
+    }
+
+    fn put(&mut self, writes: Vec<KvWrite>) -> anyhow::Result<()> {
+        for (namespace, key, value) in writes {
+            self.namespaces
+                .entry(namespace)
+                .or_default()
+                .insert(key, value);
+        }
+        Ok(())
+    }
+
+    fn scan(
+        &self,
+        namespace: KvNamespace,
+        start: &[u8],
+        end: &[u8],
+        f: &mut KvVisitor,
+    ) -> anyhow::Result<()> {
+        if let Some(m) = self.namespaces.get(&namespace) {
+            for (k, v) in m.range(start.to_vec()..end.to_vec()) {
+                f(k, v)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn last_key(&self, namespace: KvNamespace) -> anyhow::Result<Option<Vec<u8>>> {
+        Ok(self
+            .namespaces
+            .get(&namespace)
+            .and_then(|m| m.keys().next_back())
+            .cloned())
+    }
+}
+
+/// The key of the final tree head of a sealed log
+const SEALED_KEY: &[u8] = b"sealed";
+
+/// The key of the name of the compressor of the records
+const COMPRESSION_KEY: &[u8] = b"compression";
+
+/// The key of the name of the codec of the records, absent for MessagePack
+const CODEC_KEY: &[u8] = b"codec";
+
+/// The key of a record, metadata or annotation
+fn id_key(id: u64) -> Vec<u8> {
+    id.to_be_bytes().to_vec()
+}
+
+/// The key of a hash: its level and index
+fn hash_key(level: LogHeight, index: u64) -> Vec<u8> {
+    let mut key = (level as u64).to_be_bytes().to_vec();
+    key.extend_from_slice(&index.to_be_bytes());
+    key
+}
+
+/// The key of a leaf in the index: its hash then its index, so the leaves of a hash are a key range
+fn index_key(hash: &str, index: u64) -> Vec<u8> {
+    let mut key = hash.as_bytes().to_vec();
+    key.extend_from_slice(&index.to_be_bytes());
+    key
+}
+
+/// The writes of a serialized record at the index, to fill a store from another storage format
+pub fn kv_record_writes(index: u64, bytes: Vec<u8>) -> Vec<KvWrite> {
+    vec![(KvNamespace::Entries, id_key(index), bytes)]
+}
+
+/// The writes of a hash at the position, indexing it if it is a leaf, to fill a store from another storage format
+/// The level counters are rebuilt from the number of records when the log is opened
+pub fn kv_hash_writes(level: LogHeight, index: u64, hash: String) -> Vec<KvWrite> {
+    let mut writes = vec![];
+    if level == 0 {
+        writes.push((KvNamespace::Index, index_key(&hash, index), vec![]));
+    }
+    writes.push((
+        KvNamespace::Hashes,
+        hash_key(level, index),
+        hash.into_bytes(),
+    ));
+    writes
+}
+
+/// Read a big endian u64
+fn read_u64(bs: &[u8]) -> anyhow::Result<u64> {
+    Ok(u64::from_be_bytes(bs.try_into()?))
+}
+
+/// Read the number of hashes of each level, and check they match the number of records
+/// Stores written before the level counters were stored get them from the number of records
+fn read_levels<S: KvStore>(store: &mut S, size: u64) -> anyhow::Result<Vec<u64>> {
+    let expected: Vec<u64> = tree_sizes(size).into_iter().filter(|s| *s > 0).collect();
+    let mut level_sizes = vec![];
+    store.scan(
+        KvNamespace::Levels,
+        &id_key(0),
+        &id_key(u64::MAX),
+        &mut |key, value| {
+            let level = level_sizes.len();
+            let count = read_u64(value)?;
+            if read_u64(key)? != level as u64 || expected.get(level) != Some(&count) {
+                return Err(KvLogError::InconsistentLevel(level, count, size).into());
+            }
+            level_sizes.push(count);
+            Ok(())
+        },
+    )?;
+    if level_sizes.is_empty() {
+        if !expected.is_empty() {
+            store.put(
+                expected
+                    .iter()
+                    .enumerate()
+                    .map(|(level, count)| {
+                        (KvNamespace::Levels, id_key(level as u64), id_key(*count))
+                    })
+                    .collect(),
+            )?;
+        }
+        return Ok(expected);
+    }
+    if level_sizes.len() < expected.len() {
+        return Err(KvLogError::InconsistentLevel(level_sizes.len(), 0, size).into());
+    }
+    Ok(level_sizes)
+}
+
+/// Check the codec and the compressor against the ones recorded in the store, recording them for a new store
+fn check_header<C: Codec, S: KvStore>(
+    store: &mut S,
+    compressor: Option<&dyn Compressor>,
+    size: u64,
+) -> anyhow::Result<()> {
+    let recorded = store
+        .get(KvNamespace::Log, COMPRESSION_KEY)?
+        .map(String::from_utf8)
+        .transpose()?;
+    if check_compressor(recorded.as_deref(), compressor, size == 0)? {
+        if let Some(c) = compressor {
+            store.put(vec![(
+                KvNamespace::Log,
+                COMPRESSION_KEY.to_vec(),
+                c.name().as_bytes().to_vec(),
+            )])?;
+        }
+    }
+    let recorded = store
+        .get(KvNamespace::Log, CODEC_KEY)?
+        .map(String::from_utf8)
+        .transpose()?;
+    if check_codec::<C>(recorded.as_deref(), size == 0)? {
+        store.put(vec![(
+            KvNamespace::Log,
+            CODEC_KEY.to_vec(),
+            C::NAME.as_bytes().to_vec(),
+        )])?;
+    }
+    Ok(())
+}
+
+/// Decompress and deserialize a stored record with the codec C, an empty one being a tombstone
+fn decode_stored<C: Codec, T: DeserializeOwned>(
+    compressor: Option<&dyn Compressor>,
+    bs: Vec<u8>,
+) -> anyhow::Result<T> {
+    if bs.is_empty() {
+        return Err(TombstoneError::NoRecord.into());
+    }
+    C::decode(&decompress(compressor, bs)?)
+}
+
+/// A log over a key-value store, serializing its records with the codec C
+/// Appends write the record, its metadata and all its hashes in a single `put`,
+/// so a store with atomic batches never holds a record without its hashes
+#[derive(Debug)]
+pub struct KvLog<'a, T: Serialize + Deserialize<'a>, S, C: Codec = MessagePack> {
+    store: S,
+    size: u64,
+    level_sizes: Vec<u64>,
+    compressor: Option<Box<dyn Compressor>>,
+    pending: Option<KvPending>,
+    sealed: Option<LogTree<u64>>,
+    root: RootCache<u64>,
+    pending_hashes: PendingHashes<u64>,
+    _marker: PhantomData<(&'a T, C)>,
+}
+
+impl<'a, T: Serialize + Deserialize<'a>, S: KvStore> KvLog<'a, T, S> {
+    /// Open a log over a new or existing store
+    pub fn open(store: S) -> anyhow::Result<Self> {
+        Self::open_with_codec(store, None)
+    }
+
+    /// Open a log over a new or existing store, compressing the records with the given compressor
+    /// A new store records the compressor name, an existing one must have been written with it
+    pub fn open_with_compressor(store: S, compressor: Box<dyn Compressor>) -> anyhow::Result<Self> {
+        Self::open_with_codec(store, Some(compressor))
+    }
+}
+
+impl<'a, T: Serialize + Deserialize<'a>, S: KvStore, C: Codec> KvLog<'a, T, S, C> {
+    /// Open a log over a new or existing store, serializing the records with the codec C
+    /// A new store records the codec name, an existing one must have been written with it
+    pub fn open_with_codec(
+        mut store: S,
+        compressor: Option<Box<dyn Compressor>>,
+    ) -> anyhow::Result<Self> {
+        let size = match store.last_key(KvNamespace::Entries)? {
+            Some(key) => read_u64(&key)? + 1,
+            None => 0,
+        };
+        let level_sizes = read_levels(&mut store, size)?;
+        check_header::<C, S>(&mut store, compressor.as_deref(), size)?;
+        let sealed = store
+            .get(KvNamespace::Log, SEALED_KEY)?
+            .map(|bs| rmp_serde::from_slice(&bs))
+            .transpose()?;
+        Ok(Self {
+            store,
+            size,
+            level_sizes,
+            compressor,
+            pending: None,
+            sealed,
+            root: RootCache::default(),
+            pending_hashes: PendingHashes::default(),
+            _marker: PhantomData,
+        })
+    }
+
+    /// The underlying store
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+
+    /// Get back the underlying store
+    pub fn into_store(self) -> S {
+        self.store
+    }
+
+    /// Check a position is in the tree
+    fn check_position(&self, level: LogHeight, index: u64) -> anyhow::Result<()> {
+        match self.level_sizes.get(level) {
+            None => Err(LogError::InvalidHeight::<u64>(level).into()),
+            Some(size) if index >= *size => Err(LogError::InvalidIndex(level, index).into()),
+            Some(_) => Ok(()),
+        }
+    }
+
+    /// Read a value, from the pending transaction if it wrote it
+    fn read(&self, namespace: KvNamespace, key: Vec<u8>) -> anyhow::Result<Option<Vec<u8>>> {
+        let key = (namespace, key);
+        match self.pending.as_ref().and_then(|p| p.get(&key)) {
+            Some(value) => Ok(Some(value.clone())),
+            None => time_read("kv", || self.store.get(namespace, &key.1)),
+        }
+    }
+
+    /// Write a value in the pending transaction if there is one, or straight to the store
+    fn write(
+        &mut self,
+        namespace: KvNamespace,
+        key: Vec<u8>,
+        value: Vec<u8>,
+    ) -> anyhow::Result<()> {
+        match self.pending.as_mut() {
+            Some(pending) => {
+                pending.insert((namespace, key), value);
+                Ok(())
+            }
+            None => self.store.put(vec![(namespace, key, value)]),
+        }
+    }
+
+    /// Run the given writes in a single store transaction, restoring the sizes if anything fails
+    fn transaction<R>(
+        &mut self,
+        f: impl FnOnce(&mut Self) -> anyhow::Result<R>,
+    ) -> anyhow::Result<R> {
+        let size = self.size;
+        let level_sizes = self.level_sizes.clone();
+        self.pending = Some(KvPending::new());
+        let r = f(self);
+        let pending = self.pending.take();
+        let r = match (r, pending) {
+            (Ok(r), Some(pending)) => self
+                .store
+                .put(pending.into_iter().map(|((n, k), v)| (n, k, v)).collect())
+                .map(|_| r),
+            (r, _) => r,
+        };
+        if r.is_err() {
+            self.size = size;
+            self.level_sizes = level_sizes;
+            self.pending_hashes.clear();
+        }
+        r
+    }
+}
+
+/// Implement TransparentLog API
+impl<'a, T: Serialize + DeserializeOwned, S: KvStore, C: Codec> TransparentLog<'a, T>
+    for KvLog<'a, T, S, C>
+{
+    type LogSize = u64;
+
+    fn leaf_hasher(&self) -> LeafHasher<T> {
+        |record| hash_with::<C, T>(record)
+    }
+
+    fn size(&self) -> anyhow::Result<Self::LogSize> {
+        Ok(self.size)
+    }
+
+    fn latest(&self) -> anyhow::Result<LogTree<Self::LogSize>> {
+        self.root
+            .get_or_compute(self.size, || self.head_at(self.size))
+    }
+
+    fn add(&mut self, record: T) -> anyhow::Result<Self::LogSize> {
+        check_unsealed(&self.sealed)?;
+        let id = self.size;
+        let data = compress(self.compressor.as_deref(), C::encode(&record)?)?;
+        self.write(KvNamespace::Entries, id_key(id), data)?;
+        self.size += 1;
+        record_append("kv");
+        Ok(id)
+    }
+
+    /// A tombstone is an empty value
+    fn add_tombstone(&mut self) -> anyhow::Result<Self::LogSize> {
+        check_unsealed(&self.sealed)?;
+        let id = self.size;
+        self.write(KvNamespace::Entries, id_key(id), vec![])?;
+        self.size += 1;
+        record_append("kv");
+        Ok(id)
+    }
+
+    /// Compacts the record key afterwards, so the record is dropped from storage
+    fn redact(&mut self, id: Self::LogSize) -> anyhow::Result<()> {
+        check_unsealed(&self.sealed)?;
+        if id >= self.size {
+            return Err(LogError::InvalidIndex(0, id).into());
+        }
+        self.write(KvNamespace::Entries, id_key(id), vec![])?;
+        if self.pending.is_none() {
+            self.store.compact(KvNamespace::Entries, &id_key(id))?;
+        }
+        Ok(())
+    }
+
+    /// Redact the record and mark it expired in one transaction, then compact the record key
+    fn expire(&mut self, id: Self::LogSize) -> anyhow::Result<()> {
+        self.transaction(|log| {
+            log.redact(id)?;
+            log.write(KvNamespace::Expired, id_key(id), vec![])
+        })?;
+        self.store.compact(KvNamespace::Entries, &id_key(id))
+    }
+
+    fn expired(&self, id: Self::LogSize) -> anyhow::Result<bool> {
+        Ok(self.read(KvNamespace::Expired, id_key(id))?.is_some())
+    }
+
+    /// Write the tombstone and all the hashes it creates in one transaction
+    fn append_hash_only(&mut self, hash: String) -> anyhow::Result<Record<Self::LogSize>> {
+        let hash = checked_leaf_hash(&hash)?;
+        self.transaction(|log| {
+            let meta = log.next_meta()?;
+            let id = log.add_tombstone()?;
+            log.add_meta(id, meta)?;
+            log.push_hash(0, hash.clone())?;
+            Ok(Record {
+                id,
+                hash,
+                meta: Some(meta),
+            })
+        })
+    }
+
+    /// Write the record and all the hashes it creates in one transaction
+    fn append(&mut self, record: T) -> anyhow::Result<Record<Self::LogSize>> {
+        self.transaction(|log| {
+            let hash = hash_with::<C, _>(&record)?;
+            let meta = log.next_meta()?;
+            let id = log.add(record)?;
+            log.add_meta(id, meta)?;
+            log.push_hash(0, hash.clone())?;
+            Ok(Record {
+                id,
+                hash,
+                meta: Some(meta),
+            })
+        })
+    }
+
+    /// Hash all the records, in parallel with the `parallel` feature, then write them and their hashes in one transaction
+    fn bulk_append(&mut self, records: Vec<T>) -> anyhow::Result<Vec<Record<Self::LogSize>>>
+    where
+        T: Sync,
+    {
+        #[cfg(feature = "parallel")]
+        let hashes = records
+            .par_iter()
+            .map(hash_with::<C, _>)
+            .collect::<anyhow::Result<Vec<String>>>()?;
+        #[cfg(not(feature = "parallel"))]
+        let hashes = records
+            .iter()
+            .map(hash_with::<C, _>)
+            .collect::<anyhow::Result<Vec<String>>>()?;
+        self.transaction(|log| {
+            let meta = log.next_meta()?;
+            records
+                .into_iter()
+                .zip(hashes)
+                .map(|(record, hash)| {
+                    let id = log.add(record)?;
+                    log.add_meta(id, meta)?;
+                    log.push_hash(0, hash.clone())?;
+                    Ok(Record {
+                        id,
+                        hash,
+                        meta: Some(meta),
+                    })
+                })
+                .collect()
+        })
+    }
+
+    fn add_meta(&mut self, id: Self::LogSize, meta: RecordMeta) -> anyhow::Result<()> {
+        check_unsealed(&self.sealed)?;
+        self.write(KvNamespace::Meta, id_key(id), rmp_serde::to_vec(&meta)?)
+    }
+
+    fn get_meta(&self, id: Self::LogSize) -> anyhow::Result<Option<RecordMeta>> {
+        let obs = self.read(KvNamespace::Meta, id_key(id))?;
+        Ok(obs.map(|bs| rmp_serde::from_slice(&bs)).transpose()?)
+    }
+
+    fn set_annotation(&mut self, id: Self::LogSize, annotation: Vec<u8>) -> anyhow::Result<()> {
+        check_unsealed(&self.sealed)?;
+        if id >= self.size()? {
+            return Err(LogError::InvalidIndex(0, id).into());
+        }
+        self.write(KvNamespace::Annotations, id_key(id), annotation)
+    }
+
+    fn get_annotation(&self, id: Self::LogSize) -> anyhow::Result<Option<Vec<u8>>> {
+        self.read(KvNamespace::Annotations, id_key(id))
+    }
+
+    /// Stores the final tree head in the log namespace, then refuses any write
+    fn seal(&mut self) -> anyhow::Result<LogTree<Self::LogSize>> {
+        if let Some(tree) = &self.sealed {
+            return Ok(tree.clone());
+        }
+        let tree = self.latest()?;
+        self.write(
+            KvNamespace::Log,
+            SEALED_KEY.to_vec(),
+            rmp_serde::to_vec(&tree)?,
+        )?;
+        self.sealed = Some(tree.clone());
+        Ok(tree)
+    }
+
+    fn sealed(&self) -> anyhow::Result<Option<LogTree<Self::LogSize>>> {
+        Ok(self.sealed.clone())
+    }
+
+    fn pending_hashes(&mut self) -> Option<&mut PendingHashes<Self::LogSize>> {
+        Some(&mut self.pending_hashes)
+    }
+
+    fn add_hash(&mut self, level: LogHeight, hash: String) -> anyhow::Result<Self::LogSize> {
+        check_unsealed(&self.sealed)?;
+        self.root.clear();
+        if level == self.level_sizes.len() {
+            self.level_sizes.push(0);
+        }
+        let index = *self
+            .level_sizes
+            .get(level)
+            .ok_or(LogError::InvalidHeight::<Self::LogSize>(level))?;
+        for (namespace, key, value) in kv_hash_writes(level, index, hash) {
+            self.write(namespace, key, value)?;
+        }
+        // the counter is written with the hash, so indices never depend on the number of records
+        self.write(KvNamespace::Levels, id_key(level as u64), id_key(index + 1))?;
+        self.level_sizes[level] += 1;
+        Ok(index)
+    }
+
+    fn get_hash(
+        &self,
+        level: LogHeight,
+        index: Self::LogSize,
+    ) -> anyhow::Result<MaybeOwned<'_, String>> {
+        self.check_position(level, index)?;
+        let bs = self
+            .read(KvNamespace::Hashes, hash_key(level, index))?
+            .ok_or(LogError::InvalidIndex(level, index))?;
+        Ok(String::from_utf8(bs)?.into())
+    }
+
+    /// Get all the hashes with a single `get_many`
+    fn proofs<I>(
+        &self,
+        positions: I,
+    ) -> anyhow::Result<HashMap<LogTreePosition<Self::LogSize>, String>>
+    where
+        I: Iterator<Item = LogTreePosition<Self::LogSize>>,
+    {
+        let positions: Vec<_> = positions.collect();
+        let _span = proofs_span("kv", positions.len());
+        let keys = positions
+            .iter()
+            .map(|p| {
+                self.check_position(p.level, p.index)?;
+                Ok(hash_key(p.level, p.index))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let values = time_read("kv", || self.store.get_many(KvNamespace::Hashes, &keys))?;
+        record_proofs("kv", keys.len());
+        positions
+            .into_iter()
+            .zip(keys.into_iter().zip(values))
+            .map(|(p, (k, v))| {
+                let pending = self
+                    .pending
+                    .as_ref()
+                    .and_then(|pending| pending.get(&(KvNamespace::Hashes, k)));
+                let bs = pending
+                    .cloned()
+                    .or(v)
+                    .ok_or(LogError::InvalidIndex(p.level, p.index))?;
+                Ok((p, String::from_utf8(bs)?))
+            })
+            .collect()
+    }
+
+    fn get(&self, index: Self::LogSize) -> anyhow::Result<Option<MaybeOwned<'_, T>>> {
+        if index >= self.size {
+            return Ok(None);
+        }
+        self.get_raw_bytes(index)?
+            .map(|bs| Ok(MaybeOwned::Owned(C::decode(&bs)?)))
+            .transpose()
+    }
+
+    /// The stored bytes, decompressed
+    fn get_raw_bytes(&self, index: Self::LogSize) -> anyhow::Result<Option<Vec<u8>>> {
+        if index >= self.size {
+            return Ok(None);
+        }
+        match self.read(KvNamespace::Entries, id_key(index))? {
+            Some(bs) if bs.is_empty() => Err(tombstone_error(self, index)),
+            Some(bs) => Ok(Some(decompress(self.compressor.as_deref(), bs)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Scan the whole range in one go
+    fn get_range(
+        &self,
+        start: Self::LogSize,
+        end: Self::LogSize,
+    ) -> anyhow::Result<Vec<MaybeOwned<'_, T>>> {
+        let mut v = vec![];
+        if start < end {
+            self.store.scan(
+                KvNamespace::Entries,
+                &id_key(start),
+                &id_key(end),
+                &mut |_, bs| {
+                    let r = decode_stored::<C, _>(self.compressor.as_deref(), bs.to_vec())?;
+                    v.push(MaybeOwned::Owned(r));
+                    Ok(())
+                },
+            )?;
+        }
+        Ok(v)
+    }
+
+    fn index_of(&self, hash: &str) -> anyhow::Result<Option<Self::LogSize>> {
+        Ok(self.indices_of(hash)?.into_iter().next())
+    }
+
+    /// Scan the index range of the hash, and the index keys of the pending transaction
+    fn indices_of(&self, hash: &str) -> anyhow::Result<Vec<Self::LogSize>> {
+        let start = index_key(hash, 0);
+        let end = index_key(hash, self.level_sizes.first().copied().unwrap_or(0));
+        let mut v = vec![];
+        self.store
+            .scan(KvNamespace::Index, &start, &end, &mut |key, _| {
+                v.push(read_u64(&key[hash.len()..])?);
+                Ok(())
+            })?;
+        if let Some(pending) = self.pending.as_ref() {
+            for ((_, key), _) in
+                pending.range((KvNamespace::Index, start)..(KvNamespace::Index, end))
+            {
+                v.push(read_u64(&key[hash.len()..])?);
+            }
+            v.sort_unstable();
+            v.dedup();
+        }
+        Ok(v)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::id_key;
+    use crate::test_helpers::*;
+    use crate::*;
+
+    #[test]
+    fn kv_empty() -> anyhow::Result<()> {
+        let mut ml: KvLog<LogRecord, _> = KvLog::open(MemoryKvStore::default())?;
+        empty(&mut ml)
+    }
+
+    #[test]
+    fn kv_out_of_range() -> anyhow::Result<()> {
+        let mut ml: KvLog<LogRecord, _> = KvLog::open(MemoryKvStore::default())?;
+        out_of_range(&mut ml)
+    }
+
+    #[test]
+    fn kv_range() -> anyhow::Result<()> {
+        let mut ml: KvLog<LogRecord, _> = KvLog::open(MemoryKvStore::default())?;
+        range(&mut ml)
+    }
+
+    #[test]
+    fn kv_bulk() -> anyhow::Result<()> {
+        let mut ml: KvLog<LogRecord, _> = KvLog::open(MemoryKvStore::default())?;
+        bulk(&mut ml)
+    }
+
+    #[test]
+    fn kv_annotations() -> anyhow::Result<()> {
+        let mut ml: KvLog<LogRecord, _> = KvLog::open(MemoryKvStore::default())?;
+        annotations(&mut ml)
+    }
+
+    #[test]
+    fn kv_duplicates() -> anyhow::Result<()> {
+        let mut ml: KvLog<LogRecord, _> = KvLog::open(MemoryKvStore::default())?;
+        duplicates(&mut ml)?;
+        // duplicates within a single transaction
+        ml.bulk_append(vec![LogRecord::new("rec1"), LogRecord::new("rec1")])?;
+        let hash1 = hash(&LogRecord::new("rec1"))?;
+        assert_eq!(vec![1, 5, 6, 7, 8], ml.indices_of(&hash1)?);
+        // one index key per leaf
+        let store = ml.into_store();
+        assert_eq!(9, store.namespaces[&KvNamespace::Index].len());
+        Ok(())
+    }
+
+    #[test]
+    fn kv_hash_only() -> anyhow::Result<()> {
+        let mut ml: KvLog<LogRecord, _> = KvLog::open(MemoryKvStore::default())?;
+        hash_only(&mut ml)?;
+        let ml: KvLog<LogRecord, _> = KvLog::open(ml.into_store())?;
+        check_13(&ml)
+    }
+
+    #[test]
+    fn kv_invalid_hash_only() -> anyhow::Result<()> {
+        let mut ml: KvLog<LogRecord, _> = KvLog::open(MemoryKvStore::default())?;
+        invalid_hash_only(&mut ml)?;
+        let ml: KvLog<LogRecord, _> = KvLog::open(ml.into_store())?;
+        check_13(&ml)
+    }
+
+    #[test]
+    fn kv_self_check() -> anyhow::Result<()> {
+        let mut ml: KvLog<LogRecord, _> = KvLog::open(MemoryKvStore::default())?;
+        self_check(&mut ml)
+    }
+
+    #[test]
+    fn kv_expire() -> anyhow::Result<()> {
+        let mut ml: KvLog<LogRecord, _> = KvLog::open(MemoryKvStore::default())?;
+        expire(&mut ml)
+    }
+
+    #[test]
+    fn kv_redact() -> anyhow::Result<()> {
+        let mut ml: KvLog<LogRecord, _> = KvLog::open(MemoryKvStore::default())?;
+        redact(&mut ml)
+    }
+
+    #[test]
+    fn kv_seal() -> anyhow::Result<()> {
+        let mut ml: KvLog<LogRecord, _> = KvLog::open(MemoryKvStore::default())?;
+        seal(&mut ml)?;
+        let tree = ml.sealed()?;
+        let mut ml: KvLog<LogRecord, _> = KvLog::open(ml.into_store())?;
+        assert_eq!(tree, ml.sealed()?);
+        assert_sealed(ml.append(LogRecord::new("rec13")));
+        Ok(())
+    }
+
+    #[test]
+    fn kv_properties() -> anyhow::Result<()> {
+        properties(|| KvLog::<LogRecord, _>::open(MemoryKvStore::default()))
+    }
+
+    #[test]
+    fn kv_13() -> anyhow::Result<()> {
+        let mut ml: KvLog<LogRecord, _> = KvLog::open(MemoryKvStore::default())?;
+        test_13(&mut ml)?;
+        let ml: KvLog<LogRecord, _> = KvLog::open(ml.into_store())?;
+        check_13(&ml)
+    }
+
+    #[test]
+    fn kv_levels() -> anyhow::Result<()> {
+        let mut ml: KvLog<LogRecord, _> = KvLog::open(MemoryKvStore::default())?;
+        test_13(&mut ml)?;
+        // remove the counters, as in a store written before them
+        let mut store = ml.into_store();
+        store.namespaces.remove(&KvNamespace::Levels);
+        let ml: KvLog<LogRecord, _> = KvLog::open(store)?;
+        check_13(&ml)?;
+
+        // a counter out of step with the records
+        let mut store = ml.into_store();
+        store.put(vec![(KvNamespace::Levels, id_key(1), id_key(5))])?;
+        let err = KvLog::<LogRecord, _>::open(store).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<KvLogError>(),
+            Some(KvLogError::InconsistentLevel(1, 5, 13))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn kv_compressed_13() -> anyhow::Result<()> {
+        let mut ml: KvLog<LogRecord, _> =
+            KvLog::open_with_compressor(MemoryKvStore::default(), Box::new(RleCompressor))?;
+        test_13(&mut ml)?;
+        let ml: KvLog<LogRecord, _> =
+            KvLog::open_with_compressor(ml.into_store(), Box::new(RleCompressor))?;
+        check_13(&ml)?;
+        assert_eq!(3, ml.get_range(10, 13)?.len());
+        let err = KvLog::<LogRecord, _>::open(ml.into_store()).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<CompressionError>(),
+            Some(CompressionError::Mismatch { expected: None, .. })
+        ));
+        Ok(())
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn kv_codec_json() -> anyhow::Result<()> {
+        let mut ml = KvLog::<LogRecord, _, Json>::open_with_codec(MemoryKvStore::default(), None)?;
+        codec_records::<Json, _>(&mut ml)?;
+        let ml = KvLog::<LogRecord, _, Json>::open_with_codec(ml.into_store(), None)?;
+        assert_eq!(13, ml.iter().count());
+        let err = KvLog::<LogRecord, _>::open(ml.into_store()).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<CodecError>(),
+            Some(CodecError::Mismatch { expected, found }) if expected == "msgpack" && found == "json"
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn kv_client_13_no_cache() -> anyhow::Result<()> {
+        let mut ml: KvLog<LogRecord, _> = KvLog::open(MemoryKvStore::default())?;
+        let mut client = InMemoryLogClientBuilder::new(&ml)?.no_cache().build();
+        client_13_nocache(&mut ml, &mut client)
+    }
+
+    #[test]
+    fn kv_client_convert() -> anyhow::Result<()> {
+        let mut ml: InMemoryLog<LogRecord> = InMemoryLog::default();
+        append_multiple(&mut ml, 13)?;
+        let mut kv: KvLog<LogRecord, _> = KvLog::open(MemoryKvStore::default())?;
+        append_multiple(&mut kv, 13)?;
+        let record = Record {
+            id: 5,
+            hash: ml.get_hash(0, 5)?.into_owned(),
+            meta: None,
+        };
+        let mut client = InMemoryLogClientBuilder::new(&ml)?.build();
+        assert!(check_record(&mut client, &ml, &record)?);
+
+        // the client of the in-memory log checks the same records in the key value log
+        let mut converted = client.convert::<u64>()?;
+        assert_eq!(13, converted.latest().size);
+        assert!(check_record(&mut converted, &kv, &record.convert()?)?);
+
+        let tree = LogTree {
+            size: u128::MAX,
+            hash: String::new(),
+            origin: None,
+        };
+        assert_eq!(Err(SizeError::Overflow), tree.convert::<u64>());
+        assert_eq!(Ok(u128::MAX), convert_size(&tree.size));
+        Ok(())
+    }
+}
@@ -0,0 +1,293 @@
+//! # Leaf encodings
+//!
+//! Record types with a canonical encoding (DER certificates, protobufs, signed statements) implement `Leaf`
+//! and are logged through a `LeafLog`, whose leaf hashes are the hashes of their canonical bytes
+//! instead of the hashes of their serialization by the codec of the log.
+use crate::base::*;
+use crate::raw::hash_bytes;
+use maybe_owned::MaybeOwned;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+/// A record with a canonical encoding, hashed as the leaf of the tree
+pub trait Leaf {
+    /// The canonical bytes of the record
+    fn leaf_bytes(&self) -> Cow<'_, [u8]>;
+}
+
+impl Leaf for [u8] {
+    fn leaf_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Borrowed(self)
+    }
+}
+
+impl Leaf for Vec<u8> {
+    fn leaf_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Borrowed(self)
+    }
+}
+
+impl Leaf for str {
+    fn leaf_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Borrowed(self.as_bytes())
+    }
+}
+
+impl Leaf for String {
+    fn leaf_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Borrowed(self.as_bytes())
+    }
+}
+
+/// Hash a given record via its canonical bytes
+pub fn hash_leaf<T: Leaf + ?Sized>(record: &T) -> String {
+    hash_bytes(&record.leaf_bytes())
+}
+
+/// A log hashing its records via their canonical bytes
+/// Records are still stored by the wrapped log with its codec, but appended through its primitives,
+/// so logs appending in one batch or transaction, like RocksDB, lose that atomicity when wrapped
+#[derive(Debug)]
+pub struct LeafLog<L> {
+    log: L,
+}
+
+impl<L> LeafLog<L> {
+    /// Wrap a log, hashing the records appended from now on via their canonical bytes
+    pub fn new(log: L) -> Self {
+        Self { log }
+    }
+
+    /// The wrapped log
+    pub fn inner(&self) -> &L {
+        &self.log
+    }
+
+    /// Get back the wrapped log
+    pub fn into_inner(self) -> L {
+        self.log
+    }
+}
+
+/// Appends use the default implementations, with the leaf hasher of this log
+impl<'a, T, L> TransparentLog<'a, T> for LeafLog<L>
+where
+    T: Serialize + Deserialize<'a> + Leaf,
+    L: TransparentLog<'a, T>,
+{
+    type LogSize = L::LogSize;
+
+    fn leaf_hasher(&self) -> LeafHasher<T> {
+        |record| Ok(hash_leaf(record))
+    }
+
+    fn add(&mut self, record: T) -> anyhow::Result<Self::LogSize> {
+        self.log.add(record)
+    }
+
+    fn add_tombstone(&mut self) -> anyhow::Result<Self::LogSize> {
+        self.log.add_tombstone()
+    }
+
+    fn redact(&mut self, id: Self::LogSize) -> anyhow::Result<()> {
+        self.log.redact(id)
+    }
+
+    fn expire(&mut self, id: Self::LogSize) -> anyhow::Result<()> {
+        self.log.expire(id)
+    }
+
+    fn expired(&self, id: Self::LogSize) -> anyhow::Result<bool> {
+        self.log.expired(id)
+    }
+
+    fn seal(&mut self) -> anyhow::Result<LogTree<Self::LogSize>> {
+        self.log.seal()
+    }
+
+    fn sealed(&self) -> anyhow::Result<Option<LogTree<Self::LogSize>>> {
+        self.log.sealed()
+    }
+
+    fn sync(&self) -> anyhow::Result<()> {
+        self.log.sync()
+    }
+
+    fn add_meta(&mut self, id: Self::LogSize, meta: RecordMeta) -> anyhow::Result<()> {
+        self.log.add_meta(id, meta)
+    }
+
+    fn get_meta(&self, id: Self::LogSize) -> anyhow::Result<Option<RecordMeta>> {
+        self.log.get_meta(id)
+    }
+
+    fn set_annotation(&mut self, id: Self::LogSize, annotation: Vec<u8>) -> anyhow::Result<()> {
+        self.log.set_annotation(id, annotation)
+    }
+
+    fn get_annotation(&self, id: Self::LogSize) -> anyhow::Result<Option<Vec<u8>>> {
+        self.log.get_annotation(id)
+    }
+
+    fn find_since(&self, timestamp: u64) -> anyhow::Result<Self::LogSize> {
+        self.log.find_since(timestamp)
+    }
+
+    fn add_hash(&mut self, level: LogHeight, hash: String) -> anyhow::Result<Self::LogSize> {
+        self.log.add_hash(level, hash)
+    }
+
+    fn get_hash(
+        &self,
+        level: LogHeight,
+        index: Self::LogSize,
+    ) -> anyhow::Result<MaybeOwned<'_, String>> {
+        self.log.get_hash(level, index)
+    }
+
+    fn size(&self) -> anyhow::Result<Self::LogSize> {
+        self.log.size()
+    }
+
+    fn origin(&self) -> Option<String> {
+        self.log.origin()
+    }
+
+    fn latest(&self) -> anyhow::Result<LogTree<Self::LogSize>> {
+        self.log.latest()
+    }
+
+    fn get(&self, index: Self::LogSize) -> anyhow::Result<Option<MaybeOwned<'_, T>>> {
+        self.log.get(index)
+    }
+
+    /// The record as the wrapped log serialized it, which is not what the leaf hash is the hash of
+    fn get_raw_bytes(&self, index: Self::LogSize) -> anyhow::Result<Option<Vec<u8>>> {
+        self.log.get_raw_bytes(index)
+    }
+
+    /// The canonical bytes of the record
+    fn get_leaf_bytes(&self, index: Self::LogSize) -> anyhow::Result<Option<Vec<u8>>> {
+        Ok(self
+            .log
+            .get(index)?
+            .map(|record| record.leaf_bytes().into_owned()))
+    }
+
+    fn get_range(
+        &self,
+        start: Self::LogSize,
+        end: Self::LogSize,
+    ) -> anyhow::Result<Vec<MaybeOwned<'_, T>>> {
+        self.log.get_range(start, end)
+    }
+
+    fn iter(&self) -> LogIterator<'_, Self::LogSize, T> {
+        self.log.iter()
+    }
+
+    fn index_of(&self, hash: &str) -> anyhow::Result<Option<Self::LogSize>> {
+        self.log.index_of(hash)
+    }
+
+    fn indices_of(&self, hash: &str) -> anyhow::Result<Vec<Self::LogSize>> {
+        self.log.indices_of(hash)
+    }
+
+    fn proofs<I>(
+        &self,
+        positions: I,
+    ) -> anyhow::Result<HashMap<LogTreePosition<Self::LogSize>, String>>
+    where
+        I: Iterator<Item = LogTreePosition<Self::LogSize>>,
+    {
+        self.log.proofs(positions)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::*;
+    use serde::{Deserialize, Serialize};
+    use std::borrow::Cow;
+
+    /// A certificate hashed via its DER encoding only
+    #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+    struct Certificate {
+        der: Vec<u8>,
+        subject: String,
+    }
+
+    impl Leaf for Certificate {
+        fn leaf_bytes(&self) -> Cow<'_, [u8]> {
+            Cow::Borrowed(&self.der)
+        }
+    }
+
+    fn certificate(i: u8) -> Certificate {
+        Certificate {
+            der: vec![0x30, i, i],
+            subject: format!("cert{}", i),
+        }
+    }
+
+    #[test]
+    fn leaf_hash() {
+        assert_eq!(hash_bytes(b"text"), hash_leaf("text"));
+        assert_eq!(hash_bytes(b"text"), hash_leaf(&String::from("text")));
+        assert_eq!(hash_bytes(&[1, 2]), hash_leaf(&vec![1_u8, 2]));
+        assert_eq!(hash_bytes(&[0x30, 1, 1]), hash_leaf(&certificate(1)));
+    }
+
+    #[test]
+    fn leaf_memory() -> anyhow::Result<()> {
+        let mut ml = LeafLog::new(InMemoryLog::default());
+        let mut records = vec![ml.append(certificate(0))?];
+        records.extend(ml.bulk_append((1..13).map(certificate).collect())?);
+        for (i, record) in records.iter().enumerate() {
+            assert_eq!(hash_leaf(&certificate(i as u8)), record.hash);
+            assert_eq!(Some(i), ml.index_of(&record.hash)?);
+        }
+        assert_eq!(Some(&certificate(9)), ml.get(9)?.as_deref());
+
+        let tree = ml.latest()?;
+        let proofs = ml.proofs(proof_positions(9, tree.size).into_iter())?;
+        assert!(verify(&tree, &records[9], &proofs));
+        let mut client = InMemoryLogClientBuilder::new(&ml)?.build();
+        assert!(check_record(&mut client, &ml, &records[9])?);
+        assert_eq!(Some(certificate(9).der), ml.get_leaf_bytes(9)?);
+        assert_eq!(
+            Some(certificate(9).der),
+            get_verified_bytes(&mut client, &ml, 9)?
+        );
+        assert_eq!(
+            Some(rmp_serde::to_vec(&certificate(9))?),
+            ml.get_raw_bytes(9)?
+        );
+
+        let mut exported = vec![];
+        assert_eq!(tree, ml.export(tree.size, &mut exported)?);
+
+        // the wrapped log hashes the serialized records
+        let mut plain = InMemoryLog::default();
+        let record = plain.append(certificate(0))?;
+        assert_ne!(records[0].hash, record.hash);
+        Ok(())
+    }
+
+    #[test]
+    fn leaf_dedup() -> anyhow::Result<()> {
+        let mut ml = DedupLog::new(LeafLog::new(InMemoryLog::default()));
+        let record = ml.append(certificate(1))?;
+        // same DER, different serialization: the same leaf
+        let duplicate = Certificate {
+            subject: "other".into(),
+            ..certificate(1)
+        };
+        assert_eq!(record, ml.append(duplicate)?);
+        assert_eq!(1, ml.size()?);
+        Ok(())
+    }
+}
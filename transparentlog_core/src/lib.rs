@@ -7,7 +7,7 @@
 //! # Examples
 //!
 //! ```
-//! use transparentlog_core::{check_record,InMemoryLog,InMemoryLogClientBuilder,TransparentLog};
+//! use transparentlog_core::{check_record,get_verified,InMemoryLog,InMemoryLogClientBuilder,TransparentLog};
 //!
 //! # fn main() -> anyhow::Result<()> {
 //! // Create a new log
@@ -18,8 +18,8 @@
 //! let rec1 = ml.append(String::from("entry1"))?;
 //! // Check the log contains the record
 //! assert_eq!(true, check_record(&mut client,&ml,&rec1)?);
-//! // Get back the data
-//! assert_eq!("entry1",ml.get(rec1.id)?.unwrap().as_str());
+//! // Get back the data, checking it is in the log
+//! assert_eq!("entry1",get_verified(&mut client,&ml,rec1.id)?.unwrap().as_str());
 //! # Ok(())
 //! # }
 //! ```
@@ -33,4 +33,176 @@ pub use memory::*;
 mod file;
 pub use file::*;
 
+mod codec;
+pub use codec::*;
+
+mod raw;
+pub use raw::*;
+
+mod leaf;
+pub use leaf::*;
+
+#[cfg(feature = "json")]
+mod event;
+#[cfg(feature = "json")]
+pub use event::*;
+
+mod compression;
+pub use compression::*;
+
+#[cfg(feature = "encryption")]
+mod encryption;
+#[cfg(feature = "encryption")]
+pub use encryption::*;
+
+#[cfg(feature = "mmap")]
+mod mmap;
+
+mod segment;
+pub use segment::*;
+
+mod tiering;
+pub use tiering::*;
+
+mod blob;
+pub use blob::*;
+
+mod kv;
+pub use kv::*;
+
+mod monitor;
+pub use monitor::*;
+
+mod tile;
+pub use tile::*;
+
+#[cfg(feature = "checkpoint")]
+mod checkpoint;
+#[cfg(feature = "checkpoint")]
+pub use checkpoint::*;
+
+#[cfg(feature = "checkpoint")]
+mod rotation;
+#[cfg(feature = "checkpoint")]
+pub use rotation::*;
+
+#[cfg(feature = "checkpoint")]
+mod kms;
+#[cfg(feature = "checkpoint")]
+pub use kms::*;
+
+#[cfg(feature = "checkpoint")]
+mod witness;
+#[cfg(feature = "checkpoint")]
+pub use witness::*;
+
+#[cfg(feature = "checkpoint")]
+mod discovery;
+#[cfg(feature = "checkpoint")]
+pub use discovery::*;
+
+#[cfg(feature = "checkpoint")]
+mod promise;
+#[cfg(feature = "checkpoint")]
+pub use promise::*;
+
+#[cfg(feature = "checkpoint")]
+mod timestamp;
+#[cfg(feature = "checkpoint")]
+pub use timestamp::*;
+
+mod heads;
+pub use heads::*;
+
+#[cfg(feature = "checkpoint")]
+mod shard;
+#[cfg(feature = "checkpoint")]
+pub use shard::*;
+
+mod sequencer;
+pub use sequencer::*;
+
+mod shared;
+pub use shared::*;
+
+mod cache;
+pub use cache::*;
+
+mod dedup;
+pub use dedup::*;
+
+mod policy;
+pub use policy::*;
+
+mod limits;
+pub use limits::*;
+
+mod notify;
+pub use notify::*;
+
+#[cfg(feature = "checkpoint")]
+mod webhook;
+#[cfg(feature = "checkpoint")]
+pub use webhook::*;
+
+mod follow;
+pub use follow::*;
+
+mod map;
+pub use map::*;
+
+mod absence;
+pub use absence::*;
+
+mod mirror;
+pub use mirror::*;
+
+#[cfg(any(feature = "json", feature = "csv"))]
+mod import;
+#[cfg(any(feature = "json", feature = "csv"))]
+pub use import::*;
+
+mod ingest;
+pub use ingest::*;
+
+mod gossip;
+pub use gossip::*;
+
+mod multi;
+pub use multi::*;
+
+mod state;
+pub use state::*;
+
+#[cfg(feature = "checkpoint")]
+mod audit;
+#[cfg(feature = "checkpoint")]
+pub use audit::*;
+
+mod json;
+pub use json::*;
+
+#[cfg(feature = "checkpoint")]
+mod bundle;
+#[cfg(feature = "checkpoint")]
+pub use bundle::*;
+
+#[cfg(feature = "checkpoint")]
+mod dsse;
+#[cfg(feature = "checkpoint")]
+pub use dsse::*;
+
+#[cfg(feature = "checkpoint")]
+mod rekor;
+#[cfg(feature = "checkpoint")]
+pub use rekor::*;
+
+mod verify;
+pub use verify::*;
+
+pub mod instrument;
+
 pub mod test_helpers;
+
+#[cfg(any(test, feature = "fuzz"))]
+pub mod fuzz;
@@ -0,0 +1,358 @@
+//! # Append limits
+//!
+//! Safeguards for logs open to public submissions: an `AppendLimits` log wraps any backend and refuses
+//! records larger than a maximum size, appends beyond a rate, and entries beyond a maximum log size,
+//! with a `LimitError` saying which limit was exceeded. No entry of a refused batch is appended.
+use crate::base::*;
+use crate::codec::{Codec, MessagePack};
+use maybe_owned::MaybeOwned;
+use num::ToPrimitive;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::Instant;
+use thiserror::Error;
+
+/// Specific Errors for limited logs
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum LimitError {
+    #[error("Record of {size} bytes exceeds the limit of {limit} bytes")]
+    RecordTooLarge { size: usize, limit: usize },
+    #[error("Appends exceed the limit of {0} per second")]
+    RateExceeded(u32),
+    #[error("Log is full: it cannot hold more than {0} entries")]
+    LogFull(u64),
+}
+
+/// The appends allowed by a rate: a bucket of tokens refilled continuously, holding at most one second of appends
+#[derive(Debug)]
+struct RateLimit {
+    rate: u32,
+    tokens: f64,
+    last: Instant,
+}
+
+impl RateLimit {
+    fn new(rate: u32) -> Self {
+        Self {
+            rate,
+            tokens: rate.into(),
+            last: Instant::now(),
+        }
+    }
+
+    /// Take the tokens for a number of appends, if there are enough of them
+    fn take(&mut self, count: usize) -> Result<(), LimitError> {
+        let now = Instant::now();
+        let rate = f64::from(self.rate);
+        self.tokens = (self.tokens + now.duration_since(self.last).as_secs_f64() * rate).min(rate);
+        self.last = now;
+        if self.tokens < count as f64 {
+            return Err(LimitError::RateExceeded(self.rate));
+        }
+        self.tokens -= count as f64;
+        Ok(())
+    }
+}
+
+/// A log limiting the records appended to it, without limits until they are set
+/// Records are measured serialized as MessagePack
+#[derive(Debug)]
+pub struct AppendLimits<L> {
+    log: L,
+    max_record_size: Option<usize>,
+    rate: Option<RateLimit>,
+    max_size: Option<u64>,
+}
+
+impl<L> AppendLimits<L> {
+    /// Wrap a log, without limits
+    pub fn new(log: L) -> Self {
+        Self {
+            log,
+            max_record_size: None,
+            rate: None,
+            max_size: None,
+        }
+    }
+
+    /// Set the maximum size of a serialized record, in bytes
+    pub fn max_record_size(&mut self, max_record_size: usize) -> &mut Self {
+        self.max_record_size = Some(max_record_size);
+        self
+    }
+
+    /// Set the maximum number of entries appended per second, allowing bursts of that many
+    pub fn max_appends_per_second(&mut self, rate: u32) -> &mut Self {
+        self.rate = Some(RateLimit::new(rate));
+        self
+    }
+
+    /// Set the maximum number of entries of the log
+    pub fn max_size(&mut self, max_size: u64) -> &mut Self {
+        self.max_size = Some(max_size);
+        self
+    }
+
+    /// The wrapped log
+    pub fn inner(&self) -> &L {
+        &self.log
+    }
+
+    /// Get back the wrapped log
+    pub fn into_inner(self) -> L {
+        self.log
+    }
+
+    /// Check the size of a record
+    fn check_record<T: Serialize>(&self, record: &T) -> anyhow::Result<()> {
+        if let Some(limit) = self.max_record_size {
+            let size = MessagePack::encode(record)?.len();
+            if size > limit {
+                return Err(LimitError::RecordTooLarge { size, limit }.into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Check the log can take the given number of entries, then count them against the rate
+    fn admit<S: ToPrimitive>(&mut self, size: S, count: usize) -> anyhow::Result<()> {
+        if let Some(limit) = self.max_size {
+            if size
+                .to_u64()
+                .unwrap_or(u64::MAX)
+                .saturating_add(count as u64)
+                > limit
+            {
+                return Err(LimitError::LogFull(limit).into());
+            }
+        }
+        if let Some(rate) = self.rate.as_mut() {
+            rate.take(count)?;
+        }
+        Ok(())
+    }
+}
+
+/// Entries are checked when added or appended, hashes appended without the entries cannot be
+impl<'a, T, L> TransparentLog<'a, T> for AppendLimits<L>
+where
+    T: Serialize + Deserialize<'a>,
+    L: TransparentLog<'a, T>,
+{
+    type LogSize = L::LogSize;
+
+    fn add(&mut self, record: T) -> anyhow::Result<Self::LogSize> {
+        self.check_record(&record)?;
+        self.admit(self.log.size()?, 1)?;
+        self.log.add(record)
+    }
+
+    fn add_tombstone(&mut self) -> anyhow::Result<Self::LogSize> {
+        self.admit(self.log.size()?, 1)?;
+        self.log.add_tombstone()
+    }
+
+    fn redact(&mut self, id: Self::LogSize) -> anyhow::Result<()> {
+        self.log.redact(id)
+    }
+
+    fn expire(&mut self, id: Self::LogSize) -> anyhow::Result<()> {
+        self.log.expire(id)
+    }
+
+    fn expired(&self, id: Self::LogSize) -> anyhow::Result<bool> {
+        self.log.expired(id)
+    }
+
+    fn seal(&mut self) -> anyhow::Result<LogTree<Self::LogSize>> {
+        self.log.seal()
+    }
+
+    fn sealed(&self) -> anyhow::Result<Option<LogTree<Self::LogSize>>> {
+        self.log.sealed()
+    }
+
+    fn sync(&self) -> anyhow::Result<()> {
+        self.log.sync()
+    }
+
+    fn add_meta(&mut self, id: Self::LogSize, meta: RecordMeta) -> anyhow::Result<()> {
+        self.log.add_meta(id, meta)
+    }
+
+    fn get_meta(&self, id: Self::LogSize) -> anyhow::Result<Option<RecordMeta>> {
+        self.log.get_meta(id)
+    }
+
+    fn set_annotation(&mut self, id: Self::LogSize, annotation: Vec<u8>) -> anyhow::Result<()> {
+        self.log.set_annotation(id, annotation)
+    }
+
+    fn get_annotation(&self, id: Self::LogSize) -> anyhow::Result<Option<Vec<u8>>> {
+        self.log.get_annotation(id)
+    }
+
+    fn find_since(&self, timestamp: u64) -> anyhow::Result<Self::LogSize> {
+        self.log.find_since(timestamp)
+    }
+
+    fn add_hash(&mut self, level: LogHeight, hash: String) -> anyhow::Result<Self::LogSize> {
+        self.log.add_hash(level, hash)
+    }
+
+    fn get_hash(
+        &self,
+        level: LogHeight,
+        index: Self::LogSize,
+    ) -> anyhow::Result<MaybeOwned<'_, String>> {
+        self.log.get_hash(level, index)
+    }
+
+    fn append(&mut self, record: T) -> anyhow::Result<Record<Self::LogSize>> {
+        self.check_record(&record)?;
+        self.admit(self.log.size()?, 1)?;
+        self.log.append(record)
+    }
+
+    fn append_hash_only(&mut self, hash: String) -> anyhow::Result<Record<Self::LogSize>> {
+        self.admit(self.log.size()?, 1)?;
+        self.log.append_hash_only(hash)
+    }
+
+    fn bulk_append(&mut self, records: Vec<T>) -> anyhow::Result<Vec<Record<Self::LogSize>>>
+    where
+        T: Sync,
+    {
+        for record in records.iter() {
+            self.check_record(record)?;
+        }
+        self.admit(self.log.size()?, records.len())?;
+        self.log.bulk_append(records)
+    }
+
+    fn size(&self) -> anyhow::Result<Self::LogSize> {
+        self.log.size()
+    }
+
+    fn origin(&self) -> Option<String> {
+        self.log.origin()
+    }
+
+    fn latest(&self) -> anyhow::Result<LogTree<Self::LogSize>> {
+        self.log.latest()
+    }
+
+    fn leaf_hasher(&self) -> LeafHasher<T> {
+        self.log.leaf_hasher()
+    }
+
+    fn get(&self, index: Self::LogSize) -> anyhow::Result<Option<MaybeOwned<'_, T>>> {
+        self.log.get(index)
+    }
+
+    fn get_raw_bytes(&self, index: Self::LogSize) -> anyhow::Result<Option<Vec<u8>>> {
+        self.log.get_raw_bytes(index)
+    }
+
+    fn get_leaf_bytes(&self, index: Self::LogSize) -> anyhow::Result<Option<Vec<u8>>> {
+        self.log.get_leaf_bytes(index)
+    }
+
+    fn get_range(
+        &self,
+        start: Self::LogSize,
+        end: Self::LogSize,
+    ) -> anyhow::Result<Vec<MaybeOwned<'_, T>>> {
+        self.log.get_range(start, end)
+    }
+
+    fn iter(&self) -> LogIterator<'_, Self::LogSize, T> {
+        self.log.iter()
+    }
+
+    fn index_of(&self, hash: &str) -> anyhow::Result<Option<Self::LogSize>> {
+        self.log.index_of(hash)
+    }
+
+    fn indices_of(&self, hash: &str) -> anyhow::Result<Vec<Self::LogSize>> {
+        self.log.indices_of(hash)
+    }
+
+    fn proofs<I>(
+        &self,
+        positions: I,
+    ) -> anyhow::Result<HashMap<LogTreePosition<Self::LogSize>, String>>
+    where
+        I: Iterator<Item = LogTreePosition<Self::LogSize>>,
+    {
+        self.log.proofs(positions)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::test_helpers::*;
+    use crate::*;
+    use std::thread::sleep;
+    use std::time::Duration;
+
+    fn limit_error<R: std::fmt::Debug>(r: anyhow::Result<R>) -> LimitError {
+        r.unwrap_err().downcast::<LimitError>().unwrap()
+    }
+
+    #[test]
+    fn limits_sizes() -> anyhow::Result<()> {
+        let mut ml = AppendLimits::new(InMemoryLog::default());
+        ml.max_record_size(8).max_size(5);
+        append_multiple(&mut ml, 3)?;
+        let long = LogRecord::new("rec_long");
+        assert_eq!(
+            LimitError::RecordTooLarge {
+                size: MessagePack::encode(&long)?.len(),
+                limit: 8
+            },
+            limit_error(ml.append(long))
+        );
+        assert_eq!(
+            LimitError::LogFull(5),
+            limit_error(ml.bulk_append(vec![
+                LogRecord::new("rec3"),
+                LogRecord::new("rec4"),
+                LogRecord::new("rec5")
+            ]))
+        );
+        assert_eq!(3, ml.size()?);
+        ml.bulk_append(vec![LogRecord::new("rec3"), LogRecord::new("rec4")])?;
+        assert_eq!(
+            LimitError::LogFull(5),
+            limit_error(ml.append_hash_only(hash(&LogRecord::new("rec5"))?))
+        );
+        assert_eq!(5, ml.size()?);
+        Ok(())
+    }
+
+    #[test]
+    fn limits_rate() -> anyhow::Result<()> {
+        let mut ml = AppendLimits::new(InMemoryLog::default());
+        ml.max_appends_per_second(10);
+        append_multiple(&mut ml, 8)?;
+        assert_eq!(
+            LimitError::RateExceeded(10),
+            limit_error(ml.bulk_append(vec![
+                LogRecord::new("rec8"),
+                LogRecord::new("rec9"),
+                LogRecord::new("rec10")
+            ]))
+        );
+        ml.bulk_append(vec![LogRecord::new("rec8"), LogRecord::new("rec9")])?;
+        assert_eq!(
+            LimitError::RateExceeded(10),
+            limit_error(ml.append(LogRecord::new("rec10")))
+        );
+        sleep(Duration::from_millis(150));
+        ml.append(LogRecord::new("rec10"))?;
+        assert_eq!(11, ml.size()?);
+        Ok(())
+    }
+}
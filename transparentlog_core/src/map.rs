@@ -0,0 +1,345 @@
+//! A verifiable map: a sparse Merkle tree from keys to their latest value, with every mutation appended to a log
+use crate::base::*;
+use crate::codec::{Codec, MessagePack};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Display;
+use std::hash::Hash;
+use std::marker::PhantomData;
+use thiserror::Error;
+use transparentlog_verify::sha256_hex;
+
+/// The depth of the sparse Merkle tree: one level per bit of a SHA-256 key hash
+pub const MAP_DEPTH: usize = 256;
+
+/// The path of a key in the tree: the SHA-256 of the key
+type KeyPath = [u8; MAP_DEPTH / 8];
+
+/// Specific Errors for verifiable maps
+#[derive(Debug, Clone, Error)]
+pub enum MapError {
+    #[error("Map root mismatch in log entry {0}")]
+    RootMismatch(String),
+}
+
+/// A log entry recording a map mutation: the new value of the key, or None if removed, and the resulting map root
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct MapEntry<K, V> {
+    pub key: K,
+    pub value: Option<V>,
+    pub root: String,
+}
+
+/// The proof of the value of a key in the map: the hashes of the siblings on the key path that are not empty, by depth
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+pub struct MapProof {
+    pub siblings: BTreeMap<usize, String>,
+}
+
+/// The hashes of empty subtrees, by depth from the root
+fn empty_hashes() -> Vec<String> {
+    let mut hashes = vec![sha256_hex(&[])];
+    for _ in 0..MAP_DEPTH {
+        let h = hashes.last().unwrap();
+        hashes.push(hash_pair(h, h));
+    }
+    hashes.reverse();
+    hashes
+}
+
+/// The path of a key
+fn key_path<K: Serialize>(key: &K) -> anyhow::Result<KeyPath> {
+    Ok(Sha256::digest(&MessagePack::encode(key)?).into())
+}
+
+/// The bit of the path at the given depth
+fn bit(path: &KeyPath, depth: usize) -> bool {
+    (path[depth / 8] >> (7 - depth % 8)) & 1 == 1
+}
+
+/// The path with the bit at the given depth set to the given value, and all the following bits cleared
+fn prefix(path: &KeyPath, depth: usize, value: bool) -> KeyPath {
+    let mut p = *path;
+    for d in depth..MAP_DEPTH {
+        p[d / 8] &= !(1 << (7 - d % 8));
+    }
+    if value && depth < MAP_DEPTH {
+        p[depth / 8] |= 1 << (7 - depth % 8);
+    }
+    p
+}
+
+/// The hash of a leaf: its key and value hashes
+fn leaf_hash<K: Serialize, V: Serialize>(key: &K, value: &V) -> anyhow::Result<String> {
+    Ok(hash_pair(&hash(key)?, &hash(value)?))
+}
+
+/// Verify that the key maps to the given value in the map with the given root, or is absent if the value is None
+pub fn verify_map_proof<K: Serialize, V: Serialize>(
+    root: &str,
+    key: &K,
+    value: Option<&V>,
+    proof: &MapProof,
+) -> anyhow::Result<bool> {
+    let empty = empty_hashes();
+    let path = key_path(key)?;
+    let mut h = match value {
+        Some(v) => leaf_hash(key, v)?,
+        None => empty[MAP_DEPTH].clone(),
+    };
+    for depth in (1..=MAP_DEPTH).rev() {
+        let sibling = proof.siblings.get(&depth).unwrap_or(&empty[depth]);
+        h = if bit(&path, depth - 1) {
+            hash_pair(sibling, &h)
+        } else {
+            hash_pair(&h, sibling)
+        };
+    }
+    Ok(hash_eq(&h, root))
+}
+
+/// A verifiable map, keeping the latest values and the non empty tree nodes in memory
+pub struct VerifiableMap<'a, K, V, TL>
+where
+    K: Serialize + DeserializeOwned + Clone + Eq + Hash,
+    V: Serialize + DeserializeOwned + Clone,
+    TL: TransparentLog<'a, MapEntry<K, V>>,
+{
+    log: TL,
+    values: HashMap<K, V>,
+    // Non empty nodes by depth and path prefix
+    nodes: HashMap<(usize, KeyPath), String>,
+    empty: Vec<String>,
+    _marker: PhantomData<&'a (K, V)>,
+}
+
+impl<'a, K, V, TL> VerifiableMap<'a, K, V, TL>
+where
+    K: Serialize + DeserializeOwned + Clone + Eq + Hash,
+    V: Serialize + DeserializeOwned + Clone,
+    TL: TransparentLog<'a, MapEntry<K, V>>,
+    TL::LogSize: Display,
+{
+    /// Open a map from its log, replaying all mutations and checking the roots they record
+    pub fn open(log: TL) -> anyhow::Result<Self> {
+        let mut map = Self {
+            log,
+            values: HashMap::new(),
+            nodes: HashMap::new(),
+            empty: empty_hashes(),
+            _marker: PhantomData,
+        };
+        let entries = map
+            .log
+            .iter()
+            .map(|r| r.map(|(id, e)| (id, e.into_owned())))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        for (id, entry) in entries {
+            map.apply(&entry.key, entry.value)?;
+            if map.root() != entry.root {
+                return Err(MapError::RootMismatch(id.to_string()).into());
+            }
+        }
+        Ok(map)
+    }
+
+    /// The root hash of the map
+    pub fn root(&self) -> String {
+        self.node(0, &[0_u8; MAP_DEPTH / 8]).to_owned()
+    }
+
+    /// The current value of a key
+    pub fn get(&self, key: &K) -> Option<&V> {
+        self.values.get(key)
+    }
+
+    /// Set the value of a key, returning the log record of the mutation
+    pub fn insert(&mut self, key: K, value: V) -> anyhow::Result<Record<TL::LogSize>> {
+        self.mutate(key, Some(value))
+    }
+
+    /// Remove a key, returning the log record of the mutation if the key was present
+    pub fn remove(&mut self, key: K) -> anyhow::Result<Option<Record<TL::LogSize>>> {
+        if self.values.contains_key(&key) {
+            Ok(Some(self.mutate(key, None)?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Prove the current value of a key, or that it is absent
+    pub fn prove(&self, key: &K) -> anyhow::Result<MapProof> {
+        let path = key_path(key)?;
+        let siblings = (1..=MAP_DEPTH)
+            .filter_map(|depth| {
+                let sibling = prefix(&path, depth - 1, !bit(&path, depth - 1));
+                self.nodes
+                    .get(&(depth, sibling))
+                    .map(|h| (depth, h.clone()))
+            })
+            .collect();
+        Ok(MapProof { siblings })
+    }
+
+    /// The underlying log
+    pub fn log(&self) -> &TL {
+        &self.log
+    }
+
+    /// Apply a mutation and append it to the log with the new root
+    fn mutate(&mut self, key: K, value: Option<V>) -> anyhow::Result<Record<TL::LogSize>> {
+        self.apply(&key, value.clone())?;
+        let root = self.root();
+        self.log.append(MapEntry { key, value, root })
+    }
+
+    /// Apply a mutation to the tree, without logging it
+    fn apply(&mut self, key: &K, value: Option<V>) -> anyhow::Result<()> {
+        let path = key_path(key)?;
+        let leaf = match &value {
+            Some(v) => leaf_hash(key, v)?,
+            None => self.empty[MAP_DEPTH].clone(),
+        };
+        match value {
+            Some(v) => self.values.insert(key.clone(), v),
+            None => self.values.remove(key),
+        };
+        self.set_node(MAP_DEPTH, path, leaf);
+        for depth in (0..MAP_DEPTH).rev() {
+            let left = self.node(depth + 1, &prefix(&path, depth, false));
+            let right = self.node(depth + 1, &prefix(&path, depth, true));
+            let h = hash_pair(left, right);
+            self.set_node(depth, prefix(&path, depth, false), h);
+        }
+        Ok(())
+    }
+
+    /// The hash of a node, empty if not stored
+    fn node(&self, depth: usize, path: &KeyPath) -> &str {
+        self.nodes
+            .get(&(depth, *path))
+            .unwrap_or(&self.empty[depth])
+    }
+
+    /// Store the hash of a node, forgetting it if it is the empty hash
+    fn set_node(&mut self, depth: usize, path: KeyPath, hash: String) {
+        if hash == self.empty[depth] {
+            self.nodes.remove(&(depth, path));
+        } else {
+            self.nodes.insert((depth, path), hash);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::*;
+
+    type TestMap<'a> = VerifiableMap<'a, String, u32, InMemoryLog<MapEntry<String, u32>>>;
+
+    #[test]
+    fn map_proofs() -> anyhow::Result<()> {
+        let mut map = TestMap::open(InMemoryLog::default())?;
+        let empty_root = map.root();
+        let alice = String::from("alice");
+        let bob = String::from("bob");
+        let carol = String::from("carol");
+        assert!(verify_map_proof::<_, u32>(
+            &empty_root,
+            &alice,
+            None,
+            &map.prove(&alice)?
+        )?);
+
+        map.insert(alice.clone(), 1)?;
+        map.insert(bob.clone(), 2)?;
+        let root = map.root();
+        assert_eq!(Some(&1), map.get(&alice));
+        assert!(verify_map_proof(
+            &root,
+            &alice,
+            Some(&1),
+            &map.prove(&alice)?
+        )?);
+        assert!(!verify_map_proof(
+            &root,
+            &alice,
+            Some(&2),
+            &map.prove(&alice)?
+        )?);
+        assert!(!verify_map_proof::<_, u32>(
+            &root,
+            &alice,
+            None,
+            &map.prove(&alice)?
+        )?);
+        assert!(verify_map_proof::<_, u32>(
+            &root,
+            &carol,
+            None,
+            &map.prove(&carol)?
+        )?);
+
+        map.insert(alice.clone(), 3)?;
+        let root = map.root();
+        assert!(verify_map_proof(
+            &root,
+            &alice,
+            Some(&3),
+            &map.prove(&alice)?
+        )?);
+        assert!(!verify_map_proof(
+            &root,
+            &alice,
+            Some(&1),
+            &map.prove(&alice)?
+        )?);
+
+        assert!(map.remove(carol.clone())?.is_none());
+        let record = map.remove(bob.clone())?.expect("bob was present");
+        assert_eq!(3, record.id);
+        assert!(verify_map_proof::<_, u32>(
+            &map.root(),
+            &bob,
+            None,
+            &map.prove(&bob)?
+        )?);
+        map.remove(alice)?;
+        assert_eq!(empty_root, map.root());
+        Ok(())
+    }
+
+    #[test]
+    fn map_log() -> anyhow::Result<()> {
+        let mut map = TestMap::open(InMemoryLog::default())?;
+        for i in 0..10 {
+            map.insert(format!("key{}", i % 4), i)?;
+        }
+        map.remove(String::from("key1"))?;
+        let root = map.root();
+        let latest = map.log().latest()?;
+        assert_eq!(11, latest.size);
+        let entry = map.log().get(10)?.unwrap().into_owned();
+        assert_eq!(root, entry.root);
+
+        let mut log = InMemoryLog::default();
+        for i in 0..11 {
+            log.append(map.log().get(i)?.unwrap().into_owned())?;
+        }
+        let reopened = TestMap::open(log)?;
+        assert_eq!(root, reopened.root());
+        assert_eq!(None, reopened.get(&String::from("key1")));
+        assert_eq!(Some(&8), reopened.get(&String::from("key0")));
+
+        let mut log = InMemoryLog::default();
+        log.append(MapEntry {
+            key: String::from("key0"),
+            value: Some(0_u32),
+            root: root.clone(),
+        })?;
+        assert!(TestMap::open(log).is_err());
+        Ok(())
+    }
+}
@@ -1,18 +1,39 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 use maybe_owned::MaybeOwned;
+use num::{NumCast, ToPrimitive};
 use serde::{Deserialize, Serialize};
 use std::fmt::Debug;
+use std::hash::Hash;
 
 pub use crate::base::*;
+use crate::cache::RootCache;
+use crate::codec::{Codec, MessagePack};
+use crate::instrument::{proofs_span, record_append, record_proofs};
+use crate::json::ProofHash;
+use crate::state::ClientState;
 
 /// An in-memory transparent log
 #[derive(Debug)]
 pub struct InMemoryLog<T> {
-    // Records are stored in a Vec
-    data: Vec<T>,
+    // Records are stored in a Vec, None being a tombstone
+    data: Vec<Option<T>>,
     // Hashes by level in the tree
     hashes: Vec<Vec<String>>,
+    // Indices of each record hash
+    lookup: HashIndex<usize>,
+    // Metadata of each record
+    metas: HashMap<usize, RecordMeta>,
+    // Annotations of the records, outside the tree
+    annotations: HashMap<usize, Vec<u8>>,
+    // Indices of the expired records
+    expired: HashSet<usize>,
+    // Origin identifying the log
+    origin: Option<String>,
+    // Final tree head, once sealed
+    sealed: Option<LogTree<usize>>,
+    // Latest tree head
+    root: RootCache<usize>,
 }
 
 /// Default implementation for an empty in-memory log
@@ -21,10 +42,36 @@ impl<T> Default for InMemoryLog<T> {
         Self {
             data: Default::default(),
             hashes: Default::default(),
+            lookup: Default::default(),
+            metas: Default::default(),
+            annotations: Default::default(),
+            expired: Default::default(),
+            origin: None,
+            sealed: None,
+            root: RootCache::default(),
         }
     }
 }
 
+impl<T> InMemoryLog<T> {
+    /// An empty log identified by the given origin
+    pub fn with_origin(origin: &str) -> Self {
+        Self {
+            origin: Some(origin.to_owned()),
+            ..Default::default()
+        }
+    }
+
+    /// Borrow the record of the entry at the given index, without the `MaybeOwned` wrapper
+    /// Fails with a `TombstoneError::NoRecord` error on a tombstone
+    pub fn get_ref(&self, index: usize) -> anyhow::Result<Option<&T>> {
+        self.data
+            .get(index)
+            .map(|t| Ok(t.as_ref().ok_or(TombstoneError::NoRecord)?))
+            .transpose()
+    }
+}
+
 /// TransparentLog Trait implementation for in-memory log
 impl<'a, T: Serialize + Deserialize<'a>> TransparentLog<'a, T> for InMemoryLog<T> {
     /// Vec size
@@ -34,33 +81,148 @@ impl<'a, T: Serialize + Deserialize<'a>> TransparentLog<'a, T> for InMemoryLog<T
         Ok(self.data.len())
     }
 
-    fn get(&self, index: Self::LogSize) -> anyhow::Result<Option<MaybeOwned<T>>> {
-        Ok(self.data.get(index).map(|t| t.into()))
+    fn origin(&self) -> Option<String> {
+        self.origin.clone()
+    }
+
+    fn latest(&self) -> anyhow::Result<LogTree<Self::LogSize>> {
+        let size = self.data.len();
+        self.root.get_or_compute(size, || self.head_at(size))
+    }
+
+    fn get(&self, index: Self::LogSize) -> anyhow::Result<Option<MaybeOwned<'_, T>>> {
+        match self.data.get(index) {
+            Some(None) => Err(tombstone_error(self, index)),
+            t => t.map(entry).transpose(),
+        }
+    }
+
+    /// The records are kept deserialized, so they are serialized as MessagePack, the format they are hashed as
+    fn get_raw_bytes(&self, index: Self::LogSize) -> anyhow::Result<Option<Vec<u8>>> {
+        self.get(index)?
+            .map(|t| MessagePack::encode(t.as_ref()))
+            .transpose()
+    }
+
+    fn get_range(
+        &self,
+        start: Self::LogSize,
+        end: Self::LogSize,
+    ) -> anyhow::Result<Vec<MaybeOwned<'_, T>>> {
+        let end = end.min(self.data.len());
+        let start = start.min(end);
+        self.data[start..end].iter().map(entry).collect()
+    }
+
+    fn iter(&self) -> LogIterator<'_, Self::LogSize, T> {
+        Box::new(
+            self.data
+                .iter()
+                .enumerate()
+                .map(|(i, t)| Ok((i, entry(t)?))),
+        )
     }
 
     fn add(&mut self, record: T) -> anyhow::Result<Self::LogSize> {
+        check_unsealed(&self.sealed)?;
         let id = self.data.len();
-        self.data.push(record);
+        self.data.push(Some(record));
+        record_append("memory");
         Ok(id)
     }
 
+    fn add_tombstone(&mut self) -> anyhow::Result<Self::LogSize> {
+        check_unsealed(&self.sealed)?;
+        let id = self.data.len();
+        self.data.push(None);
+        record_append("memory");
+        Ok(id)
+    }
+
+    /// Drops the record from memory
+    fn redact(&mut self, id: Self::LogSize) -> anyhow::Result<()> {
+        check_unsealed(&self.sealed)?;
+        let record = self.data.get_mut(id).ok_or(LogError::InvalidIndex(0, id))?;
+        *record = None;
+        Ok(())
+    }
+
+    fn expire(&mut self, id: Self::LogSize) -> anyhow::Result<()> {
+        self.redact(id)?;
+        self.expired.insert(id);
+        Ok(())
+    }
+
+    fn expired(&self, id: Self::LogSize) -> anyhow::Result<bool> {
+        Ok(self.expired.contains(&id))
+    }
+
+    fn add_meta(&mut self, id: Self::LogSize, meta: RecordMeta) -> anyhow::Result<()> {
+        check_unsealed(&self.sealed)?;
+        self.metas.insert(id, meta);
+        Ok(())
+    }
+
+    fn get_meta(&self, id: Self::LogSize) -> anyhow::Result<Option<RecordMeta>> {
+        Ok(self.metas.get(&id).copied())
+    }
+
+    fn set_annotation(&mut self, id: Self::LogSize, annotation: Vec<u8>) -> anyhow::Result<()> {
+        check_unsealed(&self.sealed)?;
+        if id >= self.data.len() {
+            return Err(LogError::InvalidIndex(0, id).into());
+        }
+        self.annotations.insert(id, annotation);
+        Ok(())
+    }
+
+    fn get_annotation(&self, id: Self::LogSize) -> anyhow::Result<Option<Vec<u8>>> {
+        Ok(self.annotations.get(&id).cloned())
+    }
+
+    fn seal(&mut self) -> anyhow::Result<LogTree<Self::LogSize>> {
+        if let Some(tree) = &self.sealed {
+            return Ok(tree.clone());
+        }
+        let tree = self.latest()?;
+        self.sealed = Some(tree.clone());
+        Ok(tree)
+    }
+
+    fn sealed(&self) -> anyhow::Result<Option<LogTree<Self::LogSize>>> {
+        Ok(self.sealed.clone())
+    }
+
     fn add_hash(&mut self, level: LogHeight, hash: String) -> anyhow::Result<Self::LogSize> {
-        if self.hashes.len() <= level {
+        check_unsealed(&self.sealed)?;
+        self.root.clear();
+        if self.hashes.len() == level {
             self.hashes.push(vec![]);
         }
         let v = self
             .hashes
             .get_mut(level)
             .ok_or(LogError::InvalidHeight::<Self::LogSize>(level))?;
+        if level == 0 {
+            self.lookup.insert(hash.clone(), v.len());
+        }
         v.push(hash);
         Ok(v.len() - 1)
     }
 
+    fn index_of(&self, hash: &str) -> anyhow::Result<Option<Self::LogSize>> {
+        Ok(self.lookup.first(hash))
+    }
+
+    fn indices_of(&self, hash: &str) -> anyhow::Result<Vec<Self::LogSize>> {
+        Ok(self.lookup.all(hash).to_vec())
+    }
+
     fn get_hash(
         &self,
         level: LogHeight,
         index: Self::LogSize,
-    ) -> anyhow::Result<MaybeOwned<String>> {
+    ) -> anyhow::Result<MaybeOwned<'_, String>> {
         Ok(self
             .hashes
             .get(level)
@@ -69,27 +231,56 @@ impl<'a, T: Serialize + Deserialize<'a>> TransparentLog<'a, T> for InMemoryLog<T
             .ok_or(LogError::InvalidIndex(level, index))?
             .into())
     }
+
+    fn proofs<I>(
+        &self,
+        positions: I,
+    ) -> anyhow::Result<HashMap<LogTreePosition<Self::LogSize>, String>>
+    where
+        I: Iterator<Item = LogTreePosition<Self::LogSize>>,
+    {
+        let positions: Vec<_> = positions.collect();
+        let _span = proofs_span("memory", positions.len());
+        let proofs: HashMap<_, _> = positions
+            .into_iter()
+            .map(|p| {
+                let hash = self.get_hash(p.level, p.index)?.into_owned();
+                Ok((p, hash))
+            })
+            .collect::<anyhow::Result<_>>()?;
+        record_proofs("memory", proofs.len());
+        Ok(proofs)
+    }
+}
+
+/// The record of an entry, failing on a tombstone
+fn entry<T>(t: &Option<T>) -> anyhow::Result<MaybeOwned<'_, T>> {
+    Ok(t.as_ref().ok_or(TombstoneError::NoRecord)?.into())
 }
 
-/// In-memory client to a TransparentLog, keeping track of the latest log verified
-pub struct InMemoryLogClient<'a, T: Serialize + Deserialize<'a>, TL: TransparentLog<'a, T>> {
-    latest: LogTree<TL::LogSize>,
+/// In-memory client to TransparentLogs, keeping track of the latest log verified
+/// The client only depends on the size type, so one client type works with all the backends using that size type
+#[derive(Debug, Clone)]
+pub struct InMemoryLogClient<LogSize> {
+    latest: LogTree<LogSize>,
 
-    cache: Option<HashMap<LogTreePosition<TL::LogSize>, String>>,
+    cache: Option<HashMap<LogTreePosition<LogSize>, String>>,
 }
 
 /// Build an in-memory client, from the current state of the log or a saved state
-pub struct InMemoryLogClientBuilder<'a, T: Serialize + Deserialize<'a>, TL: TransparentLog<'a, T>> {
-    latest: LogTree<TL::LogSize>,
+pub struct InMemoryLogClientBuilder<LogSize> {
+    latest: LogTree<LogSize>,
     cache: bool,
 }
 
-impl<'a, T: Serialize + Deserialize<'a>, TL: TransparentLog<'a, T>>
-    InMemoryLogClientBuilder<'a, T, TL>
-{
+impl<LogSize: Clone> InMemoryLogClientBuilder<LogSize> {
     /// Create a new client on an existing log
-    pub fn new(log: &TL) -> anyhow::Result<Self> {
-        let latest = log.latest()?;
+    pub fn new<'a, T, K, TL>(log: &TL) -> anyhow::Result<Self>
+    where
+        T: Serialize + Deserialize<'a>,
+        TL: ProofSource<'a, T, K, LogSize = LogSize>,
+    {
+        let latest = log.fetch_latest()?;
         Ok(Self {
             latest,
             cache: true,
@@ -97,13 +288,19 @@ impl<'a, T: Serialize + Deserialize<'a>, TL: TransparentLog<'a, T>>
     }
 
     /// Create a new client from a tree record
-    pub fn open(latest: LogTree<TL::LogSize>) -> Self {
+    pub fn open(latest: LogTree<LogSize>) -> Self {
         Self {
             latest,
             cache: true,
         }
     }
 
+    /// Only accept proofs from the log with the given origin
+    pub fn origin(&mut self, origin: &str) -> &mut Self {
+        self.latest.origin = Some(origin.to_owned());
+        self
+    }
+
     /// Client caches positions by default, disable if needed
     pub fn no_cache(&mut self) -> &mut Self {
         self.cache = false;
@@ -111,12 +308,9 @@ impl<'a, T: Serialize + Deserialize<'a>, TL: TransparentLog<'a, T>>
     }
 
     /// Build a new client
-    pub fn build(&self) -> InMemoryLogClient<'a, T, TL> {
+    pub fn build(&self) -> InMemoryLogClient<LogSize> {
         InMemoryLogClient {
-            latest: LogTree {
-                size: self.latest.size,
-                hash: self.latest.hash.clone(),
-            },
+            latest: self.latest.clone(),
             cache: if self.cache {
                 Some(HashMap::new())
             } else {
@@ -126,8 +320,80 @@ impl<'a, T: Serialize + Deserialize<'a>, TL: TransparentLog<'a, T>>
     }
 }
 
-impl<'a, T: Serialize + Deserialize<'a>, TL: TransparentLog<'a, T>> LogClient<'a, T, TL>
-    for InMemoryLogClient<'a, T, TL>
+impl<LogSize: Eq + Hash + ToPrimitive> InMemoryLogClient<LogSize> {
+    /// Get the latest tree verified
+    pub fn latest(&self) -> &LogTree<LogSize> {
+        &self.latest
+    }
+
+    /// Set the latest tree information
+    pub fn set_latest(&mut self, latest: LogTree<LogSize>) {
+        self.latest = latest
+    }
+
+    /// The state of the client, to persist it with `ClientState::to_json`
+    /// Cached positions are sorted by level then index, so the same state always gives the same document
+    pub fn state(&self) -> ClientState<LogSize>
+    where
+        LogSize: Clone + Ord,
+    {
+        ClientState {
+            latest: self.latest.clone(),
+            cache: self.cache.as_ref().map(|m| {
+                let mut hashes: Vec<_> = m
+                    .iter()
+                    .map(|(p, h)| ProofHash {
+                        level: p.level,
+                        index: p.index.clone(),
+                        hash: h.clone(),
+                    })
+                    .collect();
+                hashes.sort();
+                hashes
+            }),
+        }
+    }
+
+    /// A client restored from a persisted state, caching positions if the state has a cache
+    pub fn from_state(state: ClientState<LogSize>) -> Self {
+        Self {
+            latest: state.latest,
+            cache: state.cache.map(|hashes| {
+                hashes
+                    .into_iter()
+                    .map(|p| {
+                        (
+                            LogTreePosition {
+                                level: p.level,
+                                index: p.index,
+                            },
+                            p.hash,
+                        )
+                    })
+                    .collect()
+            }),
+        }
+    }
+
+    /// Convert the client to another size type, keeping its latest tree and cache
+    /// to check the same log through a backend with another size type
+    pub fn convert<S: Eq + Hash + NumCast>(&self) -> Result<InMemoryLogClient<S>, SizeError> {
+        Ok(InMemoryLogClient {
+            latest: self.latest.convert()?,
+            cache: match &self.cache {
+                Some(m) => Some(
+                    m.iter()
+                        .map(|(p, h)| Ok((p.convert()?, h.clone())))
+                        .collect::<Result<_, SizeError>>()?,
+                ),
+                None => None,
+            },
+        })
+    }
+}
+
+impl<'a, T: Serialize + Deserialize<'a>, K, TL: ProofSource<'a, T, K>> LogClient<'a, T, TL, K>
+    for InMemoryLogClient<TL::LogSize>
 {
     fn latest(&self) -> &LogTree<TL::LogSize> {
         &self.latest
@@ -155,7 +421,13 @@ impl<'a, T: Serialize + Deserialize<'a>, TL: TransparentLog<'a, T>> LogClient<'a
 mod tests {
 
     use crate::test_helpers::*;
-    use crate::{InMemoryLog, InMemoryLogClientBuilder};
+    use crate::{
+        check_record, check_records, get_verified, hash, CheckDepth, InMemoryLog,
+        InMemoryLogClientBuilder, LeafHasher, LogTree, LogTreePosition, OriginError, ProofSource,
+        Record, Remote, SealError, TransparentLog,
+    };
+    use maybe_owned::MaybeOwned;
+    use std::collections::HashMap;
 
     #[test]
     fn memory_empty() -> anyhow::Result<()> {
@@ -163,18 +435,187 @@ mod tests {
         empty(&mut ml)
     }
 
+    #[test]
+    fn memory_out_of_range() -> anyhow::Result<()> {
+        let mut ml: InMemoryLog<LogRecord> = InMemoryLog::default();
+        out_of_range(&mut ml)
+    }
+
     #[test]
     fn memory_add() -> anyhow::Result<()> {
         let mut ml: InMemoryLog<LogRecord> = InMemoryLog::default();
         add(&mut ml)
     }
 
+    #[test]
+    fn memory_lookup() -> anyhow::Result<()> {
+        let mut ml: InMemoryLog<LogRecord> = InMemoryLog::default();
+        lookup(&mut ml)
+    }
+
+    #[test]
+    fn memory_range() -> anyhow::Result<()> {
+        let mut ml: InMemoryLog<LogRecord> = InMemoryLog::default();
+        range(&mut ml)
+    }
+
+    #[test]
+    fn memory_iter() -> anyhow::Result<()> {
+        let mut ml: InMemoryLog<LogRecord> = InMemoryLog::default();
+        iter(&mut ml)
+    }
+
+    #[test]
+    fn memory_tiles() -> anyhow::Result<()> {
+        let mut ml: InMemoryLog<LogRecord> = InMemoryLog::default();
+        tiles(&mut ml)
+    }
+
+    #[test]
+    fn memory_batch_proofs() -> anyhow::Result<()> {
+        let mut ml: InMemoryLog<LogRecord> = InMemoryLog::default();
+        batch_proofs(&mut ml)
+    }
+
+    #[test]
+    fn memory_range_proofs() -> anyhow::Result<()> {
+        let mut ml: InMemoryLog<LogRecord> = InMemoryLog::default();
+        range_proofs(&mut ml)
+    }
+
+    #[test]
+    fn memory_historic_roots() -> anyhow::Result<()> {
+        let mut ml: InMemoryLog<LogRecord> = InMemoryLog::default();
+        historic_roots(&mut ml)
+    }
+
+    #[test]
+    fn memory_consistency_between() -> anyhow::Result<()> {
+        let mut ml: InMemoryLog<LogRecord> = InMemoryLog::default();
+        consistency_between_sizes(&mut ml)
+    }
+
+    #[test]
+    fn memory_audit_paths() -> anyhow::Result<()> {
+        let mut ml: InMemoryLog<LogRecord> = InMemoryLog::default();
+        audit_paths(&mut ml)
+    }
+
+    #[test]
+    fn memory_prove_hashes() -> anyhow::Result<()> {
+        let mut ml: InMemoryLog<LogRecord> = InMemoryLog::default();
+        prove_hashes(&mut ml)
+    }
+
+    #[test]
+    fn memory_annotations() -> anyhow::Result<()> {
+        let mut ml: InMemoryLog<LogRecord> = InMemoryLog::default();
+        annotations(&mut ml)?;
+        ml.seal()?;
+        let err = ml.set_annotation(0, vec![1]).unwrap_err();
+        assert_eq!(Some(&SealError::Sealed), err.downcast_ref::<SealError>());
+        Ok(())
+    }
+
+    #[test]
+    fn memory_duplicates() -> anyhow::Result<()> {
+        let mut ml: InMemoryLog<LogRecord> = InMemoryLog::default();
+        duplicates(&mut ml)
+    }
+
+    #[test]
+    fn memory_bulk() -> anyhow::Result<()> {
+        let mut ml: InMemoryLog<LogRecord> = InMemoryLog::default();
+        bulk(&mut ml)
+    }
+
+    #[test]
+    fn memory_timestamps() -> anyhow::Result<()> {
+        let mut ml: InMemoryLog<LogRecord> = InMemoryLog::default();
+        timestamps(&mut ml)
+    }
+
+    #[test]
+    fn memory_export_import() -> anyhow::Result<()> {
+        let mut ml: InMemoryLog<LogRecord> = InMemoryLog::default();
+        let mut ml2: InMemoryLog<LogRecord> = InMemoryLog::default();
+        export_import(&mut ml, &mut ml2)
+    }
+
+    #[test]
+    fn memory_mirror() -> anyhow::Result<()> {
+        let mut ml: InMemoryLog<LogRecord> = InMemoryLog::default();
+        let mut ml2: InMemoryLog<LogRecord> = InMemoryLog::default();
+        mirror(&mut ml, &mut ml2)
+    }
+
+    #[test]
+    fn memory_properties() -> anyhow::Result<()> {
+        properties(|| Ok(InMemoryLog::<LogRecord>::default()))
+    }
+
+    #[test]
+    fn memory_hash_only() -> anyhow::Result<()> {
+        let mut ml: InMemoryLog<LogRecord> = InMemoryLog::default();
+        hash_only(&mut ml)
+    }
+
+    #[test]
+    fn memory_invalid_hash_only() -> anyhow::Result<()> {
+        let mut ml: InMemoryLog<LogRecord> = InMemoryLog::default();
+        invalid_hash_only(&mut ml)
+    }
+
+    #[test]
+    fn memory_self_check() -> anyhow::Result<()> {
+        let mut ml: InMemoryLog<LogRecord> = InMemoryLog::default();
+        self_check(&mut ml)?;
+        ml.hashes[1][2] = hash(&LogRecord::new("rot"))?;
+        let report = ml.self_check(CheckDepth::Full)?;
+        assert_eq!(
+            Some(LogTreePosition { level: 1, index: 2 }),
+            report.first_bad
+        );
+        assert_eq!(3, report.checked);
+        assert!(ml.self_check(CheckDepth::Sampled(1))?.passed());
+        Ok(())
+    }
+
+    #[test]
+    fn memory_expire() -> anyhow::Result<()> {
+        let mut ml: InMemoryLog<LogRecord> = InMemoryLog::default();
+        expire(&mut ml)
+    }
+
+    #[test]
+    fn memory_redact() -> anyhow::Result<()> {
+        let mut ml: InMemoryLog<LogRecord> = InMemoryLog::default();
+        redact(&mut ml)
+    }
+
+    #[test]
+    fn memory_seal() -> anyhow::Result<()> {
+        let mut ml = InMemoryLog::default();
+        seal(&mut ml)
+    }
+
     #[test]
     fn memory_13() -> anyhow::Result<()> {
         let mut ml: InMemoryLog<LogRecord> = InMemoryLog::default();
         test_13(&mut ml)
     }
 
+    #[test]
+    fn memory_raw_bytes() -> anyhow::Result<()> {
+        let mut ml: InMemoryLog<LogRecord> = InMemoryLog::default();
+        let mut client = InMemoryLogClientBuilder::new(&ml)?.build();
+        raw_bytes(&mut ml, &mut client)?;
+        assert_eq!(Some(&LogRecord::new("rec8")), ml.get_ref(8)?);
+        assert_tombstone(ml.get_ref(9));
+        assert!(ml.get_ref(13)?.is_none());
+        Ok(())
+    }
+
     #[test]
     fn client_memory_13() -> anyhow::Result<()> {
         let mut ml: InMemoryLog<LogRecord> = InMemoryLog::default();
@@ -189,6 +630,148 @@ mod tests {
         client_13_nocache(&mut ml, &mut client)
     }
 
+    #[test]
+    fn client_memory_origin() -> anyhow::Result<()> {
+        let mut ml: InMemoryLog<LogRecord> = InMemoryLog::with_origin("first");
+        let mut client = InMemoryLogClientBuilder::new(&ml)?.build();
+        client_13(&mut ml, &mut client)?;
+        assert_eq!(Some("first".to_owned()), client.latest().origin);
+        let record = ml.append(LogRecord::new("rec13"))?;
+
+        // a larger log with another origin, or none, is not an update
+        let mismatch = |found: Option<&str>| OriginError::Mismatch {
+            expected: Some("first".into()),
+            found: found.map(String::from),
+        };
+        let mut other: InMemoryLog<LogRecord> = InMemoryLog::with_origin("second");
+        append_multiple(&mut other, 20)?;
+        let err = check_record(&mut client, &other, &record).err().unwrap();
+        assert_eq!(Some(&mismatch(Some("second"))), err.downcast_ref());
+        let mut other: InMemoryLog<LogRecord> = InMemoryLog::default();
+        append_multiple(&mut other, 20)?;
+        let err = check_record(&mut client, &other, &record).err().unwrap();
+        assert_eq!(Some(&mismatch(None)), err.downcast_ref());
+        assert_eq!(13, client.latest().size);
+
+        assert!(check_record(&mut client, &ml, &record)?);
+        assert_eq!(14, client.latest().size);
+
+        // a client from a saved state without origin adopts the origin of the log
+        let mut client = InMemoryLogClientBuilder::open(LogTree {
+            size: 0,
+            hash: String::new(),
+            origin: None,
+        })
+        .build();
+        assert!(check_record(&mut client, &ml, &record)?);
+        assert_eq!(Some("first".to_owned()), client.latest().origin);
+
+        let mut client = InMemoryLogClientBuilder::new(&ml)?.origin("second").build();
+        let err = check_record(&mut client, &ml, &record).err().unwrap();
+        assert_eq!(
+            Some(&OriginError::Mismatch {
+                expected: Some("second".into()),
+                found: Some("first".into()),
+            }),
+            err.downcast_ref()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn client_memory_records() -> anyhow::Result<()> {
+        let mut ml: InMemoryLog<LogRecord> = InMemoryLog::default();
+        let mut client = InMemoryLogClientBuilder::new(&ml)?.build();
+        client_records(&mut ml, &mut client)
+    }
+
+    /// A read only view of a log, that cannot be written to, like a remote log
+    struct ReadOnly<'l>(&'l InMemoryLog<LogRecord>);
+
+    impl<'a, 'l> ProofSource<'a, LogRecord, Remote> for ReadOnly<'l> {
+        type LogSize = usize;
+
+        fn log_origin(&self) -> Option<String> {
+            self.0.origin()
+        }
+
+        fn fetch_latest(&self) -> anyhow::Result<LogTree<usize>> {
+            self.0.latest()
+        }
+
+        fn fetch_proofs<I>(
+            &self,
+            positions: I,
+        ) -> anyhow::Result<HashMap<LogTreePosition<usize>, String>>
+        where
+            I: Iterator<Item = LogTreePosition<usize>>,
+        {
+            self.0.proofs(positions)
+        }
+
+        fn fetch_entry(&self, index: usize) -> anyhow::Result<Option<MaybeOwned<'_, LogRecord>>> {
+            self.0.get(index)
+        }
+
+        fn entry_hasher(&self) -> LeafHasher<LogRecord> {
+            self.0.leaf_hasher()
+        }
+    }
+
+    /// The record at the given index of the source
+    fn record(source: &ReadOnly, id: usize) -> anyhow::Result<Record<usize>> {
+        Ok(Record {
+            id,
+            hash: hash(source.fetch_entry(id)?.unwrap().as_ref())?,
+            meta: None,
+        })
+    }
+
+    #[test]
+    fn client_memory_source() -> anyhow::Result<()> {
+        let mut ml: InMemoryLog<LogRecord> = InMemoryLog::with_origin("log");
+        append_multiple(&mut ml, 5)?;
+        let source = ReadOnly(&ml);
+        let mut client = InMemoryLogClientBuilder::new(&source)?.build();
+        assert_eq!(Some("log".to_owned()), client.latest().origin);
+        assert!(check_record(&mut client, &source, &record(&source, 3)?)?);
+        let forged = Record {
+            hash: hash(&LogRecord::new("forged"))?,
+            ..record(&source, 2)?
+        };
+        assert_eq!(
+            vec![true, false],
+            check_records(&mut client, &source, &[record(&source, 4)?, forged])?
+        );
+
+        append_multiple_offset(&mut ml, 5, 8)?;
+        let source = ReadOnly(&ml);
+        assert!(check_record(&mut client, &source, &record(&source, 12)?)?);
+        assert_eq!(13, client.latest().size);
+        Ok(())
+    }
+
+    #[test]
+    fn client_memory_get_verified() -> anyhow::Result<()> {
+        let mut ml: InMemoryLog<LogRecord> = InMemoryLog::default();
+        append_multiple(&mut ml, 13)?;
+        ml.append_hash_only(hash(&LogRecord::new("rec13"))?)?;
+        let mut client = InMemoryLogClientBuilder::new(&ml)?.build();
+        assert_eq!(
+            Some(&LogRecord::new("rec9")),
+            get_verified(&mut client, &ml, 9)?.as_deref()
+        );
+        assert!(get_verified(&mut client, &ml, 13).is_err());
+        assert!(get_verified(&mut client, &ml, 14)?.is_none());
+
+        // the same entries at other indices do not match the tree of the client
+        let mut other: InMemoryLog<LogRecord> = InMemoryLog::default();
+        other.append(LogRecord::new("rec"))?;
+        append_multiple(&mut other, 13)?;
+        assert!(get_verified(&mut client, &other, 9)?.is_none());
+        Ok(())
+    }
+
     #[test]
     fn memory_verify_tree_prefix() -> anyhow::Result<()> {
         let mut ml: InMemoryLog<LogRecord> = InMemoryLog::default();
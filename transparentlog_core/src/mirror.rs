@@ -0,0 +1,132 @@
+//! Mirroring: copy the entries of a source log into a replica, checking the replica stays a prefix of the source tree
+use crate::base::*;
+use num::Zero;
+use serde::{Deserialize, Serialize};
+use std::fmt::Display;
+use thiserror::Error;
+
+/// The default number of records a mirror copies at once
+const DEFAULT_BATCH_SIZE: u8 = 100;
+
+/// Specific Errors for mirrors
+#[derive(Debug, Clone, Error)]
+pub enum MirrorError {
+    #[error("Mirror is ahead of its source: {0} records")]
+    Ahead(String),
+    #[error("Mirror diverged from its source at size {0}")]
+    Diverged(String),
+}
+
+/// A mirror copying records from a source log into a destination log, in batches
+pub struct Mirror<LogSize> {
+    batch_size: LogSize,
+}
+
+impl<LogSize: num::Integer + Copy + From<u8>> Default for Mirror<LogSize> {
+    fn default() -> Self {
+        Self {
+            batch_size: DEFAULT_BATCH_SIZE.into(),
+        }
+    }
+}
+
+impl<LogSize: num::Integer + Copy + std::hash::Hash + Display + From<u8>> Mirror<LogSize> {
+    /// Set the number of records copied at once
+    pub fn batch_size(&mut self, batch_size: LogSize) -> &mut Self {
+        if batch_size > LogSize::zero() {
+            self.batch_size = batch_size;
+        }
+        self
+    }
+
+    /// Copy the records missing from the destination, up to the latest source tree, returning that tree
+    /// After each batch, the destination tree is checked to be a prefix of the source tree with a proof from the source
+    /// On error, the batches already copied stay in the destination
+    pub fn sync<'a, T, S, D>(&self, source: &S, dest: &mut D) -> anyhow::Result<LogTree<LogSize>>
+    where
+        T: Serialize + Deserialize<'a> + Clone,
+        S: TransparentLog<'a, T, LogSize = LogSize>,
+        D: TransparentLog<'a, T, LogSize = LogSize>,
+    {
+        let head = source.latest()?;
+        let mut tree = dest.latest()?;
+        if tree.size > head.size {
+            return Err(MirrorError::Ahead(tree.size.to_string()).into());
+        }
+        check_prefix(source, &tree, &head)?;
+        while tree.size < head.size {
+            let end = head.size.min(tree.size + self.batch_size);
+            let records = source.get_range(tree.size, end)?;
+            if records.is_empty() {
+                return Err(MirrorError::Diverged(tree.size.to_string()).into());
+            }
+            let mut id = tree.size;
+            for r in records {
+                let record = dest.append(r.into_owned())?;
+                if let Some(meta) = source.get_meta(id)? {
+                    dest.add_meta(record.id, meta)?;
+                }
+                id = id + LogSize::one();
+            }
+            tree = dest.latest()?;
+            check_prefix(source, &tree, &head)?;
+        }
+        Ok(head)
+    }
+}
+
+/// Check a destination tree is a prefix of the source tree, using proofs from the source
+fn check_prefix<'a, T, S>(
+    source: &S,
+    tree: &LogTree<S::LogSize>,
+    head: &LogTree<S::LogSize>,
+) -> anyhow::Result<()>
+where
+    T: Serialize + Deserialize<'a>,
+    S: TransparentLog<'a, T>,
+    S::LogSize: Display,
+{
+    let consistent = if tree.size.is_zero() {
+        true
+    } else if tree.size == head.size {
+        hash_eq(&tree.hash, &head.hash)
+    } else {
+        let proofs = source.proofs(prefix_proof_positions(tree.size, head.size).into_iter())?;
+        verify_tree(tree, &proofs) && verify_tree(head, &proofs)
+    };
+    if consistent {
+        Ok(())
+    } else {
+        Err(MirrorError::Diverged(tree.size.to_string()).into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::test_helpers::*;
+    use crate::*;
+
+    #[test]
+    fn mirror_diverged() -> anyhow::Result<()> {
+        let mut source: InMemoryLog<LogRecord> = InMemoryLog::default();
+        append_multiple(&mut source, 5)?;
+        let mirror = Mirror::default();
+
+        let mut dest = InMemoryLog::default();
+        dest.append(LogRecord::new("other"))?;
+        assert!(matches!(
+            mirror.sync(&source, &mut dest).unwrap_err().downcast(),
+            Ok(MirrorError::Diverged(s)) if s == "1"
+        ));
+        assert_eq!(1, dest.size()?);
+
+        let mut dest = InMemoryLog::default();
+        append_multiple(&mut dest, 6)?;
+        assert!(matches!(
+            mirror.sync(&source, &mut dest).unwrap_err().downcast(),
+            Ok(MirrorError::Ahead(s)) if s == "6"
+        ));
+        Ok(())
+    }
+}
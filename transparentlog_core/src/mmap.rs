@@ -0,0 +1,58 @@
+//! # Read-only memory maps
+//!
+//! A minimal read-only memory map of the start of a file, so `FileLog` can serve hashes as slice copies instead of
+//! a seek and read per hash. The log only appends to its hash files, so the mapped bytes never change; a map is
+//! replaced by a longer one when a read goes past its end.
+use std::fs::File;
+use std::ops::Deref;
+use std::os::unix::io::AsRawFd;
+use std::{io, ptr, slice};
+
+/// A read-only shared map of the first bytes of a file
+#[derive(Debug)]
+pub(crate) struct Mmap {
+    ptr: *mut libc::c_void,
+    len: usize,
+}
+
+// The map is read-only and only freed on drop, so it can move between threads
+unsafe impl Send for Mmap {}
+
+impl Mmap {
+    /// Map the first len bytes of the file, len must be positive and not beyond the end of the file
+    pub(crate) fn map(file: &File, len: usize) -> io::Result<Self> {
+        // SAFETY: we map a read-only shared view of an existing file and check the result
+        let ptr = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                len,
+                libc::PROT_READ,
+                libc::MAP_SHARED,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self { ptr, len })
+    }
+}
+
+impl Deref for Mmap {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        // SAFETY: the pointer is valid for len bytes until the map is dropped
+        unsafe { slice::from_raw_parts(self.ptr as *const u8, self.len) }
+    }
+}
+
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        // SAFETY: the pointer and length come from a successful mmap
+        unsafe {
+            libc::munmap(self.ptr, self.len);
+        }
+    }
+}
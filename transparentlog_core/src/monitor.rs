@@ -0,0 +1,276 @@
+use crate::base::*;
+use num::{One, Zero};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::fs::{rename, File};
+use std::io::prelude::*;
+use std::path::Path;
+
+/// The default number of records a monitor reads at once
+const DEFAULT_BATCH_SIZE: u8 = 100;
+
+/// The progress of a monitor: the number of records verified and the roots of the perfect subtrees covering them
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct MonitorState<LogSize> {
+    pub size: LogSize,
+    pub peaks: Vec<(LogHeight, String)>,
+}
+
+impl<LogSize: Zero> Default for MonitorState<LogSize> {
+    fn default() -> Self {
+        Self {
+            size: LogSize::zero(),
+            peaks: vec![],
+        }
+    }
+}
+
+impl<LogSize> MonitorState<LogSize> {
+    /// Add a leaf hash, merging the perfect subtrees of the same height
+    pub(crate) fn push(&mut self, hash: String) {
+        self.peaks.push((0, hash));
+        while self.peaks.len() > 1
+            && self.peaks[self.peaks.len() - 1].0 == self.peaks[self.peaks.len() - 2].0
+        {
+            let (_, h2) = self.peaks.pop().unwrap();
+            let (level, h1) = self.peaks.pop().unwrap();
+            self.peaks.push((level + 1, hash_pair(&h1, &h2)));
+        }
+    }
+
+    /// The root hash of the tree
+    pub fn root(&self) -> String {
+        let mut it = self.peaks.iter().rev();
+        let mut root = it.next().map(|(_, h)| h.clone()).unwrap_or_default();
+        for (_, h) in it {
+            root = hash_pair(h, &root);
+        }
+        root
+    }
+}
+
+/// A monitor replaying all the log entries to verify the published root
+pub struct Monitor<LogSize> {
+    state: MonitorState<LogSize>,
+    batch_size: LogSize,
+}
+
+impl<LogSize: num::Integer + Copy + From<u8>> Default for Monitor<LogSize> {
+    fn default() -> Self {
+        Self::open(MonitorState::default())
+    }
+}
+
+impl<LogSize: num::Integer + Copy + From<u8>> Monitor<LogSize> {
+    /// Create a monitor from a saved state
+    pub fn open(state: MonitorState<LogSize>) -> Self {
+        Self {
+            state,
+            batch_size: DEFAULT_BATCH_SIZE.into(),
+        }
+    }
+
+    /// Set the number of records read at once
+    pub fn batch_size(&mut self, batch_size: LogSize) -> &mut Self {
+        if batch_size > LogSize::zero() {
+            self.batch_size = batch_size;
+        }
+        self
+    }
+
+    /// The current state of the monitor
+    pub fn state(&self) -> &MonitorState<LogSize> {
+        &self.state
+    }
+
+    /// Fetch the new entries from the log and check they produce the latest root, returning false if they do not
+    /// The state is only updated if the verification succeeds
+    /// Records are hashed with the leaf hasher of the log, entries without a record are replayed from the leaf hashes the log stores for them
+    pub fn update<'a, T, TL>(&mut self, log: &TL) -> anyhow::Result<bool>
+    where
+        T: Serialize + Deserialize<'a>,
+        TL: TransparentLog<'a, T, LogSize = LogSize>,
+    {
+        let latest = log.latest()?;
+        if latest.size < self.state.size {
+            return Ok(false);
+        }
+        let mut state = self.state.clone();
+        while state.size < latest.size {
+            let end = latest.size.min(state.size + self.batch_size);
+            let leaves = match log.get_range(state.size, end) {
+                Ok(records) => records
+                    .iter()
+                    .map(|r| (log.leaf_hasher())(r.as_ref()))
+                    .collect::<anyhow::Result<Vec<_>>>()?,
+                Err(e) if is_tombstone(&e) => leaf_hashes(log, state.size, end)?,
+                Err(e) => return Err(e),
+            };
+            if leaves.is_empty() {
+                return Ok(false);
+            }
+            for h in leaves {
+                state.push(h);
+                state.size = state.size + LogSize::one();
+            }
+        }
+        if state.size != latest.size || state.root() != latest.hash {
+            return Ok(false);
+        }
+        self.state = state;
+        Ok(true)
+    }
+}
+
+/// Whether an error is reading a tombstone
+pub(crate) fn is_tombstone(e: &anyhow::Error) -> bool {
+    matches!(
+        e.downcast_ref(),
+        Some(TombstoneError::NoRecord | TombstoneError::Expired(_))
+    )
+}
+
+/// The leaf hashes of the entries from start to end, taken from the log for tombstones,
+/// since their records cannot be hashed
+fn leaf_hashes<'a, T, TL>(
+    log: &TL,
+    start: TL::LogSize,
+    end: TL::LogSize,
+) -> anyhow::Result<Vec<String>>
+where
+    T: Serialize + Deserialize<'a>,
+    TL: TransparentLog<'a, T>,
+{
+    let mut leaves = vec![];
+    let mut id = start;
+    while id < end {
+        match log.get(id) {
+            Ok(Some(r)) => leaves.push((log.leaf_hasher())(r.as_ref())?),
+            Ok(None) => break,
+            Err(e) if is_tombstone(&e) => leaves.push(log.get_hash(0, id)?.into_owned()),
+            Err(e) => return Err(e),
+        }
+        id = id + TL::LogSize::one();
+    }
+    Ok(leaves)
+}
+
+impl<LogSize: num::Integer + Copy + From<u8> + Serialize + DeserializeOwned> Monitor<LogSize> {
+    /// Load a monitor from a state saved in the given file
+    pub fn load<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        let mut b = vec![];
+        File::open(path)?.read_to_end(&mut b)?;
+        Ok(Self::open(rmp_serde::from_slice(&b)?))
+    }
+
+    /// Save the monitor state to the given file, replacing it atomically
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        let tmp = path.with_extension("tmp");
+        let mut f = File::create(&tmp)?;
+        f.write_all(&rmp_serde::to_vec(&self.state)?)?;
+        f.sync_all()?;
+        rename(tmp, path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::test_helpers::*;
+    use crate::{InMemoryLog, LeafLog, Monitor, TransparentLog};
+    use std::fs::{create_dir, remove_dir_all};
+    use std::path::Path;
+
+    #[test]
+    fn monitor_empty() -> anyhow::Result<()> {
+        let ml: InMemoryLog<LogRecord> = InMemoryLog::default();
+        let mut monitor = Monitor::default();
+        assert!(monitor.update(&ml)?);
+        assert_eq!(0, monitor.state().size);
+        assert_eq!("", monitor.state().root());
+        Ok(())
+    }
+
+    #[test]
+    fn monitor_incremental() -> anyhow::Result<()> {
+        let mut ml: InMemoryLog<LogRecord> = InMemoryLog::default();
+        let mut monitor = Monitor::default();
+        monitor.batch_size(4);
+        for (start, nb) in [(0, 1), (1, 12), (13, 3), (16, 7)] {
+            append_multiple_offset(&mut ml, start, nb)?;
+            assert!(monitor.update(&ml)?);
+            assert_eq!(start + nb, monitor.state().size);
+            assert_eq!(ml.latest()?.hash, monitor.state().root());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn monitor_tombstones() -> anyhow::Result<()> {
+        let mut ml: InMemoryLog<LogRecord> = InMemoryLog::default();
+        let mut monitor = Monitor::default();
+        monitor.batch_size(4);
+        hash_only(&mut ml)?;
+        ml.redact(10)?;
+        assert!(monitor.update(&ml)?);
+        assert_eq!(13, monitor.state().size);
+        assert_eq!(ml.latest()?.hash, monitor.state().root());
+        Ok(())
+    }
+
+    #[test]
+    fn monitor_leaf_hasher() -> anyhow::Result<()> {
+        let mut ml = LeafLog::new(InMemoryLog::default());
+        for i in 0..7_u8 {
+            ml.append(vec![i; 3])?;
+        }
+        let mut monitor = Monitor::default();
+        assert!(monitor.update(&ml)?);
+        assert_eq!(7, monitor.state().size);
+        assert_eq!(ml.latest()?.hash, monitor.state().root());
+        Ok(())
+    }
+
+    #[test]
+    fn monitor_mismatch() -> anyhow::Result<()> {
+        let mut ml: InMemoryLog<LogRecord> = InMemoryLog::default();
+        append_multiple(&mut ml, 5)?;
+        let mut monitor = Monitor::default();
+        assert!(monitor.update(&ml)?);
+
+        let mut ml2: InMemoryLog<LogRecord> = InMemoryLog::default();
+        append_multiple_offset(&mut ml2, 1, 8)?;
+        assert!(!monitor.update(&ml2)?);
+        assert_eq!(5, monitor.state().size);
+
+        let mut ml3: InMemoryLog<LogRecord> = InMemoryLog::default();
+        append_multiple(&mut ml3, 3)?;
+        assert!(!monitor.update(&ml3)?);
+        Ok(())
+    }
+
+    #[test]
+    fn monitor_save_load() -> anyhow::Result<()> {
+        let path = Path::new("./test_data_monitor");
+        if path.exists() {
+            remove_dir_all(path)?;
+        }
+        create_dir(path)?;
+        let state = path.join("monitor.bin");
+
+        let mut ml: InMemoryLog<LogRecord> = InMemoryLog::default();
+        append_multiple(&mut ml, 7)?;
+        let mut monitor = Monitor::default();
+        assert!(monitor.update(&ml)?);
+        monitor.save(&state)?;
+
+        append_multiple_offset(&mut ml, 7, 6)?;
+        let mut monitor2: Monitor<usize> = Monitor::load(&state)?;
+        assert_eq!(monitor.state(), monitor2.state());
+        assert!(monitor2.update(&ml)?);
+        assert_eq!(13, monitor2.state().size);
+        remove_dir_all(path)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,213 @@
+//! # Multi-log client
+//!
+//! Applications checking records from several logs (packages, containers, firmware) keep the state of all of them
+//! in one `MultiLogClient`: a latest tree head and a cache per log, keyed by the origin of the log.
+//! Each check is routed to the state of the given origin, and the log it reads proofs from must have that origin.
+use crate::base::*;
+use crate::memory::{InMemoryLogClient, InMemoryLogClientBuilder};
+use num::ToPrimitive;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::hash::Hash;
+use thiserror::Error;
+
+/// Specific Errors of multi-log clients
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum MultiLogError {
+    #[error("Tree head without an origin, it cannot be told apart from the heads of other logs")]
+    NoOrigin,
+    #[error("No state for the log with origin {0}")]
+    UnknownOrigin(String),
+}
+
+/// A client keeping the latest tree verified and the cache of each of several logs, by origin
+#[derive(Debug, Clone)]
+pub struct MultiLogClient<LogSize> {
+    clients: HashMap<String, InMemoryLogClient<LogSize>>,
+    cache: bool,
+}
+
+impl<LogSize> Default for MultiLogClient<LogSize> {
+    fn default() -> Self {
+        Self {
+            clients: HashMap::new(),
+            cache: true,
+        }
+    }
+}
+
+impl<LogSize: Clone + Eq + Hash + ToPrimitive> MultiLogClient<LogSize> {
+    /// A client without any log, caching positions
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Do not cache positions for the logs added from now on
+    pub fn no_cache(&mut self) -> &mut Self {
+        self.cache = false;
+        self
+    }
+
+    /// Start checking the log with the origin of the given tree head from that head, replacing any state for that origin
+    pub fn trust(&mut self, latest: LogTree<LogSize>) -> Result<(), MultiLogError> {
+        let origin = latest.origin.clone().ok_or(MultiLogError::NoOrigin)?;
+        let mut builder = InMemoryLogClientBuilder::open(latest);
+        if !self.cache {
+            builder.no_cache();
+        }
+        self.clients.insert(origin, builder.build());
+        Ok(())
+    }
+
+    /// Start checking the given log from its current tree head, which must have an origin
+    pub fn add<'a, T, K, TL>(&mut self, log: &TL) -> anyhow::Result<()>
+    where
+        T: Serialize + Deserialize<'a>,
+        TL: ProofSource<'a, T, K, LogSize = LogSize>,
+    {
+        let mut latest = log.fetch_latest()?;
+        latest.origin = log.log_origin();
+        Ok(self.trust(latest)?)
+    }
+
+    /// Forget the state of the log with the given origin, returning it
+    pub fn remove(&mut self, origin: &str) -> Option<InMemoryLogClient<LogSize>> {
+        self.clients.remove(origin)
+    }
+
+    /// The origins of the logs checked
+    pub fn origins(&self) -> impl Iterator<Item = &str> {
+        self.clients.keys().map(String::as_str)
+    }
+
+    /// The state of the log with the given origin
+    pub fn client(&self, origin: &str) -> Option<&InMemoryLogClient<LogSize>> {
+        self.clients.get(origin)
+    }
+
+    /// The state of the log with the given origin, to check records with the functions taking a `LogClient`
+    pub fn client_mut(
+        &mut self,
+        origin: &str,
+    ) -> Result<&mut InMemoryLogClient<LogSize>, MultiLogError> {
+        self.clients
+            .get_mut(origin)
+            .ok_or_else(|| MultiLogError::UnknownOrigin(origin.to_owned()))
+    }
+
+    /// The latest tree verified of the log with the given origin
+    pub fn latest(&self, origin: &str) -> Option<&LogTree<LogSize>> {
+        self.clients.get(origin).map(InMemoryLogClient::latest)
+    }
+
+    /// Check a record is contained in the log with the given origin, reading proofs from the given log
+    /// Fails with a `MultiLogError::UnknownOrigin` error for a log that was not added,
+    /// and with an `OriginError::Mismatch` error if the given log has another origin
+    pub fn check_record<'a, T, K, TL>(
+        &mut self,
+        origin: &str,
+        log: &TL,
+        record: &Record<LogSize>,
+    ) -> anyhow::Result<bool>
+    where
+        T: Serialize + Deserialize<'a>,
+        TL: ProofSource<'a, T, K, LogSize = LogSize>,
+    {
+        check_record(self.client_mut(origin)?, log, record)
+    }
+
+    /// Check several records are contained in the log with the given origin, like `check_records`
+    pub fn check_records<'a, T, K, TL>(
+        &mut self,
+        origin: &str,
+        log: &TL,
+        records: &[Record<LogSize>],
+    ) -> anyhow::Result<Vec<bool>>
+    where
+        T: Serialize + Deserialize<'a>,
+        TL: ProofSource<'a, T, K, LogSize = LogSize>,
+    {
+        check_records(self.client_mut(origin)?, log, records)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::test_helpers::*;
+    use crate::*;
+
+    #[test]
+    fn multi_logs() -> anyhow::Result<()> {
+        let mut packages = InMemoryLog::with_origin("packages");
+        let mut firmware = InMemoryLog::with_origin("firmware");
+        append_multiple(&mut packages, 5)?;
+        append_multiple(&mut firmware, 3)?;
+
+        let mut client = MultiLogClient::new();
+        client.add(&packages)?;
+        client.add(&firmware)?;
+        let mut origins: Vec<_> = client.origins().collect();
+        origins.sort_unstable();
+        assert_eq!(vec!["firmware", "packages"], origins);
+        assert_eq!(Some(5), client.latest("packages").map(|t| t.size));
+
+        let package = packages.append(LogRecord::new("package"))?;
+        let blob = firmware.append(LogRecord::new("blob"))?;
+        assert!(client.check_record("packages", &packages, &package)?);
+        assert!(client.check_record("firmware", &firmware, &blob)?);
+        assert_eq!(Some(6), client.latest("packages").map(|t| t.size));
+        assert_eq!(Some(4), client.latest("firmware").map(|t| t.size));
+        assert_eq!(
+            vec![true, false],
+            client.check_records(
+                "packages",
+                &packages,
+                &[
+                    package.clone(),
+                    Record {
+                        id: 0,
+                        hash: package.hash.clone(),
+                        meta: None
+                    }
+                ]
+            )?
+        );
+
+        // the proofs of a log are never checked against the state of another
+        let err = client
+            .check_record("packages", &firmware, &blob)
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<OriginError>(),
+            Some(OriginError::Mismatch { .. })
+        ));
+        let err = client
+            .check_record("containers", &packages, &package)
+            .unwrap_err();
+        assert_eq!(
+            Some(&MultiLogError::UnknownOrigin("containers".into())),
+            err.downcast_ref::<MultiLogError>()
+        );
+        assert!(client.remove("firmware").is_some());
+        assert!(client.latest("firmware").is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn multi_trust() -> anyhow::Result<()> {
+        let mut ml: InMemoryLog<LogRecord> = InMemoryLog::default();
+        append_multiple(&mut ml, 3)?;
+        let mut client = MultiLogClient::new();
+        assert_eq!(Err(MultiLogError::NoOrigin), client.trust(ml.latest()?));
+        assert!(client.add(&ml).is_err());
+
+        let mut ml = InMemoryLog::with_origin("log");
+        append_multiple(&mut ml, 3)?;
+        client.no_cache().trust(ml.latest()?)?;
+        let record = ml.append(LogRecord::new("rec3"))?;
+        assert!(client.check_record("log", &ml, &record)?);
+        assert_eq!(Some(4), client.latest("log").map(|t| t.size));
+        Ok(())
+    }
+}
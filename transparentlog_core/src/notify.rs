@@ -0,0 +1,275 @@
+//! # Append notifications
+//!
+//! A `NotifyingLog` wraps any backend and sends an `AppendEvent`, the record appended and the tree including it,
+//! to each of its subscribers after each successful append, so monitors and webhooks need not poll `latest`.
+//! Subscribers receive the events on channels, buffered until read; subscribers whose receiver is dropped are forgotten.
+use crate::base::*;
+use maybe_owned::MaybeOwned;
+use num::{Integer, NumCast};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+/// A record appended to a log, with the tree that first included it
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct AppendEvent<LogSize> {
+    pub record: Record<LogSize>,
+    pub tree: LogTree<LogSize>,
+}
+
+/// A log notifying its subscribers of each record appended
+/// Only appends are notified, not the records or hashes added on their own
+#[derive(Debug)]
+pub struct NotifyingLog<L, LogSize> {
+    log: L,
+    subscribers: Vec<Sender<AppendEvent<LogSize>>>,
+}
+
+impl<L, LogSize> NotifyingLog<L, LogSize> {
+    /// Wrap a log, without subscribers
+    pub fn new(log: L) -> Self {
+        Self {
+            log,
+            subscribers: vec![],
+        }
+    }
+
+    /// Subscribe to the records appended from now on
+    pub fn subscribe(&mut self) -> Receiver<AppendEvent<LogSize>> {
+        let (sender, receiver) = channel();
+        self.subscribers.push(sender);
+        receiver
+    }
+
+    /// The number of subscribers still listening, as of the last notification
+    pub fn subscribers(&self) -> usize {
+        self.subscribers.len()
+    }
+
+    /// The wrapped log
+    pub fn inner(&self) -> &L {
+        &self.log
+    }
+
+    /// Get back the wrapped log
+    pub fn into_inner(self) -> L {
+        self.log
+    }
+}
+
+impl<L, LogSize: Integer + Copy + Hash + NumCast + 'static> NotifyingLog<L, LogSize> {
+    /// Send the events of appended records to the subscribers, forgetting the ones who left
+    fn notify<'a, T>(&mut self, records: &[Record<LogSize>]) -> anyhow::Result<()>
+    where
+        T: Serialize + Deserialize<'a>,
+        L: TransparentLog<'a, T, LogSize = LogSize>,
+    {
+        for record in records {
+            if self.subscribers.is_empty() {
+                break;
+            }
+            let event = AppendEvent {
+                record: record.clone(),
+                tree: self.log.head_at(record.id + LogSize::one())?,
+            };
+            self.subscribers
+                .retain(|subscriber| subscriber.send(event.clone()).is_ok());
+        }
+        Ok(())
+    }
+}
+
+/// Appends are delegated to the wrapped log, then notified
+impl<'a, T, L, LogSize> TransparentLog<'a, T> for NotifyingLog<L, LogSize>
+where
+    T: Serialize + Deserialize<'a>,
+    L: TransparentLog<'a, T, LogSize = LogSize>,
+    LogSize: Integer + Copy + Hash + NumCast + 'static,
+{
+    type LogSize = LogSize;
+
+    fn add(&mut self, record: T) -> anyhow::Result<Self::LogSize> {
+        self.log.add(record)
+    }
+
+    fn add_tombstone(&mut self) -> anyhow::Result<Self::LogSize> {
+        self.log.add_tombstone()
+    }
+
+    fn redact(&mut self, id: Self::LogSize) -> anyhow::Result<()> {
+        self.log.redact(id)
+    }
+
+    fn expire(&mut self, id: Self::LogSize) -> anyhow::Result<()> {
+        self.log.expire(id)
+    }
+
+    fn expired(&self, id: Self::LogSize) -> anyhow::Result<bool> {
+        self.log.expired(id)
+    }
+
+    fn seal(&mut self) -> anyhow::Result<LogTree<Self::LogSize>> {
+        self.log.seal()
+    }
+
+    fn sealed(&self) -> anyhow::Result<Option<LogTree<Self::LogSize>>> {
+        self.log.sealed()
+    }
+
+    fn sync(&self) -> anyhow::Result<()> {
+        self.log.sync()
+    }
+
+    fn add_meta(&mut self, id: Self::LogSize, meta: RecordMeta) -> anyhow::Result<()> {
+        self.log.add_meta(id, meta)
+    }
+
+    fn get_meta(&self, id: Self::LogSize) -> anyhow::Result<Option<RecordMeta>> {
+        self.log.get_meta(id)
+    }
+
+    fn set_annotation(&mut self, id: Self::LogSize, annotation: Vec<u8>) -> anyhow::Result<()> {
+        self.log.set_annotation(id, annotation)
+    }
+
+    fn get_annotation(&self, id: Self::LogSize) -> anyhow::Result<Option<Vec<u8>>> {
+        self.log.get_annotation(id)
+    }
+
+    fn find_since(&self, timestamp: u64) -> anyhow::Result<Self::LogSize> {
+        self.log.find_since(timestamp)
+    }
+
+    fn add_hash(&mut self, level: LogHeight, hash: String) -> anyhow::Result<Self::LogSize> {
+        self.log.add_hash(level, hash)
+    }
+
+    fn get_hash(
+        &self,
+        level: LogHeight,
+        index: Self::LogSize,
+    ) -> anyhow::Result<MaybeOwned<'_, String>> {
+        self.log.get_hash(level, index)
+    }
+
+    fn append(&mut self, record: T) -> anyhow::Result<Record<Self::LogSize>> {
+        let record = self.log.append(record)?;
+        self.notify(std::slice::from_ref(&record))?;
+        Ok(record)
+    }
+
+    fn append_hash_only(&mut self, hash: String) -> anyhow::Result<Record<Self::LogSize>> {
+        let record = self.log.append_hash_only(hash)?;
+        self.notify(std::slice::from_ref(&record))?;
+        Ok(record)
+    }
+
+    /// Each record of the batch is notified with the tree ending with it
+    fn bulk_append(&mut self, records: Vec<T>) -> anyhow::Result<Vec<Record<Self::LogSize>>>
+    where
+        T: Sync,
+    {
+        let records = self.log.bulk_append(records)?;
+        self.notify(&records)?;
+        Ok(records)
+    }
+
+    fn size(&self) -> anyhow::Result<Self::LogSize> {
+        self.log.size()
+    }
+
+    fn origin(&self) -> Option<String> {
+        self.log.origin()
+    }
+
+    fn latest(&self) -> anyhow::Result<LogTree<Self::LogSize>> {
+        self.log.latest()
+    }
+
+    fn leaf_hasher(&self) -> LeafHasher<T> {
+        self.log.leaf_hasher()
+    }
+
+    fn get(&self, index: Self::LogSize) -> anyhow::Result<Option<MaybeOwned<'_, T>>> {
+        self.log.get(index)
+    }
+
+    fn get_raw_bytes(&self, index: Self::LogSize) -> anyhow::Result<Option<Vec<u8>>> {
+        self.log.get_raw_bytes(index)
+    }
+
+    fn get_leaf_bytes(&self, index: Self::LogSize) -> anyhow::Result<Option<Vec<u8>>> {
+        self.log.get_leaf_bytes(index)
+    }
+
+    fn get_range(
+        &self,
+        start: Self::LogSize,
+        end: Self::LogSize,
+    ) -> anyhow::Result<Vec<MaybeOwned<'_, T>>> {
+        self.log.get_range(start, end)
+    }
+
+    fn iter(&self) -> LogIterator<'_, Self::LogSize, T> {
+        self.log.iter()
+    }
+
+    fn index_of(&self, hash: &str) -> anyhow::Result<Option<Self::LogSize>> {
+        self.log.index_of(hash)
+    }
+
+    fn indices_of(&self, hash: &str) -> anyhow::Result<Vec<Self::LogSize>> {
+        self.log.indices_of(hash)
+    }
+
+    fn proofs<I>(
+        &self,
+        positions: I,
+    ) -> anyhow::Result<HashMap<LogTreePosition<Self::LogSize>, String>>
+    where
+        I: Iterator<Item = LogTreePosition<Self::LogSize>>,
+    {
+        self.log.proofs(positions)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::test_helpers::*;
+    use crate::*;
+
+    #[test]
+    fn notify_appends() -> anyhow::Result<()> {
+        let mut ml = NotifyingLog::new(InMemoryLog::default());
+        append_multiple(&mut ml, 2)?;
+        let events = ml.subscribe();
+        let record = ml.append(LogRecord::new("rec2"))?;
+        let event = events.try_recv()?;
+        assert_eq!(record, event.record);
+        assert_eq!(ml.latest()?, event.tree);
+        let proofs = ml.proofs(proof_positions(2, event.tree.size).into_iter())?;
+        assert!(verify(&event.tree, &event.record, &proofs));
+
+        let other = ml.subscribe();
+        let records = ml.bulk_append(vec![LogRecord::new("rec3"), LogRecord::new("rec4")])?;
+        for (i, record) in records.iter().enumerate() {
+            for rx in [&events, &other] {
+                let event = rx.try_recv()?;
+                assert_eq!(record, &event.record);
+                assert_eq!(ml.head_at(4 + i)?, event.tree);
+            }
+        }
+        assert!(events.try_recv().is_err());
+
+        drop(other);
+        let record = ml.append_hash_only(hash(&LogRecord::new("rec5"))?)?;
+        assert_eq!(record, events.try_recv()?.record);
+        assert_eq!(1, ml.subscribers());
+        drop(events);
+        ml.append(LogRecord::new("rec6"))?;
+        assert_eq!(0, ml.subscribers());
+        Ok(())
+    }
+}
@@ -0,0 +1,413 @@
+//! # Submission policies
+//!
+//! Acceptance criteria for the records submitted to a log: a `PolicyLog` wraps any backend
+//! and checks each record against its policies before adding it, rejecting it with a `SubmissionError`.
+//! Closures returning a `Result<(), SubmissionError>` are policies, `MaxRecordSize` limits the serialized size of records,
+//! `CanonicalRecord` rejects records whose serialization does not round-trip to the same bytes.
+use crate::base::*;
+use crate::codec::{encode_canonical, Codec, MessagePack};
+use maybe_owned::MaybeOwned;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use thiserror::Error;
+
+/// Reasons for rejecting a record
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum SubmissionError {
+    #[error("Record of {size} bytes exceeds the limit of {limit} bytes")]
+    TooLarge { size: usize, limit: usize },
+    #[error("Malformed record: {0}")]
+    Malformed(String),
+    #[error("Record not signed by an allowed key: {0}")]
+    Unauthorized(String),
+    #[error("Record rejected: {0}")]
+    Rejected(String),
+}
+
+/// A policy accepting or rejecting the records submitted to a log
+pub trait SubmissionPolicy<T> {
+    /// Check a record before it is added
+    fn check(&self, record: &T) -> Result<(), SubmissionError>;
+}
+
+impl<T, F> SubmissionPolicy<T> for F
+where
+    F: Fn(&T) -> Result<(), SubmissionError>,
+{
+    fn check(&self, record: &T) -> Result<(), SubmissionError> {
+        self(record)
+    }
+}
+
+/// Reject the records whose serialization with the given codec is larger than a limit
+#[derive(Debug, Clone, Copy)]
+pub struct MaxRecordSize<C: Codec = MessagePack> {
+    limit: usize,
+    codec: PhantomData<C>,
+}
+
+impl MaxRecordSize {
+    /// Limit the size of records serialized as MessagePack
+    pub fn new(limit: usize) -> Self {
+        Self::with_codec(limit)
+    }
+}
+
+impl<C: Codec> MaxRecordSize<C> {
+    /// Limit the size of records serialized with the codec
+    pub fn with_codec(limit: usize) -> Self {
+        Self {
+            limit,
+            codec: PhantomData,
+        }
+    }
+
+    /// The maximum size of a serialized record, in bytes
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+}
+
+impl<T: Serialize, C: Codec> SubmissionPolicy<T> for MaxRecordSize<C> {
+    fn check(&self, record: &T) -> Result<(), SubmissionError> {
+        let size = C::encode(record)
+            .map_err(|e| SubmissionError::Malformed(e.to_string()))?
+            .len();
+        if size > self.limit {
+            Err(SubmissionError::TooLarge {
+                size,
+                limit: self.limit,
+            })
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Reject the records whose serialization with the given codec is not canonical, so their leaf hashes
+/// could not be recomputed from the records read back
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CanonicalRecord<C: Codec = MessagePack> {
+    codec: PhantomData<C>,
+}
+
+impl CanonicalRecord {
+    /// Check records serialized as MessagePack
+    pub fn new() -> Self {
+        Self::with_codec()
+    }
+}
+
+impl<C: Codec> CanonicalRecord<C> {
+    /// Check records serialized with the codec
+    pub fn with_codec() -> Self {
+        Self { codec: PhantomData }
+    }
+}
+
+impl<T: Serialize + DeserializeOwned, C: Codec> SubmissionPolicy<T> for CanonicalRecord<C> {
+    fn check(&self, record: &T) -> Result<(), SubmissionError> {
+        encode_canonical::<C, T>(record)
+            .map(|_| ())
+            .map_err(|e| SubmissionError::Malformed(e.to_string()))
+    }
+}
+
+/// A log checking the records added against its policies, in the order they were given
+/// Without policies, records are added unchecked
+pub struct PolicyLog<L, T> {
+    log: L,
+    policies: Vec<Box<dyn SubmissionPolicy<T> + Send + Sync>>,
+}
+
+impl<L, T> PolicyLog<L, T> {
+    /// Wrap a log, adding records unchecked until policies are added
+    pub fn new(log: L) -> Self {
+        Self {
+            log,
+            policies: vec![],
+        }
+    }
+
+    /// Add a policy the records must satisfy
+    pub fn policy(&mut self, policy: Box<dyn SubmissionPolicy<T> + Send + Sync>) -> &mut Self {
+        self.policies.push(policy);
+        self
+    }
+
+    /// The wrapped log
+    pub fn inner(&self) -> &L {
+        &self.log
+    }
+
+    /// Get back the wrapped log
+    pub fn into_inner(self) -> L {
+        self.log
+    }
+
+    /// Check a record against all the policies
+    pub fn check(&self, record: &T) -> Result<(), SubmissionError> {
+        self.policies.iter().try_for_each(|p| p.check(record))
+    }
+}
+
+/// Records are checked when added or appended, hashes appended without the record cannot be
+impl<'a, T, L> TransparentLog<'a, T> for PolicyLog<L, T>
+where
+    T: Serialize + Deserialize<'a>,
+    L: TransparentLog<'a, T>,
+{
+    type LogSize = L::LogSize;
+
+    fn add(&mut self, record: T) -> anyhow::Result<Self::LogSize> {
+        self.check(&record)?;
+        self.log.add(record)
+    }
+
+    fn add_tombstone(&mut self) -> anyhow::Result<Self::LogSize> {
+        self.log.add_tombstone()
+    }
+
+    fn redact(&mut self, id: Self::LogSize) -> anyhow::Result<()> {
+        self.log.redact(id)
+    }
+
+    fn expire(&mut self, id: Self::LogSize) -> anyhow::Result<()> {
+        self.log.expire(id)
+    }
+
+    fn expired(&self, id: Self::LogSize) -> anyhow::Result<bool> {
+        self.log.expired(id)
+    }
+
+    fn seal(&mut self) -> anyhow::Result<LogTree<Self::LogSize>> {
+        self.log.seal()
+    }
+
+    fn sealed(&self) -> anyhow::Result<Option<LogTree<Self::LogSize>>> {
+        self.log.sealed()
+    }
+
+    fn sync(&self) -> anyhow::Result<()> {
+        self.log.sync()
+    }
+
+    fn add_meta(&mut self, id: Self::LogSize, meta: RecordMeta) -> anyhow::Result<()> {
+        self.log.add_meta(id, meta)
+    }
+
+    fn get_meta(&self, id: Self::LogSize) -> anyhow::Result<Option<RecordMeta>> {
+        self.log.get_meta(id)
+    }
+
+    fn set_annotation(&mut self, id: Self::LogSize, annotation: Vec<u8>) -> anyhow::Result<()> {
+        self.log.set_annotation(id, annotation)
+    }
+
+    fn get_annotation(&self, id: Self::LogSize) -> anyhow::Result<Option<Vec<u8>>> {
+        self.log.get_annotation(id)
+    }
+
+    fn find_since(&self, timestamp: u64) -> anyhow::Result<Self::LogSize> {
+        self.log.find_since(timestamp)
+    }
+
+    fn add_hash(&mut self, level: LogHeight, hash: String) -> anyhow::Result<Self::LogSize> {
+        self.log.add_hash(level, hash)
+    }
+
+    fn get_hash(
+        &self,
+        level: LogHeight,
+        index: Self::LogSize,
+    ) -> anyhow::Result<MaybeOwned<'_, String>> {
+        self.log.get_hash(level, index)
+    }
+
+    fn append(&mut self, record: T) -> anyhow::Result<Record<Self::LogSize>> {
+        self.check(&record)?;
+        self.log.append(record)
+    }
+
+    /// No record is appended if one of them is rejected
+    fn bulk_append(&mut self, records: Vec<T>) -> anyhow::Result<Vec<Record<Self::LogSize>>>
+    where
+        T: Sync,
+    {
+        for record in records.iter() {
+            self.check(record)?;
+        }
+        self.log.bulk_append(records)
+    }
+
+    fn size(&self) -> anyhow::Result<Self::LogSize> {
+        self.log.size()
+    }
+
+    fn origin(&self) -> Option<String> {
+        self.log.origin()
+    }
+
+    fn latest(&self) -> anyhow::Result<LogTree<Self::LogSize>> {
+        self.log.latest()
+    }
+
+    fn leaf_hasher(&self) -> LeafHasher<T> {
+        self.log.leaf_hasher()
+    }
+
+    fn get(&self, index: Self::LogSize) -> anyhow::Result<Option<MaybeOwned<'_, T>>> {
+        self.log.get(index)
+    }
+
+    fn get_raw_bytes(&self, index: Self::LogSize) -> anyhow::Result<Option<Vec<u8>>> {
+        self.log.get_raw_bytes(index)
+    }
+
+    fn get_leaf_bytes(&self, index: Self::LogSize) -> anyhow::Result<Option<Vec<u8>>> {
+        self.log.get_leaf_bytes(index)
+    }
+
+    fn get_range(
+        &self,
+        start: Self::LogSize,
+        end: Self::LogSize,
+    ) -> anyhow::Result<Vec<MaybeOwned<'_, T>>> {
+        self.log.get_range(start, end)
+    }
+
+    fn iter(&self) -> LogIterator<'_, Self::LogSize, T> {
+        self.log.iter()
+    }
+
+    fn index_of(&self, hash: &str) -> anyhow::Result<Option<Self::LogSize>> {
+        self.log.index_of(hash)
+    }
+
+    fn indices_of(&self, hash: &str) -> anyhow::Result<Vec<Self::LogSize>> {
+        self.log.indices_of(hash)
+    }
+
+    fn proofs<I>(
+        &self,
+        positions: I,
+    ) -> anyhow::Result<HashMap<LogTreePosition<Self::LogSize>, String>>
+    where
+        I: Iterator<Item = LogTreePosition<Self::LogSize>>,
+    {
+        self.log.proofs(positions)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::test_helpers::*;
+    use crate::*;
+    #[cfg(feature = "json")]
+    use serde::{Deserialize, Serialize};
+
+    #[test]
+    fn policy_size() -> anyhow::Result<()> {
+        let record = LogRecord::new("rec0");
+        let size = MessagePack::encode(&record)?.len();
+        assert_eq!(Ok(()), MaxRecordSize::new(size).check(&record));
+        assert_eq!(
+            Err(SubmissionError::TooLarge {
+                size,
+                limit: size - 1
+            }),
+            MaxRecordSize::new(size - 1).check(&record)
+        );
+        #[cfg(feature = "json")]
+        assert_eq!(
+            Err(SubmissionError::TooLarge {
+                size: 15,
+                limit: 14
+            }),
+            MaxRecordSize::<Json>::with_codec(14).check(&record)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn policy_log() -> anyhow::Result<()> {
+        let mut ml = PolicyLog::new(InMemoryLog::default());
+        ml.policy(Box::new(MaxRecordSize::new(16)))
+            .policy(Box::new(|r: &LogRecord| {
+                if r.text.starts_with("rec") {
+                    Ok(())
+                } else {
+                    Err(SubmissionError::Malformed(format!(
+                        "unknown record {}",
+                        r.text
+                    )))
+                }
+            }));
+        append_multiple(&mut ml, 5)?;
+        assert_eq!(5, ml.size()?);
+
+        let err = ml.append(LogRecord::new("other")).unwrap_err();
+        assert_eq!(
+            Some(&SubmissionError::Malformed("unknown record other".into())),
+            err.downcast_ref::<SubmissionError>()
+        );
+        let err = ml.add(LogRecord::new("rec_with_a_long_text")).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<SubmissionError>(),
+            Some(SubmissionError::TooLarge { limit: 16, .. })
+        ));
+        assert!(ml
+            .bulk_append(vec![LogRecord::new("rec5"), LogRecord::new("other")])
+            .is_err());
+        assert_eq!(5, ml.size()?);
+        ml.bulk_append(vec![LogRecord::new("rec5"), LogRecord::new("rec6")])?;
+        assert_eq!(7, ml.size()?);
+
+        let mut client = InMemoryLogClientBuilder::new(&ml)?.build();
+        let record = ml.append(LogRecord::new("rec7"))?;
+        assert!(check_record(&mut client, &ml, &record)?);
+        Ok(())
+    }
+
+    /// A record whose count is not read back
+    #[cfg(feature = "json")]
+    #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+    struct Counted {
+        text: String,
+        #[serde(skip_deserializing)]
+        count: u32,
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn policy_canonical() -> anyhow::Result<()> {
+        let mut ml = PolicyLog::new(InMemoryLog::default());
+        ml.policy(Box::new(CanonicalRecord::<Json>::with_codec()));
+        for i in 0..5 {
+            ml.append(Counted {
+                text: format!("rec{}", i),
+                count: 0,
+            })?;
+        }
+        assert_eq!(5, ml.size()?);
+
+        let record = Counted {
+            text: "rec5".into(),
+            count: 1,
+        };
+        let err = ml.append(record).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<SubmissionError>(),
+            Some(SubmissionError::Malformed(_))
+        ));
+        assert_eq!(5, ml.size()?);
+        assert_eq!(
+            Ok(()),
+            CanonicalRecord::new().check(&LogRecord::new("rec5"))
+        );
+        Ok(())
+    }
+}
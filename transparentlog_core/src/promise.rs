@@ -0,0 +1,239 @@
+//! Promises of inclusion: signed receipts returned on submission, before records are sequenced into the tree
+use crate::base::*;
+use crate::checkpoint::*;
+use maybe_owned::MaybeOwned;
+use serde::{Deserialize, Serialize};
+use std::marker::PhantomData;
+
+/// The second line of a promise text, distinguishing it from a checkpoint
+const PROMISE_MARKER: &str = "promise";
+
+/// The default maximum merge delay, in milliseconds: one day
+pub const DEFAULT_MAX_MERGE_DELAY: u64 = 24 * 60 * 60 * 1000;
+
+/// A promise from a log to include a record hash in its tree before a deadline
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Promise {
+    pub origin: String,
+    pub hash: String,
+    pub timestamp: u64,
+    pub max_merge_delay: u64,
+}
+
+impl Promise {
+    /// Create a new promise made now
+    pub fn new(origin: &str, hash: &str, max_merge_delay: u64) -> Self {
+        Self {
+            origin: origin.to_owned(),
+            hash: hash.to_owned(),
+            timestamp: RecordMeta::now().timestamp,
+            max_merge_delay,
+        }
+    }
+
+    /// The time before which the record should be in the tree, in milliseconds since the UNIX epoch
+    pub fn deadline(&self) -> u64 {
+        self.timestamp.saturating_add(self.max_merge_delay)
+    }
+
+    /// The text of the promise: origin, marker, record hash, timestamp and maximum merge delay lines
+    pub fn text(&self) -> String {
+        format!(
+            "{}\n{}\n{}\n{}\n{}\n",
+            self.origin, PROMISE_MARKER, self.hash, self.timestamp, self.max_merge_delay
+        )
+    }
+
+    /// Parse the text of a promise
+    pub fn parse(text: &str) -> Result<Self, CheckpointError> {
+        let lines: Vec<&str> = text.split('\n').collect();
+        match lines[..] {
+            [origin, PROMISE_MARKER, hash, timestamp, max_merge_delay, ""]
+                if !origin.is_empty() && !hash.is_empty() =>
+            {
+                Ok(Self {
+                    origin: origin.to_owned(),
+                    hash: hash.to_owned(),
+                    timestamp: timestamp
+                        .parse()
+                        .map_err(|_| CheckpointError::MalformedPromise("invalid timestamp"))?,
+                    max_merge_delay: max_merge_delay
+                        .parse()
+                        .map_err(|_| CheckpointError::MalformedPromise("invalid merge delay"))?,
+                })
+            }
+            _ => Err(CheckpointError::MalformedPromise("invalid lines")),
+        }
+    }
+
+    /// Sign the promise, returning the signed note
+    pub fn sign(&self, signers: &[&dyn LogSigner]) -> anyhow::Result<String> {
+        sign_note(&self.text(), signers)
+    }
+
+    /// Open a signed promise, verifying its signatures
+    pub fn open(note: &str, verifiers: &[&dyn LogVerifier]) -> Result<Self, CheckpointError> {
+        let (text, _) = open_note(note, verifiers)?;
+        Self::parse(text)
+    }
+}
+
+/// A log accepting records immediately with a signed promise, and sequencing them into the tree later
+pub struct PromisingLog<'a, T, TL, S>
+where
+    T: Serialize + Deserialize<'a>,
+    TL: TransparentLog<'a, T>,
+    S: LogSigner,
+{
+    log: TL,
+    origin: String,
+    signer: S,
+    max_merge_delay: u64,
+    pending: Vec<(T, Promise)>,
+    _marker: PhantomData<&'a T>,
+}
+
+impl<'a, T, TL, S> PromisingLog<'a, T, TL, S>
+where
+    T: Serialize + Deserialize<'a>,
+    TL: TransparentLog<'a, T>,
+    S: LogSigner,
+{
+    /// Wrap a log, signing promises for the given origin
+    pub fn new(log: TL, origin: &str, signer: S) -> Self {
+        Self {
+            log,
+            origin: origin.to_owned(),
+            signer,
+            max_merge_delay: DEFAULT_MAX_MERGE_DELAY,
+            pending: vec![],
+            _marker: PhantomData,
+        }
+    }
+
+    /// Set the maximum merge delay promised, in milliseconds
+    pub fn max_merge_delay(&mut self, max_merge_delay: u64) -> &mut Self {
+        self.max_merge_delay = max_merge_delay;
+        self
+    }
+
+    /// Queue a record, returning the signed promise to include it
+    pub fn submit(&mut self, record: T) -> anyhow::Result<String> {
+        let promise = Promise::new(
+            &self.origin,
+            &(self.log.leaf_hasher())(&record)?,
+            self.max_merge_delay,
+        );
+        let note = promise.sign(&[&self.signer])?;
+        self.pending.push((record, promise));
+        Ok(note)
+    }
+
+    /// The number of records waiting to be sequenced
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Append all the queued records to the log, in submission order
+    /// On failure, the records not appended yet stay queued
+    pub fn sequence(&mut self) -> anyhow::Result<Vec<Record<TL::LogSize>>>
+    where
+        T: Clone,
+    {
+        let mut records = Vec::with_capacity(self.pending.len());
+        let mut result = Ok(());
+        for (record, _) in self.pending.iter() {
+            match self.log.append(record.clone()) {
+                Ok(r) => records.push(r),
+                Err(e) => {
+                    result = Err(e);
+                    break;
+                }
+            }
+        }
+        self.pending.drain(..records.len());
+        result.map(|_| records)
+    }
+
+    /// Exchange a promise for the record reference in the log, if it has been sequenced
+    pub fn redeem(&self, promise: &Promise) -> anyhow::Result<Option<Record<TL::LogSize>>> {
+        match self.log.index_of(&promise.hash)? {
+            Some(id) => Ok(Some(Record {
+                id,
+                hash: promise.hash.clone(),
+                meta: self.log.get_meta(id)?,
+            })),
+            None => Ok(None),
+        }
+    }
+
+    /// Get a record from the underlying log
+    pub fn get(&self, index: TL::LogSize) -> anyhow::Result<Option<MaybeOwned<'_, T>>> {
+        self.log.get(index)
+    }
+
+    /// The underlying log
+    pub fn log(&self) -> &TL {
+        &self.log
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::test_helpers::*;
+    use crate::*;
+
+    #[test]
+    fn promise_text() -> anyhow::Result<()> {
+        let promise = Promise::new("log", &hash(&LogRecord::new("rec0"))?, 1000);
+        assert_eq!(promise.timestamp + 1000, promise.deadline());
+        assert_eq!(promise, Promise::parse(&promise.text())?);
+        let checkpoint = Checkpoint::new(
+            "log",
+            LogTree {
+                size: 1_usize,
+                hash: promise.hash.clone(),
+                origin: None,
+            },
+        );
+        assert!(matches!(
+            Promise::parse(&checkpoint.text()?),
+            Err(CheckpointError::MalformedPromise(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn promise_redeem() -> anyhow::Result<()> {
+        let signer = Ed25519Signer::new("example.com/log", &[3_u8; 32]);
+        let verifier = signer.verifier();
+        let ml: InMemoryLog<LogRecord> = InMemoryLog::default();
+        let mut client = InMemoryLogClientBuilder::new(&ml)?.build();
+        let mut pl = PromisingLog::new(ml, "example.com/log", signer);
+        pl.max_merge_delay(60_000);
+
+        let notes = (0..3)
+            .map(|i| pl.submit(LogRecord::new(&format!("rec{}", i))))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        assert_eq!(3, pl.pending());
+        assert_eq!(0, pl.log().size()?);
+        let promises = notes
+            .iter()
+            .map(|n| Promise::open(n, &[&verifier]))
+            .collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(hash(&LogRecord::new("rec1"))?, promises[1].hash);
+        assert_eq!(60_000, promises[1].max_merge_delay);
+        assert_eq!(None, pl.redeem(&promises[1])?);
+
+        let records = pl.sequence()?;
+        assert_eq!(3, records.len());
+        assert_eq!(0, pl.pending());
+        let record = pl.redeem(&promises[1])?.expect("sequenced record");
+        assert_eq!(records[1], record);
+        assert!(record.meta.expect("no metadata").timestamp <= promises[1].deadline());
+        assert!(check_record(&mut client, pl.log(), &record)?);
+        assert_eq!("rec1", pl.get(record.id)?.unwrap().text);
+        Ok(())
+    }
+}
@@ -0,0 +1,315 @@
+//! # Raw byte records
+//!
+//! A codec for logs of pre-serialized records: records serializing as bytes (`Vec<u8>`, byte arrays, byte buffers)
+//! are stored as they are, and their leaf hash is the hash of exactly these bytes.
+//! This interoperates with logs defining their leaves as the hashes of raw bytes, like Certificate Transparency.
+use crate::codec::Codec;
+use serde::de::value::SeqDeserializer;
+use serde::de::{DeserializeOwned, Visitor};
+use serde::ser::{Impossible, SerializeSeq, SerializeTuple};
+use serde::{forward_to_deserialize_any, Deserializer, Serialize, Serializer};
+use std::fmt::Display;
+use thiserror::Error;
+use transparentlog_verify::sha256_hex;
+
+/// Specific Errors for raw byte records
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum RawError {
+    #[error("raw records must serialize as bytes, not as {0}")]
+    NotBytes(&'static str),
+    #[error("{0}")]
+    Custom(String),
+}
+
+impl serde::ser::Error for RawError {
+    fn custom<M: Display>(msg: M) -> Self {
+        RawError::Custom(msg.to_string())
+    }
+}
+
+impl serde::de::Error for RawError {
+    fn custom<M: Display>(msg: M) -> Self {
+        RawError::Custom(msg.to_string())
+    }
+}
+
+/// Raw bytes, the record being its own serialization
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Raw;
+
+impl Codec for Raw {
+    const NAME: &'static str = "raw";
+
+    fn encode<T: Serialize + ?Sized>(value: &T) -> anyhow::Result<Vec<u8>> {
+        let mut data = vec![];
+        value.serialize(BytesSerializer { data: &mut data })?;
+        Ok(data)
+    }
+
+    fn decode<T: DeserializeOwned>(data: &[u8]) -> anyhow::Result<T> {
+        Ok(T::deserialize(BytesDeserializer { data })?)
+    }
+}
+
+/// Hash exactly the given bytes, the leaf hash of a raw record
+pub fn hash_bytes(bytes: &[u8]) -> String {
+    sha256_hex(bytes)
+}
+
+/// Serializer accepting bytes, or sequences and tuples of u8
+struct BytesSerializer<'b> {
+    data: &'b mut Vec<u8>,
+}
+
+/// Reject the serialization of anything but bytes
+macro_rules! reject {
+    ($($method:ident($($ty:ty),*) -> $ok:ty: $what:literal,)*) => {
+        $(fn $method(self, $(_: $ty),*) -> Result<$ok, RawError> {
+            Err(RawError::NotBytes($what))
+        })*
+    };
+}
+
+impl<'b> Serializer for BytesSerializer<'b> {
+    type Ok = ();
+    type Error = RawError;
+    type SerializeSeq = Self;
+    type SerializeTuple = Self;
+    type SerializeTupleStruct = Impossible<(), RawError>;
+    type SerializeTupleVariant = Impossible<(), RawError>;
+    type SerializeMap = Impossible<(), RawError>;
+    type SerializeStruct = Impossible<(), RawError>;
+    type SerializeStructVariant = Impossible<(), RawError>;
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<(), RawError> {
+        self.data.extend_from_slice(v);
+        Ok(())
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<Self, RawError> {
+        self.data.reserve(len.unwrap_or_default());
+        Ok(self)
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<Self, RawError> {
+        self.data.reserve(len);
+        Ok(self)
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<(), RawError> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<(), RawError> {
+        Err(RawError::NotBytes("enum"))
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, _value: &T) -> Result<(), RawError> {
+        Err(RawError::NotBytes("option"))
+    }
+
+    reject! {
+        serialize_bool(bool) -> (): "bool",
+        serialize_i8(i8) -> (): "integer",
+        serialize_i16(i16) -> (): "integer",
+        serialize_i32(i32) -> (): "integer",
+        serialize_i64(i64) -> (): "integer",
+        serialize_u8(u8) -> (): "integer",
+        serialize_u16(u16) -> (): "integer",
+        serialize_u32(u32) -> (): "integer",
+        serialize_u64(u64) -> (): "integer",
+        serialize_f32(f32) -> (): "float",
+        serialize_f64(f64) -> (): "float",
+        serialize_char(char) -> (): "char",
+        serialize_str(&str) -> (): "string",
+        serialize_none() -> (): "option",
+        serialize_unit() -> (): "unit",
+        serialize_unit_struct(&'static str) -> (): "unit",
+        serialize_unit_variant(&'static str, u32, &'static str) -> (): "enum",
+        serialize_tuple_struct(&'static str, usize) -> Self::SerializeTupleStruct: "tuple struct",
+        serialize_tuple_variant(&'static str, u32, &'static str, usize) -> Self::SerializeTupleVariant: "enum",
+        serialize_map(Option<usize>) -> Self::SerializeMap: "map",
+        serialize_struct(&'static str, usize) -> Self::SerializeStruct: "struct",
+        serialize_struct_variant(&'static str, u32, &'static str, usize) -> Self::SerializeStructVariant: "enum",
+    }
+}
+
+impl<'b> SerializeSeq for BytesSerializer<'b> {
+    type Ok = ();
+    type Error = RawError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), RawError> {
+        value.serialize(ByteSerializer { data: self.data })
+    }
+
+    fn end(self) -> Result<(), RawError> {
+        Ok(())
+    }
+}
+
+impl<'b> SerializeTuple for BytesSerializer<'b> {
+    type Ok = ();
+    type Error = RawError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), RawError> {
+        value.serialize(ByteSerializer { data: self.data })
+    }
+
+    fn end(self) -> Result<(), RawError> {
+        Ok(())
+    }
+}
+
+/// Serializer of one element of a byte sequence, accepting only u8
+struct ByteSerializer<'b> {
+    data: &'b mut Vec<u8>,
+}
+
+impl<'b> Serializer for ByteSerializer<'b> {
+    type Ok = ();
+    type Error = RawError;
+    type SerializeSeq = Impossible<(), RawError>;
+    type SerializeTuple = Impossible<(), RawError>;
+    type SerializeTupleStruct = Impossible<(), RawError>;
+    type SerializeTupleVariant = Impossible<(), RawError>;
+    type SerializeMap = Impossible<(), RawError>;
+    type SerializeStruct = Impossible<(), RawError>;
+    type SerializeStructVariant = Impossible<(), RawError>;
+
+    fn serialize_u8(self, v: u8) -> Result<(), RawError> {
+        self.data.push(v);
+        Ok(())
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<(), RawError> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<(), RawError> {
+        Err(RawError::NotBytes("enum"))
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, _value: &T) -> Result<(), RawError> {
+        Err(RawError::NotBytes("option"))
+    }
+
+    reject! {
+        serialize_bool(bool) -> (): "bool",
+        serialize_i8(i8) -> (): "integer",
+        serialize_i16(i16) -> (): "integer",
+        serialize_i32(i32) -> (): "integer",
+        serialize_i64(i64) -> (): "integer",
+        serialize_u16(u16) -> (): "integer",
+        serialize_u32(u32) -> (): "integer",
+        serialize_u64(u64) -> (): "integer",
+        serialize_f32(f32) -> (): "float",
+        serialize_f64(f64) -> (): "float",
+        serialize_char(char) -> (): "char",
+        serialize_str(&str) -> (): "string",
+        serialize_bytes(&[u8]) -> (): "nested bytes",
+        serialize_none() -> (): "option",
+        serialize_unit() -> (): "unit",
+        serialize_unit_struct(&'static str) -> (): "unit",
+        serialize_unit_variant(&'static str, u32, &'static str) -> (): "enum",
+        serialize_seq(Option<usize>) -> Self::SerializeSeq: "nested sequence",
+        serialize_tuple(usize) -> Self::SerializeTuple: "nested tuple",
+        serialize_tuple_struct(&'static str, usize) -> Self::SerializeTupleStruct: "tuple struct",
+        serialize_tuple_variant(&'static str, u32, &'static str, usize) -> Self::SerializeTupleVariant: "enum",
+        serialize_map(Option<usize>) -> Self::SerializeMap: "map",
+        serialize_struct(&'static str, usize) -> Self::SerializeStruct: "struct",
+        serialize_struct_variant(&'static str, u32, &'static str, usize) -> Self::SerializeStructVariant: "enum",
+    }
+}
+
+/// Deserializer giving the bytes to byte buffers, and as a sequence of u8 to anything else
+struct BytesDeserializer<'b> {
+    data: &'b [u8],
+}
+
+impl<'de, 'b> Deserializer<'de> for BytesDeserializer<'b> {
+    type Error = RawError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, RawError> {
+        let mut seq = SeqDeserializer::new(self.data.iter().copied());
+        let value = visitor.visit_seq(&mut seq)?;
+        seq.end()?;
+        Ok(value)
+    }
+
+    fn deserialize_bytes<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, RawError> {
+        visitor.visit_bytes(self.data)
+    }
+
+    fn deserialize_byte_buf<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, RawError> {
+        visitor.visit_byte_buf(self.data.to_vec())
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, RawError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        option unit unit_struct seq tuple tuple_struct map struct enum identifier ignored_any
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::test_helpers::*;
+    use crate::*;
+
+    #[test]
+    fn raw_round_trip() -> anyhow::Result<()> {
+        let bytes = b"\x00\x01raw record\xff".to_vec();
+        assert_eq!(bytes, Raw::encode(&bytes)?);
+        assert_eq!(bytes, Raw::encode(bytes.as_slice())?);
+        assert_eq!(bytes, Raw::decode::<Vec<u8>>(&bytes)?);
+        assert_eq!(vec![1, 2, 3, 4], Raw::encode(&[1_u8, 2, 3, 4])?);
+        assert_eq!([1_u8, 2, 3, 4], Raw::decode::<[u8; 4]>(&[1, 2, 3, 4])?);
+        assert!(Raw::decode::<[u8; 4]>(&[1, 2, 3]).is_err());
+        assert!(Raw::decode::<[u8; 4]>(&[1, 2, 3, 4, 5]).is_err());
+        assert!(Raw::decode::<Vec<u8>>(&[]).unwrap().is_empty());
+        assert_eq!(hash_bytes(&bytes), hash_with::<Raw, _>(&bytes)?);
+        assert_ne!(hash_bytes(&bytes), hash(&bytes)?);
+        Ok(())
+    }
+
+    #[test]
+    fn raw_not_bytes() {
+        let err = Raw::encode(&LogRecord::new("rec0")).unwrap_err();
+        assert_eq!(
+            Some(&RawError::NotBytes("struct")),
+            err.downcast_ref::<RawError>()
+        );
+        assert!(Raw::encode("text").is_err());
+        assert!(Raw::encode(&vec![256_u16]).is_err());
+        assert!(Raw::encode(&vec![vec![1_u8]]).is_err());
+        assert!(Raw::decode::<LogRecord>(b"rec0").is_err());
+    }
+}
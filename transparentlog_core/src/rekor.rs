@@ -0,0 +1,460 @@
+//! # Rekor formats
+//!
+//! The inclusion proofs and entries of Rekor, the sigstore transparency log, in their JSON format:
+//! the index of the leaf, the size and root hash of the tree, the audit path from the leaf up,
+//! and the checkpoint of the tree, a note in the same format as `Checkpoint`.
+//! Rekor hashes its trees as RFC 6962 does, prefixing leaves with 0 and nodes with 1, so its proofs are checked
+//! with `verify_inclusion`. Proofs exported from the logs of this crate have the same shape but keep the hashes
+//! of this crate, so they are checked with `verify_log_inclusion`, not with RFC 6962 tooling.
+//!
+//! See <https://github.com/sigstore/rekor>
+use crate::base::*;
+use crate::bundle::{Bundle, BundleError};
+use crate::checkpoint::{Checkpoint, LogVerifier};
+use crate::dsse::base64_bytes;
+use crate::verify::VerifyError;
+use num::Integer;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fmt::Display;
+use std::hash::Hash;
+use std::str::FromStr;
+use thiserror::Error;
+
+/// Specific Errors for Rekor proofs and entries
+#[derive(Debug, Clone, Error)]
+pub enum RekorError {
+    #[error("Proof tree does not match its signed checkpoint")]
+    CheckpointMismatch,
+    #[error("Entry has no inclusion proof")]
+    MissingProof,
+}
+
+/// An inclusion proof, serialized with the field names of Rekor
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct RekorInclusionProof<LogSize> {
+    pub log_index: LogSize,
+    pub root_hash: String,
+    pub tree_size: LogSize,
+    /// The hex encoded hashes of the siblings of the leaf and its ancestors, from the leaf up
+    pub hashes: Vec<String>,
+    pub checkpoint: String,
+}
+
+/// The verification material of an entry
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct RekorVerification<LogSize> {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub inclusion_proof: Option<RekorInclusionProof<LogSize>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signed_entry_timestamp: Option<String>,
+}
+
+/// An entry of a Rekor log, its body being the leaf data
+/// The log index of an entry spans all the shards of the log, the one of its inclusion proof is in its shard
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct RekorEntry<LogSize> {
+    #[serde(with = "base64_bytes")]
+    pub body: Vec<u8>,
+    pub integrated_time: u64,
+    #[serde(rename = "logID")]
+    pub log_id: String,
+    pub log_index: LogSize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub verification: Option<RekorVerification<LogSize>>,
+}
+
+/// The RFC 6962 hash of leaf data
+pub fn rfc6962_leaf_hash(data: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update([0]);
+    hasher.update(data);
+    hex::encode(hasher.finalize())
+}
+
+/// The RFC 6962 hash of a node from the hex encoded hashes of its children
+fn rfc6962_node_hash(left: &str, right: &str) -> Result<String, VerifyError> {
+    let mut hasher = Sha256::new();
+    hasher.update([1]);
+    for h in [left, right] {
+        hasher.update(hex::decode(h).map_err(|_| VerifyError::InvalidProof)?);
+    }
+    Ok(hex::encode(hasher.finalize()))
+}
+
+/// Whether a string is a hex encoded SHA-256 hash
+fn is_hash(h: &str) -> bool {
+    h.len() == 64 && h.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+/// The audit path of a proof in a tree of the given size: the hashes of the siblings of the leaf and its ancestors, from the leaf up
+/// An incomplete right sibling is hashed from the stored subtrees the proof carries for it
+pub fn audit_path<LogSize: Integer + Copy + Hash>(
+    proof: &InclusionProof<LogSize>,
+    size: LogSize,
+) -> Result<Vec<String>, VerifyError> {
+    if proof.record.id >= size {
+        return Err(VerifyError::OutOfTree);
+    }
+    let sizes = tree_sizes(size);
+    let two = LogSize::one() + LogSize::one();
+    let mut index = proof.record.id;
+    let mut path = vec![];
+    for level in 0..sizes.len() - 1 {
+        let sibling = LogTreePosition {
+            level,
+            index: if index.is_odd() {
+                index - LogSize::one()
+            } else {
+                index + LogSize::one()
+            },
+        };
+        // no sibling: the node is promoted to the next level
+        if first_leaf(&sibling) < size {
+            path.push(
+                calc_hash(sibling, &proof.proofs, &sizes)
+                    .map_err(|_| VerifyError::MissingProof(level))?,
+            );
+        }
+        index = index.div_floor(&two);
+    }
+    Ok(path)
+}
+
+/// Verify an audit path with the given node hash, as in RFC 9162
+fn verify_path<LogSize, H>(
+    index: LogSize,
+    size: LogSize,
+    leaf_hash: &str,
+    hashes: &[String],
+    root_hash: &str,
+    node_hash: H,
+) -> Result<(), VerifyError>
+where
+    LogSize: Integer + Copy,
+    H: Fn(&str, &str) -> Result<String, VerifyError>,
+{
+    if index >= size {
+        return Err(VerifyError::OutOfTree);
+    }
+    if !is_hash(leaf_hash) || !is_hash(root_hash) || !hashes.iter().all(|h| is_hash(h)) {
+        return Err(VerifyError::InvalidProof);
+    }
+    let two = LogSize::one() + LogSize::one();
+    let mut node = index;
+    let mut last = size - LogSize::one();
+    let mut hash = leaf_hash.to_owned();
+    for h in hashes {
+        if last.is_zero() {
+            return Err(VerifyError::InvalidProof);
+        }
+        if node.is_odd() || node == last {
+            hash = node_hash(h, &hash)?;
+            while node.is_even() && !node.is_zero() {
+                node = node.div_floor(&two);
+                last = last.div_floor(&two);
+            }
+        } else {
+            hash = node_hash(&hash, h)?;
+        }
+        node = node.div_floor(&two);
+        last = last.div_floor(&two);
+    }
+    if last.is_zero() && hash_eq(&hash, root_hash) {
+        Ok(())
+    } else {
+        Err(VerifyError::InvalidProof)
+    }
+}
+
+impl<LogSize: Integer + Copy + Hash> RekorInclusionProof<LogSize> {
+    /// The Rekor proof of a proof of this crate, with the checkpoint of its tree
+    pub fn from_proof(
+        proof: &InclusionProof<LogSize>,
+        tree: &LogTree<LogSize>,
+        checkpoint: String,
+    ) -> Result<Self, VerifyError> {
+        Ok(Self {
+            log_index: proof.record.id,
+            root_hash: tree.hash.clone(),
+            tree_size: tree.size,
+            hashes: audit_path(proof, tree.size)?,
+            checkpoint,
+        })
+    }
+
+    /// Verify that the leaf hash is included in the tree of the proof, hashed as RFC 6962 does
+    pub fn verify_inclusion(&self, leaf_hash: &str) -> Result<(), VerifyError> {
+        verify_path(
+            self.log_index,
+            self.tree_size,
+            leaf_hash,
+            &self.hashes,
+            &self.root_hash,
+            rfc6962_node_hash,
+        )
+    }
+
+    /// Verify that the leaf hash is included in the tree of a proof exported from a log of this crate
+    pub fn verify_log_inclusion(&self, leaf_hash: &str) -> Result<(), VerifyError> {
+        verify_path(
+            self.log_index,
+            self.tree_size,
+            leaf_hash,
+            &self.hashes,
+            &self.root_hash,
+            |left, right| Ok(hash_pair(left, right)),
+        )
+    }
+}
+
+impl<LogSize: Integer + Copy + Display + FromStr> RekorInclusionProof<LogSize> {
+    /// Open the checkpoint of the proof, signed by one of the verifiers, checking it is for the tree of the proof
+    pub fn open_checkpoint(
+        &self,
+        verifiers: &[&dyn LogVerifier],
+    ) -> anyhow::Result<Checkpoint<LogSize>> {
+        let checkpoint = Checkpoint::open(&self.checkpoint, verifiers)?;
+        if checkpoint.tree.size != self.tree_size
+            || !checkpoint.tree.hash.eq_ignore_ascii_case(&self.root_hash)
+        {
+            return Err(RekorError::CheckpointMismatch.into());
+        }
+        Ok(checkpoint)
+    }
+}
+
+impl<LogSize: Integer + Copy + Hash + Display + FromStr> RekorEntry<LogSize> {
+    /// The leaf hash of the entry
+    pub fn leaf_hash(&self) -> String {
+        rfc6962_leaf_hash(&self.body)
+    }
+
+    /// Verify that the entry is included in the tree of a checkpoint signed by one of the verifiers, returning that checkpoint
+    pub fn verify(&self, verifiers: &[&dyn LogVerifier]) -> anyhow::Result<Checkpoint<LogSize>> {
+        let proof = self
+            .verification
+            .as_ref()
+            .and_then(|v| v.inclusion_proof.as_ref())
+            .ok_or(RekorError::MissingProof)?;
+        let checkpoint = proof.open_checkpoint(verifiers)?;
+        proof.verify_inclusion(&self.leaf_hash())?;
+        Ok(checkpoint)
+    }
+}
+
+impl<LogSize> Bundle<LogSize>
+where
+    LogSize: Integer + Copy + Hash + Display + FromStr + Serialize + DeserializeOwned,
+{
+    /// The Rekor proof of the bundle, which must have a signed checkpoint
+    pub fn rekor_proof(&self) -> anyhow::Result<RekorInclusionProof<LogSize>> {
+        let checkpoint = self.checkpoint.clone().ok_or(BundleError::Unsigned)?;
+        Ok(RekorInclusionProof::from_proof(
+            &self.proof,
+            &self.tree,
+            checkpoint,
+        )?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::test_helpers::*;
+    use crate::*;
+
+    /// The RFC 6962 root of the given leaves
+    fn mth(leaves: &[Vec<u8>]) -> String {
+        if leaves.len() == 1 {
+            return rfc6962_leaf_hash(&leaves[0]);
+        }
+        let k = leaves.len().next_power_of_two() / 2;
+        node(&mth(&leaves[..k]), &mth(&leaves[k..]))
+    }
+
+    fn node(left: &str, right: &str) -> String {
+        let mut data = vec![1];
+        data.extend(hex::decode(left).unwrap());
+        data.extend(hex::decode(right).unwrap());
+        hash_bytes(&data)
+    }
+
+    /// The RFC 6962 audit path of a leaf, from the leaf up
+    fn path(index: usize, leaves: &[Vec<u8>]) -> Vec<String> {
+        if leaves.len() == 1 {
+            return vec![];
+        }
+        let k = leaves.len().next_power_of_two() / 2;
+        if index < k {
+            let mut p = path(index, &leaves[..k]);
+            p.push(mth(&leaves[k..]));
+            p
+        } else {
+            let mut p = path(index - k, &leaves[k..]);
+            p.push(mth(&leaves[..k]));
+            p
+        }
+    }
+
+    /// The leaves of the test vectors of RFC 6962 implementations
+    fn leaves() -> Vec<Vec<u8>> {
+        [
+            "",
+            "00",
+            "10",
+            "2021",
+            "3031",
+            "40414243",
+            "5051525354555657",
+            "606162636465666768696a6b6c6d6e6f",
+        ]
+        .iter()
+        .map(|l| hex::decode(l).unwrap())
+        .collect()
+    }
+
+    #[test]
+    fn rekor_rfc6962() -> anyhow::Result<()> {
+        let leaves = leaves();
+        assert_eq!(
+            "6e340b9cffb37a989ca544e6bb780a2c78901d3fb33738768511a30617afa01d",
+            rfc6962_leaf_hash(&leaves[0])
+        );
+        assert_eq!(
+            "5dc9da79a70659a9ad559cb701ded9a2ab9d823aad2f4960cfe370eff4604328",
+            mth(&leaves)
+        );
+        for size in 1..=leaves.len() {
+            for index in 0..size {
+                let proof = RekorInclusionProof {
+                    log_index: index,
+                    root_hash: mth(&leaves[..size]),
+                    tree_size: size,
+                    hashes: path(index, &leaves[..size]),
+                    checkpoint: String::new(),
+                };
+                let leaf = rfc6962_leaf_hash(&leaves[index]);
+                proof.verify_inclusion(&leaf)?;
+                // a single leaf is its own root, whatever the node hash
+                assert_eq!(size == 1, proof.verify_log_inclusion(&leaf).is_ok());
+                let other = rfc6962_leaf_hash(&leaves[(index + 1) % leaves.len()]);
+                assert!(proof.verify_inclusion(&other).is_err());
+
+                let mut longer = proof.clone();
+                longer.hashes.push(leaf.clone());
+                assert!(longer.verify_inclusion(&leaf).is_err());
+                let mut moved = proof.clone();
+                moved.log_index = size;
+                assert!(matches!(
+                    moved.verify_inclusion(&leaf),
+                    Err(VerifyError::OutOfTree)
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn rekor_entry() -> anyhow::Result<()> {
+        let signer = Ed25519Signer::new("rekor.example.com", &[5_u8; 32]);
+        let leaves = leaves();
+        let root = mth(&leaves[..7]);
+        let text = format!(
+            "rekor.example.com - 42\n7\n{}\nTimestamp: 1679349379012118479\n",
+            base64::encode(hex::decode(&root)?)
+        );
+        let json = format!(
+            "{{\"body\":\"{}\",\"integratedTime\":1679349379,\"logID\":\"c0d23d6ad406973f9559f3ba2d1ca01f84147d8ffc5b8445c224f98b9591801d\",\
+             \"logIndex\":1000005,\"verification\":{{\"inclusionProof\":{{\"logIndex\":5,\"rootHash\":\"{}\",\"treeSize\":7,\
+             \"hashes\":{},\"checkpoint\":{}}},\"signedEntryTimestamp\":\"MEUCIQ==\"}}}}",
+            base64::encode(&leaves[5]),
+            root,
+            serde_json::to_string(&path(5, &leaves[..7]))?,
+            serde_json::to_string(&sign_note(&text, &[&signer])?)?
+        );
+        let entry: RekorEntry<u64> = serde_json::from_str(&json)?;
+        assert_eq!(leaves[5], entry.body);
+        assert_eq!(1000005, entry.log_index);
+        let checkpoint = entry.verify(&[&signer.verifier()])?;
+        assert_eq!(7, checkpoint.tree.size);
+        assert_eq!(
+            entry,
+            serde_json::from_str(&serde_json::to_string(&entry)?)?
+        );
+
+        let other = Ed25519Signer::new("rekor.example.com", &[6_u8; 32]);
+        assert!(entry.verify(&[&other.verifier()]).is_err());
+        let mut tampered = entry.clone();
+        tampered.body = leaves[4].clone();
+        assert!(tampered.verify(&[&signer.verifier()]).is_err());
+        let mut resized = entry.clone();
+        if let Some(p) = resized
+            .verification
+            .as_mut()
+            .and_then(|v| v.inclusion_proof.as_mut())
+        {
+            p.tree_size = 8;
+        }
+        let err = resized.verify(&[&signer.verifier()]).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<RekorError>(),
+            Some(RekorError::CheckpointMismatch)
+        ));
+        let unproven = RekorEntry {
+            verification: None,
+            ..entry
+        };
+        let err = unproven.verify(&[&signer.verifier()]).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<RekorError>(),
+            Some(RekorError::MissingProof)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn rekor_export() -> anyhow::Result<()> {
+        let signer = Ed25519Signer::new("example.com/log", &[7_u8; 32]);
+        let mut ml: InMemoryLog<LogRecord> = InMemoryLog::default();
+        for size in 1..=13 {
+            ml.append(LogRecord::new(&format!("rec{}", size - 1)))?;
+            for id in 0..size {
+                let bundle = Bundle::prove(&ml, id, "example.com/log", &[&signer])?;
+                let proof = bundle.rekor_proof()?;
+                assert_eq!(id, proof.log_index);
+                assert_eq!(size, proof.tree_size);
+                let leaf = &bundle.proof.record.hash;
+                proof.verify_log_inclusion(leaf)?;
+                assert_eq!(size == 1, proof.verify_inclusion(leaf).is_ok());
+                assert_eq!(ml.latest()?.hash, proof.root_hash);
+                assert!(proof
+                    .verify_log_inclusion(&hash(&LogRecord::new("other"))?)
+                    .is_err());
+            }
+        }
+
+        let bundle = Bundle::prove(&ml, 9, "example.com/log", &[&signer])?;
+        let proof = bundle.rekor_proof()?;
+        proof.open_checkpoint(&[&signer.verifier()])?;
+        let json = serde_json::to_string(&proof)?;
+        assert!(json.starts_with(&format!(
+            "{{\"logIndex\":9,\"rootHash\":\"{}\",\"treeSize\":13,\"hashes\":[",
+            proof.root_hash
+        )));
+        assert_eq!(proof, serde_json::from_str(&json)?);
+
+        let unsigned = Bundle::prove(&ml, 9, "example.com/log", &[])?;
+        assert!(unsigned.rekor_proof().is_err());
+        let mut missing = bundle.proof.clone();
+        missing.proofs.clear();
+        assert!(matches!(
+            audit_path(&missing, 13),
+            Err(VerifyError::MissingProof(0))
+        ));
+        Ok(())
+    }
+}
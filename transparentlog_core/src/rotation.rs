@@ -0,0 +1,226 @@
+//! # Key rotation
+//!
+//! A long-lived log rotates the keys signing its checkpoints with a `KeyRing`. A new key is added next to the current
+//! one, and both sign the checkpoints published during the transition window, so clients trusting either key accept
+//! them. Once clients trust the new key, the old one is retired: it stops signing, but still opens the checkpoints it
+//! signed. A compromised key is revoked instead, and no longer trusted at all.
+//! Signatures carry the hash of their key, so several keys of the same name can be trusted at once.
+use crate::checkpoint::*;
+use num::Zero;
+use std::fmt::Display;
+use std::str::FromStr;
+use thiserror::Error;
+
+/// Specific Errors for key rings
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum RotationError {
+    #[error("No key with hash {0:08x} in the ring")]
+    UnknownKey(u32),
+    #[error("Key with hash {0:08x} already in the ring")]
+    DuplicateKey(u32),
+    #[error("Key with hash {0:08x} is the last one signing")]
+    LastKey(u32),
+}
+
+/// The state of a key in a ring
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyState {
+    /// The key signs the checkpoints
+    Active,
+    /// The key does not sign anymore, but the checkpoints it signed are still trusted
+    Retired,
+}
+
+/// A key of a ring, with the verifier of its signatures
+struct RingKey {
+    signer: Box<dyn LogSigner + Send + Sync>,
+    verifier: Box<dyn LogVerifier + Send + Sync>,
+    state: KeyState,
+}
+
+/// The keys of a log: the ones signing its checkpoints, and the retired ones still trusted to open older checkpoints
+pub struct KeyRing {
+    keys: Vec<RingKey>,
+}
+
+impl KeyRing {
+    /// A ring with a single active key
+    pub fn new(
+        signer: Box<dyn LogSigner + Send + Sync>,
+        verifier: Box<dyn LogVerifier + Send + Sync>,
+    ) -> Self {
+        Self {
+            keys: vec![RingKey {
+                signer,
+                verifier,
+                state: KeyState::Active,
+            }],
+        }
+    }
+
+    /// Start a rotation: the new key signs along the active ones, until they are retired
+    pub fn add(
+        &mut self,
+        signer: Box<dyn LogSigner + Send + Sync>,
+        verifier: Box<dyn LogVerifier + Send + Sync>,
+    ) -> Result<(), RotationError> {
+        let key_hash = signer.key_hash();
+        if self.keys.iter().any(|k| k.signer.key_hash() == key_hash) {
+            return Err(RotationError::DuplicateKey(key_hash));
+        }
+        self.keys.push(RingKey {
+            signer,
+            verifier,
+            state: KeyState::Active,
+        });
+        Ok(())
+    }
+
+    /// End a rotation: the key with the given hash stops signing, but still opens the checkpoints it signed
+    /// Fails if it is the last active key, checkpoints must always be signed
+    pub fn retire(&mut self, key_hash: u32) -> Result<(), RotationError> {
+        let ix = self.active_index(key_hash)?;
+        self.keys[ix].state = KeyState::Retired;
+        Ok(())
+    }
+
+    /// Forget the key with the given hash, a compromised one: the checkpoints it signed are no longer trusted
+    /// Fails if it is the last active key
+    pub fn revoke(&mut self, key_hash: u32) -> Result<(), RotationError> {
+        let ix = match self.state(key_hash) {
+            Some(KeyState::Active) => self.active_index(key_hash)?,
+            Some(KeyState::Retired) => self.index(key_hash)?,
+            None => return Err(RotationError::UnknownKey(key_hash)),
+        };
+        self.keys.remove(ix);
+        Ok(())
+    }
+
+    /// The state of the key with the given hash, none if it is not in the ring
+    pub fn state(&self, key_hash: u32) -> Option<KeyState> {
+        self.index(key_hash).ok().map(|ix| self.keys[ix].state)
+    }
+
+    /// Whether several keys sign, during a rotation
+    pub fn in_transition(&self) -> bool {
+        self.signers().len() > 1
+    }
+
+    /// The signers of the active keys
+    pub fn signers(&self) -> Vec<&dyn LogSigner> {
+        self.keys
+            .iter()
+            .filter(|k| k.state == KeyState::Active)
+            .map(|k| k.signer.as_ref() as &dyn LogSigner)
+            .collect()
+    }
+
+    /// The verifiers of all the keys, active or retired
+    pub fn verifiers(&self) -> Vec<&dyn LogVerifier> {
+        self.keys
+            .iter()
+            .map(|k| k.verifier.as_ref() as &dyn LogVerifier)
+            .collect()
+    }
+
+    /// Sign the checkpoint with all the active keys, returning the signed note
+    pub fn sign<LogSize: Display + FromStr + Zero>(
+        &self,
+        checkpoint: &Checkpoint<LogSize>,
+    ) -> anyhow::Result<String> {
+        checkpoint.sign(&self.signers())
+    }
+
+    /// Open a checkpoint signed by any key of the ring, active or retired
+    pub fn open<LogSize: Display + FromStr + Zero>(
+        &self,
+        note: &str,
+    ) -> Result<Checkpoint<LogSize>, CheckpointError> {
+        Checkpoint::open(note, &self.verifiers())
+    }
+
+    /// The index of the key with the given hash
+    fn index(&self, key_hash: u32) -> Result<usize, RotationError> {
+        self.keys
+            .iter()
+            .position(|k| k.signer.key_hash() == key_hash)
+            .ok_or(RotationError::UnknownKey(key_hash))
+    }
+
+    /// The index of the active key with the given hash, failing if no other key is active
+    fn active_index(&self, key_hash: u32) -> Result<usize, RotationError> {
+        let ix = self.index(key_hash)?;
+        if self.keys[ix].state == KeyState::Active && !self.in_transition() {
+            return Err(RotationError::LastKey(key_hash));
+        }
+        Ok(ix)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::test_helpers::*;
+    use crate::*;
+
+    fn key(seed: u8) -> (Box<Ed25519Signer>, Box<Ed25519Verifier>) {
+        let signer = Ed25519Signer::new("example.com/log", &[seed; 32]);
+        let verifier = signer.verifier();
+        (Box::new(signer), Box::new(verifier))
+    }
+
+    #[test]
+    fn rotation_dual_signed() -> anyhow::Result<()> {
+        let mut ml: InMemoryLog<LogRecord> = InMemoryLog::default();
+        append_multiple(&mut ml, 5)?;
+        let (old, old_verifier) = key(1);
+        let (new, new_verifier) = key(2);
+        let old_hash = old.key_hash();
+        let new_hash = new.key_hash();
+        assert_ne!(old_hash, new_hash);
+        let mut ring = KeyRing::new(old, old_verifier.clone());
+        assert!(!ring.in_transition());
+        let before = ring.sign(&Checkpoint::new("example.com/log", ml.latest()?))?;
+
+        ring.add(new, new_verifier.clone())?;
+        assert!(ring.in_transition());
+        append_multiple_offset(&mut ml, 5, 3)?;
+        let checkpoint = Checkpoint::new("example.com/log", ml.latest()?);
+        let during = ring.sign(&checkpoint)?;
+        // clients trusting either key accept the checkpoints of the transition window
+        assert_eq!(checkpoint, Checkpoint::open(&during, &[&*old_verifier])?);
+        assert_eq!(checkpoint, Checkpoint::open(&during, &[&*new_verifier])?);
+        let (_, sigs) = open_note(&during, &[&*old_verifier, &*new_verifier])?;
+        assert_eq!(
+            vec![old_hash, new_hash],
+            sigs.iter().map(|s| s.key_hash).collect::<Vec<_>>()
+        );
+
+        ring.retire(old_hash)?;
+        assert_eq!(Some(KeyState::Retired), ring.state(old_hash));
+        assert_eq!(Err(RotationError::LastKey(new_hash)), ring.retire(new_hash));
+        let after = ring.sign(&checkpoint)?;
+        assert!(matches!(
+            Checkpoint::<usize>::open(&after, &[&*old_verifier]),
+            Err(CheckpointError::UnverifiedNote)
+        ));
+        assert_eq!(checkpoint, ring.open(&after)?);
+        assert_eq!(5, ring.open::<usize>(&before)?.tree.size);
+
+        // a revoked key opens nothing anymore
+        ring.revoke(old_hash)?;
+        assert_eq!(None, ring.state(old_hash));
+        assert!(ring.open::<usize>(&before).is_err());
+        assert_eq!(
+            Err(RotationError::UnknownKey(old_hash)),
+            ring.revoke(old_hash)
+        );
+        assert_eq!(Err(RotationError::LastKey(new_hash)), ring.revoke(new_hash));
+        let (again, again_verifier) = key(2);
+        assert_eq!(
+            Err(RotationError::DuplicateKey(new_hash)),
+            ring.add(again, again_verifier)
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,767 @@
+//! # Segmented file log
+//!
+//! A file based log storing records, hashes, metadata and annotations as frames appended to numbered segment files.
+//! Each frame carries its kind, its length and a CRC32 of its kind and content, so a torn tail left by a crash
+//! is detected and truncated when the log is opened, and records can be scanned sequentially without any index.
+//! A segment is closed once it reaches the configured size and is never written again, so it can be backed up as is.
+use std::marker::PhantomData;
+
+pub use crate::base::*;
+use crate::cache::RootCache;
+use crate::instrument::{proofs_span, record_append, record_proofs, time_read};
+use maybe_owned::MaybeOwned;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::fs::{File, OpenOptions};
+use std::io::prelude::*;
+use std::io::{BufReader, SeekFrom};
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+/// The default size after which a segment is closed: 64 MiB
+pub const DEFAULT_SEGMENT_SIZE: u64 = 64 * 1024 * 1024;
+
+/// The size of a frame header: the kind, the content length and the CRC32
+const HEADER_SIZE: usize = 1 + 2 * std::mem::size_of::<u32>();
+
+/// The size of a hash
+const HASH_SIZE_IN_BYTES: usize = 64;
+
+/// The size of the content of a hash frame: the level and the hash
+const HASH_FRAME_SIZE: usize = std::mem::size_of::<u32>() + HASH_SIZE_IN_BYTES;
+
+/// Frame kind of a serialized record
+const RECORD: u8 = 1;
+/// Frame kind of a hash: its level then the hash
+const HASH: u8 = 2;
+/// Frame kind of the metadata of a record: its index then its timestamp
+const META: u8 = 3;
+/// Frame kind of the annotation of a record: its index then the annotation, replacing any previous one
+const ANNOTATION: u8 = 4;
+
+/// Errors reading segments
+#[derive(Error, Debug)]
+pub enum SegmentError {
+    #[error("corrupted frame in segment {0} at offset {1}")]
+    Corrupted(usize, u64),
+    #[error("invalid frame of kind {2} in segment {0} at offset {1}")]
+    InvalidFrame(usize, u64, u8),
+}
+
+/// The position of a frame in the segments
+#[derive(Debug, Clone, Copy)]
+struct FramePosition {
+    segment: usize,
+    offset: u64,
+    length: usize,
+}
+
+/// A frame read from a segment
+struct Frame<'b> {
+    offset: usize,
+    kind: u8,
+    content: &'b [u8],
+}
+
+/// A transparent log stored in segment files of checksummed frames
+/// The positions of the frames are kept in memory, and rebuilt by scanning the segments on open
+#[derive(Debug)]
+pub struct SegmentLog<'a, T: Serialize + Deserialize<'a>> {
+    dir: &'a Path,
+    segment_size: u64,
+    segments: RefCell<Vec<File>>,
+    tail: u64,
+    records: Vec<FramePosition>,
+    hashes: Vec<Vec<FramePosition>>,
+    lookup: HashIndex<u64>,
+    metas: HashMap<u64, RecordMeta>,
+    annotations: HashMap<u64, FramePosition>,
+    root: RootCache<u64>,
+    pending_hashes: PendingHashes<u64>,
+    _marker: PhantomData<T>,
+}
+
+impl<'a, T: Serialize + Deserialize<'a>> SegmentLog<'a, T> {
+    /// Open a new or existing log from the given directory, closing segments at the default size
+    pub fn open<P: AsRef<Path>>(dir: &'a P) -> anyhow::Result<Self> {
+        Self::open_with_segment_size(dir, DEFAULT_SEGMENT_SIZE)
+    }
+
+    /// Open a new or existing log from the given directory, closing segments once they reach the given size in bytes
+    /// A torn or corrupted tail in the last segment is truncated back to the end of the last complete append
+    pub fn open_with_segment_size<P: AsRef<Path>>(
+        dir: &'a P,
+        segment_size: u64,
+    ) -> anyhow::Result<Self> {
+        let dir = dir.as_ref();
+        let mut log = Self {
+            dir,
+            segment_size,
+            segments: RefCell::new(vec![]),
+            tail: 0,
+            records: vec![],
+            hashes: vec![],
+            lookup: HashIndex::default(),
+            metas: HashMap::new(),
+            annotations: HashMap::new(),
+            root: RootCache::default(),
+            pending_hashes: PendingHashes::default(),
+            _marker: PhantomData,
+        };
+        let mut ix = 0;
+        let mut p = segment_path(dir, ix);
+        while p.exists() {
+            let mut file = OpenOptions::new().read(true).append(true).open(&p)?;
+            let mut b = vec![];
+            file.read_to_end(&mut b)?;
+            let frames = parse_frames(&b);
+            let end = frames
+                .last()
+                .map_or(0, |f| f.offset + HEADER_SIZE + f.content.len());
+            ix += 1;
+            p = segment_path(dir, ix);
+            let keep = if p.exists() {
+                // closed segments are never written after they end on a complete append
+                if end < b.len() {
+                    return Err(SegmentError::Corrupted(ix - 1, end as u64).into());
+                }
+                frames.len()
+            } else {
+                log.complete_frames(&frames)
+            };
+            let tail = frames.get(keep).map_or(end, |f| f.offset);
+            if tail < b.len() {
+                file.set_len(tail as u64)?;
+            }
+            for frame in frames.into_iter().take(keep) {
+                log.apply_frame(ix - 1, &frame)?;
+            }
+            log.segments.get_mut().push(file);
+            log.tail = tail as u64;
+        }
+        if log.segments.get_mut().is_empty() {
+            log.new_segment()?;
+        }
+        Ok(log)
+    }
+
+    /// The number of segment files, the last one being open for appends
+    pub fn segment_count(&self) -> usize {
+        self.segments.borrow().len()
+    }
+
+    /// The number of leading frames to keep, so that the log ends on a complete append
+    fn complete_frames(&self, frames: &[Frame]) -> usize {
+        let mut records = self.records.len() as u64;
+        let mut levels: Vec<u64> = self.hashes.iter().map(|v| v.len() as u64).collect();
+        let mut keep = 0;
+        for (ix, frame) in frames.iter().enumerate() {
+            match frame.kind {
+                RECORD => records += 1,
+                HASH if frame.content.len() == HASH_FRAME_SIZE => {
+                    let level = read_u32(frame.content) as usize;
+                    if levels.len() <= level {
+                        levels.resize(level + 1, 0);
+                    }
+                    levels[level] += 1;
+                }
+                _ => {}
+            }
+            if complete(records, levels.iter().copied()) {
+                keep = ix + 1;
+            }
+        }
+        keep
+    }
+
+    /// Update the in-memory positions with a frame read from a segment
+    fn apply_frame(&mut self, segment: usize, frame: &Frame) -> anyhow::Result<()> {
+        let position = FramePosition {
+            segment,
+            offset: frame.offset as u64,
+            length: frame.content.len(),
+        };
+        let invalid = || SegmentError::InvalidFrame(segment, frame.offset as u64, frame.kind);
+        match frame.kind {
+            RECORD => self.records.push(position),
+            HASH if frame.content.len() == HASH_FRAME_SIZE => {
+                let level = read_u32(frame.content) as usize;
+                if self.hashes.len() == level {
+                    self.hashes.push(vec![]);
+                }
+                let v = self.hashes.get_mut(level).ok_or_else(invalid)?;
+                if level == 0 {
+                    let hash =
+                        String::from_utf8_lossy(&frame.content[std::mem::size_of::<u32>()..]);
+                    self.lookup.insert(hash.into_owned(), v.len() as u64);
+                }
+                v.push(position);
+            }
+            META if frame.content.len() == 2 * std::mem::size_of::<u64>() => {
+                let (id, timestamp) = frame.content.split_at(std::mem::size_of::<u64>());
+                self.metas.insert(
+                    read_u64(id),
+                    RecordMeta {
+                        timestamp: read_u64(timestamp),
+                    },
+                );
+            }
+            ANNOTATION if frame.content.len() >= std::mem::size_of::<u64>() => {
+                self.annotations.insert(read_u64(frame.content), position);
+            }
+            _ => return Err(invalid().into()),
+        }
+        Ok(())
+    }
+
+    /// Whether all the hashes of the records have been added
+    fn is_complete(&self) -> bool {
+        complete(
+            self.records.len() as u64,
+            self.hashes.iter().map(|v| v.len() as u64),
+        )
+    }
+
+    /// Create a new empty segment, closing the current one
+    fn new_segment(&mut self) -> anyhow::Result<()> {
+        let segments = self.segments.get_mut();
+        // a closed segment is never written again, so it only needs syncing once
+        if let Some(last) = segments.last() {
+            last.sync_data()?;
+        }
+        let file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(segment_path(self.dir, segments.len()))?;
+        segments.push(file);
+        self.tail = 0;
+        Ok(())
+    }
+
+    /// Append a frame to the last segment
+    fn write_frame(&mut self, kind: u8, content: &[u8]) -> anyhow::Result<FramePosition> {
+        let segments = self.segments.get_mut();
+        let segment = segments.len() - 1;
+        let mut b = Vec::with_capacity(HEADER_SIZE + content.len());
+        b.push(kind);
+        b.extend_from_slice(&(content.len() as u32).to_be_bytes());
+        b.extend_from_slice(&frame_crc(kind, content).to_be_bytes());
+        b.extend_from_slice(content);
+        segments[segment].write_all(&b)?;
+        let position = FramePosition {
+            segment,
+            offset: self.tail,
+            length: content.len(),
+        };
+        self.tail += b.len() as u64;
+        Ok(position)
+    }
+
+    /// Write a record frame, moving to a new segment first if the current one is full, and return the record ID
+    fn add_record_frame(&mut self, data: &[u8]) -> anyhow::Result<u64> {
+        // only close a segment on a complete append, so a torn tail is always in the last segment
+        if self.tail >= self.segment_size && self.is_complete() {
+            self.new_segment()?;
+        }
+        let position = self.write_frame(RECORD, data)?;
+        self.records.push(position);
+        Ok(self.records.len() as u64 - 1)
+    }
+
+    /// Read the content of a frame, checking its checksum
+    fn read_frame(&self, position: &FramePosition) -> anyhow::Result<Vec<u8>> {
+        let mut segments = self.segments.borrow_mut();
+        let file = &mut segments[position.segment];
+        let mut b = vec![0_u8; HEADER_SIZE + position.length];
+        time_read("segment", || {
+            file.seek(SeekFrom::Start(position.offset))?;
+            file.read_exact(&mut b)
+        })?;
+        let content = b.split_off(HEADER_SIZE);
+        if read_u32(&b[5..]) != frame_crc(b[0], &content) {
+            return Err(SegmentError::Corrupted(position.segment, position.offset).into());
+        }
+        Ok(content)
+    }
+}
+
+/// Whether the given number of hashes at each level are all the hashes of the tree of the given size
+fn complete<I: Iterator<Item = u64>>(size: u64, levels: I) -> bool {
+    // the last level of the tree sizes may be empty
+    tree_sizes(size)
+        .into_iter()
+        .filter(|s| *s > 0)
+        .eq(levels.filter(|s| *s > 0))
+}
+
+/// The path of a segment file
+fn segment_path(dir: &Path, index: usize) -> PathBuf {
+    dir.join(format!("segment{}.bin", index))
+}
+
+/// Parse the frames of a segment, stopping at the first incomplete or corrupted frame
+fn parse_frames(b: &[u8]) -> Vec<Frame<'_>> {
+    let mut frames = vec![];
+    let mut offset = 0;
+    while offset + HEADER_SIZE <= b.len() {
+        let kind = b[offset];
+        let length = read_u32(&b[offset + 1..]) as usize;
+        let start = offset + HEADER_SIZE;
+        if start + length > b.len() {
+            break;
+        }
+        let content = &b[start..start + length];
+        if read_u32(&b[offset + 5..]) != frame_crc(kind, content) {
+            break;
+        }
+        frames.push(Frame {
+            offset,
+            kind,
+            content,
+        });
+        offset = start + length;
+    }
+    frames
+}
+
+/// Read a big endian u32 from the start of the bytes
+fn read_u32(b: &[u8]) -> u32 {
+    let mut b1 = [0_u8; std::mem::size_of::<u32>()];
+    b1.copy_from_slice(&b[..std::mem::size_of::<u32>()]);
+    u32::from_be_bytes(b1)
+}
+
+/// Read a big endian u64 from the start of the bytes
+fn read_u64(b: &[u8]) -> u64 {
+    let mut b1 = [0_u8; std::mem::size_of::<u64>()];
+    b1.copy_from_slice(&b[..std::mem::size_of::<u64>()]);
+    u64::from_be_bytes(b1)
+}
+
+/// The CRC32 table for the IEEE polynomial
+const CRC_TABLE: [u32; 256] = crc_table();
+
+/// Build the CRC32 table
+const fn crc_table() -> [u32; 256] {
+    let mut table = [0_u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut c = i as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 == 1 {
+                0xedb8_8320 ^ (c >> 1)
+            } else {
+                c >> 1
+            };
+            k += 1;
+        }
+        table[i] = c;
+        i += 1;
+    }
+    table
+}
+
+/// The CRC32 of the given bytes
+pub fn crc32(b: &[u8]) -> u32 {
+    !b.iter().fold(!0_u32, |c, x| {
+        CRC_TABLE[((c ^ *x as u32) & 0xff) as usize] ^ (c >> 8)
+    })
+}
+
+/// The checksum of a frame, covering its kind and content
+fn frame_crc(kind: u8, content: &[u8]) -> u32 {
+    let mut b = Vec::with_capacity(1 + content.len());
+    b.push(kind);
+    b.extend_from_slice(content);
+    crc32(&b)
+}
+
+/// Sequential iterator over a SegmentLog, reading the record frames of each segment through a buffer
+struct SegmentIterator<T> {
+    paths: Vec<PathBuf>,
+    reader: Option<BufReader<File>>,
+    segment: usize,
+    offset: u64,
+    id: u64,
+    size: u64,
+    _marker: PhantomData<T>,
+}
+
+impl<T: DeserializeOwned> SegmentIterator<T> {
+    /// Read the next record frame, moving to the next segment at the end of the current one
+    fn read_next(&mut self) -> anyhow::Result<T> {
+        loop {
+            let reader = match self.reader.as_mut() {
+                Some(reader) => reader,
+                None => self
+                    .reader
+                    .insert(BufReader::new(File::open(&self.paths[self.segment])?)),
+            };
+            let mut header = [0_u8; HEADER_SIZE];
+            if reader.read(&mut header[..1])? == 0 && self.segment + 1 < self.paths.len() {
+                self.reader = None;
+                self.segment += 1;
+                self.offset = 0;
+                continue;
+            }
+            reader.read_exact(&mut header[1..])?;
+            let mut content = vec![0_u8; read_u32(&header[1..]) as usize];
+            reader.read_exact(&mut content)?;
+            if read_u32(&header[5..]) != frame_crc(header[0], &content) {
+                return Err(SegmentError::Corrupted(self.segment, self.offset).into());
+            }
+            self.offset += (HEADER_SIZE + content.len()) as u64;
+            if header[0] == RECORD {
+                return decode_record(&content);
+            }
+        }
+    }
+}
+
+impl<T: DeserializeOwned> Iterator for SegmentIterator<T> {
+    type Item = anyhow::Result<(u64, T)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.id >= self.size {
+            return None;
+        }
+        let id = self.id;
+        self.id += 1;
+        match self.read_next() {
+            Ok(r) => Some(Ok((id, r))),
+            Err(e) => {
+                self.size = 0;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+impl<'a, T: Serialize + DeserializeOwned> TransparentLog<'a, T> for SegmentLog<'a, T> {
+    type LogSize = u64;
+
+    fn size(&self) -> anyhow::Result<Self::LogSize> {
+        Ok(self.records.len() as u64)
+    }
+
+    fn latest(&self) -> anyhow::Result<LogTree<Self::LogSize>> {
+        let size = self.records.len() as u64;
+        self.root.get_or_compute(size, || self.head_at(size))
+    }
+
+    fn get(&self, index: Self::LogSize) -> anyhow::Result<Option<MaybeOwned<'_, T>>> {
+        match self.records.get(index as usize) {
+            Some(position) => {
+                let b = self.read_frame(position)?;
+                Ok(Some(MaybeOwned::Owned(decode_record(&b)?)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn get_raw_bytes(&self, index: Self::LogSize) -> anyhow::Result<Option<Vec<u8>>> {
+        match self.records.get(index as usize) {
+            Some(position) => {
+                let b = self.read_frame(position)?;
+                if b.is_empty() {
+                    return Err(TombstoneError::NoRecord.into());
+                }
+                Ok(Some(b))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Scan the segments sequentially, without reading the frame positions
+    fn iter(&self) -> LogIterator<'_, Self::LogSize, T> {
+        Box::new(
+            SegmentIterator {
+                paths: (0..self.segment_count())
+                    .map(|ix| segment_path(self.dir, ix))
+                    .collect(),
+                reader: None,
+                segment: 0,
+                offset: 0,
+                id: 0,
+                size: self.records.len() as u64,
+                _marker: PhantomData,
+            }
+            .map(|r| r.map(|(id, t)| (id, MaybeOwned::Owned(t)))),
+        )
+    }
+
+    /// Add a record, moving to a new segment first if the current one is full
+    fn add(&mut self, record: T) -> anyhow::Result<Self::LogSize> {
+        let data = rmp_serde::to_vec(&record)?;
+        let id = self.add_record_frame(&data)?;
+        record_append("segment");
+        Ok(id)
+    }
+
+    /// A tombstone is an empty record frame
+    /// Segments are append only, so records cannot be redacted
+    fn add_tombstone(&mut self) -> anyhow::Result<Self::LogSize> {
+        let id = self.add_record_frame(&[])?;
+        record_append("segment");
+        Ok(id)
+    }
+
+    fn add_meta(&mut self, id: Self::LogSize, meta: RecordMeta) -> anyhow::Result<()> {
+        let mut b = Vec::with_capacity(2 * std::mem::size_of::<u64>());
+        b.extend_from_slice(&id.to_be_bytes());
+        b.extend_from_slice(&meta.timestamp.to_be_bytes());
+        self.write_frame(META, &b)?;
+        self.metas.insert(id, meta);
+        Ok(())
+    }
+
+    fn get_meta(&self, id: Self::LogSize) -> anyhow::Result<Option<RecordMeta>> {
+        Ok(self.metas.get(&id).copied())
+    }
+
+    /// Syncs the last segment, the other ones being synced when closed
+    fn sync(&self) -> anyhow::Result<()> {
+        if let Some(last) = self.segments.borrow().last() {
+            last.sync_data()?;
+        }
+        Ok(())
+    }
+
+    fn set_annotation(&mut self, id: Self::LogSize, annotation: Vec<u8>) -> anyhow::Result<()> {
+        if id >= self.records.len() as u64 {
+            return Err(LogError::InvalidIndex(0, id).into());
+        }
+        let mut b = Vec::with_capacity(std::mem::size_of::<u64>() + annotation.len());
+        b.extend_from_slice(&id.to_be_bytes());
+        b.extend_from_slice(&annotation);
+        let position = self.write_frame(ANNOTATION, &b)?;
+        self.annotations.insert(id, position);
+        Ok(())
+    }
+
+    fn get_annotation(&self, id: Self::LogSize) -> anyhow::Result<Option<Vec<u8>>> {
+        self.annotations
+            .get(&id)
+            .map(|position| {
+                let mut b = self.read_frame(position)?;
+                Ok(b.split_off(std::mem::size_of::<u64>()))
+            })
+            .transpose()
+    }
+
+    fn pending_hashes(&mut self) -> Option<&mut PendingHashes<Self::LogSize>> {
+        Some(&mut self.pending_hashes)
+    }
+
+    fn add_hash(&mut self, level: LogHeight, hash: String) -> anyhow::Result<Self::LogSize> {
+        self.root.clear();
+        if level > self.hashes.len() {
+            return Err(LogError::InvalidHeight::<Self::LogSize>(level).into());
+        }
+        let mut b = Vec::with_capacity(HASH_FRAME_SIZE);
+        b.extend_from_slice(&(level as u32).to_be_bytes());
+        b.extend_from_slice(hash.as_bytes());
+        let position = self.write_frame(HASH, &b)?;
+        if self.hashes.len() == level {
+            self.hashes.push(vec![]);
+        }
+        let v = &mut self.hashes[level];
+        let l = v.len() as u64;
+        v.push(position);
+        if level == 0 {
+            self.lookup.insert(hash, l);
+        }
+        Ok(l)
+    }
+
+    fn index_of(&self, hash: &str) -> anyhow::Result<Option<Self::LogSize>> {
+        Ok(self.lookup.first(hash))
+    }
+
+    fn indices_of(&self, hash: &str) -> anyhow::Result<Vec<Self::LogSize>> {
+        Ok(self.lookup.all(hash).to_vec())
+    }
+
+    fn get_hash(
+        &self,
+        level: LogHeight,
+        index: Self::LogSize,
+    ) -> anyhow::Result<MaybeOwned<'_, String>> {
+        let position = self
+            .hashes
+            .get(level)
+            .ok_or(LogError::InvalidHeight::<Self::LogSize>(level))?
+            .get(index as usize)
+            .ok_or(LogError::InvalidIndex(level, index))?;
+        let b = self.read_frame(position)?;
+        Ok(String::from_utf8_lossy(&b[std::mem::size_of::<u32>()..])
+            .into_owned()
+            .into())
+    }
+
+    fn proofs<I>(
+        &self,
+        positions: I,
+    ) -> anyhow::Result<HashMap<LogTreePosition<Self::LogSize>, String>>
+    where
+        I: Iterator<Item = LogTreePosition<Self::LogSize>>,
+    {
+        let positions: Vec<_> = positions.collect();
+        let _span = proofs_span("segment", positions.len());
+        let proofs: HashMap<_, _> = positions
+            .into_iter()
+            .map(|p| {
+                let hash = self.get_hash(p.level, p.index)?.into_owned();
+                Ok((p, hash))
+            })
+            .collect::<anyhow::Result<_>>()?;
+        record_proofs("segment", proofs.len());
+        Ok(proofs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::segment::crc32;
+    use crate::test_helpers::*;
+    use crate::{hash, SegmentLog, TransparentLog};
+    use std::fs::{create_dir, remove_dir_all, OpenOptions};
+    use std::path::{Path, PathBuf};
+
+    use serial_test::serial;
+
+    fn setup() -> anyhow::Result<PathBuf> {
+        let path = Path::new("./test_data");
+        if path.exists() {
+            remove_dir_all(path)?;
+        }
+        create_dir(path)?;
+        Ok(PathBuf::from(path))
+    }
+
+    #[test]
+    fn segment_crc32() {
+        assert_eq!(0, crc32(b""));
+        assert_eq!(0xcbf4_3926, crc32(b"123456789"));
+    }
+
+    #[test]
+    #[serial]
+    fn segment_13() -> anyhow::Result<()> {
+        let path = setup()?;
+        let mut ml: SegmentLog<LogRecord> = SegmentLog::open_with_segment_size(&path, 512)?;
+        test_13(&mut ml)?;
+        assert!(ml.segment_count() > 1);
+        let ml2: SegmentLog<LogRecord> = SegmentLog::open_with_segment_size(&path, 512)?;
+        check_13(&ml2)
+    }
+
+    #[test]
+    #[serial]
+    fn segment_annotations() -> anyhow::Result<()> {
+        let path = setup()?;
+        let mut ml: SegmentLog<LogRecord> = SegmentLog::open_with_segment_size(&path, 512)?;
+        annotations(&mut ml)?;
+        let ml: SegmentLog<LogRecord> = SegmentLog::open_with_segment_size(&path, 512)?;
+        assert_eq!(Some(b"hidden".to_vec()), ml.get_annotation(1)?);
+        assert_eq!(Some(vec![]), ml.get_annotation(2)?);
+        assert_eq!(3, ml.size()?);
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    fn segment_duplicates() -> anyhow::Result<()> {
+        let path = setup()?;
+        let mut ml: SegmentLog<LogRecord> = SegmentLog::open_with_segment_size(&path, 512)?;
+        duplicates(&mut ml)?;
+        let ml: SegmentLog<LogRecord> = SegmentLog::open_with_segment_size(&path, 512)?;
+        assert_eq!(
+            vec![1, 5, 6],
+            ml.indices_of(&hash(&LogRecord::new("rec1"))?)?
+        );
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    fn segment_out_of_range() -> anyhow::Result<()> {
+        let path = setup()?;
+        let mut ml: SegmentLog<LogRecord> = SegmentLog::open_with_segment_size(&path, 512)?;
+        out_of_range(&mut ml)
+    }
+
+    #[test]
+    #[serial]
+    fn segment_iter() -> anyhow::Result<()> {
+        let path = setup()?;
+        let mut ml: SegmentLog<LogRecord> = SegmentLog::open_with_segment_size(&path, 512)?;
+        iter(&mut ml)
+    }
+
+    #[test]
+    #[serial]
+    fn segment_hash_only() -> anyhow::Result<()> {
+        let path = setup()?;
+        let mut ml: SegmentLog<LogRecord> = SegmentLog::open_with_segment_size(&path, 64)?;
+        hash_only(&mut ml)?;
+        assert!(ml.redact(4).is_err());
+        drop(ml);
+        let ml: SegmentLog<LogRecord> = SegmentLog::open_with_segment_size(&path, 64)?;
+        check_13(&ml)?;
+        assert_tombstone(ml.get(3));
+        assert_tombstone(ml.iter().nth(3).unwrap());
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    fn segment_properties() -> anyhow::Result<()> {
+        let path = setup()?;
+        properties(|| {
+            setup()?;
+            SegmentLog::<LogRecord>::open_with_segment_size(&path, 4096)
+        })
+    }
+
+    #[test]
+    #[serial]
+    fn segment_torn_tail() -> anyhow::Result<()> {
+        let path = setup()?;
+        let last = path.join("segment0.bin");
+        let mut ml: SegmentLog<LogRecord> = SegmentLog::open(&path)?;
+        append_multiple(&mut ml, 13)?;
+        let complete = last.metadata()?.len();
+        let latest = ml.latest()?;
+        ml.append(LogRecord::new("rec13"))?;
+        let appended = last.metadata()?.len();
+        drop(ml);
+
+        // cut in the middle of the last append: the record is there but not all its hashes
+        for cut in [complete + 1, appended - 70, appended - 1] {
+            OpenOptions::new().write(true).open(&last)?.set_len(cut)?;
+            let ml: SegmentLog<LogRecord> = SegmentLog::open(&path)?;
+            assert_eq!(13, ml.size()?);
+            assert_eq!(latest, ml.latest()?);
+            assert_eq!(complete, last.metadata()?.len());
+        }
+
+        // a corrupted byte drops everything after it
+        let mut ml: SegmentLog<LogRecord> = SegmentLog::open(&path)?;
+        ml.append(LogRecord::new("rec13"))?;
+        drop(ml);
+        let mut b = std::fs::read(&last)?;
+        let ix = complete as usize + 20;
+        b[ix] ^= 0xff;
+        std::fs::write(&last, b)?;
+        let ml: SegmentLog<LogRecord> = SegmentLog::open(&path)?;
+        assert_eq!(latest, ml.latest()?);
+        check_13(&ml)
+    }
+}
@@ -0,0 +1,387 @@
+//! Background sequencing: many threads queue records, a single writer thread merges them into the tree in batches
+use crate::base::*;
+use num::One;
+use serde::{de::DeserializeOwned, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::prelude::*;
+use std::path::Path;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, RwLock};
+use std::thread::JoinHandle;
+use thiserror::Error;
+
+/// The default maximum number of records merged in one batch
+const DEFAULT_BATCH_SIZE: usize = 1000;
+
+/// The size of the length prefix of a queue entry
+const LENGTH_SIZE_IN_BYTES: usize = std::mem::size_of::<u64>();
+
+/// Specific Errors for the sequencer
+#[derive(Debug, Clone, Error)]
+pub enum SequencerError {
+    #[error("Sequencer is stopped")]
+    Stopped,
+    #[error("Sequencer failed: {0}")]
+    Failed(String),
+    #[error("Sequencer lock poisoned")]
+    Poisoned,
+}
+
+/// The state shared between the producers and the writer thread
+struct SequencerState<T, LogSize> {
+    // Records queued but not merged yet, with their future ID
+    pending: Vec<(LogSize, T)>,
+    // The ID of the next queued record
+    next_id: LogSize,
+    // The latest tree after merging
+    tree: LogTree<LogSize>,
+    // The durable queue
+    queue: File,
+    stopped: bool,
+    error: Option<String>,
+}
+
+/// The state, with a condition variable notified when records are queued or merged
+struct Shared<T, LogSize> {
+    state: Mutex<SequencerState<T, LogSize>>,
+    changed: Condvar,
+}
+
+impl<T, LogSize> Shared<T, LogSize> {
+    /// Lock the state
+    fn lock(&self) -> Result<MutexGuard<'_, SequencerState<T, LogSize>>, SequencerError> {
+        self.state.lock().map_err(|_| SequencerError::Poisoned)
+    }
+
+    /// Wait for a change of the state
+    fn wait<'g>(
+        &self,
+        guard: MutexGuard<'g, SequencerState<T, LogSize>>,
+    ) -> Result<MutexGuard<'g, SequencerState<T, LogSize>>, SequencerError> {
+        self.changed
+            .wait(guard)
+            .map_err(|_| SequencerError::Poisoned)
+    }
+}
+
+/// Write a queue entry: the length of the serialized ID and record, then the bytes
+fn write_entry<T: Serialize, LogSize: Serialize>(
+    queue: &mut File,
+    id: LogSize,
+    record: &T,
+) -> anyhow::Result<()> {
+    let b = rmp_serde::to_vec(&(id, record))?;
+    let mut entry = (b.len() as u64).to_be_bytes().to_vec();
+    entry.extend_from_slice(&b);
+    queue.write_all(&entry)?;
+    queue.sync_data()?;
+    Ok(())
+}
+
+/// Read all complete queue entries, returning them with the length of the valid part of the queue
+fn read_entries<T: DeserializeOwned, LogSize: DeserializeOwned>(
+    queue: &mut File,
+) -> anyhow::Result<(Vec<(LogSize, T)>, u64)> {
+    let mut b = vec![];
+    queue.read_to_end(&mut b)?;
+    let mut entries = vec![];
+    let mut pos = 0;
+    while pos + LENGTH_SIZE_IN_BYTES <= b.len() {
+        let mut l = [0_u8; LENGTH_SIZE_IN_BYTES];
+        l.copy_from_slice(&b[pos..pos + LENGTH_SIZE_IN_BYTES]);
+        let start = pos + LENGTH_SIZE_IN_BYTES;
+        let end = start + u64::from_be_bytes(l) as usize;
+        if end > b.len() {
+            break;
+        }
+        entries.push(rmp_serde::from_slice(&b[start..end])?);
+        pos = end;
+    }
+    Ok((entries, pos as u64))
+}
+
+/// A sequencer accepting records from many threads into a durable queue,
+/// and merging them into the log tree in batches on a single writer thread
+pub struct Sequencer<T, TL>
+where
+    T: Serialize + DeserializeOwned + Send + 'static,
+    TL: TransparentLog<'static, T> + Send + Sync + 'static,
+    TL::LogSize: Serialize + DeserializeOwned + Send,
+{
+    shared: Arc<Shared<T, TL::LogSize>>,
+    log: Option<Arc<RwLock<TL>>>,
+    leaf_hasher: LeafHasher<T>,
+    writer: Option<JoinHandle<()>>,
+}
+
+impl<T, TL> Sequencer<T, TL>
+where
+    T: Serialize + DeserializeOwned + Send + 'static,
+    TL: TransparentLog<'static, T> + Send + Sync + 'static,
+    TL::LogSize: Serialize + DeserializeOwned + Send,
+{
+    /// Start sequencing into the given log, with the durable queue in the given file
+    /// Records left in the queue and not yet in the log are merged first
+    pub fn open<P: AsRef<Path>>(log: TL, queue: P) -> anyhow::Result<Self> {
+        Self::open_with_batch_size(log, queue, DEFAULT_BATCH_SIZE)
+    }
+
+    /// Start sequencing, merging at most the given number of records in one batch
+    pub fn open_with_batch_size<P: AsRef<Path>>(
+        log: TL,
+        queue: P,
+        batch_size: usize,
+    ) -> anyhow::Result<Self> {
+        let mut queue = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(queue)?;
+        let (entries, valid) = read_entries::<T, TL::LogSize>(&mut queue)?;
+        queue.set_len(valid)?;
+
+        let tree = log.latest()?;
+        let leaf_hasher = log.leaf_hasher();
+        let pending: Vec<(TL::LogSize, T)> = entries
+            .into_iter()
+            .filter(|(id, _)| *id >= tree.size)
+            .collect();
+        let next_id = pending
+            .last()
+            .map(|(id, _)| *id + TL::LogSize::one())
+            .unwrap_or(tree.size);
+        let shared = Arc::new(Shared {
+            state: Mutex::new(SequencerState {
+                pending,
+                next_id,
+                tree,
+                queue,
+                stopped: false,
+                error: None,
+            }),
+            changed: Condvar::new(),
+        });
+        let log = Arc::new(RwLock::new(log));
+
+        let writer = {
+            let shared = shared.clone();
+            let log = log.clone();
+            std::thread::spawn(move || {
+                if let Err(e) = Self::run(&shared, &log, batch_size.max(1)) {
+                    if let Ok(mut state) = shared.lock() {
+                        state.error = Some(e.to_string());
+                        state.stopped = true;
+                    }
+                    shared.changed.notify_all();
+                }
+            })
+        };
+
+        Ok(Self {
+            shared,
+            log: Some(log),
+            leaf_hasher,
+            writer: Some(writer),
+        })
+    }
+
+    /// The writer loop: merge queued records until stopped and drained
+    fn run(
+        shared: &Shared<T, TL::LogSize>,
+        log: &RwLock<TL>,
+        batch_size: usize,
+    ) -> anyhow::Result<()> {
+        loop {
+            let batch: Vec<(TL::LogSize, T)> = {
+                let mut state = shared.lock()?;
+                while state.pending.is_empty() && !state.stopped {
+                    state = shared.wait(state)?;
+                }
+                if state.pending.is_empty() {
+                    return Ok(());
+                }
+                let n = state.pending.len().min(batch_size);
+                state.pending.drain(..n).collect()
+            };
+            let tree = {
+                let mut log = log.write().map_err(|_| SequencerError::Poisoned)?;
+                for (_, record) in batch {
+                    log.append(record)?;
+                }
+                log.latest()?
+            };
+            let mut state = shared.lock()?;
+            state.tree = tree;
+            if state.pending.is_empty() {
+                state.queue.set_len(0)?;
+            }
+            shared.changed.notify_all();
+        }
+    }
+
+    /// Queue a record, returning the receipt of its future place in the log
+    pub fn submit(&self, record: T) -> anyhow::Result<Record<TL::LogSize>> {
+        let hash = (self.leaf_hasher)(&record)?;
+        let mut state = self.shared.lock()?;
+        if state.stopped {
+            return Err(SequencerError::Stopped.into());
+        }
+        let id = state.next_id;
+        write_entry(&mut state.queue, id, &record)?;
+        state.next_id = id + TL::LogSize::one();
+        state.pending.push((id, record));
+        self.shared.changed.notify_all();
+        Ok(Record {
+            id,
+            hash,
+            meta: None,
+        })
+    }
+
+    /// Wait until the record of the given receipt is in the tree, returning the tree containing it
+    pub fn await_inclusion(
+        &self,
+        receipt: &Record<TL::LogSize>,
+    ) -> anyhow::Result<LogTree<TL::LogSize>> {
+        let mut state = self.shared.lock()?;
+        while state.tree.size <= receipt.id {
+            if let Some(e) = &state.error {
+                return Err(SequencerError::Failed(e.clone()).into());
+            }
+            if state.stopped && state.pending.is_empty() {
+                return Err(SequencerError::Stopped.into());
+            }
+            state = self.shared.wait(state)?;
+        }
+        Ok(state.tree.clone())
+    }
+
+    /// Read the log, waiting for the current batch to be merged
+    pub fn read<R>(&self, f: impl FnOnce(&TL) -> R) -> anyhow::Result<R> {
+        let log = self
+            .log
+            .as_ref()
+            .ok_or(SequencerError::Stopped)?
+            .read()
+            .map_err(|_| SequencerError::Poisoned)?;
+        Ok(f(&log))
+    }
+
+    /// Stop accepting records, wait for all queued records to be merged, and return the log
+    pub fn close(mut self) -> anyhow::Result<TL> {
+        self.stop();
+        if let Some(e) = self.shared.lock()?.error.take() {
+            return Err(SequencerError::Failed(e).into());
+        }
+        let log = self.log.take().ok_or(SequencerError::Stopped)?;
+        Arc::try_unwrap(log)
+            .map_err(|_| SequencerError::Poisoned)?
+            .into_inner()
+            .map_err(|_| SequencerError::Poisoned.into())
+    }
+
+    /// Stop the writer thread once the queue is drained, and wait for it
+    fn stop(&mut self) {
+        if let Ok(mut state) = self.shared.lock() {
+            state.stopped = true;
+        }
+        self.shared.changed.notify_all();
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+    }
+}
+
+impl<T, TL> Drop for Sequencer<T, TL>
+where
+    T: Serialize + DeserializeOwned + Send + 'static,
+    TL: TransparentLog<'static, T> + Send + Sync + 'static,
+    TL::LogSize: Serialize + DeserializeOwned + Send,
+{
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::test_helpers::*;
+    use crate::*;
+    use std::fs::{create_dir, remove_dir_all, OpenOptions};
+    use std::path::{Path, PathBuf};
+    use std::sync::Arc;
+    use std::thread;
+
+    use super::write_entry;
+    use serial_test::serial;
+
+    fn setup() -> anyhow::Result<PathBuf> {
+        let path = Path::new("./test_data_sequencer");
+        if path.exists() {
+            remove_dir_all(path)?;
+        }
+        create_dir(path)?;
+        Ok(path.join("queue.bin"))
+    }
+
+    #[test]
+    #[serial]
+    fn sequencer_threads() -> anyhow::Result<()> {
+        let queue = setup()?;
+        let ml: InMemoryLog<LogRecord> = InMemoryLog::default();
+        let sequencer = Arc::new(Sequencer::open_with_batch_size(ml, &queue, 7)?);
+        let producers: Vec<_> = (0..4)
+            .map(|t| {
+                let sequencer = sequencer.clone();
+                thread::spawn(move || -> anyhow::Result<()> {
+                    for i in 0..25 {
+                        let lr = LogRecord::new(&format!("rec{}-{}", t, i));
+                        let receipt = sequencer.submit(lr.clone())?;
+                        let tree = sequencer.await_inclusion(&receipt)?;
+                        assert!(receipt.id < tree.size);
+                        let stored = sequencer
+                            .read(|log| log.get(receipt.id).map(|r| r.map(|r| r.into_owned())))??;
+                        assert_eq!(Some(lr), stored);
+                    }
+                    Ok(())
+                })
+            })
+            .collect();
+        for p in producers {
+            p.join().expect("producer panicked")?;
+        }
+        let sequencer = Arc::try_unwrap(sequencer)
+            .ok()
+            .expect("sequencer still shared");
+        let ml = sequencer.close()?;
+        assert_eq!(100, ml.size()?);
+        remove_dir_all(queue.parent().unwrap())?;
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    fn sequencer_replay() -> anyhow::Result<()> {
+        let queue = setup()?;
+        let mut ml: InMemoryLog<LogRecord> = InMemoryLog::default();
+        append_multiple(&mut ml, 2)?;
+        {
+            let mut f = OpenOptions::new().append(true).create(true).open(&queue)?;
+            for i in 0..5_usize {
+                write_entry(&mut f, i, &LogRecord::new(&format!("rec{}", i)))?;
+            }
+        }
+        let sequencer = Sequencer::open(ml, &queue)?;
+        let receipt = sequencer.submit(LogRecord::new("rec5"))?;
+        assert_eq!(5, receipt.id);
+        assert_eq!(6, sequencer.await_inclusion(&receipt)?.size);
+        let ml = sequencer.close()?;
+
+        let mut expected: InMemoryLog<LogRecord> = InMemoryLog::default();
+        append_multiple(&mut expected, 6)?;
+        assert_eq!(expected.latest()?, ml.latest()?);
+        assert_eq!(0, queue.metadata()?.len());
+        remove_dir_all(queue.parent().unwrap())?;
+        Ok(())
+    }
+}
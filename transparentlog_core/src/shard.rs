@@ -0,0 +1,483 @@
+//! # Sharded logs
+//!
+//! A `ShardedLog` spreads its records over a sequence of logs, its shards, as CT logs shard by year:
+//! when the current shard reaches a maximum size or age, it is sealed and the factory creates the next one.
+//! Records have a global index across shards, that `get` and `prove` route to the shard holding the record.
+//! The shard directory, the first global index and tree head of each shard, is signed as a note.
+use crate::base::*;
+use crate::checkpoint::*;
+use maybe_owned::MaybeOwned;
+use num::{ToPrimitive, Zero};
+use serde::{Deserialize, Serialize};
+use std::fmt::{Debug, Display};
+use std::str::FromStr;
+use thiserror::Error;
+
+/// Specific Errors for sharded logs
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum ShardError {
+    #[error("Malformed shard directory: {0}")]
+    MalformedDirectory(&'static str),
+}
+
+/// A shard of a sharded log: the global index of its first record, its tree head, and whether it is sealed
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ShardHead<LogSize> {
+    pub start: LogSize,
+    pub size: LogSize,
+    pub hash: String,
+    pub sealed: bool,
+}
+
+/// The shards of a sharded log, oldest first
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ShardDirectory<LogSize> {
+    pub origin: String,
+    pub shards: Vec<ShardHead<LogSize>>,
+}
+
+impl<LogSize: Display + FromStr> ShardDirectory<LogSize> {
+    /// The text of the directory: the origin line, then a `start size hash sealed|open` line per shard,
+    /// the hash of an empty shard being `-`
+    pub fn text(&self) -> String {
+        let mut text = format!("{}\n", self.origin);
+        for shard in &self.shards {
+            let hash = if shard.hash.is_empty() {
+                "-"
+            } else {
+                &shard.hash
+            };
+            let state = if shard.sealed { "sealed" } else { "open" };
+            text.push_str(&format!(
+                "{} {} {} {}\n",
+                shard.start, shard.size, hash, state
+            ));
+        }
+        text
+    }
+
+    /// Parse the text of a directory
+    pub fn parse(text: &str) -> Result<Self, ShardError> {
+        let mut lines = text
+            .strip_suffix('\n')
+            .ok_or(ShardError::MalformedDirectory("missing final new line"))?
+            .split('\n');
+        let origin = match lines.next() {
+            Some(origin) if !origin.is_empty() => origin.to_owned(),
+            _ => return Err(ShardError::MalformedDirectory("missing origin")),
+        };
+        let shards = lines
+            .map(|line| {
+                let fields: Vec<&str> = line.split(' ').collect();
+                let (start, size, hash, state) = match fields[..] {
+                    [start, size, hash, state] => (start, size, hash, state),
+                    _ => return Err(ShardError::MalformedDirectory("invalid shard line")),
+                };
+                let number = |n: &str| {
+                    n.parse()
+                        .map_err(|_| ShardError::MalformedDirectory("invalid number"))
+                };
+                Ok(ShardHead {
+                    start: number(start)?,
+                    size: number(size)?,
+                    hash: if hash == "-" {
+                        String::new()
+                    } else {
+                        hash.to_owned()
+                    },
+                    sealed: match state {
+                        "sealed" => true,
+                        "open" => false,
+                        _ => return Err(ShardError::MalformedDirectory("invalid shard state")),
+                    },
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self { origin, shards })
+    }
+
+    /// Sign the directory, returning the signed note
+    pub fn sign(&self, signers: &[&dyn LogSigner]) -> anyhow::Result<String> {
+        sign_note(&self.text(), signers)
+    }
+
+    /// Open a signed directory, verifying its signatures
+    pub fn open(note: &str, verifiers: &[&dyn LogVerifier]) -> anyhow::Result<Self> {
+        let (text, _) = open_note(note, verifiers)?;
+        Ok(Self::parse(text)?)
+    }
+}
+
+/// A record appended to a sharded log: the shard it went to, its global index, and the record in the shard
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShardedRecord<LogSize> {
+    pub shard: usize,
+    pub index: LogSize,
+    pub record: Record<LogSize>,
+}
+
+/// A log spread over shards, created by the factory from their position
+pub struct ShardedLog<L, F> {
+    shards: Vec<L>,
+    factory: F,
+    max_size: Option<u64>,
+    max_age: Option<u64>,
+}
+
+impl<L, F: FnMut(usize) -> anyhow::Result<L>> ShardedLog<L, F> {
+    /// A new sharded log, creating its first shard
+    pub fn new(factory: F) -> anyhow::Result<Self> {
+        Self::open(vec![], factory)
+    }
+
+    /// A sharded log over the given existing shards, oldest first, creating the first shard if there is none
+    pub fn open(mut shards: Vec<L>, mut factory: F) -> anyhow::Result<Self> {
+        if shards.is_empty() {
+            shards.push(factory(0)?);
+        }
+        Ok(Self {
+            shards,
+            factory,
+            max_size: None,
+            max_age: None,
+        })
+    }
+
+    /// Roll to a new shard once the current one holds the given number of records
+    pub fn max_shard_size(&mut self, size: u64) -> &mut Self {
+        self.max_size = Some(size);
+        self
+    }
+
+    /// Roll to a new shard once the first record of the current one is older than the given number of milliseconds
+    pub fn max_shard_age(&mut self, age: u64) -> &mut Self {
+        self.max_age = Some(age);
+        self
+    }
+
+    /// Seal the current shard and create a new one, returning its position
+    pub fn roll<'a, T>(&mut self) -> anyhow::Result<usize>
+    where
+        T: Serialize + Deserialize<'a>,
+        L: TransparentLog<'a, T>,
+    {
+        self.current().seal()?;
+        let shard = (self.factory)(self.shards.len())?;
+        self.shards.push(shard);
+        Ok(self.shards.len() - 1)
+    }
+
+    /// Append a record to the current shard, rolling to a new one first if the current one is full or too old
+    pub fn append<'a, T>(&mut self, record: T) -> anyhow::Result<ShardedRecord<L::LogSize>>
+    where
+        T: Serialize + Deserialize<'a>,
+        L: TransparentLog<'a, T>,
+    {
+        if self.is_full()? {
+            self.roll()?;
+        }
+        let start = self.start(self.shards.len() - 1)?;
+        let record = self.current().append(record)?;
+        Ok(ShardedRecord {
+            shard: self.shards.len() - 1,
+            index: start + record.id,
+            record,
+        })
+    }
+
+    /// The number of records in all the shards
+    pub fn size<'a, T>(&self) -> anyhow::Result<L::LogSize>
+    where
+        T: Serialize + Deserialize<'a>,
+        L: TransparentLog<'a, T>,
+    {
+        self.start(self.shards.len())
+    }
+
+    /// The shard holding the record with the given global index, and its index in the shard
+    pub fn locate<'a, T>(&self, index: L::LogSize) -> anyhow::Result<Option<(usize, L::LogSize)>>
+    where
+        T: Serialize + Deserialize<'a>,
+        L: TransparentLog<'a, T>,
+    {
+        let mut start = L::LogSize::zero();
+        for (shard, log) in self.shards.iter().enumerate() {
+            let size = log.size()?;
+            if index < start + size {
+                return Ok(Some((shard, index - start)));
+            }
+            start = start + size;
+        }
+        Ok(None)
+    }
+
+    /// Get the record with the given global index
+    pub fn get<'a, T>(&self, index: L::LogSize) -> anyhow::Result<Option<MaybeOwned<'_, T>>>
+    where
+        T: Serialize + Deserialize<'a>,
+        L: TransparentLog<'a, T>,
+    {
+        match self.locate(index)? {
+            Some((shard, id)) => self.shards[shard].get(id),
+            None => Ok(None),
+        }
+    }
+
+    /// Prove the record with the given global index is in the latest tree of its shard
+    /// Returns the shard and the proof, with the index of the record in the shard
+    pub fn prove<'a, T>(
+        &self,
+        index: L::LogSize,
+    ) -> anyhow::Result<Option<(usize, InclusionProof<L::LogSize>)>>
+    where
+        T: Serialize + Deserialize<'a>,
+        L: TransparentLog<'a, T>,
+        L::LogSize: Display + Debug + Send + Sync,
+    {
+        match self.locate(index)? {
+            Some((shard, id)) => {
+                let log = &self.shards[shard];
+                Ok(Some((shard, prove_inclusion(log, id, log.size()?)?)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// The directory of the shards, for the given origin
+    pub fn directory<'a, T>(&self, origin: &str) -> anyhow::Result<ShardDirectory<L::LogSize>>
+    where
+        T: Serialize + Deserialize<'a>,
+        L: TransparentLog<'a, T>,
+    {
+        let mut start = L::LogSize::zero();
+        let mut shards = Vec::with_capacity(self.shards.len());
+        for log in &self.shards {
+            let (tree, sealed) = match log.sealed()? {
+                Some(tree) => (tree, true),
+                None => (log.latest()?, false),
+            };
+            shards.push(ShardHead {
+                start,
+                size: tree.size,
+                hash: tree.hash,
+                sealed,
+            });
+            start = start + tree.size;
+        }
+        Ok(ShardDirectory {
+            origin: origin.to_owned(),
+            shards,
+        })
+    }
+
+    /// The directory of the shards, signed by the given signers
+    pub fn sign_directory<'a, T>(
+        &self,
+        origin: &str,
+        signers: &[&dyn LogSigner],
+    ) -> anyhow::Result<String>
+    where
+        T: Serialize + Deserialize<'a>,
+        L: TransparentLog<'a, T>,
+        L::LogSize: Display + FromStr,
+    {
+        self.directory(origin)?.sign(signers)
+    }
+
+    /// The shards, oldest first
+    pub fn shards(&self) -> &[L] {
+        &self.shards
+    }
+
+    /// Get back the shards
+    pub fn into_shards(self) -> Vec<L> {
+        self.shards
+    }
+
+    /// The shard appended to
+    fn current(&mut self) -> &mut L {
+        let last = self.shards.len() - 1;
+        &mut self.shards[last]
+    }
+
+    /// The global index of the first record of the shard at the given position
+    fn start<'a, T>(&self, shard: usize) -> anyhow::Result<L::LogSize>
+    where
+        T: Serialize + Deserialize<'a>,
+        L: TransparentLog<'a, T>,
+    {
+        self.shards[..shard]
+            .iter()
+            .try_fold(L::LogSize::zero(), |start, log| Ok(start + log.size()?))
+    }
+
+    /// Is the current shard at its maximum size or age?
+    fn is_full<'a, T>(&self) -> anyhow::Result<bool>
+    where
+        T: Serialize + Deserialize<'a>,
+        L: TransparentLog<'a, T>,
+    {
+        let log = &self.shards[self.shards.len() - 1];
+        let size = log.size()?;
+        if let Some(max) = self.max_size {
+            if size.to_u64().map_or(true, |s| s >= max) {
+                return Ok(true);
+            }
+        }
+        if let (Some(max), false) = (self.max_age, size.is_zero()) {
+            if let Some(first) = log.get_meta(L::LogSize::zero())? {
+                let age = RecordMeta::now().timestamp.saturating_sub(first.timestamp);
+                return Ok(age >= max);
+            }
+        }
+        Ok(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::test_helpers::*;
+    use crate::*;
+    use std::fs::{create_dir, create_dir_all, remove_dir_all};
+    use std::path::{Path, PathBuf};
+
+    use serial_test::serial;
+
+    fn setup(count: usize) -> anyhow::Result<Vec<PathBuf>> {
+        let path = Path::new("./test_data_shard");
+        if path.exists() {
+            remove_dir_all(path)?;
+        }
+        create_dir(path)?;
+        (0..count)
+            .map(|i| {
+                let dir = path.join(format!("shard{}", i));
+                create_dir_all(&dir)?;
+                Ok(dir)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn shard_by_size() -> anyhow::Result<()> {
+        let mut sl = ShardedLog::new(|_| Ok(InMemoryLog::default()))?;
+        sl.max_shard_size(5);
+        let records = (0..13)
+            .map(|i| sl.append(LogRecord::new(&format!("rec{}", i))))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        assert_eq!(3, sl.shards().len());
+        assert_eq!(13, sl.size()?);
+        assert_eq!(
+            vec![0, 0, 0, 0, 0, 1, 1, 1, 1, 1, 2, 2, 2],
+            records.iter().map(|r| r.shard).collect::<Vec<_>>()
+        );
+        assert_eq!(12, records[12].index);
+        assert_eq!(2, records[12].record.id);
+        assert_eq!(Some((2, 2)), sl.locate(12)?);
+        assert_eq!(None, sl.locate(13)?);
+        assert_eq!(Some(&LogRecord::new("rec7")), sl.get(7)?.as_deref());
+        assert!(sl.get(13)?.is_none());
+
+        let (shard, proof) = sl.prove(7)?.unwrap();
+        assert_eq!(1, shard);
+        assert_eq!(records[7].record.hash, proof.record.hash);
+        let shard_tree: LogTree<usize> = sl.shards()[1].sealed()?.unwrap();
+        assert!(proof.verify(&shard_tree));
+        assert!(sl.prove(13)?.is_none());
+
+        // full shards are sealed
+        assert_eq!(Some(sl.shards()[0].latest()?), sl.shards()[0].sealed()?);
+        assert!(sl.shards()[2].sealed()?.is_none());
+        let mut shards = sl.into_shards();
+        assert_sealed(shards[0].append(LogRecord::new("rec13")));
+        Ok(())
+    }
+
+    #[test]
+    fn shard_by_age() -> anyhow::Result<()> {
+        let mut sl = ShardedLog::new(|_| Ok(InMemoryLog::default()))?;
+        sl.max_shard_age(0);
+        append_sharded(&mut sl, 3)?;
+        assert_eq!(3, sl.shards().len());
+        let mut sl = ShardedLog::open(sl.into_shards(), |_| Ok(InMemoryLog::default()))?;
+        sl.max_shard_age(60_000);
+        append_sharded(&mut sl, 3)?;
+        assert_eq!(3, sl.shards().len());
+        assert_eq!(4, sl.shards()[2].size()?);
+        Ok(())
+    }
+
+    #[test]
+    fn shard_directory() -> anyhow::Result<()> {
+        let mut sl = ShardedLog::new(|_| Ok(InMemoryLog::default()))?;
+        sl.max_shard_size(8);
+        append_sharded(&mut sl, 13)?;
+        sl.roll()?;
+        let directory = sl.directory("example.com/log")?;
+        assert_eq!(
+            vec![(0, 8, true), (8, 5, true), (13, 0, false)],
+            directory
+                .shards
+                .iter()
+                .map(|s| (s.start, s.size, s.sealed))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(sl.shards()[1].latest()?.hash, directory.shards[1].hash);
+        assert!(directory.text().ends_with("\n13 0 - open\n"));
+        assert_eq!(directory, ShardDirectory::parse(&directory.text())?);
+
+        let signer = Ed25519Signer::new("example.com/log", &[7_u8; 32]);
+        let note = sl.sign_directory("example.com/log", &[&signer])?;
+        assert_eq!(
+            directory,
+            ShardDirectory::open(&note, &[&signer.verifier()])?
+        );
+        let other = Ed25519Signer::new("other", &[9_u8; 32]);
+        assert!(ShardDirectory::<usize>::open(&note, &[&other.verifier()]).is_err());
+        assert_eq!(
+            Err(ShardError::MalformedDirectory("invalid shard state")),
+            ShardDirectory::<usize>::parse("example.com/log\n0 0 - closed\n")
+        );
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    fn shard_files() -> anyhow::Result<()> {
+        let dirs = setup(3)?;
+        let mut sl = ShardedLog::new(|i| FileLog::open(&dirs[i]))?;
+        sl.max_shard_size(5);
+        for i in 0..13 {
+            sl.append(LogRecord::new(&format!("rec{}", i)))?;
+        }
+        let directory = sl.directory("example.com/log")?;
+        drop(sl);
+        let shards = dirs
+            .iter()
+            .map(FileLog::open)
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let sl = ShardedLog::open(shards, |i| FileLog::open(&dirs[i]))?;
+        assert_eq!(directory, sl.directory("example.com/log")?);
+        assert_eq!(Some(&LogRecord::new("rec12")), sl.get(12)?.as_deref());
+        let (shard, proof) = sl.prove(6)?.unwrap();
+        assert_eq!(1, shard);
+        assert!(proof.verify(&sl.shards()[1].latest()?));
+        drop(sl);
+        remove_dir_all("./test_data_shard")?;
+        Ok(())
+    }
+
+    fn append_sharded<F>(
+        sl: &mut ShardedLog<InMemoryLog<LogRecord>, F>,
+        count: usize,
+    ) -> anyhow::Result<()>
+    where
+        F: FnMut(usize) -> anyhow::Result<InMemoryLog<LogRecord>>,
+    {
+        for i in 0..count {
+            sl.append(LogRecord::new(&format!("rec{}", i)))?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,301 @@
+//! A log shared between threads: readers serve records and proofs concurrently while a writer appends
+use crate::base::*;
+use crate::tile::Tile;
+use maybe_owned::MaybeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+use thiserror::Error;
+
+/// Specific Errors for shared logs
+#[derive(Debug, Clone, Error)]
+pub enum SharedLogError {
+    #[error("Shared log lock poisoned")]
+    Poisoned,
+}
+
+/// A log behind a read-write lock, implementing TransparentLog for shared references
+/// Each append takes the write lock once, so concurrent appends cannot interleave their tree updates
+#[derive(Debug, Default)]
+pub struct SharedLog<L> {
+    log: RwLock<L>,
+}
+
+impl<L> SharedLog<L> {
+    /// Share the given log
+    pub fn new(log: L) -> Self {
+        Self {
+            log: RwLock::new(log),
+        }
+    }
+
+    /// Lock the log for reading
+    pub fn read(&self) -> Result<RwLockReadGuard<'_, L>, SharedLogError> {
+        self.log.read().map_err(|_| SharedLogError::Poisoned)
+    }
+
+    /// Lock the log for writing
+    pub fn write(&self) -> Result<RwLockWriteGuard<'_, L>, SharedLogError> {
+        self.log.write().map_err(|_| SharedLogError::Poisoned)
+    }
+
+    /// Get back the underlying log
+    pub fn into_inner(self) -> Result<L, SharedLogError> {
+        self.log.into_inner().map_err(|_| SharedLogError::Poisoned)
+    }
+}
+
+/// Records are cloned out of the lock, so they are always returned owned
+impl<'a, T, L> TransparentLog<'a, T> for &SharedLog<L>
+where
+    T: Serialize + Deserialize<'a> + Clone,
+    L: TransparentLog<'a, T>,
+{
+    type LogSize = L::LogSize;
+
+    fn add(&mut self, record: T) -> anyhow::Result<Self::LogSize> {
+        self.write()?.add(record)
+    }
+
+    fn add_tombstone(&mut self) -> anyhow::Result<Self::LogSize> {
+        self.write()?.add_tombstone()
+    }
+
+    fn redact(&mut self, id: Self::LogSize) -> anyhow::Result<()> {
+        self.write()?.redact(id)
+    }
+
+    fn expire(&mut self, id: Self::LogSize) -> anyhow::Result<()> {
+        self.write()?.expire(id)
+    }
+
+    fn expired(&self, id: Self::LogSize) -> anyhow::Result<bool> {
+        self.read()?.expired(id)
+    }
+
+    fn seal(&mut self) -> anyhow::Result<LogTree<Self::LogSize>> {
+        self.write()?.seal()
+    }
+
+    fn sealed(&self) -> anyhow::Result<Option<LogTree<Self::LogSize>>> {
+        self.read()?.sealed()
+    }
+
+    fn sync(&self) -> anyhow::Result<()> {
+        self.read()?.sync()
+    }
+
+    fn add_meta(&mut self, id: Self::LogSize, meta: RecordMeta) -> anyhow::Result<()> {
+        self.write()?.add_meta(id, meta)
+    }
+
+    fn get_meta(&self, id: Self::LogSize) -> anyhow::Result<Option<RecordMeta>> {
+        self.read()?.get_meta(id)
+    }
+
+    fn set_annotation(&mut self, id: Self::LogSize, annotation: Vec<u8>) -> anyhow::Result<()> {
+        self.write()?.set_annotation(id, annotation)
+    }
+
+    fn get_annotation(&self, id: Self::LogSize) -> anyhow::Result<Option<Vec<u8>>> {
+        self.read()?.get_annotation(id)
+    }
+
+    fn find_since(&self, timestamp: u64) -> anyhow::Result<Self::LogSize> {
+        self.read()?.find_since(timestamp)
+    }
+
+    fn add_hash(&mut self, level: LogHeight, hash: String) -> anyhow::Result<Self::LogSize> {
+        self.write()?.add_hash(level, hash)
+    }
+
+    fn get_hash(
+        &self,
+        level: LogHeight,
+        index: Self::LogSize,
+    ) -> anyhow::Result<MaybeOwned<'_, String>> {
+        Ok(self.read()?.get_hash(level, index)?.into_owned().into())
+    }
+
+    fn tile(&self, level: LogHeight, index: Self::LogSize) -> anyhow::Result<Tile<Self::LogSize>> {
+        self.read()?.tile(level, index)
+    }
+
+    fn append(&mut self, record: T) -> anyhow::Result<Record<Self::LogSize>> {
+        self.write()?.append(record)
+    }
+
+    fn append_hash_only(&mut self, hash: String) -> anyhow::Result<Record<Self::LogSize>> {
+        self.write()?.append_hash_only(hash)
+    }
+
+    fn bulk_append(&mut self, records: Vec<T>) -> anyhow::Result<Vec<Record<Self::LogSize>>>
+    where
+        T: Sync,
+    {
+        self.write()?.bulk_append(records)
+    }
+
+    fn push_hash(&mut self, level: LogHeight, hash: String) -> anyhow::Result<Self::LogSize> {
+        self.write()?.push_hash(level, hash)
+    }
+
+    fn size(&self) -> anyhow::Result<Self::LogSize> {
+        self.read()?.size()
+    }
+
+    fn origin(&self) -> Option<String> {
+        self.read().ok()?.origin()
+    }
+
+    fn leaf_hasher(&self) -> LeafHasher<T> {
+        self.read().map(|log| log.leaf_hasher()).unwrap_or(hash)
+    }
+
+    fn latest(&self) -> anyhow::Result<LogTree<Self::LogSize>> {
+        self.read()?.latest()
+    }
+
+    fn ephemeral_hash(&self, level: LogHeight, index: Self::LogSize) -> anyhow::Result<String> {
+        self.read()?.ephemeral_hash(level, index)
+    }
+
+    fn root_at(&self, size: Self::LogSize) -> anyhow::Result<String> {
+        self.read()?.root_at(size)
+    }
+
+    fn head_at(&self, size: Self::LogSize) -> anyhow::Result<LogTree<Self::LogSize>> {
+        self.read()?.head_at(size)
+    }
+
+    fn get(&self, index: Self::LogSize) -> anyhow::Result<Option<MaybeOwned<'_, T>>> {
+        Ok(self
+            .read()?
+            .get(index)?
+            .map(|r| MaybeOwned::Owned(r.into_owned())))
+    }
+
+    fn get_range(
+        &self,
+        start: Self::LogSize,
+        end: Self::LogSize,
+    ) -> anyhow::Result<Vec<MaybeOwned<'_, T>>> {
+        Ok(self
+            .read()?
+            .get_range(start, end)?
+            .into_iter()
+            .map(|r| MaybeOwned::Owned(r.into_owned()))
+            .collect())
+    }
+
+    fn get_raw_bytes(&self, index: Self::LogSize) -> anyhow::Result<Option<Vec<u8>>> {
+        self.read()?.get_raw_bytes(index)
+    }
+
+    fn get_leaf_bytes(&self, index: Self::LogSize) -> anyhow::Result<Option<Vec<u8>>> {
+        self.read()?.get_leaf_bytes(index)
+    }
+
+    fn index_of(&self, hash: &str) -> anyhow::Result<Option<Self::LogSize>> {
+        self.read()?.index_of(hash)
+    }
+
+    fn indices_of(&self, hash: &str) -> anyhow::Result<Vec<Self::LogSize>> {
+        self.read()?.indices_of(hash)
+    }
+
+    fn proofs<I>(
+        &self,
+        positions: I,
+    ) -> anyhow::Result<HashMap<LogTreePosition<Self::LogSize>, String>>
+    where
+        I: Iterator<Item = LogTreePosition<Self::LogSize>>,
+    {
+        self.read()?.proofs(positions)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::test_helpers::*;
+    use crate::*;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn shared_13() -> anyhow::Result<()> {
+        let shared = SharedLog::new(InMemoryLog::default());
+        let mut ml = &shared;
+        test_13(&mut ml)?;
+        check_13(&ml)
+    }
+
+    #[test]
+    fn shared_hash_only() -> anyhow::Result<()> {
+        let shared = SharedLog::new(InMemoryLog::default());
+        let mut ml = &shared;
+        hash_only(&mut ml)?;
+        redact(&mut &SharedLog::new(InMemoryLog::default()))
+    }
+
+    #[test]
+    fn shared_duplicates() -> anyhow::Result<()> {
+        let shared = SharedLog::new(InMemoryLog::default());
+        duplicates(&mut &shared)?;
+        let bytes = (&shared).get_raw_bytes(5)?.unwrap();
+        assert_eq!(hash(&LogRecord::new("rec1"))?, hash_bytes(&bytes));
+        Ok(())
+    }
+
+    #[test]
+    fn shared_threads() -> anyhow::Result<()> {
+        let shared: Arc<SharedLog<InMemoryLog<LogRecord>>> = Arc::default();
+        let writers: Vec<_> = (0..2)
+            .map(|t| {
+                let shared = shared.clone();
+                thread::spawn(move || -> anyhow::Result<()> {
+                    let mut ml = &*shared;
+                    for i in 0..50 {
+                        ml.append(LogRecord::new(&format!("rec{}-{}", t, i)))?;
+                    }
+                    Ok(())
+                })
+            })
+            .collect();
+        let readers: Vec<_> = (0..2)
+            .map(|_| {
+                let shared = shared.clone();
+                thread::spawn(move || -> anyhow::Result<()> {
+                    let ml = &*shared;
+                    let mut client = InMemoryLogClientBuilder::new(&ml)?.build();
+                    for _ in 0..50 {
+                        if let Some(r) = ml.get(0)? {
+                            let record = Record {
+                                id: 0,
+                                hash: hash(r.as_ref())?,
+                                meta: None,
+                            };
+                            assert!(check_record(&mut client, &ml, &record)?);
+                        }
+                    }
+                    Ok(())
+                })
+            })
+            .collect();
+        for h in writers.into_iter().chain(readers) {
+            h.join().expect("thread panicked")?;
+        }
+
+        let ml = Arc::try_unwrap(shared)
+            .expect("log still shared")
+            .into_inner()?;
+        assert_eq!(100, ml.size()?);
+        let mut expected = InMemoryLog::default();
+        for i in 0..ml.size()? {
+            expected.append(ml.get(i)?.unwrap().into_owned())?;
+        }
+        assert_eq!(expected.latest()?, ml.latest()?);
+        Ok(())
+    }
+}
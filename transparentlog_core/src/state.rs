@@ -0,0 +1,134 @@
+//! # Client state
+//!
+//! The state of a client, its latest verified tree and its cached positions, persists as a JSON document
+//! carrying the version of its layout. Fields added later are optional and ignored by older readers, so only
+//! incompatible changes bump the version, and `ClientState::from_json` upgrades the documents of all older versions.
+//! Version 1 is a tree head saved with `to_json`, as clients were persisted before their cache could be.
+use crate::base::*;
+use crate::json::ProofHash;
+#[cfg(feature = "json")]
+use crate::json::Versioned;
+#[cfg(feature = "json")]
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// The version of the layout of the client state
+pub const CLIENT_STATE_VERSION: u32 = 2;
+
+/// Specific Errors for client states
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum ClientStateError {
+    #[error("Client state without a version")]
+    NoVersion,
+    #[error("Unsupported client state version: {0}")]
+    UnsupportedVersion(u32),
+}
+
+/// The persisted state of a client: its latest verified tree and, if it caches them, its cached positions
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(bound(deserialize = "LogSize: Deserialize<'de>"))]
+pub struct ClientState<LogSize> {
+    pub latest: LogTree<LogSize>,
+    #[serde(default)]
+    pub cache: Option<Vec<ProofHash<LogSize>>>,
+}
+
+#[cfg(feature = "json")]
+impl<LogSize: Serialize + DeserializeOwned> ClientState<LogSize> {
+    /// Serialize the state as a JSON document of the current version
+    pub fn to_json(&self) -> anyhow::Result<String> {
+        Ok(serde_json::to_string(&Versioned {
+            version: CLIENT_STATE_VERSION,
+            value: self,
+        })?)
+    }
+
+    /// Deserialize a state from a JSON document of the current version or an older one
+    pub fn from_json(json: &str) -> anyhow::Result<Self> {
+        let document: serde_json::Value = serde_json::from_str(json)?;
+        let version = document
+            .get("version")
+            .and_then(serde_json::Value::as_u64)
+            .ok_or(ClientStateError::NoVersion)?;
+        match version {
+            1 => Ok(Self {
+                latest: serde_json::from_value(document)?,
+                cache: None,
+            }),
+            2 => Ok(serde_json::from_value(document)?),
+            v => Err(ClientStateError::UnsupportedVersion(v as u32).into()),
+        }
+    }
+
+    /// Rewrite a JSON document of any supported version as a document of the current version
+    pub fn upgrade(json: &str) -> anyhow::Result<String> {
+        Self::from_json(json)?.to_json()
+    }
+}
+
+#[cfg(all(test, feature = "json"))]
+mod tests {
+
+    use crate::test_helpers::*;
+    use crate::*;
+
+    #[test]
+    fn state_round_trip() -> anyhow::Result<()> {
+        let mut ml = InMemoryLog::default();
+        append_multiple(&mut ml, 12)?;
+        let mut client = InMemoryLogClientBuilder::new(&ml)?.build();
+        let record = ml.append(LogRecord::new("rec12"))?;
+        assert!(check_record(&mut client, &ml, &record)?);
+
+        let state = client.state();
+        assert_eq!(&ml.latest()?, &state.latest);
+        assert!(!state.cache.as_ref().unwrap().is_empty());
+        let json = state.to_json()?;
+        assert!(json.starts_with("{\"version\":2,"));
+        assert_eq!(state, ClientState::from_json(&json)?);
+
+        let mut restored = InMemoryLogClient::from_state(ClientState::from_json(&json)?);
+        assert_eq!(client.latest(), restored.latest());
+        assert_eq!(state, restored.state());
+        let record = ml.append(LogRecord::new("rec13"))?;
+        assert!(check_record(&mut restored, &ml, &record)?);
+
+        let client = InMemoryLogClientBuilder::new(&ml)?.no_cache().build();
+        assert_eq!(None, client.state().cache);
+        Ok(())
+    }
+
+    #[test]
+    fn state_versions() -> anyhow::Result<()> {
+        let mut ml = InMemoryLog::default();
+        append_multiple(&mut ml, 13)?;
+        let tree = ml.latest()?;
+
+        // a tree head persisted before client states had a version of their own
+        let state = ClientState::from_json(&to_json(&tree)?)?;
+        assert_eq!(tree, state.latest);
+        assert_eq!(None, state.cache);
+        let json = ClientState::<usize>::upgrade(&to_json(&tree)?)?;
+        assert_eq!(state, ClientState::from_json(&json)?);
+
+        // fields added by later versions are ignored
+        let json = format!(
+            "{{\"version\":2,\"latest\":{},\"cache\":null,\"witnesses\":[]}}",
+            serde_json::to_string(&tree)?
+        );
+        assert_eq!(state, ClientState::from_json(&json)?);
+
+        let err = ClientState::<usize>::from_json("{\"version\":3}").unwrap_err();
+        assert_eq!(
+            Some(&ClientStateError::UnsupportedVersion(3)),
+            err.downcast_ref::<ClientStateError>()
+        );
+        let err = ClientState::<usize>::from_json(&serde_json::to_string(&tree)?).unwrap_err();
+        assert_eq!(
+            Some(&ClientStateError::NoVersion),
+            err.downcast_ref::<ClientStateError>()
+        );
+        Ok(())
+    }
+}
@@ -1,17 +1,28 @@
 //! # Test utilities
-//! 
+//!
 //! This module provides some structs, useful methods and test methods for implementors of the TransparentLog API
-//! 
+//!
 use crate::{
-    check_record, hash, prefix_proof_positions, proof_positions, verify, verify_tree, LogClient,
-    LogTree, Record, TransparentLog,
+    audit_positions, check_record, check_records, first_leaf, get_verified_bytes, hash, hash_bytes,
+    hash_pair, hash_with, import, prefix_proof_positions, proof_positions, prove_all_by_hash,
+    prove_by_hash, range_proof, tile_proofs, tree_sizes, verify, verify_audit_path,
+    verify_consistency_between, verify_range, verify_tree, CheckDepth, Codec, Compressor,
+    HistoryError, InMemoryLog, LogClient, LogError, LogTree, Mirror, Record, RecordMeta, SealError,
+    TombstoneError, TransparentLog,
 };
 use core::ops::Deref;
-use crypto::{digest::Digest, sha2::Sha256};
-use num::{One, Zero};
-use serde::{Deserialize, Serialize};
+use num::{FromPrimitive, One, ToPrimitive, Zero};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use std::fmt::Debug;
 use std::fmt::{Display, Formatter, Result};
+use transparentlog_verify::sha256_hex;
+
+#[cfg(any(test, feature = "proptest"))]
+use proptest::{
+    collection, prop_oneof,
+    strategy::{Just, Strategy},
+    test_runner::{Config, TestCaseError, TestRunner},
+};
 
 /// The records we store in the log
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
@@ -34,6 +45,38 @@ impl Display for LogRecord {
     }
 }
 
+/// A run-length encoding compressor, to test compressed logs
+#[derive(Debug)]
+pub struct RleCompressor;
+
+impl Compressor for RleCompressor {
+    fn name(&self) -> &str {
+        "rle"
+    }
+
+    /// Write each run as its length then its byte
+    fn compress(&self, data: &[u8]) -> anyhow::Result<Vec<u8>> {
+        let mut v = vec![];
+        for b in data {
+            match v.len() {
+                l if l > 0 && v[l - 1] == *b && v[l - 2] < u8::MAX => v[l - 2] += 1,
+                _ => v.extend_from_slice(&[1, *b]),
+            }
+        }
+        Ok(v)
+    }
+
+    fn decompress(&self, data: &[u8]) -> anyhow::Result<Vec<u8>> {
+        if data.len() % 2 == 1 {
+            return Err(anyhow::anyhow!("truncated run length encoding"));
+        }
+        Ok(data
+            .chunks_exact(2)
+            .flat_map(|c| std::iter::repeat(c[1]).take(c[0] as usize))
+            .collect())
+    }
+}
+
 /// Append multiple records in order to the log, all named rec<ix> where ix is their index in the log
 pub fn append_multiple<'a, T: TransparentLog<'a, LogRecord>>(
     log: &mut T,
@@ -71,23 +114,21 @@ pub fn hash_two(start: usize) -> anyhow::Result<String> {
 
 /// Hash two strings
 pub fn hash_two_strings(s1: &str, s2: &str) -> String {
-    let mut hasher = Sha256::new();
-    hasher.input_str(&format!("{}{}", s1, s2));
-    hasher.result_str()
+    sha256_hex(format!("{}{}", s1, s2).as_bytes())
 }
 
 /// Hash four records starting from the given offset
 pub fn hash_four(start: usize) -> anyhow::Result<String> {
-    let mut hasher = Sha256::new();
-    hasher.input_str(&format!("{}{}", hash_two(start)?, hash_two(start + 2)?));
-    Ok(hasher.result_str())
+    Ok(sha256_hex(
+        format!("{}{}", hash_two(start)?, hash_two(start + 2)?).as_bytes(),
+    ))
 }
 
 /// Hash eight records starting from the given offset
 pub fn hash_eight(start: usize) -> anyhow::Result<String> {
-    let mut hasher = Sha256::new();
-    hasher.input_str(&format!("{}{}", hash_four(start)?, hash_four(start + 4)?));
-    Ok(hasher.result_str())
+    Ok(sha256_hex(
+        format!("{}{}", hash_four(start)?, hash_four(start + 4)?).as_bytes(),
+    ))
 }
 
 /// Test an empty log
@@ -96,12 +137,59 @@ where
     T: TransparentLog<'a, LogRecord>,
     T::LogSize: Debug,
 {
-    let LogTree { size: s, hash: t } = ml.latest()?;
+    let LogTree {
+        size: s, hash: t, ..
+    } = ml.latest()?;
     assert_eq!(T::LogSize::zero(), s);
     assert_eq!("", &t);
     Ok(())
 }
 
+/// Test that out of range reads return None, empty results or errors, without panicking
+pub fn out_of_range<'a, T>(ml: &mut T) -> anyhow::Result<()>
+where
+    T: TransparentLog<'a, LogRecord>,
+    T::LogSize: Debug + Display + From<u8> + Send + Sync,
+{
+    let invalid_height = |r: anyhow::Result<()>, level| {
+        matches!(r.unwrap_err().downcast_ref::<LogError<T::LogSize>>(),
+            Some(LogError::InvalidHeight(l)) if *l == level)
+    };
+    let invalid_index = |r: anyhow::Result<()>, level, index: u8| {
+        matches!(r.unwrap_err().downcast_ref::<LogError<T::LogSize>>(),
+            Some(LogError::InvalidIndex(l, i)) if *l == level && *i == index.into())
+    };
+    assert!(ml.get(0.into())?.is_none());
+    assert!(invalid_height(ml.get_hash(0, 0.into()).map(drop), 0));
+    assert!(ml.get_meta(0.into())?.is_none());
+
+    append_multiple(ml, 13)?;
+    assert!(ml.get(13.into())?.is_none());
+    assert!(ml.get(200.into())?.is_none());
+    assert!(ml.get_range(13.into(), 20.into())?.is_empty());
+    assert!(ml.get_range(20.into(), 10.into())?.is_empty());
+    assert!(ml.get_meta(200.into())?.is_none());
+    assert!(invalid_index(ml.get_hash(0, 13.into()).map(drop), 0, 13));
+    assert!(invalid_index(ml.get_hash(1, 6.into()).map(drop), 1, 6));
+    assert!(invalid_index(ml.get_hash(3, 1.into()).map(drop), 3, 1));
+    assert!(invalid_height(ml.get_hash(4, 0.into()).map(drop), 4));
+    assert!(invalid_height(ml.get_hash(100, 0.into()).map(drop), 100));
+    assert!(invalid_index(
+        ml.proofs(vec![(0, 12.into()).into(), (2, 3.into()).into()].into_iter())
+            .map(drop),
+        2,
+        3
+    ));
+    assert!(invalid_height(
+        ml.proofs(vec![(0, 12.into()).into(), (5, 0.into()).into()].into_iter())
+            .map(drop),
+        5
+    ));
+    assert!(invalid_height(ml.add_hash(6, hash_two(0)?).map(drop), 6));
+    assert_eq!(Into::<T::LogSize>::into(13), ml.latest()?.size);
+    check_13(ml)
+}
+
 /// Test adding two records to an empty log
 pub fn add<'a, T>(ml: &mut T) -> anyhow::Result<()>
 where
@@ -111,26 +199,32 @@ where
 {
     let lr1 = LogRecord::new("rec1");
     let h1 = hash(&lr1)?;
-    let Record { id: ix, hash: h1s } = ml.append(lr1)?;
+    let Record {
+        id: ix, hash: h1s, ..
+    } = ml.append(lr1)?;
     assert_eq!(T::LogSize::zero(), ix);
     assert_eq!(h1, h1s);
     let og = ml.get(ix)?;
     assert_eq!("rec1", og.unwrap().text);
-    let LogTree { size: s, hash: t } = ml.latest()?;
+    let LogTree {
+        size: s, hash: t, ..
+    } = ml.latest()?;
     assert_eq!(T::LogSize::one(), s);
     assert_eq!(h1, t);
     let lr2 = LogRecord::new("rec2");
     let h2 = hash(&lr2)?;
-    let Record { id: ix, hash: h2s } = ml.append(lr2)?;
+    let Record {
+        id: ix, hash: h2s, ..
+    } = ml.append(lr2)?;
     assert_eq!(Into::<T::LogSize>::into(1), ix);
     assert_eq!(h2, h2s);
     let og = ml.get(ix)?;
     assert_eq!("rec2", og.unwrap().text);
-    let LogTree { size: s, hash: t } = ml.latest()?;
+    let LogTree {
+        size: s, hash: t, ..
+    } = ml.latest()?;
     assert_eq!(Into::<T::LogSize>::into(2), s);
-    let mut hasher = Sha256::new();
-    hasher.input_str(&format!("{}{}", h1, h2));
-    assert_eq!(hasher.result_str(), t);
+    assert_eq!(sha256_hex(format!("{}{}", h1, h2).as_bytes()), t);
     let v = ml.proofs(proof_positions::<T::LogSize>(1.into(), 2.into()).into_iter())?;
     assert_eq!(1, v.len());
     assert_eq!(
@@ -138,16 +232,587 @@ where
         Some(&hash(&LogRecord::new("rec1"))?)
     );
     assert!(verify(
-        &LogTree { size: s, hash: t },
+        &LogTree {
+            size: s,
+            hash: t,
+            origin: None,
+        },
         &Record {
             id: 1.into(),
-            hash: hash(&LogRecord::new("rec2"))?
+            hash: hash(&LogRecord::new("rec2"))?,
+            meta: None
         },
         &v
     ));
     Ok(())
 }
 
+/// Test looking up records by their hash
+pub fn lookup<'a, T>(ml: &mut T) -> anyhow::Result<()>
+where
+    T: TransparentLog<'a, LogRecord>,
+    T::LogSize: Debug,
+    T::LogSize: From<u8>,
+{
+    assert_eq!(None, ml.index_of(&hash(&LogRecord::new("rec0"))?)?);
+    append_multiple(ml, 5)?;
+    for i in 0..5_u8 {
+        assert_eq!(
+            Some(i.into()),
+            ml.index_of(&hash(&LogRecord::new(&format!("rec{}", i)))?)?
+        );
+    }
+    assert_eq!(None, ml.index_of(&hash(&LogRecord::new("rec5"))?)?);
+    let Record { id: ix, .. } = ml.append(LogRecord::new("rec2"))?;
+    assert_eq!(Into::<T::LogSize>::into(5), ix);
+    assert_eq!(
+        Some(2.into()),
+        ml.index_of(&hash(&LogRecord::new("rec2"))?)?
+    );
+    Ok(())
+}
+
+/// Test reading ranges of records
+pub fn range<'a, T>(ml: &mut T) -> anyhow::Result<()>
+where
+    T: TransparentLog<'a, LogRecord>,
+    T::LogSize: Debug,
+    T::LogSize: From<u8>,
+{
+    assert!(ml.get_range(0.into(), 5.into())?.is_empty());
+    append_multiple(ml, 10)?;
+    let v = ml.get_range(3.into(), 7.into())?;
+    assert_eq!(4, v.len());
+    for (i, r) in v.iter().enumerate() {
+        assert_eq!(format!("rec{}", i + 3), r.text);
+    }
+    assert_eq!(10, ml.get_range(0.into(), 10.into())?.len());
+    let v = ml.get_range(8.into(), 20.into())?;
+    assert_eq!(2, v.len());
+    assert_eq!("rec9", v[1].text);
+    assert!(ml.get_range(7.into(), 7.into())?.is_empty());
+    assert!(ml.get_range(12.into(), 15.into())?.is_empty());
+    Ok(())
+}
+
+/// Test iterating over all records
+pub fn iter<'a, T>(ml: &mut T) -> anyhow::Result<()>
+where
+    T: TransparentLog<'a, LogRecord>,
+    T::LogSize: Debug,
+    T::LogSize: From<u8>,
+{
+    assert_eq!(0, ml.iter().count());
+    append_multiple(ml, 10)?;
+    let mut count = 0_u8;
+    for r in ml.iter() {
+        let (id, lr) = r?;
+        assert_eq!(Into::<T::LogSize>::into(count), id);
+        assert_eq!(format!("rec{}", count), lr.text);
+        count += 1;
+    }
+    assert_eq!(10, count);
+    Ok(())
+}
+
+/// Test getting proofs through tiles
+pub fn tiles<'a, T>(ml: &mut T) -> anyhow::Result<()>
+where
+    T: TransparentLog<'a, LogRecord>,
+    T::LogSize: Debug,
+    T::LogSize: From<u8> + ToPrimitive + Display + Send + Sync,
+{
+    append_multiple(ml, 600)?;
+    let size = ml.size()?;
+    assert!(ml.tile(0, 0.into())?.is_full());
+    assert_eq!(88, ml.tile(0, 2.into())?.hashes.len());
+    assert!(ml.tile(0, 3.into())?.hashes.is_empty());
+    assert_eq!(2, ml.tile(1, 0.into())?.hashes.len());
+    assert!(ml.tile(2, 0.into())?.hashes.is_empty());
+
+    let tree = ml.latest()?;
+    let indices: Vec<T::LogSize> = vec![
+        0.into(),
+        9.into(),
+        255.into(),
+        Into::<T::LogSize>::into(255) + T::LogSize::one(),
+        Into::<T::LogSize>::into(150) * 2.into(),
+        size - T::LogSize::one(),
+    ];
+    for index in indices {
+        let positions = proof_positions(index, size);
+        let proofs = tile_proofs(ml, &positions)?;
+        assert_eq!(ml.proofs(positions.into_iter())?, proofs);
+        let lr = ml.get(index)?.unwrap();
+        assert!(verify(
+            &tree,
+            &Record {
+                id: index,
+                hash: hash(lr.deref())?,
+                meta: None
+            },
+            &proofs
+        ));
+    }
+    Ok(())
+}
+
+/// Test getting the proofs for many records at once
+pub fn batch_proofs<'a, T>(ml: &mut T) -> anyhow::Result<()>
+where
+    T: TransparentLog<'a, LogRecord>,
+    T::LogSize: Debug,
+    T::LogSize: From<u8>,
+{
+    append_multiple(ml, 100)?;
+    let size = ml.size()?;
+    let mut positions = vec![];
+    for i in 0..100_u8 {
+        positions.extend(proof_positions(i.into(), size));
+    }
+    let proofs = ml.proofs(positions.clone().into_iter())?;
+    assert!(proofs.len() < positions.len());
+    for p in positions {
+        assert_eq!(
+            ml.get_hash(p.level, p.index)?.as_ref(),
+            proofs.get(&p).unwrap()
+        );
+    }
+    Ok(())
+}
+
+/// Test proving ranges of records
+pub fn range_proofs<'a, T>(ml: &mut T) -> anyhow::Result<()>
+where
+    T: TransparentLog<'a, LogRecord>,
+    T::LogSize: Debug + Display + Send + Sync,
+    T::LogSize: From<u8>,
+{
+    append_multiple(ml, 8)?;
+    let tree8 = ml.latest()?;
+    append_multiple_offset(ml, 8, 5)?;
+    let tree = ml.latest()?;
+    let hashes = (0..13_u8)
+        .map(|i| Ok(ml.get_hash(0, i.into())?.into_owned()))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    for start in 0..13_u8 {
+        for end in (start + 1)..=13_u8 {
+            let range = &hashes[start as usize..end as usize];
+            let proof = range_proof(ml, start.into(), end.into(), 13.into())?;
+            assert!(proof.proofs.len() <= 2 * tree_sizes(tree.size).len());
+            assert!(proof.verify(&tree, range), "range {} {}", start, end);
+            assert!(!proof.verify(&tree, &range[1..]));
+            let mut forged = range.to_vec();
+            forged[0] = hashes[(start as usize + 1) % 13].clone();
+            assert!(!proof.verify(&tree, &forged));
+            if end > start + 1 {
+                // a proof position standing for the leaves of the range is ignored
+                assert!(!verify_range(
+                    &tree,
+                    start.into(),
+                    &range[1..],
+                    &proof.proofs
+                ));
+            }
+        }
+    }
+    let proof = range_proof(ml, 2.into(), 6.into(), 8.into())?;
+    assert!(proof.verify(&tree8, &hashes[2..6]));
+    assert!(!proof.verify(&tree, &hashes[2..6]));
+    assert!(range_proof(ml, 5.into(), 5.into(), 13.into()).is_err());
+    assert!(range_proof(ml, 0.into(), 14.into(), 14.into()).is_err());
+    Ok(())
+}
+
+/// Test the root hashes and tree heads of past sizes, and the hashes of pending nodes
+pub fn historic_roots<'a, T>(ml: &mut T) -> anyhow::Result<()>
+where
+    T: TransparentLog<'a, LogRecord>,
+    T::LogSize: Debug,
+    T::LogSize: From<u8>,
+{
+    let mut heads = vec![ml.latest()?];
+    for i in 0..13_u8 {
+        ml.append(LogRecord::new(&format!("line {}", i)))?;
+        heads.push(ml.latest()?);
+    }
+    for (size, head) in heads.iter().enumerate() {
+        assert_eq!(head.hash, ml.root_at((size as u8).into())?, "size {}", size);
+        assert_eq!(head, &ml.head_at((size as u8).into())?);
+    }
+    let roots: Vec<String> = heads.into_iter().map(|h| h.hash).collect();
+
+    // a proof for a past size checks against the past head
+    let record = Record {
+        id: 2.into(),
+        hash: ml.get_hash(0, 2.into())?.into_owned(),
+        meta: None,
+    };
+    let proofs = ml.proofs(proof_positions(2.into(), 5.into()).into_iter())?;
+    assert!(verify(&ml.head_at(5.into())?, &record, &proofs));
+    assert!(!verify(&ml.latest()?, &record, &proofs));
+    assert!(ml.head_at(14.into()).is_err());
+    assert!(matches!(
+        ml.root_at(14.into())
+            .err()
+            .unwrap()
+            .downcast_ref::<HistoryError>(),
+        Some(HistoryError::BeyondLog)
+    ));
+
+    // the root of 13 records is a pending node
+    assert_eq!(roots[13], ml.ephemeral_hash(4, 0.into())?);
+    assert_eq!(
+        ml.get_hash(3, 0.into())?.as_str(),
+        ml.ephemeral_hash(3, 0.into())?
+    );
+    assert_eq!(roots[4], ml.ephemeral_hash(2, 0.into())?);
+    assert_eq!(
+        hash_pair(&ml.get_hash(2, 2.into())?, &ml.get_hash(0, 12.into())?),
+        ml.ephemeral_hash(3, 1.into())?
+    );
+    assert_eq!(
+        ml.get_hash(0, 12.into())?.as_str(),
+        ml.ephemeral_hash(1, 6.into())?
+    );
+    assert!(matches!(
+        ml.ephemeral_hash(1, 7.into())
+            .err()
+            .unwrap()
+            .downcast_ref::<HistoryError>(),
+        Some(HistoryError::EmptyPosition)
+    ));
+    Ok(())
+}
+
+/// Test audit paths against the current and past tree heads, and their positions against the proof positions
+pub fn audit_paths<'a, T>(ml: &mut T) -> anyhow::Result<()>
+where
+    T: TransparentLog<'a, LogRecord>,
+    T::LogSize: Debug,
+    T::LogSize: From<u8>,
+{
+    append_multiple(ml, 13)?;
+    for size in 1..=13_u8 {
+        let head = ml.head_at(size.into())?;
+        for id in 0..size {
+            let record = Record {
+                id: id.into(),
+                hash: ml.get_hash(0, id.into())?.into_owned(),
+                meta: None,
+            };
+            let path = ml.audit_path(id.into(), size.into())?;
+            assert!(verify_audit_path(&head, &record, &path), "{} {}", id, size);
+            // pending nodes are in the path, but no node without a record of the tree
+            assert!(audit_positions(T::LogSize::from(id), size.into())
+                .all(|p| first_leaf(&p) < T::LogSize::from(size)));
+            if !path.is_empty() {
+                let mut shorter = path.clone();
+                shorter.pop();
+                assert!(!verify_audit_path(&head, &record, &shorter));
+                let mut reversed = path.clone();
+                reversed.reverse();
+                assert!(path.len() == 1 || !verify_audit_path(&head, &record, &reversed));
+            }
+        }
+    }
+
+    // record 9 of 13 records: its sibling 8, the node 5 at level 1, the pending node 3 at level 2, the left half
+    let positions: Vec<_> = audit_positions(T::LogSize::from(9), 13.into())
+        .map(|p| (p.level, p.index))
+        .collect();
+    assert_eq!(
+        vec![(0, 8.into()), (1, 5.into()), (2, 3.into()), (3, 0.into())],
+        positions
+    );
+    let path = ml.audit_path(9.into(), 13.into())?;
+    assert_eq!(ml.get_hash(0, 12.into())?.as_str(), path[2]);
+    let record = Record {
+        id: 9.into(),
+        hash: ml.get_hash(0, 9.into())?.into_owned(),
+        meta: None,
+    };
+    assert!(!verify_audit_path(&ml.head_at(12.into())?, &record, &path));
+    let mut forged = path.clone();
+    forged[2] = String::new();
+    assert!(!verify_audit_path(&ml.latest()?, &record, &forged));
+
+    assert!(matches!(
+        ml.audit_path(13.into(), 13.into())
+            .err()
+            .unwrap()
+            .downcast_ref::<HistoryError>(),
+        Some(HistoryError::EmptyPosition)
+    ));
+    assert!(matches!(
+        ml.audit_path(0.into(), 14.into())
+            .err()
+            .unwrap()
+            .downcast_ref::<HistoryError>(),
+        Some(HistoryError::BeyondLog)
+    ));
+    Ok(())
+}
+
+/// Test consistency proofs between past sizes, catching up through intermediate heads
+pub fn consistency_between_sizes<'a, T>(ml: &mut T) -> anyhow::Result<()>
+where
+    T: TransparentLog<'a, LogRecord>,
+    T::LogSize: Debug,
+    T::LogSize: From<u8>,
+{
+    append_multiple(ml, 13)?;
+    for size_a in 0..=13_u8 {
+        let head_a = ml.head_at(size_a.into())?;
+        for size_b in size_a..=13_u8 {
+            let head_b = ml.head_at(size_b.into())?;
+            let proof = ml.consistency_between(size_a.into(), size_b.into())?;
+            assert!(
+                proof.verify(&head_a, &head_b),
+                "sizes {} {}",
+                size_a,
+                size_b
+            );
+            assert!(verify_consistency_between(&head_a, &head_b, &proof.proofs));
+            if size_a < size_b {
+                assert!(!proof.verify(&head_b, &head_a));
+            }
+        }
+    }
+
+    // an old checkpoint catches up with the latest head through an intermediate one
+    let old = ml.head_at(3.into())?;
+    let middle = ml.head_at(7.into())?;
+    let latest = ml.latest()?;
+    assert!(ml
+        .consistency_between(3.into(), 7.into())?
+        .verify(&old, &middle));
+    assert!(ml
+        .consistency_between(7.into(), 13.into())?
+        .verify(&middle, &latest));
+    let forged = LogTree {
+        hash: latest.hash.clone(),
+        ..middle.clone()
+    };
+    assert!(!ml
+        .consistency_between(3.into(), 7.into())?
+        .verify(&old, &forged));
+    assert!(!ml
+        .consistency_between(3.into(), 13.into())?
+        .verify(&old, &middle));
+
+    assert!(matches!(
+        ml.consistency_between(7.into(), 3.into())
+            .err()
+            .unwrap()
+            .downcast_ref::<HistoryError>(),
+        Some(HistoryError::Decreasing)
+    ));
+    assert!(matches!(
+        ml.consistency_between(7.into(), 14.into())
+            .err()
+            .unwrap()
+            .downcast_ref::<HistoryError>(),
+        Some(HistoryError::BeyondLog)
+    ));
+    Ok(())
+}
+
+/// Test appending records in bulk, mixed with single appends
+pub fn bulk<'a, T>(ml: &mut T) -> anyhow::Result<()>
+where
+    T: TransparentLog<'a, LogRecord>,
+    T::LogSize: Debug,
+    T::LogSize: From<u8>,
+{
+    let mut reference: InMemoryLog<LogRecord> = InMemoryLog::default();
+    append_multiple(&mut reference, 150)?;
+
+    append_multiple(ml, 5)?;
+    let recs: Vec<LogRecord> = (5..25)
+        .map(|i| LogRecord::new(&format!("rec{}", i)))
+        .collect();
+    let records = ml.bulk_append(recs)?;
+    assert_eq!(20, records.len());
+    assert_eq!(Into::<T::LogSize>::into(5), records[0].id);
+    assert_eq!(hash(&LogRecord::new("rec5"))?, records[0].hash);
+    assert_eq!(Into::<T::LogSize>::into(24), records[19].id);
+    append_multiple_offset(ml, 25, 3)?;
+    let recs: Vec<LogRecord> = (28..150)
+        .map(|i| LogRecord::new(&format!("rec{}", i)))
+        .collect();
+    ml.bulk_append(recs)?;
+    assert!(ml.bulk_append(vec![])?.is_empty());
+
+    let tree = ml.latest()?;
+    assert_eq!(Into::<T::LogSize>::into(150), tree.size);
+    assert_eq!(reference.latest()?.hash, tree.hash);
+    let lr = LogRecord::new("rec77");
+    let id = Into::<T::LogSize>::into(77);
+    let proofs = ml.proofs(proof_positions(id, tree.size).into_iter())?;
+    assert!(verify(
+        &tree,
+        &Record {
+            id,
+            hash: hash(&lr)?,
+            meta: None
+        },
+        &proofs
+    ));
+    Ok(())
+}
+
+/// Test a log hashing and storing its records with the codec C
+pub fn codec_records<'a, C, T>(ml: &mut T) -> anyhow::Result<()>
+where
+    C: Codec,
+    T: TransparentLog<'a, LogRecord>,
+    T::LogSize: Debug + FromPrimitive + ToPrimitive,
+{
+    let recs: Vec<LogRecord> = (0..13)
+        .map(|i| LogRecord::new(&format!("rec{}", i)))
+        .collect();
+    let leaves = recs
+        .iter()
+        .map(hash_with::<C, _>)
+        .collect::<anyhow::Result<Vec<String>>>()?;
+    let mut records = vec![];
+    for r in recs.iter().take(5) {
+        records.push(ml.append(r.clone())?);
+    }
+    records.extend(ml.bulk_append(recs[5..].to_vec())?);
+    assert_eq!(
+        leaves,
+        records.iter().map(|r| r.hash.clone()).collect::<Vec<_>>()
+    );
+    assert_eq!(leaves[9], (ml.leaf_hasher())(&recs[9])?);
+
+    let tree = ml.latest()?;
+    assert_eq!(model_root(&leaves), tree.hash);
+    let proofs = ml.proofs(proof_positions(records[9].id, tree.size).into_iter())?;
+    assert!(verify(&tree, &records[9], &proofs));
+    assert_eq!(
+        recs,
+        ml.iter()
+            .map(|r| r.map(|(_, lr)| lr.into_owned()))
+            .collect::<anyhow::Result<Vec<_>>>()?
+    );
+    assert_eq!(
+        &recs[2..5],
+        ml.get_range(
+            T::LogSize::from_u8(2).unwrap(),
+            T::LogSize::from_u8(5).unwrap()
+        )?
+        .iter()
+        .map(|lr| lr.deref().clone())
+        .collect::<Vec<_>>()
+    );
+    assert_eq!(
+        Some(&recs[7]),
+        ml.get(T::LogSize::from_u8(7).unwrap())?.as_deref()
+    );
+    assert_eq!(Some(records[7].id), ml.index_of(&leaves[7])?);
+    Ok(())
+}
+
+/// Test record metadata and time queries
+pub fn timestamps<'a, T>(ml: &mut T) -> anyhow::Result<()>
+where
+    T: TransparentLog<'a, LogRecord>,
+    T::LogSize: Debug,
+    T::LogSize: From<u8>,
+{
+    assert_eq!(T::LogSize::zero(), ml.find_since(0)?);
+    let before = RecordMeta::now();
+    let r0 = ml.append(LogRecord::new("rec0"))?;
+    let r1 = ml.append(LogRecord::new("rec1"))?;
+    let meta0 = r0.meta.expect("no metadata on append");
+    let meta1 = r1.meta.expect("no metadata on append");
+    assert!(before <= meta0);
+    assert!(meta0 <= meta1);
+    assert_eq!(Some(meta0), ml.get_meta(r0.id)?);
+    assert_eq!(Some(meta1), ml.get_meta(r1.id)?);
+    assert_eq!(None, ml.get_meta(2.into())?);
+
+    append_multiple_offset(ml, 2, 3)?;
+    for i in 0..5_u8 {
+        ml.add_meta(
+            i.into(),
+            RecordMeta {
+                timestamp: 100 * (i as u64 + 1),
+            },
+        )?;
+    }
+    assert_eq!(T::LogSize::zero(), ml.find_since(0)?);
+    assert_eq!(T::LogSize::zero(), ml.find_since(100)?);
+    assert_eq!(Into::<T::LogSize>::into(2), ml.find_since(250)?);
+    assert_eq!(Into::<T::LogSize>::into(2), ml.find_since(300)?);
+    assert_eq!(Into::<T::LogSize>::into(5), ml.find_since(501)?);
+    assert!(ml.next_meta()?.timestamp >= 500);
+    Ok(())
+}
+
+/// Test exporting a log truncated at a given size and importing it into another log
+pub fn export_import<'a, T, T2>(ml: &mut T, ml2: &mut T2) -> anyhow::Result<()>
+where
+    T: TransparentLog<'a, LogRecord>,
+    T::LogSize: Debug + From<u8> + Serialize,
+    T2: TransparentLog<'a, LogRecord>,
+    T2::LogSize: Debug + From<u8> + DeserializeOwned,
+{
+    append_multiple(ml, 20)?;
+    let mut reference: InMemoryLog<LogRecord> = InMemoryLog::default();
+    append_multiple(&mut reference, 13)?;
+
+    let mut b = vec![];
+    let exported = ml.export(13.into(), &mut b)?;
+    assert_eq!(Into::<T::LogSize>::into(13), exported.size);
+    assert_eq!(reference.latest()?.hash, exported.hash);
+
+    let imported = import(ml2, &b[..])?;
+    assert_eq!(Into::<T2::LogSize>::into(13), imported.size);
+    assert_eq!(exported.hash, imported.hash);
+    assert_eq!(ml.get_meta(12.into())?, ml2.get_meta(12.into())?);
+    assert_eq!("rec12", ml2.get(12.into())?.unwrap().text);
+    assert!(import(ml2, &b[..]).is_err());
+
+    // the root hash is compared as a digest, whatever its case
+    let mut upper = b.clone();
+    let n = upper.len();
+    upper[n - 64..].make_ascii_uppercase();
+    let mut fresh: InMemoryLog<LogRecord> = InMemoryLog::default();
+    assert_eq!(exported.hash, import(&mut fresh, &upper[..])?.hash);
+
+    let mut fresh: InMemoryLog<LogRecord> = InMemoryLog::default();
+    let last = b.len() - 2;
+    b[last] ^= 1;
+    assert!(import(&mut fresh, &b[..]).is_err());
+    Ok(())
+}
+
+/// Test mirroring a log into an empty log, in several syncs
+pub fn mirror<'a, T, T2>(ml: &mut T, ml2: &mut T2) -> anyhow::Result<()>
+where
+    T: TransparentLog<'a, LogRecord>,
+    T::LogSize: Debug + Display + From<u8>,
+    T2: TransparentLog<'a, LogRecord, LogSize = T::LogSize>,
+{
+    let mut mirror = Mirror::default();
+    assert_eq!(ml.latest()?, mirror.sync(ml, ml2)?);
+    append_multiple(ml, 5)?;
+    assert_eq!(ml.latest()?, mirror.sync(ml, ml2)?);
+    assert_eq!(ml.latest()?, ml2.latest()?);
+
+    append_multiple_offset(ml, 5, 8)?;
+    mirror.batch_size(3.into());
+    let head = mirror.sync(ml, ml2)?;
+    assert_eq!(head, ml2.latest()?);
+    check_13(ml2)?;
+    assert_eq!(ml.get_meta(12.into())?, ml2.get_meta(12.into())?);
+    assert_eq!(head, mirror.sync(ml, ml2)?);
+    Ok(())
+}
+
 /// Test adding 13 records to an empty log
 pub fn test_13<'a, T>(ml: &mut T) -> anyhow::Result<()>
 where
@@ -159,6 +824,91 @@ where
     check_13(ml)
 }
 
+/// Test proving records by their leaf hash
+pub fn prove_hashes<'a, T>(ml: &mut T) -> anyhow::Result<()>
+where
+    T: TransparentLog<'a, LogRecord>,
+    T::LogSize: Debug + Display + Send + Sync,
+    T::LogSize: From<u8>,
+{
+    append_multiple(ml, 13)?;
+    let hash9 = hash(&LogRecord::new("rec9"))?;
+    let proof = prove_by_hash(ml, &hash9, 13.into())?.unwrap();
+    assert_eq!(Into::<T::LogSize>::into(9), proof.record.id);
+    assert_eq!(hash9, proof.record.hash);
+    assert!(proof.verify(&ml.latest()?));
+    let proof = prove_by_hash(ml, &hash9, 10.into())?.unwrap();
+    assert!(proof.verify(&ml.head_at(10.into())?));
+    assert!(prove_by_hash(ml, &hash9, 9.into())?.is_none());
+    assert!(prove_by_hash(ml, &hash(&LogRecord::new("rec13"))?, 13.into())?.is_none());
+    assert!(prove_by_hash(ml, &hash9, 14.into()).is_err());
+    Ok(())
+}
+
+/// Test looking up and proving a record appended several times
+pub fn duplicates<'a, T>(ml: &mut T) -> anyhow::Result<()>
+where
+    T: TransparentLog<'a, LogRecord>,
+    T::LogSize: Debug + Display + Send + Sync,
+    T::LogSize: From<u8>,
+{
+    append_multiple(ml, 5)?;
+    ml.append(LogRecord::new("rec1"))?;
+    ml.append(LogRecord::new("rec1"))?;
+    let hash1 = hash(&LogRecord::new("rec1"))?;
+    assert_eq!(Some(1.into()), ml.index_of(&hash1)?);
+    let expected: Vec<T::LogSize> = vec![1.into(), 5.into(), 6.into()];
+    assert_eq!(expected, ml.indices_of(&hash1)?);
+    assert_eq!(
+        vec![T::LogSize::zero()],
+        ml.indices_of(&hash(&LogRecord::new("rec0"))?)?
+    );
+    assert!(ml.indices_of(&hash(&LogRecord::new("rec7"))?)?.is_empty());
+
+    let tree = ml.latest()?;
+    let proofs = prove_all_by_hash(ml, &hash1, tree.size)?;
+    assert_eq!(3, proofs.len());
+    assert!(proofs
+        .iter()
+        .all(|p| p.record.hash == hash1 && p.verify(&tree)));
+    assert_eq!(2, prove_all_by_hash(ml, &hash1, 6.into())?.len());
+    assert!(prove_all_by_hash(ml, &hash1, 1.into())?.is_empty());
+    Ok(())
+}
+
+/// Test annotating records, without changing the tree
+pub fn annotations<'a, T>(ml: &mut T) -> anyhow::Result<()>
+where
+    T: TransparentLog<'a, LogRecord>,
+    T::LogSize: Debug + Display + Send + Sync,
+    T::LogSize: From<u8>,
+{
+    append_multiple(ml, 3)?;
+    let tree = ml.latest()?;
+    assert_eq!(None, ml.get_annotation(1.into())?);
+    ml.set_annotation(1.into(), b"source: api".to_vec())?;
+    assert_eq!(Some(b"source: api".to_vec()), ml.get_annotation(1.into())?);
+    ml.set_annotation(1.into(), b"hidden".to_vec())?;
+    ml.set_annotation(2.into(), vec![])?;
+    assert_eq!(Some(b"hidden".to_vec()), ml.get_annotation(1.into())?);
+    assert_eq!(Some(vec![]), ml.get_annotation(2.into())?);
+    assert_eq!(None, ml.get_annotation(0.into())?);
+    assert_eq!(tree, ml.latest()?);
+    assert_eq!(
+        Some(LogRecord::new("rec1")),
+        ml.get(1.into())?.map(|r| r.into_owned())
+    );
+
+    let err = ml
+        .set_annotation(3.into(), b"missing".to_vec())
+        .unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<LogError<T::LogSize>>(),
+        Some(LogError::InvalidIndex(0, i)) if *i == 3.into()
+    ));
+    Ok(())
+}
+
 /// Checking the behavior of a log containing 13 recods
 pub fn check_13<'a, T>(ml: &T) -> anyhow::Result<()>
 where
@@ -166,7 +916,9 @@ where
     T::LogSize: Debug,
     T::LogSize: From<u8>,
 {
-    let LogTree { size: s, hash: t } = ml.latest()?;
+    let LogTree {
+        size: s, hash: t, ..
+    } = ml.latest()?;
     assert_eq!(Into::<T::LogSize>::into(13), s);
     let v = ml.proofs(proof_positions::<T::LogSize>(9.into(), 13.into()).into_iter())?;
     assert_eq!(4, v.len());
@@ -191,13 +943,192 @@ where
     assert_eq!(t, h);
 
     assert!(verify(
-        &LogTree { size: s, hash: t },
+        &LogTree {
+            size: s,
+            hash: t,
+            origin: None,
+        },
         &Record {
             id: 9.into(),
-            hash: hash(&LogRecord::new("rec9"))?
+            hash: hash(&LogRecord::new("rec9"))?,
+            meta: None
         },
         &v
     ));
+    assert_eq!(
+        Some(9.into()),
+        ml.index_of(&hash(&LogRecord::new("rec9"))?)?
+    );
+    Ok(())
+}
+
+/// Check reading a record fails on a tombstone
+pub fn assert_tombstone<R: Debug>(r: anyhow::Result<R>) {
+    let e = r.expect_err("tombstone");
+    assert_eq!(
+        Some(&TombstoneError::NoRecord),
+        e.downcast_ref::<TombstoneError>()
+    );
+}
+
+/// Test appending a record as its hash only
+pub fn hash_only<'a, T>(ml: &mut T) -> anyhow::Result<()>
+where
+    T: TransparentLog<'a, LogRecord>,
+    T::LogSize: Debug,
+    T::LogSize: From<u8>,
+{
+    append_multiple(ml, 3)?;
+    let r = ml.append_hash_only(hash(&LogRecord::new("rec3"))?)?;
+    assert_eq!(Into::<T::LogSize>::into(3), r.id);
+    assert!(r.meta.is_some());
+    assert_eq!(r.meta, ml.get_meta(3.into())?);
+    append_multiple_offset(ml, 4, 9)?;
+    check_13(ml)?;
+    assert_eq!(Some(3.into()), ml.index_of(&r.hash)?);
+    assert_tombstone(ml.get(3.into()));
+    assert_tombstone(ml.get_range(2.into(), 5.into()));
+    assert_eq!(Some(&LogRecord::new("rec4")), ml.get(4.into())?.as_deref());
+    assert!(ml.get(13.into())?.is_none());
+    Ok(())
+}
+
+/// Test appending leaf hashes that are not digests, rejected without changing the log, and uppercase ones, lowercased
+pub fn invalid_hash_only<'a, T>(ml: &mut T) -> anyhow::Result<()>
+where
+    T: TransparentLog<'a, LogRecord>,
+    T::LogSize: Debug,
+    T::LogSize: From<u8>,
+{
+    append_multiple(ml, 3)?;
+    let hash3 = hash(&LogRecord::new("rec3"))?;
+    for invalid in [
+        String::new(),
+        hash3[2..].to_string(),
+        format!("{}00", hash3),
+        "g".repeat(64),
+    ] {
+        let err = ml.append_hash_only(invalid).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<TombstoneError>(),
+            Some(TombstoneError::InvalidHash(_))
+        ));
+    }
+    assert_eq!(Into::<T::LogSize>::into(3), ml.size()?);
+    let r = ml.append_hash_only(hash3.to_ascii_uppercase())?;
+    assert_eq!(hash3, r.hash);
+    assert_eq!(Some(3.into()), ml.index_of(&hash3)?);
+    append_multiple_offset(ml, 4, 9)?;
+    check_13(ml)
+}
+
+/// Test redacting records
+pub fn redact<'a, T>(ml: &mut T) -> anyhow::Result<()>
+where
+    T: TransparentLog<'a, LogRecord>,
+    T::LogSize: Debug,
+    T::LogSize: From<u8>,
+{
+    test_13(ml)?;
+    let meta = ml.get_meta(9.into())?;
+    ml.redact(9.into())?;
+    assert_tombstone(ml.get(9.into()));
+    ml.redact(9.into())?;
+    assert_tombstone(ml.get(9.into()));
+    assert!(ml.redact(13.into()).is_err());
+    assert_eq!(meta, ml.get_meta(9.into())?);
+    assert_eq!(Some(&LogRecord::new("rec8")), ml.get(8.into())?.as_deref());
+    assert_eq!(
+        Some(&LogRecord::new("rec10")),
+        ml.get(10.into())?.as_deref()
+    );
+    check_13(ml)?;
+    let r = ml.append(LogRecord::new("rec13"))?;
+    assert_eq!(Into::<T::LogSize>::into(13), r.id);
+    assert_eq!(
+        Some(&LogRecord::new("rec13")),
+        ml.get(13.into())?.as_deref()
+    );
+    Ok(())
+}
+
+/// Test expiring records: their leaf hash is kept and reported when getting them
+pub fn expire<'a, T>(ml: &mut T) -> anyhow::Result<()>
+where
+    T: TransparentLog<'a, LogRecord>,
+    T::LogSize: Debug,
+    T::LogSize: From<u8>,
+{
+    test_13(ml)?;
+    let meta = ml.get_meta(9.into())?;
+    assert!(!ml.expired(9.into())?);
+    ml.expire(9.into())?;
+    assert!(ml.expired(9.into())?);
+    let e = ml.get(9.into()).expect_err("expired");
+    assert_eq!(
+        Some(&TombstoneError::Expired(hash(&LogRecord::new("rec9"))?)),
+        e.downcast_ref::<TombstoneError>()
+    );
+    assert_tombstone(ml.get_range(8.into(), 10.into()));
+    assert_eq!(meta, ml.get_meta(9.into())?);
+    check_13(ml)?;
+
+    ml.redact(10.into())?;
+    assert!(!ml.expired(10.into())?);
+    assert_tombstone(ml.get(10.into()));
+    assert!(ml.expire(13.into()).is_err());
+    assert!(!ml.expired(13.into())?);
+    Ok(())
+}
+
+/// Test checking the stored hashes, fully and sampled
+pub fn self_check<'a, T>(ml: &mut T) -> anyhow::Result<()>
+where
+    T: TransparentLog<'a, LogRecord>,
+    T::LogSize: Debug,
+    T::LogSize: From<u8>,
+{
+    let report = ml.self_check(CheckDepth::Full)?;
+    assert!(report.passed());
+    assert_eq!(0, report.checked);
+    test_13(ml)?;
+    let report = ml.self_check(CheckDepth::Full)?;
+    assert!(report.passed());
+    assert_eq!(Into::<T::LogSize>::into(13), report.size);
+    assert_eq!(10, report.checked);
+    let report = ml.self_check(CheckDepth::Sampled(1))?;
+    assert!(report.passed());
+    assert_eq!(5, report.checked);
+    Ok(())
+}
+
+/// Check writing to a log fails as it is sealed
+pub fn assert_sealed<R: Debug>(r: anyhow::Result<R>) {
+    let e = r.expect_err("sealed");
+    assert_eq!(Some(&SealError::Sealed), e.downcast_ref::<SealError>());
+}
+
+/// Test sealing a log
+pub fn seal<'a, T>(ml: &mut T) -> anyhow::Result<()>
+where
+    T: TransparentLog<'a, LogRecord>,
+    T::LogSize: Debug,
+    T::LogSize: From<u8>,
+{
+    test_13(ml)?;
+    assert_eq!(None, ml.sealed()?);
+    let tree = ml.latest()?;
+    assert_eq!(tree, ml.seal()?);
+    assert_eq!(Some(tree.clone()), ml.sealed()?);
+    assert_sealed(ml.append(LogRecord::new("rec13")));
+    assert_sealed(ml.append_hash_only(hash(&LogRecord::new("rec13"))?));
+    assert_sealed(ml.bulk_append(vec![LogRecord::new("rec13")]));
+    assert_sealed(ml.redact(9.into()));
+    assert_sealed(ml.add_meta(0.into(), RecordMeta::now()));
+    assert_eq!(tree, ml.seal()?);
+    assert_eq!(Into::<T::LogSize>::into(13), ml.size()?);
+    check_13(ml)?;
+    assert_eq!(Some(&LogRecord::new("rec9")), ml.get(9.into())?.as_deref());
     Ok(())
 }
 
@@ -219,7 +1150,8 @@ where
         ml,
         &Record {
             id: 9.into(),
-            hash: hash(lr.deref())?
+            hash: hash(lr.deref())?,
+            meta: None
         }
     )?);
     assert_eq!(Into::<T::LogSize>::into(13), client.latest().size);
@@ -227,6 +1159,33 @@ where
     Ok(())
 }
 
+/// Test reading and checking the serialized bytes of the records of a log with 13 records
+pub fn raw_bytes<'a, T, LC>(ml: &mut T, client: &mut LC) -> anyhow::Result<()>
+where
+    T: TransparentLog<'a, LogRecord>,
+    T::LogSize: Debug,
+    T::LogSize: From<u8>,
+    LC: LogClient<'a, LogRecord, T>,
+{
+    append_multiple(ml, 13)?;
+    for id in 0..13_u8 {
+        let bytes = ml.get_raw_bytes(id.into())?.unwrap();
+        assert_eq!(ml.get_hash(0, id.into())?.as_str(), hash_bytes(&bytes));
+        assert_eq!(Some(bytes), ml.get_leaf_bytes(id.into())?);
+    }
+    assert!(ml.get_raw_bytes(13.into())?.is_none());
+
+    let bytes = get_verified_bytes(client, ml, 9.into())?;
+    assert_eq!(ml.get_raw_bytes(9.into())?, bytes);
+    assert_eq!(Into::<T::LogSize>::into(13), client.latest().size);
+    assert!(get_verified_bytes(client, ml, 13.into())?.is_none());
+
+    ml.redact(9.into())?;
+    assert_tombstone(ml.get_raw_bytes(9.into()));
+    assert_tombstone(get_verified_bytes(client, ml, 9.into()));
+    Ok(())
+}
+
 /// Test with a non caching client
 pub fn client_13_nocache<'a, T, LC>(ml: &mut T, client: &mut LC) -> anyhow::Result<()>
 where
@@ -245,7 +1204,8 @@ where
         ml,
         &Record {
             id: 9.into(),
-            hash: hash(lr.deref())?
+            hash: hash(lr.deref())?,
+            meta: None
         }
     )?);
     assert_eq!(Into::<T::LogSize>::into(13), client.latest().size);
@@ -253,6 +1213,47 @@ where
     Ok(())
 }
 
+/// Test checking several records at once
+pub fn client_records<'a, T, LC>(ml: &mut T, client: &mut LC) -> anyhow::Result<()>
+where
+    T: TransparentLog<'a, LogRecord>,
+    T::LogSize: Debug,
+    T::LogSize: From<u8>,
+    LC: LogClient<'a, LogRecord, T>,
+{
+    let record = |id: u8, text: &str| -> anyhow::Result<Record<T::LogSize>> {
+        Ok(Record {
+            id: id.into(),
+            hash: hash(&LogRecord::new(text))?,
+            meta: None,
+        })
+    };
+    assert!(check_records(client, ml, &[])?.is_empty());
+    append_multiple(ml, 13)?;
+    let records = vec![
+        record(2, "rec2")?,
+        record(9, "rec9")?,
+        record(12, "rec12")?,
+        record(5, "rec6")?,
+        record(20, "rec20")?,
+    ];
+    assert_eq!(
+        vec![true, true, true, false, false],
+        check_records(client, ml, &records)?
+    );
+    assert_eq!(Into::<T::LogSize>::into(13), client.latest().size);
+    // a record after the latest tree of a log that did not grow
+    assert!(!check_record(client, ml, &record(20, "rec20")?)?);
+
+    append_multiple_offset(ml, 13, 3)?;
+    assert_eq!(
+        vec![true, true],
+        check_records(client, ml, &[record(14, "rec14")?, record(0, "rec0")?])?
+    );
+    assert_eq!(Into::<T::LogSize>::into(16), client.latest().size);
+    Ok(())
+}
+
 /// Test tree prefix verification
 pub fn test_verify_tree_prefix<'a, T>(ml: &mut T) -> anyhow::Result<()>
 where
@@ -270,3 +1271,157 @@ where
     assert!(verify_tree(&lt1, &proofs));
     Ok(())
 }
+
+/// The reference root hash of the given leaf hashes, reducing them level by level
+pub fn model_root(leaves: &[String]) -> String {
+    let mut level = leaves.to_vec();
+    while level.len() > 1 {
+        level = level
+            .chunks(2)
+            .map(|c| match c {
+                [h1, h2] => hash_two_strings(h1, h2),
+                [h1] => h1.clone(),
+                _ => unreachable!(),
+            })
+            .collect();
+    }
+    level.pop().unwrap_or_default()
+}
+
+/// The largest log size the reads and proofs of `log_ops` refer to
+#[cfg(any(test, feature = "proptest"))]
+pub const MAX_OPS_SIZE: usize = 40;
+
+/// An operation on a log, checked against a reference model by `properties`
+#[cfg(any(test, feature = "proptest"))]
+#[derive(Debug, Clone)]
+pub enum LogOp {
+    /// Append that many records
+    Append(usize),
+    /// Read the record at the index
+    Get(usize),
+    /// Prove the record at the index is in the tree of the size
+    Prove(usize, usize),
+    /// Prove the tree of the first size is a prefix of the tree of the second size
+    Prefix(usize, usize),
+}
+
+/// Strategy for an index and a size, the index below the size, for sizes up to the maximum
+#[cfg(any(test, feature = "proptest"))]
+pub fn index_and_size(max: usize) -> impl Strategy<Value = (usize, usize)> {
+    (1..=max).prop_flat_map(|size| (0..size, Just(size)))
+}
+
+/// Strategy for two sizes, the first smaller than the second, for sizes up to the maximum
+#[cfg(any(test, feature = "proptest"))]
+pub fn prefix_sizes(max: usize) -> impl Strategy<Value = (usize, usize)> {
+    (2..=max).prop_flat_map(|size2| (1..size2, Just(size2)))
+}
+
+/// Strategy for sequences of operations on a log, mostly appends so that most reads and proofs fall within the log
+#[cfg(any(test, feature = "proptest"))]
+pub fn log_ops() -> impl Strategy<Value = Vec<LogOp>> {
+    collection::vec(
+        prop_oneof![
+            3 => (1..=5_usize).prop_map(LogOp::Append),
+            1 => (0..MAX_OPS_SIZE).prop_map(LogOp::Get),
+            1 => index_and_size(MAX_OPS_SIZE).prop_map(|(id, size)| LogOp::Prove(id, size)),
+            1 => prefix_sizes(MAX_OPS_SIZE).prop_map(|(size1, size2)| LogOp::Prefix(size1, size2)),
+        ],
+        1..50,
+    )
+}
+
+/// Check sequences of operations from `log_ops` against a reference model, each on a new log from the given function
+/// A failing sequence is shrunk to a minimal one, reported in the error
+#[cfg(any(test, feature = "proptest"))]
+pub fn properties<'a, T, F>(new_log: F) -> anyhow::Result<()>
+where
+    T: TransparentLog<'a, LogRecord>,
+    T::LogSize: Debug + FromPrimitive,
+    F: Fn() -> anyhow::Result<T>,
+{
+    let mut runner = TestRunner::new(Config::with_cases(32));
+    runner
+        .run(&log_ops(), |ops| {
+            let mut ml = new_log().map_err(|e| TestCaseError::fail(format!("{:#}", e)))?;
+            check_ops(&mut ml, &ops).map_err(|e| TestCaseError::fail(format!("{:#}", e)))
+        })
+        .map_err(|e| anyhow::anyhow!("{}", e))
+}
+
+/// Run the operations on the log and check their results against the reference model
+/// Reads and proofs beyond the current log size are skipped
+#[cfg(any(test, feature = "proptest"))]
+fn check_ops<'a, T>(ml: &mut T, ops: &[LogOp]) -> anyhow::Result<()>
+where
+    T: TransparentLog<'a, LogRecord>,
+    T::LogSize: Debug + FromPrimitive,
+{
+    let size = |n: usize| T::LogSize::from_usize(n).expect("size out of range");
+    let model_tree = |leaves: &[String]| LogTree {
+        size: size(leaves.len()),
+        hash: model_root(leaves),
+        origin: None,
+    };
+    let mut leaves: Vec<String> = vec![];
+    for op in ops {
+        match *op {
+            LogOp::Append(count) => {
+                for _ in 0..count {
+                    let record = LogRecord::new(&format!("rec{}", leaves.len()));
+                    let r = ml.append(record.clone())?;
+                    anyhow::ensure!(
+                        size(leaves.len()) == r.id,
+                        "{:?}: appended at {:?}",
+                        op,
+                        r.id
+                    );
+                    leaves.push(hash(&record)?);
+                }
+                let latest = ml.latest()?;
+                anyhow::ensure!(
+                    latest.size == size(leaves.len()) && latest.hash == model_root(&leaves),
+                    "{:?}: latest tree {:?}",
+                    op,
+                    latest
+                );
+            }
+            LogOp::Get(id) if id < leaves.len() => {
+                let expected = LogRecord::new(&format!("rec{}", id));
+                anyhow::ensure!(
+                    Some(&expected) == ml.get(size(id))?.as_deref(),
+                    "{:?}: wrong record",
+                    op
+                );
+            }
+            LogOp::Prove(id, sz) if sz <= leaves.len() => {
+                let tree = model_tree(&leaves[..sz]);
+                let proofs = ml.proofs(proof_positions(size(id), size(sz)).into_iter())?;
+                let mut record = Record {
+                    id: size(id),
+                    hash: leaves[id].clone(),
+                    meta: None,
+                };
+                anyhow::ensure!(verify(&tree, &record, &proofs), "{:?}: proof rejected", op);
+                record.hash = hash(&LogRecord::new("other"))?;
+                anyhow::ensure!(
+                    !verify(&tree, &record, &proofs),
+                    "{:?}: other record proven",
+                    op
+                );
+            }
+            LogOp::Prefix(sz1, sz2) if sz2 <= leaves.len() => {
+                let proofs = ml.proofs(prefix_proof_positions(size(sz1), size(sz2)).into_iter())?;
+                anyhow::ensure!(
+                    verify_tree(&model_tree(&leaves[..sz1]), &proofs)
+                        && verify_tree(&model_tree(&leaves[..sz2]), &proofs),
+                    "{:?}: prefix proof rejected",
+                    op
+                );
+            }
+            _ => (),
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,475 @@
+//! # Storage tiering
+//!
+//! A log keeping its hashes and metadata local while the record payloads live in two record stores:
+//! recent records in a fast hot store, older ones moved to a slower cold store (another disk, an object store...).
+//! Reads transparently fall back to the cold store, and proofs never need it.
+use crate::base::*;
+use crate::tile::Tile;
+use maybe_owned::MaybeOwned;
+use num::One;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Display;
+use std::fs::{create_dir_all, read, read_dir, remove_file, write};
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Storage for serialized records, by record index
+pub trait RecordStore<LogSize> {
+    /// Store a serialized record
+    fn put(&mut self, id: LogSize, data: Vec<u8>) -> anyhow::Result<()>;
+
+    /// Get a serialized record, if stored
+    fn get(&self, id: LogSize) -> anyhow::Result<Option<Vec<u8>>>;
+
+    /// Remove a serialized record, doing nothing if it is not stored
+    fn remove(&mut self, id: LogSize) -> anyhow::Result<()>;
+
+    /// The lowest index stored, if any
+    fn first(&self) -> anyhow::Result<Option<LogSize>>;
+}
+
+/// A record store in memory
+#[derive(Debug)]
+pub struct MemoryRecordStore<LogSize> {
+    records: BTreeMap<LogSize, Vec<u8>>,
+}
+
+impl<LogSize> Default for MemoryRecordStore<LogSize> {
+    fn default() -> Self {
+        Self {
+            records: BTreeMap::new(),
+        }
+    }
+}
+
+impl<LogSize: Ord + Copy> RecordStore<LogSize> for MemoryRecordStore<LogSize> {
+    fn put(&mut self, id: LogSize, data: Vec<u8>) -> anyhow::Result<()> {
+        self.records.insert(id, data);
+        Ok(())
+    }
+
+    fn get(&self, id: LogSize) -> anyhow::Result<Option<Vec<u8>>> {
+        Ok(self.records.get(&id).cloned())
+    }
+
+    fn remove(&mut self, id: LogSize) -> anyhow::Result<()> {
+        self.records.remove(&id);
+        Ok(())
+    }
+
+    fn first(&self) -> anyhow::Result<Option<LogSize>> {
+        Ok(self.records.keys().next().copied())
+    }
+}
+
+/// A record store keeping each record in its own file of a directory, for example on a slower disk
+#[derive(Debug)]
+pub struct DirRecordStore<LogSize> {
+    dir: PathBuf,
+    _marker: PhantomData<LogSize>,
+}
+
+impl<LogSize: Display> DirRecordStore<LogSize> {
+    /// Open a store in the given directory, creating it if needed
+    pub fn open<P: AsRef<Path>>(dir: P) -> anyhow::Result<Self> {
+        create_dir_all(&dir)?;
+        Ok(Self {
+            dir: dir.as_ref().to_path_buf(),
+            _marker: PhantomData,
+        })
+    }
+
+    /// The path of the file of a record
+    fn path(&self, id: LogSize) -> PathBuf {
+        self.dir.join(format!("{}.bin", id))
+    }
+}
+
+impl<LogSize: Display + FromStr + Ord> RecordStore<LogSize> for DirRecordStore<LogSize> {
+    fn put(&mut self, id: LogSize, data: Vec<u8>) -> anyhow::Result<()> {
+        write(self.path(id), data)?;
+        Ok(())
+    }
+
+    fn get(&self, id: LogSize) -> anyhow::Result<Option<Vec<u8>>> {
+        let p = self.path(id);
+        if p.exists() {
+            Ok(Some(read(p)?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn remove(&mut self, id: LogSize) -> anyhow::Result<()> {
+        let p = self.path(id);
+        if p.exists() {
+            remove_file(p)?;
+        }
+        Ok(())
+    }
+
+    /// Scans the directory
+    fn first(&self) -> anyhow::Result<Option<LogSize>> {
+        let mut first = None;
+        for entry in read_dir(&self.dir)? {
+            let name = entry?.file_name();
+            let id = name
+                .to_str()
+                .and_then(|n| n.strip_suffix(".bin"))
+                .and_then(|n| n.parse::<LogSize>().ok());
+            if let Some(id) = id {
+                if first.as_ref().map_or(true, |f| id < *f) {
+                    first = Some(id);
+                }
+            }
+        }
+        Ok(first)
+    }
+}
+
+/// A log with its records in a hot store, moved to a cold store once they are older than a configurable boundary
+/// The inner log stores an empty record for each record, along with the hashes and metadata,
+/// so sizes, proofs and lookups never touch the record stores
+#[derive(Debug)]
+pub struct TieredLog<T, L, H, C, LogSize> {
+    log: L,
+    hot: H,
+    cold: C,
+    keep_hot: Option<LogSize>,
+    boundary: Option<LogSize>,
+    _marker: PhantomData<T>,
+}
+
+impl<'a, T, L, H, C> TieredLog<T, L, H, C, L::LogSize>
+where
+    L: TransparentLog<'a, ()>,
+    H: RecordStore<L::LogSize>,
+    C: RecordStore<L::LogSize>,
+{
+    /// Create a tiered log from the inner log of hashes and the two stores
+    /// Records stay in the hot store until moved with `tier_before`, unless `keep_hot` is set
+    pub fn new(log: L, hot: H, cold: C) -> Self {
+        Self {
+            log,
+            hot,
+            cold,
+            keep_hot: None,
+            boundary: None,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Only keep the given number of latest records in the hot store, moving older ones to the cold store on append
+    pub fn keep_hot(&mut self, count: L::LogSize) -> &mut Self {
+        self.keep_hot = Some(count);
+        self
+    }
+
+    /// Move all the records before the given index from the hot store to the cold store
+    /// Records are written to the cold store before being removed from the hot one, so none is lost on failure
+    /// Returns the number of records moved
+    pub fn tier_before(&mut self, before: L::LogSize) -> anyhow::Result<usize> {
+        let mut id = match self.boundary {
+            Some(b) => b,
+            None => match self.hot.first()? {
+                Some(f) => f,
+                None => return Ok(0),
+            },
+        };
+        let mut moved = 0;
+        while id < before {
+            if let Some(data) = self.hot.get(id)? {
+                self.cold.put(id, data)?;
+                self.hot.remove(id)?;
+                moved += 1;
+            }
+            id = id + L::LogSize::one();
+        }
+        self.boundary = Some(id);
+        Ok(moved)
+    }
+
+    /// The hot record store
+    pub fn hot(&self) -> &H {
+        &self.hot
+    }
+
+    /// The cold record store
+    pub fn cold(&self) -> &C {
+        &self.cold
+    }
+
+    /// Get back the inner log and the stores
+    pub fn into_inner(self) -> (L, H, C) {
+        (self.log, self.hot, self.cold)
+    }
+}
+
+impl<'a, T, L, H, C> TransparentLog<'a, T> for TieredLog<T, L, H, C, L::LogSize>
+where
+    T: Serialize + DeserializeOwned,
+    L: TransparentLog<'a, ()>,
+    H: RecordStore<L::LogSize>,
+    C: RecordStore<L::LogSize>,
+{
+    type LogSize = L::LogSize;
+
+    fn size(&self) -> anyhow::Result<Self::LogSize> {
+        self.log.size()
+    }
+
+    fn origin(&self) -> Option<String> {
+        self.log.origin()
+    }
+
+    /// Records are serialized as MessagePack in the record stores
+    fn leaf_hasher(&self) -> LeafHasher<T> {
+        hash
+    }
+
+    fn get(&self, index: Self::LogSize) -> anyhow::Result<Option<MaybeOwned<'_, T>>> {
+        self.get_raw_bytes(index)?
+            .map(|d| Ok(MaybeOwned::Owned(rmp_serde::from_slice(&d)?)))
+            .transpose()
+    }
+
+    /// Reads from the hot store, then the cold store, a record in neither being a tombstone
+    fn get_raw_bytes(&self, index: Self::LogSize) -> anyhow::Result<Option<Vec<u8>>> {
+        let data = match self.hot.get(index)? {
+            Some(data) => Some(data),
+            None => self.cold.get(index)?,
+        };
+        if data.is_none() && index < self.log.size()? {
+            return Err(tombstone_error(&self.log, index));
+        }
+        Ok(data)
+    }
+
+    fn add(&mut self, record: T) -> anyhow::Result<Self::LogSize> {
+        let data = rmp_serde::to_vec(&record)?;
+        let id = self.log.add(())?;
+        self.hot.put(id, data)?;
+        if let Some(keep) = self.keep_hot {
+            let size = id + Self::LogSize::one();
+            if size > keep {
+                self.tier_before(size - keep)?;
+            }
+        }
+        Ok(id)
+    }
+
+    /// Stores nothing in the record stores
+    fn add_tombstone(&mut self) -> anyhow::Result<Self::LogSize> {
+        self.log.add(())
+    }
+
+    /// Redacts the empty record of the inner log, which checks the ID, then removes the record from both stores
+    fn redact(&mut self, id: Self::LogSize) -> anyhow::Result<()> {
+        self.log.redact(id)?;
+        self.hot.remove(id)?;
+        self.cold.remove(id)
+    }
+
+    /// Expires the empty record of the inner log, then removes the record from both stores
+    fn expire(&mut self, id: Self::LogSize) -> anyhow::Result<()> {
+        self.log.expire(id)?;
+        self.hot.remove(id)?;
+        self.cold.remove(id)
+    }
+
+    fn expired(&self, id: Self::LogSize) -> anyhow::Result<bool> {
+        self.log.expired(id)
+    }
+
+    fn seal(&mut self) -> anyhow::Result<LogTree<Self::LogSize>> {
+        self.log.seal()
+    }
+
+    fn sealed(&self) -> anyhow::Result<Option<LogTree<Self::LogSize>>> {
+        self.log.sealed()
+    }
+
+    /// Only syncs the inner log, not the record stores
+    fn sync(&self) -> anyhow::Result<()> {
+        self.log.sync()
+    }
+
+    fn add_meta(&mut self, id: Self::LogSize, meta: RecordMeta) -> anyhow::Result<()> {
+        self.log.add_meta(id, meta)
+    }
+
+    fn get_meta(&self, id: Self::LogSize) -> anyhow::Result<Option<RecordMeta>> {
+        self.log.get_meta(id)
+    }
+
+    fn set_annotation(&mut self, id: Self::LogSize, annotation: Vec<u8>) -> anyhow::Result<()> {
+        self.log.set_annotation(id, annotation)
+    }
+
+    fn get_annotation(&self, id: Self::LogSize) -> anyhow::Result<Option<Vec<u8>>> {
+        self.log.get_annotation(id)
+    }
+
+    fn find_since(&self, timestamp: u64) -> anyhow::Result<Self::LogSize> {
+        self.log.find_since(timestamp)
+    }
+
+    fn add_hash(&mut self, level: LogHeight, hash: String) -> anyhow::Result<Self::LogSize> {
+        self.log.add_hash(level, hash)
+    }
+
+    fn get_hash(
+        &self,
+        level: LogHeight,
+        index: Self::LogSize,
+    ) -> anyhow::Result<MaybeOwned<'_, String>> {
+        self.log.get_hash(level, index)
+    }
+
+    fn index_of(&self, hash: &str) -> anyhow::Result<Option<Self::LogSize>> {
+        self.log.index_of(hash)
+    }
+
+    fn indices_of(&self, hash: &str) -> anyhow::Result<Vec<Self::LogSize>> {
+        self.log.indices_of(hash)
+    }
+
+    fn tile(&self, level: LogHeight, index: Self::LogSize) -> anyhow::Result<Tile<Self::LogSize>> {
+        self.log.tile(level, index)
+    }
+
+    fn latest(&self) -> anyhow::Result<LogTree<Self::LogSize>> {
+        self.log.latest()
+    }
+
+    fn ephemeral_hash(&self, level: LogHeight, index: Self::LogSize) -> anyhow::Result<String> {
+        self.log.ephemeral_hash(level, index)
+    }
+
+    fn root_at(&self, size: Self::LogSize) -> anyhow::Result<String> {
+        self.log.root_at(size)
+    }
+
+    fn head_at(&self, size: Self::LogSize) -> anyhow::Result<LogTree<Self::LogSize>> {
+        self.log.head_at(size)
+    }
+
+    fn proofs<I>(
+        &self,
+        positions: I,
+    ) -> anyhow::Result<HashMap<LogTreePosition<Self::LogSize>, String>>
+    where
+        I: Iterator<Item = LogTreePosition<Self::LogSize>>,
+    {
+        self.log.proofs(positions)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::test_helpers::*;
+    use crate::*;
+    use std::fs::{create_dir, remove_dir_all};
+    use std::path::Path;
+
+    use serial_test::serial;
+
+    fn memory_tiered_log() -> TieredLog<
+        LogRecord,
+        InMemoryLog<()>,
+        MemoryRecordStore<usize>,
+        MemoryRecordStore<usize>,
+        usize,
+    > {
+        TieredLog::new(
+            InMemoryLog::default(),
+            MemoryRecordStore::default(),
+            MemoryRecordStore::default(),
+        )
+    }
+
+    #[test]
+    fn tiering_memory() -> anyhow::Result<()> {
+        let mut ml = memory_tiered_log();
+        ml.keep_hot(5);
+        test_13(&mut ml)?;
+        assert_eq!(Some(8), ml.hot().first()?);
+        assert_eq!(Some(0), ml.cold().first()?);
+        assert_eq!(0, ml.tier_before(8)?);
+        assert_eq!(3, ml.tier_before(11)?);
+        assert_eq!(Some(11), ml.hot().first()?);
+        check_13(&ml)
+    }
+
+    #[test]
+    fn tiering_memory_raw_bytes() -> anyhow::Result<()> {
+        let mut ml = memory_tiered_log();
+        ml.keep_hot(5);
+        let mut client = InMemoryLogClientBuilder::new(&ml)?.build();
+        raw_bytes(&mut ml, &mut client)
+    }
+
+    #[test]
+    fn tiering_memory_duplicates() -> anyhow::Result<()> {
+        let mut ml = memory_tiered_log();
+        ml.keep_hot(2);
+        duplicates(&mut ml)
+    }
+
+    #[test]
+    fn tiering_memory_properties() -> anyhow::Result<()> {
+        properties(|| {
+            let mut ml = memory_tiered_log();
+            ml.keep_hot(3);
+            Ok(ml)
+        })
+    }
+
+    #[test]
+    fn tiering_hash_only() -> anyhow::Result<()> {
+        let mut ml = memory_tiered_log();
+        ml.keep_hot(5);
+        hash_only(&mut ml)
+    }
+
+    #[test]
+    fn tiering_redact() -> anyhow::Result<()> {
+        let mut ml = memory_tiered_log();
+        ml.keep_hot(2);
+        redact(&mut ml)?;
+        ml.redact(12)?;
+        assert_tombstone(ml.get(12));
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    fn tiering_file() -> anyhow::Result<()> {
+        let path = Path::new("./test_data");
+        if path.exists() {
+            remove_dir_all(path)?;
+        }
+        create_dir(path)?;
+        let hashes = path.join("hashes");
+        create_dir(&hashes)?;
+        let mut ml = TieredLog::new(
+            FileLog::<()>::open(&hashes)?,
+            MemoryRecordStore::default(),
+            DirRecordStore::open(path.join("cold"))?,
+        );
+        test_13(&mut ml)?;
+        assert_eq!(13, ml.tier_before(13)?);
+        assert_eq!(None, ml.hot().first()?);
+        assert_eq!(Some(0), ml.cold().first()?);
+        check_13(&ml)?;
+        let (log, _, cold) = ml.into_inner();
+
+        // reopen with an empty hot store
+        let ml = TieredLog::new(log, MemoryRecordStore::default(), cold);
+        check_13(&ml)
+    }
+}
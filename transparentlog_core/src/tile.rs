@@ -0,0 +1,109 @@
+use crate::base::*;
+use num::{Integer, ToPrimitive};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Debug, Display};
+use std::hash::Hash;
+
+/// The height of a tile: a tile holds up to 2^TILE_HEIGHT hashes of the same tree level
+pub const TILE_HEIGHT: LogHeight = 8;
+
+/// A group of consecutive hashes from one tree level, at tile level `level` and tile index `index`
+#[derive(Hash, PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
+pub struct Tile<LogSize> {
+    pub level: LogHeight,
+    pub index: LogSize,
+    pub hashes: Vec<String>,
+}
+
+/// Tiles by tile level and index
+pub type Tiles<LogSize> = HashMap<(LogHeight, LogSize), Tile<LogSize>>;
+
+impl<LogSize: Integer + Copy> Tile<LogSize> {
+    /// Is the tile complete, and hence will never change?
+    pub fn is_full(&self) -> bool {
+        self.hashes.len() == 1 << TILE_HEIGHT
+    }
+}
+
+/// Two to the power of the given height
+pub(crate) fn pow2<LogSize: Integer + Copy>(height: LogHeight) -> LogSize {
+    let two = LogSize::one() + LogSize::one();
+    (0..height).fold(LogSize::one(), |acc, _| acc * two)
+}
+
+/// The number of hashes in a full tile
+pub fn tile_width<LogSize: Integer + Copy>() -> LogSize {
+    pow2(TILE_HEIGHT)
+}
+
+/// The tile level and index of the tile needed to calculate the hash at the given position
+pub fn tile_for<LogSize: Integer + Copy>(
+    position: &LogTreePosition<LogSize>,
+) -> (LogHeight, LogSize) {
+    let tile_level = position.level / TILE_HEIGHT;
+    let first = position.index * pow2(position.level - tile_level * TILE_HEIGHT);
+    (tile_level, first / tile_width())
+}
+
+/// The tiles needed to calculate all the given positions
+pub fn tile_positions<LogSize: Integer + Copy + Hash>(
+    positions: &HashSet<LogTreePosition<LogSize>>,
+) -> HashSet<(LogHeight, LogSize)> {
+    positions.iter().map(tile_for).collect()
+}
+
+/// Fetch the given tiles from the log
+pub fn fetch_tiles<'a, T, TL, I>(log: &TL, tiles: I) -> anyhow::Result<Tiles<TL::LogSize>>
+where
+    T: Serialize + Deserialize<'a>,
+    TL: TransparentLog<'a, T>,
+    I: IntoIterator<Item = (LogHeight, TL::LogSize)>,
+{
+    tiles
+        .into_iter()
+        .map(|(level, index)| Ok(((level, index), log.tile(level, index)?)))
+        .collect()
+}
+
+/// Calculate the hashes at the given positions from the tiles
+pub fn proofs_from_tiles<LogSize: Integer + Copy + Hash + ToPrimitive + Display>(
+    positions: &HashSet<LogTreePosition<LogSize>>,
+    tiles: &Tiles<LogSize>,
+) -> Result<HashMap<LogTreePosition<LogSize>, String>, LogError<LogSize>> {
+    positions
+        .iter()
+        .map(|p| {
+            let (tile_level, tile_index) = tile_for(p);
+            let missing = || LogError::MissingTile(tile_level, tile_index);
+            let tile = tiles.get(&(tile_level, tile_index)).ok_or_else(missing)?;
+            let depth = p.level - tile_level * TILE_HEIGHT;
+            let start = (p.index * pow2(depth) - tile_index * tile_width())
+                .to_usize()
+                .ok_or_else(missing)?;
+            let mut hashes: Vec<String> = tile
+                .hashes
+                .get(start..start + (1 << depth))
+                .ok_or_else(missing)?
+                .to_vec();
+            while hashes.len() > 1 {
+                hashes = hashes.chunks(2).map(|c| hash_pair(&c[0], &c[1])).collect();
+            }
+            Ok((*p, hashes.pop().ok_or_else(missing)?))
+        })
+        .collect()
+}
+
+/// Get the proofs for the given positions from the log by fetching whole tiles
+pub fn tile_proofs<'a, T, TL>(
+    log: &TL,
+    positions: &HashSet<LogTreePosition<TL::LogSize>>,
+) -> anyhow::Result<HashMap<LogTreePosition<TL::LogSize>, String>>
+where
+    T: Serialize + Deserialize<'a>,
+    TL: TransparentLog<'a, T>,
+    TL::LogSize: ToPrimitive + Display + Debug + Send + Sync,
+{
+    let tiles = fetch_tiles(log, tile_positions(positions))?;
+    Ok(proofs_from_tiles(positions, &tiles)?)
+}
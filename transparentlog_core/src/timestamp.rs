@@ -0,0 +1,691 @@
+//! # Timestamped checkpoints
+//!
+//! A `TimestampingLog` signs the checkpoint of its tree when it is published and has a `TimestampAuthority`,
+//! an RFC 3161 time-stamping authority or a Roughtime server, timestamp the signed note, keeping each
+//! `TimestampedCheckpoint` so auditors can prove when a tree head existed.
+//! RFC 3161 requests are built with `rfc3161_request` and tokens read with `Rfc3161Token`; checking the signature
+//! of a token needs the certificate of the authority, so `Rfc3161Verifier` leaves it to a given function.
+use crate::base::*;
+use crate::checkpoint::*;
+use crate::dsse::base64_bytes;
+use maybe_owned::MaybeOwned;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::str::FromStr;
+use thiserror::Error;
+
+/// The DER tags used by RFC 3161
+const BOOLEAN: u8 = 0x01;
+const INTEGER: u8 = 0x02;
+const OCTET_STRING: u8 = 0x04;
+const OBJECT_IDENTIFIER: u8 = 0x06;
+const GENERALIZED_TIME: u8 = 0x18;
+const SEQUENCE: u8 = 0x30;
+const SET: u8 = 0x31;
+const EXPLICIT_0: u8 = 0xa0;
+
+/// The DER AlgorithmIdentifier of SHA-256
+const SHA256_ALGORITHM: [u8; 15] = [
+    0x30, 0x0d, 0x06, 0x09, 0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x01, 0x05, 0x00,
+];
+
+/// The content of the id-signedData object identifier
+const SIGNED_DATA: [u8; 9] = [0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x07, 0x02];
+
+/// The content of the id-ct-TSTInfo object identifier
+const TST_INFO: [u8; 11] = [
+    0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x09, 0x10, 0x01, 0x04,
+];
+
+/// Specific Errors for timestamps
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum TimestampError {
+    #[error("Malformed timestamp: {0}")]
+    Malformed(&'static str),
+    #[error("Timestamp request rejected with status {0}")]
+    Rejected(u64),
+    #[error("Timestamp is not over the message")]
+    ImprintMismatch,
+    #[error("Invalid timestamp signature from {0}")]
+    InvalidSignature(String),
+    #[error("No verifier for timestamp authority {0}")]
+    UnknownAuthority(String),
+}
+
+/// Something that can timestamp messages, like an RFC 3161 time-stamping authority or a Roughtime server
+pub trait TimestampAuthority {
+    /// The name of the authority
+    fn name(&self) -> &str;
+
+    /// Get a timestamp token over the given message
+    fn timestamp(&self, msg: &[u8]) -> anyhow::Result<Vec<u8>>;
+}
+
+/// Something that can verify the timestamp tokens of an authority
+pub trait TimestampVerifier {
+    /// The name of the authority
+    fn name(&self) -> &str;
+
+    /// Verify a token is over the given message, returning its time in milliseconds since the UNIX epoch
+    fn verify(&self, msg: &[u8], token: &[u8]) -> Result<u64, TimestampError>;
+}
+
+/// The SHA-256 hash of a message
+fn sha256(msg: &[u8]) -> Vec<u8> {
+    Sha256::digest(msg).to_vec()
+}
+
+/// Encode a DER element
+fn der(tag: u8, content: &[u8]) -> Vec<u8> {
+    let mut b = vec![tag];
+    if content.len() < 0x80 {
+        b.push(content.len() as u8);
+    } else {
+        let len: Vec<u8> = content
+            .len()
+            .to_be_bytes()
+            .iter()
+            .copied()
+            .skip_while(|b| *b == 0)
+            .collect();
+        b.push(0x80 | len.len() as u8);
+        b.extend(len);
+    }
+    b.extend_from_slice(content);
+    b
+}
+
+/// Encode a positive DER integer
+fn der_integer(value: u64) -> Vec<u8> {
+    let mut b: Vec<u8> = value
+        .to_be_bytes()
+        .iter()
+        .copied()
+        .skip_while(|b| *b == 0)
+        .collect();
+    if b.first().map_or(true, |b| b & 0x80 != 0) {
+        b.insert(0, 0);
+    }
+    der(INTEGER, &b)
+}
+
+/// Read a DER element: its tag, its content and the bytes following it
+fn read_der(input: &[u8]) -> Result<(u8, &[u8], &[u8]), TimestampError> {
+    let truncated = || TimestampError::Malformed("truncated element");
+    let (&tag, rest) = input.split_first().ok_or_else(truncated)?;
+    let (&first, rest) = rest.split_first().ok_or_else(truncated)?;
+    let (len, rest) = if first < 0x80 {
+        (first as usize, rest)
+    } else {
+        let n = (first & 0x7f) as usize;
+        if n == 0 || n > 4 || rest.len() < n {
+            return Err(TimestampError::Malformed("invalid length"));
+        }
+        let len = rest[..n].iter().fold(0, |acc, b| acc << 8 | *b as usize);
+        (len, &rest[n..])
+    };
+    if rest.len() < len {
+        return Err(truncated());
+    }
+    Ok((tag, &rest[..len], &rest[len..]))
+}
+
+/// Read a DER element with the given tag, returning its content and the bytes following it
+fn expect_der<'d>(
+    input: &'d [u8],
+    tag: u8,
+    what: &'static str,
+) -> Result<(&'d [u8], &'d [u8]), TimestampError> {
+    match read_der(input)? {
+        (t, content, rest) if t == tag => Ok((content, rest)),
+        _ => Err(TimestampError::Malformed(what)),
+    }
+}
+
+/// Read the content of a positive DER integer
+fn read_integer(content: &[u8]) -> Result<u64, TimestampError> {
+    match content {
+        [0, rest @ ..] if rest.len() <= 8 => Ok(rest.iter().fold(0, |acc, b| acc << 8 | *b as u64)),
+        _ if content.len() <= 8 && content.first().map_or(false, |b| b & 0x80 == 0) => {
+            Ok(content.iter().fold(0, |acc, b| acc << 8 | *b as u64))
+        }
+        _ => Err(TimestampError::Malformed("invalid integer")),
+    }
+}
+
+/// The days from the UNIX epoch to a date of the proleptic Gregorian calendar
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// Read a DER GeneralizedTime, `YYYYMMDDHHMMSS[.fff]Z`, in milliseconds since the UNIX epoch
+fn read_time(content: &[u8]) -> Result<u64, TimestampError> {
+    let invalid = || TimestampError::Malformed("invalid time");
+    let text = std::str::from_utf8(content).map_err(|_| invalid())?;
+    let text = text.strip_suffix('Z').ok_or_else(invalid)?;
+    let (time, fraction) = text.split_once('.').unwrap_or((text, ""));
+    if time.len() != 14
+        || !time
+            .bytes()
+            .chain(fraction.bytes())
+            .all(|b| b.is_ascii_digit())
+    {
+        return Err(invalid());
+    }
+    let field = |start: usize, end: usize| time[start..end].parse::<i64>().map_err(|_| invalid());
+    let days = days_from_civil(field(0, 4)?, field(4, 6)?, field(6, 8)?);
+    let seconds = days * 86_400 + field(8, 10)? * 3_600 + field(10, 12)? * 60 + field(12, 14)?;
+    let millis: i64 = format!("{:0<3}", &fraction[..fraction.len().min(3)])
+        .parse()
+        .map_err(|_| invalid())?;
+    u64::try_from(seconds * 1000 + millis).map_err(|_| invalid())
+}
+
+/// The DER RFC 3161 request of a timestamp over the SHA-256 hash of a message, with a nonce and asking for the certificate
+pub fn rfc3161_request(msg: &[u8], nonce: u64) -> Vec<u8> {
+    let mut imprint = SHA256_ALGORITHM.to_vec();
+    imprint.extend(der(OCTET_STRING, &sha256(msg)));
+    let mut request = der_integer(1);
+    request.extend(der(SEQUENCE, &imprint));
+    request.extend(der_integer(nonce));
+    request.extend(der(BOOLEAN, &[0xff]));
+    der(SEQUENCE, &request)
+}
+
+/// The timestamp token of a DER RFC 3161 response, if the request was granted
+pub fn rfc3161_token(response: &[u8]) -> Result<Vec<u8>, TimestampError> {
+    let (response, _) = expect_der(response, SEQUENCE, "response")?;
+    let (status_info, rest) = expect_der(response, SEQUENCE, "status")?;
+    let (status, _) = expect_der(status_info, INTEGER, "status")?;
+    match read_integer(status)? {
+        // granted, or granted with modifications
+        0 | 1 => {
+            let (tag, content, _) = read_der(rest)?;
+            if tag != SEQUENCE {
+                return Err(TimestampError::Malformed("token"));
+            }
+            Ok(der(SEQUENCE, content))
+        }
+        status => Err(TimestampError::Rejected(status)),
+    }
+}
+
+/// The timestamp information of an RFC 3161 token, read without checking its signature
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rfc3161Token {
+    /// The SHA-256 hash of the message timestamped
+    pub imprint: Vec<u8>,
+    /// The time of the timestamp, in milliseconds since the UNIX epoch
+    pub time: u64,
+    pub nonce: Option<u64>,
+}
+
+impl Rfc3161Token {
+    /// Read the TSTInfo of a DER timestamp token, only accepting SHA-256 imprints
+    pub fn parse(token: &[u8]) -> Result<Self, TimestampError> {
+        let (content_info, _) = expect_der(token, SEQUENCE, "content info")?;
+        let (oid, rest) = expect_der(content_info, OBJECT_IDENTIFIER, "content type")?;
+        if oid != SIGNED_DATA {
+            return Err(TimestampError::Malformed("not signed data"));
+        }
+        let (content, _) = expect_der(rest, EXPLICIT_0, "content")?;
+        let (signed_data, _) = expect_der(content, SEQUENCE, "signed data")?;
+        let (_, rest) = expect_der(signed_data, INTEGER, "signed data version")?;
+        let (_, rest) = expect_der(rest, SET, "digest algorithms")?;
+        let (encapsulated, _) = expect_der(rest, SEQUENCE, "encapsulated content")?;
+        let (oid, rest) = expect_der(encapsulated, OBJECT_IDENTIFIER, "content type")?;
+        if oid != TST_INFO {
+            return Err(TimestampError::Malformed("not timestamp information"));
+        }
+        let (content, _) = expect_der(rest, EXPLICIT_0, "content")?;
+        let (tst_info, _) = expect_der(content, OCTET_STRING, "timestamp information")?;
+        let (tst_info, _) = expect_der(tst_info, SEQUENCE, "timestamp information")?;
+        let (_, rest) = expect_der(tst_info, INTEGER, "version")?;
+        let (_, rest) = expect_der(rest, OBJECT_IDENTIFIER, "policy")?;
+        let (imprint, rest) = expect_der(rest, SEQUENCE, "message imprint")?;
+        let hashed = imprint
+            .strip_prefix(&SHA256_ALGORITHM[..])
+            .ok_or(TimestampError::Malformed("not a SHA-256 imprint"))?;
+        let (imprint, _) = expect_der(hashed, OCTET_STRING, "message imprint")?;
+        let (_, rest) = expect_der(rest, INTEGER, "serial number")?;
+        let (time, mut rest) = expect_der(rest, GENERALIZED_TIME, "time")?;
+        // skip the optional accuracy and ordering before the nonce
+        let mut nonce = None;
+        while !rest.is_empty() {
+            let (tag, content, next) = read_der(rest)?;
+            if tag == INTEGER {
+                nonce = Some(read_integer(content)?);
+                break;
+            }
+            rest = next;
+        }
+        Ok(Self {
+            imprint: imprint.to_vec(),
+            time: read_time(time)?,
+            nonce,
+        })
+    }
+}
+
+/// A verifier of RFC 3161 tokens, that checks their imprint and leaves their signature to a function,
+/// which checks the CMS signature of the token with the certificate of the authority
+pub struct Rfc3161Verifier<F> {
+    name: String,
+    signature: F,
+}
+
+impl<F: Fn(&[u8]) -> bool> Rfc3161Verifier<F> {
+    /// Create a verifier for the named authority, checking signatures with the given function
+    pub fn new(name: &str, signature: F) -> Self {
+        Self {
+            name: name.to_owned(),
+            signature,
+        }
+    }
+}
+
+impl<F: Fn(&[u8]) -> bool> TimestampVerifier for Rfc3161Verifier<F> {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn verify(&self, msg: &[u8], token: &[u8]) -> Result<u64, TimestampError> {
+        let info = Rfc3161Token::parse(token)?;
+        if !(self.signature)(token) {
+            return Err(TimestampError::InvalidSignature(self.name.clone()));
+        }
+        if info.imprint != sha256(msg) {
+            return Err(TimestampError::ImprintMismatch);
+        }
+        Ok(info.time)
+    }
+}
+
+/// A signed checkpoint with a timestamp token over its note
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TimestampedCheckpoint {
+    pub note: String,
+    pub authority: String,
+    #[serde(with = "base64_bytes")]
+    pub token: Vec<u8>,
+}
+
+impl TimestampedCheckpoint {
+    /// Open the checkpoint, verifying its signatures and its timestamp
+    /// Returns the checkpoint and the time it existed at, in milliseconds since the UNIX epoch
+    pub fn open<LogSize: Display + FromStr + num::Zero>(
+        &self,
+        verifiers: &[&dyn LogVerifier],
+        timestamp_verifiers: &[&dyn TimestampVerifier],
+    ) -> anyhow::Result<(Checkpoint<LogSize>, u64)> {
+        let checkpoint = Checkpoint::open(&self.note, verifiers)?;
+        let verifier = timestamp_verifiers
+            .iter()
+            .find(|v| v.name() == self.authority)
+            .ok_or_else(|| TimestampError::UnknownAuthority(self.authority.clone()))?;
+        let time = verifier.verify(self.note.as_bytes(), &self.token)?;
+        Ok((checkpoint, time))
+    }
+}
+
+/// A log timestamping the signed checkpoints of its tree when it publishes them
+/// Timestamped checkpoints are kept in memory, in publication order
+pub struct TimestampingLog<L, S, A> {
+    log: L,
+    origin: String,
+    signer: S,
+    authority: A,
+    checkpoints: Vec<TimestampedCheckpoint>,
+}
+
+impl<L, S: LogSigner, A: TimestampAuthority> TimestampingLog<L, S, A> {
+    /// Wrap a log, signing checkpoints for the given origin and timestamping them with the authority
+    pub fn new(log: L, origin: &str, signer: S, authority: A) -> Self {
+        Self {
+            log,
+            origin: origin.to_owned(),
+            signer,
+            authority,
+            checkpoints: vec![],
+        }
+    }
+
+    /// Sign and timestamp the checkpoint of the latest tree, unless it was already published
+    pub fn publish<'a, T>(&mut self) -> anyhow::Result<&TimestampedCheckpoint>
+    where
+        T: Serialize + Deserialize<'a>,
+        L: TransparentLog<'a, T>,
+        L::LogSize: Display + FromStr,
+    {
+        let checkpoint = Checkpoint::new(&self.origin, self.log.latest()?);
+        let text = checkpoint.text()?;
+        let published = match self.checkpoints.last() {
+            Some(last) => parse_note(&last.note)?.0 == text,
+            None => false,
+        };
+        if !published {
+            let note = checkpoint.sign(&[&self.signer])?;
+            let token = self.authority.timestamp(note.as_bytes())?;
+            self.checkpoints.push(TimestampedCheckpoint {
+                note,
+                authority: self.authority.name().to_owned(),
+                token,
+            });
+        }
+        Ok(&self.checkpoints[self.checkpoints.len() - 1])
+    }
+
+    /// Seal the wrapped log, then publish its final checkpoint
+    pub fn publish_sealed<'a, T>(&mut self) -> anyhow::Result<&TimestampedCheckpoint>
+    where
+        T: Serialize + Deserialize<'a>,
+        L: TransparentLog<'a, T>,
+        L::LogSize: Display + FromStr,
+    {
+        self.log.seal()?;
+        self.publish()
+    }
+
+    /// The last checkpoint published, which may be older than the latest tree
+    pub fn latest_with_timestamp(&self) -> Option<&TimestampedCheckpoint> {
+        self.checkpoints.last()
+    }
+
+    /// All the checkpoints published, oldest first
+    pub fn checkpoints(&self) -> &[TimestampedCheckpoint] {
+        &self.checkpoints
+    }
+
+    /// The wrapped log
+    pub fn inner(&self) -> &L {
+        &self.log
+    }
+
+    /// Get back the wrapped log
+    pub fn into_inner(self) -> L {
+        self.log
+    }
+}
+
+impl<'a, T, L, S, A> TransparentLog<'a, T> for TimestampingLog<L, S, A>
+where
+    T: Serialize + Deserialize<'a>,
+    L: TransparentLog<'a, T>,
+{
+    type LogSize = L::LogSize;
+
+    fn add(&mut self, record: T) -> anyhow::Result<Self::LogSize> {
+        self.log.add(record)
+    }
+
+    fn add_tombstone(&mut self) -> anyhow::Result<Self::LogSize> {
+        self.log.add_tombstone()
+    }
+
+    fn redact(&mut self, id: Self::LogSize) -> anyhow::Result<()> {
+        self.log.redact(id)
+    }
+
+    fn expire(&mut self, id: Self::LogSize) -> anyhow::Result<()> {
+        self.log.expire(id)
+    }
+
+    fn expired(&self, id: Self::LogSize) -> anyhow::Result<bool> {
+        self.log.expired(id)
+    }
+
+    fn seal(&mut self) -> anyhow::Result<LogTree<Self::LogSize>> {
+        self.log.seal()
+    }
+
+    fn sealed(&self) -> anyhow::Result<Option<LogTree<Self::LogSize>>> {
+        self.log.sealed()
+    }
+
+    fn sync(&self) -> anyhow::Result<()> {
+        self.log.sync()
+    }
+
+    fn add_meta(&mut self, id: Self::LogSize, meta: RecordMeta) -> anyhow::Result<()> {
+        self.log.add_meta(id, meta)
+    }
+
+    fn get_meta(&self, id: Self::LogSize) -> anyhow::Result<Option<RecordMeta>> {
+        self.log.get_meta(id)
+    }
+
+    fn set_annotation(&mut self, id: Self::LogSize, annotation: Vec<u8>) -> anyhow::Result<()> {
+        self.log.set_annotation(id, annotation)
+    }
+
+    fn get_annotation(&self, id: Self::LogSize) -> anyhow::Result<Option<Vec<u8>>> {
+        self.log.get_annotation(id)
+    }
+
+    fn find_since(&self, timestamp: u64) -> anyhow::Result<Self::LogSize> {
+        self.log.find_since(timestamp)
+    }
+
+    fn add_hash(&mut self, level: LogHeight, hash: String) -> anyhow::Result<Self::LogSize> {
+        self.log.add_hash(level, hash)
+    }
+
+    fn get_hash(
+        &self,
+        level: LogHeight,
+        index: Self::LogSize,
+    ) -> anyhow::Result<MaybeOwned<'_, String>> {
+        self.log.get_hash(level, index)
+    }
+
+    fn append(&mut self, record: T) -> anyhow::Result<Record<Self::LogSize>> {
+        self.log.append(record)
+    }
+
+    fn append_hash_only(&mut self, hash: String) -> anyhow::Result<Record<Self::LogSize>> {
+        self.log.append_hash_only(hash)
+    }
+
+    fn bulk_append(&mut self, records: Vec<T>) -> anyhow::Result<Vec<Record<Self::LogSize>>>
+    where
+        T: Sync,
+    {
+        self.log.bulk_append(records)
+    }
+
+    fn size(&self) -> anyhow::Result<Self::LogSize> {
+        self.log.size()
+    }
+
+    fn origin(&self) -> Option<String> {
+        self.log.origin()
+    }
+
+    fn latest(&self) -> anyhow::Result<LogTree<Self::LogSize>> {
+        self.log.latest()
+    }
+
+    fn leaf_hasher(&self) -> LeafHasher<T> {
+        self.log.leaf_hasher()
+    }
+
+    fn get(&self, index: Self::LogSize) -> anyhow::Result<Option<MaybeOwned<'_, T>>> {
+        self.log.get(index)
+    }
+
+    fn get_raw_bytes(&self, index: Self::LogSize) -> anyhow::Result<Option<Vec<u8>>> {
+        self.log.get_raw_bytes(index)
+    }
+
+    fn get_leaf_bytes(&self, index: Self::LogSize) -> anyhow::Result<Option<Vec<u8>>> {
+        self.log.get_leaf_bytes(index)
+    }
+
+    fn get_range(
+        &self,
+        start: Self::LogSize,
+        end: Self::LogSize,
+    ) -> anyhow::Result<Vec<MaybeOwned<'_, T>>> {
+        self.log.get_range(start, end)
+    }
+
+    fn iter(&self) -> LogIterator<'_, Self::LogSize, T> {
+        self.log.iter()
+    }
+
+    fn index_of(&self, hash: &str) -> anyhow::Result<Option<Self::LogSize>> {
+        self.log.index_of(hash)
+    }
+
+    fn indices_of(&self, hash: &str) -> anyhow::Result<Vec<Self::LogSize>> {
+        self.log.indices_of(hash)
+    }
+
+    fn proofs<I>(
+        &self,
+        positions: I,
+    ) -> anyhow::Result<HashMap<LogTreePosition<Self::LogSize>, String>>
+    where
+        I: Iterator<Item = LogTreePosition<Self::LogSize>>,
+    {
+        self.log.proofs(positions)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::{der, der_integer, sha256, SHA256_ALGORITHM, SIGNED_DATA, TST_INFO};
+    use crate::test_helpers::*;
+    use crate::*;
+    use std::cell::Cell;
+
+    /// A DER timestamp token over a message, as an authority would return it, without signer information
+    fn token(msg: &[u8], time: &str, nonce: u64) -> Vec<u8> {
+        let mut imprint = SHA256_ALGORITHM.to_vec();
+        imprint.extend(der(0x04, &sha256(msg)));
+        let mut tst_info = der_integer(1);
+        tst_info.extend(der(0x06, &[0x2a, 0x03, 0x04]));
+        tst_info.extend(der(0x30, &imprint));
+        tst_info.extend(der_integer(42));
+        tst_info.extend(der(0x18, time.as_bytes()));
+        tst_info.extend(der(0x30, &der_integer(1)));
+        tst_info.extend(der_integer(nonce));
+        let mut encapsulated = der(0x06, &TST_INFO);
+        encapsulated.extend(der(0xa0, &der(0x04, &der(0x30, &tst_info))));
+        let mut signed_data = der_integer(3);
+        signed_data.extend(der(0x31, &SHA256_ALGORITHM));
+        signed_data.extend(der(0x30, &encapsulated));
+        signed_data.extend(der(0x31, &[0_u8; 200]));
+        let mut content_info = der(0x06, &SIGNED_DATA);
+        content_info.extend(der(0xa0, &der(0x30, &signed_data)));
+        der(0x30, &content_info)
+    }
+
+    /// An authority timestamping everything at the same time
+    struct FixedAuthority {
+        requests: Cell<u64>,
+    }
+
+    impl TimestampAuthority for FixedAuthority {
+        fn name(&self) -> &str {
+            "tsa.example.com"
+        }
+
+        fn timestamp(&self, msg: &[u8]) -> anyhow::Result<Vec<u8>> {
+            let nonce = self.requests.get();
+            self.requests.set(nonce + 1);
+            let mut response = der(0x30, &der_integer(0));
+            response.extend(token(msg, "20260102030405Z", nonce));
+            Ok(rfc3161_token(&der(0x30, &response))?)
+        }
+    }
+
+    #[test]
+    fn timestamp_rfc3161() -> anyhow::Result<()> {
+        let request = rfc3161_request(b"note", 0x80);
+        assert_eq!(&[0x30, 0x3d, 0x02, 0x01, 0x01, 0x30, 0x31], &request[..7]);
+        assert_eq!(&sha256(b"note")[..], &request[24..56]);
+        assert_eq!(&[0x02, 0x02, 0x00, 0x80, 0x01, 0x01, 0xff], &request[56..]);
+
+        let t = token(b"note", "20260102030405.25Z", 7);
+        let info = Rfc3161Token::parse(&t)?;
+        assert_eq!(1_767_323_045_250, info.time);
+        assert_eq!(Some(7), info.nonce);
+        let verifier = Rfc3161Verifier::new("tsa.example.com", |_: &[u8]| true);
+        assert_eq!(1_767_323_045_250, verifier.verify(b"note", &t)?);
+        assert_eq!(
+            Err(TimestampError::ImprintMismatch),
+            verifier.verify(b"other", &t)
+        );
+        let forged = Rfc3161Verifier::new("tsa.example.com", |_: &[u8]| false);
+        assert_eq!(
+            Err(TimestampError::InvalidSignature("tsa.example.com".into())),
+            forged.verify(b"note", &t)
+        );
+        assert!(Rfc3161Token::parse(&t[..t.len() - 1]).is_err());
+
+        let rejection = der(0x30, &der(0x30, &der_integer(2)));
+        assert_eq!(Err(TimestampError::Rejected(2)), rfc3161_token(&rejection));
+        Ok(())
+    }
+
+    #[test]
+    fn timestamp_log() -> anyhow::Result<()> {
+        let signer = Ed25519Signer::new("example.com/log", &[7_u8; 32]);
+        let verifier = signer.verifier();
+        let authority = FixedAuthority {
+            requests: Cell::new(0),
+        };
+        let tsa = Rfc3161Verifier::new("tsa.example.com", |_: &[u8]| true);
+        let mut ml =
+            TimestampingLog::new(InMemoryLog::default(), "example.com/log", signer, authority);
+        assert!(ml.latest_with_timestamp().is_none());
+        append_multiple(&mut ml, 5)?;
+        let first = ml.publish()?.clone();
+        let (checkpoint, time) = first.open::<usize>(&[&verifier], &[&tsa])?;
+        assert_eq!(ml.latest()?.hash, checkpoint.tree.hash);
+        assert_eq!(1_767_323_045_000, time);
+
+        // the same tree is only timestamped once
+        assert_eq!(&first, ml.publish()?);
+        append_multiple_offset(&mut ml, 5, 8)?;
+        ml.publish()?;
+        assert_eq!(2, ml.checkpoints().len());
+        let latest = ml.latest_with_timestamp().unwrap();
+        let (checkpoint, _) = latest.open::<usize>(&[&verifier], &[&tsa])?;
+        assert_eq!(13, checkpoint.tree.size);
+        assert_eq!(
+            latest,
+            &serde_json::from_str(&serde_json::to_string(latest)?)?
+        );
+
+        let unknown = Rfc3161Verifier::new("other.example.com", |_: &[u8]| true);
+        let err = latest.open::<usize>(&[&verifier], &[&unknown]).unwrap_err();
+        assert_eq!(
+            Some(&TimestampError::UnknownAuthority("tsa.example.com".into())),
+            err.downcast_ref()
+        );
+        let swapped = TimestampedCheckpoint {
+            token: first.token.clone(),
+            ..latest.clone()
+        };
+        assert!(swapped.open::<usize>(&[&verifier], &[&tsa]).is_err());
+
+        // the final checkpoint of a sealed log is the latest one published
+        append_multiple_offset(&mut ml, 13, 1)?;
+        let last = ml.publish_sealed()?.clone();
+        assert_eq!(3, ml.checkpoints().len());
+        let (checkpoint, _) = last.open::<usize>(&[&verifier], &[&tsa])?;
+        assert_eq!(14, checkpoint.tree.size);
+        assert_sealed(ml.append(LogRecord::new("rec14")));
+        Ok(())
+    }
+}
@@ -0,0 +1,247 @@
+//! Offline verification: check inclusion proofs against signed tree heads, without access to the log
+use crate::base::*;
+#[cfg(feature = "checkpoint")]
+use crate::bundle::*;
+#[cfg(feature = "checkpoint")]
+use crate::checkpoint::*;
+use num::Integer;
+#[cfg(feature = "checkpoint")]
+use serde::{de::DeserializeOwned, Serialize};
+#[cfg(feature = "checkpoint")]
+use std::fmt::Display;
+use std::hash::Hash;
+#[cfg(feature = "checkpoint")]
+use std::str::FromStr;
+
+pub use transparentlog_verify::VerifyError;
+
+/// Verify that the leaf hash is included in the given tree
+/// Only the proof hashes at the positions needed for the leaf are used, so extra hashes cannot stand for the leaf
+pub fn verify_inclusion<LogSize: Integer + Copy + Hash>(
+    proof: &InclusionProof<LogSize>,
+    leaf_hash: &str,
+    tree: &LogTree<LogSize>,
+) -> Result<(), VerifyError> {
+    if !hash_eq(&proof.record.hash, leaf_hash) {
+        return Err(VerifyError::LeafMismatch);
+    }
+    if try_verify(tree, &proof.record, &proof.proofs)? {
+        Ok(())
+    } else {
+        Err(VerifyError::InvalidProof)
+    }
+}
+
+/// Verify that the leaf hash is included in the tree of a checkpoint signed by one of the verifiers, returning that checkpoint
+#[cfg(feature = "checkpoint")]
+pub fn verify_inclusion_proof<LogSize>(
+    proof: &InclusionProof<LogSize>,
+    leaf_hash: &str,
+    checkpoint: &str,
+    verifiers: &[&dyn LogVerifier],
+) -> anyhow::Result<Checkpoint<LogSize>>
+where
+    LogSize: Integer + Copy + Hash + Display + FromStr,
+{
+    let checkpoint = Checkpoint::open(checkpoint, verifiers)?;
+    verify_inclusion(proof, leaf_hash, &checkpoint.tree)?;
+    Ok(checkpoint)
+}
+
+/// Verify that a sealed bundle proves the inclusion of the leaf hash in a checkpoint signed by one of the verifiers, returning that checkpoint
+#[cfg(feature = "checkpoint")]
+pub fn verify_bundle<LogSize>(
+    bundle: &[u8],
+    leaf_hash: &str,
+    verifiers: &[&dyn LogVerifier],
+) -> anyhow::Result<Checkpoint<LogSize>>
+where
+    LogSize: Integer + Copy + Hash + Display + FromStr + Serialize + DeserializeOwned,
+{
+    let bundle = Bundle::unseal(bundle)?;
+    let note = bundle.checkpoint.as_ref().ok_or(BundleError::Unsigned)?;
+    verify_inclusion_proof(&bundle.proof, leaf_hash, note, verifiers)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::test_helpers::*;
+    use crate::*;
+    use std::collections::HashMap;
+
+    #[cfg(feature = "checkpoint")]
+    #[test]
+    fn verify_offline() -> anyhow::Result<()> {
+        let signer = Ed25519Signer::new("example.com/log", &[7_u8; 32]);
+        let verifier = signer.verifier();
+        let mut ml: InMemoryLog<LogRecord> = InMemoryLog::default();
+        append_multiple(&mut ml, 13)?;
+        let leaf = hash(&LogRecord::new("rec9"))?;
+
+        let b = Bundle::prove(&ml, 9, "example.com/log", &[&signer])?.seal()?;
+        let checkpoint = verify_bundle::<usize>(&b, &leaf, &[&verifier])?;
+        assert_eq!(ml.latest()?, checkpoint.tree);
+        assert!(verify_bundle::<usize>(&b, &hash_two(0)?, &[&verifier]).is_err());
+        let unsigned = Bundle::prove(&ml, 9, "example.com/log", &[])?.seal()?;
+        assert!(verify_bundle::<usize>(&unsigned, &leaf, &[&verifier]).is_err());
+
+        let note = Checkpoint::new("example.com/log", ml.latest()?).sign(&[&signer])?;
+        let proof = prove_inclusion(&ml, 9, 13)?;
+        verify_inclusion_proof(&proof, &leaf, &note, &[&verifier])?;
+
+        let mut missing = proof.clone();
+        let p = *missing.proofs.keys().next().unwrap();
+        missing.proofs.remove(&p);
+        assert!(matches!(
+            verify_inclusion(&missing, &leaf, &checkpoint.tree),
+            Err(VerifyError::MissingProof(_))
+        ));
+
+        let mut forged = proof.clone();
+        forged.record.hash = hash_two(0)?;
+        forged.proofs.insert(
+            LogTreePosition { level: 1, index: 4 },
+            ml.get_hash(1, 4)?.into_owned(),
+        );
+        assert!(!verify(&checkpoint.tree, &forged.record, &forged.proofs));
+        assert!(matches!(
+            verify_inclusion(&forged, &forged.record.hash, &checkpoint.tree),
+            Err(VerifyError::InvalidProof)
+        ));
+        assert!(matches!(
+            verify_inclusion(
+                &proof,
+                &leaf,
+                &LogTree {
+                    size: 9,
+                    hash: checkpoint.tree.hash.clone(),
+                    origin: None,
+                }
+            ),
+            Err(VerifyError::OutOfTree)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn verify_adversarial() -> anyhow::Result<()> {
+        let mut ml: InMemoryLog<LogRecord> = InMemoryLog::default();
+        append_multiple(&mut ml, 13)?;
+        let tree = ml.latest()?;
+        let proof = prove_inclusion(&ml, 9, 13)?;
+        assert_eq!(Ok(true), proof.try_verify(&tree));
+
+        // a truncated proof or an empty hash is incomplete, not a mismatch
+        for p in proof.proofs.keys() {
+            let mut missing = proof.clone();
+            missing.proofs.remove(p);
+            assert_eq!(
+                Err(VerifyError::MissingProof(p.level)),
+                missing.try_verify(&tree)
+            );
+            assert!(!missing.verify(&tree));
+            let mut empty = proof.clone();
+            empty.proofs.insert(*p, String::new());
+            assert_eq!(
+                Err(VerifyError::MissingProof(p.level)),
+                empty.try_verify(&tree)
+            );
+            assert!(!empty.verify(&tree));
+            let mut wrong = proof.clone();
+            wrong.proofs.insert(*p, hash_two(0)?);
+            assert_eq!(Ok(false), wrong.try_verify(&tree));
+        }
+
+        // hashes at other positions cannot stand for the record
+        let mut forged = proof.clone();
+        forged.record.hash = hash_two(0)?;
+        forged.proofs.insert(
+            LogTreePosition { level: 1, index: 4 },
+            ml.get_hash(1, 4)?.into_owned(),
+        );
+        assert_eq!(Ok(false), forged.try_verify(&tree));
+        let mut outside = proof.clone();
+        outside.record.id = 13;
+        assert_eq!(Err(VerifyError::OutOfTree), outside.try_verify(&tree));
+
+        let hashes: Vec<String> = (4..9)
+            .map(|i| ml.get_hash(0, i).map(|h| h.into_owned()))
+            .collect::<anyhow::Result<_>>()?;
+        let range = range_proof(&ml, 4, 9, 13)?;
+        assert_eq!(Ok(true), range.try_verify(&tree, &hashes));
+        let p = *range.proofs.keys().next().unwrap();
+        let mut missing = range.clone();
+        missing.proofs.remove(&p);
+        assert_eq!(
+            Err(VerifyError::MissingProof(p.level)),
+            missing.try_verify(&tree, &hashes)
+        );
+        let mut forged = hashes.clone();
+        forged[2] = hash_two(0)?;
+        assert_eq!(Ok(false), range.try_verify(&tree, &forged));
+        assert_eq!(Ok(false), range.try_verify(&tree, &hashes[1..]));
+        assert_eq!(
+            Err(VerifyError::OutOfTree),
+            try_verify_range(&tree, 4, &[], &range.proofs)
+        );
+
+        let head = ml.head_at(5)?;
+        let consistency = ml.consistency_between(5, 13)?;
+        assert_eq!(Ok(true), consistency.try_verify(&head, &tree));
+        let empty = HashMap::new();
+        assert_eq!(
+            Err(VerifyError::MissingProof(0)),
+            try_verify_consistency_between(&head, &tree, &empty)
+        );
+        assert!(!verify_consistency_between(&head, &tree, &empty));
+        assert_eq!(
+            Err(VerifyError::MissingProof(0)),
+            try_verify_tree(&tree, &empty)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn verify_same_as_standalone() -> anyhow::Result<()> {
+        use std::collections::BTreeMap;
+        use transparentlog_verify as standalone;
+
+        let mut ml: InMemoryLog<LogRecord> = InMemoryLog::default();
+        let mut previous: Option<standalone::LogTree<usize>> = None;
+        for size in 1..40_usize {
+            ml.append(LogRecord::new(&format!("rec{}", size - 1)))?;
+            let latest = ml.latest()?;
+            let tree = standalone::LogTree {
+                size: latest.size,
+                hash: latest.hash.clone(),
+            };
+            for index in 0..size {
+                let mut expected: Vec<_> = proof_positions(index, size).into_iter().collect();
+                expected.sort();
+                let positions: Vec<_> = standalone::proof_positions(index, size)
+                    .into_iter()
+                    .collect();
+                assert_eq!(expected, positions);
+
+                let proofs = positions
+                    .iter()
+                    .map(|p| Ok((*p, ml.get_hash(p.level, p.index)?.into_owned())))
+                    .collect::<anyhow::Result<BTreeMap<_, _>>>()?;
+                let leaf = ml.get_hash(0, index)?.into_owned();
+                assert!(standalone::verify(&tree, index, &leaf, &proofs));
+                assert!(!standalone::verify(&tree, index, &hash_two(0)?, &proofs));
+            }
+            if let Some(previous) = previous {
+                let proofs = standalone::prefix_proof_positions(size - 1, size)
+                    .iter()
+                    .map(|p| Ok((*p, ml.get_hash(p.level, p.index)?.into_owned())))
+                    .collect::<anyhow::Result<BTreeMap<_, _>>>()?;
+                assert!(standalone::verify_consistency(&previous, &tree, &proofs));
+                assert!(!standalone::verify_consistency(&tree, &previous, &proofs));
+            }
+            previous = Some(tree);
+        }
+        Ok(())
+    }
+}
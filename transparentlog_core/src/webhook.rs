@@ -0,0 +1,399 @@
+//! # Webhooks
+//!
+//! A `WebhookDispatcher` posts a JSON `WebhookEvent` to registered URLs for each record appended, read from the
+//! channel of a `NotifyingLog`, and for each new checkpoint, so integrators get receipts pushed instead of polling.
+//! Each body is signed with the HMAC-SHA256 of the secret of its webhook, in the `X-Transparentlog-Signature` header,
+//! and deliveries failing on the network or with a server error are retried with an exponential backoff.
+//! The requests go through a `WebhookTransport`, so servers use the HTTP client they already have.
+use crate::base::*;
+use crate::checkpoint::Checkpoint;
+use crate::json::to_json;
+use crate::notify::AppendEvent;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::sync::mpsc::Receiver;
+use std::time::Duration;
+use subtle::ConstantTimeEq;
+use thiserror::Error;
+
+/// The header holding the signature of the body
+pub const SIGNATURE_HEADER: &str = "X-Transparentlog-Signature";
+
+/// Specific Errors for webhooks
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum WebhookError {
+    #[error("Webhook {url} rejected the event with status {status}")]
+    Rejected { url: String, status: u16 },
+    #[error("Webhook {url} failed after {attempts} attempts: {error}")]
+    Exhausted {
+        url: String,
+        attempts: u32,
+        error: String,
+    },
+}
+
+/// Posts the events to the webhooks
+pub trait WebhookTransport {
+    /// Post a JSON body with the given headers, returning the HTTP status of the response
+    fn post(&self, url: &str, headers: &[(&str, String)], body: &str) -> anyhow::Result<u16>;
+}
+
+/// An event posted to the webhooks, tagged with its kind in the `event` field
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum WebhookEvent<LogSize> {
+    /// A record appended, with the tree that first included it
+    Append {
+        index: LogSize,
+        hash: String,
+        tree: LogTree<LogSize>,
+    },
+    /// A new signed checkpoint
+    Checkpoint {
+        tree: LogTree<LogSize>,
+        note: String,
+    },
+}
+
+impl<LogSize> From<AppendEvent<LogSize>> for WebhookEvent<LogSize> {
+    fn from(event: AppendEvent<LogSize>) -> Self {
+        Self::Append {
+            index: event.record.id,
+            hash: event.record.hash,
+            tree: event.tree,
+        }
+    }
+}
+
+impl<LogSize: Clone> WebhookEvent<LogSize> {
+    /// The event of a new checkpoint and its signed note
+    pub fn checkpoint(checkpoint: &Checkpoint<LogSize>, note: &str) -> Self {
+        Self::Checkpoint {
+            tree: LogTree {
+                origin: Some(checkpoint.origin.clone()),
+                ..checkpoint.tree.clone()
+            },
+            note: note.to_owned(),
+        }
+    }
+}
+
+/// A registered URL, and the secret signing the events posted to it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Webhook {
+    pub url: String,
+    pub secret: String,
+}
+
+/// How failed deliveries are retried
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RetryPolicy {
+    /// The number of attempts, the first one included
+    pub attempts: u32,
+    /// The delay before the first retry, doubling before each next one
+    pub backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    /// Five attempts, the first retry after half a second
+    fn default() -> Self {
+        Self {
+            attempts: 5,
+            backoff: Duration::from_millis(500),
+        }
+    }
+}
+
+/// The size in bytes of the blocks of SHA-256, that HMAC pads keys to
+const SHA256_BLOCK_SIZE: usize = 64;
+
+/// The HMAC-SHA256 of a message with a key, as in RFC 2104
+fn hmac_sha256(key: &[u8], msg: &[u8]) -> Vec<u8> {
+    let mut block = [0_u8; SHA256_BLOCK_SIZE];
+    if key.len() > SHA256_BLOCK_SIZE {
+        block[..DIGEST_SIZE].copy_from_slice(&Sha256::digest(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+    let pad = |b: u8| block.iter().map(|k| k ^ b).collect::<Vec<_>>();
+    let inner = Sha256::new()
+        .chain_update(pad(0x36))
+        .chain_update(msg)
+        .finalize();
+    Sha256::new()
+        .chain_update(pad(0x5c))
+        .chain_update(inner)
+        .finalize()
+        .to_vec()
+}
+
+/// The signature of a body with a secret: `sha256=` and the hexadecimal HMAC-SHA256
+pub fn sign_webhook(secret: &str, body: &str) -> String {
+    format!(
+        "sha256={}",
+        hex::encode(hmac_sha256(secret.as_bytes(), body.as_bytes()))
+    )
+}
+
+/// Check the signature of a body received by a webhook, in constant time
+pub fn verify_webhook(secret: &str, body: &str, signature: &str) -> bool {
+    let expected = sign_webhook(secret, body);
+    expected.as_bytes().ct_eq(signature.as_bytes()).into()
+}
+
+/// Posts the events to all the registered webhooks
+pub struct WebhookDispatcher<Tr> {
+    transport: Tr,
+    webhooks: Vec<Webhook>,
+    retry: RetryPolicy,
+}
+
+impl<Tr: WebhookTransport> WebhookDispatcher<Tr> {
+    /// A dispatcher posting through the given transport, with the default retry policy
+    pub fn new(transport: Tr, webhooks: Vec<Webhook>) -> Self {
+        Self {
+            transport,
+            webhooks,
+            retry: RetryPolicy::default(),
+        }
+    }
+
+    /// Retry failed deliveries with the given policy
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Register a webhook
+    pub fn register(&mut self, webhook: Webhook) {
+        self.webhooks.push(webhook);
+    }
+
+    /// The registered webhooks
+    pub fn webhooks(&self) -> &[Webhook] {
+        &self.webhooks
+    }
+
+    /// Post an event to all the webhooks, returning the errors of the ones that did not accept it
+    pub fn dispatch<LogSize: Serialize + DeserializeOwned>(
+        &self,
+        event: &WebhookEvent<LogSize>,
+    ) -> anyhow::Result<Vec<WebhookError>> {
+        let body = to_json(event)?;
+        Ok(self
+            .webhooks
+            .iter()
+            .filter_map(|webhook| self.deliver(webhook, &body).err())
+            .collect())
+    }
+
+    /// Post the events of the records appended to a `NotifyingLog`, until the log is dropped
+    /// The errors of the webhooks that did not accept an event are given to the `failed` function
+    pub fn run<LogSize, F>(&self, events: Receiver<AppendEvent<LogSize>>, mut failed: F)
+    where
+        LogSize: Serialize + DeserializeOwned,
+        F: FnMut(&WebhookEvent<LogSize>, anyhow::Result<Vec<WebhookError>>),
+    {
+        for event in events {
+            let event = WebhookEvent::from(event);
+            match self.dispatch(&event) {
+                Ok(errors) if errors.is_empty() => {}
+                errors => failed(&event, errors),
+            }
+        }
+    }
+
+    /// Post a body to a webhook, retrying on network and server errors
+    fn deliver(&self, webhook: &Webhook, body: &str) -> Result<(), WebhookError> {
+        let headers = [
+            ("Content-Type", "application/json".to_owned()),
+            (SIGNATURE_HEADER, sign_webhook(&webhook.secret, body)),
+        ];
+        let mut backoff = self.retry.backoff;
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+            let error = match self.transport.post(&webhook.url, &headers, body) {
+                Ok(status) if (200..300).contains(&status) => return Ok(()),
+                // client errors will not go away by themselves, except timeouts and rate limits
+                Ok(status) if (400..500).contains(&status) && status != 408 && status != 429 => {
+                    return Err(WebhookError::Rejected {
+                        url: webhook.url.clone(),
+                        status,
+                    })
+                }
+                Ok(status) => format!("status {}", status),
+                Err(e) => format!("{:#}", e),
+            };
+            if attempts >= self.retry.attempts {
+                return Err(WebhookError::Exhausted {
+                    url: webhook.url.clone(),
+                    attempts,
+                    error,
+                });
+            }
+            std::thread::sleep(backoff);
+            backoff *= 2;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::test_helpers::*;
+    use crate::*;
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    /// Answers each URL with the next of its statuses, the last one repeated, recording the requests
+    #[derive(Default)]
+    struct ScriptedTransport {
+        statuses: HashMap<String, Vec<u16>>,
+        requests: Mutex<Vec<(String, String, String)>>,
+    }
+
+    impl WebhookTransport for ScriptedTransport {
+        fn post(&self, url: &str, headers: &[(&str, String)], body: &str) -> anyhow::Result<u16> {
+            let mut requests = self.requests.lock().unwrap();
+            let signature = headers
+                .iter()
+                .find(|(name, _)| *name == SIGNATURE_HEADER)
+                .map(|(_, value)| value.clone())
+                .unwrap_or_default();
+            requests.push((url.to_owned(), signature, body.to_owned()));
+            let statuses = self
+                .statuses
+                .get(url)
+                .ok_or_else(|| anyhow::anyhow!("Connection refused"))?;
+            let sent = requests.iter().filter(|(u, _, _)| u == url).count();
+            Ok(statuses[(sent - 1).min(statuses.len() - 1)])
+        }
+    }
+
+    fn webhook(url: &str) -> Webhook {
+        Webhook {
+            url: url.to_owned(),
+            secret: format!("secret of {}", url),
+        }
+    }
+
+    #[test]
+    fn webhook_dispatch() -> anyhow::Result<()> {
+        let mut transport = ScriptedTransport::default();
+        transport
+            .statuses
+            .insert("https://ok.example.com".into(), vec![200]);
+        transport
+            .statuses
+            .insert("https://flaky.example.com".into(), vec![503, 429, 204]);
+        transport
+            .statuses
+            .insert("https://gone.example.com".into(), vec![410]);
+        let mut dispatcher = WebhookDispatcher::new(
+            transport,
+            vec![
+                webhook("https://ok.example.com"),
+                webhook("https://flaky.example.com"),
+            ],
+        )
+        .with_retry(RetryPolicy {
+            attempts: 3,
+            backoff: Duration::ZERO,
+        });
+
+        let mut ml = NotifyingLog::new(InMemoryLog::default());
+        let events = ml.subscribe();
+        append_multiple(&mut ml, 2)?;
+        events.recv()?;
+        let event = WebhookEvent::from(events.recv()?);
+        assert!(dispatcher.dispatch(&event)?.is_empty());
+        {
+            let requests = dispatcher.transport.requests.lock().unwrap();
+            assert_eq!(4, requests.len());
+            let (url, signature, body) = &requests[0];
+            assert_eq!("https://ok.example.com", url);
+            assert!(verify_webhook(&webhook(url).secret, body, signature));
+            assert!(!verify_webhook("other secret", body, signature));
+            assert!(body.contains("\"event\":\"append\""));
+            assert_eq!(event, from_json(body)?);
+        }
+        match &event {
+            WebhookEvent::Append { index, tree, .. } => {
+                assert_eq!(1, *index);
+                assert_eq!(ml.latest()?, *tree);
+            }
+            _ => panic!("not an append event"),
+        }
+
+        dispatcher.register(webhook("https://gone.example.com"));
+        dispatcher.register(webhook("https://down.example.com"));
+        let signer = Ed25519Signer::new("example.com/log", &[1_u8; 32]);
+        let checkpoint = Checkpoint::new("example.com/log", ml.latest()?);
+        let note = checkpoint.sign(&[&signer])?;
+        let errors = dispatcher.dispatch(&WebhookEvent::checkpoint(&checkpoint, &note))?;
+        assert_eq!(
+            vec![
+                WebhookError::Rejected {
+                    url: "https://gone.example.com".into(),
+                    status: 410
+                },
+                WebhookError::Exhausted {
+                    url: "https://down.example.com".into(),
+                    attempts: 3,
+                    error: "Connection refused".into()
+                },
+            ],
+            errors
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn webhook_run() -> anyhow::Result<()> {
+        let mut transport = ScriptedTransport::default();
+        transport
+            .statuses
+            .insert("https://ok.example.com".into(), vec![200]);
+        let dispatcher = WebhookDispatcher::new(
+            transport,
+            vec![
+                webhook("https://ok.example.com"),
+                webhook("https://down.example.com"),
+            ],
+        )
+        .with_retry(RetryPolicy {
+            attempts: 1,
+            backoff: Duration::ZERO,
+        });
+        let mut ml = NotifyingLog::new(InMemoryLog::default());
+        let events = ml.subscribe();
+        append_multiple(&mut ml, 3)?;
+        drop(ml);
+        let mut failed = vec![];
+        dispatcher.run(events, |event, errors| {
+            failed.push((event.clone(), errors.map(|e| e.len()).ok()))
+        });
+        assert_eq!(3, failed.len());
+        assert!(failed.iter().all(|(_, errors)| *errors == Some(1)));
+        assert_eq!(6, dispatcher.transport.requests.lock().unwrap().len());
+        Ok(())
+    }
+
+    #[test]
+    fn webhook_hmac() {
+        // RFC 4231 test cases 2 and 6
+        assert_eq!(
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843",
+            sign_webhook("Jefe", "what do ya want for nothing?")
+        );
+        assert_eq!(
+            "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54",
+            hex::encode(super::hmac_sha256(
+                &[0xaa; 131],
+                b"Test Using Larger Than Block-Size Key - Hash Key First"
+            ))
+        );
+    }
+}
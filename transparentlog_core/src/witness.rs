@@ -0,0 +1,462 @@
+//! # Witness policies
+//!
+//! Witnesses cosign the checkpoints of a log once they checked them consistent with the ones they saw before, so a
+//! client accepting a checkpoint cosigned by enough witnesses knows the log shows the same tree to all of them.
+//! A `WitnessPolicy` says which signatures a checkpoint needs: the one of a key, or a quorum of policies, like the
+//! log key and 3 of 5 witnesses, or 2 of 3 groups of witnesses run by different organizations.
+//! Policies are read from configuration with serde, keys being verifier keys in the `<name>+<hash>+<base64 key>` format.
+//! A `Witness` is the other side: it keeps the latest checkpoint it saw for each log it watches, and only cosigns a
+//! new checkpoint once it proved it a continuation of that one.
+use crate::base::*;
+use crate::checkpoint::*;
+use num::{Integer, Zero};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::Display;
+use std::hash::Hash;
+use std::str::FromStr;
+use thiserror::Error;
+
+/// Specific Errors for witness policies
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum WitnessPolicyError {
+    #[error("Invalid witness policy: {0}")]
+    Invalid(&'static str),
+    #[error("Checkpoint signatures do not satisfy the witness policy")]
+    Unsatisfied,
+}
+
+/// Specific Errors for witnesses
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum WitnessError {
+    #[error("Log {0} is not witnessed")]
+    UnknownLog(String),
+    #[error("Checkpoint of log {0} older than the witnessed one")]
+    Stale(String),
+    #[error("Checkpoint of log {0} inconsistent with the witnessed one")]
+    Inconsistent(String),
+}
+
+/// The signatures a checkpoint needs to be accepted
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WitnessPolicy {
+    /// A valid signature from the given key
+    Key(#[serde(with = "vkey")] Ed25519Verifier),
+    /// At least `threshold` of the given policies satisfied
+    Quorum {
+        threshold: usize,
+        of: Vec<WitnessPolicy>,
+    },
+}
+
+impl WitnessPolicy {
+    /// A signature from the key of the given verifier key
+    pub fn key(vkey: &str) -> Result<Self, CheckpointError> {
+        Ok(Self::Key(Ed25519Verifier::from_vkey(vkey)?))
+    }
+
+    /// At least `threshold` of the given policies
+    pub fn quorum(threshold: usize, of: Vec<WitnessPolicy>) -> Self {
+        Self::Quorum { threshold, of }
+    }
+
+    /// All the given policies
+    pub fn all(of: Vec<WitnessPolicy>) -> Self {
+        Self::quorum(of.len(), of)
+    }
+
+    /// Check the policy can be satisfied, and not by a checkpoint without signatures
+    pub fn validate(&self) -> Result<(), WitnessPolicyError> {
+        if let Self::Quorum { threshold, of } = self {
+            if *threshold == 0 {
+                return Err(WitnessPolicyError::Invalid("quorum of no policy"));
+            }
+            if *threshold > of.len() {
+                return Err(WitnessPolicyError::Invalid(
+                    "quorum larger than its policies",
+                ));
+            }
+            for (ix, p) in of.iter().enumerate() {
+                // a key counted twice would make a smaller quorum
+                if of[..ix].contains(p) {
+                    return Err(WitnessPolicyError::Invalid("duplicate policy in quorum"));
+                }
+                p.validate()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The verifiers of all the keys of the policy
+    pub fn verifiers(&self) -> Vec<&Ed25519Verifier> {
+        match self {
+            Self::Key(verifier) => vec![verifier],
+            Self::Quorum { of, .. } => of.iter().flat_map(Self::verifiers).collect(),
+        }
+    }
+
+    /// Whether the given verified signatures satisfy the policy
+    pub fn satisfied_by(&self, signatures: &[NoteSignature]) -> bool {
+        match self {
+            Self::Key(verifier) => signatures
+                .iter()
+                .any(|s| s.name == verifier.name() && s.key_hash == verifier.key_hash()),
+            Self::Quorum { threshold, of } => {
+                of.iter().filter(|p| p.satisfied_by(signatures)).count() >= *threshold
+            }
+        }
+    }
+
+    /// Open a signed checkpoint, verifying its signatures satisfy the policy
+    /// Fails with a `CheckpointError::InvalidSignature` error if a signature from a key of the policy is invalid,
+    /// and with a `WitnessPolicyError::Unsatisfied` error if the valid signatures are not enough
+    pub fn open<LogSize: Display + FromStr + Zero>(
+        &self,
+        note: &str,
+    ) -> anyhow::Result<Checkpoint<LogSize>> {
+        self.validate()?;
+        let verifiers = self.verifiers();
+        let verifiers: Vec<&dyn LogVerifier> =
+            verifiers.iter().map(|v| *v as &dyn LogVerifier).collect();
+        let signatures = match open_note(note, &verifiers) {
+            Ok((_, signatures)) => signatures,
+            Err(CheckpointError::UnverifiedNote) => vec![],
+            Err(e) => return Err(e.into()),
+        };
+        if !self.satisfied_by(&signatures) {
+            return Err(WitnessPolicyError::Unsatisfied.into());
+        }
+        let (text, _) = parse_note(note)?;
+        Ok(Checkpoint::parse(text)?)
+    }
+}
+
+/// Move the client to the tree of a signed checkpoint, if its signatures satisfy the policy
+/// and it is consistent with the latest tree of the client, using the proofs of the log
+/// Returns false if the trees are inconsistent, and fails with an `OriginError::Mismatch` error
+/// for a checkpoint of another log than the one of the client
+pub fn accept_checkpoint<'a, T, K, TL, LC>(
+    client: &mut LC,
+    log: &TL,
+    note: &str,
+    policy: &WitnessPolicy,
+) -> anyhow::Result<bool>
+where
+    T: Serialize + Deserialize<'a>,
+    TL: ProofSource<'a, T, K>,
+    TL::LogSize: Display + FromStr,
+    LC: LogClient<'a, T, TL, K>,
+{
+    let checkpoint = policy.open::<TL::LogSize>(note)?;
+    let origin = client.latest().origin.clone().or_else(|| log.log_origin());
+    if origin.iter().any(|o| *o != checkpoint.origin) {
+        return Err(OriginError::Mismatch {
+            expected: origin,
+            found: Some(checkpoint.origin),
+        }
+        .into());
+    }
+    let tree = LogTree {
+        origin: Some(checkpoint.origin),
+        ..checkpoint.tree
+    };
+    move_latest(client, log, tree)
+}
+
+/// The latest checkpoint a witness cosigned for a log
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WitnessedHead<LogSize> {
+    /// The tree of the checkpoint, with the origin of the log
+    pub tree: LogTree<LogSize>,
+    /// The checkpoint note, with the signature of the witness
+    pub note: String,
+}
+
+/// A witness of logs, cosigning their checkpoints once it checked them consistent with the ones it cosigned before
+/// The first checkpoint of a log is trusted on first use, later ones need proofs from the log
+pub struct Witness<LogSize> {
+    signer: Box<dyn LogSigner + Send + Sync>,
+    logs: HashMap<String, Box<dyn LogVerifier + Send + Sync>>,
+    heads: BTreeMap<String, WitnessedHead<LogSize>>,
+}
+
+impl<LogSize> Witness<LogSize>
+where
+    LogSize: Integer + Copy + Hash + Display + FromStr,
+{
+    /// A witness cosigning with the given key, watching no log yet
+    pub fn new(signer: Box<dyn LogSigner + Send + Sync>) -> Self {
+        Self {
+            signer,
+            logs: HashMap::new(),
+            heads: BTreeMap::new(),
+        }
+    }
+
+    /// Watch the log of the given origin, whose checkpoints are signed by the key of the given verifier
+    pub fn add_log(&mut self, origin: &str, verifier: Box<dyn LogVerifier + Send + Sync>) {
+        self.logs.insert(origin.to_owned(), verifier);
+    }
+
+    /// Restore the heads saved from a previous run, so the witness does not trust the next checkpoints on first use
+    pub fn restore(&mut self, heads: BTreeMap<String, WitnessedHead<LogSize>>) {
+        self.heads = heads;
+    }
+
+    /// The latest cosigned checkpoint of each log, by origin
+    pub fn heads(&self) -> &BTreeMap<String, WitnessedHead<LogSize>> {
+        &self.heads
+    }
+
+    /// The latest cosigned checkpoint note of the log of the given origin
+    pub fn cosigned(&self, origin: &str) -> Option<&str> {
+        self.heads.get(origin).map(|h| h.note.as_str())
+    }
+
+    /// Cosign a checkpoint signed by a watched log, returning the cosigned note
+    /// The proofs the checkpoint is consistent with the witnessed one are asked to the given function
+    /// Fails with a `WitnessError` for a checkpoint of an unknown log, older than the witnessed one, or a fork of it
+    pub fn cosign<F>(&mut self, note: &str, fetch: F) -> anyhow::Result<String>
+    where
+        F: FnOnce(
+            HashSet<LogTreePosition<LogSize>>,
+        ) -> anyhow::Result<HashMap<LogTreePosition<LogSize>, String>>,
+    {
+        let (text, _) = parse_note(note)?;
+        let checkpoint = Checkpoint::<LogSize>::parse(text)?;
+        let origin = checkpoint.origin;
+        let verifier = self
+            .logs
+            .get(&origin)
+            .ok_or_else(|| WitnessError::UnknownLog(origin.clone()))?;
+        open_note(note, &[verifier.as_ref() as &dyn LogVerifier])?;
+        let tree = LogTree {
+            origin: Some(origin.clone()),
+            ..checkpoint.tree
+        };
+        if let Some(head) = self.heads.get(&origin) {
+            if tree.size < head.tree.size {
+                return Err(WitnessError::Stale(origin).into());
+            }
+            let consistent = if tree.size == head.tree.size || head.tree.size.is_zero() {
+                verify_consistency_between(&head.tree, &tree, &HashMap::new())
+            } else {
+                let proofs = fetch(prefix_proof_positions(head.tree.size, tree.size))?;
+                verify_consistency_between(&head.tree, &tree, &proofs)
+            };
+            if !consistent {
+                return Err(WitnessError::Inconsistent(origin).into());
+            }
+        }
+        let note = cosign_note(note, &[self.signer.as_ref() as &dyn LogSigner])?;
+        self.heads.insert(
+            origin,
+            WitnessedHead {
+                tree,
+                note: note.clone(),
+            },
+        );
+        Ok(note)
+    }
+}
+
+/// Verifier keys as strings, in the `<name>+<hash>+<base64 key>` format
+mod vkey {
+    use crate::checkpoint::Ed25519Verifier;
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        verifier: &Ed25519Verifier,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&verifier.vkey())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Ed25519Verifier, D::Error> {
+        Ed25519Verifier::from_vkey(&String::deserialize(deserializer)?).map_err(D::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::test_helpers::*;
+    use crate::*;
+
+    /// The log key, then five witnesses
+    fn signers() -> Vec<Ed25519Signer> {
+        let mut signers = vec![Ed25519Signer::new("example.com/log", &[1_u8; 32])];
+        signers.extend(
+            (2_u8..7).map(|seed| Ed25519Signer::new(&format!("witness{}", seed), &[seed; 32])),
+        );
+        signers
+    }
+
+    /// The log key and 3 of the 5 witnesses
+    fn policy(signers: &[Ed25519Signer]) -> WitnessPolicy {
+        let key = |s: &Ed25519Signer| WitnessPolicy::Key(s.verifier());
+        WitnessPolicy::all(vec![
+            key(&signers[0]),
+            WitnessPolicy::quorum(3, signers[1..].iter().map(key).collect()),
+        ])
+    }
+
+    #[test]
+    fn witness_quorum() -> anyhow::Result<()> {
+        let signers = signers();
+        let policy = policy(&signers);
+        policy.validate()?;
+        let mut ml = InMemoryLog::with_origin("example.com/log");
+        append_multiple(&mut ml, 5)?;
+        let mut client = InMemoryLogClientBuilder::new(&ml)?.build();
+        append_multiple_offset(&mut ml, 5, 3)?;
+        let checkpoint = Checkpoint::new("example.com/log", ml.latest()?);
+
+        // the log and two witnesses only
+        let note = checkpoint.sign(&[&signers[0], &signers[1], &signers[4]])?;
+        let err = accept_checkpoint(&mut client, &ml, &note, &policy).unwrap_err();
+        assert_eq!(
+            Some(&WitnessPolicyError::Unsatisfied),
+            err.downcast_ref::<WitnessPolicyError>()
+        );
+        // the witnesses without the log
+        let note = checkpoint.sign(&[&signers[1], &signers[2], &signers[3], &signers[5]])?;
+        assert!(policy.open::<usize>(&note).is_err());
+
+        let note = checkpoint.sign(&[&signers[0], &signers[2], &signers[3], &signers[5]])?;
+        // the note carries the origin of the checkpoint, not of its tree
+        assert_eq!(
+            Checkpoint::<usize>::parse(&checkpoint.text()?)?,
+            policy.open(&note)?
+        );
+        assert!(accept_checkpoint(&mut client, &ml, &note, &policy)?);
+        assert_eq!(&ml.latest()?, client.latest());
+
+        // a fork of the log, cosigned by the same witnesses
+        let mut fork = InMemoryLog::with_origin("example.com/log");
+        append_multiple_offset(&mut fork, 1, 8)?;
+        let note = Checkpoint::new("example.com/log", fork.latest()?).sign(&[
+            &signers[0],
+            &signers[1],
+            &signers[2],
+            &signers[3],
+        ])?;
+        assert!(!accept_checkpoint(&mut client, &ml, &note, &policy)?);
+        assert_eq!(8, client.latest().size);
+
+        let note = Checkpoint::new("other.com/log", ml.latest()?).sign(&[
+            &signers[0],
+            &signers[1],
+            &signers[2],
+            &signers[3],
+        ])?;
+        let err = accept_checkpoint(&mut client, &ml, &note, &policy).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<OriginError>(),
+            Some(OriginError::Mismatch { .. })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn witness_cosign() -> anyhow::Result<()> {
+        let signers = signers();
+        let log = &signers[0];
+        let mut witness = Witness::new(Box::new(Ed25519Signer::new("witness2", &[2; 32])));
+        witness.add_log("example.com/log", Box::new(log.verifier()));
+        let mut ml = InMemoryLog::with_origin("example.com/log");
+        append_multiple(&mut ml, 5)?;
+        let note = Checkpoint::new("example.com/log", ml.latest()?).sign(&[log])?;
+        let first = witness.cosign(&note, |_| unreachable!())?;
+        assert!(first.starts_with(&note));
+        assert_eq!(Some(first.as_str()), witness.cosigned("example.com/log"));
+        // the witness signs once
+        assert_eq!(first, cosign_note(&first, &[&signers[1]])?);
+
+        append_multiple_offset(&mut ml, 5, 8)?;
+        let note = Checkpoint::new("example.com/log", ml.latest()?).sign(&[log])?;
+        let second = witness.cosign(&note, |positions| ml.proofs(positions.into_iter()))?;
+        let policy = WitnessPolicy::all(vec![
+            WitnessPolicy::Key(log.verifier()),
+            WitnessPolicy::Key(signers[1].verifier()),
+        ]);
+        assert_eq!(13, policy.open::<usize>(&second)?.tree.size);
+        assert_eq!(ml.latest()?, witness.heads()["example.com/log"].tree);
+
+        let err = witness.cosign(&first, |_| unreachable!()).unwrap_err();
+        assert_eq!(
+            Some(&WitnessError::Stale("example.com/log".into())),
+            err.downcast_ref::<WitnessError>()
+        );
+        let mut fork = InMemoryLog::with_origin("example.com/log");
+        append_multiple_offset(&mut fork, 1, 15)?;
+        let forked = Checkpoint::new("example.com/log", fork.latest()?).sign(&[log])?;
+        let err = witness
+            .cosign(&forked, |positions| fork.proofs(positions.into_iter()))
+            .unwrap_err();
+        assert_eq!(
+            Some(&WitnessError::Inconsistent("example.com/log".into())),
+            err.downcast_ref::<WitnessError>()
+        );
+        let note = Checkpoint::new("other.com/log", ml.latest()?).sign(&[log])?;
+        let err = witness.cosign(&note, |_| unreachable!()).unwrap_err();
+        assert_eq!(
+            Some(&WitnessError::UnknownLog("other.com/log".into())),
+            err.downcast_ref::<WitnessError>()
+        );
+        // a checkpoint not signed by the log
+        let note = Checkpoint::new("example.com/log", ml.latest()?).sign(&[&signers[2]])?;
+        assert!(witness.cosign(&note, |_| unreachable!()).is_err());
+
+        // a restarted witness keeps checking from the saved heads
+        let saved = serde_json::to_string(witness.heads())?;
+        let mut restarted = Witness::new(Box::new(Ed25519Signer::new("witness2", &[2; 32])));
+        restarted.add_log("example.com/log", Box::new(log.verifier()));
+        restarted.restore(serde_json::from_str(&saved)?);
+        assert_eq!(second, restarted.cosign(&second, |_| unreachable!())?);
+        assert!(restarted
+            .cosign(&forked, |positions| fork.proofs(positions.into_iter()))
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn witness_policy_config() -> anyhow::Result<()> {
+        let signers = signers();
+        let policy = policy(&signers);
+        let json = serde_json::to_string(&policy)?;
+        assert!(json.starts_with(&format!(
+            "{{\"quorum\":{{\"threshold\":2,\"of\":[{{\"key\":\"{}\"}}",
+            signers[0].verifier().vkey()
+        )));
+        assert_eq!(policy, serde_json::from_str(&json)?);
+        assert_eq!(
+            WitnessPolicy::Key(signers[1].verifier()),
+            WitnessPolicy::key(&signers[1].verifier().vkey())?
+        );
+        assert!(
+            serde_json::from_str::<WitnessPolicy>("{\"key\":\"witness+00000000+AAAA\"}").is_err()
+        );
+        assert_eq!(6, policy.verifiers().len());
+
+        let key = WitnessPolicy::Key(signers[0].verifier());
+        assert_eq!(
+            Err(WitnessPolicyError::Invalid("quorum of no policy")),
+            WitnessPolicy::quorum(0, vec![key.clone()]).validate()
+        );
+        assert_eq!(
+            Err(WitnessPolicyError::Invalid(
+                "quorum larger than its policies"
+            )),
+            WitnessPolicy::all(vec![WitnessPolicy::quorum(2, vec![key.clone()])]).validate()
+        );
+        assert_eq!(
+            Err(WitnessPolicyError::Invalid("duplicate policy in quorum")),
+            WitnessPolicy::quorum(2, vec![key.clone(), key]).validate()
+        );
+        Ok(())
+    }
+}
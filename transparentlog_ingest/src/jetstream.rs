@@ -0,0 +1,51 @@
+//! NATS JetStream source
+use nats::jetstream::{self, PullSubscribeOptions, PullSubscription};
+use std::time::Duration;
+use transparentlog_core::{Message, MessageSource};
+
+/// A source pulling the messages of a subject from a durable JetStream consumer
+pub struct NatsSource {
+    subscription: PullSubscription,
+    timeout: Duration,
+}
+
+impl NatsSource {
+    /// A source pulling the messages of the subject through the durable consumer of the given name, created if needed,
+    /// so messages not acknowledged before a restart are delivered again
+    pub fn new(url: &str, subject: &str, durable: &str) -> anyhow::Result<Self> {
+        let context = jetstream::new(nats::connect(url)?);
+        let options = PullSubscribeOptions::new().durable_name(durable.to_owned());
+        Ok(Self {
+            subscription: context.pull_subscribe_with_options(subject, &options)?,
+            timeout: Duration::from_secs(1),
+        })
+    }
+
+    /// Set how long to wait for a batch of messages, one second by default
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+}
+
+impl MessageSource for NatsSource {
+    type Ack = nats::Message;
+
+    fn receive(&mut self, max: usize) -> anyhow::Result<Vec<Message<nats::Message>>> {
+        Ok(self
+            .subscription
+            .timeout_fetch(max, self.timeout)?
+            .map(|message| Message {
+                payload: message.data.clone(),
+                ack: message,
+            })
+            .collect())
+    }
+
+    fn commit(&mut self, acks: Vec<nats::Message>) -> anyhow::Result<()> {
+        for message in acks {
+            message.ack()?;
+        }
+        Ok(())
+    }
+}
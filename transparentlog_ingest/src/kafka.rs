@@ -0,0 +1,116 @@
+//! Kafka source
+use rdkafka::config::ClientConfig;
+use rdkafka::consumer::{BaseConsumer, CommitMode, Consumer};
+use rdkafka::message::Message as _;
+use rdkafka::{Offset, TopicPartitionList};
+use std::collections::HashMap;
+use std::time::Duration;
+use transparentlog_core::{Message, MessageSource};
+
+/// The position of a Kafka message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KafkaOffset {
+    pub topic: String,
+    pub partition: i32,
+    pub offset: i64,
+}
+
+/// A source consuming a Kafka topic, committing offsets manually
+pub struct KafkaSource {
+    consumer: BaseConsumer,
+    timeout: Duration,
+}
+
+impl KafkaSource {
+    /// A source consuming the topic in the consumer group, from its committed offsets,
+    /// or from the start of the topic for a new group
+    pub fn new(brokers: &str, group: &str, topic: &str) -> anyhow::Result<Self> {
+        let mut config = ClientConfig::new();
+        config
+            .set("bootstrap.servers", brokers)
+            .set("group.id", group)
+            .set("auto.offset.reset", "earliest");
+        Self::with_config(config, topic)
+    }
+
+    /// A source consuming the topic with the given client configuration, automatic commits being disabled
+    pub fn with_config(mut config: ClientConfig, topic: &str) -> anyhow::Result<Self> {
+        let consumer: BaseConsumer = config.set("enable.auto.commit", "false").create()?;
+        consumer.subscribe(&[topic])?;
+        Ok(Self {
+            consumer,
+            timeout: Duration::from_secs(1),
+        })
+    }
+
+    /// Set how long to wait for the first message of a batch, one second by default
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+}
+
+impl MessageSource for KafkaSource {
+    type Ack = KafkaOffset;
+
+    fn receive(&mut self, max: usize) -> anyhow::Result<Vec<Message<KafkaOffset>>> {
+        let mut messages = vec![];
+        let mut timeout = self.timeout;
+        while messages.len() < max {
+            let message = match self.consumer.poll(timeout) {
+                Some(message) => message?,
+                None => break,
+            };
+            messages.push(Message {
+                payload: message.payload().unwrap_or_default().to_vec(),
+                ack: KafkaOffset {
+                    topic: message.topic().to_owned(),
+                    partition: message.partition(),
+                    offset: message.offset(),
+                },
+            });
+            // only wait for the first message
+            timeout = Duration::ZERO;
+        }
+        Ok(messages)
+    }
+
+    fn commit(&mut self, acks: Vec<KafkaOffset>) -> anyhow::Result<()> {
+        let mut list = TopicPartitionList::new();
+        for ((topic, partition), offset) in next_offsets(acks) {
+            list.add_partition_offset(&topic, partition, Offset::Offset(offset))?;
+        }
+        self.consumer.commit(&list, CommitMode::Sync)?;
+        Ok(())
+    }
+}
+
+/// The offsets to commit: the one after the last message of each partition
+fn next_offsets(acks: Vec<KafkaOffset>) -> HashMap<(String, i32), i64> {
+    let mut next = HashMap::new();
+    for ack in acks {
+        let offset = next.entry((ack.topic, ack.partition)).or_insert(0);
+        *offset = ack.offset.saturating_add(1).max(*offset);
+    }
+    next
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::kafka::next_offsets;
+    use crate::*;
+
+    #[test]
+    fn kafka_next_offsets() {
+        let ack = |partition: i32, offset: i64| KafkaOffset {
+            topic: "audit".to_owned(),
+            partition,
+            offset,
+        };
+        let next = next_offsets(vec![ack(0, 12), ack(1, 3), ack(0, 14), ack(0, 13)]);
+        assert_eq!(2, next.len());
+        assert_eq!(Some(&15), next.get(&("audit".to_owned(), 0)));
+        assert_eq!(Some(&4), next.get(&("audit".to_owned(), 1)));
+    }
+}
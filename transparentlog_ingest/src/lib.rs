@@ -0,0 +1,17 @@
+//! # Message bus ingestion for Transparent Log
+//!
+//! `MessageSource` implementations for the `Ingestor` of the core crate, appending the records of a message bus to a log.
+//! With the `kafka` feature, a `KafkaSource` consumes a topic in a consumer group, committing the offsets only once
+//! the records are appended and synced; with the `nats` feature, a `NatsSource` pulls the messages of a subject from a durable
+//! JetStream consumer, acknowledging them only once the records are appended and synced.
+//! After an error, create a new source: it starts again from the last messages committed.
+
+#[cfg(feature = "kafka")]
+mod kafka;
+#[cfg(feature = "kafka")]
+pub use kafka::*;
+
+#[cfg(feature = "nats")]
+mod jetstream;
+#[cfg(feature = "nats")]
+pub use jetstream::*;
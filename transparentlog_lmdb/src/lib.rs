@@ -0,0 +1,286 @@
+//! # Transparent Log for LMDB
+//!
+//! Uses [LMDB](http://www.lmdb.tech/doc/) through [heed](https://docs.rs/heed) as the storage backend.
+//! Reads go straight to the memory-mapped file without copying or locking, so the log can be shared
+//! between many reader threads while one writer appends, each append being committed in a single transaction.
+//!
+
+use heed::types::ByteSlice;
+use heed::{Database, Env, EnvOpenOptions};
+use std::{fs::create_dir_all, ops::Bound, path::Path};
+use transparentlog_core::{KvLog, KvNamespace, KvStore, KvVisitor, KvWrite};
+
+/// The default maximum size of the memory map, 1 GiB
+pub const DEFAULT_MAP_SIZE: usize = 1 << 30;
+
+/// A key-value store over an LMDB environment, with a database per namespace
+pub struct LmdbStore {
+    env: Env,
+    databases: Vec<Database<ByteSlice, ByteSlice>>,
+}
+
+impl LmdbStore {
+    /// Open a new or existing environment in the given directory
+    pub fn open<P: AsRef<Path>>(dir: &P) -> anyhow::Result<Self> {
+        Self::open_with_map_size(dir, DEFAULT_MAP_SIZE)
+    }
+
+    /// Open a new or existing environment, with the given maximum size of the memory map, in bytes
+    pub fn open_with_map_size<P: AsRef<Path>>(dir: &P, map_size: usize) -> anyhow::Result<Self> {
+        create_dir_all(dir)?;
+        let env = EnvOpenOptions::new()
+            .map_size(map_size)
+            .max_dbs(KvNamespace::ALL.len() as u32)
+            .open(dir)?;
+        let databases = KvNamespace::ALL
+            .iter()
+            .map(|namespace| env.create_database(Some(namespace.name())))
+            .collect::<heed::Result<Vec<_>>>()?;
+        Ok(Self { env, databases })
+    }
+
+    /// The database of a namespace
+    fn database(&self, namespace: KvNamespace) -> &Database<ByteSlice, ByteSlice> {
+        &self.databases[namespace as usize]
+    }
+}
+
+impl KvStore for LmdbStore {
+    fn get(&self, namespace: KvNamespace, key: &[u8]) -> anyhow::Result<Option<Vec<u8>>> {
+        let rtxn = self.env.read_txn()?;
+        let obs = self.database(namespace).get(&rtxn, key)?;
+        Ok(obs.map(|bs| bs.to_vec()))
+    }
+
+    /// Read all the keys in a single read transaction
+    fn get_many(
+        &self,
+        namespace: KvNamespace,
+        keys: &[Vec<u8>],
+    ) -> anyhow::Result<Vec<Option<Vec<u8>>>> {
+        let rtxn = self.env.read_txn()?;
+        let database = self.database(namespace);
+        keys.iter()
+            .map(|k| Ok(database.get(&rtxn, k.as_slice())?.map(|bs| bs.to_vec())))
+            .collect()
+    }
+
+    fn put(&mut self, writes: Vec<KvWrite>) -> anyhow::Result<()> {
+        let mut wtxn = self.env.write_txn()?;
+        for (namespace, key, value) in writes.iter() {
+            self.database(*namespace)
+                .put(&mut wtxn, key.as_slice(), value.as_slice())?;
+        }
+        wtxn.commit()?;
+        Ok(())
+    }
+
+    fn scan(
+        &self,
+        namespace: KvNamespace,
+        start: &[u8],
+        end: &[u8],
+        f: &mut KvVisitor,
+    ) -> anyhow::Result<()> {
+        let rtxn = self.env.read_txn()?;
+        let range = (Bound::Included(start), Bound::Excluded(end));
+        for r in self.database(namespace).range(&rtxn, &range)? {
+            let (key, value) = r?;
+            f(key, value)?;
+        }
+        Ok(())
+    }
+
+    fn last_key(&self, namespace: KvNamespace) -> anyhow::Result<Option<Vec<u8>>> {
+        let rtxn = self.env.read_txn()?;
+        let last = self.database(namespace).last(&rtxn)?;
+        Ok(last.map(|(key, _)| key.to_vec()))
+    }
+}
+
+/// LMDB implementation of the Transparent log
+pub type LmdbLog<'a, T> = KvLog<'a, T, LmdbStore>;
+
+#[cfg(test)]
+mod tests {
+
+    use crate::{LmdbLog, LmdbStore};
+    use std::fs::{create_dir, remove_dir_all};
+    use std::path::{Path, PathBuf};
+    use std::thread;
+    use transparentlog_core::test_helpers::*;
+    use transparentlog_core::{InMemoryLog, InMemoryLogClientBuilder, KvLog, TransparentLog};
+
+    use serial_test::serial;
+
+    fn setup() -> anyhow::Result<PathBuf> {
+        let path = Path::new("./test_data_lmdb");
+        if path.exists() {
+            remove_dir_all(path)?;
+        }
+        create_dir(path)?;
+        Ok(path.join("log"))
+    }
+
+    #[test]
+    #[serial]
+    fn lmdb_empty() -> anyhow::Result<()> {
+        let path = setup()?;
+        let mut ml: LmdbLog<LogRecord> = KvLog::open(LmdbStore::open(&path)?)?;
+        empty(&mut ml)
+    }
+
+    #[test]
+    #[serial]
+    fn lmdb_out_of_range() -> anyhow::Result<()> {
+        let path = setup()?;
+        let mut ml: LmdbLog<LogRecord> = KvLog::open(LmdbStore::open(&path)?)?;
+        out_of_range(&mut ml)
+    }
+
+    #[test]
+    #[serial]
+    fn lmdb_add() -> anyhow::Result<()> {
+        let path = setup()?;
+        let mut ml: LmdbLog<LogRecord> = KvLog::open(LmdbStore::open(&path)?)?;
+        add(&mut ml)
+    }
+
+    #[test]
+    #[serial]
+    fn lmdb_lookup() -> anyhow::Result<()> {
+        let path = setup()?;
+        let mut ml: LmdbLog<LogRecord> = KvLog::open(LmdbStore::open(&path)?)?;
+        lookup(&mut ml)
+    }
+
+    #[test]
+    #[serial]
+    fn lmdb_range() -> anyhow::Result<()> {
+        let path = setup()?;
+        let mut ml: LmdbLog<LogRecord> = KvLog::open(LmdbStore::open(&path)?)?;
+        range(&mut ml)
+    }
+
+    #[test]
+    #[serial]
+    fn lmdb_iter() -> anyhow::Result<()> {
+        let path = setup()?;
+        let mut ml: LmdbLog<LogRecord> = KvLog::open(LmdbStore::open(&path)?)?;
+        iter(&mut ml)
+    }
+
+    #[test]
+    #[serial]
+    fn lmdb_tiles() -> anyhow::Result<()> {
+        let path = setup()?;
+        let mut ml: LmdbLog<LogRecord> = KvLog::open(LmdbStore::open(&path)?)?;
+        tiles(&mut ml)
+    }
+
+    #[test]
+    #[serial]
+    fn lmdb_batch_proofs() -> anyhow::Result<()> {
+        let path = setup()?;
+        let mut ml: LmdbLog<LogRecord> = KvLog::open(LmdbStore::open(&path)?)?;
+        batch_proofs(&mut ml)
+    }
+
+    #[test]
+    #[serial]
+    fn lmdb_bulk() -> anyhow::Result<()> {
+        let path = setup()?;
+        let mut ml: LmdbLog<LogRecord> = KvLog::open(LmdbStore::open(&path)?)?;
+        bulk(&mut ml)
+    }
+
+    #[test]
+    #[serial]
+    fn lmdb_timestamps() -> anyhow::Result<()> {
+        let path = setup()?;
+        let mut ml: LmdbLog<LogRecord> = KvLog::open(LmdbStore::open(&path)?)?;
+        timestamps(&mut ml)
+    }
+
+    #[test]
+    #[serial]
+    fn lmdb_export_import() -> anyhow::Result<()> {
+        let path = setup()?;
+        let mut ml = InMemoryLog::default();
+        let mut ml2: LmdbLog<LogRecord> = KvLog::open(LmdbStore::open(&path)?)?;
+        export_import(&mut ml, &mut ml2)
+    }
+
+    #[test]
+    #[serial]
+    fn lmdb_mirror() -> anyhow::Result<()> {
+        let path = setup()?;
+        let path2 = path.with_file_name("mirror");
+        let mut ml: LmdbLog<LogRecord> = KvLog::open(LmdbStore::open(&path)?)?;
+        let mut ml2: LmdbLog<LogRecord> = KvLog::open(LmdbStore::open(&path2)?)?;
+        mirror(&mut ml, &mut ml2)
+    }
+
+    #[test]
+    #[serial]
+    fn lmdb_properties() -> anyhow::Result<()> {
+        let path = setup()?;
+        properties(|| -> anyhow::Result<LmdbLog<LogRecord>> {
+            setup()?;
+            KvLog::open(LmdbStore::open(&path)?)
+        })
+    }
+
+    #[test]
+    #[serial]
+    fn lmdb_13() -> anyhow::Result<()> {
+        let path = setup()?;
+        let mut ml: LmdbLog<LogRecord> = KvLog::open(LmdbStore::open(&path)?)?;
+        test_13(&mut ml)?;
+        drop(ml);
+        let ml: LmdbLog<LogRecord> = KvLog::open(LmdbStore::open(&path)?)?;
+        check_13(&ml)
+    }
+
+    #[test]
+    #[serial]
+    fn lmdb_concurrent_readers() -> anyhow::Result<()> {
+        let path = setup()?;
+        let mut ml: LmdbLog<LogRecord> = KvLog::open(LmdbStore::open(&path)?)?;
+        test_13(&mut ml)?;
+        thread::scope(|s| {
+            let readers: Vec<_> = (0..4).map(|_| s.spawn(|| check_13(&ml))).collect();
+            readers
+                .into_iter()
+                .try_for_each(|r| r.join().expect("reader thread"))
+        })?;
+        assert_eq!(13, ml.size()?);
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    fn lmdb_memory_13() -> anyhow::Result<()> {
+        let path = setup()?;
+        let mut ml: LmdbLog<LogRecord> = KvLog::open(LmdbStore::open(&path)?)?;
+        let mut client = InMemoryLogClientBuilder::new(&ml)?.build();
+        client_13(&mut ml, &mut client)
+    }
+
+    #[test]
+    #[serial]
+    fn lmdb_memory_13_no_cache() -> anyhow::Result<()> {
+        let path = setup()?;
+        let mut ml: LmdbLog<LogRecord> = KvLog::open(LmdbStore::open(&path)?)?;
+        let mut client = InMemoryLogClientBuilder::new(&ml)?.no_cache().build();
+        client_13_nocache(&mut ml, &mut client)
+    }
+
+    #[test]
+    #[serial]
+    fn lmdb_verify_tree_prefix() -> anyhow::Result<()> {
+        let path = setup()?;
+        let mut ml: LmdbLog<LogRecord> = KvLog::open(LmdbStore::open(&path)?)?;
+        test_verify_tree_prefix(&mut ml)
+    }
+}
@@ -0,0 +1,663 @@
+//! # Transparent Log object store
+//!
+//! Publish a log to an object store as tiles of hashes, bundles of records and a signed checkpoint,
+//! read and prove records from such a published log, and store cold records of a `TieredLog`
+//! or the blobs of a `BlobLog` in an object store.
+//!
+//! Objects are laid out under a prefix:
+//! - `checkpoint`: the signed checkpoint of the published tree, written last
+//! - `tile/{level}/{index}`: the 256 hashes of a full tile, as concatenated hexadecimal hashes
+//! - `tile/{level}/{index}.p/{width}`: a partial tile of `width` hashes
+//! - `entries/{index}`: a bundle of the 256 records from `index * 256`, serialized with MessagePack
+//! - `entries/{index}.p/{width}`: a partial bundle of `width` records
+//! - `records/{id}`: the records of an `ObjectRecordStore`
+//! - `blobs/{hash}`: the blobs of an `ObjectBlobStore`
+use bytes::Bytes;
+use futures::TryStreamExt;
+use maybe_owned::MaybeOwned;
+use object_store::path::Path;
+use object_store::ObjectStore;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fmt::Display;
+use std::str::FromStr;
+use std::sync::Arc;
+use thiserror::Error;
+use tokio::runtime::{Builder, Runtime};
+use transparentlog_core::*;
+
+/// The number of records in a full bundle, the same as the number of hashes in a full tile
+pub const BUNDLE_WIDTH: u64 = 1 << TILE_HEIGHT;
+
+/// Errors publishing or reading a log
+#[derive(Error, Debug)]
+pub enum PublishError {
+    #[error("The published log has {0} records but the log only has {1}")]
+    Behind(u64, u64),
+    #[error("The published log has a different root hash at size {0}")]
+    Diverged(u64),
+    #[error("Invalid tile {0}/{1}")]
+    InvalidTile(LogHeight, u64),
+}
+
+/// The path of an object under a prefix
+fn object_path(prefix: &str, name: &str) -> Path {
+    Path::from(format!("{}/{}", prefix, name))
+}
+
+/// The name of a tile or bundle, full or partial
+fn part_name(kind: &str, level: Option<LogHeight>, index: u64, width: u64) -> String {
+    let level = level.map(|l| format!("{}/", l)).unwrap_or_default();
+    if width == BUNDLE_WIDTH {
+        format!("{}/{}{}", kind, level, index)
+    } else {
+        format!("{}/{}{}.p/{}", kind, level, index, width)
+    }
+}
+
+/// The number of hashes of the tile at the given tile level and index, in a tree of the given size
+fn tile_size(size: u64, level: LogHeight, index: u64) -> u64 {
+    let hashes = tree_sizes(size)
+        .get(level * TILE_HEIGHT)
+        .copied()
+        .unwrap_or_default();
+    hashes
+        .saturating_sub(index * BUNDLE_WIDTH)
+        .min(BUNDLE_WIDTH)
+}
+
+/// Read an object, None if it does not exist
+async fn read_object(store: &dyn ObjectStore, path: &Path) -> anyhow::Result<Option<Bytes>> {
+    match store.get(path).await {
+        Ok(r) => Ok(Some(r.bytes().await?)),
+        Err(object_store::Error::NotFound { .. }) => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Publishes a log to an object store
+#[derive(Debug)]
+pub struct Publisher {
+    store: Arc<dyn ObjectStore>,
+    prefix: String,
+}
+
+impl Publisher {
+    /// Create a publisher writing to the given prefix of the store
+    pub fn new(store: Arc<dyn ObjectStore>, prefix: &str) -> Self {
+        Self {
+            store,
+            prefix: prefix.to_owned(),
+        }
+    }
+
+    /// The checkpoint currently published, without verifying its signatures, if any
+    pub async fn published(&self) -> anyhow::Result<Option<Checkpoint<u64>>> {
+        match read_object(
+            self.store.as_ref(),
+            &object_path(&self.prefix, "checkpoint"),
+        )
+        .await?
+        {
+            Some(b) => {
+                let note = String::from_utf8(b.to_vec())?;
+                let (text, _) = parse_note(&note)?;
+                Ok(Some(Checkpoint::parse(text)?))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Publish the new tiles and bundles of the log since the last published checkpoint, then the signed checkpoint
+    /// Fails if the published log is not a prefix of the given log
+    pub async fn publish<'a, T, TL>(
+        &self,
+        log: &TL,
+        origin: &str,
+        signers: &[&dyn LogSigner],
+    ) -> anyhow::Result<Checkpoint<u64>>
+    where
+        T: Serialize + Deserialize<'a>,
+        TL: TransparentLog<'a, T, LogSize = u64>,
+    {
+        let tree = log.latest()?;
+        let old = match self.published().await? {
+            Some(c) => c.tree,
+            None => LogTree {
+                size: 0,
+                hash: String::new(),
+                origin: None,
+            },
+        };
+        if old.size > tree.size {
+            return Err(PublishError::Behind(old.size, tree.size).into());
+        }
+        if old.size > 0 {
+            let proofs = log.proofs(prefix_proof_positions(old.size, tree.size).into_iter())?;
+            if (old.size == tree.size && old.hash != tree.hash)
+                || (old.size < tree.size && !verify_tree(&old, &proofs))
+            {
+                return Err(PublishError::Diverged(old.size).into());
+            }
+        }
+
+        let mut level = 0;
+        while level * TILE_HEIGHT < tree_sizes(tree.size).len() {
+            // tiles that were full at the old size are already published
+            let mut index = tree_sizes(old.size)
+                .get(level * TILE_HEIGHT)
+                .map_or(0, |n| n / BUNDLE_WIDTH);
+            loop {
+                let width = tile_size(tree.size, level, index);
+                if width == 0 {
+                    break;
+                }
+                let tile = log.tile(level, index)?;
+                let b = tile.hashes.concat().into_bytes();
+                self.put(&part_name("tile", Some(level), index, width), b)
+                    .await?;
+                index += 1;
+            }
+            level += 1;
+        }
+
+        let mut index = old.size / BUNDLE_WIDTH;
+        while index * BUNDLE_WIDTH < tree.size {
+            let start = index * BUNDLE_WIDTH;
+            let end = tree.size.min(start + BUNDLE_WIDTH);
+            let records = log.get_range(start, end)?;
+            let records: Vec<&T> = records.iter().map(|r| &**r).collect();
+            self.put(
+                &part_name("entries", None, index, end - start),
+                rmp_serde::to_vec(&records)?,
+            )
+            .await?;
+            index += 1;
+        }
+
+        let checkpoint = Checkpoint::new(origin, tree);
+        let note = checkpoint.sign_async(signers).await?;
+        self.put("checkpoint", note.into_bytes()).await?;
+        Ok(checkpoint)
+    }
+
+    /// Write an object
+    async fn put(&self, name: &str, data: Vec<u8>) -> anyhow::Result<()> {
+        self.store
+            .put(&object_path(&self.prefix, name), Bytes::from(data))
+            .await?;
+        Ok(())
+    }
+}
+
+/// Reads a log published to an object store
+#[derive(Debug)]
+pub struct TileReader {
+    store: Arc<dyn ObjectStore>,
+    prefix: String,
+}
+
+impl TileReader {
+    /// Create a reader of the log published at the given prefix of the store
+    pub fn new(store: Arc<dyn ObjectStore>, prefix: &str) -> Self {
+        Self {
+            store,
+            prefix: prefix.to_owned(),
+        }
+    }
+
+    /// The published checkpoint note, without verifying its signatures
+    pub async fn note(&self) -> anyhow::Result<Option<String>> {
+        match self.read("checkpoint").await? {
+            Some(b) => Ok(Some(String::from_utf8(b.to_vec())?)),
+            None => Ok(None),
+        }
+    }
+
+    /// The published checkpoint, verifying its signatures
+    pub async fn checkpoint(
+        &self,
+        verifiers: &[&dyn LogVerifier],
+    ) -> anyhow::Result<Option<Checkpoint<u64>>> {
+        match self.note().await? {
+            Some(note) => Ok(Some(Checkpoint::open(&note, verifiers)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Fetch a tile of the tree of the given size
+    pub async fn tile(&self, size: u64, level: LogHeight, index: u64) -> anyhow::Result<Tile<u64>> {
+        let width = tile_size(size, level, index);
+        let b = self
+            .read(&part_name("tile", Some(level), index, width))
+            .await?
+            .ok_or(PublishError::InvalidTile(level, index))?;
+        if b.len() as u64 != width * 64 {
+            return Err(PublishError::InvalidTile(level, index).into());
+        }
+        let hashes = b
+            .chunks_exact(64)
+            .map(|c| String::from_utf8(c.to_vec()))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Tile {
+            level,
+            index,
+            hashes,
+        })
+    }
+
+    /// Get the hashes at the given positions of the tree of the given size, fetching the tiles they need
+    pub async fn proofs(
+        &self,
+        size: u64,
+        positions: &HashSet<LogTreePosition<u64>>,
+    ) -> anyhow::Result<HashMap<LogTreePosition<u64>, String>> {
+        let mut tiles: Tiles<u64> = HashMap::new();
+        for (level, index) in tile_positions(positions) {
+            tiles.insert((level, index), self.tile(size, level, index).await?);
+        }
+        Ok(proofs_from_tiles(positions, &tiles)?)
+    }
+
+    /// Check that the record at the given index with the given hash is in the tree
+    pub async fn check_record(
+        &self,
+        tree: &LogTree<u64>,
+        record: &Record<u64>,
+    ) -> anyhow::Result<bool> {
+        if record.id >= tree.size {
+            return Ok(false);
+        }
+        let proofs = self
+            .proofs(tree.size, &proof_positions(record.id, tree.size))
+            .await?;
+        Ok(verify(tree, record, &proofs))
+    }
+
+    /// Get the record at the given index in the tree of the given size
+    /// Records are not verified: check their hash with `check_record`
+    pub async fn get<T: DeserializeOwned>(&self, size: u64, id: u64) -> anyhow::Result<Option<T>> {
+        if id >= size {
+            return Ok(None);
+        }
+        let index = id / BUNDLE_WIDTH;
+        let width = size.min((index + 1) * BUNDLE_WIDTH) - index * BUNDLE_WIDTH;
+        match self.read(&part_name("entries", None, index, width)).await? {
+            Some(b) => {
+                let mut records: Vec<T> = rmp_serde::from_slice(&b)?;
+                let ix = (id - index * BUNDLE_WIDTH) as usize;
+                Ok((ix < records.len()).then(|| records.swap_remove(ix)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Read an object
+    async fn read(&self, name: &str) -> anyhow::Result<Option<Bytes>> {
+        read_object(self.store.as_ref(), &object_path(&self.prefix, name)).await
+    }
+}
+
+/// A proof source over a log published to an object store, to check records with `check_record`
+/// The latest tree is the one of the published checkpoint, which must have the expected origin and be signed by the verifiers
+/// The source calls block on its own runtime, so it must not be called from an asynchronous task
+pub struct TileSource {
+    reader: TileReader,
+    origin: String,
+    verifiers: Vec<Box<dyn LogVerifier + Send + Sync>>,
+    runtime: Runtime,
+}
+
+impl TileSource {
+    /// Create a source reading the log with the given origin, checking its checkpoint with the verifiers
+    pub fn new(
+        reader: TileReader,
+        origin: &str,
+        verifiers: Vec<Box<dyn LogVerifier + Send + Sync>>,
+    ) -> anyhow::Result<Self> {
+        Ok(Self {
+            reader,
+            origin: origin.to_owned(),
+            verifiers,
+            runtime: Builder::new_current_thread().enable_all().build()?,
+        })
+    }
+
+    /// The wrapped reader
+    pub fn reader(&self) -> &TileReader {
+        &self.reader
+    }
+
+    /// The published tree, empty if nothing was published yet
+    fn published(&self) -> anyhow::Result<LogTree<u64>> {
+        let verifiers: Vec<&dyn LogVerifier> = self
+            .verifiers
+            .iter()
+            .map(|v| v.as_ref() as &dyn LogVerifier)
+            .collect();
+        let tree = match self.runtime.block_on(self.reader.checkpoint(&verifiers))? {
+            Some(checkpoint) if checkpoint.origin != self.origin => {
+                return Err(OriginError::Mismatch {
+                    expected: Some(self.origin.clone()),
+                    found: Some(checkpoint.origin),
+                }
+                .into())
+            }
+            Some(checkpoint) => checkpoint.tree,
+            None => LogTree {
+                size: 0,
+                hash: String::new(),
+                origin: None,
+            },
+        };
+        Ok(LogTree {
+            origin: Some(self.origin.clone()),
+            ..tree
+        })
+    }
+}
+
+/// Proofs and entries are read from the tiles and bundles of the latest published tree
+impl<'a, T: Serialize + DeserializeOwned> ProofSource<'a, T, Remote> for TileSource {
+    type LogSize = u64;
+
+    fn log_origin(&self) -> Option<String> {
+        Some(self.origin.clone())
+    }
+
+    fn fetch_latest(&self) -> anyhow::Result<LogTree<u64>> {
+        self.published()
+    }
+
+    fn fetch_proofs<I>(&self, positions: I) -> anyhow::Result<HashMap<LogTreePosition<u64>, String>>
+    where
+        I: Iterator<Item = LogTreePosition<u64>>,
+    {
+        let positions: HashSet<_> = positions.collect();
+        if positions.is_empty() {
+            return Ok(HashMap::new());
+        }
+        let size = self.published()?.size;
+        self.runtime.block_on(self.reader.proofs(size, &positions))
+    }
+
+    fn fetch_entry(&self, index: u64) -> anyhow::Result<Option<MaybeOwned<'_, T>>> {
+        let size = self.published()?.size;
+        Ok(self
+            .runtime
+            .block_on(self.reader.get(size, index))?
+            .map(MaybeOwned::Owned))
+    }
+
+    /// The records are hashed as MessagePack, the default leaf hash
+    fn entry_hasher(&self) -> LeafHasher<T> {
+        hash::<T>
+    }
+}
+
+/// A record store in an object store, for the cold tier of a `TieredLog`
+/// The store calls block on its own runtime, so it must not be called from an asynchronous task
+#[derive(Debug)]
+pub struct ObjectRecordStore {
+    store: Arc<dyn ObjectStore>,
+    prefix: String,
+    runtime: Runtime,
+}
+
+impl ObjectRecordStore {
+    /// Create a record store writing to the given prefix of the store
+    pub fn new(store: Arc<dyn ObjectStore>, prefix: &str) -> anyhow::Result<Self> {
+        Ok(Self {
+            store,
+            prefix: prefix.to_owned(),
+            runtime: Builder::new_current_thread().enable_all().build()?,
+        })
+    }
+
+    /// The path of the record
+    fn path<LogSize: Display>(&self, id: LogSize) -> Path {
+        object_path(&self.prefix, &format!("records/{}", id))
+    }
+}
+
+impl<LogSize: Display + FromStr + Ord> RecordStore<LogSize> for ObjectRecordStore {
+    fn put(&mut self, id: LogSize, data: Vec<u8>) -> anyhow::Result<()> {
+        self.runtime
+            .block_on(self.store.put(&self.path(id), Bytes::from(data)))?;
+        Ok(())
+    }
+
+    fn get(&self, id: LogSize) -> anyhow::Result<Option<Vec<u8>>> {
+        Ok(self
+            .runtime
+            .block_on(read_object(self.store.as_ref(), &self.path(id)))?
+            .map(|b| b.to_vec()))
+    }
+
+    fn remove(&mut self, id: LogSize) -> anyhow::Result<()> {
+        match self.runtime.block_on(self.store.delete(&self.path(id))) {
+            Ok(()) | Err(object_store::Error::NotFound { .. }) => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Lists all the records
+    fn first(&self) -> anyhow::Result<Option<LogSize>> {
+        let prefix = object_path(&self.prefix, "records");
+        self.runtime.block_on(async {
+            let metas: Vec<_> = self.store.list(Some(&prefix)).await?.try_collect().await?;
+            Ok(metas
+                .iter()
+                .filter_map(|m| m.location.filename().and_then(|f| f.parse().ok()))
+                .min())
+        })
+    }
+}
+
+/// A blob store in an object store, for the records of a `BlobLog`
+/// The store calls block on its own runtime, so it must not be called from an asynchronous task
+#[derive(Debug)]
+pub struct ObjectBlobStore {
+    store: Arc<dyn ObjectStore>,
+    prefix: String,
+    runtime: Runtime,
+}
+
+impl ObjectBlobStore {
+    /// Create a blob store writing to the given prefix of the store
+    pub fn new(store: Arc<dyn ObjectStore>, prefix: &str) -> anyhow::Result<Self> {
+        Ok(Self {
+            store,
+            prefix: prefix.to_owned(),
+            runtime: Builder::new_current_thread().enable_all().build()?,
+        })
+    }
+
+    /// The path of the blob
+    fn path(&self, hash: &str) -> Path {
+        object_path(&self.prefix, &format!("blobs/{}", hash))
+    }
+}
+
+impl BlobStore for ObjectBlobStore {
+    fn put(&mut self, hash: &str, data: Vec<u8>) -> anyhow::Result<()> {
+        self.runtime
+            .block_on(self.store.put(&self.path(hash), Bytes::from(data)))?;
+        Ok(())
+    }
+
+    fn get(&self, hash: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        Ok(self
+            .runtime
+            .block_on(read_object(self.store.as_ref(), &self.path(hash)))?
+            .map(|b| b.to_vec()))
+    }
+
+    /// Only reads the object metadata
+    fn contains(&self, hash: &str) -> anyhow::Result<bool> {
+        match self.runtime.block_on(self.store.head(&self.path(hash))) {
+            Ok(_) => Ok(true),
+            Err(object_store::Error::NotFound { .. }) => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::*;
+    use object_store::memory::InMemory;
+    use std::fs::{create_dir, remove_dir_all};
+    use std::path::{Path, PathBuf};
+    use transparentlog_core::test_helpers::*;
+
+    fn setup() -> anyhow::Result<PathBuf> {
+        let path = Path::new("./test_data");
+        if path.exists() {
+            remove_dir_all(path)?;
+        }
+        create_dir(path)?;
+        Ok(PathBuf::from(path))
+    }
+
+    #[tokio::test]
+    async fn object_store_publish() -> anyhow::Result<()> {
+        let store = Arc::new(InMemory::new());
+        let signer = Ed25519Signer::new("example.com/log", &[3_u8; 32]);
+        let verifier = signer.verifier();
+        let publisher = Publisher::new(store.clone(), "log");
+        let reader = TileReader::new(store.clone(), "log");
+        assert!(reader.checkpoint(&[&verifier]).await?.is_none());
+
+        let path = setup()?;
+        let mut ml: FileLog<LogRecord> = FileLog::open(&path)?;
+        append_multiple(&mut ml, 300)?;
+        publisher
+            .publish(&ml, "example.com/log", &[&signer])
+            .await?;
+        append_multiple(&mut ml, 400)?;
+        let published = publisher
+            .publish(&ml, "example.com/log", &[&signer])
+            .await?;
+        assert_eq!(700, published.tree.size);
+
+        let checkpoint = reader.checkpoint(&[&verifier]).await?.unwrap();
+        assert_eq!(published, checkpoint);
+        for id in [0, 255, 256, 299, 511, 512, 699] {
+            let record: LogRecord = reader.get(checkpoint.tree.size, id).await?.unwrap();
+            assert_eq!(*ml.get(id)?.unwrap(), record);
+            let r = Record {
+                id,
+                hash: hash(&record)?,
+                meta: None,
+            };
+            assert!(reader.check_record(&checkpoint.tree, &r).await?);
+            let forged = Record {
+                hash: hash(&LogRecord::new("forged"))?,
+                ..r
+            };
+            assert!(!reader.check_record(&checkpoint.tree, &forged).await?);
+        }
+        assert!(reader
+            .get::<LogRecord>(checkpoint.tree.size, 700)
+            .await?
+            .is_none());
+
+        let path2 = path.join("other");
+        create_dir(&path2)?;
+        let mut other: FileLog<LogRecord> = FileLog::open(&path2)?;
+        append_multiple(&mut other, 10)?;
+        assert!(publisher
+            .publish(&other, "example.com/log", &[&signer])
+            .await
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn object_store_source() -> anyhow::Result<()> {
+        let store = Arc::new(InMemory::new());
+        let signer = Ed25519Signer::new("example.com/log", &[3_u8; 32]);
+        let publisher = Publisher::new(store.clone(), "log");
+        let source = TileSource::new(
+            TileReader::new(store.clone(), "log"),
+            "example.com/log",
+            vec![Box::new(signer.verifier())],
+        )?;
+        let mut client = InMemoryLogClientBuilder::new::<LogRecord, _, _>(&source)?.build();
+        assert_eq!(0, client.latest().size);
+
+        let runtime = Builder::new_current_thread().enable_all().build()?;
+        let mut ml: KvLog<LogRecord, _> = KvLog::open(MemoryKvStore::default())?;
+        append_multiple(&mut ml, 300)?;
+        runtime.block_on(publisher.publish(&ml, "example.com/log", &[&signer]))?;
+        let record = |id: u64| -> anyhow::Result<Record<u64>> {
+            let entry: MaybeOwned<LogRecord> = source.fetch_entry(id)?.unwrap();
+            assert_eq!(*ml.get(id)?.unwrap(), *entry);
+            Ok(Record {
+                id,
+                hash: hash(entry.as_ref())?,
+                meta: None,
+            })
+        };
+        for id in [0, 255, 256, 299] {
+            assert!(check_record::<LogRecord, _, _, _>(
+                &mut client,
+                &source,
+                &record(id)?
+            )?);
+        }
+        assert_eq!(ml.latest()?.hash, client.latest().hash);
+        let forged = Record {
+            hash: hash(&LogRecord::new("forged"))?,
+            ..record(12)?
+        };
+        assert!(!check_record::<LogRecord, _, _, _>(
+            &mut client,
+            &source,
+            &forged
+        )?);
+        let verified: Option<MaybeOwned<LogRecord>> = get_verified(&mut client, &source, 42)?;
+        assert_eq!(ml.get(42)?.as_deref(), verified.as_deref());
+        assert!(ProofSource::<LogRecord, Remote>::fetch_entry(&source, 300)?.is_none());
+
+        let other = TileSource::new(
+            TileReader::new(store, "log"),
+            "example.com/other",
+            vec![Box::new(signer.verifier())],
+        )?;
+        assert!(ProofSource::<LogRecord, Remote>::fetch_latest(&other).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn object_store_tiered() -> anyhow::Result<()> {
+        let store = Arc::new(InMemory::new());
+        let mut ml = TieredLog::new(
+            InMemoryLog::<()>::default(),
+            MemoryRecordStore::default(),
+            ObjectRecordStore::new(store, "cold")?,
+        );
+        ml.keep_hot(5);
+        test_13(&mut ml)?;
+        assert_eq!(Some(0), ml.cold().first()?);
+        check_13(&ml)
+    }
+
+    #[test]
+    fn object_store_blobs() -> anyhow::Result<()> {
+        let store = Arc::new(InMemory::new());
+        let mut ml = BlobLog::new(
+            InMemoryLog::<BlobRef>::default(),
+            ObjectBlobStore::new(store, "blobs")?,
+        );
+        test_13(&mut ml)?;
+        let h = hash(&LogRecord::new("rec9"))?;
+        assert!(ml.blobs().contains(&h)?);
+        assert!(!ml.blobs().contains(&hash(&LogRecord::new("other"))?)?);
+        assert_eq!(Some(&LogRecord::new("rec9")), ml.get(9)?.as_deref());
+        Ok(())
+    }
+}
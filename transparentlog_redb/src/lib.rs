@@ -0,0 +1,282 @@
+//! # Transparent Log for redb
+//!
+//! Uses [redb](https://www.redb.org/), a pure Rust embedded database, as the storage backend.
+//! Each namespace of the log is a table, and each append and bulk append is committed
+//! in a single write transaction, so a crash never leaves a record without its hashes.
+//!
+
+use redb::{Database, ReadableTable, TableDefinition};
+use std::path::Path;
+use transparentlog_core::{KvLog, KvNamespace, KvStore, KvVisitor, KvWrite};
+
+/// The table of a namespace
+fn table(namespace: KvNamespace) -> TableDefinition<'static, &'static [u8], &'static [u8]> {
+    TableDefinition::new(namespace.name())
+}
+
+/// A key-value store over a redb database
+pub struct RedbStore {
+    db: Database,
+}
+
+impl RedbStore {
+    /// Open a new or existing database file
+    pub fn open<P: AsRef<Path>>(path: &P) -> anyhow::Result<Self> {
+        let db = Database::create(path)?;
+        // create all the tables, so read transactions can always open them
+        let txn = db.begin_write()?;
+        for namespace in KvNamespace::ALL {
+            txn.open_table(table(namespace))?;
+        }
+        txn.commit()?;
+        Ok(Self { db })
+    }
+}
+
+impl KvStore for RedbStore {
+    fn get(&self, namespace: KvNamespace, key: &[u8]) -> anyhow::Result<Option<Vec<u8>>> {
+        let txn = self.db.begin_read()?;
+        let table = txn.open_table(table(namespace))?;
+        let obs = table.get(key)?;
+        Ok(obs.map(|v| v.value().to_vec()))
+    }
+
+    /// Read all the keys in a single read transaction
+    fn get_many(
+        &self,
+        namespace: KvNamespace,
+        keys: &[Vec<u8>],
+    ) -> anyhow::Result<Vec<Option<Vec<u8>>>> {
+        let txn = self.db.begin_read()?;
+        let table = txn.open_table(table(namespace))?;
+        keys.iter()
+            .map(|k| Ok(table.get(k.as_slice())?.map(|v| v.value().to_vec())))
+            .collect()
+    }
+
+    fn put(&mut self, writes: Vec<KvWrite>) -> anyhow::Result<()> {
+        let txn = self.db.begin_write()?;
+        for namespace in KvNamespace::ALL {
+            let mut table = txn.open_table(table(namespace))?;
+            for (_, key, value) in writes.iter().filter(|w| w.0 == namespace) {
+                table.insert(key.as_slice(), value.as_slice())?;
+            }
+        }
+        txn.commit()?;
+        Ok(())
+    }
+
+    fn scan(
+        &self,
+        namespace: KvNamespace,
+        start: &[u8],
+        end: &[u8],
+        f: &mut KvVisitor,
+    ) -> anyhow::Result<()> {
+        let txn = self.db.begin_read()?;
+        let table = txn.open_table(table(namespace))?;
+        for r in table.range(start..end)? {
+            let (key, value) = r?;
+            f(key.value(), value.value())?;
+        }
+        Ok(())
+    }
+
+    fn last_key(&self, namespace: KvNamespace) -> anyhow::Result<Option<Vec<u8>>> {
+        let txn = self.db.begin_read()?;
+        let table = txn.open_table(table(namespace))?;
+        let last = table.iter()?.next_back().transpose()?;
+        Ok(last.map(|(key, _)| key.value().to_vec()))
+    }
+}
+
+/// redb implementation of the Transparent log
+pub type RedbLog<'a, T> = KvLog<'a, T, RedbStore>;
+
+#[cfg(test)]
+mod tests {
+
+    use crate::{RedbLog, RedbStore};
+    use std::fs::{create_dir, remove_dir_all};
+    use std::path::{Path, PathBuf};
+    use transparentlog_core::test_helpers::*;
+    use transparentlog_core::{InMemoryLog, InMemoryLogClientBuilder, KvLog, TransparentLog};
+
+    use serial_test::serial;
+
+    fn setup() -> anyhow::Result<PathBuf> {
+        let path = Path::new("./test_data_redb");
+        if path.exists() {
+            remove_dir_all(path)?;
+        }
+        create_dir(path)?;
+        Ok(path.join("log.redb"))
+    }
+
+    #[test]
+    #[serial]
+    fn redb_empty() -> anyhow::Result<()> {
+        let path = setup()?;
+        let mut ml: RedbLog<LogRecord> = KvLog::open(RedbStore::open(&path)?)?;
+        empty(&mut ml)
+    }
+
+    #[test]
+    #[serial]
+    fn redb_out_of_range() -> anyhow::Result<()> {
+        let path = setup()?;
+        let mut ml: RedbLog<LogRecord> = KvLog::open(RedbStore::open(&path)?)?;
+        out_of_range(&mut ml)
+    }
+
+    #[test]
+    #[serial]
+    fn redb_add() -> anyhow::Result<()> {
+        let path = setup()?;
+        let mut ml: RedbLog<LogRecord> = KvLog::open(RedbStore::open(&path)?)?;
+        add(&mut ml)
+    }
+
+    #[test]
+    #[serial]
+    fn redb_lookup() -> anyhow::Result<()> {
+        let path = setup()?;
+        let mut ml: RedbLog<LogRecord> = KvLog::open(RedbStore::open(&path)?)?;
+        lookup(&mut ml)
+    }
+
+    #[test]
+    #[serial]
+    fn redb_range() -> anyhow::Result<()> {
+        let path = setup()?;
+        let mut ml: RedbLog<LogRecord> = KvLog::open(RedbStore::open(&path)?)?;
+        range(&mut ml)
+    }
+
+    #[test]
+    #[serial]
+    fn redb_iter() -> anyhow::Result<()> {
+        let path = setup()?;
+        let mut ml: RedbLog<LogRecord> = KvLog::open(RedbStore::open(&path)?)?;
+        iter(&mut ml)
+    }
+
+    #[test]
+    #[serial]
+    fn redb_tiles() -> anyhow::Result<()> {
+        let path = setup()?;
+        let mut ml: RedbLog<LogRecord> = KvLog::open(RedbStore::open(&path)?)?;
+        tiles(&mut ml)
+    }
+
+    #[test]
+    #[serial]
+    fn redb_batch_proofs() -> anyhow::Result<()> {
+        let path = setup()?;
+        let mut ml: RedbLog<LogRecord> = KvLog::open(RedbStore::open(&path)?)?;
+        batch_proofs(&mut ml)
+    }
+
+    #[test]
+    #[serial]
+    fn redb_bulk() -> anyhow::Result<()> {
+        let path = setup()?;
+        let mut ml: RedbLog<LogRecord> = KvLog::open(RedbStore::open(&path)?)?;
+        bulk(&mut ml)?;
+        let size = ml.size()?;
+        let latest = ml.latest()?;
+        drop(ml);
+        let ml: RedbLog<LogRecord> = KvLog::open(RedbStore::open(&path)?)?;
+        assert_eq!(size, ml.size()?);
+        assert_eq!(latest, ml.latest()?);
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    fn redb_timestamps() -> anyhow::Result<()> {
+        let path = setup()?;
+        let mut ml: RedbLog<LogRecord> = KvLog::open(RedbStore::open(&path)?)?;
+        timestamps(&mut ml)
+    }
+
+    #[test]
+    #[serial]
+    fn redb_export_import() -> anyhow::Result<()> {
+        let path = setup()?;
+        let mut ml = InMemoryLog::default();
+        let mut ml2: RedbLog<LogRecord> = KvLog::open(RedbStore::open(&path)?)?;
+        export_import(&mut ml, &mut ml2)
+    }
+
+    #[test]
+    #[serial]
+    fn redb_mirror() -> anyhow::Result<()> {
+        let path = setup()?;
+        let path2 = path.with_file_name("mirror.redb");
+        let mut ml: RedbLog<LogRecord> = KvLog::open(RedbStore::open(&path)?)?;
+        let mut ml2: RedbLog<LogRecord> = KvLog::open(RedbStore::open(&path2)?)?;
+        mirror(&mut ml, &mut ml2)
+    }
+
+    #[test]
+    #[serial]
+    fn redb_properties() -> anyhow::Result<()> {
+        let path = setup()?;
+        properties(|| -> anyhow::Result<RedbLog<LogRecord>> {
+            setup()?;
+            KvLog::open(RedbStore::open(&path)?)
+        })
+    }
+
+    #[test]
+    #[serial]
+    fn redb_13() -> anyhow::Result<()> {
+        let path = setup()?;
+        let mut ml: RedbLog<LogRecord> = KvLog::open(RedbStore::open(&path)?)?;
+        test_13(&mut ml)?;
+        drop(ml);
+        let ml: RedbLog<LogRecord> = KvLog::open(RedbStore::open(&path)?)?;
+        check_13(&ml)
+    }
+
+    #[test]
+    #[serial]
+    fn redb_seal() -> anyhow::Result<()> {
+        let path = setup()?;
+        let mut ml: RedbLog<LogRecord> = KvLog::open(RedbStore::open(&path)?)?;
+        seal(&mut ml)?;
+        let tree = ml.sealed()?;
+        drop(ml);
+        let mut ml: RedbLog<LogRecord> = KvLog::open(RedbStore::open(&path)?)?;
+        assert_eq!(tree, ml.sealed()?);
+        assert_sealed(ml.append(LogRecord::new("rec13")));
+        check_13(&ml)
+    }
+
+    #[test]
+    #[serial]
+    fn redb_memory_13() -> anyhow::Result<()> {
+        let path = setup()?;
+        let mut ml: RedbLog<LogRecord> = KvLog::open(RedbStore::open(&path)?)?;
+        let mut client = InMemoryLogClientBuilder::new(&ml)?.build();
+        client_13(&mut ml, &mut client)
+    }
+
+    #[test]
+    #[serial]
+    fn redb_memory_13_no_cache() -> anyhow::Result<()> {
+        let path = setup()?;
+        let mut ml: RedbLog<LogRecord> = KvLog::open(RedbStore::open(&path)?)?;
+        let mut client = InMemoryLogClientBuilder::new(&ml)?.no_cache().build();
+        client_13_nocache(&mut ml, &mut client)
+    }
+
+    #[test]
+    #[serial]
+    fn redb_verify_tree_prefix() -> anyhow::Result<()> {
+        let path = setup()?;
+        let mut ml: RedbLog<LogRecord> = KvLog::open(RedbStore::open(&path)?)?;
+        test_verify_tree_prefix(&mut ml)
+    }
+}
@@ -0,0 +1,76 @@
+use rocksdb::{BlockBasedOptions, Cache, DBCompressionType, Options, WriteOptions};
+
+/// Tuning of the RocksDB storage, applied when opening the log
+#[derive(Debug, Clone, Default)]
+pub struct RocksConfig {
+    block_cache_size: Option<usize>,
+    compression: Option<DBCompressionType>,
+    bloom_filter_bits: Option<f64>,
+    sync_writes: bool,
+    disable_wal: bool,
+}
+
+impl RocksConfig {
+    /// Share a LRU block cache of the given size in bytes between all column families
+    pub fn block_cache_size(&mut self, size: usize) -> &mut Self {
+        self.block_cache_size = Some(size);
+        self
+    }
+
+    /// Compress all column families with the given algorithm
+    pub fn compression(&mut self, compression: DBCompressionType) -> &mut Self {
+        self.compression = Some(compression);
+        self
+    }
+
+    /// Add bloom filters with the given bits per key on the hash column family, to speed up point lookups
+    pub fn bloom_filter(&mut self, bits_per_key: f64) -> &mut Self {
+        self.bloom_filter_bits = Some(bits_per_key);
+        self
+    }
+
+    /// Sync the WAL on every write
+    pub fn sync_writes(&mut self, sync: bool) -> &mut Self {
+        self.sync_writes = sync;
+        self
+    }
+
+    /// Do not write to the WAL at all: faster, but recent writes are lost on a crash
+    pub fn disable_wal(&mut self, disable: bool) -> &mut Self {
+        self.disable_wal = disable;
+        self
+    }
+
+    /// Create the shared block cache, if any
+    pub(crate) fn cache(&self) -> anyhow::Result<Option<Cache>> {
+        Ok(self
+            .block_cache_size
+            .map(Cache::new_lru_cache)
+            .transpose()?)
+    }
+
+    /// Options of a column family, with a bloom filter if requested and wanted for this family
+    pub(crate) fn family_options(&self, cache: Option<&Cache>, bloom: bool) -> Options {
+        let mut opts = Options::default();
+        if let Some(compression) = self.compression {
+            opts.set_compression_type(compression);
+        }
+        let mut block_opts = BlockBasedOptions::default();
+        if let Some(cache) = cache {
+            block_opts.set_block_cache(cache);
+        }
+        if let (true, Some(bits)) = (bloom, self.bloom_filter_bits) {
+            block_opts.set_bloom_filter(bits, false);
+        }
+        opts.set_block_based_table_factory(&block_opts);
+        opts
+    }
+
+    /// Options for all writes
+    pub(crate) fn write_options(&self) -> WriteOptions {
+        let mut opts = WriteOptions::default();
+        opts.set_sync(self.sync_writes);
+        opts.disable_wal(self.disable_wal);
+        opts
+    }
+}
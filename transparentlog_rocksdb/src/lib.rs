@@ -1,150 +1,53 @@
 //! # Transparent Log for RocksDB
 //!
-//! Uses [RocksDB](http://rocksdb.org/) as the storage backend
+//! Uses [RocksDB](http://rocksdb.org/) as the storage backend, through the generic key-value log of the core crate:
+//! each namespace of the log is a column family, and each append is written in one atomic batch.
+//!
+//! Logs are opened with `KvLog::open(RocksDBStore::open(&path)?)` and indexed by `u64`, where earlier versions had
+//! `RocksDBLog::open(&path)` and `u128` indices. `RocksDBStore::open` migrates the databases of earlier versions.
 //!
 
-use byteorder::{BigEndian, ByteOrder, WriteBytesExt};
-use maybe_owned::MaybeOwned;
-use rocksdb::{ColumnFamilyDescriptor, Options, DB};
-use serde::{de::DeserializeOwned, Deserialize, Serialize};
-use std::{marker::PhantomData, path::Path};
 use thiserror::Error;
-use transparentlog_core::{tree_sizes, LogError, TransparentLog};
+use transparentlog_core::{KvLog, MessagePack};
+
+mod config;
+pub use config::*;
+
+mod store;
+pub use store::*;
 
-const FAMILY_DATA: &str = "data";
-const FAMILY_HASH: &str = "hash";
+mod shared;
+pub use shared::*;
 
 #[derive(Debug, Clone, Error)]
 pub enum RocksDBLogError {
     #[error("Unknown Column Family {0}")]
     UnknownColumnFamily(&'static str),
+    #[error("Database already holds a single log, it cannot be shared")]
+    NotShared,
+    #[error("Log registry poisoned by a panic")]
+    Poisoned,
+    #[error("Index {0} of a legacy database does not fit in 64 bits")]
+    LegacyIndex(u128),
 }
 
-/// RocksDB implementation of the Transparent log
-pub struct RocksDBLog<'a, T: Serialize + Deserialize<'a>> {
-    db: DB,
-    size: u128,
-    _marker: PhantomData<&'a T>,
-}
-
-impl<'a, T: Serialize + Deserialize<'a>> RocksDBLog<'a, T> {
-    /// Open a new or existing database
-    pub fn open<P: AsRef<Path>>(path: &'a P) -> anyhow::Result<Self> {
-        let data_cf = ColumnFamilyDescriptor::new(FAMILY_DATA, Options::default());
-        let hash_cf = ColumnFamilyDescriptor::new(FAMILY_HASH, Options::default());
-
-        let mut db_opts = Options::default();
-        db_opts.create_missing_column_families(true);
-        db_opts.create_if_missing(true);
-
-        let db = DB::open_cf_descriptors(&db_opts, path, vec![data_cf, hash_cf])?;
-        let mut size = 0;
-        {
-            let mut it = db.iterator_cf(
-                db.cf_handle(FAMILY_DATA)
-                    .ok_or(RocksDBLogError::UnknownColumnFamily(FAMILY_DATA))?,
-                rocksdb::IteratorMode::End,
-            );
-            if let Some((key, _)) = it.next() {
-                size = BigEndian::read_u128(key.as_ref()) + 1_u128;
-            }
-        }
-        Ok(Self {
-            db,
-            size,
-            _marker: PhantomData,
-        })
-    }
-}
-
-/// Implement TransparentLog API
-impl<'a, T: Serialize + DeserializeOwned> TransparentLog<'a, T> for RocksDBLog<'a, T> {
-    type LogSize = u128;
-
-    fn size(&self) -> anyhow::Result<Self::LogSize> {
-        Ok(self.size)
-    }
-
-    fn add(&mut self, record: T) -> anyhow::Result<Self::LogSize> {
-        let id = self.size;
-        self.db.put_cf(
-            self.db
-                .cf_handle(FAMILY_DATA)
-                .ok_or(RocksDBLogError::UnknownColumnFamily(FAMILY_DATA))?,
-            id.to_be_bytes(),
-            rmp_serde::to_vec(&record)?,
-        )?;
-        self.size += 1;
-        Ok(id)
-    }
-
-    fn add_hash(
-        &mut self,
-        level: transparentlog_core::LogHeight,
-        hash: String,
-    ) -> anyhow::Result<Self::LogSize> {
-        let sizes = tree_sizes(self.size);
-        let index = sizes
-            .get(level)
-            .ok_or(LogError::InvalidHeight::<Self::LogSize>(level))?
-            - 1;
-        let mut v = vec![];
-        v.write_u64::<BigEndian>(level as u64)?;
-        v.write_u128::<BigEndian>(index)?;
-        self.db.put_cf(
-            self.db
-                .cf_handle(FAMILY_HASH)
-                .ok_or(RocksDBLogError::UnknownColumnFamily(FAMILY_HASH))?,
-            v,
-            hash,
-        )?;
-        Ok(index)
-    }
-
-    fn get_hash(
-        &self,
-        level: transparentlog_core::LogHeight,
-        index: Self::LogSize,
-    ) -> anyhow::Result<MaybeOwned<String>> {
-        let mut v = vec![];
-        v.write_u64::<BigEndian>(level as u64)?;
-        v.write_u128::<BigEndian>(index)?;
-        let bs = self
-            .db
-            .get_cf(
-                self.db
-                    .cf_handle(FAMILY_HASH)
-                    .ok_or(RocksDBLogError::UnknownColumnFamily(FAMILY_HASH))?,
-                v,
-            )?
-            .ok_or(LogError::InvalidIndex(level, index))?;
-        Ok(String::from_utf8(bs)?.into())
-    }
-
-    fn get(&self, index: Self::LogSize) -> anyhow::Result<Option<MaybeOwned<T>>> {
-        let obs = self.db.get_cf(
-            self.db
-                .cf_handle(FAMILY_DATA)
-                .ok_or(RocksDBLogError::UnknownColumnFamily(FAMILY_DATA))?,
-            index.to_be_bytes(),
-        )?;
-        if let Some(bs) = obs {
-            let r = rmp_serde::from_read_ref(&bs)?;
-            Ok(Some(MaybeOwned::Owned(r)))
-        } else {
-            Ok(None)
-        }
-    }
-}
+/// RocksDB implementation of the Transparent log, serializing its records with the codec C
+/// Several logs can share a database, each log prefixing all its keys
+pub type RocksDBLog<'a, T, C = MessagePack> = KvLog<'a, T, RocksDBStore, C>;
 
 #[cfg(test)]
 mod tests {
 
-    use crate::RocksDBLog;
+    use crate::{RocksConfig, RocksDBLog, RocksDBLogError, RocksDBStore, SharedRocksDB};
+    use rocksdb::{DBCompressionType, Options, WriteOptions, DB};
     use std::fs::{create_dir, remove_dir_all};
     use std::path::{Path, PathBuf};
     use transparentlog_core::test_helpers::*;
-    use transparentlog_core::InMemoryLogClientBuilder;
+    use transparentlog_core::{
+        hash, tree_sizes, Codec, CodecError, CompressionError, InMemoryLog,
+        InMemoryLogClientBuilder, Json, KvLog, KvLogError, KvNamespace, KvStore, MessagePack,
+        TransparentLog,
+    };
 
     use serial_test::serial;
 
@@ -152,9 +55,9 @@ mod tests {
         let path = Path::new("./test_data_rocks");
         println!("{} {}", path.display(), path.exists());
         if path.exists() {
-            remove_dir_all(&path)?;
+            remove_dir_all(path)?;
         }
-        create_dir(&path)?;
+        create_dir(path)?;
         Ok(PathBuf::from(path))
     }
 
@@ -162,34 +65,395 @@ mod tests {
     #[serial]
     fn rocksdb_empty() -> anyhow::Result<()> {
         let path = setup()?;
-        let mut ml: RocksDBLog<LogRecord> = RocksDBLog::open(&path)?;
+        let mut ml: RocksDBLog<LogRecord> = KvLog::open(RocksDBStore::open(&path)?)?;
         empty(&mut ml)
     }
 
+    #[test]
+    #[serial]
+    fn rocksdb_out_of_range() -> anyhow::Result<()> {
+        let path = setup()?;
+        let mut ml: RocksDBLog<LogRecord> = KvLog::open(RocksDBStore::open(&path)?)?;
+        out_of_range(&mut ml)
+    }
+
     #[test]
     #[serial]
     fn rocksdb_add() -> anyhow::Result<()> {
         let path = setup()?;
-        let mut ml: RocksDBLog<LogRecord> = RocksDBLog::open(&path)?;
+        let mut ml: RocksDBLog<LogRecord> = KvLog::open(RocksDBStore::open(&path)?)?;
         add(&mut ml)
     }
 
+    #[test]
+    #[serial]
+    fn rocksdb_lookup() -> anyhow::Result<()> {
+        let path = setup()?;
+        let mut ml: RocksDBLog<LogRecord> = KvLog::open(RocksDBStore::open(&path)?)?;
+        lookup(&mut ml)
+    }
+
+    #[test]
+    #[serial]
+    fn rocksdb_range() -> anyhow::Result<()> {
+        let path = setup()?;
+        let mut ml: RocksDBLog<LogRecord> = KvLog::open(RocksDBStore::open(&path)?)?;
+        range(&mut ml)
+    }
+
+    #[test]
+    #[serial]
+    fn rocksdb_iter() -> anyhow::Result<()> {
+        let path = setup()?;
+        let mut ml: RocksDBLog<LogRecord> = KvLog::open(RocksDBStore::open(&path)?)?;
+        iter(&mut ml)
+    }
+
+    #[test]
+    #[serial]
+    fn rocksdb_annotations() -> anyhow::Result<()> {
+        let path = setup()?;
+        let mut ml: RocksDBLog<LogRecord> = KvLog::open(RocksDBStore::open(&path)?)?;
+        annotations(&mut ml)
+    }
+
+    #[test]
+    #[serial]
+    fn rocksdb_tiles() -> anyhow::Result<()> {
+        let path = setup()?;
+        let mut ml: RocksDBLog<LogRecord> = KvLog::open(RocksDBStore::open(&path)?)?;
+        tiles(&mut ml)
+    }
+
+    #[test]
+    #[serial]
+    fn rocksdb_batch_proofs() -> anyhow::Result<()> {
+        let path = setup()?;
+        let mut ml: RocksDBLog<LogRecord> = KvLog::open(RocksDBStore::open(&path)?)?;
+        batch_proofs(&mut ml)
+    }
+
+    #[test]
+    #[serial]
+    fn rocksdb_bulk() -> anyhow::Result<()> {
+        let path = setup()?;
+        let mut ml: RocksDBLog<LogRecord> = KvLog::open(RocksDBStore::open(&path)?)?;
+        bulk(&mut ml)
+    }
+
+    #[test]
+    #[serial]
+    fn rocksdb_timestamps() -> anyhow::Result<()> {
+        let path = setup()?;
+        let mut ml: RocksDBLog<LogRecord> = KvLog::open(RocksDBStore::open(&path)?)?;
+        timestamps(&mut ml)
+    }
+
+    #[test]
+    #[serial]
+    fn rocksdb_export_import() -> anyhow::Result<()> {
+        let path = setup()?;
+        let mut ml = InMemoryLog::default();
+        let mut ml2: RocksDBLog<LogRecord> = KvLog::open(RocksDBStore::open(&path)?)?;
+        export_import(&mut ml, &mut ml2)
+    }
+
+    #[test]
+    #[serial]
+    fn rocksdb_mirror() -> anyhow::Result<()> {
+        let path = setup()?;
+        let source = path.join("source");
+        let path2 = path.join("mirror");
+        let mut ml: RocksDBLog<LogRecord> = KvLog::open(RocksDBStore::open(&source)?)?;
+        let mut ml2: RocksDBLog<LogRecord> = KvLog::open(RocksDBStore::open(&path2)?)?;
+        mirror(&mut ml, &mut ml2)
+    }
+
+    #[test]
+    #[serial]
+    fn rocksdb_properties() -> anyhow::Result<()> {
+        let path = setup()?;
+        properties(|| -> anyhow::Result<RocksDBLog<LogRecord>> {
+            setup()?;
+            KvLog::open(RocksDBStore::open(&path)?)
+        })
+    }
+
+    #[test]
+    #[serial]
+    fn rocksdb_hash_only() -> anyhow::Result<()> {
+        let path = setup()?;
+        let mut ml: RocksDBLog<LogRecord> = KvLog::open(RocksDBStore::open(&path)?)?;
+        hash_only(&mut ml)?;
+        drop(ml);
+        let ml: RocksDBLog<LogRecord> = KvLog::open(RocksDBStore::open(&path)?)?;
+        check_13(&ml)?;
+        assert_tombstone(ml.get(3));
+        assert_tombstone(ml.iter().nth(3).unwrap());
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    fn rocksdb_expire() -> anyhow::Result<()> {
+        let path = setup()?;
+        let mut ml: RocksDBLog<LogRecord> = KvLog::open(RocksDBStore::open(&path)?)?;
+        expire(&mut ml)
+    }
+
+    #[test]
+    #[serial]
+    fn rocksdb_redact() -> anyhow::Result<()> {
+        let path = setup()?;
+        let mut ml: RocksDBLog<LogRecord> =
+            KvLog::open_with_compressor(RocksDBStore::open(&path)?, Box::new(RleCompressor))?;
+        redact(&mut ml)
+    }
+
     #[test]
     #[serial]
     fn rocksdb_13() -> anyhow::Result<()> {
         let path = setup()?;
-        let mut ml: RocksDBLog<LogRecord> = RocksDBLog::open(&path)?;
+        let mut ml: RocksDBLog<LogRecord> = KvLog::open(RocksDBStore::open(&path)?)?;
+        test_13(&mut ml)?;
+        drop(ml);
+        let ml: RocksDBLog<LogRecord> = KvLog::open(RocksDBStore::open(&path)?)?;
+        check_13(&ml)
+    }
+
+    #[test]
+    #[serial]
+    fn rocksdb_levels() -> anyhow::Result<()> {
+        let path = setup()?;
+        let mut ml: RocksDBLog<LogRecord> = KvLog::open(RocksDBStore::open(&path)?)?;
+        test_13(&mut ml)?;
+
+        // a counter out of step with the records
+        let mut store = ml.into_store();
+        store.put(vec![(
+            KvNamespace::Levels,
+            1_u64.to_be_bytes().to_vec(),
+            5_u64.to_be_bytes().to_vec(),
+        )])?;
+        drop(store);
+        let err = RocksDBLog::<LogRecord>::open(RocksDBStore::open(&path)?)
+            .err()
+            .unwrap();
+        assert!(matches!(
+            err.downcast_ref::<KvLogError>(),
+            Some(KvLogError::InconsistentLevel(1, 5, 13))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    fn rocksdb_compressed_13() -> anyhow::Result<()> {
+        let path = setup()?;
+        let config = RocksConfig::default();
+        let mut ml: RocksDBLog<LogRecord> = KvLog::open_with_compressor(
+            RocksDBStore::open_with(&path, &config)?,
+            Box::new(RleCompressor),
+        )?;
+        test_13(&mut ml)?;
+        drop(ml);
+        let ml: RocksDBLog<LogRecord> = KvLog::open_with_compressor(
+            RocksDBStore::open_with(&path, &config)?,
+            Box::new(RleCompressor),
+        )?;
+        check_13(&ml)?;
+        assert_eq!(13, ml.iter().count());
+        assert_eq!(3, ml.get_range(10, 13)?.len());
+        drop(ml);
+        let err = RocksDBLog::<LogRecord>::open(RocksDBStore::open(&path)?)
+            .err()
+            .unwrap();
+        assert!(matches!(
+            err.downcast_ref::<CompressionError>(),
+            Some(CompressionError::Mismatch { expected: None, .. })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    fn rocksdb_codec_json() -> anyhow::Result<()> {
+        let path = setup()?;
+        let config = RocksConfig::default();
+        let mut ml = RocksDBLog::<LogRecord, Json>::open_with_codec(
+            RocksDBStore::open_with(&path, &config)?,
+            None,
+        )?;
+        codec_records::<Json, _>(&mut ml)?;
+        drop(ml);
+        let ml = RocksDBLog::<LogRecord, Json>::open_with_codec(
+            RocksDBStore::open_with(&path, &config)?,
+            None,
+        )?;
+        assert_eq!(13, ml.iter().count());
+        drop(ml);
+        let err = RocksDBLog::<LogRecord>::open(RocksDBStore::open(&path)?)
+            .err()
+            .unwrap();
+        assert!(matches!(
+            err.downcast_ref::<CodecError>(),
+            Some(CodecError::Mismatch { expected, found }) if expected == "msgpack" && found == "json"
+        ));
+
+        let path = setup()?;
+        let db = SharedRocksDB::open(&path)?;
+        let mut ml = RocksDBLog::<LogRecord, Json>::open_with_codec(
+            RocksDBStore::open_named(&db, "json")?,
+            Some(Box::new(RleCompressor)),
+        )?;
+        codec_records::<Json, _>(&mut ml)?;
+        let mut ml2: RocksDBLog<LogRecord> =
+            KvLog::open(RocksDBStore::open_named(&db, "msgpack")?)?;
+        test_13(&mut ml2)?;
+        assert!(RocksDBLog::<LogRecord, Json>::open_with_codec(
+            RocksDBStore::open_named(&db, "msgpack")?,
+            None
+        )
+        .is_err());
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    fn rocksdb_named() -> anyhow::Result<()> {
+        let path = setup()?;
+        let db = SharedRocksDB::open(&path)?;
+        let mut ml: RocksDBLog<LogRecord> = KvLog::open(RocksDBStore::open_named(&db, "first")?)?;
+        test_13(&mut ml)?;
+        let mut ml2: RocksDBLog<LogRecord> = KvLog::open_with_compressor(
+            RocksDBStore::open_named(&db, "second")?,
+            Box::new(RleCompressor),
+        )?;
+        empty(&mut ml2)?;
+        range(&mut ml2)?;
+        let mut ml3: RocksDBLog<LogRecord> = KvLog::open(RocksDBStore::open_named(&db, "third")?)?;
+        iter(&mut ml3)?;
+        drop((ml, ml2, ml3, db));
+
+        let db = SharedRocksDB::open(&path)?;
+        assert_eq!(vec!["first", "second", "third"], db.logs()?);
+        let ml: RocksDBLog<LogRecord> = KvLog::open(RocksDBStore::open_named(&db, "first")?)?;
+        check_13(&ml)?;
+        assert_eq!(13, ml.iter().count());
+        let ml2: RocksDBLog<LogRecord> = KvLog::open_with_compressor(
+            RocksDBStore::open_named(&db, "second")?,
+            Box::new(RleCompressor),
+        )?;
+        assert_eq!(10, ml2.size()?);
+        assert!(RocksDBLog::<LogRecord>::open(RocksDBStore::open_named(&db, "second")?).is_err());
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    fn rocksdb_named_not_shared() -> anyhow::Result<()> {
+        let path = setup()?;
+        let mut ml: RocksDBLog<LogRecord> = KvLog::open(RocksDBStore::open(&path)?)?;
+        test_13(&mut ml)?;
+        drop(ml);
+        let err = SharedRocksDB::open(&path).err().unwrap();
+        assert!(matches!(
+            err.downcast_ref::<RocksDBLogError>(),
+            Some(RocksDBLogError::NotShared)
+        ));
+
+        let path = setup()?;
+        let db = SharedRocksDB::open(&path)?;
+        let mut ml: RocksDBLog<LogRecord> = KvLog::open(RocksDBStore::open_named(&db, "first")?)?;
+        test_13(&mut ml)?;
+        drop((ml, db));
+        assert!(RocksDBStore::open(&path).is_err());
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    fn rocksdb_sync_13() -> anyhow::Result<()> {
+        let path = setup()?;
+        let mut opts = WriteOptions::default();
+        opts.set_sync(true);
+        let mut ml: RocksDBLog<LogRecord> =
+            KvLog::open(RocksDBStore::open_with_write_options(&path, opts)?)?;
         test_13(&mut ml)?;
         drop(ml);
-        let ml: RocksDBLog<LogRecord> = RocksDBLog::open(&path)?;
+        let ml: RocksDBLog<LogRecord> = KvLog::open(RocksDBStore::open(&path)?)?;
         check_13(&ml)
     }
 
+    #[test]
+    #[serial]
+    fn rocksdb_config_13() -> anyhow::Result<()> {
+        let path = setup()?;
+        let mut config = RocksConfig::default();
+        config
+            .block_cache_size(1 << 20)
+            .compression(DBCompressionType::Snappy)
+            .bloom_filter(10.0)
+            .sync_writes(true);
+        let mut ml: RocksDBLog<LogRecord> = KvLog::open(RocksDBStore::open_with(&path, &config)?)?;
+        test_13(&mut ml)?;
+        drop(ml);
+        let ml: RocksDBLog<LogRecord> = KvLog::open(RocksDBStore::open(&path)?)?;
+        check_13(&ml)
+    }
+
+    #[test]
+    #[serial]
+    fn rocksdb_legacy_13() -> anyhow::Result<()> {
+        let path = setup()?;
+        let mut reference = InMemoryLog::default();
+        test_13(&mut reference)?;
+        // the column families and 128 bits keys of earlier versions
+        {
+            let mut opts = Options::default();
+            opts.create_if_missing(true);
+            opts.create_missing_column_families(true);
+            let db = DB::open_cf(&opts, &path, ["data", "hash"])?;
+            let data = db.cf_handle("data").unwrap();
+            for id in 0..13_u128 {
+                let record = LogRecord::new(&format!("rec{}", id));
+                db.put_cf(data, id.to_be_bytes(), MessagePack::encode(&record)?)?;
+            }
+            let hashes = db.cf_handle("hash").unwrap();
+            for (level, count) in tree_sizes(13_usize).into_iter().enumerate() {
+                for index in 0..count {
+                    let mut key = (level as u64).to_be_bytes().to_vec();
+                    key.extend_from_slice(&(index as u128).to_be_bytes());
+                    db.put_cf(hashes, key, reference.get_hash(level, index)?.as_str())?;
+                }
+            }
+        }
+
+        let mut ml: RocksDBLog<LogRecord> = KvLog::open(RocksDBStore::open(&path)?)?;
+        check_13(&ml)?;
+        assert_eq!(
+            Some(LogRecord::new("rec5")),
+            ml.get(5)?.map(|r| r.into_owned())
+        );
+        let hash5 = hash(&LogRecord::new("rec5"))?;
+        assert_eq!(vec![5], ml.indices_of(&hash5)?);
+        ml.append(LogRecord::new("rec13"))?;
+        reference.append(LogRecord::new("rec13"))?;
+        assert_eq!(reference.latest()?.hash, ml.latest()?.hash);
+        drop(ml);
+
+        let families = DB::list_cf(&Options::default(), &path)?;
+        assert!(!families.iter().any(|f| f == "data" || f == "hash"));
+        let ml: RocksDBLog<LogRecord> = KvLog::open(RocksDBStore::open(&path)?)?;
+        assert_eq!(14, ml.size()?);
+        Ok(())
+    }
+
     #[test]
     #[serial]
     fn rocksdb_memory_13() -> anyhow::Result<()> {
         let path = setup()?;
-        let mut ml: RocksDBLog<LogRecord> = RocksDBLog::open(&path)?;
+        let mut ml: RocksDBLog<LogRecord> = KvLog::open(RocksDBStore::open(&path)?)?;
         let mut client = InMemoryLogClientBuilder::new(&ml)?.build();
         client_13(&mut ml, &mut client)
     }
@@ -198,7 +462,7 @@ mod tests {
     #[serial]
     fn rocksdb_memory_13_no_cache() -> anyhow::Result<()> {
         let path = setup()?;
-        let mut ml: RocksDBLog<LogRecord> = RocksDBLog::open(&path)?;
+        let mut ml: RocksDBLog<LogRecord> = KvLog::open(RocksDBStore::open(&path)?)?;
         let mut client = InMemoryLogClientBuilder::new(&ml)?.no_cache().build();
         client_13_nocache(&mut ml, &mut client)
     }
@@ -207,7 +471,7 @@ mod tests {
     #[serial]
     fn rocksdb_verify_tree_prefix() -> anyhow::Result<()> {
         let path = setup()?;
-        let mut ml: RocksDBLog<LogRecord> = RocksDBLog::open(&path)?;
+        let mut ml: RocksDBLog<LogRecord> = KvLog::open(RocksDBStore::open(&path)?)?;
         test_verify_tree_prefix(&mut ml)
     }
 }
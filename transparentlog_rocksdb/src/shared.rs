@@ -0,0 +1,77 @@
+//! A RocksDB database shared by many named logs
+use crate::store::{families, family, open_db};
+use crate::{RocksConfig, RocksDBLogError};
+use rocksdb::{WriteOptions, DB};
+use std::{
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+/// The column family mapping log names to their numeric ids
+const FAMILY_LOGS: &str = "logs";
+
+/// A database holding many independent logs, whose stores are opened with `RocksDBStore::open_named`
+/// All logs share the column families, each log prefixing its keys with its id, recorded with its name
+pub struct SharedRocksDB {
+    pub(crate) db: Arc<DB>,
+    config: RocksConfig,
+    next_id: Mutex<u32>,
+}
+
+impl SharedRocksDB {
+    /// Open a new or existing shared database
+    pub fn open<P: AsRef<Path>>(path: &P) -> anyhow::Result<Self> {
+        Self::open_with(path, &RocksConfig::default())
+    }
+
+    /// Open a new or existing shared database with the given tuning, used by all its logs
+    pub fn open_with<P: AsRef<Path>>(path: &P, config: &RocksConfig) -> anyhow::Result<Self> {
+        let mut all = families();
+        all.push(FAMILY_LOGS);
+        let db = open_db(path, config, &all)?;
+        let next_id = db
+            .iterator_cf(family(&db, FAMILY_LOGS)?, rocksdb::IteratorMode::Start)
+            .count();
+        if next_id == 0 {
+            // keys without a log id come from a database of a single log
+            for name in families() {
+                let mut it = db.iterator_cf(family(&db, name)?, rocksdb::IteratorMode::Start);
+                if it.next().is_some() {
+                    return Err(RocksDBLogError::NotShared.into());
+                }
+            }
+        }
+        Ok(Self {
+            db: Arc::new(db),
+            config: config.clone(),
+            next_id: Mutex::new(next_id as u32),
+        })
+    }
+
+    /// The names of all the logs, in order
+    pub fn logs(&self) -> anyhow::Result<Vec<String>> {
+        self.db
+            .iterator_cf(family(&self.db, FAMILY_LOGS)?, rocksdb::IteratorMode::Start)
+            .map(|(name, _)| Ok(String::from_utf8(name.into())?))
+            .collect()
+    }
+
+    /// The key prefix of the named log, registering the log if it is new
+    pub(crate) fn log_prefix(&self, name: &str) -> anyhow::Result<Vec<u8>> {
+        let cf = family(&self.db, FAMILY_LOGS)?;
+        // hold the lock while registering, so two new logs never get the same id
+        let mut next_id = self.next_id.lock().map_err(|_| RocksDBLogError::Poisoned)?;
+        if let Some(id) = self.db.get_cf(cf, name)? {
+            return Ok(id);
+        }
+        let id = next_id.to_be_bytes();
+        self.db.put_cf_opt(cf, name, id, &self.write_options())?;
+        *next_id += 1;
+        Ok(id.to_vec())
+    }
+
+    /// The options for the writes of all logs
+    pub(crate) fn write_options(&self) -> WriteOptions {
+        self.config.write_options()
+    }
+}
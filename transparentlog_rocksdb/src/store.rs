@@ -0,0 +1,233 @@
+//! RocksDB adapter for the generic key-value log
+use crate::{RocksConfig, RocksDBLogError, SharedRocksDB};
+use rocksdb::{
+    ColumnFamily, ColumnFamilyDescriptor, IteratorMode, Options, WriteBatch, WriteOptions, DB,
+};
+use std::{path::Path, sync::Arc};
+use transparentlog_core::{
+    kv_hash_writes, kv_record_writes, KvNamespace, KvStore, KvVisitor, KvWrite, LogHeight,
+};
+
+/// The column families of the records and of the hashes, in databases written before the key-value log
+const LEGACY_FAMILIES: [&str; 2] = ["data", "hash"];
+
+/// A column family of the database
+pub(crate) fn family<'d>(db: &'d DB, name: &'static str) -> anyhow::Result<&'d ColumnFamily> {
+    Ok(db
+        .cf_handle(name)
+        .ok_or(RocksDBLogError::UnknownColumnFamily(name))?)
+}
+
+/// The column families of the namespaces of a log
+pub(crate) fn families() -> Vec<&'static str> {
+    KvNamespace::ALL.iter().map(KvNamespace::name).collect()
+}
+
+/// Open the database with the given column families, tuned by the given configuration
+pub(crate) fn open_db<P: AsRef<Path>>(
+    path: &P,
+    config: &RocksConfig,
+    families: &[&str],
+) -> anyhow::Result<DB> {
+    let cache = config.cache()?;
+    let families = families.iter().map(|name| {
+        // hashes get point lookups, the leaf index gets range scans that bloom filters do not help
+        let bloom = *name == KvNamespace::Hashes.name();
+        ColumnFamilyDescriptor::new(*name, config.family_options(cache.as_ref(), bloom))
+    });
+
+    let mut db_opts = Options::default();
+    db_opts.create_missing_column_families(true);
+    db_opts.create_if_missing(true);
+
+    Ok(DB::open_cf_descriptors(&db_opts, path, families)?)
+}
+
+/// Open the database of a single log, migrating it first if it was written before the key-value log
+fn open_migrated<P: AsRef<Path>>(path: &P, config: &RocksConfig) -> anyhow::Result<DB> {
+    // a missing database has no column families
+    let existing = DB::list_cf(&Options::default(), path).unwrap_or_default();
+    if !existing.iter().any(|name| name == LEGACY_FAMILIES[0]) {
+        return open_db(path, config, &families());
+    }
+    let mut all = families();
+    all.extend(LEGACY_FAMILIES);
+    let mut db = open_db(path, config, &all)?;
+    migrate(&db)?;
+    for name in LEGACY_FAMILIES {
+        db.drop_cf(name)?;
+    }
+    Ok(db)
+}
+
+/// Copy the records and the hashes of the legacy column families to the column families of the namespaces,
+/// in one batch so an interrupted migration is run again at the next open
+/// Legacy records are MessagePack, the codec of a log without a recorded codec
+fn migrate(db: &DB) -> anyhow::Result<()> {
+    let mut writes = vec![];
+    for (key, value) in db.iterator_cf(family(db, LEGACY_FAMILIES[0])?, IteratorMode::Start) {
+        writes.extend(kv_record_writes(legacy_index(&key)?, value.into_vec()));
+    }
+    for (key, value) in db.iterator_cf(family(db, LEGACY_FAMILIES[1])?, IteratorMode::Start) {
+        // the level as a big endian u64, then the index
+        let (level, index) = key.split_at(key.len().min(8));
+        let level = u64::from_be_bytes(level.try_into()?) as LogHeight;
+        let hash = String::from_utf8(value.into_vec())?;
+        writes.extend(kv_hash_writes(level, legacy_index(index)?, hash));
+    }
+    let mut batch = WriteBatch::default();
+    for (namespace, key, value) in writes {
+        batch.put_cf(family(db, namespace.name())?, key, value);
+    }
+    db.write(batch)?;
+    Ok(())
+}
+
+/// A legacy index, a big endian u128 that must fit in the u64 of the key-value log
+fn legacy_index(key: &[u8]) -> anyhow::Result<u64> {
+    let index = u128::from_be_bytes(key.try_into()?);
+    Ok(u64::try_from(index).map_err(|_| RocksDBLogError::LegacyIndex(index))?)
+}
+
+/// A key in a log, after the log prefix
+fn prefixed<K: AsRef<[u8]>>(prefix: &[u8], key: K) -> Vec<u8> {
+    let mut v = prefix.to_vec();
+    v.extend_from_slice(key.as_ref());
+    v
+}
+
+/// A key-value store over a RocksDB database, with a column family per namespace
+/// Several stores can share a database, each store prefixing all its keys
+pub struct RocksDBStore {
+    db: Arc<DB>,
+    prefix: Vec<u8>,
+    write_options: WriteOptions,
+}
+
+impl RocksDBStore {
+    /// Open a new or existing database
+    /// A database written before the key-value log, with its records and hashes in the "data" and "hash"
+    /// column families, is migrated to the column families of the namespaces
+    pub fn open<P: AsRef<Path>>(path: &P) -> anyhow::Result<Self> {
+        Self::open_with(path, &RocksConfig::default())
+    }
+
+    /// Open a new or existing database with the given tuning
+    pub fn open_with<P: AsRef<Path>>(path: &P, config: &RocksConfig) -> anyhow::Result<Self> {
+        let db = open_migrated(path, config)?;
+        Ok(Self {
+            db: Arc::new(db),
+            prefix: vec![],
+            write_options: config.write_options(),
+        })
+    }
+
+    /// Open a new or existing database, using the given options for all writes (to sync the WAL, for example)
+    pub fn open_with_write_options<P: AsRef<Path>>(
+        path: &P,
+        write_options: WriteOptions,
+    ) -> anyhow::Result<Self> {
+        let db = open_migrated(path, &RocksConfig::default())?;
+        Ok(Self {
+            db: Arc::new(db),
+            prefix: vec![],
+            write_options,
+        })
+    }
+
+    /// Open the store of the named log of a shared database, registering the log if it is new
+    pub fn open_named(db: &SharedRocksDB, name: &str) -> anyhow::Result<Self> {
+        Ok(Self {
+            db: db.db.clone(),
+            prefix: db.log_prefix(name)?,
+            write_options: db.write_options(),
+        })
+    }
+
+    /// The column family of a namespace
+    fn family(&self, namespace: KvNamespace) -> anyhow::Result<&ColumnFamily> {
+        family(&self.db, namespace.name())
+    }
+}
+
+impl KvStore for RocksDBStore {
+    fn get(&self, namespace: KvNamespace, key: &[u8]) -> anyhow::Result<Option<Vec<u8>>> {
+        Ok(self
+            .db
+            .get_cf(self.family(namespace)?, prefixed(&self.prefix, key))?)
+    }
+
+    /// Get all the values with a single multi get
+    fn get_many(
+        &self,
+        namespace: KvNamespace,
+        keys: &[Vec<u8>],
+    ) -> anyhow::Result<Vec<Option<Vec<u8>>>> {
+        let cf = self.family(namespace)?;
+        self.db
+            .multi_get_cf(keys.iter().map(|k| (cf, prefixed(&self.prefix, k))))
+            .into_iter()
+            .map(|r| r.map_err(Into::into))
+            .collect()
+    }
+
+    /// Write all the values in one batch
+    fn put(&mut self, writes: Vec<KvWrite>) -> anyhow::Result<()> {
+        let mut batch = WriteBatch::default();
+        for (namespace, key, value) in writes {
+            batch.put_cf(self.family(namespace)?, prefixed(&self.prefix, key), value);
+        }
+        self.db.write_opt(batch, &self.write_options)?;
+        Ok(())
+    }
+
+    fn scan(
+        &self,
+        namespace: KvNamespace,
+        start: &[u8],
+        end: &[u8],
+        f: &mut KvVisitor,
+    ) -> anyhow::Result<()> {
+        let start = prefixed(&self.prefix, start);
+        let end = prefixed(&self.prefix, end);
+        let it = self.db.iterator_cf(
+            self.family(namespace)?,
+            rocksdb::IteratorMode::From(&start, rocksdb::Direction::Forward),
+        );
+        for (key, value) in it.take_while(|(key, _)| key.as_ref() < end.as_slice()) {
+            f(&key[self.prefix.len()..], &value[..])?;
+        }
+        Ok(())
+    }
+
+    fn last_key(&self, namespace: KvNamespace) -> anyhow::Result<Option<Vec<u8>>> {
+        // the smallest key after all the keys of the log, if there is one
+        let mut bound = self.prefix.clone();
+        while let Some(b) = bound.pop() {
+            if b < u8::MAX {
+                bound.push(b + 1);
+                break;
+            }
+        }
+        let mode = if bound.is_empty() {
+            rocksdb::IteratorMode::End
+        } else {
+            rocksdb::IteratorMode::From(&bound, rocksdb::Direction::Reverse)
+        };
+        Ok(self
+            .db
+            .iterator_cf(self.family(namespace)?, mode)
+            // the seek lands on the bound itself if another log uses it
+            .find(|(key, _)| bound.is_empty() || key.as_ref() < bound.as_slice())
+            .filter(|(key, _)| key.starts_with(&self.prefix))
+            .map(|(key, _)| key[self.prefix.len()..].to_vec()))
+    }
+
+    /// Compact the key, so its overwritten values are dropped from the database files
+    fn compact(&self, namespace: KvNamespace, key: &[u8]) -> anyhow::Result<()> {
+        let key = prefixed(&self.prefix, key);
+        self.db
+            .compact_range_cf(self.family(namespace)?, Some(&key), Some(&key));
+        Ok(())
+    }
+}
@@ -0,0 +1,374 @@
+//! # tracing integration for Transparent Log
+//!
+//! An `AuditLayer` is a `tracing_subscriber` layer turning the events it sees into `TracingRecord` records appended to
+//! any log, for a tamper-evident audit log of an application. Select the events to log with a per-layer filter, like
+//! `Targets::new().with_target("audit", Level::INFO)`, so only audit events get in the log.
+//! Events go through a bounded channel to a writer thread owning the log, appending them in batches: when the writer
+//! falls behind, the application threads logging events block, or the events are dropped and counted, as configured.
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{channel, sync_channel, Receiver, Sender, SyncSender};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use thiserror::Error;
+use tracing::field::{Field, Visit};
+use tracing::{Event, Subscriber};
+use tracing_subscriber::layer::{Context, Layer};
+use tracing_subscriber::registry::LookupSpan;
+use transparentlog_core::*;
+
+/// The default number of events waiting for the writer
+const DEFAULT_CAPACITY: usize = 10_000;
+
+/// The default maximum number of events appended at once
+const DEFAULT_BATCH_SIZE: usize = 1000;
+
+/// Specific Errors for the audit layer
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum AuditLayerError {
+    #[error("Audit log writer stopped")]
+    Stopped,
+}
+
+/// A `tracing` event, as logged
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TracingRecord {
+    /// Event time, in milliseconds since the UNIX epoch
+    pub timestamp: u64,
+    pub level: String,
+    pub target: String,
+    /// The message of the event, if it has one
+    pub message: Option<String>,
+    /// The other fields of the event
+    pub fields: BTreeMap<String, serde_json::Value>,
+    /// The names of the spans the event is in, from the root
+    pub spans: Vec<String>,
+}
+
+/// What to do with events when the writer falls behind
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Overflow {
+    /// Block the thread logging the event until the writer catches up, so no event is lost
+    Block,
+    /// Drop the event, counting it
+    Drop,
+}
+
+/// What the layer sends to the writer
+enum Command {
+    Record(TracingRecord),
+    Flush(Sender<()>),
+    Stop,
+}
+
+/// The layer sending the events to the writer of the log
+pub struct AuditLayer {
+    sender: SyncSender<Command>,
+    overflow: Overflow,
+    dropped: Arc<AtomicU64>,
+}
+
+/// The builder of an audit layer and its writer
+#[derive(Debug, Clone, Copy)]
+pub struct AuditLayerBuilder {
+    capacity: usize,
+    batch_size: usize,
+    overflow: Overflow,
+}
+
+impl Default for AuditLayerBuilder {
+    fn default() -> Self {
+        Self {
+            capacity: DEFAULT_CAPACITY,
+            batch_size: DEFAULT_BATCH_SIZE,
+            overflow: Overflow::Block,
+        }
+    }
+}
+
+impl AuditLayerBuilder {
+    /// Set the number of events waiting for the writer before the overflow strategy applies
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity.max(1);
+        self
+    }
+
+    /// Set the maximum number of events appended at once
+    pub fn batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// Set what to do with events when the writer falls behind, blocking by default
+    pub fn overflow(mut self, overflow: Overflow) -> Self {
+        self.overflow = overflow;
+        self
+    }
+
+    /// Start the writer thread appending the events to the given log, and return the layer sending them to it
+    pub fn build<TL>(self, log: TL) -> (AuditLayer, AuditWriter<TL>)
+    where
+        TL: TransparentLog<'static, TracingRecord> + Send + 'static,
+    {
+        let (sender, receiver) = sync_channel(self.capacity);
+        let batch_size = self.batch_size;
+        let writer = std::thread::spawn(move || write(log, receiver, batch_size));
+        let dropped = Arc::new(AtomicU64::new(0));
+        (
+            AuditLayer {
+                sender: sender.clone(),
+                overflow: self.overflow,
+                dropped: dropped.clone(),
+            },
+            AuditWriter {
+                sender,
+                writer,
+                dropped,
+            },
+        )
+    }
+}
+
+impl AuditLayer {
+    /// The builder of a layer, with a capacity of 10000 events, batches of 1000 events, blocking on overflow
+    pub fn builder() -> AuditLayerBuilder {
+        AuditLayerBuilder::default()
+    }
+}
+
+impl<S> Layer<S> for AuditLayer
+where
+    S: Subscriber + for<'l> LookupSpan<'l>,
+{
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let metadata = event.metadata();
+        let mut visitor = FieldVisitor::default();
+        event.record(&mut visitor);
+        let spans = ctx
+            .event_scope(event)
+            .map(|scope| {
+                scope
+                    .from_root()
+                    .map(|span| span.name().to_owned())
+                    .collect()
+            })
+            .unwrap_or_default();
+        let record = TracingRecord {
+            timestamp: RecordMeta::now().timestamp,
+            level: metadata.level().to_string(),
+            target: metadata.target().to_owned(),
+            message: visitor.message,
+            fields: visitor.fields,
+            spans,
+        };
+        let sent = match self.overflow {
+            Overflow::Block => self.sender.send(Command::Record(record)).is_ok(),
+            Overflow::Drop => self.sender.try_send(Command::Record(record)).is_ok(),
+        };
+        if !sent {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// The handle on the writer thread appending the events to the log
+pub struct AuditWriter<TL> {
+    sender: SyncSender<Command>,
+    writer: JoinHandle<anyhow::Result<TL>>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl<TL> AuditWriter<TL> {
+    /// The number of events dropped, because the writer fell behind or stopped
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Wait until the events sent so far are appended
+    pub fn flush(&self) -> anyhow::Result<()> {
+        let (done, appended) = channel();
+        self.sender
+            .send(Command::Flush(done))
+            .map_err(|_| AuditLayerError::Stopped)?;
+        appended.recv().map_err(|_| AuditLayerError::Stopped)?;
+        Ok(())
+    }
+
+    /// Append the events sent so far, stop the writer and get back the log
+    /// Events sent afterwards are dropped
+    pub fn finish(self) -> anyhow::Result<TL> {
+        // a stopped writer returns its error when joined
+        let _ = self.sender.send(Command::Stop);
+        self.writer
+            .join()
+            .map_err(|_| anyhow::anyhow!("Audit log writer panicked"))?
+    }
+}
+
+/// The writer loop: append the events received in batches, until stopped
+fn write<TL>(mut log: TL, receiver: Receiver<Command>, batch_size: usize) -> anyhow::Result<TL>
+where
+    TL: TransparentLog<'static, TracingRecord>,
+{
+    let mut batch = Vec::with_capacity(batch_size);
+    let mut flushed = vec![];
+    while let Ok(command) = receiver.recv() {
+        let mut next = Some(command);
+        let mut stop = false;
+        while let Some(command) = next {
+            match command {
+                Command::Record(record) => batch.push(record),
+                Command::Flush(done) => flushed.push(done),
+                Command::Stop => stop = true,
+            }
+            next = if batch.len() < batch_size && !stop {
+                receiver.try_recv().ok()
+            } else {
+                None
+            };
+        }
+        if !batch.is_empty() {
+            log.bulk_append(std::mem::take(&mut batch))?;
+        }
+        for done in flushed.drain(..) {
+            // the flushing thread may have given up
+            let _ = done.send(());
+        }
+        if stop {
+            break;
+        }
+    }
+    Ok(log)
+}
+
+/// Collects the fields of an event
+#[derive(Default)]
+struct FieldVisitor {
+    message: Option<String>,
+    fields: BTreeMap<String, serde_json::Value>,
+}
+
+impl FieldVisitor {
+    fn insert(&mut self, field: &Field, value: serde_json::Value) {
+        self.fields.insert(field.name().to_owned(), value);
+    }
+}
+
+impl Visit for FieldVisitor {
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        if field.name() == "message" {
+            self.message = Some(format!("{:?}", value));
+        } else {
+            self.insert(field, format!("{:?}", value).into());
+        }
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.message = Some(value.to_owned());
+        } else {
+            self.insert(field, value.into());
+        }
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.insert(field, value.into());
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.insert(field, value.into());
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.insert(field, value.into());
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.insert(field, value.into());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::*;
+    use tracing::Level;
+    use tracing_subscriber::filter::Targets;
+    use tracing_subscriber::prelude::*;
+    use tracing_subscriber::Registry;
+
+    #[test]
+    fn tracing_audit_events() -> anyhow::Result<()> {
+        let (layer, writer) = AuditLayer::builder()
+            .batch_size(2)
+            .build(InMemoryLog::<TracingRecord>::default());
+        let subscriber = Registry::default()
+            .with(layer.with_filter(Targets::new().with_target("audit", Level::INFO)));
+        tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::info_span!("request", id = 7);
+            let _entered = span.enter();
+            tracing::info!(target: "audit", user = "alice", attempts = 2, admin = false, "Logged in");
+            tracing::info!(target: "app", "Not audited");
+            tracing::debug!(target: "audit", "Too verbose");
+            for i in 0..4 {
+                tracing::warn!(target: "audit::files", file = i, "Deleted");
+            }
+        });
+        writer.flush()?;
+        assert_eq!(0, writer.dropped());
+        let log = writer.finish()?;
+        assert_eq!(5, log.size()?);
+
+        let record = log.get(0)?.unwrap();
+        assert_eq!("INFO", record.level);
+        assert_eq!("audit", record.target);
+        assert_eq!(Some("Logged in".to_owned()), record.message);
+        assert_eq!(
+            Some(&serde_json::Value::from("alice")),
+            record.fields.get("user")
+        );
+        assert_eq!(
+            Some(&serde_json::Value::from(2)),
+            record.fields.get("attempts")
+        );
+        assert_eq!(
+            Some(&serde_json::Value::from(false)),
+            record.fields.get("admin")
+        );
+        assert_eq!(vec!["request".to_owned()], record.spans);
+        let record = log.get(4)?.unwrap();
+        assert_eq!("audit::files", record.target);
+        assert_eq!(Some(&serde_json::Value::from(3)), record.fields.get("file"));
+
+        let tree = log.latest()?;
+        let proofs = log.proofs(proof_positions(4, tree.size).into_iter())?;
+        let hash = (log.leaf_hasher())(&*record)?;
+        assert!(verify(
+            &tree,
+            &Record {
+                id: 4,
+                hash,
+                meta: None
+            },
+            &proofs
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn tracing_writer_stopped() -> anyhow::Result<()> {
+        let (layer, writer) = AuditLayer::builder()
+            .overflow(Overflow::Drop)
+            .build(InMemoryLog::<TracingRecord>::default());
+        let dropped = writer.dropped.clone();
+        let dispatch = tracing::Dispatch::new(Registry::default().with(layer));
+        tracing::dispatcher::with_default(&dispatch, || tracing::info!("Before"));
+        assert_eq!(1, writer.finish()?.size()?);
+        // events logged after the writer stopped are dropped
+        tracing::dispatcher::with_default(&dispatch, || tracing::info!("After"));
+        assert_eq!(1, dropped.load(Ordering::Relaxed));
+        Ok(())
+    }
+}
@@ -0,0 +1,36 @@
+//! SHA-256 hashes of records and nodes, as lowercase hexadecimal strings
+use alloc::format;
+use alloc::string::String;
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+
+/// The SHA-256 of the given bytes, as a lowercase hexadecimal string
+pub fn sha256_hex(data: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(data);
+    hex::encode(hasher.finalize())
+}
+
+/// Hash two sibling hashes into their parent hash
+pub fn hash_pair(h1: &str, h2: &str) -> String {
+    sha256_hex(format!("{}{}", h1, h2).as_bytes())
+}
+
+/// The size in bytes of the SHA-256 digests hexadecimal hashes encode
+pub const DIGEST_SIZE: usize = 32;
+
+/// Decode a hexadecimal hash into its fixed-size digest, or none if it is not one
+pub fn decode_digest(hash: &str) -> Option<[u8; DIGEST_SIZE]> {
+    let mut digest = [0; DIGEST_SIZE];
+    hex::decode_to_slice(hash, &mut digest).ok()?;
+    Some(digest)
+}
+
+/// Compare two hashes in constant time, so how long a verification takes does not tell how much of a forged hash matches
+/// Digests are compared as bytes, whatever the case of their hexadecimal encoding, and other hashes as strings
+pub fn hash_eq(h1: &str, h2: &str) -> bool {
+    match (decode_digest(h1), decode_digest(h2)) {
+        (Some(d1), Some(d2)) => d1[..].ct_eq(&d2[..]).into(),
+        _ => h1.as_bytes().ct_eq(h2.as_bytes()).into(),
+    }
+}
@@ -0,0 +1,354 @@
+//! # Transparent Log verification
+//!
+//! This crate provides the verification of Transparent Log proofs without the standard library, only `alloc`,
+//! for firmware or WASM verifiers receiving proofs from a log served by `transparentlog_core`.
+//!
+//! Records are identified by their leaf hash: the lowercase hexadecimal SHA-256 of their serialized form.
+//!
+//! # Examples
+//!
+//! ```
+//! use transparentlog_verify::{hash_pair, proof_positions, sha256_hex, verify, LogTree, LogTreePosition};
+//! use std::collections::BTreeMap;
+//!
+//! let h0 = sha256_hex(b"rec0");
+//! let h1 = sha256_hex(b"rec1");
+//! let tree = LogTree { size: 2_u64, hash: hash_pair(&h0, &h1) };
+//! let positions = proof_positions(0, tree.size);
+//! assert!(positions.contains(&LogTreePosition { level: 0, index: 1 }));
+//! let proofs: BTreeMap<_, _> = positions.into_iter().map(|p| (p, h1.clone())).collect();
+//! assert!(verify(&tree, 0, &h0, &proofs));
+//! ```
+#![no_std]
+
+extern crate alloc;
+#[cfg(feature = "std")]
+extern crate std;
+
+mod hash;
+pub use hash::*;
+mod tree;
+pub use tree::*;
+
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::string::String;
+use core::fmt::{Display, Formatter};
+use num_integer::Integer;
+
+/// Reference to a full log: its size and root hash
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct LogTree<LogSize> {
+    pub size: LogSize,
+    pub hash: String,
+}
+
+/// Errors preventing a proof from being checked
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VerifyError {
+    /// The proof is for another leaf hash
+    LeafMismatch,
+    /// The proof is for a leaf outside the tree
+    OutOfTree,
+    /// A hash needed at the given level is missing or empty
+    MissingProof(LogHeight),
+    /// The proof leads to another root hash
+    InvalidProof,
+}
+
+impl Display for VerifyError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::LeafMismatch => write!(f, "Proof is for another leaf hash"),
+            Self::OutOfTree => write!(f, "Proof is for a leaf outside the tree"),
+            Self::MissingProof(level) => write!(f, "Missing proof hash at level {}", level),
+            Self::InvalidProof => write!(f, "Inclusion proof is invalid"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for VerifyError {}
+
+/// Calculate the proof positions needed to assert the record at the given index is present in a log of the given size
+pub fn proof_positions<LogSize: Integer + Copy>(
+    index: LogSize,
+    size: LogSize,
+) -> BTreeSet<LogTreePosition<LogSize>> {
+    proof_positions_iter(index, size).collect()
+}
+
+/// Calculate the proof positions needed to assert a tree of size1 is a prefix of a tree of size 2
+/// Returns no positions unless 0 < size1 < size2
+pub fn prefix_proof_positions<LogSize: Integer + Copy>(
+    size1: LogSize,
+    size2: LogSize,
+) -> BTreeSet<LogTreePosition<LogSize>> {
+    prefix_proof_positions_iter(size1, size2).collect()
+}
+
+/// Verify that the leaf hash at the given index belongs to the given tree, using the proofs provided
+/// Only the proofs at the positions needed for the leaf are used, so other hashes cannot stand for the leaf
+pub fn verify<LogSize: Integer + Copy>(
+    tree: &LogTree<LogSize>,
+    index: LogSize,
+    leaf_hash: &str,
+    proofs: &BTreeMap<LogTreePosition<LogSize>, String>,
+) -> bool {
+    matches!(try_verify(tree, index, leaf_hash, proofs), Ok(true))
+}
+
+/// Verify that the leaf hash at the given index belongs to the given tree, using the proofs provided, telling why it cannot be checked
+/// Returns false if the proofs lead to another root, and fails with a `VerifyError::OutOfTree` error for a leaf
+/// outside the tree, or a `VerifyError::MissingProof` error if a position needed is missing or has an empty hash
+pub fn try_verify<LogSize: Integer + Copy>(
+    tree: &LogTree<LogSize>,
+    index: LogSize,
+    leaf_hash: &str,
+    proofs: &BTreeMap<LogTreePosition<LogSize>, String>,
+) -> Result<bool, VerifyError> {
+    if index >= tree.size {
+        return Err(VerifyError::OutOfTree);
+    }
+    let mut proofs2 = proof_positions_iter(index, tree.size)
+        .map(|p| match proofs.get(&p) {
+            Some(h) if !h.is_empty() => Ok((p, h.clone())),
+            _ => Err(VerifyError::MissingProof(p.level)),
+        })
+        .collect::<Result<BTreeMap<_, _>, _>>()?;
+    proofs2.insert(LogTreePosition { level: 0, index }, leaf_hash.into());
+    try_verify_tree(tree, &proofs2)
+}
+
+/// Verify that the tree is correct with the proofs provided
+pub fn verify_tree<LogSize: Integer + Copy>(
+    tree: &LogTree<LogSize>,
+    proofs: &BTreeMap<LogTreePosition<LogSize>, String>,
+) -> bool {
+    matches!(try_verify_tree(tree, proofs), Ok(true))
+}
+
+/// Verify that the tree is correct with the proofs provided, telling why it cannot be checked
+/// Returns false if the proofs lead to another root or the tree is empty,
+/// and fails with a `VerifyError::MissingProof` error if the proofs do not cover the tree
+pub fn try_verify_tree<LogSize: Integer + Copy>(
+    tree: &LogTree<LogSize>,
+    proofs: &BTreeMap<LogTreePosition<LogSize>, String>,
+) -> Result<bool, VerifyError> {
+    let sizes = tree_sizes(tree.size);
+    if sizes.is_empty() {
+        return Ok(false);
+    }
+    let root = calc_root(&sizes, &|p| proofs.get(p).map(String::as_str))?;
+    Ok(hash_eq(&tree.hash, &root))
+}
+
+/// Verify that the tree of size1 is a prefix of the tree of size2, using the proofs provided
+pub fn verify_consistency<LogSize: Integer + Copy>(
+    tree1: &LogTree<LogSize>,
+    tree2: &LogTree<LogSize>,
+    proofs: &BTreeMap<LogTreePosition<LogSize>, String>,
+) -> bool {
+    matches!(try_verify_consistency(tree1, tree2, proofs), Ok(true))
+}
+
+/// Verify that the tree of size1 is a prefix of the tree of size2, using the proofs provided, like `try_verify_tree`
+/// Returns false if the first tree is empty or larger than the second one
+pub fn try_verify_consistency<LogSize: Integer + Copy>(
+    tree1: &LogTree<LogSize>,
+    tree2: &LogTree<LogSize>,
+    proofs: &BTreeMap<LogTreePosition<LogSize>, String>,
+) -> Result<bool, VerifyError> {
+    if tree1.size.is_zero() || tree1.size > tree2.size {
+        return Ok(false);
+    }
+    if tree1.size == tree2.size {
+        return Ok(hash_eq(&tree1.hash, &tree2.hash));
+    }
+    Ok(try_verify_tree(tree1, proofs)? && try_verify_tree(tree2, proofs)?)
+}
+
+#[cfg(test)]
+mod tests {
+
+    extern crate std;
+
+    use crate::*;
+    use alloc::format;
+    use alloc::string::ToString;
+    use alloc::vec::Vec;
+
+    #[test]
+    fn test_sha256() {
+        assert_eq!(
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+            sha256_hex(b"")
+        );
+        assert_eq!(
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+            sha256_hex(b"abc")
+        );
+        assert_eq!(
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1",
+            sha256_hex(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq")
+        );
+        assert_eq!(
+            "cdc76e5c9914fb9281a1c7e284d73e67f1809a48a497200e046d39ccc7112cd0",
+            sha256_hex(&[b'a'; 1_000_000])
+        );
+    }
+
+    /// The leaf hashes of a tree of seven records, and the hashes of its complete subtrees
+    fn tree_of_seven() -> BTreeMap<LogTreePosition<u64>, String> {
+        let mut hashes: BTreeMap<_, _> = (0..7)
+            .map(|i| {
+                (
+                    LogTreePosition { level: 0, index: i },
+                    sha256_hex(format!("rec{}", i).as_bytes()),
+                )
+            })
+            .collect();
+        for (level, count) in [(1, 3), (2, 1)] {
+            for index in 0..count {
+                let left = &hashes[&LogTreePosition {
+                    level: level - 1,
+                    index: index * 2,
+                }];
+                let right = &hashes[&LogTreePosition {
+                    level: level - 1,
+                    index: index * 2 + 1,
+                }];
+                let hash = hash_pair(left, right);
+                hashes.insert(LogTreePosition { level, index }, hash);
+            }
+        }
+        hashes
+    }
+
+    #[test]
+    fn fixed_vectors() {
+        let hashes = tree_of_seven();
+        assert_eq!(
+            "c97c121a37e664a6ccbe874f9b0afb22e21661305772e0c2939e61ddfdb74924",
+            hashes[&LogTreePosition { level: 0, index: 1 }]
+        );
+        let tree = LogTree {
+            size: 7_u64,
+            hash: "20b572ac181dcb0a6424af7fa38999e345e7fde8ee489af694cb321dc17c5770".to_string(),
+        };
+        let position = |level, index| LogTreePosition { level, index };
+        assert_eq!(
+            [
+                position(0, 1),
+                position(0, 6),
+                position(1, 1),
+                position(1, 2)
+            ]
+            .into_iter()
+            .collect::<BTreeSet<_>>(),
+            proof_positions(0, 7)
+        );
+        assert_eq!(
+            [position(0, 4), position(0, 6), position(2, 0)]
+                .into_iter()
+                .collect::<BTreeSet<_>>(),
+            proof_positions(5, 7)
+        );
+        for index in 0..7 {
+            let leaf = &hashes[&position(0, index)];
+            let proofs: BTreeMap<_, _> = proof_positions(index, 7)
+                .into_iter()
+                .map(|p| (p, hashes[&p].clone()))
+                .collect();
+            assert!(verify(&tree, index, leaf, &proofs));
+            assert!(!verify(&tree, index, &hashes[&position(1, 0)], &proofs));
+        }
+
+        let old = LogTree {
+            size: 3_u64,
+            hash: "4e9c0e05ca16359360b420ca52d36acd231b63f3b0b4123694cac05e6512a322".to_string(),
+        };
+        let positions = prefix_proof_positions(3, 7);
+        assert_eq!(
+            [
+                position(0, 2),
+                position(0, 3),
+                position(0, 6),
+                position(1, 0),
+                position(1, 2)
+            ]
+            .into_iter()
+            .collect::<BTreeSet<_>>(),
+            positions
+        );
+        let proofs: BTreeMap<_, _> = positions
+            .into_iter()
+            .map(|p| (p, hashes[&p].clone()))
+            .collect();
+        assert!(verify_consistency(&old, &tree, &proofs));
+        assert!(!verify_consistency(&tree, &old, &proofs));
+        assert!(prefix_proof_positions(0_u64, 3).is_empty());
+    }
+
+    #[test]
+    fn verify_errors() {
+        let h: Vec<String> = (0..3)
+            .map(|i| sha256_hex(format!("rec{}", i).as_bytes()))
+            .collect();
+        let tree = LogTree {
+            size: 3_u64,
+            hash: hash_pair(&hash_pair(&h[0], &h[1]), &h[2]),
+        };
+        // the sibling leaf 1 and the leaf 2, stored alone at level 0
+        let mut proofs: BTreeMap<_, _> = proof_positions(0, 3)
+            .into_iter()
+            .map(|p| (p, h[p.index as usize].clone()))
+            .collect();
+        assert_eq!(2, proofs.len());
+        assert_eq!(Ok(true), try_verify(&tree, 0, &h[0], &proofs));
+        assert_eq!(Ok(false), try_verify(&tree, 0, &h[2], &proofs));
+        assert_eq!(
+            Err(VerifyError::OutOfTree),
+            try_verify(&tree, 3, &h[0], &proofs)
+        );
+        proofs.insert(LogTreePosition { level: 0, index: 2 }, String::new());
+        assert_eq!(
+            Err(VerifyError::MissingProof(0)),
+            try_verify(&tree, 0, &h[0], &proofs)
+        );
+        assert!(!verify(&tree, 0, &h[0], &proofs));
+        assert_eq!(
+            Err(VerifyError::MissingProof(0)),
+            try_verify_tree(&tree, &BTreeMap::new())
+        );
+        assert_eq!(
+            "Missing proof hash at level 0",
+            VerifyError::MissingProof(0).to_string()
+        );
+    }
+
+    #[test]
+    fn test_hash_eq() {
+        let h = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+        assert_eq!(Some(0xe3), decode_digest(h).map(|d| d[0]));
+        assert_eq!(None, decode_digest(&h[2..]));
+        assert_eq!(None, decode_digest("not a digest"));
+        assert!(hash_eq(h, h));
+        assert!(hash_eq(h, &h.to_uppercase()));
+        assert!(!hash_eq(h, &format!("{}00", &h[..62])));
+        assert!(!hash_eq(h, &h[2..]));
+        assert!(!hash_eq(h, ""));
+        assert!(hash_eq("", ""));
+        assert!(hash_eq("h4", "h4"));
+        assert!(!hash_eq("h4", "H4"));
+
+        let tree = LogTree {
+            size: 1_u64,
+            hash: h.to_uppercase(),
+        };
+        let leaf = LogTreePosition { level: 0, index: 0 };
+        assert_eq!(
+            Ok(true),
+            try_verify_tree(&tree, &[(leaf, h.to_string())].into_iter().collect())
+        );
+    }
+}
@@ -0,0 +1,287 @@
+//! The shape of the tree: level sizes, proof positions and the hashes they lead to, shared with `transparentlog_core`
+use crate::hash::hash_pair;
+use crate::VerifyError;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::{Display, Formatter};
+use num_integer::Integer;
+use num_traits::{NumCast, ToPrimitive};
+
+/// The height of the tree
+pub type LogHeight = usize;
+
+/// Position in the tree
+#[derive(Hash, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LogTreePosition<LogSize> {
+    pub level: LogHeight,
+    pub index: LogSize,
+}
+
+impl<LogSize> From<(LogHeight, LogSize)> for LogTreePosition<LogSize> {
+    fn from((level, index): (LogHeight, LogSize)) -> Self {
+        Self { level, index }
+    }
+}
+
+impl<LogSize: ToPrimitive> LogTreePosition<LogSize> {
+    /// Convert the position to another size type
+    pub fn convert<S: NumCast>(&self) -> Result<LogTreePosition<S>, SizeError> {
+        Ok(LogTreePosition {
+            level: self.level,
+            index: convert_size(&self.index)?,
+        })
+    }
+}
+
+/// Specific Errors when converting between size types
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SizeError {
+    /// The value does not fit in the target size type
+    Overflow,
+}
+
+impl Display for SizeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Overflow => write!(f, "Tree size or index does not fit in the target size type"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for SizeError {}
+
+/// Convert a tree size or index to another size type, failing if it does not fit
+pub fn convert_size<S1: ToPrimitive, S2: NumCast>(size: &S1) -> Result<S2, SizeError> {
+    S2::from(size.to_u128().ok_or(SizeError::Overflow)?).ok_or(SizeError::Overflow)
+}
+
+/// Calculate the number of hashes at each level of a tree of the given size
+pub fn tree_sizes<LogSize: Integer + Copy>(size: LogSize) -> Vec<LogSize> {
+    let mut v = vec![];
+    let mut sz = size;
+    let mut height = LogSize::one();
+    let two = LogSize::one() + LogSize::one();
+    if sz > LogSize::zero() {
+        v.push(sz);
+        while height < size && sz > LogSize::zero() {
+            if sz.is_one() {
+                sz = LogSize::zero();
+            } else {
+                sz = sz / two;
+            }
+            v.push(sz);
+            height = height * two;
+        }
+    }
+    v
+}
+
+/// The index of the first leaf under a given position
+pub fn first_leaf<LogSize: Integer + Copy>(position: &LogTreePosition<LogSize>) -> LogSize {
+    let two = LogSize::one() + LogSize::one();
+    (0..position.level).fold(position.index, |index, _| index * two)
+}
+
+/// Iterate over the proof positions needed to assert the record at the given index is present in a log of the given size,
+/// without allocating: each position is computed from the index and size shifted to its level
+/// The positions are all different, in the order of the path from the record up to the root
+pub fn proof_positions_iter<LogSize: Integer + Copy>(
+    index: LogSize,
+    size: LogSize,
+) -> ProofPositions<LogSize> {
+    let two = LogSize::one() + LogSize::one();
+    // the top level of the tree, as in `tree_sizes`
+    let mut top = 0;
+    let mut width = LogSize::one();
+    while width < size {
+        top += 1;
+        width = width * two;
+    }
+    ProofPositions {
+        level: 0,
+        index,
+        size,
+        width: LogSize::one(),
+        total: size,
+        top,
+        descent: None,
+        done: size.is_zero(),
+    }
+}
+
+/// Iterator over the proof positions of a record, from `proof_positions_iter`
+#[derive(Debug, Clone)]
+pub struct ProofPositions<LogSize> {
+    // the level of the path from the record to the root
+    level: LogHeight,
+    // the index and the tree size at that level
+    index: LogSize,
+    size: LogSize,
+    // the number of records under a node of that level
+    width: LogSize,
+    total: LogSize,
+    top: LogHeight,
+    // when the right sibling is incomplete: the level, index and width of the next stored subtree to look at
+    descent: Option<(LogHeight, LogSize, LogSize)>,
+    done: bool,
+}
+
+impl<LogSize: Integer + Copy> ProofPositions<LogSize> {
+    /// Move up the path
+    fn up(&mut self) {
+        let two = LogSize::one() + LogSize::one();
+        if self.level < self.top {
+            self.level += 1;
+            self.index = self.index / two;
+            self.size = self.size / two;
+            self.width = self.width * two;
+        } else {
+            self.done = true;
+        }
+    }
+}
+
+impl<LogSize: Integer + Copy> Iterator for ProofPositions<LogSize> {
+    type Item = LogTreePosition<LogSize>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let two = LogSize::one() + LogSize::one();
+        loop {
+            // the right sibling is incomplete: take each stored subtree on its way down
+            if let Some((level, index, width)) = self.descent {
+                if level == 0 {
+                    self.descent = None;
+                    self.up();
+                    continue;
+                }
+                let (level, index, width) = (level - 1, index * two, width / two);
+                if index < self.total / width {
+                    self.descent = Some((level, index + LogSize::one(), width));
+                    return Some(LogTreePosition { level, index });
+                }
+                self.descent = Some((level, index, width));
+                continue;
+            }
+            if self.done {
+                return None;
+            }
+            let level = self.level;
+            let index = self.index;
+            if index.is_odd() {
+                self.up();
+                return Some(LogTreePosition {
+                    level,
+                    index: index - LogSize::one(),
+                });
+            }
+            if index + LogSize::one() < self.size {
+                self.up();
+                return Some(LogTreePosition {
+                    level,
+                    index: index + LogSize::one(),
+                });
+            }
+            self.descent = Some((level, index + LogSize::one(), self.width));
+        }
+    }
+}
+
+/// Iterate over the proof positions needed to assert a tree of size1 is a prefix of a tree of size2,
+/// the positions shared by the two paths coming twice
+/// There are no positions unless 0 < size1 < size2
+pub fn prefix_proof_positions_iter<LogSize: Integer + Copy>(
+    size1: LogSize,
+    size2: LogSize,
+) -> impl Iterator<Item = LogTreePosition<LogSize>> {
+    let valid = size1 > LogSize::zero() && size1 < size2;
+    // the last stored node of the lowest level of odd size, below the top
+    let two = LogSize::one() + LogSize::one();
+    let mut odd = None;
+    let mut level = 0;
+    let mut sz = size2;
+    let mut width = LogSize::one();
+    while valid && width < size2 {
+        if sz.is_odd() {
+            odd = Some(LogTreePosition {
+                level,
+                index: sz - LogSize::one(),
+            });
+            break;
+        }
+        level += 1;
+        sz = sz / two;
+        width = width * two;
+    }
+    // an empty tree has no positions
+    let (size1, size2) = if valid {
+        (size1, size2)
+    } else {
+        (LogSize::one(), LogSize::zero())
+    };
+    proof_positions_iter(size1, size2)
+        .chain(proof_positions_iter(size1 - LogSize::one(), size2))
+        .chain(odd)
+}
+
+/// Calculate the root hash of a tree with the given sizes, from the hashes the proof gives for its positions
+/// Fails with a `VerifyError::MissingProof` error if the proof does not cover the tree
+pub fn calc_root<'p, LogSize, F>(sizes: &[LogSize], proof: &F) -> Result<String, VerifyError>
+where
+    LogSize: Integer + Copy,
+    F: Fn(&LogTreePosition<LogSize>) -> Option<&'p str>,
+{
+    let level = sizes.len().checked_sub(1).ok_or(VerifyError::OutOfTree)?;
+    calc_hash(
+        LogTreePosition {
+            level,
+            index: LogSize::zero(),
+        },
+        proof,
+        sizes,
+    )
+}
+
+/// Calculate the hash of a given level or index, recursively going down the tree
+/// Fails with a `VerifyError::MissingProof` error if the position is outside the tree or a hash it needs is missing or empty
+pub fn calc_hash<'p, LogSize, F>(
+    position: LogTreePosition<LogSize>,
+    proof: &F,
+    sizes: &[LogSize],
+) -> Result<String, VerifyError>
+where
+    LogSize: Integer + Copy,
+    F: Fn(&LogTreePosition<LogSize>) -> Option<&'p str>,
+{
+    if position.index < sizes[position.level] {
+        // empty hashes are never valid, they would stand for the root of an empty tree
+        if let Some(h) = proof(&position).filter(|h| !h.is_empty()) {
+            return Ok(h.into());
+        }
+    }
+    if position.level > 0 {
+        let two = LogSize::one() + LogSize::one();
+        let new_index = position.index * two;
+        let h1 = calc_hash(
+            LogTreePosition {
+                level: position.level - 1,
+                index: new_index,
+            },
+            proof,
+            sizes,
+        )?;
+        let right = LogTreePosition {
+            level: position.level - 1,
+            index: new_index + LogSize::one(),
+        };
+        // a subtree with no leaf in the tree is absent: its parent hash is the one of its left sibling
+        if first_leaf(&right) >= sizes[0] {
+            return Ok(h1);
+        }
+        let h2 = calc_hash(right, proof, sizes)?;
+        return Ok(hash_pair(&h1, &h2));
+    }
+    Err(VerifyError::MissingProof(position.level))
+}
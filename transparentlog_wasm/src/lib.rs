@@ -0,0 +1,315 @@
+//! # Transparent Log WASM bindings
+//!
+//! This crate exposes the proof verification of `transparentlog_verify` to JavaScript, with JSON proofs and tree heads,
+//! and a client keeping the latest verified tree head so each new head is checked to be consistent with it.
+//! Proofs leading to another root hash verify to false, and incomplete proofs throw an error.
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use transparentlog_verify::{LogHeight, LogTree, LogTreePosition, VerifyError};
+use wasm_bindgen::prelude::*;
+
+/// A tree head, as JSON
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TreeHeadJson {
+    pub size: u64,
+    pub hash: String,
+}
+
+impl From<TreeHeadJson> for LogTree<u64> {
+    fn from(t: TreeHeadJson) -> Self {
+        LogTree {
+            size: t.size,
+            hash: t.hash,
+        }
+    }
+}
+
+impl From<&LogTree<u64>> for TreeHeadJson {
+    fn from(t: &LogTree<u64>) -> Self {
+        TreeHeadJson {
+            size: t.size,
+            hash: t.hash.clone(),
+        }
+    }
+}
+
+/// A hash at a position in the tree, as JSON
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct HashJson {
+    pub level: LogHeight,
+    pub index: u64,
+    pub hash: String,
+}
+
+/// The proof that a leaf hash is included in a tree, as JSON
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct InclusionProofJson {
+    pub index: u64,
+    pub leaf_hash: String,
+    pub hashes: Vec<HashJson>,
+}
+
+/// Index proof hashes by their position
+fn proof_map(hashes: Vec<HashJson>) -> BTreeMap<LogTreePosition<u64>, String> {
+    hashes
+        .into_iter()
+        .map(|h| {
+            (
+                LogTreePosition {
+                    level: h.level,
+                    index: h.index,
+                },
+                h.hash,
+            )
+        })
+        .collect()
+}
+
+/// Convert a JSON error to a JavaScript error
+fn js_error(e: serde_json::Error) -> JsValue {
+    JsValue::from_str(&e.to_string())
+}
+
+/// Convert a verification error to a JavaScript error
+fn verify_error(e: VerifyError) -> JsValue {
+    JsValue::from_str(&e.to_string())
+}
+
+/// Verify the inclusion proof in the given tree
+fn check_inclusion(tree: &LogTree<u64>, proof: InclusionProofJson) -> Result<bool, VerifyError> {
+    transparentlog_verify::try_verify(
+        tree,
+        proof.index,
+        &proof.leaf_hash,
+        &proof_map(proof.hashes),
+    )
+}
+
+/// Verify the new tree is consistent with the old one
+fn check_consistency(
+    old: &LogTree<u64>,
+    new: &LogTree<u64>,
+    hashes: Vec<HashJson>,
+) -> Result<bool, VerifyError> {
+    transparentlog_verify::try_verify_consistency(old, new, &proof_map(hashes))
+}
+
+/// Verify the JSON inclusion proof against the JSON tree head
+#[wasm_bindgen]
+pub fn verify_inclusion(tree: &str, proof: &str) -> Result<bool, JsValue> {
+    let tree: TreeHeadJson = serde_json::from_str(tree).map_err(js_error)?;
+    let proof = serde_json::from_str(proof).map_err(js_error)?;
+    check_inclusion(&tree.into(), proof).map_err(verify_error)
+}
+
+/// Verify the old JSON tree head is a prefix of the new one, with the JSON list of proof hashes
+#[wasm_bindgen]
+pub fn verify_consistency(old: &str, new: &str, hashes: &str) -> Result<bool, JsValue> {
+    let old: TreeHeadJson = serde_json::from_str(old).map_err(js_error)?;
+    let new: TreeHeadJson = serde_json::from_str(new).map_err(js_error)?;
+    let hashes = serde_json::from_str(hashes).map_err(js_error)?;
+    check_consistency(&old.into(), &new.into(), hashes).map_err(verify_error)
+}
+
+/// The positions of the hashes needed to prove the inclusion of the leaf at the given index, as a JSON list of `{level, index}`
+#[wasm_bindgen]
+pub fn inclusion_positions(index: u64, size: u64) -> Result<String, JsValue> {
+    positions_json(transparentlog_verify::proof_positions(index, size))
+}
+
+/// The positions of the hashes needed to prove the tree of size1 is a prefix of the tree of size2, as a JSON list of `{level, index}`
+#[wasm_bindgen]
+pub fn consistency_positions(size1: u64, size2: u64) -> Result<String, JsValue> {
+    positions_json(transparentlog_verify::prefix_proof_positions(size1, size2))
+}
+
+/// Serialize positions
+fn positions_json<I: IntoIterator<Item = LogTreePosition<u64>>>(
+    positions: I,
+) -> Result<String, JsValue> {
+    let positions: Vec<PositionJson> = positions
+        .into_iter()
+        .map(|p| PositionJson {
+            level: p.level,
+            index: p.index,
+        })
+        .collect();
+    serde_json::to_string(&positions).map_err(js_error)
+}
+
+/// A position in the tree, as JSON
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct PositionJson {
+    pub level: LogHeight,
+    pub index: u64,
+}
+
+/// A client keeping the latest verified tree head of a log
+#[wasm_bindgen]
+#[derive(Debug, Clone, Default)]
+pub struct Client {
+    latest: Option<LogTree<u64>>,
+}
+
+#[wasm_bindgen]
+impl Client {
+    /// Create a client that has not seen any tree head yet
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Restore a client from its JSON state, the latest tree head or null
+    pub fn from_json(state: &str) -> Result<Client, JsValue> {
+        let latest: Option<TreeHeadJson> = serde_json::from_str(state).map_err(js_error)?;
+        Ok(Self {
+            latest: latest.map(|t| t.into()),
+        })
+    }
+
+    /// The JSON state of the client, to save between sessions
+    pub fn to_json(&self) -> Result<String, JsValue> {
+        let latest: Option<TreeHeadJson> = self.latest.as_ref().map(|t| t.into());
+        serde_json::to_string(&latest).map_err(js_error)
+    }
+
+    /// The latest verified JSON tree head, if any
+    pub fn latest(&self) -> Option<String> {
+        self.latest
+            .as_ref()
+            .and_then(|t| serde_json::to_string(&TreeHeadJson::from(t)).ok())
+    }
+
+    /// Move to a new JSON tree head if it is consistent with the latest one, using the JSON list of proof hashes
+    /// The first tree head is trusted as is
+    pub fn update(&mut self, tree: &str, hashes: &str) -> Result<bool, JsValue> {
+        let tree: TreeHeadJson = serde_json::from_str(tree).map_err(js_error)?;
+        let hashes = serde_json::from_str(hashes).map_err(js_error)?;
+        self.update_tree(tree.into(), hashes).map_err(verify_error)
+    }
+
+    /// Verify a JSON inclusion proof against the latest tree head
+    pub fn verify(&self, proof: &str) -> Result<bool, JsValue> {
+        let proof = serde_json::from_str(proof).map_err(js_error)?;
+        match &self.latest {
+            Some(latest) => check_inclusion(latest, proof).map_err(verify_error),
+            None => Ok(false),
+        }
+    }
+}
+
+impl Client {
+    /// Move to a new tree head if it is consistent with the latest one
+    fn update_tree(
+        &mut self,
+        tree: LogTree<u64>,
+        hashes: Vec<HashJson>,
+    ) -> Result<bool, VerifyError> {
+        let ok = match &self.latest {
+            None => true,
+            Some(latest) => check_consistency(latest, &tree, hashes)?,
+        };
+        if ok {
+            self.latest = Some(tree);
+        }
+        Ok(ok)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::*;
+    use transparentlog_verify::{hash_pair, sha256_hex};
+
+    fn leaves() -> Vec<String> {
+        (0..3)
+            .map(|i| sha256_hex(format!("rec{}", i).as_bytes()))
+            .collect()
+    }
+
+    #[test]
+    fn wasm_client() {
+        let h = leaves();
+        let tree2 = LogTree {
+            size: 2,
+            hash: hash_pair(&h[0], &h[1]),
+        };
+        let tree3 = LogTree {
+            size: 3,
+            hash: hash_pair(&tree2.hash, &h[2]),
+        };
+
+        let mut client = Client::new();
+        assert_eq!(Ok(true), client.update_tree(tree2.clone(), vec![]));
+        let proof = InclusionProofJson {
+            index: 1,
+            leaf_hash: h[1].clone(),
+            hashes: vec![HashJson {
+                level: 0,
+                index: 0,
+                hash: h[0].clone(),
+            }],
+        };
+        assert_eq!(
+            Ok(true),
+            check_inclusion(client.latest.as_ref().unwrap(), proof.clone())
+        );
+
+        let consistency = vec![
+            HashJson {
+                level: 0,
+                index: 0,
+                hash: h[0].clone(),
+            },
+            HashJson {
+                level: 0,
+                index: 1,
+                hash: h[1].clone(),
+            },
+            HashJson {
+                level: 0,
+                index: 2,
+                hash: h[2].clone(),
+            },
+        ];
+        assert_eq!(
+            Ok(false),
+            client.update_tree(
+                LogTree {
+                    size: 3,
+                    hash: h[2].clone()
+                },
+                consistency.clone()
+            )
+        );
+        assert_eq!(Some(&tree2), client.latest.as_ref());
+        assert_eq!(
+            Err(VerifyError::MissingProof(0)),
+            client.update_tree(tree3.clone(), consistency[..2].to_vec())
+        );
+        assert_eq!(Ok(true), client.update_tree(tree3.clone(), consistency));
+        assert_eq!(Some(&tree3), client.latest.as_ref());
+        // the proof misses the hash of the leaf 2, needed in a tree of 3 leaves
+        assert_eq!(
+            Err(VerifyError::MissingProof(0)),
+            check_inclusion(&tree3, proof.clone())
+        );
+        assert_eq!(
+            Ok(true),
+            check_inclusion(&tree3, {
+                let mut p = proof;
+                p.hashes.push(HashJson {
+                    level: 0,
+                    index: 2,
+                    hash: h[2].clone(),
+                });
+                p
+            })
+        );
+
+        let json = serde_json::to_string(&TreeHeadJson::from(&tree3)).unwrap();
+        let parsed: TreeHeadJson = serde_json::from_str(&json).unwrap();
+        assert_eq!(tree3, LogTree::from(parsed));
+    }
+}
@@ -0,0 +1,334 @@
+//! # Transparent Log witness
+//!
+//! A witness daemon polls the checkpoints of logs published with `transparentlog_object_store`, checks each new one
+//! is consistent with the last one it cosigned, fetching the proofs from the published tiles, cosigns it with its own
+//! key, and serves the cosigned checkpoints over HTTP, at `/checkpoint/<origin>`, pushing them to the given URLs too.
+//! The cosigned heads are saved after each poll, so a restarted witness keeps checking from them.
+use object_store::local::LocalFileSystem;
+use object_store::ObjectStore;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use tokio::runtime::{Builder, Runtime};
+use transparentlog_core::*;
+use transparentlog_object_store::TileReader;
+
+/// The configuration of the witness daemon, read from a JSON file
+#[derive(Debug, Clone, Deserialize)]
+pub struct Config {
+    /// The name of the witness key
+    pub name: String,
+    /// A file holding the 32 bytes Ed25519 seed of the witness key
+    pub key: PathBuf,
+    /// The file the cosigned heads are saved to
+    pub state: PathBuf,
+    /// The address the cosigned checkpoints are served on
+    #[serde(default = "default_listen")]
+    pub listen: String,
+    /// The number of seconds between two polls
+    #[serde(default = "default_interval")]
+    pub interval: u64,
+    /// The witnessed logs
+    pub logs: Vec<LogConfig>,
+}
+
+/// The configuration of a witnessed log
+#[derive(Debug, Clone, Deserialize)]
+pub struct LogConfig {
+    /// The origin of the log
+    pub origin: String,
+    /// The verifier key of the log, in the `<name>+<hash>+<base64 key>` format
+    pub vkey: String,
+    /// The directory of the store the log is published to
+    pub directory: PathBuf,
+    /// The prefix the log is published at in the store
+    #[serde(default)]
+    pub prefix: String,
+    /// The URLs the cosigned checkpoints are posted to
+    #[serde(default)]
+    pub push: Vec<String>,
+}
+
+fn default_listen() -> String {
+    "127.0.0.1:8080".to_owned()
+}
+
+fn default_interval() -> u64 {
+    60
+}
+
+/// A log polled by the daemon
+pub struct WatchedLog {
+    /// The origin of the log
+    pub origin: String,
+    /// The reader of the published log
+    pub reader: TileReader,
+    /// The URLs the cosigned checkpoints are posted to
+    pub push: Vec<String>,
+}
+
+/// The witness daemon: polls the watched logs, cosigns their new checkpoints and serves them
+/// The calls block on its own runtime, so they must not be made from an asynchronous task
+pub struct WitnessDaemon {
+    witness: RwLock<Witness<u64>>,
+    logs: Vec<WatchedLog>,
+    state: Option<PathBuf>,
+    runtime: Runtime,
+}
+
+impl WitnessDaemon {
+    /// Create a daemon polling the given logs, that the witness must know the keys of
+    pub fn new(witness: Witness<u64>, logs: Vec<WatchedLog>) -> anyhow::Result<Self> {
+        Ok(Self {
+            witness: RwLock::new(witness),
+            logs,
+            state: None,
+            runtime: Builder::new_current_thread().enable_all().build()?,
+        })
+    }
+
+    /// Create a daemon from its configuration, restoring the saved heads
+    pub fn from_config(config: &Config) -> anyhow::Result<Self> {
+        let seed: [u8; 32] = std::fs::read(&config.key)?.as_slice().try_into()?;
+        let mut witness = Witness::new(Box::new(Ed25519Signer::new(&config.name, &seed)));
+        let mut logs = vec![];
+        for log in config.logs.iter() {
+            witness.add_log(
+                &log.origin,
+                Box::new(Ed25519Verifier::from_vkey(&log.vkey)?),
+            );
+            let store: Arc<dyn ObjectStore> =
+                Arc::new(LocalFileSystem::new_with_prefix(&log.directory)?);
+            logs.push(WatchedLog {
+                origin: log.origin.clone(),
+                reader: TileReader::new(store, &log.prefix),
+                push: log.push.clone(),
+            });
+        }
+        Self::new(witness, logs)?.with_state(&config.state)
+    }
+
+    /// Save the cosigned heads to the given file after each poll, restoring them first if it exists
+    pub fn with_state(mut self, path: &Path) -> anyhow::Result<Self> {
+        if path.exists() {
+            let heads: BTreeMap<String, WitnessedHead<u64>> =
+                serde_json::from_reader(File::open(path)?)?;
+            self.write()?.restore(heads);
+        }
+        self.state = Some(path.to_owned());
+        Ok(self)
+    }
+
+    /// The watched logs
+    pub fn logs(&self) -> &[WatchedLog] {
+        &self.logs
+    }
+
+    /// The latest cosigned checkpoint note of the log of the given origin
+    pub fn cosigned(&self, origin: &str) -> anyhow::Result<Option<String>> {
+        Ok(self.read()?.cosigned(origin).map(str::to_owned))
+    }
+
+    /// Poll all the watched logs, cosigning their new checkpoints, and save the cosigned heads
+    /// Returns for each log the new cosigned note, none if the log did not publish a new checkpoint
+    pub fn poll(&self) -> Vec<anyhow::Result<Option<String>>> {
+        let polled: Vec<_> = self.logs.iter().map(|log| self.poll_log(log)).collect();
+        if polled.iter().any(|p| matches!(p, Ok(Some(_)))) {
+            if let Err(e) = self.save() {
+                return polled
+                    .into_iter()
+                    .map(|_| Err(anyhow::anyhow!("{:#}", e)))
+                    .collect();
+            }
+        }
+        polled
+    }
+
+    /// Post the given cosigned note to the URLs of the log of the given origin
+    /// All the URLs are tried, the error of the last failing one returned
+    pub fn push(&self, origin: &str, note: &str) -> anyhow::Result<()> {
+        let mut result = Ok(());
+        for log in self.logs.iter().filter(|l| l.origin == origin) {
+            for url in log.push.iter() {
+                if let Err(e) = ureq::post(url)
+                    .set("Content-Type", "text/plain; charset=utf-8")
+                    .send_string(note)
+                {
+                    result = Err(e.into());
+                }
+            }
+        }
+        result
+    }
+
+    /// The body of the response to a GET request of the given URL, none if there is nothing there
+    pub fn respond(&self, url: &str) -> anyhow::Result<Option<String>> {
+        match url.strip_prefix("/checkpoint/") {
+            Some(origin) => self.cosigned(origin),
+            None => Ok(None),
+        }
+    }
+
+    /// Serve the cosigned checkpoints, until the server stops
+    pub fn serve(&self, server: tiny_http::Server) {
+        for request in server.incoming_requests() {
+            let response = match (request.method(), self.respond(request.url())) {
+                (tiny_http::Method::Get, Ok(Some(note))) => tiny_http::Response::from_string(note),
+                (tiny_http::Method::Get, Ok(None)) => {
+                    tiny_http::Response::from_string("Not found").with_status_code(404)
+                }
+                (tiny_http::Method::Get, Err(e)) => {
+                    tiny_http::Response::from_string(format!("{:#}", e)).with_status_code(500)
+                }
+                _ => tiny_http::Response::from_string("Method not allowed").with_status_code(405),
+            };
+            // the client went away, nothing to do
+            let _ = request.respond(response);
+        }
+    }
+
+    /// Cosign the published checkpoint of a log, if it is new
+    fn poll_log(&self, log: &WatchedLog) -> anyhow::Result<Option<String>> {
+        let note = match self.runtime.block_on(log.reader.note())? {
+            Some(note) => note,
+            None => return Ok(None),
+        };
+        let (text, _) = parse_note(&note)?;
+        let checkpoint = Checkpoint::<u64>::parse(text)?;
+        if checkpoint.origin != log.origin {
+            return Err(OriginError::Mismatch {
+                expected: Some(log.origin.clone()),
+                found: Some(checkpoint.origin),
+            }
+            .into());
+        }
+        let mut witness = self.write()?;
+        let before = witness.cosigned(&log.origin).map(str::to_owned);
+        let cosigned = witness.cosign(&note, |positions| {
+            self.runtime
+                .block_on(log.reader.proofs(checkpoint.tree.size, &positions))
+        })?;
+        Ok((before.as_ref() != Some(&cosigned)).then(|| cosigned))
+    }
+
+    /// Save the cosigned heads, through a temporary file so a crash never leaves a partial state
+    fn save(&self) -> anyhow::Result<()> {
+        if let Some(path) = &self.state {
+            let tmp = path.with_extension("tmp");
+            serde_json::to_writer(File::create(&tmp)?, self.read()?.heads())?;
+            std::fs::rename(tmp, path)?;
+        }
+        Ok(())
+    }
+
+    fn read(&self) -> anyhow::Result<std::sync::RwLockReadGuard<Witness<u64>>> {
+        self.witness
+            .read()
+            .map_err(|_| anyhow::anyhow!("Poisoned witness lock"))
+    }
+
+    fn write(&self) -> anyhow::Result<std::sync::RwLockWriteGuard<Witness<u64>>> {
+        self.witness
+            .write()
+            .map_err(|_| anyhow::anyhow!("Poisoned witness lock"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::*;
+    use object_store::memory::InMemory;
+    use std::fs::{create_dir, remove_dir_all};
+    use std::path::{Path, PathBuf};
+    use transparentlog_core::test_helpers::*;
+    use transparentlog_object_store::Publisher;
+
+    fn setup() -> anyhow::Result<PathBuf> {
+        let path = Path::new("./test_data");
+        if path.exists() {
+            remove_dir_all(path)?;
+        }
+        create_dir(path)?;
+        Ok(PathBuf::from(path))
+    }
+
+    fn watching(store: Arc<InMemory>, log: &Ed25519Signer) -> anyhow::Result<WitnessDaemon> {
+        let mut witness = Witness::new(Box::new(Ed25519Signer::new("witness", &[5_u8; 32])));
+        witness.add_log("example.com/log", Box::new(log.verifier()));
+        WitnessDaemon::new(
+            witness,
+            vec![WatchedLog {
+                origin: "example.com/log".to_owned(),
+                reader: TileReader::new(store, "log"),
+                push: vec![],
+            }],
+        )
+    }
+
+    #[test]
+    fn witness_daemon_poll() -> anyhow::Result<()> {
+        let path = setup()?;
+        let store = Arc::new(InMemory::new());
+        let signer = Ed25519Signer::new("example.com/log", &[3_u8; 32]);
+        let witness = Ed25519Signer::new("witness", &[5_u8; 32]).verifier();
+        let publisher = Publisher::new(store.clone(), "log");
+        let state = path.join("state.json");
+        let daemon = watching(store.clone(), &signer)?.with_state(&state)?;
+        assert!(matches!(daemon.poll().as_slice(), [Ok(None)]));
+        assert!(!state.exists());
+
+        let runtime = Builder::new_current_thread().enable_all().build()?;
+        let mut ml: KvLog<LogRecord, _> = KvLog::open(MemoryKvStore::default())?;
+        append_multiple(&mut ml, 300)?;
+        runtime.block_on(publisher.publish(&ml, "example.com/log", &[&signer]))?;
+        let first = daemon.poll().remove(0)?.unwrap();
+        let policy = WitnessPolicy::all(vec![
+            WitnessPolicy::Key(signer.verifier()),
+            WitnessPolicy::Key(witness.clone()),
+        ]);
+        assert_eq!(300, policy.open::<u64>(&first)?.tree.size);
+        assert!(matches!(daemon.poll().as_slice(), [Ok(None)]));
+        assert_eq!(Some(first), daemon.respond("/checkpoint/example.com/log")?);
+        assert_eq!(None, daemon.respond("/checkpoint/other.com/log")?);
+        assert_eq!(None, daemon.respond("/example.com/log")?);
+
+        append_multiple_offset(&mut ml, 300, 400)?;
+        runtime.block_on(publisher.publish(&ml, "example.com/log", &[&signer]))?;
+        let second = daemon.poll().remove(0)?.unwrap();
+        assert_eq!(ml.latest()?.hash, policy.open::<u64>(&second)?.tree.hash);
+
+        // a fork published in another store, polled by the restarted witness
+        let forked = Arc::new(InMemory::new());
+        let mut fork: KvLog<LogRecord, _> = KvLog::open(MemoryKvStore::default())?;
+        append_multiple_offset(&mut fork, 1, 800)?;
+        runtime.block_on(Publisher::new(forked.clone(), "log").publish(
+            &fork,
+            "example.com/log",
+            &[&signer],
+        ))?;
+        let restarted = watching(forked, &signer)?.with_state(&state)?;
+        assert_eq!(Some(second), restarted.cosigned("example.com/log")?);
+        let err = restarted.poll().remove(0).unwrap_err();
+        assert_eq!(
+            Some(&WitnessError::Inconsistent("example.com/log".into())),
+            err.downcast_ref::<WitnessError>()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn witness_daemon_config() -> anyhow::Result<()> {
+        let config: Config = serde_json::from_str(
+            r#"{"name": "witness", "key": "witness.key", "state": "heads.json",
+                "logs": [{"origin": "example.com/log", "vkey": "example.com/log+00000000+AAAA", "directory": "/var/log"}]}"#,
+        )?;
+        assert_eq!("127.0.0.1:8080", config.listen);
+        assert_eq!(60, config.interval);
+        assert_eq!("", config.logs[0].prefix);
+        assert!(config.logs[0].push.is_empty());
+        Ok(())
+    }
+}
@@ -0,0 +1,43 @@
+//! # Transparent Log witness daemon
+//!
+//! Poll the logs of the configuration, cosign their new checkpoints, push and serve them.
+use clap::Parser;
+use std::fs::File;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use transparentlog_witness::{Config, WitnessDaemon};
+
+/// The command line arguments
+#[derive(Parser)]
+#[clap(name = "transparentlog-witness", version, about)]
+struct Cli {
+    /// The JSON configuration file
+    #[clap(short, long)]
+    config: PathBuf,
+}
+
+fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+    let config: Config = serde_json::from_reader(File::open(&cli.config)?)?;
+    let daemon = Arc::new(WitnessDaemon::from_config(&config)?);
+    let server = tiny_http::Server::http(&config.listen).map_err(|e| anyhow::anyhow!(e))?;
+    let serving = daemon.clone();
+    thread::spawn(move || serving.serve(server));
+    loop {
+        for (log, polled) in daemon.logs().iter().zip(daemon.poll()) {
+            match polled {
+                Ok(Some(note)) => {
+                    println!("Cosigned checkpoint of {}", log.origin);
+                    if let Err(e) = daemon.push(&log.origin, &note) {
+                        eprintln!("Cannot push checkpoint of {}: {:#}", log.origin, e);
+                    }
+                }
+                Ok(None) => {}
+                Err(e) => eprintln!("Cannot cosign checkpoint of {}: {:#}", log.origin, e),
+            }
+        }
+        thread::sleep(Duration::from_secs(config.interval));
+    }
+}